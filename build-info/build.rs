@@ -0,0 +1,60 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+fn git_tag() -> String {
+    if let Ok(tag) = env::var("TRUSTY_VERSION")
+        && !tag.trim().is_empty()
+    {
+        return tag;
+    }
+    git(&["describe", "--tags", "--dirty", "--always"]).unwrap_or_else(|| "unknown".to_string())
+}
+
+fn build_time() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute()
+    )
+}
+
+/// This crate's sources hardly ever change, so rebuild it whenever the
+/// checked out commit or the index does instead.
+fn watch_git() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=TRUSTY_VERSION");
+    let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) else {
+        return;
+    };
+    let git_dir = Path::new(&git_dir);
+    for name in ["HEAD", "index", "packed-refs"] {
+        println!("cargo:rerun-if-changed={}", git_dir.join(name).display());
+    }
+    if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        println!("cargo:rerun-if-changed={}", git_dir.join(head).display());
+    }
+}
+
+fn main() {
+    watch_git();
+    println!("cargo:rustc-env=TERN_VERSION={}", git_tag());
+    println!(
+        "cargo:rustc-env=TERN_GIT_HASH={}",
+        git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rustc-env=TERN_BUILD_TIME={}", build_time());
+}
@@ -0,0 +1,31 @@
+//! Version and build details shared by the firmware and every tool, so the
+//! About screen, the USB handshake and `--version` all agree.
+//!
+//! `VERSION` is `git describe` of the build, or `TRUSTY_VERSION` when a
+//! release build sets it.
+
+#![no_std]
+
+use core::fmt;
+
+pub const VERSION: &str = env!("TERN_VERSION");
+/// Workspace version from `Cargo.toml`.
+pub const SEMVER: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the commit built, or `unknown` outside a git checkout.
+pub const GIT_HASH: &str = env!("TERN_GIT_HASH");
+/// UTC, to the minute.
+pub const BUILD_TIME: &str = env!("TERN_BUILD_TIME");
+
+/// `--version` line for the program `name`, e.g.
+/// `tern-book v0.4.0 (0.1.0, commit 1a2b3c4, built 2026-01-02 03:04)`.
+pub struct VersionLine(pub &'static str);
+
+impl fmt::Display for VersionLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {VERSION} ({SEMVER}, commit {GIT_HASH}, built {BUILD_TIME})",
+            self.0
+        )
+    }
+}
@@ -1,30 +1,6 @@
 use std::env;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-
-fn git_tag() -> String {
-    if let Ok(tag) = env::var("TRUSTY_VERSION") {
-        if !tag.trim().is_empty() {
-            return tag;
-        }
-    }
-    let output = Command::new("git")
-        .args(["describe", "--tags", "--dirty", "--always"])
-        .output();
-    match output {
-        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).trim().to_string(),
-        _ => "unknown".to_string(),
-    }
-}
-
-fn build_time() -> String {
-    use time::format_description::parse;
-    use time::OffsetDateTime;
-
-    let format = parse("[year]-[month]-[day] [hour]:[minute]").unwrap();
-    OffsetDateTime::now_utc().format(&format).unwrap_or_else(|_| "unknown".to_string())
-}
+use std::path::{Path, PathBuf};
 
 fn pack_mask(bits: &[bool]) -> Vec<u8> {
     let mut out = vec![0u8; (bits.len() + 7) / 8];
@@ -88,20 +64,38 @@ fn render_icon_fit(svg_path: &Path, target_w: u32, target_h: u32) -> (Vec<u8>, V
     (pack_mask(&dark_bits), pack_mask(&light_bits))
 }
 
+/// SVGs in `dir` by constant name, e.g. `battery_0.svg` -> `BATTERY_0`.
+fn svg_icons(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut icons: Vec<(String, PathBuf)> = fs::read_dir(dir)
+        .expect("read icon dir")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "svg"))
+        .map(|path| {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            let name = stem
+                .chars()
+                .map(|ch| if ch.is_ascii_alphanumeric() { ch.to_ascii_uppercase() } else { '_' })
+                .collect();
+            (name, path)
+        })
+        .collect();
+    icons.sort();
+    icons
+}
+
 fn write_icons(out_dir: &Path) {
     let icon_dir = Path::new("icons");
     let size = 80u32;
+    let small_size = 24u32;
     let logo_path = Path::new("../ternreader_logo_4color.svg");
     let logo_w = 600u32;
     let logo_h = 180u32;
 
-    let (folder_dark, folder_light) = render_icon(&icon_dir.join("folder.svg"), size);
-    let (gear_dark, gear_light) = render_icon(&icon_dir.join("gear.svg"), size);
-    let (battery_dark, battery_light) = render_icon(&icon_dir.join("battery.svg"), size);
     let (logo_dark, logo_light) = render_icon_fit(logo_path, logo_w, logo_h);
 
     let mut output = String::new();
     output.push_str(&format!("pub const ICON_SIZE: usize = {size};\n"));
+    output.push_str(&format!("pub const SMALL_ICON_SIZE: usize = {small_size};\n"));
     output.push_str(&format!("pub const LOGO_WIDTH: usize = {logo_w};\n"));
     output.push_str(&format!("pub const LOGO_HEIGHT: usize = {logo_h};\n"));
 
@@ -117,12 +111,26 @@ fn write_icons(out_dir: &Path) {
         out.push_str("];\n");
     };
 
-    emit(&mut output, "ICON_FOLDER_DARK_MASK", &folder_dark);
-    emit(&mut output, "ICON_FOLDER_LIGHT_MASK", &folder_light);
-    emit(&mut output, "ICON_GEAR_DARK_MASK", &gear_dark);
-    emit(&mut output, "ICON_GEAR_LIGHT_MASK", &gear_light);
-    emit(&mut output, "ICON_BATTERY_DARK_MASK", &battery_dark);
-    emit(&mut output, "ICON_BATTERY_LIGHT_MASK", &battery_light);
+    // icons/*.svg at ICON_SIZE, icons/small/*.svg at SMALL_ICON_SIZE.
+    for (name, path) in svg_icons(icon_dir) {
+        let (dark, light) = render_icon(&path, size);
+        emit(&mut output, &format!("ICON_{name}_DARK_MASK"), &dark);
+        emit(&mut output, &format!("ICON_{name}_LIGHT_MASK"), &light);
+    }
+    let small = svg_icons(&icon_dir.join("small"));
+    for (name, path) in &small {
+        let (dark, light) = render_icon(path, small_size);
+        emit(&mut output, &format!("SMALL_ICON_{name}_DARK_MASK"), &dark);
+        emit(&mut output, &format!("SMALL_ICON_{name}_LIGHT_MASK"), &light);
+    }
+    output.push_str("pub const SMALL_ICONS: &[(&str, &[u8], &[u8])] = &[\n");
+    for (name, path) in &small {
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        output.push_str(&format!(
+            "    (\"{stem}\", SMALL_ICON_{name}_DARK_MASK, SMALL_ICON_{name}_LIGHT_MASK),\n"
+        ));
+    }
+    output.push_str("];\n");
     emit(&mut output, "LOGO_DARK_MASK", &logo_dark);
     emit(&mut output, "LOGO_LIGHT_MASK", &logo_light);
 
@@ -132,6 +140,4 @@ fn write_icons(out_dir: &Path) {
 fn main() {
     let out_dir = env::var_os("OUT_DIR").unwrap();
     write_icons(Path::new(&out_dir));
-    println!("cargo:rustc-env=TRUSTY_VERSION={}", git_tag());
-    println!("cargo:rustc-env=TRUSTY_BUILD_TIME={}", build_time());
 }
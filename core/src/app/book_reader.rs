@@ -6,22 +6,106 @@ use alloc::vec::Vec;
 use embedded_graphics::{
     mono_font::{ascii::FONT_10X20, MonoTextStyle},
     pixelcolor::BinaryColor,
-    prelude::{DrawTarget, OriginDimensions, Point},
+    prelude::{DrawTarget, OriginDimensions, Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
     text::Text,
     Drawable,
 };
 
+use crate::app::chapters::{entry_progress, ChapterMap};
+use crate::app::diagnostics::{elapsed_us, PageTiming, PageTimings};
+use crate::app::reading_pace::{format_time_left, ReadingPace};
 use crate::display::{Display, GrayscaleMode, RefreshMode};
 use crate::framebuffer::{DisplayBuffers, Rotation, BUFFER_SIZE, HEIGHT as FB_HEIGHT, WIDTH as FB_WIDTH};
 use crate::image_viewer::{AppSource, ImageData, ImageError};
 use crate::input;
-use crate::ui::{flush_queue, ListItem, ListView, Rect, RenderQueue, UiContext, View};
+use crate::ui::{
+    flush_queue, relative, ListItem, ListMetrics, ListView, Rect, RenderQueue, UiContext, View,
+};
 
-const LIST_TOP: i32 = 60;
-const LINE_HEIGHT: i32 = 24;
-const LIST_MARGIN_X: i32 = 16;
-const HEADER_Y: i32 = 24;
+/// Contents list chrome on the portrait panel, scaled to the screen.
+const LIST_METRICS: ListMetrics = ListMetrics {
+    margin_x: 16,
+    header_y: 24,
+    list_top: 60,
+    line_height: 24,
+};
 const BOOK_FULL_REFRESH_EVERY: usize = 10;
+const AUTO_TURN_INTERVALS_MS: [u32; 6] = [0, 10_000, 20_000, 30_000, 60_000, 120_000];
+const AUTO_TURN_PIPS: u32 = 5;
+const AUTO_TURN_PIP_SIZE: i32 = 6;
+const AUTO_TURN_PIP_GAP: i32 = 4;
+const AUTO_TURN_PIP_MARGIN: i32 = 8;
+/// How long the page number stays up after a turn in clean page mode.
+const CLEAN_PAGE_INDICATOR_MS: u32 = 2_000;
+/// Holding a page turn button this long opens the page scrubber.
+const SCRUB_HOLD_MS: u32 = 600;
+/// The scrubber moves and is redrawn once per this much holding.
+const SCRUB_FRAME_MS: u32 = 100;
+/// Pages the scrubber moves per frame once held at least this long.
+const SCRUB_SPEEDS: [(u32, usize); 4] = [(0, 1), (1_500, 5), (3_000, 10), (5_000, 25)];
+/// Pages left by jumps that can be gone back to.
+const HISTORY_LEN: usize = 8;
+/// A second Back within this long goes back a jump instead of leaving.
+const DOUBLE_BACK_MS: u32 = 400;
+/// Scrubber inset, per mille of the screen's short side.
+const SCRUB_MARGIN_PER_MILLE: i32 = 33;
+/// Fits the page label and the track under it.
+const SCRUB_HEIGHT: i32 = 64;
+/// Zoom crop steps, in logical pixels cut off the left and right of a page.
+const PAGE_CROPS: [u8; 5] = [0, 8, 16, 24, 32];
+/// ToC titles the text of a book usually starts at, lowercase.
+const FIRST_CHAPTER_TITLES: &[&str] = &[
+    "prologue",
+    "chapter 1",
+    "chapter one",
+    "chapter i",
+    "part 1",
+    "part one",
+    "part i",
+    "book 1",
+    "book one",
+    "1",
+    "i",
+    "one",
+];
+/// ToC titles of pages in front of the text, lowercase.
+const FRONT_MATTER_TITLES: &[&str] = &[
+    "cover",
+    "title",
+    "half title",
+    "copyright",
+    "contents",
+    "table of contents",
+    "dedication",
+    "epigraph",
+    "also by",
+    "praise",
+    "frontispiece",
+    "imprint",
+];
+
+/// Page scrubber opened by holding a page turn button. Only the target page
+/// number moves while it is held; the page is drawn on release.
+#[derive(Clone, Copy, Debug)]
+pub struct Scrub {
+    pub target: usize,
+    forward: bool,
+    held_ms: u32,
+    frame_ms: u32,
+}
+
+pub enum ScrubTick {
+    /// Not scrubbing; buttons work as usual.
+    Idle,
+    /// Scrubbing with nothing new to draw.
+    Held,
+    Moved,
+    /// Released on another page, which is now the current one.
+    Jumped,
+    /// Released where it started or cancelled with Back.
+    Closed,
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum PageTurnIndicator {
@@ -41,6 +125,47 @@ pub struct BookReaderState {
     pub book_turns_since_full: usize,
     pub last_rendered_page: Option<usize>,
     pub page_turn_indicator: Option<PageTurnIndicator>,
+    pub part_prompt: bool,
+    pub auto_turn_ms: u32,
+    pub auto_turn_elapsed_ms: u32,
+    pub auto_turn_paused: bool,
+    pub auto_turn_pips_drawn: Option<u32>,
+    pub auto_turn_pips_pending: bool,
+    /// Set while the open book is laid out on the device instead of shown
+    /// from its prerendered pages.
+    pub reflow: Option<crate::reflow::Reflow>,
+    /// Zoom crop of the open book; see [`PAGE_CROPS`].
+    pub page_crop: u8,
+    /// The ToC screen's Left/Right changes the crop instead of auto-turn.
+    pub toc_crop_focus: bool,
+    /// The ToC screen's "Back to page" row is selected.
+    pub toc_back_focus: bool,
+    /// Pages left by ToC jumps and the scrubber in the open book, latest
+    /// last.
+    pub history: Vec<usize>,
+    /// Time since a Back press that may be the first of two.
+    back_pending_ms: Option<u32>,
+    /// ToC entry the book was opened at instead of its first page, shown in
+    /// a toast until the next key press.
+    pub front_matter_skipped: Option<String>,
+    /// Render stage times of the pages being loaded, drawn and shown.
+    pub timings: PageTimings,
+    /// Hide the page number except for a moment after each turn.
+    pub clean_page: bool,
+    /// Where the page number is on screen and for how long, while clean page
+    /// mode is waiting to erase it.
+    pub page_indicator_shown: Option<(Rect, u32)>,
+    pub page_indicator_erase_pending: bool,
+    /// Count pages within the chapter in the page number too.
+    pub chapter_progress: bool,
+    /// Chapters of the open book, for [`Self::chapter_progress`].
+    pub chapters: ChapterMap,
+    /// Time per page in the open book, for the time left on the sleep screen.
+    pub pace: ReadingPace,
+    pub scrub: Option<Scrub>,
+    /// How long a page turn button has been held towards opening the scrubber.
+    scrub_hold_ms: u32,
+    pub scrub_draw_pending: bool,
 }
 
 pub struct BookReaderContext<'a, S: AppSource> {
@@ -49,18 +174,36 @@ pub struct BookReaderContext<'a, S: AppSource> {
     pub gray2_msb: &'a mut [u8],
     pub source: &'a mut S,
     pub full_refresh: &'a mut bool,
+    pub refresh_tuning: crate::display::RefreshTuning,
 }
 
 pub struct BookViewResult {
     pub exit: bool,
     pub open_toc: bool,
+    pub open_next_part: bool,
     pub dirty: bool,
 }
 
+/// Where a page is in its chapter, for the page number.
+#[derive(Clone, Copy, Debug)]
+struct ChapterPosition {
+    page: usize,
+    pages: usize,
+    /// Reading time to the end of the chapter, once the pace is known.
+    time_left_ms: Option<u64>,
+}
+
+pub enum AutoTurnTick {
+    None,
+    Pips,
+    Turned,
+}
+
 pub struct TocResult {
     pub exit: bool,
     pub jumped: bool,
     pub dirty: bool,
+    pub crop_changed: bool,
 }
 
 impl BookReaderState {
@@ -77,6 +220,29 @@ impl BookReaderState {
             book_turns_since_full: 0,
             last_rendered_page: None,
             page_turn_indicator: None,
+            part_prompt: false,
+            auto_turn_ms: 0,
+            auto_turn_elapsed_ms: 0,
+            auto_turn_paused: false,
+            auto_turn_pips_drawn: None,
+            auto_turn_pips_pending: false,
+            reflow: None,
+            page_crop: 0,
+            toc_crop_focus: false,
+            toc_back_focus: false,
+            history: Vec::new(),
+            back_pending_ms: None,
+            front_matter_skipped: None,
+            timings: PageTimings::default(),
+            clean_page: false,
+            page_indicator_shown: None,
+            page_indicator_erase_pending: false,
+            chapter_progress: false,
+            chapters: ChapterMap::default(),
+            pace: ReadingPace::default(),
+            scrub: None,
+            scrub_hold_ms: 0,
+            scrub_draw_pending: false,
         }
     }
 
@@ -92,6 +258,26 @@ impl BookReaderState {
         self.book_turns_since_full = 0;
         self.last_rendered_page = None;
         self.page_turn_indicator = None;
+        self.part_prompt = false;
+        self.auto_turn_elapsed_ms = 0;
+        self.auto_turn_paused = false;
+        self.auto_turn_pips_drawn = None;
+        self.auto_turn_pips_pending = false;
+        self.reflow = None;
+        self.page_crop = 0;
+        self.toc_crop_focus = false;
+        self.toc_back_focus = false;
+        self.history.clear();
+        self.back_pending_ms = None;
+        self.front_matter_skipped = None;
+        self.page_indicator_shown = None;
+        self.page_indicator_erase_pending = false;
+        self.chapters = ChapterMap::default();
+        self.pace = ReadingPace::default();
+        self.scrub = None;
+        self.scrub_hold_ms = 0;
+        self.scrub_draw_pending = false;
+        self.timings.clear();
     }
 
     pub fn close<S: AppSource>(&mut self, source: &mut S) {
@@ -106,24 +292,124 @@ impl BookReaderState {
         entry: &crate::image_viewer::ImageEntry,
         entry_name: &str,
         book_positions: &BTreeMap<String, usize>,
+        reading: crate::reflow::ReadingLayout,
     ) -> Result<(), ImageError> {
         let info = source.open_trbk(path, entry)?;
-        self.current_book = Some(info);
+        if let Err(err) = crate::trbk::check_trbk_screen(info.screen_width, info.screen_height) {
+            source.close_trbk();
+            return Err(err);
+        }
+        self.reflow = None;
+        self.current_book = Some(info.clone());
+        if info.text.is_some() {
+            let layout = crate::reflow::ReflowLayout::for_book(&info, reading);
+            // A book with no prerendered pages (an EPUB read straight from
+            // the card) has to be laid out here or not at all.
+            let text_only = info.page_count == 0;
+            if text_only || layout != crate::reflow::ReflowLayout::converted(&info) {
+                match crate::reflow::paginate(source, &info, layout) {
+                    Ok(reflow) => {
+                        self.current_book = Some(Rc::new(reflow.book_info(&info)));
+                        self.reflow = Some(reflow);
+                    }
+                    Err(err) if text_only => {
+                        self.close(source);
+                        return Err(err);
+                    }
+                    Err(err) => log::warn!("Reflow failed, showing prerendered pages: {:?}", err),
+                }
+            }
+        }
         self.toc_labels = None;
-        self.current_page = book_positions.get(entry_name).copied().unwrap_or(0);
-        self.current_page_ops = source.trbk_page(self.current_page).ok();
+        if let Some(book) = &self.current_book {
+            self.chapters = ChapterMap::for_book(book);
+        }
+        let saved = book_positions.get(entry_name).copied().unwrap_or(0);
+        self.current_page = self.page_for_saved(saved);
+        self.history.clear();
+        self.back_pending_ms = None;
+        self.current_page_ops = self.load_page(source, self.current_page).ok();
         self.next_page_ops = None;
         self.prefetched_page = None;
         self.prefetched_gray2_used = false;
         self.last_rendered_page = None;
         self.book_turns_since_full = 0;
+        self.part_prompt = false;
+        self.auto_turn_elapsed_ms = 0;
+        self.auto_turn_paused = false;
         Ok(())
     }
 
+    /// Moves a book opened for the first time past its cover, title and
+    /// copyright pages to where its text starts, if the ToC shows where that is.
+    pub fn skip_front_matter(&mut self) {
+        let Some(book) = &self.current_book else {
+            return;
+        };
+        let Some(index) = front_matter_end(book) else {
+            return;
+        };
+        let entry = &book.toc[index];
+        log::info!("Skipping front matter to {:?}", entry.title);
+        self.current_page = entry.page_index as usize;
+        self.front_matter_skipped = Some(entry.title.clone());
+        self.current_page_ops = None;
+        self.next_page_ops = None;
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+    }
+
     pub fn has_book(&self) -> bool {
         self.current_book.is_some()
     }
 
+    /// Ops of page `page_index` as shown: laid out on the device when the
+    /// book is reflowed, else its prerendered page.
+    pub fn load_page<S: AppSource>(
+        &mut self,
+        source: &mut S,
+        page_index: usize,
+    ) -> Result<crate::trbk::TrbkPage, ImageError> {
+        let started = source.now_us();
+        let (page, read_us) = match &self.reflow {
+            Some(reflow) => (reflow.page(source, page_index), 0),
+            None => {
+                let page = source.trbk_page(page_index);
+                (page, source.last_page_read_us().unwrap_or(0))
+            }
+        };
+        let timing = self.timings.page(page_index);
+        timing.read_us = read_us;
+        timing.parse_us = elapsed_us(source, started).saturating_sub(read_us);
+        page
+    }
+
+    /// Position to save for the current page. Reflowed books save the
+    /// prerendered page, so positions survive layout changes.
+    pub fn saved_page(&self) -> usize {
+        match &self.reflow {
+            Some(reflow) => reflow.source_page(self.current_page),
+            None => self.current_page,
+        }
+    }
+
+    /// Page to show for a position saved by [`Self::saved_page`].
+    pub fn page_for_saved(&self, saved: usize) -> usize {
+        match &self.reflow {
+            Some(reflow) => reflow.page_for_source(saved),
+            None => saved,
+        }
+    }
+
+    pub fn next_part_name(&self) -> Option<&str> {
+        let part = self.current_book.as_ref()?.metadata.part.as_ref()?;
+        if part.has_next() {
+            Some(part.next.as_str())
+        } else {
+            None
+        }
+    }
+
     pub fn take_page_turn_indicator(&mut self) -> Option<PageTurnIndicator> {
         self.page_turn_indicator.take()
     }
@@ -136,14 +422,54 @@ impl BookReaderState {
         let mut result = BookViewResult {
             exit: false,
             open_toc: false,
+            open_next_part: false,
             dirty: false,
         };
 
+        if self.auto_turn_active() && has_pressed(buttons) {
+            self.auto_turn_paused = true;
+            self.auto_turn_elapsed_ms = 0;
+            result.dirty = true;
+        }
+
+        if has_pressed(buttons) && !buttons.is_pressed(input::Buttons::Back) {
+            self.back_pending_ms = None;
+        }
+
+        if self.front_matter_skipped.is_some() && has_pressed(buttons) {
+            self.front_matter_skipped = None;
+            result.dirty = true;
+            if buttons.is_pressed(input::Buttons::Confirm) {
+                self.jump_to(0);
+                self.page_turn_indicator = Some(PageTurnIndicator::Backward);
+                return result;
+            }
+        }
+
+        if self.part_prompt {
+            if buttons.is_pressed(input::Buttons::Confirm)
+                || buttons.is_pressed(input::Buttons::Right)
+                || buttons.is_pressed(input::Buttons::Down)
+            {
+                self.part_prompt = false;
+                result.open_next_part = true;
+                result.dirty = true;
+            } else if buttons.is_pressed(input::Buttons::Back)
+                || buttons.is_pressed(input::Buttons::Left)
+                || buttons.is_pressed(input::Buttons::Up)
+            {
+                self.part_prompt = false;
+                result.dirty = true;
+            }
+            return result;
+        }
+
         if buttons.is_pressed(input::Buttons::Left)
             || buttons.is_pressed(input::Buttons::Up)
         {
             if self.current_page > 0 {
                 self.current_page = self.current_page.saturating_sub(1);
+                self.pace.restart();
                 self.current_page_ops = None;
                 self.next_page_ops = None;
                 self.prefetched_page = None;
@@ -158,40 +484,41 @@ impl BookReaderState {
         if buttons.is_pressed(input::Buttons::Right)
             || buttons.is_pressed(input::Buttons::Down)
         {
-            if let Some(book) = &self.current_book {
-                if self.current_page + 1 < book.page_count {
-                    self.current_page += 1;
-                    if let Some(next_ops) = self.next_page_ops.take() {
-                        self.current_page_ops = Some(next_ops);
-                    } else {
-                        self.current_page_ops = None;
-                    }
-                    self.next_page_ops = None;
-                    self.prefetched_page = None;
-                    self.prefetched_gray2_used = false;
-                    self.book_turns_since_full = self.book_turns_since_full.saturating_add(1);
-                    self.page_turn_indicator = Some(PageTurnIndicator::Forward);
-                    result.dirty = true;
-                }
+            if self.turn_forward() {
+                result.dirty = true;
+            } else if self.next_part_name().is_some() {
+                self.part_prompt = true;
+                result.dirty = true;
             }
             return result;
         }
 
         if buttons.is_pressed(input::Buttons::Confirm) {
             if let Some(book) = &self.current_book {
-                if !book.toc.is_empty() {
-                    self.toc_selected = find_toc_selection(book, self.current_page);
-                    self.toc_labels = None;
-                    result.open_toc = true;
-                    result.dirty = true;
-                }
+                // Books without a ToC still open the screen for auto-turn and crop.
+                self.toc_selected = find_toc_selection(book, self.current_page);
+                self.toc_labels = None;
+                self.toc_crop_focus = false;
+                self.toc_back_focus = false;
+                result.open_toc = true;
+                result.dirty = true;
             }
             return result;
         }
 
         if buttons.is_pressed(input::Buttons::Back) {
-            result.exit = true;
-            result.dirty = true;
+            if self.back_pending_ms.take().is_some() {
+                if self.go_back() {
+                    self.page_turn_indicator = Some(PageTurnIndicator::Backward);
+                    result.dirty = true;
+                }
+            } else if self.history.is_empty() {
+                result.exit = true;
+                result.dirty = true;
+            } else {
+                // Wait to see whether a second press follows.
+                self.back_pending_ms = Some(0);
+            }
             return result;
         }
 
@@ -200,6 +527,374 @@ impl BookReaderState {
         result
     }
 
+    /// Moves to `page` from somewhere other than a page turn, remembering
+    /// where it came from for [`Self::go_back`].
+    fn jump_to(&mut self, page: usize) {
+        if self.history.last() != Some(&self.current_page) {
+            self.history.push(self.current_page);
+            if self.history.len() > HISTORY_LEN {
+                self.history.remove(0);
+            }
+        }
+        self.show_page(page);
+    }
+
+    /// Returns to the page the last jump left, if any.
+    pub fn go_back(&mut self) -> bool {
+        let Some(page) = self.history.pop() else {
+            return false;
+        };
+        log::info!("Back from page {} to {}", self.current_page + 1, page + 1);
+        self.show_page(page);
+        true
+    }
+
+    fn show_page(&mut self, page: usize) {
+        self.current_page = page;
+        self.pace.restart();
+        self.current_page_ops = None;
+        self.next_page_ops = None;
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+        self.last_rendered_page = None;
+        self.book_turns_since_full = 0;
+        self.auto_turn_elapsed_ms = 0;
+    }
+
+    /// Counts the wait after a Back press that could start a double press.
+    /// Returns true once it is over and the press should leave the book.
+    pub fn tick_back(&mut self, elapsed_ms: u32) -> bool {
+        let Some(waited) = self.back_pending_ms.as_mut() else {
+            return false;
+        };
+        *waited = waited.saturating_add(elapsed_ms);
+        if *waited < DOUBLE_BACK_MS {
+            return false;
+        }
+        self.back_pending_ms = None;
+        true
+    }
+
+    fn turn_forward(&mut self) -> bool {
+        let Some(book) = &self.current_book else {
+            return false;
+        };
+        if self.current_page + 1 >= book.page_count {
+            return false;
+        }
+        self.current_page += 1;
+        self.pace.turned();
+        if let Some(next_ops) = self.next_page_ops.take() {
+            self.current_page_ops = Some(next_ops);
+        } else {
+            self.current_page_ops = None;
+        }
+        self.next_page_ops = None;
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+        self.book_turns_since_full = self.book_turns_since_full.saturating_add(1);
+        self.page_turn_indicator = Some(PageTurnIndicator::Forward);
+        self.auto_turn_elapsed_ms = 0;
+        true
+    }
+
+    /// Opens the page scrubber once a page turn button has been held, moves
+    /// its target faster the longer it is held and jumps there on release.
+    pub fn tick_scrub(&mut self, buttons: &input::ButtonState, elapsed_ms: u32) -> ScrubTick {
+        let Some(book) = &self.current_book else {
+            return ScrubTick::Idle;
+        };
+        let last_page = book.page_count.saturating_sub(1);
+        let forward_held =
+            buttons.is_held(input::Buttons::Right) || buttons.is_held(input::Buttons::Down);
+        let back_held =
+            buttons.is_held(input::Buttons::Left) || buttons.is_held(input::Buttons::Up);
+        let Some(scrub) = self.scrub.as_mut() else {
+            if forward_held == back_held || self.part_prompt {
+                self.scrub_hold_ms = 0;
+                return ScrubTick::Idle;
+            }
+            self.scrub_hold_ms = self.scrub_hold_ms.saturating_add(elapsed_ms);
+            if self.scrub_hold_ms < SCRUB_HOLD_MS {
+                return ScrubTick::Idle;
+            }
+            self.scrub_hold_ms = 0;
+            self.scrub = Some(Scrub {
+                target: self.current_page,
+                forward: forward_held,
+                held_ms: 0,
+                frame_ms: 0,
+            });
+            self.scrub_draw_pending = true;
+            return ScrubTick::Moved;
+        };
+        if buttons.is_pressed(input::Buttons::Back) {
+            self.scrub = None;
+            return ScrubTick::Closed;
+        }
+        let held = if scrub.forward { forward_held } else { back_held };
+        if !held {
+            let target = scrub.target;
+            self.scrub = None;
+            if target == self.current_page {
+                return ScrubTick::Closed;
+            }
+            log::info!("Scrubbed from page {} to {}", self.current_page + 1, target + 1);
+            self.jump_to(target);
+            return ScrubTick::Jumped;
+        }
+        scrub.held_ms = scrub.held_ms.saturating_add(elapsed_ms);
+        scrub.frame_ms = scrub.frame_ms.saturating_add(elapsed_ms);
+        if scrub.frame_ms < SCRUB_FRAME_MS {
+            return ScrubTick::Held;
+        }
+        scrub.frame_ms = 0;
+        let step = SCRUB_SPEEDS
+            .iter()
+            .rev()
+            .find(|(after_ms, _)| scrub.held_ms >= *after_ms)
+            .map_or(1, |(_, pages)| *pages);
+        let target = if scrub.forward {
+            scrub.target.saturating_add(step).min(last_page)
+        } else {
+            scrub.target.saturating_sub(step)
+        };
+        if target == scrub.target {
+            return ScrubTick::Held;
+        }
+        scrub.target = target;
+        self.scrub_draw_pending = true;
+        ScrubTick::Moved
+    }
+
+    /// Draws the scrubber over the shown page with a fast refresh of just
+    /// its panel.
+    pub fn draw_scrubber(&mut self, buffers: &mut DisplayBuffers, display: &mut impl Display) {
+        self.scrub_draw_pending = false;
+        let (Some(scrub), Some(book)) = (self.scrub, &self.current_book) else {
+            return;
+        };
+        let inactive = *buffers.get_inactive_buffer();
+        buffers.get_active_buffer_mut().copy_from_slice(&inactive);
+        let size = buffers.size();
+        let (width, height) = (size.width as i32, size.height as i32);
+        let margin = relative(width.min(height), SCRUB_MARGIN_PER_MILLE, 8, 24);
+        let rect = Rect::new(
+            margin,
+            height - margin * 3 - SCRUB_HEIGHT,
+            width - margin * 2,
+            SCRUB_HEIGHT,
+        );
+        draw_scrub_panel(buffers, rect, scrub.target, book.page_count);
+        let mut rq = RenderQueue::default();
+        rq.push(rect, RefreshMode::Fast);
+        flush_queue(display, buffers, &mut rq, RefreshMode::Fast);
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+    }
+
+    pub fn auto_turn_active(&self) -> bool {
+        self.auto_turn_ms > 0 && !self.auto_turn_paused && self.current_book.is_some()
+    }
+
+    pub fn cycle_auto_turn(&mut self, forward: bool) {
+        let len = AUTO_TURN_INTERVALS_MS.len();
+        let current = AUTO_TURN_INTERVALS_MS
+            .iter()
+            .position(|ms| *ms == self.auto_turn_ms)
+            .unwrap_or(0);
+        let next = if forward {
+            (current + 1) % len
+        } else {
+            (current + len - 1) % len
+        };
+        self.auto_turn_ms = AUTO_TURN_INTERVALS_MS[next];
+        self.auto_turn_elapsed_ms = 0;
+        self.auto_turn_paused = false;
+    }
+
+    pub fn cycle_page_crop(&mut self, forward: bool) {
+        let len = PAGE_CROPS.len();
+        let current = PAGE_CROPS
+            .iter()
+            .position(|crop| *crop == self.page_crop)
+            .unwrap_or(0);
+        let next = if forward {
+            (current + 1).min(len - 1)
+        } else {
+            current.saturating_sub(1)
+        };
+        self.page_crop = PAGE_CROPS[next];
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+    }
+
+    /// Whether the zoom crop cuts into the book's margins on any side, so
+    /// some text would be lost.
+    pub fn crop_cuts_margins(&self, size: Size) -> bool {
+        let Some(book) = &self.current_book else {
+            return false;
+        };
+        let (crop_x, crop_y) = page_crop_sides(self.page_crop, size);
+        let metadata = &book.metadata;
+        (metadata.margin_left as i32) < crop_x
+            || (metadata.margin_right as i32) < crop_x
+            || (metadata.margin_top as i32) < crop_y
+            || (metadata.margin_bottom as i32) < crop_y
+    }
+
+    /// Advances the timed-reading countdown. Returns `Turned` when the page
+    /// should be redrawn and `Pips` when only the footer countdown changed.
+    pub fn tick_auto_turn(&mut self, elapsed_ms: u32) -> AutoTurnTick {
+        if !self.auto_turn_active() || self.part_prompt {
+            return AutoTurnTick::None;
+        }
+        self.auto_turn_elapsed_ms = self.auto_turn_elapsed_ms.saturating_add(elapsed_ms);
+        if self.auto_turn_elapsed_ms >= self.auto_turn_ms {
+            if self.turn_forward() {
+                self.page_turn_indicator = None;
+                return AutoTurnTick::Turned;
+            }
+            // Stop at the end of the book (or part) rather than spinning.
+            self.auto_turn_paused = true;
+            if self.next_part_name().is_some() {
+                self.part_prompt = true;
+            }
+            return AutoTurnTick::Turned;
+        }
+        if self.auto_turn_pips_drawn != Some(self.auto_turn_pips_remaining()) {
+            self.auto_turn_pips_pending = true;
+            return AutoTurnTick::Pips;
+        }
+        AutoTurnTick::None
+    }
+
+    fn chapter_position(&self, page: usize) -> Option<ChapterPosition> {
+        if !self.chapter_progress {
+            return None;
+        }
+        let (page_in_chapter, pages) = self.chapters.position(page)?;
+        Some(ChapterPosition {
+            page: page_in_chapter,
+            pages,
+            time_left_ms: self.pace.time_left_ms(pages - page_in_chapter + 1),
+        })
+    }
+
+    /// Estimated reading time to the end of the chapter and of the book,
+    /// e.g. `~18 min left in chapter, 3h 5m in book`.
+    fn time_left_label(&self, book: &crate::trbk::TrbkBookInfo) -> Option<String> {
+        let page = self.current_page.min(book.page_count.saturating_sub(1));
+        let book_ms = self.pace.time_left_ms(book.page_count.saturating_sub(page))?;
+        let label = match self.chapters.position(page) {
+            Some((page_in_chapter, pages)) => format!(
+                "~{} left in chapter, {} in book",
+                format_time_left(self.pace.time_left_ms(pages - page_in_chapter + 1)?),
+                format_time_left(book_ms)
+            ),
+            None => format!("~{} left in book", format_time_left(book_ms)),
+        };
+        Some(label)
+    }
+
+    /// Counts how long the page number has been up in clean page mode.
+    /// Returns true once it is due to be erased.
+    pub fn tick_page_indicator(&mut self, elapsed_ms: u32) -> bool {
+        let Some((_, shown_ms)) = self.page_indicator_shown.as_mut() else {
+            return false;
+        };
+        if self.page_indicator_erase_pending {
+            return false;
+        }
+        *shown_ms = shown_ms.saturating_add(elapsed_ms);
+        self.page_indicator_erase_pending = *shown_ms >= CLEAN_PAGE_INDICATOR_MS;
+        self.page_indicator_erase_pending
+    }
+
+    /// Blanks the page number with a fast refresh of the shown page, then
+    /// prefetches the next page again since the erase drew over it.
+    pub fn erase_page_indicator<S: AppSource>(
+        &mut self,
+        ctx: &mut BookReaderContext<'_, S>,
+        display: &mut impl Display,
+    ) {
+        self.page_indicator_erase_pending = false;
+        let Some((rect, _)) = self.page_indicator_shown.take() else {
+            return;
+        };
+        let buffers = &mut *ctx.display_buffers;
+        let inactive = *buffers.get_inactive_buffer();
+        buffers.get_active_buffer_mut().copy_from_slice(&inactive);
+        Rectangle::new(
+            Point::new(rect.x, rect.y),
+            Size::new(rect.w.max(0) as u32, rect.h.max(0) as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(buffers)
+        .ok();
+        let mut rq = RenderQueue::default();
+        rq.push(rect, RefreshMode::Fast);
+        flush_queue(display, buffers, &mut rq, RefreshMode::Fast);
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+        if let Some(book) = self.current_book.clone() {
+            self.prefetch_next_page(ctx, &book);
+        }
+    }
+
+    fn auto_turn_pips_remaining(&self) -> u32 {
+        if self.auto_turn_ms == 0 {
+            return 0;
+        }
+        let remaining = self.auto_turn_ms.saturating_sub(self.auto_turn_elapsed_ms);
+        (remaining * AUTO_TURN_PIPS).div_ceil(self.auto_turn_ms)
+    }
+
+    pub fn draw_auto_turn_pips(&mut self, buffers: &mut DisplayBuffers, display: &mut impl Display) {
+        // Draw over the last displayed frame (active buffer may hold a prefetched page).
+        let inactive = *buffers.get_inactive_buffer();
+        buffers.get_active_buffer_mut().copy_from_slice(&inactive);
+        self.auto_turn_pips_pending = false;
+        let rect = self.render_auto_turn_pips(buffers);
+        let mut rq = RenderQueue::default();
+        rq.push(rect, RefreshMode::Fast);
+        flush_queue(display, buffers, &mut rq, RefreshMode::Fast);
+        self.prefetched_page = None;
+        self.prefetched_gray2_used = false;
+    }
+
+    fn render_auto_turn_pips(&mut self, buffers: &mut DisplayBuffers) -> Rect {
+        let filled = self.auto_turn_pips_remaining();
+        let size = buffers.size();
+        let x = AUTO_TURN_PIP_MARGIN;
+        let y = size.height as i32 - AUTO_TURN_PIP_MARGIN - 14 + (14 - AUTO_TURN_PIP_SIZE) / 2;
+        let width = AUTO_TURN_PIPS as i32 * (AUTO_TURN_PIP_SIZE + AUTO_TURN_PIP_GAP);
+        Rectangle::new(
+            Point::new(x, y),
+            Size::new(width as u32, AUTO_TURN_PIP_SIZE as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(buffers)
+        .ok();
+        for idx in 0..AUTO_TURN_PIPS {
+            let pip_x = x + idx as i32 * (AUTO_TURN_PIP_SIZE + AUTO_TURN_PIP_GAP);
+            let style = if idx < filled {
+                PrimitiveStyle::with_fill(BinaryColor::Off)
+            } else {
+                PrimitiveStyle::with_stroke(BinaryColor::Off, 1)
+            };
+            Rectangle::new(
+                Point::new(pip_x, y),
+                Size::new(AUTO_TURN_PIP_SIZE as u32, AUTO_TURN_PIP_SIZE as u32),
+            )
+            .into_styled(style)
+            .draw(buffers)
+            .ok();
+        }
+        self.auto_turn_pips_drawn = Some(filled);
+        Rect::new(x - 2, y - 2, width + 4, AUTO_TURN_PIP_SIZE + 4)
+    }
+
     pub fn handle_toc_input(
         &mut self,
         buttons: &input::ButtonState,
@@ -208,6 +903,7 @@ impl BookReaderState {
             exit: false,
             jumped: false,
             dirty: false,
+            crop_changed: false,
         };
 
         let Some(book) = &self.current_book else {
@@ -217,35 +913,64 @@ impl BookReaderState {
         };
 
         let toc_len = book.toc.len();
+        if buttons.is_pressed(input::Buttons::Left) || buttons.is_pressed(input::Buttons::Right) {
+            let forward = buttons.is_pressed(input::Buttons::Right);
+            if self.toc_crop_focus {
+                self.cycle_page_crop(forward);
+                result.crop_changed = true;
+            } else {
+                self.cycle_auto_turn(forward);
+            }
+            result.dirty = true;
+            return result;
+        }
         if buttons.is_pressed(input::Buttons::Up) {
-            if self.toc_selected > 0 {
+            if self.toc_crop_focus {
+                self.toc_crop_focus = false;
+                result.dirty = true;
+            } else if self.toc_selected > 0 {
                 self.toc_selected -= 1;
                 result.dirty = true;
+            } else if !self.toc_back_focus && !self.history.is_empty() {
+                self.toc_back_focus = true;
+                result.dirty = true;
             }
             return result;
         }
         if buttons.is_pressed(input::Buttons::Down) {
-            if self.toc_selected + 1 < toc_len {
+            if self.toc_back_focus {
+                self.toc_back_focus = false;
+                result.dirty = true;
+            } else if self.toc_selected + 1 < toc_len {
                 self.toc_selected += 1;
                 result.dirty = true;
+            } else if !self.toc_crop_focus {
+                self.toc_crop_focus = true;
+                result.dirty = true;
             }
             return result;
         }
-        if buttons.is_pressed(input::Buttons::Confirm) {
-            if let Some(entry) = book.toc.get(self.toc_selected) {
-                self.current_page = entry.page_index as usize;
-                self.current_page_ops = None;
-                self.next_page_ops = None;
-                self.prefetched_page = None;
-                self.prefetched_gray2_used = false;
-                self.last_rendered_page = None;
-                self.book_turns_since_full = 0;
+        if buttons.is_pressed(input::Buttons::Confirm) && !self.toc_crop_focus {
+            let book = Rc::clone(book);
+            if self.toc_back_focus {
+                self.toc_back_focus = false;
+                if self.go_back() {
+                    self.auto_turn_paused = false;
+                    result.jumped = true;
+                    result.dirty = true;
+                }
+            } else if let Some(entry) = book.toc.get(self.toc_selected) {
+                let page = entry.page_index as usize;
+                self.jump_to(page);
+                self.auto_turn_paused = false;
                 result.jumped = true;
                 result.dirty = true;
             }
             return result;
         }
         if buttons.is_pressed(input::Buttons::Back) {
+            self.auto_turn_elapsed_ms = 0;
+            self.auto_turn_paused = false;
             result.exit = true;
             result.dirty = true;
             return result;
@@ -265,41 +990,87 @@ impl BookReaderState {
         };
         if self.toc_labels.is_none() {
             let mut labels: Vec<String> = Vec::with_capacity(book.toc.len());
-            for entry in &book.toc {
+            for (index, entry) in book.toc.iter().enumerate() {
                 let mut label = String::new();
                 let indent = (entry.level as usize).min(6);
                 for _ in 0..indent {
                     label.push_str("  ");
                 }
                 label.push_str(entry.title.as_str());
+                if let Some(percent) = entry_progress(book, index, self.current_page) {
+                    label.push_str(&format!("  {}%", percent));
+                }
                 labels.push(label);
             }
             self.toc_labels = Some(labels);
         }
         let labels = self.toc_labels.as_ref().map(Vec::as_slice).unwrap_or(&[]);
-        let items: Vec<ListItem<'_>> = labels
+        let back_label = self
+            .history
+            .last()
+            .map(|page| format!("Back to page {}", page + 1));
+        let items: Vec<ListItem<'_>> = back_label
             .iter()
+            .chain(labels)
             .map(|label| ListItem { label: label.as_str() })
             .collect();
 
         let title = book.metadata.title.as_str();
+        let time_left = self.time_left_label(book);
         let mut list = ListView::new(&items);
         list.title = Some(title);
-        list.footer = Some("Up/Down: select  Confirm: jump  Back: return");
+        list.footer = Some(if self.toc_crop_focus {
+            "Up: contents  Back: return"
+        } else {
+            "Up/Down: select  Confirm: jump  Back: return"
+        });
         list.empty_label = Some("No table of contents.");
-        list.selected = self.toc_selected.min(items.len().saturating_sub(1));
-        list.margin_x = LIST_MARGIN_X;
-        list.header_y = HEADER_Y;
-        list.list_top = LIST_TOP;
-        list.line_height = LINE_HEIGHT;
+        list.selected = if self.toc_back_focus {
+            0
+        } else {
+            let offset = back_label.is_some() as usize;
+            offset + self.toc_selected.min(labels.len().saturating_sub(1))
+        };
 
         let size = ctx.display_buffers.size();
         let rect = Rect::new(0, 0, size.width as i32, size.height as i32);
+        let metrics = LIST_METRICS.scaled(rect);
+        list.set_metrics(metrics);
+        // The time left goes under the title, a row above the entries.
+        if time_left.is_some() {
+            list.list_top += metrics.line_height;
+        }
         let mut rq = RenderQueue::default();
         let mut ui = UiContext {
             buffers: ctx.display_buffers,
         };
         list.render(&mut ui, rect, &mut rq);
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        if let Some(time_left) = time_left.as_deref() {
+            Text::new(time_left, Point::new(metrics.margin_x, metrics.header_y + metrics.line_height), style)
+                .draw(ctx.display_buffers)
+                .ok();
+        }
+        let auto_label = if self.toc_crop_focus {
+            match self.page_crop {
+                0 => String::from("> Left/Right: crop off"),
+                crop if self.crop_cuts_margins(size) => {
+                    format!("> Left/Right: crop {}px, cuts text", crop)
+                }
+                crop => format!("> Left/Right: crop {}px", crop),
+            }
+        } else if self.auto_turn_ms == 0 {
+            String::from("Left/Right: auto-turn off")
+        } else {
+            format!("Left/Right: auto-turn {}s", self.auto_turn_ms / 1000)
+        };
+        Text::new(
+            auto_label.as_str(),
+            Point::new(metrics.margin_x, size.height as i32 - 40),
+            style,
+        )
+        .draw(ctx.display_buffers)
+        .ok();
         let refresh = if *ctx.full_refresh {
             RefreshMode::Full
         } else {
@@ -319,6 +1090,11 @@ impl BookReaderState {
         };
         let book_ptr = book.as_ref() as *const crate::trbk::TrbkBookInfo;
         let book_page_count = book.page_count;
+        let part_prompt = if self.part_prompt {
+            book.metadata.part.clone()
+        } else {
+            None
+        };
         let using_prefetch = self.prefetched_page == Some(self.current_page);
         let mut gray2_used = false;
         let mut gray2_absolute = false;
@@ -329,17 +1105,42 @@ impl BookReaderState {
             ctx.gray2_lsb.fill(0);
             ctx.gray2_msb.fill(0);
             if self.current_page_ops.is_none() {
-                self.current_page_ops = ctx.source.trbk_page(self.current_page).ok();
+                self.current_page_ops = self.load_page(ctx.source, self.current_page).ok();
             }
             let page = self.current_page_ops.clone();
             if let Some(page) = page.as_ref() {
+                let mut timing = PageTiming::default();
                 unsafe {
-                    self.render_trbk_page_ops(ctx, &*book_ptr, page, &mut gray2_used, &mut gray2_absolute);
+                    self.render_trbk_page_ops(ctx, &*book_ptr, page, &mut gray2_used, &mut gray2_absolute, &mut timing);
                 }
+                self.record_drawing(self.current_page, timing);
+                self.apply_page_crop(ctx, gray2_used);
             }
         }
         self.last_rendered_page = Some(self.current_page);
-        draw_page_indicator(ctx.display_buffers, self.current_page, book_page_count);
+        let cut = match (&self.current_book, &self.current_page_ops) {
+            (Some(book), Some(page)) => {
+                page_cut_by_crop(book, page, self.page_crop, ctx.display_buffers.size())
+            }
+            _ => false,
+        };
+        let chapter = self.chapter_position(self.current_page);
+        let indicator =
+            draw_page_indicator(ctx.display_buffers, self.current_page, book_page_count, chapter, cut);
+        self.page_indicator_shown = indicator.filter(|_| self.clean_page).map(|rect| (rect, 0));
+        self.page_indicator_erase_pending = false;
+        if let Some(part) = part_prompt.as_ref() {
+            draw_part_prompt(ctx.display_buffers, part);
+        }
+        if let Some(title) = self.front_matter_skipped.as_deref() {
+            draw_front_matter_toast(ctx.display_buffers, title);
+        }
+        self.auto_turn_pips_pending = false;
+        if self.auto_turn_active() {
+            self.render_auto_turn_pips(ctx.display_buffers);
+        } else {
+            self.auto_turn_pips_drawn = None;
+        }
         if self.book_turns_since_full >= BOOK_FULL_REFRESH_EVERY {
             *ctx.full_refresh = true;
             self.book_turns_since_full = 0;
@@ -349,7 +1150,11 @@ impl BookReaderState {
         } else {
             RefreshMode::Fast
         };
-        if gray2_used {
+        let skip_gray = mode == RefreshMode::Fast
+            && !gray2_absolute
+            && !ctx.refresh_tuning.gray_after_fast;
+        let flush_started = ctx.source.now_us();
+        if gray2_used && !skip_gray {
             display.display(ctx.display_buffers, mode);
             let lsb_buf: &[u8; BUFFER_SIZE] = ctx.gray2_lsb.as_ref().try_into().unwrap();
             let msb_buf: &[u8; BUFFER_SIZE] = ctx.gray2_msb.as_ref().try_into().unwrap();
@@ -365,6 +1170,12 @@ impl BookReaderState {
             rq.push(Rect::new(0, 0, size.width as i32, size.height as i32), mode);
             flush_queue(display, ctx.display_buffers, &mut rq, mode);
         }
+        self.timings.page(self.current_page).flush_us = elapsed_us(ctx.source, flush_started);
+        if ctx.source.now_us().is_some() {
+            if let Some(timing) = self.timings.finish(self.current_page) {
+                timing.log(self.current_page);
+            }
+        }
 
         self.prefetched_page = None;
         self.prefetched_gray2_used = false;
@@ -372,7 +1183,7 @@ impl BookReaderState {
         if self.next_page_ops.is_none() {
             let next = self.current_page + 1;
             if next < book_page_count {
-                self.next_page_ops = ctx.source.trbk_page(next).ok();
+                self.next_page_ops = self.load_page(ctx.source, next).ok();
             }
         }
         unsafe {
@@ -388,8 +1199,10 @@ impl BookReaderState {
         page: &crate::trbk::TrbkPage,
         gray2_used: &mut bool,
         gray2_absolute: &mut bool,
+        timing: &mut PageTiming,
     ) {
         for op in &page.ops {
+            let started = ctx.source.now_us();
             match op {
                 crate::trbk::TrbkOp::TextRun { x, y, style, text } => {
                     let gray2_lsb = &mut *ctx.gray2_lsb;
@@ -404,6 +1217,8 @@ impl BookReaderState {
                         *style,
                         text,
                     );
+                    timing.glyph_count += text.chars().filter(|ch| !ch.is_whitespace()).count() as u32;
+                    timing.glyphs_us += elapsed_us(ctx.source, started);
                 }
                 crate::trbk::TrbkOp::Image {
                     x,
@@ -517,11 +1332,39 @@ impl BookReaderState {
                             );
                         }
                     }
+                    timing.image_count += 1;
+                    timing.images_us += elapsed_us(ctx.source, started);
                 }
             }
         }
     }
 
+    /// Keeps the draw times of `page`, replacing those of an earlier drawing.
+    fn record_drawing(&mut self, page: usize, drawn: PageTiming) {
+        let timing = self.timings.page(page);
+        timing.glyphs_us = drawn.glyphs_us;
+        timing.images_us = drawn.images_us;
+        timing.glyph_count = drawn.glyph_count;
+        timing.image_count = drawn.image_count;
+    }
+
+    /// Zooms the rendered page so the crop is cut off every side.
+    fn apply_page_crop<S: AppSource>(&self, ctx: &mut BookReaderContext<'_, S>, gray2_used: bool) {
+        if self.page_crop == 0 {
+            return;
+        }
+        let (crop_x, crop_y) = page_crop_sides(self.page_crop, ctx.display_buffers.size());
+        let (crop_x, crop_y) = match ctx.display_buffers.rotation() {
+            Rotation::Rotate0 | Rotation::Rotate180 => (crop_x as usize, crop_y as usize),
+            Rotation::Rotate90 | Rotation::Rotate270 => (crop_y as usize, crop_x as usize),
+        };
+        zoom_plane(ctx.display_buffers.get_active_buffer_mut(), crop_x, crop_y);
+        if gray2_used {
+            zoom_plane(ctx.gray2_lsb, crop_x, crop_y);
+            zoom_plane(ctx.gray2_msb, crop_x, crop_y);
+        }
+    }
+
     fn prefetch_next_page<S: AppSource>(
         &mut self,
         ctx: &mut BookReaderContext<'_, S>,
@@ -535,7 +1378,7 @@ impl BookReaderState {
             return;
         }
         if self.next_page_ops.is_none() {
-            self.next_page_ops = ctx.source.trbk_page(next).ok();
+            self.next_page_ops = self.load_page(ctx.source, next).ok();
         }
         let Some(page) = self.next_page_ops.clone() else {
             return;
@@ -545,8 +1388,13 @@ impl BookReaderState {
         ctx.gray2_msb.fill(0);
         let mut gray2_used = false;
         let mut gray2_absolute = false;
-        self.render_trbk_page_ops(ctx, book, &page, &mut gray2_used, &mut gray2_absolute);
-        draw_page_indicator(ctx.display_buffers, next, book.page_count);
+        let mut timing = PageTiming::default();
+        self.render_trbk_page_ops(ctx, book, &page, &mut gray2_used, &mut gray2_absolute, &mut timing);
+        self.record_drawing(next, timing);
+        self.apply_page_crop(ctx, gray2_used);
+        let cut = page_cut_by_crop(book, &page, self.page_crop, ctx.display_buffers.size());
+        let chapter = self.chapter_position(next);
+        draw_page_indicator(ctx.display_buffers, next, book.page_count, chapter, cut);
         if gray2_absolute {
             self.prefetched_page = None;
             self.prefetched_gray2_used = false;
@@ -736,11 +1584,108 @@ pub(crate) fn draw_trbk_image(
     }
 }
 
-fn draw_page_indicator(buffers: &mut DisplayBuffers, page: usize, total: usize) {
+/// Logical pixels a zoom crop of `crop` cuts off the left and right, and off
+/// the top and bottom, which keeps the page's aspect ratio.
+fn page_crop_sides(crop: u8, size: Size) -> (i32, i32) {
+    let crop = crop as i32;
+    (crop, crop * size.height as i32 / size.width.max(1) as i32)
+}
+
+/// Scales the middle of a framebuffer plane up to the whole plane, cutting
+/// `crop_x` and `crop_y` framebuffer pixels off each side.
+fn zoom_plane(plane: &mut [u8], crop_x: usize, crop_y: usize) {
+    const ROW_BYTES: usize = FB_WIDTH / 8;
+    let src_w = FB_WIDTH.saturating_sub(crop_x * 2).max(1);
+    let src_h = FB_HEIGHT.saturating_sub(crop_y * 2).max(1);
+    let mut row = [0u8; ROW_BYTES];
+    // Rows spread out from the middle, so the lower half is filled bottom-up
+    // and the upper half top-down to read each source row before it is
+    // overwritten.
+    let rows = (FB_HEIGHT / 2..FB_HEIGHT).rev().chain(0..FB_HEIGHT / 2);
+    for y in rows {
+        let src_y = crop_y + y * src_h / FB_HEIGHT;
+        row.copy_from_slice(&plane[src_y * ROW_BYTES..(src_y + 1) * ROW_BYTES]);
+        let dst = &mut plane[y * ROW_BYTES..(y + 1) * ROW_BYTES];
+        for x in 0..FB_WIDTH {
+            let src_x = crop_x + x * src_w / FB_WIDTH;
+            let mask = 1 << (7 - (x % 8));
+            if (row[src_x / 8] >> (7 - (src_x % 8))) & 1 == 1 {
+                dst[x / 8] |= mask;
+            } else {
+                dst[x / 8] &= !mask;
+            }
+        }
+    }
+}
+
+/// Whether a zoom crop of `crop` cuts off any text or inline image on `page`.
+/// Full-screen images such as covers are expected to lose their edges.
+fn page_cut_by_crop(
+    book: &crate::trbk::TrbkBookInfo,
+    page: &crate::trbk::TrbkPage,
+    crop: u8,
+    size: Size,
+) -> bool {
+    if crop == 0 {
+        return false;
+    }
+    let (crop_x, crop_y) = page_crop_sides(crop, size);
+    let (width, height) = (size.width as i32, size.height as i32);
+    let outside = |left: i32, top: i32, right: i32, bottom: i32| {
+        left < crop_x || top < crop_y || right > width - crop_x || bottom > height - crop_y
+    };
+    page.ops.iter().any(|op| match op {
+        crate::trbk::TrbkOp::TextRun { x, y, style, text } => {
+            let advance: i32 = text
+                .chars()
+                .map(|ch| match find_glyph(book.glyphs.as_slice(), *style, ch as u32) {
+                    Some(glyph) => glyph.x_advance as i32,
+                    None => book.metadata.char_width as i32,
+                })
+                .sum();
+            outside(*x, *y - book.metadata.ascent as i32, *x + advance, *y)
+        }
+        crate::trbk::TrbkOp::Image {
+            x,
+            y,
+            width: image_w,
+            height: image_h,
+            ..
+        } => {
+            let full_screen = *x <= 0
+                && *y <= 0
+                && *image_w as i32 >= width
+                && *image_h as i32 >= height;
+            !full_screen && outside(*x, *y, *x + *image_w as i32, *y + *image_h as i32)
+        }
+    })
+}
+
+/// Page number in the bottom-right corner, after the page within its chapter
+/// and the time left in it when `chapter` is given, and marked with `!` when
+/// the zoom crop cuts off some of the page. Returns the area it covers.
+fn draw_page_indicator(
+    buffers: &mut DisplayBuffers,
+    page: usize,
+    total: usize,
+    chapter: Option<ChapterPosition>,
+    cut: bool,
+) -> Option<Rect> {
     if total == 0 {
-        return;
+        return None;
+    }
+    let mut label = String::new();
+    if cut {
+        label.push_str("! ");
     }
-    let label = format!("{}/{}", page.saturating_add(1), total);
+    if let Some(chapter) = chapter {
+        label.push_str(&format!("{} of {} in chapter", chapter.page, chapter.pages));
+        if let Some(ms) = chapter.time_left_ms {
+            label.push_str(&format!(" (~{})", format_time_left(ms)));
+        }
+        label.push_str("  ");
+    }
+    label.push_str(&format!("{}/{}", page.saturating_add(1), total));
     let text_w = (label.len() as i32) * 10;
     let size = buffers.size();
     let margin = 8;
@@ -750,6 +1695,80 @@ fn draw_page_indicator(buffers: &mut DisplayBuffers, page: usize, total: usize)
     Text::new(label.as_str(), Point::new(x, y), style)
         .draw(buffers)
         .ok();
+    Some(Rect::new(x - 2, y - 18, text_w + 4, 24))
+}
+
+fn draw_scrub_panel(buffers: &mut DisplayBuffers, rect: Rect, target: usize, total: usize) {
+    Rectangle::new(Point::new(rect.x, rect.y), Size::new(rect.w as u32, rect.h as u32))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(buffers)
+        .ok();
+    Rectangle::new(Point::new(rect.x, rect.y), Size::new(rect.w as u32, rect.h as u32))
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+        .draw(buffers)
+        .ok();
+    let label = format!("Page {} of {}", target + 1, total);
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+    Text::new(&label, Point::new(rect.x + 12, rect.y + 26), style)
+        .draw(buffers)
+        .ok();
+    let track_x = rect.x + 12;
+    let track_w = rect.w - 24;
+    let track_y = rect.y + 46;
+    Rectangle::new(Point::new(track_x, track_y), Size::new(track_w as u32, 2))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(buffers)
+        .ok();
+    let marker_x = if total > 1 {
+        track_x + (track_w as i64 * target as i64 / (total as i64 - 1)) as i32
+    } else {
+        track_x
+    };
+    Rectangle::new(Point::new(marker_x - 4, track_y - 9), Size::new(8, 20))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(buffers)
+        .ok();
+}
+
+fn has_pressed(buttons: &input::ButtonState) -> bool {
+    use input::Buttons::*;
+    [Back, Confirm, Left, Right, Up, Down]
+        .iter()
+        .any(|b| buttons.is_pressed(*b))
+}
+
+fn draw_part_prompt(buffers: &mut DisplayBuffers, part: &crate::trbk::TrbkPartInfo) {
+    let title = format!("Continue in part {}?", part.index.saturating_add(1));
+    draw_prompt(buffers, &title, "Confirm: open  Back: stay");
+}
+
+fn draw_front_matter_toast(buffers: &mut DisplayBuffers, title: &str) {
+    let title = crate::ui::ellipsize(title, 30);
+    let title = format!("Skipped to {}", title);
+    draw_prompt(buffers, &title, "Confirm: back to beginning");
+}
+
+/// Two lines in an inverted box above the page number.
+fn draw_prompt(buffers: &mut DisplayBuffers, title: &str, hint: &str) {
+    let text_w = (title.len().max(hint.len()) as i32) * 10;
+    let padding_x = 12;
+    let padding_y = 8;
+    let rect_w = text_w + padding_x * 2;
+    let rect_h = 20 * 2 + padding_y * 3;
+    let size = buffers.size();
+    let x = ((size.width as i32 - rect_w) / 2).max(0);
+    let y = (size.height as i32 - rect_h - 40).max(0);
+    Rectangle::new(Point::new(x, y), Size::new(rect_w as u32, rect_h as u32))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(buffers)
+        .ok();
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    Text::new(title, Point::new(x + padding_x, y + padding_y + 16), style)
+        .draw(buffers)
+        .ok();
+    Text::new(hint, Point::new(x + padding_x, y + padding_y * 2 + 36), style)
+        .draw(buffers)
+        .ok();
 }
 
 fn map_display_point(rotation: Rotation, x: i32, y: i32) -> Option<(usize, usize)> {
@@ -779,6 +1798,38 @@ fn find_glyph<'a>(
         .find(|glyph| glyph.style == style && glyph.codepoint == codepoint)
 }
 
+/// ToC entry where the text of `book` starts: the first that looks like a
+/// first chapter or prologue, else the first after the entries that look
+/// like front matter. Only entries in the first fifth of the book count, and
+/// `None` means the book should open at its first page.
+pub fn front_matter_end(book: &crate::trbk::TrbkBookInfo) -> Option<usize> {
+    let limit = book.page_count / 5;
+    let toc = &book.toc;
+    let start = toc
+        .iter()
+        .position(|entry| title_matches(&entry.title, FIRST_CHAPTER_TITLES))
+        .or_else(|| {
+            let first = toc
+                .iter()
+                .position(|entry| !title_matches(&entry.title, FRONT_MATTER_TITLES))?;
+            (first > 0).then_some(first)
+        })?;
+    let page = toc[start].page_index as usize;
+    (page > 0 && page <= limit).then_some(start)
+}
+
+/// Whether `title` is one of `patterns` or starts with one followed by
+/// punctuation or a space, so "Chapter 1: Arrival" matches "chapter 1" but
+/// "Chapter 10" does not.
+fn title_matches(title: &str, patterns: &[&str]) -> bool {
+    let title = title.trim().to_lowercase();
+    patterns.iter().any(|pattern| {
+        title.strip_prefix(pattern).is_some_and(|rest| {
+            rest.chars().next().is_none_or(|ch| !ch.is_alphanumeric())
+        })
+    })
+}
+
 pub fn find_toc_selection(book: &crate::trbk::TrbkBookInfo, page: usize) -> usize {
     let mut selected = 0usize;
     for (idx, entry) in book.toc.iter().enumerate() {
@@ -0,0 +1,68 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::trbk::TrbkBookInfo;
+
+/// First page of each chapter of the open book, taken from the top-level ToC
+/// entries. Built once when the book is opened.
+#[derive(Clone, Debug, Default)]
+pub struct ChapterMap {
+    starts: Vec<usize>,
+    page_count: usize,
+}
+
+impl ChapterMap {
+    /// Pages before the first chapter count as a chapter of their own, so
+    /// every page has one.
+    pub fn for_book(book: &TrbkBookInfo) -> Self {
+        let top = book.toc.iter().map(|entry| entry.level).min().unwrap_or(0);
+        let mut starts = Vec::with_capacity(book.toc.len() + 1);
+        starts.push(0);
+        starts.extend(
+            book.toc
+                .iter()
+                .filter(|entry| entry.level == top)
+                .map(|entry| entry.page_index as usize)
+                .filter(|page| *page < book.page_count),
+        );
+        starts.sort_unstable();
+        starts.dedup();
+        Self {
+            starts,
+            page_count: book.page_count,
+        }
+    }
+
+    /// Page within its chapter, counting from 1, and the chapter's length.
+    pub fn position(&self, page: usize) -> Option<(usize, usize)> {
+        if page >= self.page_count {
+            return None;
+        }
+        let chapter = self.starts.partition_point(|start| *start <= page).checked_sub(1)?;
+        let start = self.starts[chapter];
+        let end = self
+            .starts
+            .get(chapter + 1)
+            .copied()
+            .unwrap_or(self.page_count);
+        Some((page - start + 1, end - start))
+    }
+}
+
+/// How far `page` is through ToC entry `index`, in percent: up to the next
+/// entry at the same or a higher level. `None` for entries not reached yet.
+pub fn entry_progress(book: &TrbkBookInfo, index: usize, page: usize) -> Option<u8> {
+    let entry = book.toc.get(index)?;
+    let start = entry.page_index as usize;
+    if page < start {
+        return None;
+    }
+    let end = book.toc[index + 1..]
+        .iter()
+        .find(|next| next.level <= entry.level)
+        .map_or(book.page_count, |next| next.page_index as usize)
+        .max(start + 1);
+    let read = (page + 1 - start).min(end - start);
+    Some((read * 100 / (end - start)) as u8)
+}
@@ -0,0 +1,263 @@
+extern crate alloc;
+
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
+
+use crate::image_viewer::{DiagnosticsSource, HeapUsage};
+use crate::input::{ButtonState, Buttons};
+
+const MAX_MARKS: usize = 24;
+/// Pages whose load and draw take longer than this are logged as slow. The
+/// panel flush is left out, as full refreshes are slow by design.
+pub const SLOW_PAGE_US: u32 = 800_000;
+/// Pages timed at once: the one shown and the ones loaded or drawn ahead.
+const TIMED_PAGES: usize = 3;
+/// Events the flight recorder keeps; older ones are dropped first.
+const FLIGHT_EVENTS: usize = 200;
+/// Text file in the root of the card the flight recorder is written to.
+pub const FLIGHT_LOG_FILE: &str = "TRFLIGHT.TXT";
+const BUTTON_NAMES: [(Buttons, &str); 7] = [
+    (Buttons::Back, "Back"),
+    (Buttons::Confirm, "Confirm"),
+    (Buttons::Left, "Left"),
+    (Buttons::Right, "Right"),
+    (Buttons::Up, "Up"),
+    (Buttons::Down, "Down"),
+    (Buttons::Power, "Power"),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapMark {
+    pub label: &'static str,
+    pub peak: usize,
+    pub samples: u32,
+}
+
+/// Heap high-water marks keyed by app state or operation name.
+///
+/// Samples are taken after an operation completes, so a mark is the most heap
+/// that stayed live across it, not transient peaks inside it; the allocator's
+/// own peak covers those globally.
+#[derive(Default)]
+pub struct HeapMarks {
+    marks: Vec<HeapMark>,
+    last: Option<HeapUsage>,
+}
+
+impl HeapMarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, label: &'static str, usage: Option<HeapUsage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        self.last = Some(usage);
+        if let Some(mark) = self.marks.iter_mut().find(|mark| mark.label == label) {
+            mark.samples = mark.samples.saturating_add(1);
+            if usage.used > mark.peak {
+                mark.peak = usage.used;
+                log::info!("heap high-water {}: {} bytes", label, usage.used);
+            }
+            return;
+        }
+        if self.marks.len() >= MAX_MARKS {
+            return;
+        }
+        log::info!("heap high-water {}: {} bytes", label, usage.used);
+        self.marks.push(HeapMark {
+            label,
+            peak: usage.used,
+            samples: 1,
+        });
+    }
+
+    pub fn marks(&self) -> &[HeapMark] {
+        &self.marks
+    }
+
+    pub fn last(&self) -> Option<HeapUsage> {
+        self.last
+    }
+
+    pub fn log_summary(&self) {
+        if let Some(usage) = self.last {
+            log::info!(
+                "heap used={} free={} peak={}",
+                usage.used,
+                usage.free,
+                usage.peak
+            );
+        }
+        for mark in &self.marks {
+            log::info!(
+                "heap mark {}: peak={} samples={}",
+                mark.label,
+                mark.peak,
+                mark.samples
+            );
+        }
+    }
+}
+
+/// Where the time to show one book page went, in microseconds. Stages stay
+/// zero when the platform has no clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PageTiming {
+    pub read_us: u32,
+    pub parse_us: u32,
+    pub glyphs_us: u32,
+    pub images_us: u32,
+    pub flush_us: u32,
+    pub glyph_count: u32,
+    pub image_count: u32,
+}
+
+impl PageTiming {
+    pub fn render_us(&self) -> u32 {
+        self.read_us
+            .saturating_add(self.parse_us)
+            .saturating_add(self.glyphs_us)
+            .saturating_add(self.images_us)
+    }
+
+    pub fn log(&self, page: usize) {
+        let ms = |us: u32| us / 1000;
+        if self.render_us() >= SLOW_PAGE_US {
+            log::warn!(
+                "slow page {}: {} ms (read {}, parse {}, {} glyphs {}, {} images {}, flush {})",
+                page + 1,
+                ms(self.render_us()),
+                ms(self.read_us),
+                ms(self.parse_us),
+                self.glyph_count,
+                ms(self.glyphs_us),
+                self.image_count,
+                ms(self.images_us),
+                ms(self.flush_us)
+            );
+        } else {
+            log::debug!(
+                "page {}: {} ms + flush {} ms",
+                page + 1,
+                ms(self.render_us()),
+                ms(self.flush_us)
+            );
+        }
+    }
+}
+
+/// Timings of the pages being loaded, drawn and shown. A page is often loaded
+/// and drawn ahead, while the one before it is on screen.
+#[derive(Default)]
+pub struct PageTimings {
+    pages: Vec<(usize, PageTiming)>,
+}
+
+impl PageTimings {
+    pub fn page(&mut self, page: usize) -> &mut PageTiming {
+        let at = match self.pages.iter().position(|(index, _)| *index == page) {
+            Some(at) => at,
+            None => {
+                if self.pages.len() >= TIMED_PAGES {
+                    self.pages.remove(0);
+                }
+                self.pages.push((page, PageTiming::default()));
+                self.pages.len() - 1
+            }
+        };
+        &mut self.pages[at].1
+    }
+
+    /// Timing of a page once it is on screen.
+    pub fn finish(&mut self, page: usize) -> Option<PageTiming> {
+        let at = self.pages.iter().position(|(index, _)| *index == page)?;
+        Some(self.pages.remove(at).1)
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+/// Microseconds since `start`, an earlier [`DiagnosticsSource::now_us`].
+pub fn elapsed_us(source: &impl DiagnosticsSource, start: Option<u64>) -> u32 {
+    start
+        .zip(source.now_us())
+        .map(|(start, now)| now.saturating_sub(start).min(u32::MAX as u64) as u32)
+        .unwrap_or(0)
+}
+
+enum FlightEvent {
+    State(&'static str),
+    /// Buttons pressed in one update, as a mask over `BUTTON_NAMES`.
+    Press(u8),
+    Error(String),
+}
+
+/// The last few hundred state changes, button presses and errors, kept in
+/// RAM and written to [`FLIGHT_LOG_FILE`] when something goes wrong, so a bug
+/// report can come with what led up to it.
+#[derive(Default)]
+pub struct FlightRecorder {
+    events: VecDeque<(u32, FlightEvent)>,
+    state: Option<&'static str>,
+}
+
+impl FlightRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, at_ms: u32, event: FlightEvent) {
+        if self.events.len() >= FLIGHT_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back((at_ms, event));
+    }
+
+    /// Records `state` if it differs from the last one recorded.
+    pub fn state(&mut self, at_ms: u32, state: &'static str) {
+        if self.state != Some(state) {
+            self.state = Some(state);
+            self.push(at_ms, FlightEvent::State(state));
+        }
+    }
+
+    pub fn buttons(&mut self, at_ms: u32, buttons: &ButtonState) {
+        let mask = BUTTON_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(_, (button, _))| buttons.is_pressed(*button))
+            .fold(0u8, |mask, (index, _)| mask | 1 << index);
+        if mask != 0 {
+            self.push(at_ms, FlightEvent::Press(mask));
+        }
+    }
+
+    pub fn error(&mut self, at_ms: u32, message: &str) {
+        self.push(at_ms, FlightEvent::Error(message.into()));
+    }
+
+    /// One line per event, oldest first, e.g. `  12345 ms  press Up`.
+    pub fn to_text(&self, now_ms: u32, reason: &str) -> String {
+        let mut text = format!("TernReader flight log ({}), uptime {} ms\n", reason, now_ms);
+        for (at_ms, event) in &self.events {
+            let line = match event {
+                FlightEvent::State(state) => format!("state {}", state),
+                FlightEvent::Press(mask) => {
+                    let names: Vec<&str> = BUTTON_NAMES
+                        .iter()
+                        .enumerate()
+                        .filter(|(index, _)| mask & (1 << index) != 0)
+                        .map(|(_, (_, name))| *name)
+                        .collect();
+                    format!("press {}", names.join("+"))
+                }
+                FlightEvent::Error(message) => format!("error {}", message),
+            };
+            text.push_str(&format!("{:>9} ms  {}\n", at_ms, line));
+        }
+        text
+    }
+}
@@ -1,6 +1,6 @@
 extern crate alloc;
 
-use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec, vec::Vec};
 
 use embedded_graphics::{
     mono_font::{ascii::FONT_10X20, MonoTextStyle},
@@ -14,15 +14,35 @@ use embedded_graphics::{
 use crate::display::{Display, GrayscaleMode, RefreshMode};
 use crate::framebuffer::{DisplayBuffers, Rotation, BUFFER_SIZE, HEIGHT as FB_HEIGHT, WIDTH as FB_WIDTH};
 use crate::image_viewer::{AppSource, ImageData, ImageEntry, ImageError};
-use crate::ui::{flush_queue, ListItem, ListView, Rect, RenderQueue, UiContext, View};
+use crate::ui::{
+    ellipsize, flush_queue, relative, wrap_two_lines, ListItem, ListMetrics, ListView, Rect,
+    RenderQueue, UiContext, View,
+};
 
 const START_MENU_MARGIN: i32 = 16;
 const START_MENU_RECENT_THUMB: i32 = 74;
 const START_MENU_ACTION_GAP: i32 = 12;
-const HEADER_Y: i32 = 28;
-const LIST_TOP: i32 = 72;
-const LINE_HEIGHT: i32 = 30;
-const LIST_MARGIN_X: i32 = 18;
+/// Space on either side of the line between recents and actions.
+const START_MENU_SECTION_GAP: i32 = 17;
+const START_MENU_MAX_RECENTS: i32 = 6;
+/// File browser chrome on the portrait panel, scaled to the screen.
+const LIST_METRICS: ListMetrics = ListMetrics {
+    margin_x: 18,
+    header_y: 28,
+    list_top: 72,
+    line_height: 30,
+};
+/// Image in a folder that stands for the folder, e.g. a series cover.
+const FOLDER_COVER: &str = "_cover.tri";
+const FOLDER_ICON: i32 = 26;
+/// Virtual first entry of every folder below the root; opening it goes up.
+const PARENT_ENTRY: &str = "..";
+/// Characters of the file browser header.
+const MENU_TITLE_CHARS: usize = 44;
+/// Letters of the file browser's jump rail; `#` is everything else.
+const LETTER_RAIL: &[u8] = b"#ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const RAIL_WIDTH: i32 = 28;
+const RAIL_LINE_HEIGHT: i32 = 24;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StartMenuSection {
@@ -54,6 +74,22 @@ pub struct HomeState {
     pub start_menu_cache: Vec<RecentPreview>,
     pub start_menu_nav_pending: bool,
     pub start_menu_need_base_refresh: bool,
+    pub skip_thumbnails: bool,
+    /// Folder cover thumbnails by folder path and size; `None` for folders
+    /// without one.
+    pub folder_covers: BTreeMap<(String, u32), Option<ImageData>>,
+    /// Selected letter while the file browser's jump rail is open.
+    pub letter_rail: Option<usize>,
+    /// Folder just left by going up, selected once the parent is listed.
+    pub return_to: Option<String>,
+    /// Profile in use, shown in the header when there is more than one.
+    pub profile_name: Option<String>,
+    /// Folder browsing is locked to in simple mode, which also hides
+    /// Settings and the profile switcher.
+    pub simple_root: Option<String>,
+    /// Folders and files [`Self::warm_step`] has yet to visit.
+    warm_dirs: Vec<Vec<String>>,
+    warm_files: Vec<(Vec<String>, ImageEntry)>,
 }
 
 #[derive(Debug)]
@@ -72,6 +108,7 @@ pub enum HomeAction {
     OpenRecent(String),
     OpenFileBrowser,
     OpenSettings,
+    OpenProfiles,
 }
 
 pub enum MenuAction {
@@ -125,30 +162,151 @@ impl HomeState {
             start_menu_cache: Vec::new(),
             start_menu_nav_pending: false,
             start_menu_need_base_refresh: true,
+            skip_thumbnails: false,
+            folder_covers: BTreeMap::new(),
+            letter_rail: None,
+            return_to: None,
+            profile_name: None,
+            simple_root: None,
+            warm_dirs: vec![Vec::new()],
+            warm_files: Vec::new(),
         }
     }
 
+    /// Walks the card again with [`Self::warm_step`], e.g. after USB.
+    pub fn restart_warm(&mut self) {
+        self.warm_dirs = vec![Vec::new()];
+        self.warm_files.clear();
+    }
+
+    /// Does one piece of the background walk that builds missing book
+    /// thumbnails, titles and folder covers: lists one folder or handles one
+    /// entry. Returns false once the whole card was visited.
+    pub fn warm_step<S: AppSource>(&mut self, source: &mut S) -> bool {
+        if self.skip_thumbnails {
+            return false;
+        }
+        if let Some((dir, entry)) = self.warm_files.pop() {
+            let mut path = dir.clone();
+            path.push(entry.name.clone());
+            if entry.kind == crate::image_viewer::EntryKind::Dir {
+                self.folder_cover(source, &path, FOLDER_ICON as u32);
+                self.warm_dirs.push(path);
+                return true;
+            }
+            let lower = entry.name.to_ascii_lowercase();
+            let thumbnailed = lower.ends_with(".trbk") || lower.ends_with(".tbk") || lower.ends_with(".tri");
+            if thumbnailed && !entry.name.eq_ignore_ascii_case(FOLDER_COVER) {
+                let key = path.join("/");
+                if source.load_thumbnail(&key).is_none() {
+                    self.load_recent_preview(source, &key);
+                }
+            }
+            return true;
+        }
+        let Some(dir) = self.warm_dirs.pop() else {
+            return false;
+        };
+        match source.refresh(&dir) {
+            Ok(entries) => {
+                for entry in entries.into_iter().rev() {
+                    if entry.name != PARENT_ENTRY {
+                        self.warm_files.push((dir.clone(), entry));
+                    }
+                }
+            }
+            Err(err) => log::warn!("Thumbnail walk skipped {}: {:?}", dir.join("/"), err),
+        }
+        true
+    }
+
     pub fn set_entries(&mut self, entries: Vec<ImageEntry>) {
         self.entries = entries;
+        if self.path.len() > self.root_depth() {
+            self.entries.insert(
+                0,
+                ImageEntry {
+                    name: PARENT_ENTRY.to_string(),
+                    kind: crate::image_viewer::EntryKind::Dir,
+                },
+            );
+        }
+        self.letter_rail = None;
+        let returned = self
+            .return_to
+            .take()
+            .and_then(|name| self.entries.iter().position(|entry| entry.name == name));
+        if let Some(index) = returned {
+            self.selected = index;
+        }
         if self.selected >= self.entries.len() {
             self.selected = 0;
         }
     }
 
+    /// Leaves the current folder; the caller lists the parent. Returns false
+    /// at the root, or at the simple mode folder.
+    pub fn go_up(&mut self) -> bool {
+        if self.path.len() <= self.root_depth() {
+            self.return_to = None;
+            return false;
+        }
+        self.return_to = self.path.pop();
+        self.return_to.is_some()
+    }
+
+    fn root_depth(&self) -> usize {
+        self.simple_root.is_some() as usize
+    }
+
+    /// Moves the file browser into the simple mode folder if it is outside.
+    pub fn enter_root(&mut self) {
+        let Some(root) = &self.simple_root else {
+            return;
+        };
+        if self.path.first() != Some(root) {
+            self.path = vec![root.clone()];
+            self.selected = 0;
+        }
+    }
+
+    /// Whether entry path `path` may be opened in simple mode.
+    pub fn in_root(&self, path: &str) -> bool {
+        match &self.simple_root {
+            Some(root) => path
+                .trim_start_matches('/')
+                .strip_prefix(root.as_str())
+                .is_some_and(|rest| rest.starts_with('/')),
+            None => true,
+        }
+    }
+
     pub fn refresh_entries<S: AppSource>(&mut self, source: &mut S) -> Result<(), ImageError> {
         let entries = source.refresh(&self.path)?;
         self.set_entries(entries);
         Ok(())
     }
 
+    /// Breadcrumb of the current folder. Deep paths keep the first and last
+    /// folders and elide the middle, e.g. `/Books/.../Series/Part 2`.
     pub fn menu_title(&self) -> String {
-        if self.path.is_empty() {
-            "/".to_string()
-        } else {
-            let mut title = String::from("/");
-            title.push_str(&self.path.join("/"));
-            title
+        let full = format!("/{}", self.path.join("/"));
+        if full.chars().count() <= MENU_TITLE_CHARS || self.path.len() < 3 {
+            return ellipsize(&full, MENU_TITLE_CHARS).into_owned();
         }
+        let head = format!("/{}/...", self.path[0]);
+        let last = &self.path[self.path.len() - 1];
+        let mut used = head.chars().count() + 1 + last.chars().count();
+        let mut kept = 0;
+        for part in self.path[1..self.path.len() - 1].iter().rev() {
+            used += part.chars().count() + 1;
+            if used > MENU_TITLE_CHARS {
+                break;
+            }
+            kept += 1;
+        }
+        let tail = self.path[self.path.len() - 1 - kept..].join("/");
+        ellipsize(&format!("{}/{}", head, tail), MENU_TITLE_CHARS).into_owned()
     }
 
     pub fn entry_path_string(&self, entry: &ImageEntry) -> String {
@@ -173,8 +331,13 @@ impl HomeState {
             return Err(HomeOpenError::Empty);
         };
         match entry.kind {
+            crate::image_viewer::EntryKind::Dir if entry.name == PARENT_ENTRY => {
+                self.go_up();
+                Ok(HomeOpen::EnterDir)
+            }
             crate::image_viewer::EntryKind::Dir => {
                 self.path.push(entry.name);
+                self.selected = 0;
                 Ok(HomeOpen::EnterDir)
             }
             crate::image_viewer::EntryKind::File => Ok(HomeOpen::OpenFile(entry)),
@@ -221,6 +384,16 @@ impl HomeState {
         }
     }
 
+    /// First entry filed under rail letter `letter`, or failing that under
+    /// the nearest later one.
+    fn first_entry_from_letter(&self, letter: usize) -> Option<usize> {
+        (letter..LETTER_RAIL.len()).find_map(|letter| {
+            self.entries
+                .iter()
+                .position(|entry| entry.name != PARENT_ENTRY && rail_letter(&entry.name) == letter)
+        })
+    }
+
     pub fn start_menu_cache_same(&self, recents: &[String]) -> bool {
         recents.len() == self.start_menu_cache.len()
             && recents
@@ -251,7 +424,7 @@ impl HomeState {
                     self.start_menu_section = StartMenuSection::Recents;
                     self.start_menu_index = recents.len().saturating_sub(1);
                 } else {
-                    self.start_menu_index = self.start_menu_index.saturating_sub(1);
+                    self.start_menu_index = self.action_step(false);
                 }
             }
             self.start_menu_nav_pending = true;
@@ -269,9 +442,7 @@ impl HomeState {
                     self.start_menu_index = 0;
                 }
             } else if self.start_menu_section == StartMenuSection::Actions {
-                if self.start_menu_index + 1 < 3 {
-                    self.start_menu_index += 1;
-                }
+                self.start_menu_index = self.action_step(true);
             }
             self.start_menu_nav_pending = true;
             return HomeAction::None;
@@ -281,7 +452,7 @@ impl HomeState {
             if self.start_menu_section == StartMenuSection::Actions {
                 self.start_menu_prev_section = self.start_menu_section;
                 self.start_menu_prev_index = self.start_menu_index;
-                self.start_menu_index = self.start_menu_index.saturating_sub(1);
+                self.start_menu_index = self.action_step(false);
                 self.start_menu_nav_pending = true;
             }
             return HomeAction::None;
@@ -291,7 +462,7 @@ impl HomeState {
             if self.start_menu_section == StartMenuSection::Actions {
                 self.start_menu_prev_section = self.start_menu_section;
                 self.start_menu_prev_index = self.start_menu_index;
-                self.start_menu_index = (self.start_menu_index + 1).min(2);
+                self.start_menu_index = self.action_step(true);
                 self.start_menu_nav_pending = true;
             }
             return HomeAction::None;
@@ -307,22 +478,75 @@ impl HomeState {
                 StartMenuSection::Actions => {
                     return match self.start_menu_index {
                         0 => HomeAction::OpenFileBrowser,
-                        1 => HomeAction::OpenSettings,
+                        1 if self.simple_root.is_none() => HomeAction::OpenSettings,
                         _ => HomeAction::None,
                     };
                 }
             }
         }
 
+        if buttons.is_pressed(Buttons::Back)
+            && self.profile_name.is_some()
+            && self.simple_root.is_none()
+        {
+            return HomeAction::OpenProfiles;
+        }
+
         HomeAction::None
     }
 
+    /// Next action tile left or right of the selected one; the Settings tile
+    /// is skipped in simple mode.
+    fn action_step(&self, forward: bool) -> usize {
+        let next = if forward {
+            (self.start_menu_index + 1).min(2)
+        } else {
+            self.start_menu_index.saturating_sub(1)
+        };
+        if next == 1 && self.simple_root.is_some() {
+            return if forward { 2 } else { 0 };
+        }
+        next
+    }
+
     pub fn handle_menu_input(
         &mut self,
         buttons: &crate::input::ButtonState,
     ) -> MenuAction {
         use crate::input::Buttons;
 
+        if let Some(letter) = self.letter_rail {
+            if buttons.is_pressed(Buttons::Up) {
+                self.letter_rail = Some(letter.checked_sub(1).unwrap_or(LETTER_RAIL.len() - 1));
+                return MenuAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Down) {
+                self.letter_rail = Some((letter + 1) % LETTER_RAIL.len());
+                return MenuAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Confirm) {
+                if let Some(index) = self.first_entry_from_letter(letter) {
+                    self.selected = index;
+                }
+                self.letter_rail = None;
+                return MenuAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Back) || buttons.is_pressed(Buttons::Left) {
+                self.letter_rail = None;
+                return MenuAction::Dirty;
+            }
+            return MenuAction::None;
+        }
+        if buttons.is_pressed(Buttons::Right) && !self.entries.is_empty() {
+            let current = self
+                .entries
+                .get(self.selected)
+                .map(|entry| rail_letter(&entry.name))
+                .unwrap_or(0);
+            self.letter_rail = Some(current);
+            return MenuAction::Dirty;
+        }
+
         if buttons.is_pressed(Buttons::Up) {
             if !self.entries.is_empty() {
                 self.selected = self.selected.saturating_sub(1);
@@ -354,34 +578,12 @@ impl HomeState {
         let size = ctx.display_buffers.size();
         let width = size.width as i32;
         let height = size.height as i32;
-        let mid_y = (height * 82) / 100;
+        let layout = StartMenuLayout::new(width, height, ctx.icons.icon_size);
 
         self.ensure_start_menu_cache(ctx, recents);
 
-        let list_top = HEADER_Y + 24;
-        let max_items = 6usize;
-        let list_width = width - (START_MENU_MARGIN * 2);
-        let item_height = 99;
-        let thumb_size = 74;
-        let action_top = mid_y + 17;
-        let action_width = (width - (START_MENU_MARGIN * 2) - (START_MENU_ACTION_GAP * 2)) / 3;
-        let action_height = 110;
-
         if self.start_menu_need_base_refresh {
-            let (gray2_used, draw_count) = self.render_start_menu_contents(
-                ctx,
-                true,
-                width,
-                mid_y,
-                list_top,
-                max_items,
-                list_width,
-                item_height,
-                thumb_size,
-                action_top,
-                action_width,
-                action_height,
-            );
+            let (gray2_used, draw_count) = self.render_start_menu_contents(ctx, true, &layout);
             log::info!(
                 "Start menu base render: recents={}, cache={}",
                 draw_count,
@@ -407,53 +609,8 @@ impl HomeState {
                 flush_queue(display, ctx.display_buffers, &mut rq, RefreshMode::Full);
             }
             self.start_menu_need_base_refresh = false;
-            self.render_start_menu_contents(
-                ctx,
-                false,
-                width,
-                mid_y,
-                list_top,
-                max_items,
-                list_width,
-                item_height,
-                thumb_size,
-                action_top,
-                action_width,
-                action_height,
-            );
-            let rect_for = |section: StartMenuSection, index: usize| -> Option<Rect> {
-                match section {
-                    StartMenuSection::Recents => {
-                        if index >= max_items {
-                            return None;
-                        }
-                        let y = list_top + (index as i32 * item_height);
-                        if y + item_height > mid_y {
-                            return None;
-                        }
-                        Some(Rect::new(
-                            START_MENU_MARGIN - 4,
-                            y - 4,
-                            list_width + 8,
-                            item_height - 4,
-                        ))
-                    }
-                    StartMenuSection::Actions => {
-                        if index >= 3 {
-                            return None;
-                        }
-                        let x = START_MENU_MARGIN
-                            + index as i32 * (action_width + START_MENU_ACTION_GAP);
-                        Some(Rect::new(
-                            x - 4,
-                            action_top - 4,
-                            action_width + 8,
-                            action_height + 8,
-                        ))
-                    }
-                }
-            };
-            if let Some(rect) = rect_for(self.start_menu_section, self.start_menu_index) {
+            self.render_start_menu_contents(ctx, false, &layout);
+            if let Some(rect) = layout.selection_rect(self.start_menu_section, self.start_menu_index) {
                 let mut rq = RenderQueue::default();
                 rq.push(rect, RefreshMode::Fast);
                 flush_queue(display, ctx.display_buffers, &mut rq, RefreshMode::Fast);
@@ -461,20 +618,7 @@ impl HomeState {
             return;
         }
 
-        let (gray2_used, draw_count) = self.render_start_menu_contents(
-            ctx,
-            false,
-            width,
-            mid_y,
-            list_top,
-            max_items,
-            list_width,
-            item_height,
-            thumb_size,
-            action_top,
-            action_width,
-            action_height,
-        );
+        let (gray2_used, draw_count) = self.render_start_menu_contents(ctx, false, &layout);
         log::info!(
             "Start menu render: recents={}, cache={}",
             draw_count,
@@ -486,47 +630,17 @@ impl HomeState {
                 let mut push_rect = |rect: Rect| {
                     rq.push(rect, RefreshMode::Fast);
                 };
-                let rect_for = |section: StartMenuSection, index: usize| -> Option<Rect> {
-                    match section {
-                        StartMenuSection::Recents => {
-                            if index >= max_items {
-                                return None;
-                            }
-                            let y = list_top + (index as i32 * item_height);
-                            if y + item_height > mid_y {
-                                return None;
-                            }
-                            Some(Rect::new(
-                                START_MENU_MARGIN - 4,
-                                y - 4,
-                                list_width + 8,
-                                item_height - 4,
-                            ))
-                        }
-                        StartMenuSection::Actions => {
-                            if index >= 3 {
-                                return None;
-                            }
-                            let x = START_MENU_MARGIN
-                                + index as i32 * (action_width + START_MENU_ACTION_GAP);
-                            Some(Rect::new(
-                                x - 4,
-                                action_top - 4,
-                                action_width + 8,
-                                action_height + 8,
-                            ))
-                        }
-                    }
-                };
                 if let Some(rect) =
-                    rect_for(self.start_menu_prev_section, self.start_menu_prev_index)
+                    layout.selection_rect(self.start_menu_prev_section, self.start_menu_prev_index)
                 {
                     push_rect(rect);
                 }
                 if (self.start_menu_prev_section != self.start_menu_section)
                     || (self.start_menu_prev_index != self.start_menu_index)
                 {
-                    if let Some(rect) = rect_for(self.start_menu_section, self.start_menu_index) {
+                    if let Some(rect) =
+                        layout.selection_rect(self.start_menu_section, self.start_menu_index)
+                    {
                         push_rect(rect);
                     }
                 }
@@ -558,7 +672,7 @@ impl HomeState {
     ) {
         let mut labels: Vec<String> = Vec::with_capacity(self.entries.len());
         for entry in &self.entries {
-            if entry.kind == crate::image_viewer::EntryKind::Dir {
+            if entry.kind == crate::image_viewer::EntryKind::Dir && entry.name != PARENT_ENTRY {
                 let mut label = entry.name.clone();
                 label.push('/');
                 labels.push(label);
@@ -574,21 +688,55 @@ impl HomeState {
         let title = self.menu_title();
         let mut list = ListView::new(&items);
         list.title = Some(title.as_str());
-        list.footer = Some("Up/Down: select  Confirm: open  Back: up");
+        list.footer = Some(if self.letter_rail.is_some() {
+            "Up/Down: letter  Confirm: jump  Back: close"
+        } else {
+            "Confirm: open  Back: up  Right: A-Z"
+        });
         list.empty_label = Some("No files found.");
         list.selected = self.selected;
-        list.margin_x = LIST_MARGIN_X;
-        list.header_y = HEADER_Y;
-        list.list_top = LIST_TOP;
-        list.line_height = LINE_HEIGHT;
 
         let size = ctx.display_buffers.size();
         let rect = Rect::new(0, 0, size.width as i32, size.height as i32);
+        let metrics = LIST_METRICS.scaled(rect);
+        list.set_metrics(metrics);
+        let visible = list.visible_range(rect);
+        let mut icons = Vec::new();
+        for index in visible {
+            let entry = &self.entries[index];
+            if entry.kind != crate::image_viewer::EntryKind::Dir || entry.name == PARENT_ENTRY {
+                continue;
+            }
+            let mut dir = self.path.clone();
+            dir.push(entry.name.clone());
+            if let Some(icon) = self.folder_cover(ctx.source, &dir, FOLDER_ICON as u32) {
+                icons.push((index, icon));
+            }
+        }
+        if !icons.is_empty() {
+            list.label_indent = FOLDER_ICON + 8;
+        }
         let mut rq = RenderQueue::default();
         let mut ui = UiContext {
             buffers: ctx.display_buffers,
         };
         list.render(&mut ui, rect, &mut rq);
+        for (index, icon) in icons {
+            let y = list.row_y(rect, index) - 18 + (metrics.line_height - FOLDER_ICON) / 2;
+            let mut gray2_ctx = None;
+            (ctx.draw_trbk_image)(
+                ctx.display_buffers,
+                &icon,
+                &mut gray2_ctx,
+                metrics.margin_x,
+                y,
+                FOLDER_ICON,
+                FOLDER_ICON,
+            );
+        }
+        if let Some(letter) = self.letter_rail {
+            draw_letter_rail(ctx.display_buffers, rect, metrics.list_top, letter);
+        }
 
         let fallback = if ctx.full_refresh {
             RefreshMode::Full
@@ -602,16 +750,7 @@ impl HomeState {
         &mut self,
         ctx: &mut HomeRenderContext<'_, S>,
         suppress_selection: bool,
-        width: i32,
-        mid_y: i32,
-        list_top: i32,
-        max_items: usize,
-        list_width: i32,
-        item_height: i32,
-        thumb_size: i32,
-        action_top: i32,
-        action_width: i32,
-        action_height: i32,
+        layout: &StartMenuLayout,
     ) -> (bool, usize) {
         let header_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
         ctx.display_buffers.clear(BinaryColor::On).ok();
@@ -619,23 +758,29 @@ impl HomeState {
         ctx.gray2_msb.fill(0);
         let mut gray2_used = false;
 
-        Text::new("Recents", Point::new(START_MENU_MARGIN, HEADER_Y), header_style)
+        Text::new("Recents", Point::new(START_MENU_MARGIN, layout.header_y), header_style)
             .draw(ctx.display_buffers)
             .ok();
+        if let Some(name) = &self.profile_name {
+            let name = ellipsize(name, 20);
+            let name_x = START_MENU_MARGIN + layout.list_width - name.chars().count() as i32 * 10;
+            Text::new(&name, Point::new(name_x, layout.header_y), header_style)
+                .draw(ctx.display_buffers)
+                .ok();
+        }
 
         let mut draw_count = 0usize;
-        for (idx, preview) in self.start_menu_cache.iter().take(max_items).enumerate() {
-            let y = list_top + (idx as i32 * item_height);
-            if y + item_height > mid_y {
-                break;
-            }
+        let item_height = layout.item_height;
+        let thumb_size = START_MENU_RECENT_THUMB;
+        for (idx, preview) in self.start_menu_cache.iter().take(layout.max_items).enumerate() {
+            let y = layout.list_top + (idx as i32 * item_height);
             let is_selected = !suppress_selection
                 && self.start_menu_section == StartMenuSection::Recents
                 && self.start_menu_index == idx;
             if is_selected {
                 Rectangle::new(
                     Point::new(START_MENU_MARGIN - 4, y - 4),
-                    Size::new((list_width + 8) as u32, (item_height - 4) as u32),
+                    Size::new((layout.list_width + 8) as u32, (item_height - 4) as u32),
                 )
                 .into_styled(embedded_graphics::primitives::PrimitiveStyle::with_fill(
                     BinaryColor::Off,
@@ -692,19 +837,23 @@ impl HomeState {
                 BinaryColor::Off
             };
             let label_style = MonoTextStyle::new(&FONT_10X20, text_color);
-            Text::new(
-                &preview.title,
-                Point::new(thumb_x + thumb_size + 12, y + 26),
-                label_style,
-            )
-            .draw(ctx.display_buffers)
-            .ok();
+            let label_x = thumb_x + thumb_size + 12;
+            let max_chars = ((START_MENU_MARGIN + layout.list_width - label_x) / 10).max(1) as usize;
+            let (first, second) = wrap_two_lines(&preview.title, max_chars);
+            Text::new(first, Point::new(label_x, y + 26), label_style)
+                .draw(ctx.display_buffers)
+                .ok();
+            if let Some(second) = second {
+                Text::new(&second, Point::new(label_x, y + 50), label_style)
+                    .draw(ctx.display_buffers)
+                    .ok();
+            }
             draw_count += 1;
         }
         if draw_count == 0 {
             Text::new(
                 "No recent items.",
-                Point::new(START_MENU_MARGIN, list_top + 24),
+                Point::new(START_MENU_MARGIN, layout.list_top + 24),
                 header_style,
             )
             .draw(ctx.display_buffers)
@@ -712,8 +861,8 @@ impl HomeState {
         }
 
         Rectangle::new(
-            Point::new(START_MENU_MARGIN, mid_y),
-            Size::new((width - (START_MENU_MARGIN * 2)) as u32, 1),
+            Point::new(layout.divider.x, layout.divider.y),
+            Size::new(layout.divider.w as u32, layout.divider.h as u32),
         )
         .into_styled(embedded_graphics::primitives::PrimitiveStyle::with_fill(
             BinaryColor::Off,
@@ -726,9 +875,13 @@ impl HomeState {
             (StartMenuAction::Settings, "Settings"),
             (StartMenuAction::Battery, ""),
         ];
-        for (idx, (_, label)) in actions.iter().enumerate() {
-            let x = START_MENU_MARGIN + idx as i32 * (action_width + START_MENU_ACTION_GAP);
-            let y = action_top;
+        for (idx, (action, label)) in actions.iter().enumerate() {
+            if matches!(action, StartMenuAction::Settings) && self.simple_root.is_some() {
+                continue;
+            }
+            let Point { x, y } = layout.action_origin(idx);
+            let action_width = layout.action_width;
+            let action_height = layout.action_height;
             let is_selected = !suppress_selection
                 && self.start_menu_section == StartMenuSection::Actions
                 && self.start_menu_index == idx;
@@ -839,7 +992,7 @@ impl HomeState {
         }
         self.start_menu_cache.clear();
         for path in recents {
-            let (title, image) = self.load_recent_preview(ctx, path);
+            let (title, image) = self.load_recent_preview(ctx.source, path);
             self.start_menu_cache.push(RecentPreview {
                 path: path.clone(),
                 title,
@@ -851,19 +1004,21 @@ impl HomeState {
 
     fn load_recent_preview<S: AppSource>(
         &mut self,
-        ctx: &mut HomeRenderContext<'_, S>,
+        source: &mut S,
         path: &str,
     ) -> (String, Option<ImageData>) {
         let label_fallback = basename_from_path(path);
-        if let Some(image) = ctx.source.load_thumbnail(path) {
-            let title = ctx
-                .source
+        if self.skip_thumbnails {
+            return (label_fallback, None);
+        }
+        if let Some(image) = source.load_thumbnail(path) {
+            let title = source
                 .load_thumbnail_title(path)
                 .filter(|value| !value.is_empty())
                 .unwrap_or(label_fallback);
             if let Some(mono) = thumbnail_to_mono(&image) {
                 if !matches!(image, ImageData::Mono1 { .. }) {
-                    ctx.source.save_thumbnail(path, &mono);
+                    source.save_thumbnail(path, &mono);
                 }
                 return (title, Some(mono));
             }
@@ -878,50 +1033,13 @@ impl HomeState {
             };
             if needs_resize {
                 if let Some(thumb) = thumbnail_from_image(&image, START_MENU_RECENT_THUMB as u32) {
-                    ctx.source.save_thumbnail(path, &thumb);
+                    source.save_thumbnail(path, &thumb);
                     return (title, Some(thumb));
                 }
             }
             return (title, Some(image));
         }
         let lower = path.to_ascii_lowercase();
-        if lower.ends_with(".tri") || lower.ends_with(".trimg") {
-            let mut parts: Vec<String> = path
-                .split('/')
-                .filter(|part| !part.is_empty())
-                .map(|part| part.to_string())
-                .collect();
-            if parts.is_empty() {
-                return (label_fallback, None);
-            }
-            let file = parts.pop().unwrap_or_default();
-            let entry = ImageEntry {
-                name: file,
-                kind: crate::image_viewer::EntryKind::File,
-            };
-            if let Ok(image) = ctx.source.load(&parts, &entry) {
-                if let ImageData::Gray2Stream { width, height, key } = &image {
-                    if let Some(thumb) = ctx.source.load_gray2_stream_thumbnail(
-                        key,
-                        *width,
-                        *height,
-                        74,
-                        74,
-                    ) {
-                        ctx.source.save_thumbnail(path, &thumb);
-                        return (label_fallback, Some(thumb));
-                    }
-                }
-                if let Some(thumb) = thumbnail_from_image(&image, 74) {
-                    ctx.source.save_thumbnail(path, &thumb);
-                    return (label_fallback, Some(thumb));
-                }
-            }
-            return (label_fallback, None);
-        }
-        if !lower.ends_with(".trbk") && !lower.ends_with(".tbk") {
-            return (label_fallback, None);
-        }
         let mut parts: Vec<String> = path
             .split('/')
             .filter(|part| !part.is_empty())
@@ -931,14 +1049,24 @@ impl HomeState {
             return (label_fallback, None);
         }
         let file = parts.pop().unwrap_or_default();
+        if lower.ends_with(".tri") || lower.ends_with(".trimg") {
+            let thumb = image_thumbnail(source, &parts, file, 74);
+            if let Some(thumb) = thumb.as_ref() {
+                source.save_thumbnail(path, thumb);
+            }
+            return (label_fallback, thumb);
+        }
+        if !lower.ends_with(".trbk") && !lower.ends_with(".tbk") {
+            return (label_fallback, None);
+        }
         let entry = ImageEntry {
             name: file,
             kind: crate::image_viewer::EntryKind::File,
         };
-        let info = match ctx.source.open_trbk(&parts, &entry) {
+        let info = match source.open_trbk(&parts, &entry) {
             Ok(info) => info,
             Err(_) => {
-                ctx.source.close_trbk();
+                source.close_trbk();
                 return (label_fallback, None);
             }
         };
@@ -948,9 +1076,9 @@ impl HomeState {
             info.metadata.title.clone()
         };
         let preview = if !info.images.is_empty() {
-            ctx.source.trbk_image(0).ok().and_then(|image| {
+            source.trbk_image(0).ok().and_then(|image| {
                 if let ImageData::Gray2Stream { width, height, key } = &image {
-                    if let Some(thumb) = ctx.source.load_gray2_stream_thumbnail(
+                    if let Some(thumb) = source.load_gray2_stream_thumbnail(
                         key,
                         *width,
                         *height,
@@ -965,13 +1093,68 @@ impl HomeState {
         } else {
             None
         };
-        ctx.source.close_trbk();
+        source.close_trbk();
         if let Some(image) = preview.as_ref() {
-            ctx.source.save_thumbnail(path, image);
-            ctx.source.save_thumbnail_title(path, &title);
+            source.save_thumbnail(path, image);
+            source.save_thumbnail_title(path, &title);
+            return (title, preview);
         }
+        // Books without a cover of their own show their folder's.
+        let preview = self.folder_cover(source, &parts, START_MENU_RECENT_THUMB as u32);
         (title, preview)
     }
+
+    /// Thumbnail of the folder's [`FOLDER_COVER`] at `size`, from the
+    /// thumbnail cache when possible. Misses are remembered until
+    /// [`Self::clear_folder_covers`].
+    fn folder_cover<S: AppSource>(
+        &mut self,
+        source: &mut S,
+        dir: &[String],
+        size: u32,
+    ) -> Option<ImageData> {
+        if self.skip_thumbnails || dir.is_empty() {
+            return None;
+        }
+        let dir_path = dir.join("/");
+        if let Some(cover) = self.folder_covers.get(&(dir_path.clone(), size)) {
+            return cover.clone();
+        }
+        let key = format!("{}/{}@{}", dir_path, FOLDER_COVER, size);
+        let cover = source.load_thumbnail(&key).or_else(|| {
+            let thumb = image_thumbnail(source, dir, FOLDER_COVER.to_string(), size)?;
+            let thumb = thumbnail_to_mono(&thumb).unwrap_or(thumb);
+            source.save_thumbnail(&key, &thumb);
+            Some(thumb)
+        });
+        self.folder_covers.insert((dir_path, size), cover.clone());
+        cover
+    }
+
+    /// Forgets folder covers, e.g. after the card was changed over USB.
+    pub fn clear_folder_covers(&mut self) {
+        self.folder_covers.clear();
+    }
+}
+
+/// Square thumbnail of the image `name` in `dir`.
+fn image_thumbnail<S: AppSource>(
+    source: &mut S,
+    dir: &[String],
+    name: String,
+    size: u32,
+) -> Option<ImageData> {
+    let entry = ImageEntry {
+        name,
+        kind: crate::image_viewer::EntryKind::File,
+    };
+    let image = source.load(dir, &entry).ok()?;
+    if let ImageData::Gray2Stream { width, height, key } = &image {
+        if let Some(thumb) = source.load_gray2_stream_thumbnail(key, *width, *height, size, size) {
+            return Some(thumb);
+        }
+    }
+    thumbnail_from_image(&image, size)
 }
 
 pub fn draw_icon_gray2(
@@ -1046,6 +1229,186 @@ fn map_display_point(rotation: Rotation, x: i32, y: i32) -> Option<(usize, usize
     }
 }
 
+/// Start menu geometry for one screen size. Portrait screens put the actions
+/// in a row below the recents, landscape ones in a column beside them.
+struct StartMenuLayout {
+    header_y: i32,
+    list_top: i32,
+    list_width: i32,
+    item_height: i32,
+    max_items: usize,
+    /// Line between the recents and the actions.
+    divider: Rect,
+    landscape: bool,
+    actions_x: i32,
+    actions_y: i32,
+    action_width: i32,
+    action_height: i32,
+}
+
+impl StartMenuLayout {
+    fn new(width: i32, height: i32, icon_size: i32) -> Self {
+        let header_y = LIST_METRICS.scaled(Rect::new(0, 0, width, height)).header_y;
+        let list_top = header_y + 24;
+        let item_height = relative(height, 124, START_MENU_RECENT_THUMB + 12, 99);
+        let min_action_height = icon_size + 30;
+        let landscape = width > height;
+        let (list_width, list_bottom, divider, actions_x, actions_y, action_width, action_height) =
+            if landscape {
+                let action_width = relative(width, 250, icon_size + 40, 240);
+                let actions_x = width - START_MENU_MARGIN - action_width;
+                let divider_x = actions_x - START_MENU_SECTION_GAP;
+                let action_height = ((height - list_top - START_MENU_SECTION_GAP
+                    - START_MENU_ACTION_GAP * 2)
+                    / 3)
+                    .max(min_action_height);
+                (
+                    divider_x - START_MENU_MARGIN - START_MENU_SECTION_GAP,
+                    height - START_MENU_SECTION_GAP,
+                    Rect::new(divider_x, list_top - 4, 1, height - list_top),
+                    actions_x,
+                    list_top,
+                    action_width,
+                    action_height,
+                )
+            } else {
+                let action_height = relative(height, 138, min_action_height, 140);
+                let actions_y = height - START_MENU_SECTION_GAP - action_height;
+                let divider_y = actions_y - START_MENU_SECTION_GAP;
+                (
+                    width - START_MENU_MARGIN * 2,
+                    divider_y,
+                    Rect::new(START_MENU_MARGIN, divider_y, width - START_MENU_MARGIN * 2, 1),
+                    START_MENU_MARGIN,
+                    actions_y,
+                    (width - START_MENU_MARGIN * 2 - START_MENU_ACTION_GAP * 2) / 3,
+                    action_height,
+                )
+            };
+        let max_items = ((list_bottom - list_top) / item_height).clamp(1, START_MENU_MAX_RECENTS);
+        Self {
+            header_y,
+            list_top,
+            list_width,
+            item_height,
+            max_items: max_items as usize,
+            divider,
+            landscape,
+            actions_x,
+            actions_y,
+            action_width,
+            action_height,
+        }
+    }
+
+    fn action_origin(&self, index: usize) -> Point {
+        let index = index as i32;
+        if self.landscape {
+            Point::new(
+                self.actions_x,
+                self.actions_y + index * (self.action_height + START_MENU_ACTION_GAP),
+            )
+        } else {
+            Point::new(
+                self.actions_x + index * (self.action_width + START_MENU_ACTION_GAP),
+                self.actions_y,
+            )
+        }
+    }
+
+    /// Area the selection highlight of an item covers.
+    fn selection_rect(&self, section: StartMenuSection, index: usize) -> Option<Rect> {
+        match section {
+            StartMenuSection::Recents => {
+                if index >= self.max_items {
+                    return None;
+                }
+                let y = self.list_top + index as i32 * self.item_height;
+                Some(Rect::new(
+                    START_MENU_MARGIN - 4,
+                    y - 4,
+                    self.list_width + 8,
+                    self.item_height - 4,
+                ))
+            }
+            StartMenuSection::Actions => {
+                if index >= 3 {
+                    return None;
+                }
+                let origin = self.action_origin(index);
+                Some(Rect::new(
+                    origin.x - 4,
+                    origin.y - 4,
+                    self.action_width + 8,
+                    self.action_height + 8,
+                ))
+            }
+        }
+    }
+}
+
+/// Rail position an entry is filed under, by its first character.
+fn rail_letter(name: &str) -> usize {
+    match name.chars().next().map(|ch| ch.to_ascii_uppercase()) {
+        Some(ch @ 'A'..='Z') => (ch as u8 - b'A') as usize + 1,
+        _ => 0,
+    }
+}
+
+/// Column of letters along the right edge, level with the first row at
+/// `list_top`, with `selected` inverted. Screens too short for all of them
+/// get more columns, filled top to bottom.
+fn draw_letter_rail(buffers: &mut DisplayBuffers, rect: Rect, list_top: i32, selected: usize) {
+    let top = list_top - 18;
+    let rows = ((rect.y + rect.h - top - 40) / RAIL_LINE_HEIGHT).clamp(1, LETTER_RAIL.len() as i32);
+    let columns = (LETTER_RAIL.len() as i32 + rows - 1) / rows;
+    let rows = (LETTER_RAIL.len() as i32 + columns - 1) / columns;
+    let left = rect.x + rect.w - RAIL_WIDTH * columns - 4;
+    Rectangle::new(
+        Point::new(left, top - 4),
+        Size::new(
+            (RAIL_WIDTH * columns) as u32,
+            (rows * RAIL_LINE_HEIGHT + 8) as u32,
+        ),
+    )
+    .into_styled(
+        embedded_graphics::primitives::PrimitiveStyleBuilder::new()
+            .fill_color(BinaryColor::On)
+            .stroke_color(BinaryColor::Off)
+            .stroke_width(1)
+            .build(),
+    )
+    .draw(buffers)
+    .ok();
+    let mut label = [0u8; 4];
+    for (index, letter) in LETTER_RAIL.iter().enumerate() {
+        let x = left + (index as i32 / rows) * RAIL_WIDTH;
+        let y = top + (index as i32 % rows) * RAIL_LINE_HEIGHT;
+        let color = if index == selected {
+            Rectangle::new(
+                Point::new(x + 2, y),
+                Size::new((RAIL_WIDTH - 4) as u32, RAIL_LINE_HEIGHT as u32),
+            )
+            .into_styled(embedded_graphics::primitives::PrimitiveStyle::with_fill(
+                BinaryColor::Off,
+            ))
+            .draw(buffers)
+            .ok();
+            BinaryColor::On
+        } else {
+            BinaryColor::Off
+        };
+        let text = (*letter as char).encode_utf8(&mut label);
+        Text::new(
+            text,
+            Point::new(x + (RAIL_WIDTH - 10) / 2, y + 18),
+            MonoTextStyle::new(&FONT_10X20, color),
+        )
+        .draw(buffers)
+        .ok();
+    }
+}
+
 fn basename_from_path(path: &str) -> String {
     path.rsplit('/').next().unwrap_or(path).to_string()
 }
@@ -1119,7 +1482,7 @@ fn thumbnail_from_image(image: &ImageData, size: u32) -> Option<ImageData> {
     })
 }
 
-fn thumbnail_to_mono(image: &ImageData) -> Option<ImageData> {
+pub(crate) fn thumbnail_to_mono(image: &ImageData) -> Option<ImageData> {
     match image {
         ImageData::Mono1 { .. } => Some(image.clone()),
         ImageData::Gray8 { width, height, pixels } => {
@@ -1,20 +1,56 @@
 extern crate alloc;
 
 use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::geometry::{OriginDimensions, Point};
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
 use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::text::Text;
+use embedded_graphics::Drawable;
 
-use alloc::string::String;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 use crate::display::{Display, GrayscaleMode, RefreshMode};
 use crate::framebuffer::{DisplayBuffers, Rotation, BUFFER_SIZE, HEIGHT as FB_HEIGHT, WIDTH as FB_WIDTH};
-use crate::image_viewer::{AppSource, ImageData, ImageEntry, ImageError};
-use crate::ui::{flush_queue, Rect, RenderQueue, UiContext, ReaderView, View};
+use crate::image_viewer::{AppSource, ImageData, ImageEntry, ImageError, ImageInfo};
+use crate::ui::{ellipsize, flush_queue, wrap_two_lines, Rect, RenderQueue, UiContext, ReaderView, View};
 
 const DEBUG_GRAY2_MODE: u8 = 0; // 0=normal, 1=base, 2=lsb, 3=msb
+const INFO_PADDING: i32 = 12;
+const INFO_LINE_HEIGHT: i32 = 24;
+
+/// Order Left/Right steps through the images of a folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageOrder {
+    #[default]
+    Name,
+    /// Oldest capture date first; images without one follow by name.
+    Date,
+    Shuffle,
+}
+
+impl ImageOrder {
+    pub const ALL: [ImageOrder; 3] = [ImageOrder::Name, ImageOrder::Date, ImageOrder::Shuffle];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ImageOrder::Name => "name",
+            ImageOrder::Date => "date taken",
+            ImageOrder::Shuffle => "shuffle",
+        }
+    }
+}
 
 pub struct ImageViewerState {
     current_image: Option<ImageData>,
+    /// Entry indices of the folder's images in the order Left/Right steps
+    /// through them.
+    order: Vec<usize>,
+    /// File name and stored details of the open image.
+    info: (String, Option<ImageInfo>),
+    /// Draw the details over the bottom of the image.
+    pub show_info: bool,
 }
 
 pub struct ImageViewerContext<'a, S: AppSource> {
@@ -27,7 +63,58 @@ pub struct ImageViewerContext<'a, S: AppSource> {
 
 impl ImageViewerState {
     pub fn new() -> Self {
-        Self { current_image: None }
+        Self {
+            current_image: None,
+            order: Vec::new(),
+            info: (String::new(), None),
+            show_info: false,
+        }
+    }
+
+    /// Orders `images`, indices into `entries`, for stepping through the
+    /// folder. Entries come sorted by name; `seed` varies the shuffle.
+    pub fn set_order<S: AppSource>(
+        &mut self,
+        source: &mut S,
+        path: &[String],
+        entries: &[ImageEntry],
+        mut images: Vec<usize>,
+        order: ImageOrder,
+        seed: u32,
+    ) {
+        match order {
+            ImageOrder::Name => {}
+            ImageOrder::Date => {
+                let mut dated: Vec<(Option<u32>, usize)> = images
+                    .iter()
+                    .map(|&index| (source.capture_date(path, &entries[index]), index))
+                    .collect();
+                dated.sort_by_key(|&(date, index)| (date.is_none(), date, index));
+                images = dated.into_iter().map(|(_, index)| index).collect();
+            }
+            ImageOrder::Shuffle => {
+                // xorshift32, which must not start at zero.
+                let mut state = seed | 1;
+                for last in (1..images.len()).rev() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    images.swap(last, state as usize % (last + 1));
+                }
+            }
+        }
+        self.order = images;
+    }
+
+    /// Entry index of the image before or after `current`, if any.
+    pub fn step(&self, current: usize, forward: bool) -> Option<usize> {
+        let position = self.order.iter().position(|&index| index == current)?;
+        let next = if forward {
+            position + 1
+        } else {
+            position.checked_sub(1)?
+        };
+        self.order.get(next).copied()
     }
 
     pub fn set_image(&mut self, image: ImageData) {
@@ -42,11 +129,71 @@ impl ImageViewerState {
     ) -> Result<(), ImageError> {
         let image = source.load(path, entry)?;
         self.current_image = Some(image);
+        self.info = (entry.name.clone(), source.image_info(path, entry));
         Ok(())
     }
 
+    fn info_lines(&self, max_chars: usize) -> Vec<String> {
+        let (name, info) = &self.info;
+        let Some(info) = info else {
+            return alloc::vec![
+                ellipsize(name, max_chars).into_owned(),
+                "No details stored in this image".to_string(),
+            ];
+        };
+        let mut lines = alloc::vec![ellipsize(info.source_name.as_deref().unwrap_or(name), max_chars).into_owned()];
+        if let Some(date) = info.captured {
+            lines.push(format!("Taken: {}", format_packed_date(date)));
+        }
+        if let Some(date) = info.converted {
+            lines.push(format!("Converted: {}", format_packed_date(date)));
+        }
+        if let Some(description) = info.description.as_deref().filter(|text| !text.is_empty()) {
+            let (first, rest) = wrap_two_lines(description, max_chars);
+            lines.push(first.to_string());
+            if let Some(rest) = rest {
+                lines.push(rest.into_owned());
+            }
+        }
+        lines
+    }
+
+    /// Draws the details panel over the bottom of the image, in black and
+    /// white, when it is shown.
+    fn draw_info_panel(&self, buffers: &mut DisplayBuffers, gray2_lsb: &mut [u8], gray2_msb: &mut [u8]) {
+        if !self.show_info {
+            return;
+        }
+        let size = buffers.size();
+        let width = size.width as i32;
+        let lines = self.info_lines(((width - INFO_PADDING * 2) / 10).max(1) as usize);
+        let height = INFO_PADDING * 2 + lines.len() as i32 * INFO_LINE_HEIGHT;
+        let top = size.height as i32 - height;
+        let rotation = buffers.rotation();
+        for y in top..size.height as i32 {
+            for x in 0..width {
+                let color = if y == top { BinaryColor::Off } else { BinaryColor::On };
+                buffers.set_pixel(x, y, color);
+                if let Some((fx, fy)) = map_display_point(rotation, x, y) {
+                    let idx = fy * FB_WIDTH + fx;
+                    let mask = !(1u8 << (7 - idx % 8));
+                    gray2_lsb[idx / 8] &= mask;
+                    gray2_msb[idx / 8] &= mask;
+                }
+            }
+        }
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        for (index, line) in lines.iter().enumerate() {
+            let y = top + INFO_PADDING + 16 + index as i32 * INFO_LINE_HEIGHT;
+            Text::new(line, Point::new(INFO_PADDING, y), style)
+                .draw(buffers)
+                .ok();
+        }
+    }
+
     pub fn clear(&mut self) {
         self.current_image = None;
+        self.order.clear();
     }
 
     pub fn has_image(&self) -> bool {
@@ -109,6 +256,7 @@ impl ImageViewerState {
                     lsb,
                     msb,
                 );
+                self.draw_info_panel(ctx.display_buffers, ctx.gray2_lsb, ctx.gray2_msb);
                 ctx.display_buffers.copy_active_to_inactive();
                 if DEBUG_GRAY2_MODE != 0 {
                     apply_gray2_debug_overlay(
@@ -158,6 +306,7 @@ impl ImageViewerState {
                 {
                     return Err(ImageError::Decode);
                 }
+                self.draw_info_panel(ctx.display_buffers, ctx.gray2_lsb, ctx.gray2_msb);
                 ctx.display_buffers.copy_active_to_inactive();
                 if DEBUG_GRAY2_MODE != 0 {
                     apply_gray2_debug_overlay(
@@ -184,6 +333,7 @@ impl ImageViewerState {
                 let mut reader = ReaderView::new(&image);
                 reader.refresh = RefreshMode::Full;
                 reader.render(&mut ctx_ui, rect, &mut rq);
+                self.draw_info_panel(ctx.display_buffers, ctx.gray2_lsb, ctx.gray2_msb);
                 flush_queue(display, ctx.display_buffers, &mut rq, RefreshMode::Full);
             }
         }
@@ -302,3 +452,16 @@ fn map_display_point(rotation: Rotation, x: i32, y: i32) -> Option<(usize, usize
         Some((x, y))
     }
 }
+
+/// `YYYY-MM-DD HH:MM` for a date packed as by
+/// [`crate::trbk::trimg_capture_date`].
+fn format_packed_date(date: u32) -> String {
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        1980 + (date >> 25),
+        (date >> 21) & 0x0F,
+        (date >> 16) & 0x1F,
+        (date >> 11) & 0x1F,
+        (date >> 5) & 0x3F
+    )
+}
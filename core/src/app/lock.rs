@@ -0,0 +1,128 @@
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, OriginDimensions, Point},
+    text::Text,
+    Drawable,
+};
+
+use crate::{
+    display::{Display, RefreshMode},
+    framebuffer::DisplayBuffers,
+    input::{ButtonState, Buttons},
+    ui::{flush_queue, Rect, RenderQueue},
+};
+
+/// Presses in an unlock code.
+pub const LOCK_CODE_LEN: usize = 4;
+/// Buttons a code is made of. Back, Confirm and Power keep their usual
+/// meaning while a code is set up.
+const CODE_BUTTONS: [Buttons; 4] = [Buttons::Up, Buttons::Down, Buttons::Left, Buttons::Right];
+
+/// Index into the code buttons of the one pressed this frame, if any.
+pub fn code_press(buttons: &ButtonState) -> Option<u8> {
+    CODE_BUTTONS
+        .iter()
+        .position(|button| buttons.is_pressed(*button))
+        .map(|index| index as u8)
+}
+
+/// Progress through a code as `* * _ _`.
+pub fn code_progress(entered: usize) -> String {
+    (0..LOCK_CODE_LEN)
+        .map(|index| if index < entered { "*" } else { "_" })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub enum LockAction {
+    None,
+    Dirty,
+    Unlocked,
+}
+
+/// Shown on wake while a lock code is set, until the code is entered.
+#[derive(Default)]
+pub struct LockScreen {
+    entered: Vec<u8>,
+    wrong: bool,
+}
+
+impl LockScreen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.entered.clear();
+        self.wrong = false;
+    }
+
+    /// A wrong code is only reported once all of it has been entered.
+    pub fn handle_input(&mut self, code: &[u8], buttons: &ButtonState) -> LockAction {
+        let Some(press) = code_press(buttons) else {
+            return LockAction::None;
+        };
+        self.entered.push(press);
+        self.wrong = false;
+        if self.entered.len() < code.len() {
+            return LockAction::Dirty;
+        }
+        if self.entered == code {
+            self.reset();
+            return LockAction::Unlocked;
+        }
+        log::warn!("Wrong unlock code");
+        self.entered.clear();
+        self.wrong = true;
+        LockAction::Dirty
+    }
+
+    pub fn draw(
+        &self,
+        display_buffers: &mut DisplayBuffers,
+        display: &mut impl Display,
+        full_refresh: bool,
+    ) {
+        display_buffers.clear(BinaryColor::On).ok();
+        let size = display_buffers.size();
+        let width = size.width as i32;
+        let height = size.height as i32;
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        let centered = |text: &str| (width - text.len() as i32 * 10) / 2;
+        let top = height / 2 - 60;
+
+        for offset in 0..2 {
+            Text::new("Locked", Point::new(centered("Locked") + offset, top), style)
+                .draw(display_buffers)
+                .ok();
+        }
+        let prompt = if self.wrong {
+            "Wrong code, try again"
+        } else {
+            "Enter the unlock code"
+        };
+        Text::new(prompt, Point::new(centered(prompt), top + 40), style)
+            .draw(display_buffers)
+            .ok();
+        let progress = code_progress(self.entered.len());
+        Text::new(&progress, Point::new(centered(&progress), top + 80), style)
+            .draw(display_buffers)
+            .ok();
+
+        let mut rq = RenderQueue::default();
+        rq.push(
+            Rect::new(0, 0, width, height),
+            if full_refresh {
+                RefreshMode::Full
+            } else {
+                RefreshMode::Fast
+            },
+        );
+        flush_queue(display, display_buffers, &mut rq, RefreshMode::Fast);
+    }
+}
@@ -1,5 +1,12 @@
 pub mod image_viewer;
+pub mod lock;
 pub mod book_reader;
+pub mod chapters;
 pub mod home;
 pub mod system;
 pub mod settings;
+pub mod diagnostics;
+pub mod power_menu;
+pub mod profiles;
+pub mod reading_pace;
+pub mod switcher;
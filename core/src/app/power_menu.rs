@@ -0,0 +1,187 @@
+use embedded_graphics::{
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{OriginDimensions, Point, Primitive},
+    geometry::Size,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+
+use crate::{
+    display::{Display, RefreshMode},
+    framebuffer::DisplayBuffers,
+    input::{ButtonState, Buttons},
+    ui::{flush_queue, Rect, RenderQueue},
+};
+
+/// How long Power must be held to open the power menu.
+pub const POWER_LONG_PRESS_MS: u32 = 3000;
+
+const MENU_WIDTH: i32 = 260;
+const ITEM_HEIGHT: i32 = 32;
+const TITLE_HEIGHT: i32 = 40;
+const PADDING: i32 = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PowerButtonMode {
+    /// Any Power press sleeps straight away (the original behaviour).
+    SleepOnPress,
+    /// Short press sleeps, holding for `POWER_LONG_PRESS_MS` opens the power menu.
+    #[default]
+    ShortSleepLongMenu,
+}
+
+impl PowerButtonMode {
+    /// In the order Settings cycles through them; the index is what is saved.
+    pub const ALL: [PowerButtonMode; 2] =
+        [PowerButtonMode::ShortSleepLongMenu, PowerButtonMode::SleepOnPress];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PowerButtonMode::SleepOnPress => "sleep",
+            PowerButtonMode::ShortSleepLongMenu => "sleep, hold for menu",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerMenuItem {
+    Sleep,
+    Reboot,
+    UsbMode,
+    Cancel,
+}
+
+impl PowerMenuItem {
+    fn label(self) -> &'static str {
+        match self {
+            PowerMenuItem::Sleep => "Sleep",
+            PowerMenuItem::Reboot => "Reboot",
+            PowerMenuItem::UsbMode => "USB mode",
+            PowerMenuItem::Cancel => "Cancel",
+        }
+    }
+}
+
+const ITEMS: [PowerMenuItem; 4] = [
+    PowerMenuItem::Sleep,
+    PowerMenuItem::Reboot,
+    PowerMenuItem::UsbMode,
+    PowerMenuItem::Cancel,
+];
+
+/// Power actions the platform has to carry out; see `Application::take_power_request`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerRequest {
+    Reboot,
+    UsbMode,
+}
+
+pub enum PowerMenuAction {
+    None,
+    Dirty,
+    Select(PowerMenuItem),
+}
+
+#[derive(Default)]
+pub struct PowerMenuState {
+    pub selected: usize,
+    /// Leaves out USB mode, e.g. in simple mode.
+    pub hide_usb: bool,
+}
+
+impl PowerMenuState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.selected = 0;
+    }
+
+    fn items(&self) -> impl Iterator<Item = PowerMenuItem> + '_ {
+        ITEMS
+            .iter()
+            .copied()
+            .filter(|item| !(self.hide_usb && *item == PowerMenuItem::UsbMode))
+    }
+
+    fn item_count(&self) -> usize {
+        self.items().count()
+    }
+
+    pub fn handle_input(&mut self, buttons: &ButtonState) -> PowerMenuAction {
+        if buttons.is_pressed(Buttons::Back) {
+            return PowerMenuAction::Select(PowerMenuItem::Cancel);
+        }
+        let count = self.item_count();
+        if buttons.is_pressed(Buttons::Confirm) {
+            let item = self.items().nth(self.selected).unwrap_or(PowerMenuItem::Cancel);
+            return PowerMenuAction::Select(item);
+        }
+        if buttons.is_pressed(Buttons::Up) || buttons.is_pressed(Buttons::Left) {
+            self.selected = (self.selected + count - 1) % count;
+            return PowerMenuAction::Dirty;
+        }
+        if buttons.is_pressed(Buttons::Down) || buttons.is_pressed(Buttons::Right) {
+            self.selected = (self.selected + 1) % count;
+            return PowerMenuAction::Dirty;
+        }
+        PowerMenuAction::None
+    }
+
+    /// Draws the menu as a box over whatever is currently on screen.
+    pub fn draw(&self, display_buffers: &mut DisplayBuffers, display: &mut impl Display) {
+        let inactive = *display_buffers.get_inactive_buffer();
+        display_buffers
+            .get_active_buffer_mut()
+            .copy_from_slice(&inactive);
+
+        let size = display_buffers.size();
+        let height = TITLE_HEIGHT + ITEM_HEIGHT * self.item_count() as i32 + PADDING;
+        let x = (size.width as i32 - MENU_WIDTH) / 2;
+        let y = (size.height as i32 - height) / 2;
+
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display_buffers)
+            .ok();
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+            .draw(display_buffers)
+            .ok();
+
+        let dark = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        let light = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        Text::new("Power", Point::new(x + PADDING, y + 28), dark)
+            .draw(display_buffers)
+            .ok();
+        Text::new("Power", Point::new(x + PADDING + 1, y + 28), dark)
+            .draw(display_buffers)
+            .ok();
+
+        for (index, item) in self.items().enumerate() {
+            let item_y = y + TITLE_HEIGHT + index as i32 * ITEM_HEIGHT;
+            let style = if index == self.selected {
+                Rectangle::new(
+                    Point::new(x + 4, item_y),
+                    Size::new((MENU_WIDTH - 8) as u32, (ITEM_HEIGHT - 4) as u32),
+                )
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                .draw(display_buffers)
+                .ok();
+                light
+            } else {
+                dark
+            };
+            Text::new(item.label(), Point::new(x + PADDING, item_y + 21), style)
+                .draw(display_buffers)
+                .ok();
+        }
+
+        let mut rq = RenderQueue::default();
+        rq.push(Rect::new(x, y, MENU_WIDTH, height), RefreshMode::Fast);
+        flush_queue(display, display_buffers, &mut rq, RefreshMode::Fast);
+    }
+}
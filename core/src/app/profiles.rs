@@ -0,0 +1,289 @@
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use embedded_graphics::{
+    geometry::Size,
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{OriginDimensions, Point, Primitive},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+
+use crate::{
+    display::{Display, RefreshMode},
+    framebuffer::DisplayBuffers,
+    input::{ButtonState, Buttons},
+    persistence::StateStorage,
+    ui::{ellipsize, flush_queue, Rect, RenderQueue},
+};
+
+/// Text file in the root of the card listing the profiles, one per line as
+/// `Name` or `Name:1234` with a PIN. Without it there is a single profile.
+pub const PROFILES_FILE: &str = "PROFILES.TXT";
+pub const MAX_PROFILES: usize = 4;
+const PIN_DIGITS: usize = 4;
+const NAME_CHARS: usize = 20;
+
+const MENU_WIDTH: i32 = 260;
+const ITEM_HEIGHT: i32 = 32;
+const TITLE_HEIGHT: i32 = 40;
+const PADDING: i32 = 12;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub pin: Option<[u8; PIN_DIGITS]>,
+}
+
+/// Lines that are blank or start with `#` are skipped, as is a PIN that is
+/// not exactly four digits.
+pub fn parse_profiles(text: &str) -> Vec<Profile> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, pin) = match line.rsplit_once(':') {
+                Some((name, pin)) => (name.trim(), parse_pin(pin.trim())),
+                None => (line, None),
+            };
+            Profile {
+                name: name.to_string(),
+                pin,
+            }
+        })
+        .take(MAX_PROFILES)
+        .collect()
+}
+
+fn parse_pin(text: &str) -> Option<[u8; PIN_DIGITS]> {
+    let bytes = text.as_bytes();
+    if bytes.len() != PIN_DIGITS || !bytes.iter().all(u8::is_ascii_digit) {
+        log::warn!("Ignoring profile PIN that is not {} digits", PIN_DIGITS);
+        return None;
+    }
+    let mut pin = [0u8; PIN_DIGITS];
+    for (digit, byte) in pin.iter_mut().zip(bytes) {
+        *digit = byte - b'0';
+    }
+    Some(pin)
+}
+
+pub fn read_profiles(storage: &mut impl StateStorage) -> Vec<Profile> {
+    storage
+        .read_state_file(PROFILES_FILE)
+        .map(|data| parse_profiles(&String::from_utf8_lossy(&data)))
+        .unwrap_or_default()
+}
+
+pub enum ProfileAction {
+    None,
+    Dirty,
+    Close,
+    Switch(usize),
+}
+
+struct PinEntry {
+    profile: usize,
+    digits: [u8; PIN_DIGITS],
+    cursor: usize,
+    wrong: bool,
+}
+
+/// Profile switcher opened from the home screen, with the PIN prompt for
+/// profiles that have one.
+#[derive(Default)]
+pub struct ProfilePicker {
+    pub selected: usize,
+    pin: Option<PinEntry>,
+}
+
+impl ProfilePicker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&mut self, active: usize) {
+        self.selected = active;
+        self.pin = None;
+    }
+
+    pub fn handle_input(
+        &mut self,
+        profiles: &[Profile],
+        active: usize,
+        buttons: &ButtonState,
+    ) -> ProfileAction {
+        if let Some(entry) = self.pin.as_mut() {
+            if buttons.is_pressed(Buttons::Back) {
+                self.pin = None;
+                return ProfileAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Up) || buttons.is_pressed(Buttons::Down) {
+                let digit = &mut entry.digits[entry.cursor];
+                *digit = if buttons.is_pressed(Buttons::Up) {
+                    (*digit + 1) % 10
+                } else {
+                    (*digit + 9) % 10
+                };
+                entry.wrong = false;
+                return ProfileAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Left) {
+                entry.cursor = entry.cursor.saturating_sub(1);
+                return ProfileAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Right) {
+                entry.cursor = (entry.cursor + 1).min(PIN_DIGITS - 1);
+                return ProfileAction::Dirty;
+            }
+            if buttons.is_pressed(Buttons::Confirm) {
+                let profile = entry.profile;
+                if profiles.get(profile).and_then(|p| p.pin) == Some(entry.digits) {
+                    self.pin = None;
+                    return ProfileAction::Switch(profile);
+                }
+                entry.digits = [0; PIN_DIGITS];
+                entry.cursor = 0;
+                entry.wrong = true;
+                return ProfileAction::Dirty;
+            }
+            return ProfileAction::None;
+        }
+
+        if buttons.is_pressed(Buttons::Back) {
+            return ProfileAction::Close;
+        }
+        if profiles.is_empty() {
+            return ProfileAction::None;
+        }
+        if buttons.is_pressed(Buttons::Up) || buttons.is_pressed(Buttons::Left) {
+            self.selected = (self.selected + profiles.len() - 1) % profiles.len();
+            return ProfileAction::Dirty;
+        }
+        if buttons.is_pressed(Buttons::Down) || buttons.is_pressed(Buttons::Right) {
+            self.selected = (self.selected + 1) % profiles.len();
+            return ProfileAction::Dirty;
+        }
+        if buttons.is_pressed(Buttons::Confirm) {
+            let Some(profile) = profiles.get(self.selected) else {
+                return ProfileAction::None;
+            };
+            if self.selected == active {
+                return ProfileAction::Close;
+            }
+            if profile.pin.is_none() {
+                return ProfileAction::Switch(self.selected);
+            }
+            self.pin = Some(PinEntry {
+                profile: self.selected,
+                digits: [0; PIN_DIGITS],
+                cursor: 0,
+                wrong: false,
+            });
+            return ProfileAction::Dirty;
+        }
+        ProfileAction::None
+    }
+
+    /// Draws the picker as a box over the home screen.
+    pub fn draw(
+        &self,
+        display_buffers: &mut DisplayBuffers,
+        display: &mut impl Display,
+        profiles: &[Profile],
+        active: usize,
+    ) {
+        let inactive = *display_buffers.get_inactive_buffer();
+        display_buffers
+            .get_active_buffer_mut()
+            .copy_from_slice(&inactive);
+
+        let rows = if self.pin.is_some() { 2 } else { profiles.len() };
+        let size = display_buffers.size();
+        let height = TITLE_HEIGHT + ITEM_HEIGHT * rows as i32 + PADDING;
+        let x = (size.width as i32 - MENU_WIDTH) / 2;
+        let y = (size.height as i32 - height) / 2;
+
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display_buffers)
+            .ok();
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+            .draw(display_buffers)
+            .ok();
+
+        let dark = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        let light = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let title = match &self.pin {
+            Some(entry) => profiles
+                .get(entry.profile)
+                .map(|profile| ellipsize(&profile.name, NAME_CHARS).to_string())
+                .unwrap_or_default(),
+            None => "Profile".to_string(),
+        };
+        for offset in 0..2 {
+            Text::new(&title, Point::new(x + PADDING + offset, y + 28), dark)
+                .draw(display_buffers)
+                .ok();
+        }
+
+        let top = y + TITLE_HEIGHT;
+        match &self.pin {
+            Some(entry) => {
+                for (index, digit) in entry.digits.iter().enumerate() {
+                    let digit_x = x + PADDING + index as i32 * 24;
+                    let style = if index == entry.cursor {
+                        Rectangle::new(Point::new(digit_x - 4, top), Size::new(20, 28))
+                            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                            .draw(display_buffers)
+                            .ok();
+                        light
+                    } else {
+                        dark
+                    };
+                    Text::new(&format!("{}", digit), Point::new(digit_x + 1, top + 21), style)
+                        .draw(display_buffers)
+                        .ok();
+                }
+                let hint = if entry.wrong { "Wrong PIN" } else { "Enter PIN" };
+                Text::new(hint, Point::new(x + PADDING, top + ITEM_HEIGHT + 21), dark)
+                    .draw(display_buffers)
+                    .ok();
+            }
+            None => {
+                for (index, profile) in profiles.iter().enumerate() {
+                    let item_y = top + index as i32 * ITEM_HEIGHT;
+                    let style = if index == self.selected {
+                        Rectangle::new(
+                            Point::new(x + 4, item_y),
+                            Size::new((MENU_WIDTH - 8) as u32, (ITEM_HEIGHT - 4) as u32),
+                        )
+                        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                        .draw(display_buffers)
+                        .ok();
+                        light
+                    } else {
+                        dark
+                    };
+                    let marker = if index == active { "* " } else { "  " };
+                    let label = format!("{}{}", marker, ellipsize(&profile.name, NAME_CHARS));
+                    Text::new(&label, Point::new(x + PADDING, item_y + 21), style)
+                        .draw(display_buffers)
+                        .ok();
+                }
+            }
+        }
+
+        let mut rq = RenderQueue::default();
+        rq.push(Rect::new(x, y, MENU_WIDTH, height), RefreshMode::Fast);
+        flush_queue(display, display_buffers, &mut rq, RefreshMode::Fast);
+    }
+}
@@ -0,0 +1,68 @@
+extern crate alloc;
+
+use alloc::{format, string::String};
+
+/// Pages shown for less than this are taken as skimmed and not counted.
+const MIN_PAGE_MS: u32 = 3_000;
+/// Pages shown for longer than this were probably left open, not read.
+const MAX_PAGE_MS: u32 = 600_000;
+
+/// How long the reader spends on a page, measured from forward page turns in
+/// the open book and kept per book across sessions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReadingPace {
+    on_page_ms: u32,
+    /// Moving average over recent pages.
+    page_ms: Option<u32>,
+}
+
+impl ReadingPace {
+    /// Starts from a pace measured in an earlier session.
+    pub fn with_page_ms(page_ms: Option<u32>) -> Self {
+        Self {
+            on_page_ms: 0,
+            page_ms,
+        }
+    }
+
+    pub fn page_ms(&self) -> Option<u32> {
+        self.page_ms
+    }
+
+    pub fn tick(&mut self, elapsed_ms: u32) {
+        self.on_page_ms = self.on_page_ms.saturating_add(elapsed_ms);
+    }
+
+    /// Counts the page just left after a forward turn; other page changes
+    /// should only [`ReadingPace::restart`] the clock.
+    pub fn turned(&mut self) {
+        let on_page_ms = self.on_page_ms;
+        self.on_page_ms = 0;
+        if !(MIN_PAGE_MS..=MAX_PAGE_MS).contains(&on_page_ms) {
+            return;
+        }
+        self.page_ms = Some(match self.page_ms {
+            Some(average) => ((average as u64 * 7 + on_page_ms as u64) / 8) as u32,
+            None => on_page_ms,
+        });
+    }
+
+    pub fn restart(&mut self) {
+        self.on_page_ms = 0;
+    }
+
+    /// Time to read `pages` more at the measured pace, once there is one.
+    pub fn time_left_ms(&self, pages: usize) -> Option<u64> {
+        self.page_ms.map(|page_ms| page_ms as u64 * pages as u64)
+    }
+}
+
+/// Rounded up to the minute, e.g. `2h 5m` or `40 min`.
+pub fn format_time_left(ms: u64) -> String {
+    let minutes = ms.div_ceil(60_000);
+    if minutes >= 60 {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{} min", minutes)
+    }
+}
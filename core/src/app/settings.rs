@@ -1,6 +1,6 @@
 extern crate alloc;
 
-use alloc::format;
+use alloc::{format, string::String, vec::Vec};
 
 use embedded_graphics::{
     mono_font::{ascii::FONT_10X20, MonoTextStyle},
@@ -11,10 +11,16 @@ use embedded_graphics::{
 };
 
 use crate::{
+    app::diagnostics::HeapMark,
     app::home::{draw_icon_gray2, merge_bw_into_gray2},
-    display::{Display, GrayscaleMode, RefreshMode},
+    app::image_viewer::ImageOrder,
+    app::power_menu::PowerButtonMode,
+    app::lock::code_progress,
+    display::{Display, FrontlightLevel, GrayscaleMode, RefreshMode, RefreshTuning, FRONTLIGHT_STEPS},
     framebuffer::{DisplayBuffers, BUFFER_SIZE},
-    ui::{flush_queue, Rect, RenderQueue},
+    image_viewer::HeapUsage,
+    reflow::ReadingLayout,
+    ui::{flush_queue, ListItem, ListView, Rect, RenderQueue, UiContext, View},
 };
 
 const LIST_MARGIN_X: i32 = 16;
@@ -29,7 +35,42 @@ pub struct SettingsContext<'a> {
     pub logo_dark: &'a [u8],
     pub logo_light: &'a [u8],
     pub version: &'a str,
+    pub git_hash: &'a str,
     pub build_time: &'a str,
+    pub heap: Option<HeapUsage>,
+    pub heap_marks: &'a [HeapMark],
+    pub safe_mode: bool,
+    /// Typeface books are drawn in, `None` for the book's own.
+    pub reading_font: Option<&'a str>,
+    pub reading_font_count: usize,
+    pub reading_layout: ReadingLayout,
+    /// Hosts remembered as always allowed USB file access.
+    pub usb_host_count: usize,
+    /// New books open at their first page instead of their first chapter.
+    pub show_front_matter: bool,
+    pub image_order: ImageOrder,
+    /// Books hide the page number except briefly after a turn.
+    pub clean_page: bool,
+    /// The page number counts pages within the chapter as well.
+    pub chapter_progress: bool,
+    /// Folder simple mode locks the library to, `None` when off.
+    pub simple_mode: Option<&'a str>,
+    pub simple_folder_count: usize,
+    /// Waking asks for an unlock code.
+    pub screen_lock: bool,
+    /// Presses entered so far while a new unlock code is set up.
+    pub lock_setup: Option<usize>,
+    /// macOS and Windows clutter is deleted after USB access.
+    pub clean_system_files: bool,
+    pub power_button: PowerButtonMode,
+    /// Frontlight setting, `None` on boards without one.
+    pub frontlight: Option<FrontlightLevel>,
+    pub frontlight_warmth: bool,
+    /// Row Left/Right changes: reading font, text size, margins, USB hosts,
+    /// where new books open, the image order, the clean page mode, what the
+    /// page number counts, simple mode, the screen lock, system files, the
+    /// power button, then frontlight brightness and warmth where there is one.
+    pub selected_row: usize,
 }
 
 pub fn draw_settings(ctx: &mut SettingsContext<'_>, display: &mut impl Display) {
@@ -64,7 +105,7 @@ pub fn draw_settings(ctx: &mut SettingsContext<'_>, display: &mut impl Display)
         ctx.logo_light,
     );
 
-    let version_line = format!("Version: {}", ctx.version);
+    let version_line = format!("Version: {} ({})", ctx.version, ctx.git_hash);
     let time_line = format!("Build time: {}", ctx.build_time);
 
     let details_y = logo_y + ctx.logo_h + 12;
@@ -82,6 +123,137 @@ pub fn draw_settings(ctx: &mut SettingsContext<'_>, display: &mut impl Display)
     )
     .draw(ctx.display_buffers)
     .ok();
+    Text::new(
+        "Hold Power to reboot",
+        Point::new(LIST_MARGIN_X, details_y + 74),
+        body_style,
+    )
+    .draw(ctx.display_buffers)
+    .ok();
+
+    let mut rows = Vec::from([
+        format!("Reading font: {}", ctx.reading_font.unwrap_or("Book default")),
+        format!("Text size: {}", ctx.reading_layout.text_size_label()),
+        format!("Margins: {}", ctx.reading_layout.margins_label()),
+        match ctx.usb_host_count {
+            0 => "USB hosts: none remembered".into(),
+            count => format!("USB hosts: {} remembered", count),
+        },
+        format!(
+            "New books open at: {}",
+            if ctx.show_front_matter { "first page" } else { "chapter 1" }
+        ),
+        format!("Image order: {}", ctx.image_order.label()),
+        format!("Clean page: {}", if ctx.clean_page { "on" } else { "off" }),
+        format!(
+            "Page number: {}",
+            if ctx.chapter_progress { "book and chapter" } else { "book" }
+        ),
+        format!("Simple mode: {}", ctx.simple_mode.unwrap_or("off")),
+        match ctx.lock_setup {
+            Some(entered) => format!("Screen lock: new code {}", code_progress(entered)),
+            None => format!("Screen lock: {}", if ctx.screen_lock { "on" } else { "off" }),
+        },
+        format!(
+            "System files: {}",
+            if ctx.clean_system_files { "hide and delete" } else { "hide" }
+        ),
+        format!("Power button: {}", ctx.power_button.label()),
+    ]);
+    if let Some(level) = ctx.frontlight {
+        rows.push(format!("Frontlight: {}", slider(level.brightness)));
+        if ctx.frontlight_warmth {
+            rows.push(format!("Warmth: {}", slider(level.warmth)));
+        }
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let marker = if index == ctx.selected_row { ">" } else { " " };
+        let line = format!("{marker} {row}");
+        Text::new(&line, Point::new(LIST_MARGIN_X, details_y + 108 + index as i32 * 22), body_style)
+            .draw(ctx.display_buffers)
+            .ok();
+    }
+    let hint = if ctx.selected_row == 0 && ctx.reading_font_count == 0 {
+        "No font packs in /fonts"
+    } else if ctx.selected_row == 0 {
+        "Up/Down, Left/Right to change"
+    } else if ctx.selected_row == 3 {
+        "Left/Right to forget them"
+    } else if ctx.selected_row == 4 {
+        "Chapter 1 skips covers and title pages"
+    } else if ctx.selected_row == 5 {
+        "Left/Right in the image viewer"
+    } else if ctx.selected_row == 6 {
+        "Page number only after a turn"
+    } else if ctx.selected_row == 7 {
+        "Chapters as listed in the ToC"
+    } else if ctx.selected_row == 8 && ctx.simple_folder_count == 0 {
+        "No folders on the card"
+    } else if ctx.selected_row == 8 {
+        "Hold Left+Right on Home to leave"
+    } else if ctx.lock_setup.is_some() {
+        "Press Up/Down/Left/Right, Back cancels"
+    } else if ctx.selected_row == 9 {
+        "Waking asks for a button code"
+    } else if ctx.selected_row == 10 {
+        "._ files, .Spotlight-V100 and the like"
+    } else if ctx.selected_row == 11 && ctx.power_button == PowerButtonMode::SleepOnPress {
+        "No power menu while set"
+    } else if ctx.selected_row == 11 {
+        "Hold Power 3s for the power menu"
+    } else if ctx.selected_row == 12 {
+        "Off while asleep"
+    } else if ctx.selected_row == 13 {
+        "Left cooler, Right warmer"
+    } else {
+        "Applies to books converted with --reflow"
+    };
+    let hint_y = details_y + 114 + rows.len() as i32 * 22;
+    Text::new(hint, Point::new(LIST_MARGIN_X, hint_y), body_style)
+        .draw(ctx.display_buffers)
+        .ok();
+
+    let mut y = hint_y + 44;
+    if ctx.safe_mode {
+        let safe_pos = Point::new(LIST_MARGIN_X, y);
+        Text::new("SAFE MODE", safe_pos, heading_style)
+            .draw(ctx.display_buffers)
+            .ok();
+        Text::new("SAFE MODE", Point::new(safe_pos.x + 1, safe_pos.y), heading_style)
+            .draw(ctx.display_buffers)
+            .ok();
+        Text::new(
+            "Saved state not loaded",
+            Point::new(LIST_MARGIN_X, y + 22),
+            body_style,
+        )
+        .draw(ctx.display_buffers)
+        .ok();
+        y += 52;
+    }
+
+    if let Some(heap) = ctx.heap {
+        let heap_line = format!("Heap: {} used, {} free", heap.used, heap.free);
+        Text::new(&heap_line, Point::new(LIST_MARGIN_X, y), body_style)
+            .draw(ctx.display_buffers)
+            .ok();
+        y += 22;
+        let peak_line = format!("Heap peak: {}", heap.peak);
+        Text::new(&peak_line, Point::new(LIST_MARGIN_X, y), body_style)
+            .draw(ctx.display_buffers)
+            .ok();
+        y += 30;
+        for mark in ctx.heap_marks {
+            if y > size.height as i32 - 12 {
+                break;
+            }
+            let line = format!("{:<12} {:>7}", mark.label, mark.peak);
+            Text::new(&line, Point::new(LIST_MARGIN_X, y), body_style)
+                .draw(ctx.display_buffers)
+                .ok();
+            y += 22;
+        }
+    }
 
     if gray2_used {
         merge_bw_into_gray2(ctx.display_buffers, ctx.gray2_lsb, ctx.gray2_msb);
@@ -99,3 +271,49 @@ pub fn draw_settings(ctx: &mut SettingsContext<'_>, display: &mut impl Display)
         flush_queue(display, ctx.display_buffers, &mut rq, RefreshMode::Full);
     }
 }
+
+/// A slider for a frontlight value, filled up to `value`.
+fn slider(value: u8) -> String {
+    let steps = FRONTLIGHT_STEPS as usize;
+    let filled = (value as usize).min(steps);
+    let mut out = String::from("[");
+    out.extend(core::iter::repeat_n('#', filled));
+    out.extend(core::iter::repeat_n('-', steps - filled));
+    out.push(']');
+    out
+}
+
+/// Rows of the hidden refresh tuning menu, opened by holding Down in Settings.
+pub const TUNING_ROWS: usize = 4;
+
+pub fn draw_tuning(
+    buffers: &mut DisplayBuffers,
+    display: &mut impl Display,
+    tuning: RefreshTuning,
+    selected_row: usize,
+) {
+    let on_off = |value: bool| if value { "on" } else { "off" };
+    let labels: [String; TUNING_ROWS] = [
+        format!("Gray pass after fast refresh: {}", on_off(tuning.gray_after_fast)),
+        format!("Border: {}", tuning.border.label()),
+        format!("Double fast refresh: {}", on_off(tuning.double_fast)),
+        format!("Deep clean when idle: {}", on_off(tuning.idle_deep_clean)),
+    ];
+    let items: Vec<ListItem<'_>> = labels
+        .iter()
+        .map(|label| ListItem { label: label.as_str() })
+        .collect();
+    let mut list = ListView::new(&items);
+    list.title = Some("Refresh tuning");
+    list.footer = Some("Left/Right: change  Back: return");
+    list.selected = selected_row;
+    list.margin_x = LIST_MARGIN_X;
+    list.header_y = HEADER_Y;
+
+    let size = buffers.size();
+    let rect = Rect::new(0, 0, size.width as i32, size.height as i32);
+    let mut rq = RenderQueue::default();
+    let mut ui = UiContext { buffers };
+    list.render(&mut ui, rect, &mut rq);
+    flush_queue(display, buffers, &mut rq, RefreshMode::Fast);
+}
@@ -0,0 +1,180 @@
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+
+use embedded_graphics::{
+    geometry::Size,
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::{OriginDimensions, Point, Primitive},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+    Drawable,
+};
+
+use crate::{
+    app::book_reader::draw_trbk_image,
+    display::{Display, RefreshMode},
+    framebuffer::DisplayBuffers,
+    image_viewer::ImageData,
+    input::{ButtonState, Buttons},
+    ui::{ellipsize, flush_queue, Rect, RenderQueue},
+};
+
+pub const SWITCHER_BOOKS: usize = 3;
+
+const MENU_WIDTH: i32 = 400;
+const ITEM_HEIGHT: i32 = 84;
+const TITLE_HEIGHT: i32 = 40;
+const PADDING: i32 = 12;
+const THUMB_SIZE: i32 = 74;
+const TITLE_CHARS: usize = 28;
+
+/// A recently read book, at the page it was left on.
+pub struct SwitcherBook {
+    pub path: String,
+    pub title: String,
+    pub page: usize,
+    /// Mono thumbnail taken from the Home recents.
+    pub image: Option<ImageData>,
+}
+
+pub enum SwitcherAction {
+    None,
+    Dirty,
+    Close,
+    Open(String),
+}
+
+/// Quick switcher between the last few books, opened by pressing Confirm
+/// twice on Home.
+#[derive(Default)]
+pub struct BookSwitcher {
+    pub books: Vec<SwitcherBook>,
+    pub selected: usize,
+}
+
+impl BookSwitcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&mut self, books: Vec<SwitcherBook>) {
+        self.books = books;
+        self.selected = 0;
+    }
+
+    pub fn close(&mut self) {
+        self.books.clear();
+        self.selected = 0;
+    }
+
+    pub fn handle_input(&mut self, buttons: &ButtonState) -> SwitcherAction {
+        if buttons.is_pressed(Buttons::Back) {
+            return SwitcherAction::Close;
+        }
+        if self.books.is_empty() {
+            return SwitcherAction::None;
+        }
+        if buttons.is_pressed(Buttons::Up) || buttons.is_pressed(Buttons::Left) {
+            self.selected = (self.selected + self.books.len() - 1) % self.books.len();
+            return SwitcherAction::Dirty;
+        }
+        if buttons.is_pressed(Buttons::Down) || buttons.is_pressed(Buttons::Right) {
+            self.selected = (self.selected + 1) % self.books.len();
+            return SwitcherAction::Dirty;
+        }
+        if buttons.is_pressed(Buttons::Confirm) {
+            return match self.books.get(self.selected) {
+                Some(book) => SwitcherAction::Open(book.path.clone()),
+                None => SwitcherAction::None,
+            };
+        }
+        SwitcherAction::None
+    }
+
+    /// Draws the switcher as a box over the home screen.
+    pub fn draw(&self, display_buffers: &mut DisplayBuffers, display: &mut impl Display) {
+        let inactive = *display_buffers.get_inactive_buffer();
+        display_buffers
+            .get_active_buffer_mut()
+            .copy_from_slice(&inactive);
+
+        let size = display_buffers.size();
+        let height = TITLE_HEIGHT + ITEM_HEIGHT * self.books.len() as i32 + PADDING;
+        let x = (size.width as i32 - MENU_WIDTH) / 2;
+        let y = (size.height as i32 - height) / 2;
+
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display_buffers)
+            .ok();
+        Rectangle::new(Point::new(x, y), Size::new(MENU_WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+            .draw(display_buffers)
+            .ok();
+
+        let dark = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        let light = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        for offset in 0..2 {
+            Text::new("Switch book", Point::new(x + PADDING + offset, y + 28), dark)
+                .draw(display_buffers)
+                .ok();
+        }
+
+        let top = y + TITLE_HEIGHT;
+        for (index, book) in self.books.iter().enumerate() {
+            let item_y = top + index as i32 * ITEM_HEIGHT;
+            let selected = index == self.selected;
+            if selected {
+                Rectangle::new(
+                    Point::new(x + 4, item_y),
+                    Size::new((MENU_WIDTH - 8) as u32, (ITEM_HEIGHT - 4) as u32),
+                )
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                .draw(display_buffers)
+                .ok();
+            }
+            let thumb_x = x + PADDING;
+            let thumb_y = item_y + (ITEM_HEIGHT - 4 - THUMB_SIZE) / 2;
+            Rectangle::new(
+                Point::new(thumb_x, thumb_y),
+                Size::new(THUMB_SIZE as u32, THUMB_SIZE as u32),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display_buffers)
+            .ok();
+            if let Some(image) = book.image.as_ref() {
+                draw_trbk_image(
+                    display_buffers,
+                    image,
+                    &mut None,
+                    thumb_x + 2,
+                    thumb_y + 2,
+                    THUMB_SIZE - 4,
+                    THUMB_SIZE - 4,
+                );
+            }
+            let style = if selected { light } else { dark };
+            let label_x = thumb_x + THUMB_SIZE + PADDING;
+            Text::new(
+                &ellipsize(&book.title, TITLE_CHARS),
+                Point::new(label_x, item_y + 30),
+                style,
+            )
+            .draw(display_buffers)
+            .ok();
+            Text::new(
+                &format!("Page {}", book.page + 1),
+                Point::new(label_x, item_y + 56),
+                style,
+            )
+            .draw(display_buffers)
+            .ok();
+        }
+
+        let mut rq = RenderQueue::default();
+        rq.push(Rect::new(x, y, MENU_WIDTH, height), RefreshMode::Fast);
+        flush_queue(display, display_buffers, &mut rq, RefreshMode::Fast);
+    }
+}
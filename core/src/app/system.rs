@@ -1,6 +1,6 @@
 extern crate alloc;
 
-use alloc::{collections::BTreeMap, string::{String, ToString}, vec::Vec};
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec, vec::Vec};
 
 use embedded_graphics::{
     Drawable,
@@ -17,11 +17,13 @@ use crate::{
         book_reader::BookReaderState,
         home::draw_icon_gray2,
         image_viewer::ImageViewerState,
+        reading_pace::format_time_left,
     },
     display::{GrayscaleMode, RefreshMode},
     framebuffer::{DisplayBuffers, Rotation, BUFFER_SIZE, HEIGHT as FB_HEIGHT, WIDTH as FB_WIDTH},
     image_viewer::{AppSource, EntryKind, ImageData, ImageEntry},
-    ui::{flush_queue, ReaderView, Rect, RenderQueue, UiContext, View},
+    persistence::{cap_book_positions, MAX_BOOK_POSITIONS},
+    ui::{ellipsize, flush_queue, ReaderView, Rect, RenderQueue, UiContext, View},
 };
 
 pub struct SleepOverlay {
@@ -45,6 +47,9 @@ pub struct SystemRenderContext<'a, S: AppSource> {
     pub book_reader: &'a mut BookReaderState,
     pub last_viewed_entry: &'a Option<String>,
     pub is_start_menu: bool,
+    /// Waking asks for the unlock code, so the sleep screen shows nothing
+    /// of what is open.
+    pub locked: bool,
     pub logo: SleepWallpaperIcons<'a>,
 }
 
@@ -58,6 +63,15 @@ pub struct ResumeContext<'a, S: AppSource> {
     pub book_reader: &'a BookReaderState,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BootMode {
+    #[default]
+    Normal,
+    /// Started with Back held: resume, recents, book positions and thumbnails
+    /// are not loaded, so a corrupt state file cannot trap the UI in an error loop.
+    Safe,
+}
+
 pub enum TryResumeOutcome {
     None,
     Resume {
@@ -89,6 +103,8 @@ pub struct SystemState {
     pub wake_restore_only: bool,
     pub resume_name: Option<String>,
     pub book_positions: BTreeMap<String, usize>,
+    /// Books in `book_positions`, least recently read first.
+    pub book_order: Vec<String>,
     pub recent_entries: Vec<String>,
     pub recent_dirty: bool,
     pub book_positions_dirty: bool,
@@ -97,15 +113,16 @@ pub struct SystemState {
     pub sleep_wallpaper_gray2: bool,
     pub sleep_wallpaper_trbk_open: bool,
     pub battery_percent: Option<u8>,
+    pub safe_mode: bool,
 }
 
 impl SystemState {
     pub fn new(
         resume_name: Option<String>,
-        book_positions: BTreeMap<String, usize>,
+        book_positions: Vec<(String, usize)>,
         recent_entries: Vec<String>,
     ) -> Self {
-        Self {
+        let mut system = Self {
             sleep_transition: false,
             wake_transition: false,
             full_refresh: true,
@@ -116,7 +133,8 @@ impl SystemState {
             sleep_overlay_pending: false,
             wake_restore_only: false,
             resume_name,
-            book_positions,
+            book_positions: BTreeMap::new(),
+            book_order: Vec::new(),
             recent_entries,
             recent_dirty: false,
             book_positions_dirty: false,
@@ -125,7 +143,18 @@ impl SystemState {
             sleep_wallpaper_gray2: false,
             sleep_wallpaper_trbk_open: false,
             battery_percent: None,
-        }
+            safe_mode: false,
+        };
+        system.set_book_positions(book_positions);
+        system
+    }
+
+    /// Takes positions as saved, oldest first, keeping at most
+    /// `MAX_BOOK_POSITIONS` of them.
+    fn set_book_positions(&mut self, mut entries: Vec<(String, usize)>) {
+        cap_book_positions(&mut entries);
+        self.book_order = entries.iter().map(|(name, _)| name.clone()).collect();
+        self.book_positions = entries.into_iter().collect();
     }
 
     pub fn reset_idle(&mut self) {
@@ -206,7 +235,7 @@ impl SystemState {
                 recent.insert(0, entry.clone());
             }
         }
-        for (name, _) in &self.book_positions {
+        for name in self.book_order.iter().rev() {
             if recent.len() >= 5 {
                 break;
             }
@@ -304,35 +333,77 @@ impl SystemState {
     ) {
         if book_reader.current_book.is_some() {
             if let Some(name) = current_entry.or(last_viewed_entry) {
-                let prev = self.book_positions.insert(name.clone(), book_reader.current_page);
-                if prev != Some(book_reader.current_page) {
+                let page = book_reader.saved_page();
+                let prev = self.book_positions.insert(name.clone(), page);
+                if prev != Some(page) || self.book_order.last() != Some(name) {
+                    self.book_order.retain(|entry| entry != name);
+                    self.book_order.push(name.clone());
                     self.book_positions_dirty = true;
                 }
+                while self.book_order.len() > MAX_BOOK_POSITIONS {
+                    let oldest = self.book_order.remove(0);
+                    self.book_positions.remove(&oldest);
+                }
             }
         }
     }
 
     pub fn save_book_positions_now<S: AppSource>(&mut self, source: &mut S) {
-        if !self.book_positions_dirty {
+        // In safe mode only this session's positions are known; keep the file as is.
+        if !self.book_positions_dirty || self.safe_mode {
             return;
         }
         let entries: Vec<(String, usize)> = self
-            .book_positions
+            .book_order
             .iter()
-            .map(|(name, page)| (name.clone(), *page))
+            .filter_map(|name| Some((name.clone(), *self.book_positions.get(name)?)))
             .collect();
         source.save_book_positions(&entries);
         self.book_positions_dirty = false;
     }
 
     pub fn save_recent_entries_now<S: AppSource>(&mut self, source: &mut S) {
-        if !self.recent_dirty {
+        if !self.recent_dirty || self.safe_mode {
             return;
         }
         source.save_recent_entries(&self.recent_entries);
         self.recent_dirty = false;
     }
 
+    /// Writes out positions and recents that have changed since they were
+    /// last saved, so nothing waits on a path that deep sleep cuts short.
+    pub fn flush_before_sleep<S: AppSource>(&mut self, source: &mut S) {
+        let positions = self.book_positions_dirty;
+        let recents = self.recent_dirty;
+        self.save_book_positions_now(source);
+        self.save_recent_entries_now(source);
+        if self.safe_mode {
+            log::info!("Sleep flush skipped in safe mode");
+        } else if positions || recents {
+            log::info!(
+                "Sleep flush: {} book positions{}, {} recents{}",
+                self.book_positions.len(),
+                if positions { " saved" } else { " unchanged" },
+                self.recent_entries.len(),
+                if recents { " saved" } else { " unchanged" }
+            );
+        } else {
+            log::info!("Sleep flush: nothing pending");
+        }
+    }
+
+    /// Saves what is pending for the profile in use, then loads resume,
+    /// positions and recents of profile `index`.
+    pub fn switch_profile<S: AppSource>(&mut self, source: &mut S, index: usize) {
+        self.save_book_positions_now(source);
+        self.save_recent_entries_now(source);
+        source.switch_profile(index);
+        self.resume_name = source.load_resume();
+        self.set_book_positions(source.load_book_positions());
+        self.recent_entries = source.load_recent_entries();
+        self.last_saved_resume = None;
+    }
+
     pub fn current_resume_string(
         &self,
         in_start_menu: bool,
@@ -395,10 +466,14 @@ impl SystemState {
         display: &mut impl crate::display::Display,
     ) {
         let size = ctx.display_buffers.size();
-        let text = "Sleeping...";
-        let text_w = (text.len() as i32) * 10;
         let padding = 8;
-        let bar_h = 28;
+        let lines = if ctx.locked {
+            vec!["Locked".to_string()]
+        } else {
+            Self::sleep_lines(ctx, (size.width as usize).saturating_sub(padding as usize * 2) / 10)
+        };
+        let text_w = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as i32 * 10;
+        let bar_h = 28 + (lines.len() as i32 - 1) * 22;
         let bar_w = (text_w + padding * 2).min(size.width as i32);
         let x = ((size.width as i32 - bar_w) / 2).max(0);
         let y = (size.height as i32 - bar_h).max(0);
@@ -420,10 +495,12 @@ impl SystemState {
 
         let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
         let text_x = x + padding;
-        let text_y = y + bar_h - 14;
-        Text::new(text, Point::new(text_x, text_y), style)
-            .draw(ctx.display_buffers)
-            .ok();
+        for (index, line) in lines.iter().enumerate() {
+            let text_y = y + 14 + index as i32 * 22;
+            Text::new(line, Point::new(text_x, text_y), style)
+                .draw(ctx.display_buffers)
+                .ok();
+        }
 
         let mut rq = RenderQueue::default();
         rq.push(
@@ -440,6 +517,30 @@ impl SystemState {
         }
     }
 
+    /// Title, author and progress when sleeping in a book, with the time
+    /// left at the pace pages have been turned; otherwise just "Sleeping...".
+    fn sleep_lines<S: AppSource>(ctx: &SystemRenderContext<'_, S>, max_chars: usize) -> Vec<String> {
+        let book = ctx
+            .book_reader
+            .current_book
+            .as_ref()
+            .filter(|book| book.page_count > 0 && !ctx.image_viewer.has_image());
+        let Some(book) = book else {
+            return vec!["Sleeping...".to_string()];
+        };
+        let page = ctx.book_reader.current_page.min(book.page_count - 1);
+        let percent = (page + 1) * 100 / book.page_count;
+        let progress = match ctx.book_reader.pace.time_left_ms(book.page_count - page) {
+            Some(ms) => format!("{}% read, {} left", percent, format_time_left(ms)),
+            None => format!("{}% read", percent),
+        };
+        [book.metadata.title.as_str(), book.metadata.author.as_str(), progress.as_str()]
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| ellipsize(line.trim(), max_chars).into_owned())
+            .collect()
+    }
+
     pub fn process_sleep_overlay<S: AppSource>(
         &mut self,
         ctx: &mut SystemRenderContext<'_, S>,
@@ -448,6 +549,7 @@ impl SystemState {
         if !self.sleep_overlay_pending {
             return;
         }
+        self.flush_before_sleep(ctx.source);
         self.draw_sleep_overlay(ctx, display);
         ctx.source.sleep();
         self.clear_sleep_overlay_pending();
@@ -481,6 +583,11 @@ impl SystemState {
             ctx.book_reader.current_book.is_some(),
             ctx.last_viewed_entry
         );
+        if ctx.locked {
+            self.sleep_from_home = false;
+            self.render_sleep_fallback_logo(ctx);
+            return;
+        }
         if ctx.image_viewer.has_image() {
             if let Some(image) = ctx.image_viewer.take_image() {
                 self.render_wallpaper(ctx, &image);
@@ -12,9 +12,7 @@ use embedded_graphics::{
     text::Text,
 };
 
-mod generated_icons {
-    include!(concat!(env!("OUT_DIR"), "/icons.rs"));
-}
+use crate::icons;
 
 fn is_trbk(name: &str) -> bool {
     let lower = name.to_ascii_lowercase();
@@ -28,7 +26,11 @@ fn is_epub(name: &str) -> bool {
 
 use crate::{
     app::{
-        book_reader::{draw_trbk_image, BookReaderContext, BookReaderState, PageTurnIndicator},
+        book_reader::{
+            draw_trbk_image, AutoTurnTick, BookReaderContext, BookReaderState, PageTurnIndicator,
+            ScrubTick,
+        },
+        diagnostics::{FlightRecorder, HeapMarks},
         home::{
             HomeAction,
             HomeIcons,
@@ -37,23 +39,51 @@ use crate::{
             HomeRenderContext,
             HomeState,
             MenuAction,
+            StartMenuSection,
+            thumbnail_to_mono,
         },
-        image_viewer::{ImageViewerContext, ImageViewerState},
-        settings::{draw_settings, SettingsContext},
-        system::{ApplyResumeOutcome, ResumeContext, SleepWallpaperIcons, SystemRenderContext, SystemState},
+        image_viewer::{ImageOrder, ImageViewerContext, ImageViewerState},
+        lock::{code_press, LockAction, LockScreen, LOCK_CODE_LEN},
+        power_menu::{
+            PowerButtonMode, PowerMenuAction, PowerMenuItem, PowerMenuState, PowerRequest,
+            POWER_LONG_PRESS_MS,
+        },
+        profiles::{Profile, ProfileAction, ProfilePicker},
+        reading_pace::ReadingPace,
+        switcher::{BookSwitcher, SwitcherAction, SwitcherBook, SWITCHER_BOOKS},
+        settings::{draw_settings, draw_tuning, SettingsContext, TUNING_ROWS},
+        system::{ApplyResumeOutcome, BootMode, ResumeContext, SleepWallpaperIcons, SystemRenderContext, SystemState},
     },
     build_info,
-    display::RefreshMode,
+    display::{BorderMode, Frontlight, FrontlightLevel, RefreshMode, RefreshTuning, FRONTLIGHT_STEPS},
     framebuffer::{DisplayBuffers, Rotation},
-    image_viewer::{AppSource, ImageEntry, ImageError},
+    image_viewer::{AppSource, EntryKind, ImageEntry, ImageError},
     input,
-    ui::{flush_queue, Rect, RenderQueue},
+    reflow::{self, ReadingLayout},
+    ui::{flush_queue, wrap_two_lines, ProgressView, Rect, RenderQueue, UiContext, View},
 };
 
 const LIST_MARGIN_X: i32 = 16;
 const HEADER_Y: i32 = 24;
 const PAGE_INDICATOR_MARGIN: i32 = 12;
 const PAGE_INDICATOR_Y: i32 = 24;
+const SETTINGS_ROWS: usize = 12;
+/// Holding Down this long in Settings opens the refresh tuning menu.
+const TUNING_LONG_PRESS_MS: u32 = 3000;
+/// A page left this long after fast refreshes is redrawn with a full refresh
+/// to clear the ghosting they left.
+const GHOST_CLEAN_IDLE_MS: u32 = 120_000;
+/// Home or the library left alone this long starts building missing
+/// thumbnails, one per update.
+const WARM_IDLE_MS: u32 = 1500;
+/// How long the frontlight takes to fade out on sleep and back in on wake.
+const FRONTLIGHT_FADE_MS: u32 = 400;
+/// Holding Left and Right together this long on Home leaves simple mode.
+const SIMPLE_EXIT_MS: u32 = 5000;
+/// A second Confirm on Home within this long opens the book switcher.
+const DOUBLE_CONFIRM_MS: u32 = 400;
+/// Books whose reading pace is remembered; the oldest entries are dropped first.
+const MAX_BOOK_PACES: usize = 64;
 pub struct Application<'a, S: AppSource> {
     dirty: bool,
     display_buffers: &'a mut DisplayBuffers,
@@ -70,6 +100,64 @@ pub struct Application<'a, S: AppSource> {
     gray2_msb: Vec<u8>,
     exit_from: ExitFrom,
     exit_overlay_drawn: bool,
+    heap_marks: HeapMarks,
+    flight: FlightRecorder,
+    power_mode: PowerButtonMode,
+    power_press: input::LongPress,
+    power_menu: PowerMenuState,
+    power_menu_return: AppState,
+    power_request: Option<PowerRequest>,
+    sleep_after_power_menu: bool,
+    reading_font: Option<String>,
+    reading_fonts: Vec<String>,
+    reading_layout: ReadingLayout,
+    usb_host_count: usize,
+    show_front_matter: bool,
+    image_order: ImageOrder,
+    clean_page: bool,
+    chapter_progress: bool,
+    /// Time since power on, which seeds the image shuffle.
+    uptime_ms: u32,
+    refresh_tuning: RefreshTuning,
+    /// Set when the display has not been given `refresh_tuning` yet.
+    refresh_tuning_pending: bool,
+    /// Row of the hidden refresh tuning menu, while it is open.
+    tuning_row: Option<usize>,
+    tuning_press: input::LongPress,
+    /// Run the panel's deep clean before the next draw.
+    deep_clean_pending: bool,
+    /// Book or image that was open when USB file access started.
+    usb_return: Option<String>,
+    /// File being read ahead by the source, opened once the read is done.
+    pending_open: Option<ImageEntry>,
+    /// Profiles listed on the card; empty when there is just the one.
+    profiles: Vec<Profile>,
+    active_profile: usize,
+    profile_picker: ProfilePicker,
+    book_switcher: BookSwitcher,
+    /// Time since a Confirm on Home that may be the first of two.
+    home_confirm_ms: Option<u32>,
+    /// Top-level folders simple mode can be locked to, listed with Settings.
+    simple_folders: Vec<String>,
+    /// How long Left and Right have been held together in simple mode.
+    simple_exit_ms: u32,
+    /// Unlock code asked for on wake and power on; empty when off.
+    lock_code: Vec<u8>,
+    lock_screen: LockScreen,
+    /// Presses of a new unlock code while Settings sets one up.
+    lock_setup: Option<Vec<u8>>,
+    /// macOS and Windows clutter is deleted after USB access.
+    clean_system_files: bool,
+    /// Settings row Left/Right changes: reading font, text size, margins or
+    /// USB hosts.
+    settings_row: usize,
+    frontlight: FrontlightLevel,
+    /// What the board's light can do, as last seen by `apply_frontlight`.
+    frontlight_fitted: bool,
+    frontlight_warmth: bool,
+    /// Fade for the next `apply_frontlight`; `None` while the light is
+    /// already right.
+    frontlight_fade: Option<u32>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -81,11 +169,36 @@ enum AppState {
     BookViewing,
     ExitingPending,
     Toc,
+    PowerMenu,
+    Profiles,
+    Switcher,
+    Locked,
     SleepingPending,
     Sleeping,
     Error,
 }
 
+impl AppState {
+    fn label(&self) -> &'static str {
+        match self {
+            AppState::StartMenu => "start_menu",
+            AppState::Settings => "settings",
+            AppState::Menu => "menu",
+            AppState::Viewing => "viewing",
+            AppState::BookViewing => "book_viewing",
+            AppState::ExitingPending => "exiting",
+            AppState::Toc => "toc",
+            AppState::PowerMenu => "power_menu",
+            AppState::Profiles => "profiles",
+            AppState::Switcher => "switcher",
+            AppState::Locked => "locked",
+            AppState::SleepingPending => "sleep_pending",
+            AppState::Sleeping => "sleeping",
+            AppState::Error => "error",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum ExitFrom {
     Image,
@@ -94,14 +207,36 @@ enum ExitFrom {
 
 impl<'a, S: AppSource> Application<'a, S> {
     pub fn new(display_buffers: &'a mut DisplayBuffers, source: &'a mut S) -> Self {
+        Self::with_boot_mode(display_buffers, source, BootMode::Normal)
+    }
+
+    pub fn with_boot_mode(
+        display_buffers: &'a mut DisplayBuffers,
+        source: &'a mut S,
+        boot_mode: BootMode,
+    ) -> Self {
         display_buffers.set_rotation(Rotation::Rotate90);
-        let resume_name = source.load_resume();
-        let book_positions = source
-            .load_book_positions()
-            .into_iter()
-            .collect();
-        let recent_entries = source.load_recent_entries();
-        let system = SystemState::new(resume_name, book_positions, recent_entries);
+        let safe_mode = boot_mode == BootMode::Safe;
+        let mut profiles = Vec::new();
+        let mut active_profile = 0;
+        let system = if safe_mode {
+            log::warn!("Safe mode: skipping resume, recents and thumbnails");
+            let mut system = SystemState::new(None, Default::default(), Vec::new());
+            system.safe_mode = true;
+            system
+        } else {
+            profiles = source.load_profiles();
+            active_profile = source.active_profile();
+            if active_profile != 0 && active_profile >= profiles.len() {
+                log::warn!("Profile {} no longer listed, using the first", active_profile);
+                source.switch_profile(0);
+                active_profile = 0;
+            }
+            let resume_name = source.load_resume();
+            let book_positions = source.load_book_positions();
+            let recent_entries = source.load_recent_entries();
+            SystemState::new(resume_name, book_positions, recent_entries)
+        };
         let mut app = Application {
             dirty: true,
             display_buffers,
@@ -118,49 +253,133 @@ impl<'a, S: AppSource> Application<'a, S> {
             gray2_msb: vec![0u8; crate::framebuffer::BUFFER_SIZE],
             exit_from: ExitFrom::Image,
             exit_overlay_drawn: false,
+            heap_marks: HeapMarks::new(),
+            flight: FlightRecorder::new(),
+            power_mode: PowerButtonMode::default(),
+            power_press: input::LongPress::new(input::Buttons::Power, POWER_LONG_PRESS_MS),
+            power_menu: PowerMenuState::new(),
+            power_menu_return: AppState::StartMenu,
+            power_request: None,
+            sleep_after_power_menu: false,
+            reading_font: None,
+            reading_fonts: Vec::new(),
+            reading_layout: ReadingLayout::default(),
+            usb_host_count: 0,
+            show_front_matter: false,
+            image_order: ImageOrder::default(),
+            clean_page: false,
+            chapter_progress: false,
+            uptime_ms: 0,
+            refresh_tuning: RefreshTuning::default(),
+            refresh_tuning_pending: true,
+            tuning_row: None,
+            tuning_press: input::LongPress::new(input::Buttons::Down, TUNING_LONG_PRESS_MS),
+            deep_clean_pending: false,
+            usb_return: None,
+            pending_open: None,
+            profiles,
+            active_profile,
+            profile_picker: ProfilePicker::new(),
+            book_switcher: BookSwitcher::new(),
+            home_confirm_ms: None,
+            simple_folders: Vec::new(),
+            simple_exit_ms: 0,
+            lock_code: Vec::new(),
+            lock_screen: LockScreen::new(),
+            lock_setup: None,
+            clean_system_files: false,
+            settings_row: 0,
+            frontlight: FrontlightLevel::default(),
+            frontlight_fitted: false,
+            frontlight_warmth: false,
+            frontlight_fade: Some(0),
         };
+        app.home.skip_thumbnails = safe_mode;
+        app.home.profile_name = app.profile_name();
+        if !safe_mode {
+            app.home.simple_root = app.source.load_simple_mode();
+        }
         app.refresh_entries();
-        app.try_resume();
+        if !safe_mode {
+            app.refresh_tuning = app.source.load_refresh_tuning();
+            app.frontlight = app.source.load_frontlight();
+            app.power_mode = app.source.load_power_button_mode();
+            app.try_resume();
+            app.lock_code = app.source.load_lock_code();
+            if !app.lock_code.is_empty() {
+                app.lock();
+            }
+        }
         app
     }
 
     pub fn update(&mut self, buttons: &input::ButtonState, elapsed_ms: u32) {
+        self.uptime_ms = self.uptime_ms.wrapping_add(elapsed_ms);
+        self.flight.buttons(self.uptime_ms, buttons);
+        if self.pending_open.is_some() {
+            self.update_pending_open(buttons);
+        } else {
+            self.update_state(buttons, elapsed_ms);
+        }
+        self.flight.state(self.uptime_ms, self.state.label());
+    }
+
+    /// Waits for the source to finish reading the file being opened. Back
+    /// gives up on it and stays where the open started.
+    fn update_pending_open(&mut self, buttons: &input::ButtonState) {
+        if buttons.is_pressed(input::Buttons::Back) {
+            self.pending_open = None;
+            self.source.cancel_prefetch();
+            return;
+        }
+        if self.source.prefetch_pending() {
+            return;
+        }
+        if let Some(entry) = self.pending_open.take() {
+            self.open_file_now(entry);
+        }
+    }
+
+    fn update_state(&mut self, buttons: &input::ButtonState, elapsed_ms: u32) {
         if self.state == AppState::Sleeping
             && (buttons.is_pressed(input::Buttons::Power)
                 || buttons.is_held(input::Buttons::Power))
         {
-            self.source.wake();
-            let mut resumed_viewer = false;
-            if let Some(overlay) = self.system.sleep_overlay.take() {
-                SystemState::restore_rect_bits(self.display_buffers, &overlay);
-                if self.book_reader.current_book.is_some() {
-                    self.set_state_book_viewing();
-                    self.system.full_refresh = true;
-                    self.system.wake_restore_only = false;
-                } else if self.image_viewer.has_image() {
-                    self.set_state_viewing();
-                    self.system.wake_restore_only = true;
-                } else {
-                    self.set_state_start_menu(true);
-                }
-                resumed_viewer = true;
-            } else {
-                self.set_state_start_menu(true);
-            }
-            self.system.on_wake();
-            self.dirty = true;
-            if !resumed_viewer {
-                self.refresh_entries();
-            }
+            self.wake_up();
             return;
         }
 
-        if self.state != AppState::Sleeping
-            && self.state != AppState::SleepingPending
-            && buttons.is_pressed(input::Buttons::Power)
-        {
-            self.start_sleep_request();
-            return;
+        if self.state != AppState::Sleeping && self.state != AppState::SleepingPending {
+            match self.power_mode {
+                PowerButtonMode::SleepOnPress => {
+                    if buttons.is_pressed(input::Buttons::Power) {
+                        self.start_sleep_request();
+                        return;
+                    }
+                }
+                PowerButtonMode::ShortSleepLongMenu => {
+                    match self.power_press.update(buttons, elapsed_ms) {
+                        Some(input::PressKind::Short) => {
+                            if self.state == AppState::PowerMenu {
+                                self.close_power_menu();
+                            } else {
+                                self.start_sleep_request();
+                            }
+                            return;
+                        }
+                        Some(input::PressKind::Long) => {
+                            self.open_power_menu();
+                            return;
+                        }
+                        None => {
+                            if self.power_press.is_tracking() {
+                                self.system.reset_idle();
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
         }
 
         if Self::has_input(buttons) {
@@ -169,34 +388,59 @@ impl<'a, S: AppSource> Application<'a, S> {
 
         match self.state {
             AppState::StartMenu => {
-                let recents = self.system.collect_recent_paths(self.last_viewed_entry.as_ref());
-                match self.home.handle_start_menu_input(&recents, buttons) {
-                    HomeAction::OpenRecent(path) => {
-                        match self.home.open_recent_path(self.source, &path) {
-                            Ok(()) => {
-                                let index = self.home.selected;
-                                self.open_index(index);
-                            }
-                            Err(err) => {
-                                if self.system.remove_recent(&path) {
-                                    if self.last_viewed_entry.as_deref() == Some(path.as_str()) {
-                                        self.last_viewed_entry = None;
-                                    }
-                                    self.system.save_recent_entries_now(self.source);
-                                }
-                                self.set_error(err);
-                            }
+                if self.home.simple_root.is_some() {
+                    if buttons.is_held(input::Buttons::Left) && buttons.is_held(input::Buttons::Right) {
+                        self.simple_exit_ms = self.simple_exit_ms.saturating_add(elapsed_ms);
+                        if self.simple_exit_ms >= SIMPLE_EXIT_MS {
+                            self.leave_simple_mode();
                         }
+                        return;
                     }
+                    self.simple_exit_ms = 0;
+                }
+                let replay;
+                let buttons = match self.home_confirm_ms.take() {
+                    Some(_) if buttons.is_pressed(input::Buttons::Confirm) => {
+                        self.open_switcher();
+                        return;
+                    }
+                    Some(waited) => {
+                        let waited = waited.saturating_add(elapsed_ms);
+                        if waited < DOUBLE_CONFIRM_MS && !Self::has_input(buttons) {
+                            self.home_confirm_ms = Some(waited);
+                            return;
+                        }
+                        let mut confirm = input::ButtonState::default();
+                        confirm.update(1 << input::Buttons::Confirm as u8);
+                        replay = confirm;
+                        &replay
+                    }
+                    None => {
+                        if buttons.is_pressed(input::Buttons::Confirm) && self.has_switcher() {
+                            self.home_confirm_ms = Some(0);
+                            return;
+                        }
+                        buttons
+                    }
+                };
+                let recents = self.recent_paths();
+                match self.home.handle_start_menu_input(&recents, buttons) {
+                    HomeAction::OpenRecent(path) => self.open_recent(&path),
                     HomeAction::OpenFileBrowser => {
                         self.state = AppState::Menu;
                         self.home.selected = 0;
+                        self.home.enter_root();
                         self.refresh_entries();
                         self.dirty = true;
                     }
                     HomeAction::OpenSettings => {
                         self.set_state_settings();
                     }
+                    HomeAction::OpenProfiles => {
+                        self.profile_picker.open(self.active_profile);
+                        self.state = AppState::Profiles;
+                        self.dirty = true;
+                    }
                     HomeAction::None => {
                         if Self::has_input(buttons) {
                             self.dirty = true;
@@ -210,6 +454,8 @@ impl<'a, S: AppSource> Application<'a, S> {
                 if !Self::has_input(buttons) {
                     if self.system.add_idle(elapsed_ms) {
                         self.start_sleep_request();
+                    } else {
+                        self.warm_thumbnails();
                     }
                 }
             }
@@ -219,8 +465,7 @@ impl<'a, S: AppSource> Application<'a, S> {
                         self.open_selected();
                     }
                     MenuAction::Back => {
-                        if !self.home.path.is_empty() {
-                            self.home.path.pop();
+                        if self.home.go_up() {
                             self.refresh_entries();
                         } else {
                             self.set_state_start_menu(true);
@@ -232,15 +477,39 @@ impl<'a, S: AppSource> Application<'a, S> {
                     MenuAction::None => {
                         if self.system.add_idle(elapsed_ms) {
                             self.start_sleep_request();
+                        } else {
+                            self.warm_thumbnails();
                         }
                     }
                 }
             }
             AppState::Settings => {
-                if buttons.is_pressed(input::Buttons::Back)
+                if self.tuning_row.is_some() {
+                    self.update_tuning(buttons);
+                    return;
+                }
+                if self.lock_setup.is_some() {
+                    self.update_lock_setup(buttons);
+                    return;
+                }
+                if self.tuning_press.update(buttons, elapsed_ms) == Some(input::PressKind::Long) {
+                    self.tuning_row = Some(0);
+                    self.dirty = true;
+                } else if buttons.is_pressed(input::Buttons::Back)
                     || buttons.is_pressed(input::Buttons::Confirm)
                 {
                     self.set_state_start_menu(true);
+                } else if buttons.is_pressed(input::Buttons::Up) {
+                    let rows = self.settings_rows();
+                    self.settings_row = (self.settings_row + rows - 1) % rows;
+                    self.dirty = true;
+                } else if buttons.is_pressed(input::Buttons::Down) {
+                    self.settings_row = (self.settings_row + 1) % self.settings_rows();
+                    self.dirty = true;
+                } else if buttons.is_pressed(input::Buttons::Left) {
+                    self.change_setting(false);
+                } else if buttons.is_pressed(input::Buttons::Right) {
+                    self.change_setting(true);
                 } else {
                     if self.system.add_idle(elapsed_ms) {
                         self.start_sleep_request();
@@ -248,16 +517,18 @@ impl<'a, S: AppSource> Application<'a, S> {
                 }
             }
             AppState::Viewing => {
-                if buttons.is_pressed(input::Buttons::Left) {
-                    if !self.home.entries.is_empty() {
-                        let next = self.home.selected.saturating_sub(1);
-                        self.open_index(next);
-                    }
-                } else if buttons.is_pressed(input::Buttons::Right) {
-                    if !self.home.entries.is_empty() {
-                        let next = (self.home.selected + 1).min(self.home.entries.len() - 1);
+                if buttons.is_pressed(input::Buttons::Left)
+                    || buttons.is_pressed(input::Buttons::Right)
+                {
+                    let forward = buttons.is_pressed(input::Buttons::Right);
+                    if let Some(next) = self.image_viewer.step(self.home.selected, forward) {
                         self.open_index(next);
                     }
+                } else if buttons.is_pressed(input::Buttons::Up)
+                    || buttons.is_pressed(input::Buttons::Down)
+                {
+                    self.image_viewer.show_info = !self.image_viewer.show_info;
+                    self.dirty = true;
                 } else if buttons.is_pressed(input::Buttons::Back)
                     || buttons.is_pressed(input::Buttons::Confirm)
                 {
@@ -272,26 +543,66 @@ impl<'a, S: AppSource> Application<'a, S> {
                 }
             }
             AppState::BookViewing => {
+                self.book_reader.pace.tick(elapsed_ms);
+                match self.book_reader.tick_scrub(buttons, elapsed_ms) {
+                    ScrubTick::Idle => {}
+                    ScrubTick::Held => {
+                        self.system.reset_idle();
+                        return;
+                    }
+                    ScrubTick::Moved | ScrubTick::Closed => {
+                        self.system.reset_idle();
+                        self.dirty = true;
+                        return;
+                    }
+                    ScrubTick::Jumped => {
+                        self.system.reset_idle();
+                        self.set_state_book_viewing();
+                        return;
+                    }
+                }
                 let result = self
                     .book_reader
                     .handle_view_input(self.source, buttons);
-                if result.exit {
+                if result.exit || self.book_reader.tick_back(elapsed_ms) {
                     self.exit_from = ExitFrom::Book;
                     self.exit_overlay_drawn = false;
                     self.state = AppState::ExitingPending;
                     self.dirty = true;
                 } else if result.open_toc {
                     self.set_state_toc();
+                } else if result.open_next_part {
+                    self.open_next_part();
                 } else if result.dirty {
                     self.dirty = true;
                 } else {
-                    if self.system.add_idle(elapsed_ms) {
-                        self.start_sleep_request();
+                    if self.book_reader.tick_page_indicator(elapsed_ms) {
+                        self.dirty = true;
+                    }
+                    match self.book_reader.tick_auto_turn(elapsed_ms) {
+                        AutoTurnTick::Turned | AutoTurnTick::Pips => {
+                            self.system.reset_idle();
+                            self.dirty = true;
+                        }
+                        AutoTurnTick::None => {
+                            if self.book_reader.auto_turn_active() {
+                                self.system.reset_idle();
+                            } else if self.system.add_idle(elapsed_ms) {
+                                self.start_sleep_request();
+                            } else if self.system.idle_ms >= GHOST_CLEAN_IDLE_MS
+                                && self.book_reader.book_turns_since_full > 0
+                            {
+                                self.clean_ghosting();
+                            }
+                        }
                     }
                 }
             }
             AppState::Toc => {
                 let result = self.book_reader.handle_toc_input(buttons);
+                if result.crop_changed {
+                    self.save_book_crop();
+                }
                 if result.exit {
                     self.set_state_book_viewing();
                 } else if result.jumped {
@@ -304,6 +615,66 @@ impl<'a, S: AppSource> Application<'a, S> {
                     }
                 }
             }
+            AppState::Locked => match self.lock_screen.handle_input(&self.lock_code, buttons) {
+                LockAction::None => {
+                    if self.system.add_idle(elapsed_ms) {
+                        self.start_sleep_request();
+                    }
+                }
+                LockAction::Dirty => self.dirty = true,
+                LockAction::Unlocked => self.unlock(),
+            },
+            AppState::Profiles => {
+                match self
+                    .profile_picker
+                    .handle_input(&self.profiles, self.active_profile, buttons)
+                {
+                    ProfileAction::None => {
+                        if self.system.add_idle(elapsed_ms) {
+                            self.start_sleep_request();
+                        }
+                    }
+                    ProfileAction::Dirty => self.dirty = true,
+                    ProfileAction::Close => self.set_state_start_menu(true),
+                    ProfileAction::Switch(index) => self.switch_profile(index),
+                }
+            }
+            AppState::Switcher => match self.book_switcher.handle_input(buttons) {
+                SwitcherAction::None => {
+                    if self.system.add_idle(elapsed_ms) {
+                        self.start_sleep_request();
+                    }
+                }
+                SwitcherAction::Dirty => self.dirty = true,
+                SwitcherAction::Close => {
+                    self.book_switcher.close();
+                    self.set_state_start_menu(true);
+                }
+                SwitcherAction::Open(path) => {
+                    self.book_switcher.close();
+                    self.set_state_start_menu(true);
+                    self.open_recent(&path);
+                }
+            },
+            AppState::PowerMenu => match self.power_menu.handle_input(buttons) {
+                PowerMenuAction::None => {}
+                PowerMenuAction::Dirty => self.dirty = true,
+                PowerMenuAction::Select(item) => {
+                    self.close_power_menu();
+                    match item {
+                        PowerMenuItem::Sleep => self.sleep_after_power_menu = true,
+                        PowerMenuItem::Reboot => {
+                            self.save_flight_log("reboot");
+                            if let Err(message) = self.save_resume() {
+                                log::warn!("Resume not saved before reboot: {}", message);
+                            }
+                            self.power_request = Some(PowerRequest::Reboot);
+                        }
+                        PowerMenuItem::UsbMode => self.power_request = Some(PowerRequest::UsbMode),
+                        PowerMenuItem::Cancel => {}
+                    }
+                }
+            },
             AppState::SleepingPending => {}
             AppState::Sleeping => {}
             AppState::ExitingPending => {}
@@ -319,21 +690,45 @@ impl<'a, S: AppSource> Application<'a, S> {
     }
 
     pub fn draw(&mut self, display: &mut impl crate::display::Display) {
+        if self.refresh_tuning_pending {
+            display.set_refresh_tuning(self.refresh_tuning);
+            self.refresh_tuning_pending = false;
+        }
         if !self.dirty {
             return;
         }
+        if self.deep_clean_pending {
+            display.deep_clean();
+            self.deep_clean_pending = false;
+        }
 
         self.dirty = false;
+        let drawn_state = self.state.clone();
         match self.state {
             AppState::StartMenu => self.draw_start_menu(display),
             AppState::Settings => self.draw_settings(display),
             AppState::Menu => self.draw_menu(display),
             AppState::Viewing => self.draw_image_viewer(display),
             AppState::BookViewing => {
-                if let Some(indicator) = self.book_reader.take_page_turn_indicator() {
-                    self.draw_page_turn_indicator(display, indicator);
+                if self.book_reader.scrub_draw_pending {
+                    self.book_reader.draw_scrubber(self.display_buffers, display);
+                } else if self.book_reader.auto_turn_pips_pending
+                    || self.book_reader.page_indicator_erase_pending
+                {
+                    if self.book_reader.auto_turn_pips_pending {
+                        self.book_reader
+                            .draw_auto_turn_pips(self.display_buffers, display);
+                    }
+                    if self.book_reader.page_indicator_erase_pending {
+                        self.erase_book_page_indicator(display);
+                    }
+                } else {
+                    if let Some(indicator) = self.book_reader.take_page_turn_indicator() {
+                        self.draw_page_turn_indicator(display, indicator);
+                    }
+                    self.draw_book_reader(display);
+                    self.sample_heap("page_turn");
                 }
-                self.draw_book_reader(display);
             }
             AppState::ExitingPending => {
                 if !self.exit_overlay_drawn {
@@ -355,28 +750,22 @@ impl<'a, S: AppSource> Application<'a, S> {
                 self.set_state_start_menu(true);
             }
             AppState::Toc => self.draw_toc_view(display),
+            AppState::PowerMenu => self.power_menu.draw(self.display_buffers, display),
+            AppState::Locked => {
+                self.lock_screen
+                    .draw(self.display_buffers, display, self.system.full_refresh)
+            }
+            AppState::Profiles => self.profile_picker.draw(
+                self.display_buffers,
+                display,
+                &self.profiles,
+                self.active_profile,
+            ),
+            AppState::Switcher => self.book_switcher.draw(self.display_buffers, display),
             AppState::SleepingPending => {
                 self.draw_sleeping_indicator(display);
-                let resume_debug = format!(
-                    "state={:?} current_entry={:?} last_viewed_entry={:?} path={:?} selected={} has_book={} current_page={} last_rendered={:?}",
-                    self.state,
-                    self.current_entry,
-                    self.last_viewed_entry,
-                    self.home.path,
-                    self.home.selected,
-                    self.book_reader.current_book.is_some(),
-                    self.book_reader.current_page,
-                    self.book_reader.last_rendered_page
-                );
-                let outcome = self.system.save_resume_or_error(ResumeContext {
-                    source: self.source,
-                    resume_debug: &resume_debug,
-                    in_start_menu: self.state == AppState::StartMenu,
-                    current_entry: self.current_entry.as_ref(),
-                    last_viewed_entry: self.last_viewed_entry.as_ref(),
-                    home_current_entry: self.home.current_entry_name_owned(),
-                    book_reader: &self.book_reader,
-                });
+                self.save_book_pace();
+                let outcome = self.save_resume();
                 if outcome.is_ok() {
                     self.state = AppState::Sleeping;
                     self.system.start_sleep_overlay();
@@ -390,15 +779,28 @@ impl<'a, S: AppSource> Application<'a, S> {
             }
             AppState::Error => self.draw_error(display),
         }
+        self.sample_heap(drawn_state.label());
+        self.flight.state(self.uptime_ms, self.state.label());
         self.system.full_refresh = false;
+        if self.sleep_after_power_menu {
+            // The screen underneath the menu has been redrawn; sleep on top of it.
+            self.sleep_after_power_menu = false;
+            self.start_sleep_request();
+        }
         if self.state == AppState::Error && self.system.sleep_after_error {
             self.system.sleep_after_error = false;
             self.state = AppState::Sleeping;
+            self.frontlight_fade = Some(FRONTLIGHT_FADE_MS);
             self.system.start_sleep_overlay();
             self.dirty = true;
         }
     }
 
+    /// Name of the screen shown, as recorded in the flight log.
+    pub fn state_label(&self) -> &'static str {
+        self.state.label()
+    }
+
     pub fn with_source<R>(&mut self, f: impl FnOnce(&mut S) -> R) -> R {
         f(self.source)
     }
@@ -407,6 +809,11 @@ impl<'a, S: AppSource> Application<'a, S> {
         self.source
     }
 
+    fn sample_heap(&mut self, label: &'static str) {
+        let usage = self.source.heap_usage();
+        self.heap_marks.record(label, usage);
+    }
+
     fn has_input(buttons: &input::ButtonState) -> bool {
         use input::Buttons::*;
         let list = [Back, Confirm, Left, Right, Up, Down, Power];
@@ -414,6 +821,68 @@ impl<'a, S: AppSource> Application<'a, S> {
             .any(|b| buttons.is_pressed(*b) || buttons.is_held(*b))
     }
 
+    /// Saves the reading position and closes the open book or image before
+    /// the host gets the card.
+    pub fn enter_usb_mode(&mut self) {
+        // The host can then read how the device got here.
+        self.save_flight_log("usb");
+        if self.pending_open.take().is_some() {
+            self.source.cancel_prefetch();
+        }
+        let open = self.book_reader.has_book() || self.image_viewer.has_image();
+        let reading = matches!(
+            self.state,
+            AppState::Viewing | AppState::BookViewing | AppState::Toc
+        ) || (self.state == AppState::Locked && open);
+        self.usb_return = if reading {
+            self.current_entry.clone()
+        } else {
+            None
+        };
+        self.system.update_book_position(
+            &self.book_reader,
+            self.current_entry.as_ref(),
+            self.last_viewed_entry.as_ref(),
+        );
+        self.system.save_book_positions_now(self.source);
+        self.system.save_recent_entries_now(self.source);
+        if self.book_reader.has_book() {
+            self.book_reader.close(self.source);
+        }
+        self.image_viewer.clear();
+    }
+
+    /// Reopens what [`Self::enter_usb_mode`] closed at the same page, or goes
+    /// to its folder if the host removed it.
+    pub fn leave_usb_mode(&mut self) {
+        let locked = self.state == AppState::Locked;
+        self.home.start_menu_cache.clear();
+        self.home.clear_folder_covers();
+        self.home.restart_warm();
+        match self.usb_return.take() {
+            Some(name) => {
+                log::info!("Returning to {} after USB", name);
+                self.system.resume_name = Some(name);
+                self.try_resume();
+                let reopened = self.book_reader.has_book() || self.image_viewer.has_image();
+                if !reopened && self.state != AppState::Error {
+                    log::warn!("File gone after USB, showing its folder");
+                    self.refresh_entries();
+                }
+            }
+            None => match self.state {
+                AppState::StartMenu => self.set_state_start_menu(true),
+                AppState::Settings => self.set_state_settings(),
+                _ => self.refresh_entries(),
+            },
+        }
+        if locked {
+            self.lock();
+        }
+        self.system.full_refresh = true;
+        self.dirty = true;
+    }
+
     pub fn take_sleep_transition(&mut self) -> bool {
         self.system.take_sleep_transition()
     }
@@ -422,6 +891,60 @@ impl<'a, S: AppSource> Application<'a, S> {
         self.system.take_wake_transition()
     }
 
+    pub fn take_power_request(&mut self) -> Option<PowerRequest> {
+        self.power_request.take()
+    }
+
+    /// Acts on an accelerometer event: turning the device round flips the
+    /// screen, a double tap wakes it and laying it face down puts it to sleep.
+    pub fn handle_motion(&mut self, event: input::MotionEvent) {
+        let asleep = matches!(self.state, AppState::Sleeping | AppState::SleepingPending);
+        match event {
+            input::MotionEvent::DoubleTap => {
+                if self.state == AppState::Sleeping {
+                    self.wake_up();
+                }
+            }
+            input::MotionEvent::FaceDown => self.start_sleep_request(),
+            input::MotionEvent::Upright | input::MotionEvent::UpsideDown => {
+                let rotation = if event == input::MotionEvent::Upright {
+                    Rotation::Rotate90
+                } else {
+                    Rotation::Rotate270
+                };
+                if asleep || self.display_buffers.rotation() == rotation {
+                    return;
+                }
+                log::info!("Rotating the screen to {:?}", rotation);
+                self.display_buffers.set_rotation(rotation);
+                // Prefetched grey planes were drawn for the old rotation.
+                self.book_reader.prefetched_page = None;
+                self.book_reader.prefetched_gray2_used = false;
+                self.system.full_refresh = true;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Brings the board's frontlight in line with the setting, or off while
+    /// asleep. Call once per loop.
+    pub fn apply_frontlight(&mut self, light: &mut impl Frontlight) {
+        self.frontlight_fitted = light.has_frontlight();
+        self.frontlight_warmth = light.has_warmth();
+        let Some(fade_ms) = self.frontlight_fade.take() else {
+            return;
+        };
+        if !self.frontlight_fitted {
+            return;
+        }
+        let level = if matches!(self.state, AppState::Sleeping | AppState::SleepingPending) {
+            FrontlightLevel::OFF
+        } else {
+            self.frontlight
+        };
+        light.set_frontlight(level, fade_ms);
+    }
+
     pub fn set_battery_percent(&mut self, percent: Option<u8>) {
         if self.system.set_battery_percent(percent) && self.state == AppState::StartMenu {
             self.dirty = true;
@@ -463,15 +986,18 @@ impl<'a, S: AppSource> Application<'a, S> {
         }
     }
 
+    /// Opens `entry`, after the source read it ahead if it wants to.
     fn open_file_entry(&mut self, entry: ImageEntry) {
-        if is_trbk(&entry.name) {
-            self.open_book_entry(entry);
+        if self.source.prefetch(&self.home.path, &entry) {
+            self.pending_open = Some(entry);
             return;
         }
-        if is_epub(&entry.name) {
-            self.set_error(ImageError::Message(
-                "EPUB files must be converted to .trbk.".into(),
-            ));
+        self.open_file_now(entry);
+    }
+
+    fn open_file_now(&mut self, entry: ImageEntry) {
+        if is_trbk(&entry.name) || is_epub(&entry.name) {
+            self.open_book_entry(entry);
             return;
         }
         self.open_image_entry(entry);
@@ -479,27 +1005,114 @@ impl<'a, S: AppSource> Application<'a, S> {
 
     fn open_book_entry(&mut self, entry: ImageEntry) {
         let entry_name = self.home.entry_path_string(&entry);
+        let reading_layout = self.source.load_reading_layout();
         match self.book_reader.open(
             self.source,
             &self.home.path,
             &entry,
             &entry_name,
             &self.system.book_positions,
+            reading_layout,
         ) {
             Ok(()) => {
+                self.book_reader.page_crop = self
+                    .source
+                    .load_book_crops()
+                    .into_iter()
+                    .find(|(name, _)| *name == entry_name)
+                    .map(|(_, crop)| crop)
+                    .unwrap_or(0);
+                let page_ms = self
+                    .source
+                    .load_book_paces()
+                    .into_iter()
+                    .find(|(name, _)| *name == entry_name)
+                    .map(|(_, page_ms)| page_ms);
+                self.book_reader.pace = ReadingPace::with_page_ms(page_ms);
+                self.book_reader.clean_page = self.source.load_clean_page();
+                self.book_reader.chapter_progress = self.source.load_chapter_progress();
+                if !self.system.book_positions.contains_key(&entry_name)
+                    && !self.source.load_show_front_matter()
+                {
+                    self.book_reader.skip_front_matter();
+                }
                 self.current_entry = Some(entry_name.clone());
                 self.last_viewed_entry = Some(entry_name.clone());
                 self.system.mark_recent(entry_name);
                 log::info!("Opened book entry: {:?}", self.current_entry);
+                self.sample_heap("open_trbk");
                 self.set_state_book_viewing();
             }
             Err(err) => self.set_error(err),
         }
     }
 
+    /// Remembers the open book's zoom crop, dropping books without one.
+    fn save_book_crop(&mut self) {
+        let Some(entry_name) = self.current_entry.clone() else {
+            return;
+        };
+        let crop = self.book_reader.page_crop;
+        if self.book_reader.crop_cuts_margins(self.display_buffers.size()) {
+            log::warn!("Crop of {}px cuts into the margins of {}", crop, entry_name);
+        }
+        let mut crops = self.source.load_book_crops();
+        crops.retain(|(name, _)| *name != entry_name);
+        if crop > 0 {
+            crops.push((entry_name, crop));
+        }
+        self.source.save_book_crops(&crops);
+    }
+
+    /// Remembers the open book's reading pace, once one has been measured.
+    fn save_book_pace(&mut self) {
+        let Some(entry_name) = self.current_entry.clone() else {
+            return;
+        };
+        if !self.book_reader.has_book() {
+            return;
+        }
+        let Some(page_ms) = self.book_reader.pace.page_ms() else {
+            return;
+        };
+        let mut paces = self.source.load_book_paces();
+        if paces.iter().any(|(name, saved)| *name == entry_name && *saved == page_ms) {
+            return;
+        }
+        paces.retain(|(name, _)| *name != entry_name);
+        paces.push((entry_name, page_ms));
+        if paces.len() > MAX_BOOK_PACES {
+            paces.remove(0);
+        }
+        self.source.save_book_paces(&paces);
+    }
+
+    fn open_next_part(&mut self) {
+        let Some(next) = self.book_reader.next_part_name().map(String::from) else {
+            return;
+        };
+        let Some(index) = self.home.entries.iter().position(|entry| entry.name == next) else {
+            self.set_error(ImageError::Message(format!("{} not found.", next)));
+            return;
+        };
+        self.exit_book();
+        // Opened straight away, as the part starts from its first page.
+        if let Some(HomeOpen::OpenFile(entry)) = self.home.open_index(index) {
+            self.open_file_now(entry);
+        }
+        if self.book_reader.has_book() {
+            self.book_reader.front_matter_skipped = None;
+            self.book_reader.current_page = 0;
+            self.book_reader.current_page_ops = self.book_reader.load_page(self.source, 0).ok();
+        }
+    }
+
     fn open_image_entry(&mut self, entry: ImageEntry) {
         match self.image_viewer.open(self.source, &self.home.path, &entry) {
             Ok(()) => {
+                if self.state != AppState::Viewing {
+                    self.order_images();
+                }
                 let entry_name = self.home.entry_path_string(&entry);
                 self.current_entry = Some(entry_name.clone());
                 self.last_viewed_entry = Some(entry_name.clone());
@@ -514,12 +1127,36 @@ impl<'a, S: AppSource> Application<'a, S> {
         }
     }
 
+    /// Orders the folder's images for Left/Right in the viewer.
+    fn order_images(&mut self) {
+        let images = self
+            .home
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.kind == EntryKind::File && !is_trbk(&entry.name) && !is_epub(&entry.name)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let order = self.source.load_image_order();
+        self.image_viewer.set_order(
+            self.source,
+            &self.home.path,
+            &self.home.entries,
+            images,
+            order,
+            self.uptime_ms,
+        );
+    }
+
     fn exit_image(&mut self) {
         self.source.save_resume(None);
         self.system.save_recent_entries_now(self.source);
     }
 
     fn exit_book(&mut self) {
+        self.save_book_pace();
         self.system.update_book_position(
             &self.book_reader,
             self.current_entry.as_ref(),
@@ -530,6 +1167,14 @@ impl<'a, S: AppSource> Application<'a, S> {
         self.book_reader.close(self.source);
     }
 
+    /// Builds one missing thumbnail in the background once the user has not
+    /// pressed anything for a moment; a press stops it until the next pause.
+    fn warm_thumbnails(&mut self) {
+        if self.system.idle_ms >= WARM_IDLE_MS && !self.book_reader.has_book() {
+            self.home.warm_step(self.source);
+        }
+    }
+
     fn refresh_entries(&mut self) {
         match self.home.refresh_entries(self.source) {
             Ok(()) => {
@@ -561,11 +1206,418 @@ impl<'a, S: AppSource> Application<'a, S> {
         self.dirty = true;
     }
 
+    fn profile_name(&self) -> Option<String> {
+        if self.profiles.len() < 2 {
+            return None;
+        }
+        self.profiles
+            .get(self.active_profile)
+            .map(|profile| profile.name.clone())
+    }
+
+    fn switch_profile(&mut self, index: usize) {
+        log::info!("Switching to profile {}", index);
+        self.system.switch_profile(self.source, index);
+        self.active_profile = index;
+        self.last_viewed_entry = None;
+        self.refresh_tuning = self.source.load_refresh_tuning();
+        self.refresh_tuning_pending = true;
+        self.home.profile_name = self.profile_name();
+        self.home.simple_root = self.source.load_simple_mode();
+        self.lock_code = self.source.load_lock_code();
+        self.power_mode = self.source.load_power_button_mode();
+        self.power_press.reset();
+        self.home.start_menu_cache.clear();
+        self.home.start_menu_section = StartMenuSection::Recents;
+        self.home.start_menu_index = 0;
+        self.system.full_refresh = true;
+        self.set_state_start_menu(true);
+    }
+
+    fn open_recent(&mut self, path: &str) {
+        match self.home.open_recent_path(self.source, path) {
+            Ok(()) => {
+                let index = self.home.selected;
+                self.open_index(index);
+            }
+            Err(err) => {
+                if self.system.remove_recent(path) {
+                    if self.last_viewed_entry.as_deref() == Some(path) {
+                        self.last_viewed_entry = None;
+                    }
+                    self.system.save_recent_entries_now(self.source);
+                }
+                self.set_error(err);
+            }
+        }
+    }
+
+    /// The last few books on Home with their saved pages, and the thumbnails
+    /// Home has already loaded for them.
+    fn switcher_books(&self) -> Vec<SwitcherBook> {
+        self.recent_paths()
+            .into_iter()
+            .filter(|path| is_trbk(path))
+            .take(SWITCHER_BOOKS)
+            .map(|path| {
+                let preview = self
+                    .home
+                    .start_menu_cache
+                    .iter()
+                    .find(|preview| preview.path == path);
+                let title = match preview {
+                    Some(preview) => preview.title.clone(),
+                    None => String::from(path.rsplit('/').next().unwrap_or(&path)),
+                };
+                let image = preview
+                    .and_then(|preview| preview.image.as_ref())
+                    .and_then(thumbnail_to_mono);
+                let page = self.system.book_positions.get(&path).copied().unwrap_or(0);
+                SwitcherBook {
+                    path,
+                    title,
+                    page,
+                    image,
+                }
+            })
+            .collect()
+    }
+
+    /// The switcher only waits for a second Confirm when there are books to
+    /// switch between.
+    fn has_switcher(&self) -> bool {
+        self.recent_paths().iter().filter(|path| is_trbk(path)).count() > 1
+    }
+
+    fn open_switcher(&mut self) {
+        let books = self.switcher_books();
+        self.book_switcher.open(books);
+        self.state = AppState::Switcher;
+        self.dirty = true;
+    }
+
+    /// Recents and saved positions to show on Home, leaving out books outside
+    /// the simple mode folder.
+    fn recent_paths(&self) -> Vec<String> {
+        let mut recents = self.system.collect_recent_paths(self.last_viewed_entry.as_ref());
+        recents.retain(|path| self.home.in_root(path));
+        recents
+    }
+
+    /// Collects the presses of a new unlock code; Back gives up on it.
+    fn update_lock_setup(&mut self, buttons: &input::ButtonState) {
+        let Some(code) = self.lock_setup.as_mut() else {
+            return;
+        };
+        if buttons.is_pressed(input::Buttons::Back) {
+            self.lock_setup = None;
+            self.dirty = true;
+            return;
+        }
+        let Some(press) = code_press(buttons) else {
+            return;
+        };
+        code.push(press);
+        if code.len() == LOCK_CODE_LEN {
+            self.lock_code = core::mem::take(code);
+            self.lock_setup = None;
+            self.source.save_lock_code(&self.lock_code);
+            log::info!("Screen lock on");
+        }
+        self.tuning_press.reset();
+        self.dirty = true;
+    }
+
+    fn leave_simple_mode(&mut self) {
+        log::info!("Leaving simple mode");
+        self.simple_exit_ms = 0;
+        self.home.simple_root = None;
+        self.source.save_simple_mode(None);
+        self.home.start_menu_cache.clear();
+        self.set_state_settings();
+    }
+
+    /// Whether a USB host may be offered file access; not in simple mode or
+    /// while locked.
+    pub fn usb_allowed(&self) -> bool {
+        self.home.simple_root.is_none() && self.state != AppState::Locked
+    }
+
+    /// Whether the platform should delete macOS and Windows clutter from the
+    /// card now that USB access ended. Never in safe mode.
+    pub fn clean_system_files(&mut self) -> bool {
+        !self.system.safe_mode && self.source.load_clean_system_files()
+    }
+
+    /// Hides whatever is open behind the lock screen until the code is
+    /// entered.
+    fn lock(&mut self) {
+        self.lock_screen.reset();
+        self.state = AppState::Locked;
+        self.system.full_refresh = true;
+        self.dirty = true;
+    }
+
+    fn unlock(&mut self) {
+        log::info!("Unlocked");
+        if self.book_reader.current_book.is_some() {
+            self.set_state_book_viewing();
+        } else if self.image_viewer.has_image() {
+            self.system.wake_restore_only = false;
+            self.set_state_viewing();
+        } else {
+            self.set_state_start_menu(true);
+            self.refresh_entries();
+        }
+    }
+
+    /// Settings rows, with brightness and warmth sliders after the rest on
+    /// boards with a frontlight.
+    fn settings_rows(&self) -> usize {
+        match (self.frontlight_fitted, self.frontlight_warmth) {
+            (false, _) => SETTINGS_ROWS,
+            (true, false) => SETTINGS_ROWS + 1,
+            (true, true) => SETTINGS_ROWS + 2,
+        }
+    }
+
     fn set_state_settings(&mut self) {
+        self.sample_heap("settings");
+        self.heap_marks.log_summary();
+        self.reading_fonts = self.source.reading_fonts();
+        self.reading_font = self.source.load_reading_font();
+        self.reading_layout = self.source.load_reading_layout();
+        self.usb_host_count = self.source.load_usb_hosts().len();
+        self.show_front_matter = self.source.load_show_front_matter();
+        self.image_order = self.source.load_image_order();
+        self.clean_page = self.source.load_clean_page();
+        self.chapter_progress = self.source.load_chapter_progress();
+        self.lock_code = self.source.load_lock_code();
+        self.lock_setup = None;
+        self.clean_system_files = self.source.load_clean_system_files();
+        self.simple_folders = match self.source.refresh(&[]) {
+            Ok(entries) => entries
+                .into_iter()
+                .filter(|entry| entry.kind == EntryKind::Dir)
+                .map(|entry| entry.name)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        self.tuning_row = None;
+        self.tuning_press.reset();
         self.state = AppState::Settings;
         self.dirty = true;
     }
 
+    /// Redraws the idle page with a full refresh, after the deep clean when
+    /// it is turned on.
+    fn clean_ghosting(&mut self) {
+        log::info!("Idle: clearing ghosting");
+        self.book_reader.book_turns_since_full = 0;
+        self.deep_clean_pending = self.refresh_tuning.idle_deep_clean;
+        self.system.full_refresh = true;
+        self.dirty = true;
+    }
+
+    fn update_tuning(&mut self, buttons: &input::ButtonState) {
+        let Some(row) = self.tuning_row else {
+            return;
+        };
+        if buttons.is_pressed(input::Buttons::Back) || buttons.is_pressed(input::Buttons::Confirm) {
+            self.tuning_row = None;
+            self.tuning_press.reset();
+        } else if buttons.is_pressed(input::Buttons::Up) {
+            self.tuning_row = Some((row + TUNING_ROWS - 1) % TUNING_ROWS);
+        } else if buttons.is_pressed(input::Buttons::Down) {
+            self.tuning_row = Some((row + 1) % TUNING_ROWS);
+        } else if buttons.is_pressed(input::Buttons::Left) || buttons.is_pressed(input::Buttons::Right) {
+            let tuning = &mut self.refresh_tuning;
+            match row {
+                0 => tuning.gray_after_fast = !tuning.gray_after_fast,
+                1 => {
+                    let count = BorderMode::ALL.len();
+                    let current = BorderMode::ALL
+                        .iter()
+                        .position(|mode| *mode == tuning.border)
+                        .unwrap_or(0);
+                    let next = if buttons.is_pressed(input::Buttons::Right) {
+                        (current + 1) % count
+                    } else {
+                        (current + count - 1) % count
+                    };
+                    tuning.border = BorderMode::ALL[next];
+                }
+                2 => tuning.double_fast = !tuning.double_fast,
+                _ => tuning.idle_deep_clean = !tuning.idle_deep_clean,
+            }
+            self.source.save_refresh_tuning(self.refresh_tuning);
+            self.refresh_tuning_pending = true;
+        } else {
+            return;
+        }
+        self.dirty = true;
+    }
+
+    fn change_setting(&mut self, forward: bool) {
+        match self.settings_row {
+            0 => self.cycle_reading_font(forward),
+            3 => {
+                // Either direction forgets every remembered USB host.
+                if self.usb_host_count > 0 {
+                    self.source.save_usb_hosts(&[]);
+                    self.usb_host_count = 0;
+                    self.dirty = true;
+                }
+            }
+            4 => {
+                self.show_front_matter = !self.show_front_matter;
+                self.source.save_show_front_matter(self.show_front_matter);
+                self.dirty = true;
+            }
+            5 => {
+                let count = ImageOrder::ALL.len();
+                let current = ImageOrder::ALL
+                    .iter()
+                    .position(|order| *order == self.image_order)
+                    .unwrap_or(0);
+                let next = if forward {
+                    (current + 1) % count
+                } else {
+                    (current + count - 1) % count
+                };
+                self.image_order = ImageOrder::ALL[next];
+                self.source.save_image_order(self.image_order);
+                self.dirty = true;
+            }
+            6 => {
+                self.clean_page = !self.clean_page;
+                self.source.save_clean_page(self.clean_page);
+                self.dirty = true;
+            }
+            7 => {
+                self.chapter_progress = !self.chapter_progress;
+                self.source.save_chapter_progress(self.chapter_progress);
+                self.dirty = true;
+            }
+            10 => {
+                self.clean_system_files = !self.clean_system_files;
+                self.source.save_clean_system_files(self.clean_system_files);
+                self.dirty = true;
+            }
+            9 => {
+                // Turning the lock on asks for the new code first.
+                if self.lock_code.is_empty() {
+                    self.lock_setup = Some(Vec::new());
+                } else {
+                    self.lock_code.clear();
+                    self.source.save_lock_code(&[]);
+                }
+                self.dirty = true;
+            }
+            11 => {
+                let count = PowerButtonMode::ALL.len();
+                let current = PowerButtonMode::ALL
+                    .iter()
+                    .position(|mode| *mode == self.power_mode)
+                    .unwrap_or(0);
+                let next = if forward {
+                    (current + 1) % count
+                } else {
+                    (current + count - 1) % count
+                };
+                self.power_mode = PowerButtonMode::ALL[next];
+                self.power_press.reset();
+                self.source.save_power_button_mode(self.power_mode);
+                self.dirty = true;
+            }
+            12 | 13 => {
+                let value = if self.settings_row == 12 {
+                    &mut self.frontlight.brightness
+                } else {
+                    &mut self.frontlight.warmth
+                };
+                let next = if forward {
+                    (*value + 1).min(FRONTLIGHT_STEPS)
+                } else {
+                    value.saturating_sub(1)
+                };
+                if next == *value {
+                    return;
+                }
+                *value = next;
+                self.source.save_frontlight(self.frontlight);
+                self.frontlight_fade = Some(0);
+                self.dirty = true;
+            }
+            8 => {
+                if self.simple_folders.is_empty() {
+                    return;
+                }
+                // Position 0 is off, then one per top-level folder.
+                let count = self.simple_folders.len() + 1;
+                let current = self
+                    .home
+                    .simple_root
+                    .as_ref()
+                    .and_then(|root| self.simple_folders.iter().position(|folder| folder == root))
+                    .map_or(0, |index| index + 1);
+                let next = if forward {
+                    (current + 1) % count
+                } else {
+                    (current + count - 1) % count
+                };
+                self.home.simple_root =
+                    next.checked_sub(1).map(|index| self.simple_folders[index].clone());
+                self.source.save_simple_mode(self.home.simple_root.as_deref());
+                self.home.start_menu_cache.clear();
+                let on_settings = self.home.start_menu_section == StartMenuSection::Actions
+                    && self.home.start_menu_index == 1;
+                if self.home.simple_root.is_some() && on_settings {
+                    self.home.start_menu_index = 0;
+                }
+                self.dirty = true;
+            }
+            row => {
+                let (value, count) = if row == 1 {
+                    (&mut self.reading_layout.text_size, reflow::TEXT_SIZES.len())
+                } else {
+                    (&mut self.reading_layout.margins, reflow::MARGINS.len())
+                };
+                let current = *value as usize % count;
+                *value = if forward {
+                    (current + 1) % count
+                } else {
+                    (current + count - 1) % count
+                } as u8;
+                self.source.save_reading_layout(self.reading_layout);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Steps through the book's own font and the installed typefaces. The
+    /// choice applies to books opened afterwards.
+    fn cycle_reading_font(&mut self, forward: bool) {
+        if self.reading_fonts.is_empty() {
+            return;
+        }
+        // Position 0 is the book's own font, then one per typeface.
+        let count = self.reading_fonts.len() + 1;
+        let current = self
+            .reading_font
+            .as_ref()
+            .and_then(|name| self.reading_fonts.iter().position(|font| font == name))
+            .map_or(0, |index| index + 1);
+        let next = if forward {
+            (current + 1) % count
+        } else {
+            (current + count - 1) % count
+        };
+        self.reading_font = next.checked_sub(1).map(|index| self.reading_fonts[index].clone());
+        self.source.save_reading_font(self.reading_font.as_deref());
+        self.dirty = true;
+    }
+
     fn set_state_menu(&mut self) {
         self.state = AppState::Menu;
         self.dirty = true;
@@ -589,22 +1641,45 @@ impl<'a, S: AppSource> Application<'a, S> {
     }
 
     fn set_state_error_message(&mut self, message: String) {
+        self.flight.error(self.uptime_ms, &message);
         self.error_message = Some(message);
         self.state = AppState::Error;
+        self.flight.state(self.uptime_ms, self.state.label());
+        self.save_flight_log("error");
         self.dirty = true;
     }
 
+    /// Called by the platform after it recovered from a hang, e.g. a panel
+    /// that had to be reset or a restart by the watchdog. The lock screen
+    /// stays up; otherwise the message is shown until dismissed.
+    pub fn report_hang(&mut self, message: &str) {
+        if self.state == AppState::Locked {
+            self.flight.error(self.uptime_ms, message);
+            self.save_flight_log("hang");
+            self.dirty = true;
+            return;
+        }
+        self.set_state_error_message(message.into());
+    }
+
+    /// Writes the flight recorder to the card, e.g. to go with a bug report.
+    pub fn save_flight_log(&mut self, reason: &str) {
+        let text = self.flight.to_text(self.uptime_ms, reason);
+        self.source.save_flight_log(&text);
+    }
+
 
     fn draw_start_menu(&mut self, display: &mut impl crate::display::Display) {
-        let recents = self.system.collect_recent_paths(self.last_viewed_entry.as_ref());
+        let recents = self.recent_paths();
+        let loads_thumbnails = !self.home.start_menu_cache_same(&recents);
         let icons = HomeIcons {
-            icon_size: generated_icons::ICON_SIZE as i32,
-            folder_dark: generated_icons::ICON_FOLDER_DARK_MASK,
-            folder_light: generated_icons::ICON_FOLDER_LIGHT_MASK,
-            gear_dark: generated_icons::ICON_GEAR_DARK_MASK,
-            gear_light: generated_icons::ICON_GEAR_LIGHT_MASK,
-            battery_dark: generated_icons::ICON_BATTERY_DARK_MASK,
-            battery_light: generated_icons::ICON_BATTERY_LIGHT_MASK,
+            icon_size: icons::ICON_SIZE as i32,
+            folder_dark: icons::ICON_FOLDER_DARK_MASK,
+            folder_light: icons::ICON_FOLDER_LIGHT_MASK,
+            gear_dark: icons::ICON_GEAR_DARK_MASK,
+            gear_light: icons::ICON_GEAR_LIGHT_MASK,
+            battery_dark: icons::ICON_BATTERY_DARK_MASK,
+            battery_light: icons::ICON_BATTERY_LIGHT_MASK,
         };
         let mut ctx = HomeRenderContext {
             display_buffers: self.display_buffers,
@@ -617,19 +1692,22 @@ impl<'a, S: AppSource> Application<'a, S> {
             draw_trbk_image,
         };
         self.home.draw_start_menu(&mut ctx, display, &recents);
+        if loads_thumbnails {
+            self.sample_heap("thumbnail");
+        }
     }
 
 
 
     fn draw_menu(&mut self, display: &mut impl crate::display::Display) {
         let icons = HomeIcons {
-            icon_size: generated_icons::ICON_SIZE as i32,
-            folder_dark: generated_icons::ICON_FOLDER_DARK_MASK,
-            folder_light: generated_icons::ICON_FOLDER_LIGHT_MASK,
-            gear_dark: generated_icons::ICON_GEAR_DARK_MASK,
-            gear_light: generated_icons::ICON_GEAR_LIGHT_MASK,
-            battery_dark: generated_icons::ICON_BATTERY_DARK_MASK,
-            battery_light: generated_icons::ICON_BATTERY_LIGHT_MASK,
+            icon_size: icons::ICON_SIZE as i32,
+            folder_dark: icons::ICON_FOLDER_DARK_MASK,
+            folder_light: icons::ICON_FOLDER_LIGHT_MASK,
+            gear_dark: icons::ICON_GEAR_DARK_MASK,
+            gear_light: icons::ICON_GEAR_LIGHT_MASK,
+            battery_dark: icons::ICON_BATTERY_DARK_MASK,
+            battery_light: icons::ICON_BATTERY_LIGHT_MASK,
         };
         let mut ctx = HomeRenderContext {
             display_buffers: self.display_buffers,
@@ -652,19 +1730,28 @@ impl<'a, S: AppSource> Application<'a, S> {
         Text::new("Error", Point::new(LIST_MARGIN_X, HEADER_Y), header_style)
             .draw(self.display_buffers)
             .ok();
+        let size = self.display_buffers.size();
+        let mut hint_y = ERROR_LIST_TOP + 40;
         if let Some(message) = &self.error_message {
-            Text::new(message, Point::new(LIST_MARGIN_X, ERROR_LIST_TOP), header_style)
+            let max_chars = ((size.width as i32 - LIST_MARGIN_X * 2) / 10).max(1) as usize;
+            let (first, second) = wrap_two_lines(message, max_chars);
+            Text::new(first, Point::new(LIST_MARGIN_X, ERROR_LIST_TOP), header_style)
                 .draw(self.display_buffers)
                 .ok();
+            if let Some(second) = second {
+                Text::new(&second, Point::new(LIST_MARGIN_X, ERROR_LIST_TOP + 22), header_style)
+                    .draw(self.display_buffers)
+                    .ok();
+                hint_y += 22;
+            }
         }
         Text::new(
             "Press Back to return",
-            Point::new(LIST_MARGIN_X, ERROR_LIST_TOP + 40),
+            Point::new(LIST_MARGIN_X, hint_y),
             header_style,
         )
         .draw(self.display_buffers)
         .ok();
-        let size = self.display_buffers.size();
         let mut rq = RenderQueue::default();
         rq.push(
             Rect::new(0, 0, size.width as i32, size.height as i32),
@@ -674,16 +1761,41 @@ impl<'a, S: AppSource> Application<'a, S> {
     }
 
     fn draw_settings(&mut self, display: &mut impl crate::display::Display) {
+        if let Some(row) = self.tuning_row {
+            draw_tuning(self.display_buffers, display, self.refresh_tuning, row);
+            return;
+        }
         let mut ctx = SettingsContext {
             display_buffers: self.display_buffers,
             gray2_lsb: self.gray2_lsb.as_mut_slice(),
             gray2_msb: self.gray2_msb.as_mut_slice(),
-            logo_w: generated_icons::LOGO_WIDTH as i32,
-            logo_h: generated_icons::LOGO_HEIGHT as i32,
-            logo_dark: generated_icons::LOGO_DARK_MASK,
-            logo_light: generated_icons::LOGO_LIGHT_MASK,
+            logo_w: icons::LOGO_WIDTH as i32,
+            logo_h: icons::LOGO_HEIGHT as i32,
+            logo_dark: icons::LOGO_DARK_MASK,
+            logo_light: icons::LOGO_LIGHT_MASK,
             version: build_info::VERSION,
+            git_hash: build_info::GIT_HASH,
             build_time: build_info::BUILD_TIME,
+            heap: self.heap_marks.last(),
+            heap_marks: self.heap_marks.marks(),
+            safe_mode: self.system.safe_mode,
+            reading_font: self.reading_font.as_deref(),
+            reading_font_count: self.reading_fonts.len(),
+            reading_layout: self.reading_layout,
+            usb_host_count: self.usb_host_count,
+            show_front_matter: self.show_front_matter,
+            image_order: self.image_order,
+            clean_page: self.clean_page,
+            chapter_progress: self.chapter_progress,
+            simple_mode: self.home.simple_root.as_deref(),
+            simple_folder_count: self.simple_folders.len(),
+            screen_lock: !self.lock_code.is_empty(),
+            lock_setup: self.lock_setup.as_ref().map(Vec::len),
+            clean_system_files: self.clean_system_files,
+            power_button: self.power_mode,
+            frontlight: self.frontlight_fitted.then_some(self.frontlight),
+            frontlight_warmth: self.frontlight_warmth,
+            selected_row: self.settings_row,
         };
         draw_settings(&mut ctx, display);
     }
@@ -718,6 +1830,46 @@ impl<'a, S: AppSource> Application<'a, S> {
         display.display(self.display_buffers, RefreshMode::Full);
     }
 
+    /// USB modal for a transfer: status lines and, when the size is known,
+    /// a progress bar. Redraws while a transfer runs can use a fast refresh.
+    pub fn draw_usb_transfer(
+        &mut self,
+        display: &mut impl crate::display::Display,
+        title: &str,
+        lines: &[&str],
+        progress: Option<(u64, u64)>,
+        footer: &str,
+        refresh: RefreshMode,
+    ) {
+        self.display_buffers.clear(BinaryColor::On).ok();
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+        Text::new(title, Point::new(16, 24), style)
+            .draw(self.display_buffers)
+            .ok();
+        let mut y = 60;
+        for line in lines {
+            Text::new(line, Point::new(16, y), style)
+                .draw(self.display_buffers)
+                .ok();
+            y += 24;
+        }
+        let mut rq = RenderQueue::default();
+        if let Some((done, total)) = progress {
+            let width = self.display_buffers.size().width as i32 - 32;
+            let mut ui = UiContext {
+                buffers: self.display_buffers,
+            };
+            let mut bar = ProgressView::new(done, total);
+            bar.refresh = refresh;
+            bar.render(&mut ui, Rect::new(16, y - 8, width, 24), &mut rq);
+            y += 40;
+        }
+        Text::new(footer, Point::new(16, y + 8), style)
+            .draw(self.display_buffers)
+            .ok();
+        flush_queue(display, self.display_buffers, &mut rq, refresh);
+    }
+
 
     fn draw_image_viewer(&mut self, display: &mut impl crate::display::Display) {
         let mut ctx = ImageViewerContext {
@@ -741,12 +1893,25 @@ impl<'a, S: AppSource> Application<'a, S> {
             gray2_msb: self.gray2_msb.as_mut_slice(),
             source: self.source,
             full_refresh: &mut self.system.full_refresh,
+            refresh_tuning: self.refresh_tuning,
         };
         if let Err(err) = self.book_reader.draw_book(&mut ctx, display) {
             self.set_error(err);
         }
     }
 
+    fn erase_book_page_indicator(&mut self, display: &mut impl crate::display::Display) {
+        let mut ctx = BookReaderContext {
+            display_buffers: self.display_buffers,
+            gray2_lsb: self.gray2_lsb.as_mut_slice(),
+            gray2_msb: self.gray2_msb.as_mut_slice(),
+            source: self.source,
+            full_refresh: &mut self.system.full_refresh,
+            refresh_tuning: self.refresh_tuning,
+        };
+        self.book_reader.erase_page_indicator(&mut ctx, display);
+    }
+
     fn draw_toc_view(&mut self, display: &mut impl crate::display::Display) {
         let mut ctx = BookReaderContext {
             display_buffers: self.display_buffers,
@@ -754,6 +1919,7 @@ impl<'a, S: AppSource> Application<'a, S> {
             gray2_msb: self.gray2_msb.as_mut_slice(),
             source: self.source,
             full_refresh: &mut self.system.full_refresh,
+            refresh_tuning: self.refresh_tuning,
         };
         if let Err(err) = self.book_reader.draw_toc(&mut ctx, display) {
             self.set_error(err);
@@ -854,10 +2020,10 @@ impl<'a, S: AppSource> Application<'a, S> {
 
     fn draw_sleep_overlay(&mut self, display: &mut impl crate::display::Display) {
         let logo = SleepWallpaperIcons {
-            logo_w: generated_icons::LOGO_WIDTH as i32,
-            logo_h: generated_icons::LOGO_HEIGHT as i32,
-            logo_dark: generated_icons::LOGO_DARK_MASK,
-            logo_light: generated_icons::LOGO_LIGHT_MASK,
+            logo_w: icons::LOGO_WIDTH as i32,
+            logo_h: icons::LOGO_HEIGHT as i32,
+            logo_dark: icons::LOGO_DARK_MASK,
+            logo_light: icons::LOGO_LIGHT_MASK,
         };
         let is_start_menu = self.state == AppState::StartMenu;
         let last_viewed_entry = &self.last_viewed_entry;
@@ -870,6 +2036,7 @@ impl<'a, S: AppSource> Application<'a, S> {
             book_reader: &mut self.book_reader,
             last_viewed_entry,
             is_start_menu,
+            locked: !self.lock_code.is_empty(),
             logo,
         };
         self.system.process_sleep_overlay(&mut ctx, display);
@@ -897,29 +2064,123 @@ impl<'a, S: AppSource> Application<'a, S> {
                     self.error_message = None;
                     self.dirty = true;
                 }
-                self.open_file_entry(entry);
+                // Opened straight away to apply the page and, after USB, to
+                // tell whether the file is still there.
+                self.open_file_now(entry);
                 if let Some(page) = page {
-                    if let Some(book) = &self.book_reader.current_book {
-                        if page < book.page_count {
-                            self.book_reader.current_page = page;
-                            self.book_reader.current_page_ops =
-                                self.source.trbk_page(self.book_reader.current_page).ok();
-                            self.system.full_refresh = true;
-                            self.book_reader.book_turns_since_full = 0;
-                            self.dirty = true;
-                        }
+                    let page = self.book_reader.page_for_saved(page);
+                    let page_count = self.book_reader.current_book.as_ref().map(|book| book.page_count);
+                    if page_count.is_some_and(|count| page < count) {
+                        self.book_reader.current_page = page;
+                        self.book_reader.current_page_ops =
+                            self.book_reader.load_page(self.source, page).ok();
+                        self.system.full_refresh = true;
+                        self.book_reader.book_turns_since_full = 0;
+                        self.dirty = true;
                     }
                 }
             }
         }
     }
 
+    fn open_power_menu(&mut self) {
+        if matches!(
+            self.state,
+            AppState::PowerMenu | AppState::ExitingPending | AppState::Locked
+        ) {
+            return;
+        }
+        self.power_menu_return = self.state.clone();
+        self.power_menu.reset();
+        self.power_menu.hide_usb = self.home.simple_root.is_some();
+        self.state = AppState::PowerMenu;
+        self.dirty = true;
+    }
+
+    fn close_power_menu(&mut self) {
+        match self.power_menu_return.clone() {
+            AppState::Settings => {
+                self.state = AppState::Settings;
+                self.dirty = true;
+            }
+            AppState::Menu => self.set_state_menu(),
+            AppState::Viewing => self.set_state_viewing(),
+            AppState::BookViewing => self.set_state_book_viewing(),
+            AppState::Toc => self.set_state_toc(),
+            AppState::Error => {
+                self.state = AppState::Error;
+                self.dirty = true;
+            }
+            _ => self.set_state_start_menu(true),
+        }
+    }
+
+    fn save_resume(&mut self) -> Result<(), String> {
+        let resume_debug = format!(
+            "state={:?} current_entry={:?} last_viewed_entry={:?} path={:?} selected={} has_book={} current_page={} last_rendered={:?}",
+            self.state,
+            self.current_entry,
+            self.last_viewed_entry,
+            self.home.path,
+            self.home.selected,
+            self.book_reader.current_book.is_some(),
+            self.book_reader.current_page,
+            self.book_reader.last_rendered_page
+        );
+        self.system.save_resume_or_error(ResumeContext {
+            source: self.source,
+            resume_debug: &resume_debug,
+            in_start_menu: self.state == AppState::StartMenu,
+            current_entry: self.current_entry.as_ref(),
+            last_viewed_entry: self.last_viewed_entry.as_ref(),
+            home_current_entry: self.home.current_entry_name_owned(),
+            book_reader: &self.book_reader,
+        })
+    }
+
+    fn wake_up(&mut self) {
+        self.source.wake();
+        self.frontlight_fade = Some(FRONTLIGHT_FADE_MS);
+        let mut resumed_viewer = false;
+        let locked = !self.lock_code.is_empty();
+        if locked {
+            self.system.sleep_overlay = None;
+            self.lock();
+        } else if let Some(overlay) = self.system.sleep_overlay.take() {
+            SystemState::restore_rect_bits(self.display_buffers, &overlay);
+            if self.book_reader.current_book.is_some() {
+                self.set_state_book_viewing();
+                self.system.full_refresh = true;
+                self.system.wake_restore_only = false;
+            } else if self.image_viewer.has_image() {
+                self.set_state_viewing();
+                self.system.wake_restore_only = true;
+            } else {
+                self.set_state_start_menu(true);
+            }
+            resumed_viewer = true;
+        } else {
+            self.set_state_start_menu(true);
+        }
+        self.system.on_wake();
+        // The press that woke us must not count as a new short press.
+        self.power_press.reset();
+        self.dirty = true;
+        if !resumed_viewer && !locked {
+            self.refresh_entries();
+        }
+    }
+
     fn start_sleep_request(&mut self) {
         if self.state == AppState::Sleeping || self.state == AppState::SleepingPending {
             return;
         }
-        self.system.start_sleep_request(self.state == AppState::StartMenu);
+        self.system.start_sleep_request(matches!(
+            self.state,
+            AppState::StartMenu | AppState::Profiles | AppState::Switcher
+        ));
         self.state = AppState::SleepingPending;
+        self.frontlight_fade = Some(FRONTLIGHT_FADE_MS);
         self.dirty = true;
     }
 
@@ -1,2 +1 @@
-pub const VERSION: &str = env!("TRUSTY_VERSION");
-pub const BUILD_TIME: &str = env!("TRUSTY_BUILD_TIME");
+pub use tern_build_info::{BUILD_TIME, GIT_HASH, SEMVER, VERSION};
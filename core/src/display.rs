@@ -21,6 +21,57 @@ pub enum GrayscaleMode {
     Fast,
 }
 
+/// What the panel border does during a refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BorderMode {
+    /// Driven by the refresh waveform, like the rest of the panel.
+    #[default]
+    Waveform,
+    /// Held at VCOM, which flashes less but can leave a faint edge.
+    Vcom,
+    /// Left floating.
+    Floating,
+}
+
+impl BorderMode {
+    pub const ALL: [BorderMode; 3] = [BorderMode::Waveform, BorderMode::Vcom, BorderMode::Floating];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BorderMode::Waveform => "waveform",
+            BorderMode::Vcom => "VCOM",
+            BorderMode::Floating => "floating",
+        }
+    }
+}
+
+/// Refresh trade-offs between ghosting and flashing, which differ between
+/// panel batches. Set from the hidden tuning menu in Settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefreshTuning {
+    /// Follow a fast refresh of a page with grays by the differential
+    /// grayscale pass; off leaves such pages black and white.
+    pub gray_after_fast: bool,
+    pub border: BorderMode,
+    /// Run every fast refresh twice, which clears more ghosting but takes
+    /// twice as long.
+    pub double_fast: bool,
+    /// Drive the panel black and white before the full refresh that clears
+    /// ghosting from a page left idle.
+    pub idle_deep_clean: bool,
+}
+
+impl Default for RefreshTuning {
+    fn default() -> Self {
+        Self {
+            gray_after_fast: true,
+            border: BorderMode::Waveform,
+            double_fast: false,
+            idle_deep_clean: false,
+        }
+    }
+}
+
 pub trait Display {
     fn display(&mut self, buffers: &mut DisplayBuffers, mode: RefreshMode);
     fn copy_to_lsb(&mut self, buffers: &[u8; BUFFER_SIZE]);
@@ -28,4 +79,55 @@ pub trait Display {
     fn copy_grayscale_buffers(&mut self, lsb: &[u8; BUFFER_SIZE], msb: &[u8; BUFFER_SIZE]);
     fn display_differential_grayscale(&mut self, turn_off_screen: bool);
     fn display_absolute_grayscale(&mut self, mode: GrayscaleMode);
+    /// Applies panel settings; displays without such knobs ignore them.
+    fn set_refresh_tuning(&mut self, _tuning: RefreshTuning) {}
+    /// Flashes the whole panel black then white to shake out ghosting. What
+    /// was shown is lost, so the next refresh must be full.
+    fn deep_clean(&mut self) {}
+}
+
+/// Steps the frontlight brightness and warmth sliders go up in.
+pub const FRONTLIGHT_STEPS: u8 = 10;
+
+/// Frontlight setting, each from 0 to [`FRONTLIGHT_STEPS`]. Brightness 0 is
+/// off; warmth 0 is the coolest white.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontlightLevel {
+    pub brightness: u8,
+    pub warmth: u8,
 }
+
+impl FrontlightLevel {
+    pub const OFF: FrontlightLevel = FrontlightLevel {
+        brightness: 0,
+        warmth: 0,
+    };
+}
+
+impl Default for FrontlightLevel {
+    fn default() -> Self {
+        Self {
+            brightness: FRONTLIGHT_STEPS / 2,
+            warmth: 0,
+        }
+    }
+}
+
+/// Frontlight of boards that have one. The defaults describe a board
+/// without, which is what [`NoFrontlight`] stands in for.
+pub trait Frontlight {
+    fn has_frontlight(&self) -> bool {
+        false
+    }
+    /// Whether warmth can be set as well as brightness.
+    fn has_warmth(&self) -> bool {
+        false
+    }
+    /// Moves to `level` over `fade_ms`; 0 switches at once.
+    fn set_frontlight(&mut self, _level: FrontlightLevel, _fade_ms: u32) {}
+}
+
+/// Frontlight of boards without one, such as the X4.
+pub struct NoFrontlight;
+
+impl Frontlight for NoFrontlight {}
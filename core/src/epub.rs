@@ -0,0 +1,1105 @@
+//! Small EPUBs opened straight from the card.
+//!
+//! Converted books stay the fast path: their pages are laid out ahead of time
+//! with the book's own glyphs. A small, mostly text EPUB can be read here
+//! instead. The zip is inflated one spine document at a time, the XHTML is
+//! reduced to paragraphs of bold and italic runs, and those become the same
+//! [`TextItem`]s a v3 book carries, so [`crate::reflow`] lays them out in a
+//! font pack from `/fonts`. Images, tables and CSS are left out.
+//!
+//! Every item is its own source page, so a saved position is the paragraph
+//! it was in, whatever the text size.
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::image_viewer::ImageError;
+use crate::reflow::{ReadingLayout, TextItem, TextItemKind, TextRun};
+use crate::trbk::{
+    font_pack_typeface, TrbkBookInfo, TrbkFontPackHeader, TrbkGlyph, TrbkMetadata, TrbkTextInfo,
+    TrbkTocEntry,
+};
+
+/// Largest EPUB file opened on the device; bigger ones need converting.
+///
+/// The whole archive is held while one spine document is inflated beside it
+/// and the text read so far, and the text then stays next to the font pack
+/// glyphs, all inside the X4's heap of about 320 KB.
+pub const MAX_EPUB_BYTES: usize = 96 * 1024;
+/// Largest spine document once inflated, and largest text of a whole book.
+const MAX_DOCUMENT_BYTES: usize = 64 * 1024;
+const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+/// Advance of the regular `n` an EPUB is drawn at with the book text size,
+/// as tern-book's default.
+pub const EPUB_CHAR_WIDTH: u16 = 10;
+/// Margins an EPUB is laid out with when the reader keeps the book's own,
+/// as tern-book's defaults.
+const EPUB_MARGIN_X: u16 = 16;
+const EPUB_MARGIN_Y: u16 = 60;
+
+/// Shown instead of the book when no font pack is installed to draw it with.
+pub const NO_FONT_PACK: &str = "Add a font pack to /fonts to read EPUBs, or convert to .trbk.";
+
+/// Run styles, as the glyph styles of a font pack.
+const STYLE_BOLD: u8 = 1;
+const STYLE_ITALIC: u8 = 2;
+
+const LOCAL_SIGNATURE: u32 = 0x0403_4B50;
+const CENTRAL_SIGNATURE: u32 = 0x0201_4B50;
+const END_SIGNATURE: u32 = 0x0605_4B50;
+const CENTRAL_RECORD_LEN: usize = 46;
+const END_RECORD_LEN: usize = 22;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// An EPUB read into text items.
+#[derive(Clone, Debug)]
+pub struct EpubBook {
+    pub title: String,
+    pub author: String,
+    pub language: String,
+    pub identifier: String,
+    pub items: Vec<TextItem>,
+    /// Headings `h1` to `h3`, at the item they start.
+    pub toc: Vec<TrbkTocEntry>,
+}
+
+impl EpubBook {
+    /// The book as the reader shows it, drawn with the glyphs of a font pack
+    /// of `typeface`. It has no prerendered pages, so it is always reflowed.
+    pub fn book_info(&self, typeface: &str, glyphs: Vec<TrbkGlyph>) -> TrbkBookInfo {
+        let (char_width, line_height, ascent) = line_metrics(&glyphs);
+        TrbkBookInfo {
+            screen_width: crate::framebuffer::HEIGHT as u16,
+            screen_height: crate::framebuffer::WIDTH as u16,
+            page_count: 0,
+            metadata: TrbkMetadata {
+                title: self.title.clone(),
+                author: self.author.clone(),
+                language: self.language.clone(),
+                identifier: self.identifier.clone(),
+                font_name: typeface.to_string(),
+                char_width,
+                line_height,
+                ascent,
+                margin_left: EPUB_MARGIN_X,
+                margin_right: EPUB_MARGIN_X,
+                margin_top: EPUB_MARGIN_Y,
+                margin_bottom: EPUB_MARGIN_Y,
+                part: None,
+                series: None,
+                font_pack: None,
+            },
+            glyphs: Rc::new(glyphs),
+            toc: self.toc.clone(),
+            images: Vec::new(),
+            text: Some(TrbkTextInfo {
+                offset: 0,
+                item_count: self.items.len() as u32,
+            }),
+        }
+    }
+
+    /// Streams the items from `first` until `visit` returns false, as
+    /// [`crate::image_viewer::BookSource::trbk_text`] does for a book.
+    pub fn text(
+        &self,
+        first: usize,
+        visit: &mut dyn FnMut(usize, TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        for (index, item) in self.items.iter().enumerate().skip(first) {
+            if !visit(index, item.clone()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Of the installed packs, given as file name and header, the one to draw an
+/// EPUB with: the reading font's when it is installed, else the first
+/// typeface's, in the size closest to the reader's text size.
+pub fn pick_epub_font_pack<'a>(
+    packs: &'a [(String, TrbkFontPackHeader)],
+    reading_font: Option<&str>,
+    reading: ReadingLayout,
+) -> Option<&'a str> {
+    let installed = || packs.iter().filter_map(|(name, _)| font_pack_typeface(name));
+    let typeface = reading_font
+        .filter(|chosen| installed().any(|typeface| typeface == *chosen))
+        .or_else(|| installed().min())?;
+    let char_width = reading.text_width(EPUB_CHAR_WIDTH);
+    packs
+        .iter()
+        .filter(|(name, _)| font_pack_typeface(name) == Some(typeface))
+        .min_by_key(|(_, header)| header.char_width.abs_diff(char_width))
+        .map(|(name, _)| name.as_str())
+}
+
+/// Rejects an EPUB of `len` bytes before it is read if the device could not
+/// open it anyway.
+pub fn check_epub_size(len: usize) -> Result<(), ImageError> {
+    if len > MAX_EPUB_BYTES {
+        return Err(too_large());
+    }
+    Ok(())
+}
+
+/// Reads the text of the EPUB in `data`. Books too large for the device, or
+/// packed in a way this reader does not follow, should be converted instead.
+pub fn parse_epub(data: &[u8]) -> Result<EpubBook, ImageError> {
+    check_epub_size(data.len())?;
+    let entries = zip_entries(data)?;
+    let container = read_document(data, &entries, "META-INF/container.xml")?;
+    let package_path = Tokens::new(&container)
+        .find_map(|token| match token {
+            Token::Start {
+                name: "rootfile",
+                attrs,
+                ..
+            } => attribute(attrs, "full-path"),
+            _ => None,
+        })
+        .map(percent_decode)
+        .ok_or(ImageError::Decode)?;
+    let package = read_document(data, &entries, &package_path)?;
+    let base = package_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let package = parse_package(&package, base);
+
+    let mut builder = TextBuilder::default();
+    for path in &package.spine {
+        match read_document(data, &entries, path) {
+            Ok(document) => builder.push_document(&document)?,
+            Err(ImageError::Decode) => log::warn!("EPUB spine document {} not found", path),
+            Err(err) => return Err(err),
+        }
+    }
+    if !builder
+        .items
+        .iter()
+        .any(|item| matches!(item.kind, TextItemKind::Paragraph(_)))
+    {
+        return Err(ImageError::Message("EPUB has no text to show.".into()));
+    }
+    let [title, author, language, identifier] = package.fields;
+    Ok(EpubBook {
+        title,
+        author,
+        language,
+        identifier,
+        items: builder.items,
+        toc: builder.toc,
+    })
+}
+
+fn too_large() -> ImageError {
+    ImageError::Message("EPUB too large for device; convert it to .trbk.".into())
+}
+
+fn unsupported() -> ImageError {
+    ImageError::Message("EPUB packed in an unsupported way; convert it to .trbk.".into())
+}
+
+/// Char width, line height and ascent of text drawn with `glyphs`, worked
+/// out the way tern-book does from the font: the regular `n`, the full
+/// height plus a sixth, and the height of capitals.
+fn line_metrics(glyphs: &[TrbkGlyph]) -> (u16, u16, i16) {
+    let regular = || glyphs.iter().filter(|glyph| glyph.style == 0 && glyph.height > 0);
+    let char_width = regular()
+        .find(|glyph| glyph.codepoint == 'n' as u32)
+        .map_or(EPUB_CHAR_WIDTH, |glyph| glyph.x_advance.max(1) as u16);
+    let top = regular().map(|glyph| glyph.y_offset as i32).max().unwrap_or(0);
+    let bottom = regular()
+        .map(|glyph| glyph.height as i32 - glyph.y_offset as i32)
+        .max()
+        .unwrap_or(0);
+    let height = top + bottom;
+    if height <= 0 {
+        return (char_width, char_width * 2, char_width as i16 * 3 / 2);
+    }
+    let line_height = height + (height / 6).max(2);
+    let ascent = regular()
+        .filter(|glyph| char::from_u32(glyph.codepoint).is_some_and(|ch| ch.is_ascii_uppercase()))
+        .map(|glyph| glyph.y_offset as i32)
+        .max()
+        .unwrap_or(top);
+    (char_width, line_height as u16, ascent as i16)
+}
+
+/// A file in the zip's central directory.
+struct ZipEntry<'a> {
+    name: &'a [u8],
+    flags: u16,
+    method: u16,
+    compressed_len: usize,
+    len: usize,
+    local_offset: usize,
+}
+
+impl ZipEntry<'_> {
+    /// The file's contents, refused when they inflate past `limit`.
+    fn read(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, ImageError> {
+        // Encrypted, or sizes kept in a zip64 record.
+        if self.flags & 1 != 0 || self.compressed_len == u32::MAX as usize {
+            return Err(unsupported());
+        }
+        if self.len > limit {
+            return Err(too_large());
+        }
+        if read_u32(data, self.local_offset)? != LOCAL_SIGNATURE {
+            return Err(ImageError::Decode);
+        }
+        let name_len = read_u16(data, offset(self.local_offset, 26)?)? as usize;
+        let extra_len = read_u16(data, offset(self.local_offset, 28)?)? as usize;
+        let start = offset(self.local_offset, 30 + name_len + extra_len)?;
+        let body = data
+            .get(start..offset(start, self.compressed_len)?)
+            .ok_or(ImageError::Decode)?;
+        match self.method {
+            METHOD_STORED => Ok(body.to_vec()),
+            METHOD_DEFLATE => miniz_oxide::inflate::decompress_to_vec_with_limit(body, limit)
+                .map_err(|_| ImageError::Decode),
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry<'_>>, ImageError> {
+    let end = end_record(data).ok_or_else(|| {
+        ImageError::Message("Not an EPUB: the zip directory is missing.".into())
+    })?;
+    let count = read_u16(data, end + 10)? as usize;
+    let mut cursor = read_u32(data, end + 16)? as usize;
+    // The count is only trusted as far as the directory has room for it.
+    let room = data.len().saturating_sub(cursor) / CENTRAL_RECORD_LEN;
+    let mut entries = Vec::with_capacity(count.min(room));
+    for _ in 0..count {
+        if read_u32(data, cursor)? != CENTRAL_SIGNATURE {
+            return Err(ImageError::Decode);
+        }
+        let name_len = read_u16(data, cursor + 28)? as usize;
+        let extra_len = read_u16(data, cursor + 30)? as usize;
+        let comment_len = read_u16(data, cursor + 32)? as usize;
+        let name_start = cursor + CENTRAL_RECORD_LEN;
+        entries.push(ZipEntry {
+            name: data
+                .get(name_start..name_start + name_len)
+                .ok_or(ImageError::Decode)?,
+            flags: read_u16(data, cursor + 8)?,
+            method: read_u16(data, cursor + 10)?,
+            compressed_len: read_u32(data, cursor + 20)? as usize,
+            len: read_u32(data, cursor + 24)? as usize,
+            local_offset: read_u32(data, cursor + 42)? as usize,
+        });
+        cursor = offset(name_start, name_len + extra_len + comment_len)?;
+    }
+    Ok(entries)
+}
+
+/// Offset of the end of central directory record, which may be followed by
+/// a comment of up to 64 KiB.
+fn end_record(data: &[u8]) -> Option<usize> {
+    let last = data.len().checked_sub(END_RECORD_LEN)?;
+    let first = last.saturating_sub(u16::MAX as usize);
+    (first..=last)
+        .rev()
+        .find(|&offset| read_u32(data, offset).ok() == Some(END_SIGNATURE))
+}
+
+/// A text file of the EPUB by its path in the zip. Paths are matched
+/// without case when there is no exact match, as books get that wrong.
+fn read_document(data: &[u8], entries: &[ZipEntry<'_>], path: &str) -> Result<String, ImageError> {
+    let entry = entries
+        .iter()
+        .find(|entry| entry.name == path.as_bytes())
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|entry| entry.name.eq_ignore_ascii_case(path.as_bytes()))
+        })
+        .ok_or(ImageError::Decode)?;
+    let bytes = entry.read(data, MAX_DOCUMENT_BYTES)?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    };
+    Ok(match text.strip_prefix('\u{feff}') {
+        Some(text) => text.to_string(),
+        None => text,
+    })
+}
+
+/// What the reader takes from the OPF package document.
+struct Package {
+    /// Title, author, language and identifier.
+    fields: [String; 4],
+    /// Zip paths of the XHTML documents in reading order.
+    spine: Vec<String>,
+}
+
+const PACKAGE_FIELDS: [&str; 4] = ["title", "creator", "language", "identifier"];
+
+/// Reads the package document at `base`; the first of each metadata field
+/// is kept and spine items marked non-linear are left out.
+fn parse_package(package: &str, base: &str) -> Package {
+    let mut fields: [String; 4] = Default::default();
+    let mut field = None;
+    let mut manifest = Vec::new();
+    let mut spine = Vec::new();
+    for token in Tokens::new(package) {
+        match token {
+            Token::Start { name, attrs, empty } => {
+                field = PACKAGE_FIELDS
+                    .iter()
+                    .position(|candidate| *candidate == name)
+                    .filter(|&index| !empty && fields[index].is_empty());
+                match name {
+                    "item" => {
+                        let html = attribute(attrs, "media-type").is_some_and(|media| media.contains("html"));
+                        if let (Some(id), Some(href), true) = (attribute(attrs, "id"), attribute(attrs, "href"), html) {
+                            manifest.push((id, resolve(base, href)));
+                        }
+                    }
+                    "itemref" if attribute(attrs, "linear") != Some("no") => {
+                        if let Some(idref) = attribute(attrs, "idref") {
+                            spine.push(idref);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Token::End { .. } => field = None,
+            Token::Text(text) => {
+                if let Some(index) = field {
+                    push_decoded(&mut fields[index], text);
+                }
+            }
+        }
+    }
+    for field in &mut fields {
+        *field = field.trim().to_string();
+    }
+    let spine = spine
+        .into_iter()
+        .filter_map(|idref| manifest.iter().find(|(id, _)| *id == idref))
+        .map(|(_, path)| path.clone())
+        .collect();
+    Package { fields, spine }
+}
+
+/// Zip path of `href` as written in a document in directory `base`.
+fn resolve(base: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or_default());
+    let mut parts: Vec<&str> = if href.starts_with('/') {
+        Vec::new()
+    } else {
+        base.split('/').filter(|part| !part.is_empty()).collect()
+    };
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    if !text.contains('%') {
+        return text.to_string();
+    }
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = text
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                index += 3;
+            }
+            None => {
+                out.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| text.to_string())
+}
+
+/// Collects the paragraphs of the spine documents in order.
+#[derive(Default)]
+struct TextBuilder {
+    items: Vec<TextItem>,
+    toc: Vec<TrbkTocEntry>,
+    runs: Vec<TextRun>,
+    /// Level of the heading being read, or 0.
+    heading: u8,
+    bold: u32,
+    italic: u32,
+    /// Depth inside elements whose text is not shown.
+    hidden: u32,
+    in_body: bool,
+    /// Whitespace came after the last word, so the next one is set apart.
+    space: bool,
+    /// A line break ended the last run; the next text starts another.
+    new_run: bool,
+    text_len: usize,
+}
+
+impl TextBuilder {
+    /// Adds a spine document, starting it on a new page.
+    fn push_document(&mut self, document: &str) -> Result<(), ImageError> {
+        self.end_block();
+        if self.items.last().is_some_and(|item| !matches!(item.kind, TextItemKind::PageBreak)) {
+            self.push_item(0, TextItemKind::PageBreak);
+        }
+        self.in_body = false;
+        self.hidden = 0;
+        for token in Tokens::new(document) {
+            match token {
+                Token::Start { name, attrs: _, empty } => self.start(name, empty),
+                Token::End { name } => self.end(name),
+                Token::Text(text) if self.in_body && self.hidden == 0 => self.push_text(text),
+                Token::Text(_) => {}
+            }
+            if self.text_len > MAX_TEXT_BYTES {
+                return Err(too_large());
+            }
+        }
+        self.end_block();
+        Ok(())
+    }
+
+    fn start(&mut self, name: &str, empty: bool) {
+        let name = name.to_ascii_lowercase();
+        if is_hidden(&name) {
+            if !empty {
+                self.hidden += 1;
+            }
+            return;
+        }
+        match name.as_str() {
+            "body" => self.in_body = !empty,
+            "br" => {
+                self.append("\n");
+                self.space = false;
+                self.new_run = true;
+            }
+            "b" | "strong" if !empty => self.bold += 1,
+            "i" | "em" | "cite" | "dfn" | "var" if !empty => self.italic += 1,
+            _ => {
+                if let Some(level) = heading_level(&name) {
+                    self.end_block();
+                    self.heading = level;
+                } else if is_block(&name) {
+                    self.end_block();
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+        if is_hidden(&name) {
+            self.hidden = self.hidden.saturating_sub(1);
+            return;
+        }
+        match name.as_str() {
+            "body" => {
+                self.end_block();
+                self.in_body = false;
+            }
+            "b" | "strong" => self.bold = self.bold.saturating_sub(1),
+            "i" | "em" | "cite" | "dfn" | "var" => self.italic = self.italic.saturating_sub(1),
+            _ => {
+                if heading_level(&name).is_some() {
+                    self.end_block();
+                    self.heading = 0;
+                } else if is_block(&name) {
+                    self.end_block();
+                }
+            }
+        }
+    }
+
+    /// Adds text with entities decoded and whitespace collapsed.
+    fn push_text(&mut self, text: &str) {
+        let mut decoded = String::new();
+        push_decoded(&mut decoded, text);
+        let mut words = String::with_capacity(decoded.len());
+        for ch in decoded.chars() {
+            if ch == '\u{ad}' {
+                continue;
+            }
+            if ch.is_whitespace() && !matches!(ch, '\u{a0}' | '\u{2007}' | '\u{202f}') {
+                self.space = true;
+                continue;
+            }
+            if self.space && (!self.runs.is_empty() || !words.is_empty()) {
+                words.push(' ');
+            }
+            self.space = false;
+            words.push(ch);
+        }
+        if !words.is_empty() {
+            self.append(&words);
+        }
+    }
+
+    /// Appends to the last run when it has the current style.
+    fn append(&mut self, text: &str) {
+        self.text_len += text.len();
+        let style = self.style();
+        match self.runs.last_mut() {
+            Some(TextRun::Text { style: last, text: run }) if *last == style && !self.new_run => {
+                run.push_str(text);
+            }
+            _ => {
+                self.new_run = false;
+                self.runs.push(TextRun::Text {
+                    style,
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+
+    fn style(&self) -> u8 {
+        let bold = if self.bold > 0 { STYLE_BOLD } else { 0 };
+        let italic = if self.italic > 0 { STYLE_ITALIC } else { 0 };
+        bold | italic
+    }
+
+    /// Ends the paragraph being read; headings also go in the ToC.
+    fn end_block(&mut self) {
+        self.space = false;
+        self.new_run = false;
+        let runs = core::mem::take(&mut self.runs);
+        let mut title = String::new();
+        for run in &runs {
+            if let TextRun::Text { text, .. } = run {
+                for word in text.split_whitespace() {
+                    if !title.is_empty() {
+                        title.push(' ');
+                    }
+                    title.push_str(word);
+                }
+            }
+        }
+        if title.is_empty() {
+            return;
+        }
+        if (1..=3).contains(&self.heading) {
+            self.toc.push(TrbkTocEntry {
+                title,
+                page_index: self.items.len() as u32,
+                level: self.heading - 1,
+            });
+        }
+        self.push_item(self.heading, TextItemKind::Paragraph(runs));
+    }
+
+    fn push_item(&mut self, heading_level: u8, kind: TextItemKind) {
+        self.items.push(TextItem {
+            page: self.items.len() as u32,
+            heading_level,
+            kind,
+        });
+    }
+}
+
+/// Elements whose text is not part of the book.
+fn is_hidden(name: &str) -> bool {
+    matches!(name, "head" | "script" | "style" | "svg" | "math" | "noscript")
+}
+
+/// Elements that end the paragraph before them and start a new one.
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "section"
+            | "article"
+            | "aside"
+            | "header"
+            | "footer"
+            | "blockquote"
+            | "pre"
+            | "ul"
+            | "ol"
+            | "li"
+            | "dl"
+            | "dt"
+            | "dd"
+            | "table"
+            | "tr"
+            | "figure"
+            | "figcaption"
+            | "hr"
+    )
+}
+
+fn heading_level(name: &str) -> Option<u8> {
+    match name.as_bytes() {
+        [b'h', level @ b'1'..=b'6'] => Some(level - b'0'),
+        _ => None,
+    }
+}
+
+/// A piece of an XML or XHTML document. Names have their namespace prefix
+/// removed; comments, declarations and processing instructions are skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Start { name: &'a str, attrs: &'a str, empty: bool },
+    End { name: &'a str },
+    /// Text with entities still escaped.
+    Text(&'a str),
+}
+
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(document: &'a str) -> Self {
+        Self { rest: document }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            if !self.rest.starts_with('<') {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                let (text, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                return Some(Token::Text(text));
+            }
+            if let Some(body) = self.rest.strip_prefix("<!--") {
+                self.rest = body.find("-->").map_or("", |end| &body[end + 3..]);
+                continue;
+            }
+            if let Some(body) = self.rest.strip_prefix("<![CDATA[") {
+                let end = body.find("]]>").unwrap_or(body.len());
+                self.rest = body.get(end + 3..).unwrap_or_default();
+                return Some(Token::Text(&body[..end]));
+            }
+            let Some(end) = self.rest.find('>') else {
+                self.rest = "";
+                return None;
+            };
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[end + 1..];
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(Token::End {
+                    name: local_name(name.trim()),
+                });
+            }
+            let empty = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name_end = tag
+                .find(|ch: char| ch.is_ascii_whitespace())
+                .unwrap_or(tag.len());
+            return Some(Token::Start {
+                name: local_name(&tag[..name_end]),
+                attrs: &tag[name_end..],
+                empty,
+            });
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Value of attribute `key` in the attributes of a start tag, still escaped.
+fn attribute<'a>(attrs: &'a str, key: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    loop {
+        let equals = rest.find('=')?;
+        let name = rest[..equals].split_whitespace().last().unwrap_or_default();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|quote| matches!(quote, '"' | '\''))?;
+        let len = value[1..].find(quote)?;
+        if local_name(name).eq_ignore_ascii_case(key) {
+            return Some(&value[1..1 + len]);
+        }
+        rest = &value[len + 2..];
+    }
+}
+
+/// Appends `text` with character and common named entities decoded.
+/// Unknown entities are kept as written.
+fn push_decoded(out: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((ch, end)) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "ndash" => '\u{2013}',
+        "mdash" => '\u{2014}',
+        "lsquo" => '\u{2018}',
+        "rsquo" => '\u{2019}',
+        "ldquo" => '\u{201c}',
+        "rdquo" => '\u{201d}',
+        "hellip" => '\u{2026}',
+        _ => return None,
+    })
+}
+
+/// `base + len` for offsets read from the file, which may point anywhere.
+fn offset(base: usize, len: usize) -> Result<usize, ImageError> {
+    base.checked_add(len).ok_or(ImageError::Decode)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, ImageError> {
+    let bytes = data.get(at..offset(at, 2)?).ok_or(ImageError::Decode)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, ImageError> {
+    let bytes = data.get(at..offset(at, 4)?).ok_or(ImageError::Decode)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+    const PACKAGE: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <metadata>
+    <dc:title>Fish &amp; Chips</dc:title>
+    <dc:creator>A. Cook</dc:creator>
+    <dc:language>en</dc:language>
+    <dc:identifier>urn:test:1</dc:identifier>
+  </metadata>
+  <manifest>
+    <item id="one" href="Text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+    <item id="two" href="Text/two.xhtml" media-type="application/xhtml+xml"/>
+    <item id="css" href="style.css" media-type="text/css"/>
+  </manifest>
+  <spine><itemref idref="one"/><itemref idref="css"/><itemref idref="two" linear="no"/></spine>
+</package>"#;
+
+    const CHAPTER: &str = r#"<html><head><title>Hidden</title><style>p {}</style></head>
+<body><h1>Chapter <i>One</i></h1>
+<p>Salt  &amp; <b>vinegar</b>&#8230;</p><script>nope()</script>
+<p>Second<br/>line</p></body></html>"#;
+
+    /// A zip of `files`, each stored or deflated.
+    fn zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, contents, deflate) in files {
+            let body = if *deflate {
+                miniz_oxide::deflate::compress_to_vec(contents, 6)
+            } else {
+                contents.to_vec()
+            };
+            let method = if *deflate {
+                METHOD_DEFLATE
+            } else {
+                METHOD_STORED
+            };
+            let local_offset = out.len() as u32;
+            let header = |out: &mut Vec<u8>| {
+                out.extend_from_slice(&0u16.to_le_bytes());
+                out.extend_from_slice(&method.to_le_bytes());
+                out.extend_from_slice(&[0; 8]);
+                out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+                out.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+                out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                out.extend_from_slice(&0u16.to_le_bytes());
+            };
+            out.extend_from_slice(&LOCAL_SIGNATURE.to_le_bytes());
+            out.extend_from_slice(&20u16.to_le_bytes());
+            header(&mut out);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&body);
+
+            directory.extend_from_slice(&CENTRAL_SIGNATURE.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0]);
+            header(&mut directory);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&local_offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_offset = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(&END_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    fn epub(deflate: bool) -> Vec<u8> {
+        zip(&[
+            ("mimetype", b"application/epub+zip", false),
+            ("META-INF/container.xml", CONTAINER.as_bytes(), deflate),
+            ("OEBPS/content.opf", PACKAGE.as_bytes(), deflate),
+            ("OEBPS/Text/chapter 1.xhtml", CHAPTER.as_bytes(), deflate),
+        ])
+    }
+
+    /// The paragraphs of `book`, with `*` around bold runs.
+    fn paragraphs(book: &EpubBook) -> Vec<String> {
+        book.items
+            .iter()
+            .filter_map(|item| match &item.kind {
+                TextItemKind::Paragraph(runs) => Some(
+                    runs.iter()
+                        .map(|run| match run {
+                            TextRun::Text { style, text } if style & STYLE_BOLD != 0 => {
+                                alloc::format!("*{}*", text)
+                            }
+                            TextRun::Text { text, .. } => text.clone(),
+                            TextRun::Image { .. } => String::new(),
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn assert_sample_book(book: &EpubBook) {
+        assert_eq!(book.title, "Fish & Chips");
+        assert_eq!(book.author, "A. Cook");
+        assert_eq!(book.language, "en");
+        assert_eq!(book.identifier, "urn:test:1");
+        assert_eq!(
+            paragraphs(book),
+            // The space before a styled word starts its run.
+            vec!["Chapter One", "Salt &* vinegar*\u{2026}", "Second\nline"]
+        );
+        assert_eq!(book.toc.len(), 1);
+        assert_eq!(book.toc[0].title, "Chapter One");
+        assert_eq!(book.toc[0].level, 0);
+    }
+
+    #[test]
+    fn stored_epub_is_read() {
+        assert_sample_book(&parse_epub(&epub(false)).unwrap());
+    }
+
+    #[test]
+    fn deflated_epub_is_read() {
+        assert_sample_book(&parse_epub(&epub(true)).unwrap());
+    }
+
+    #[test]
+    fn truncated_epub_is_an_error_at_every_length() {
+        for deflate in [false, true] {
+            let data = epub(deflate);
+            for len in 0..data.len() {
+                assert!(parse_epub(&data[..len]).is_err(), "{} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn not_a_zip_is_an_error() {
+        assert!(matches!(
+            parse_epub(b"plain text, not a zip"),
+            Err(ImageError::Message(_))
+        ));
+    }
+
+    /// Offset of the central directory record of the file at `index`.
+    fn central_record(data: &[u8], index: usize) -> usize {
+        let end = end_record(data).unwrap();
+        let mut cursor = read_u32(data, end + 16).unwrap() as usize;
+        for _ in 0..index {
+            let lens: usize = [28, 30, 32]
+                .iter()
+                .map(|field| read_u16(data, cursor + field).unwrap() as usize)
+                .sum();
+            cursor += CENTRAL_RECORD_LEN + lens;
+        }
+        cursor
+    }
+
+    #[test]
+    fn entry_count_past_the_directory_is_an_error() {
+        let mut data = epub(false);
+        let end = end_record(&data).unwrap();
+        data[end + 10..end + 12].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(matches!(zip_entries(&data), Err(ImageError::Decode)));
+    }
+
+    #[test]
+    fn offsets_past_the_end_are_errors() {
+        let data = epub(false);
+        let record = central_record(&data, 2);
+        for (field, value) in [(42, u32::MAX), (42, u32::MAX - 20), (20, u32::MAX - 1)] {
+            let mut data = data.clone();
+            data[record + field..record + field + 4].copy_from_slice(&value.to_le_bytes());
+            let entries = zip_entries(&data).unwrap();
+            assert!(matches!(
+                entries[2].read(&data, MAX_DOCUMENT_BYTES),
+                Err(ImageError::Decode)
+            ));
+        }
+        let mut data = data.clone();
+        let end = end_record(&data).unwrap();
+        data[end + 16..end + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(zip_entries(&data), Err(ImageError::Decode)));
+    }
+
+    #[test]
+    fn oversized_and_encrypted_entries_are_refused() {
+        let data = epub(true);
+        let entries = zip_entries(&data).unwrap();
+        assert_eq!(entries[3].name, b"OEBPS/Text/chapter 1.xhtml");
+        assert!(matches!(
+            entries[3].read(&data, 16),
+            Err(ImageError::Message(_))
+        ));
+
+        let mut data = data.clone();
+        let record = central_record(&data, 3);
+        data[record + 8] |= 1;
+        let entries = zip_entries(&data).unwrap();
+        assert!(matches!(
+            entries[3].read(&data, MAX_DOCUMENT_BYTES),
+            Err(ImageError::Message(_))
+        ));
+    }
+
+    #[test]
+    fn tokens_skip_comments_and_declarations() {
+        let tokens: Vec<Token<'_>> =
+            Tokens::new("<?xml v?><!DOCTYPE x><a:p id='1'>t<!-- c -->u<br/><![CDATA[<x>]]></a:p>")
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Start {
+                    name: "p",
+                    attrs: " id='1'",
+                    empty: false
+                },
+                Token::Text("t"),
+                Token::Text("u"),
+                Token::Start {
+                    name: "br",
+                    attrs: "",
+                    empty: true
+                },
+                Token::Text("<x>"),
+                Token::End { name: "p" },
+            ]
+        );
+        assert_eq!(Tokens::new("text <unclosed").count(), 1);
+        assert_eq!(Tokens::new("<!-- never ends").count(), 0);
+    }
+
+    #[test]
+    fn attributes_are_found_by_local_name() {
+        let attrs = r#" id="a" xml:lang='en' opf:role="aut" bad=x"#;
+        assert_eq!(attribute(attrs, "id"), Some("a"));
+        assert_eq!(attribute(attrs, "lang"), Some("en"));
+        assert_eq!(attribute(attrs, "ROLE"), Some("aut"));
+        assert_eq!(attribute(attrs, "bad"), None);
+        assert_eq!(attribute(attrs, "missing"), None);
+        assert_eq!(attribute(r#" a="unterminated"#, "a"), None);
+    }
+
+    #[test]
+    fn entities_are_decoded_and_unknown_ones_kept() {
+        let mut out = String::new();
+        push_decoded(
+            &mut out,
+            "a &amp; b &#65;&#x42; &bogus; & &verylongentityname;",
+        );
+        assert_eq!(out, "a & b AB &bogus; & &verylongentityname;");
+        let mut out = String::new();
+        push_decoded(&mut out, "&#xD800;&");
+        assert_eq!(out, "&#xD800;&");
+    }
+
+    #[test]
+    fn hrefs_resolve_against_the_document() {
+        assert_eq!(
+            resolve("OEBPS/Text", "../Images/a.png"),
+            "OEBPS/Images/a.png"
+        );
+        assert_eq!(resolve("OEBPS", "./ch%201.xhtml#top"), "OEBPS/ch 1.xhtml");
+        assert_eq!(resolve("OEBPS", "/root.xhtml"), "root.xhtml");
+        assert_eq!(resolve("", "../../a.xhtml"), "a.xhtml");
+    }
+
+    #[test]
+    fn bad_percent_escapes_are_kept() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%C3%A9"), "\u{e9}");
+        // Escapes that are not UTF-8 leave the text as written.
+        assert_eq!(percent_decode("%FF"), "%FF");
+    }
+}
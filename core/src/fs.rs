@@ -59,6 +59,33 @@ pub trait Directory: ErrorType {
     fn list(&self) -> Result<Vec<Self::Entry>, Self::Error>;
 }
 
+/// Folders and files macOS and Windows leave on a card they mounted.
+const SYSTEM_CLUTTER: [&str; 6] = [
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".Trashes",
+    ".TemporaryItems",
+    ".DS_Store",
+    "System Volume Information",
+];
+
+/// Whether an entry is operating system clutter, including the `._` files
+/// macOS writes next to each file it copies. Never listed, and deleted when
+/// asked to.
+pub fn is_system_clutter(name: &str) -> bool {
+    name.starts_with("._")
+        || SYSTEM_CLUTTER
+            .iter()
+            .any(|clutter| name.eq_ignore_ascii_case(clutter))
+}
+
+/// FAT attribute bits, as returned by [`DirEntry::attributes`].
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+
 pub trait DirEntry {
     fn name(&self) -> &str;
     fn short_name(&self) -> &str {
@@ -66,4 +93,11 @@ pub trait DirEntry {
     }
     fn is_directory(&self) -> bool;
     fn size(&self) -> usize;
+    fn attributes(&self) -> u8 {
+        if self.is_directory() {
+            ATTR_DIRECTORY
+        } else {
+            0
+        }
+    }
 }
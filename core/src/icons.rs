@@ -0,0 +1,25 @@
+//! Icon masks rendered at build time from the SVGs in `core/icons`, drawn with
+//! [`crate::app::home::draw_icon_gray2`]. `icons/small` holds status glyphs at
+//! [`SMALL_ICON_SIZE`], looked up by file name with [`small_icon`].
+
+include!(concat!(env!("OUT_DIR"), "/icons.rs"));
+
+/// Dark and light masks of `icons/small/<name>.svg`.
+pub fn small_icon(name: &str) -> Option<(&'static [u8], &'static [u8])> {
+    SMALL_ICONS
+        .iter()
+        .find(|(icon, _, _)| *icon == name)
+        .map(|(_, dark, light)| (*dark, *light))
+}
+
+/// Name of the battery glyph for `percent`: `battery_0` (empty) to
+/// `battery_4` (full).
+pub fn battery_icon_name(percent: u8) -> &'static str {
+    match percent {
+        0..=9 => "battery_0",
+        10..=34 => "battery_1",
+        35..=59 => "battery_2",
+        60..=84 => "battery_3",
+        _ => "battery_4",
+    }
+}
@@ -40,6 +40,16 @@ pub enum ImageData {
     },
 }
 
+/// Details tern-image stores with a TRI image. Dates are packed as by
+/// [`crate::trbk::trimg_capture_date`].
+#[derive(Clone, Debug, Default)]
+pub struct ImageInfo {
+    pub source_name: Option<String>,
+    pub captured: Option<u32>,
+    pub converted: Option<u32>,
+    pub description: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub enum ImageError {
     Io,
@@ -51,6 +61,15 @@ pub enum ImageError {
 pub trait ImageSource {
     fn refresh(&mut self, path: &[String]) -> Result<Vec<ImageEntry>, ImageError>;
     fn load(&mut self, path: &[String], entry: &ImageEntry) -> Result<ImageData, ImageError>;
+    /// When the photo in a TRI image was taken, if tern-image found it in
+    /// the EXIF data; see [`crate::trbk::trimg_capture_date`].
+    fn capture_date(&mut self, _path: &[String], _entry: &ImageEntry) -> Option<u32> {
+        None
+    }
+    /// Header details and metadata section of a TRI image.
+    fn image_info(&mut self, _path: &[String], _entry: &ImageEntry) -> Option<ImageInfo> {
+        None
+    }
 }
 
 pub trait BookSource {
@@ -75,6 +94,20 @@ pub trait BookSource {
         Err(ImageError::Unsupported)
     }
     fn close_trbk(&mut self) {}
+    /// Streams the open book's text items from `first` until `visit`
+    /// returns false; only v3 books have them.
+    fn trbk_text(
+        &mut self,
+        _first: usize,
+        _visit: &mut dyn FnMut(usize, crate::reflow::TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        Err(ImageError::Unsupported)
+    }
+    /// Typefaces of the font packs installed in `/fonts`, for the reading
+    /// font setting.
+    fn reading_fonts(&mut self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 pub trait Gray2StreamSource {
@@ -137,6 +170,88 @@ pub trait PersistenceSource {
         None
     }
     fn save_thumbnail_title(&mut self, _key: &str, _title: &str) {}
+    /// Typeface books opened from now on are drawn in; `None` keeps their own.
+    fn save_reading_font(&mut self, _name: Option<&str>) {}
+    fn load_reading_font(&mut self) -> Option<String> {
+        None
+    }
+    /// Text size and margins reflowable books are laid out with.
+    fn save_reading_layout(&mut self, _layout: crate::reflow::ReadingLayout) {}
+    fn load_reading_layout(&mut self) -> crate::reflow::ReadingLayout {
+        crate::reflow::ReadingLayout::default()
+    }
+    /// Hosts remembered as always allowed USB file access.
+    fn save_usb_hosts(&mut self, _hosts: &[String]) {}
+    fn load_usb_hosts(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Zoom crop of prerendered pages by book entry path.
+    fn save_book_crops(&mut self, _crops: &[(String, u8)]) {}
+    fn load_book_crops(&mut self) -> Vec<(String, u8)> {
+        Vec::new()
+    }
+    /// Whether new books open at their first page rather than their first
+    /// chapter.
+    fn save_show_front_matter(&mut self, _show: bool) {}
+    fn load_show_front_matter(&mut self) -> bool {
+        false
+    }
+    fn save_refresh_tuning(&mut self, _tuning: crate::display::RefreshTuning) {}
+    fn load_refresh_tuning(&mut self) -> crate::display::RefreshTuning {
+        crate::display::RefreshTuning::default()
+    }
+    fn save_image_order(&mut self, _order: crate::app::image_viewer::ImageOrder) {}
+    fn load_image_order(&mut self) -> crate::app::image_viewer::ImageOrder {
+        crate::app::image_viewer::ImageOrder::default()
+    }
+    /// Whether books hide the page number except briefly after a turn.
+    fn save_clean_page(&mut self, _clean: bool) {}
+    fn load_clean_page(&mut self) -> bool {
+        false
+    }
+    /// Reading pace by book entry path, in milliseconds per page.
+    fn save_book_paces(&mut self, _paces: &[(String, u32)]) {}
+    fn load_book_paces(&mut self) -> Vec<(String, u32)> {
+        Vec::new()
+    }
+    /// Whether the page number also counts pages within the chapter.
+    fn save_chapter_progress(&mut self, _show: bool) {}
+    fn load_chapter_progress(&mut self) -> bool {
+        false
+    }
+    /// Folder the library is locked to in simple mode; `None` when off.
+    fn save_simple_mode(&mut self, _folder: Option<&str>) {}
+    fn load_simple_mode(&mut self) -> Option<String> {
+        None
+    }
+    /// Button presses that unlock the reader on wake; empty when off.
+    fn save_lock_code(&mut self, _code: &[u8]) {}
+    fn load_lock_code(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Whether macOS and Windows clutter is deleted after USB access.
+    fn save_clean_system_files(&mut self, _clean: bool) {}
+    fn load_clean_system_files(&mut self) -> bool {
+        false
+    }
+    fn save_frontlight(&mut self, _level: crate::display::FrontlightLevel) {}
+    fn load_frontlight(&mut self) -> crate::display::FrontlightLevel {
+        crate::display::FrontlightLevel::default()
+    }
+    /// What a Power press does while awake.
+    fn save_power_button_mode(&mut self, _mode: crate::app::power_menu::PowerButtonMode) {}
+    fn load_power_button_mode(&mut self) -> crate::app::power_menu::PowerButtonMode {
+        crate::app::power_menu::PowerButtonMode::default()
+    }
+    /// Profiles listed on the card; empty when there is just the one.
+    fn load_profiles(&mut self) -> Vec<crate::app::profiles::Profile> {
+        Vec::new()
+    }
+    fn active_profile(&mut self) -> usize {
+        0
+    }
+    /// Everything saved above is kept per profile; this switches all of it.
+    fn switch_profile(&mut self, _index: usize) {}
 }
 
 pub trait PowerSource {
@@ -144,12 +259,66 @@ pub trait PowerSource {
     fn wake(&mut self) {}
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapUsage {
+    pub used: usize,
+    pub free: usize,
+    pub peak: usize,
+}
+
+pub trait DiagnosticsSource {
+    fn heap_usage(&self) -> Option<HeapUsage> {
+        None
+    }
+    /// Microseconds on a monotonic clock, for timing page renders.
+    fn now_us(&self) -> Option<u64> {
+        None
+    }
+    /// Microseconds the last `trbk_page` spent reading storage; the rest of
+    /// its time went to parsing ops.
+    fn last_page_read_us(&self) -> Option<u32> {
+        None
+    }
+    /// Replaces the flight recorder log on the card.
+    fn save_flight_log(&mut self, _log: &str) {}
+}
+
+/// Reads a file ahead of the load that opens it, so a platform whose card
+/// reads block can await them between main loop passes instead.
+pub trait PrefetchSource {
+    /// Starts reading `entry` ahead of its load and returns whether the read
+    /// is pending. The application opens the entry once
+    /// [`PrefetchSource::prefetch_pending`] turns false.
+    fn prefetch(&mut self, _path: &[String], _entry: &ImageEntry) -> bool {
+        false
+    }
+    /// Whether the read started by [`PrefetchSource::prefetch`] is still
+    /// going. A failed read stops, and the load then reports the error.
+    fn prefetch_pending(&self) -> bool {
+        false
+    }
+    /// Drops a read whose entry is no longer being opened.
+    fn cancel_prefetch(&mut self) {}
+}
+
 pub trait AppSource:
-    ImageSource + BookSource + Gray2StreamSource + PersistenceSource + PowerSource
+    ImageSource
+    + BookSource
+    + Gray2StreamSource
+    + PersistenceSource
+    + PowerSource
+    + DiagnosticsSource
+    + PrefetchSource
 {
 }
 
 impl<T> AppSource for T where
-    T: ImageSource + BookSource + Gray2StreamSource + PersistenceSource + PowerSource
+    T: ImageSource
+        + BookSource
+        + Gray2StreamSource
+        + PersistenceSource
+        + PowerSource
+        + DiagnosticsSource
+        + PrefetchSource
 {
 }
@@ -49,3 +49,217 @@ impl ButtonState {
         (self.released() & mask) != 0
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PressKind {
+    Short,
+    Long,
+}
+
+/// Tells a short tap from a long hold on one button.
+///
+/// `Long` fires once while the button is still down; releasing after that
+/// reports nothing. A button already down when tracking starts (e.g. the press
+/// that woke the device) is ignored until it has been released once.
+#[derive(Clone, Copy)]
+pub struct LongPress {
+    button: Buttons,
+    threshold_ms: u32,
+    held_ms: u32,
+    tracking: bool,
+    fired: bool,
+    armed: bool,
+}
+
+impl LongPress {
+    pub const fn new(button: Buttons, threshold_ms: u32) -> Self {
+        Self {
+            button,
+            threshold_ms,
+            held_ms: 0,
+            tracking: false,
+            fired: false,
+            armed: false,
+        }
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    pub fn reset(&mut self) {
+        self.held_ms = 0;
+        self.tracking = false;
+        self.fired = false;
+        self.armed = false;
+    }
+
+    pub fn update(&mut self, buttons: &ButtonState, elapsed_ms: u32) -> Option<PressKind> {
+        let down = buttons.is_pressed(self.button) || buttons.is_held(self.button);
+        if !self.armed {
+            self.armed = !down;
+            return None;
+        }
+        if buttons.is_pressed(self.button) {
+            self.held_ms = 0;
+            self.tracking = true;
+            self.fired = false;
+            return None;
+        }
+        if !self.tracking {
+            return None;
+        }
+        if buttons.is_held(self.button) {
+            self.held_ms = self.held_ms.saturating_add(elapsed_ms);
+            if !self.fired && self.held_ms >= self.threshold_ms {
+                self.fired = true;
+                return Some(PressKind::Long);
+            }
+            return None;
+        }
+        let fired = self.fired;
+        self.tracking = false;
+        self.fired = false;
+        self.held_ms = 0;
+        if fired { None } else { Some(PressKind::Short) }
+    }
+}
+
+/// Reader tap zones and swipe distance, in touch panel coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TouchZones {
+    /// Taps left of this turn back.
+    pub back_before: u16,
+    /// Taps at or right of this turn forward; taps in between open the menu.
+    pub forward_from: u16,
+    /// Shortest movement counted as a swipe rather than a tap.
+    pub swipe_min: u16,
+    /// A touch held this long without moving is Back.
+    pub hold_ms: u32,
+}
+
+impl TouchZones {
+    /// Left, centre and right thirds of a panel `width` wide.
+    pub const fn thirds(width: u16) -> Self {
+        Self {
+            back_before: width / 3,
+            forward_from: width - width / 3,
+            swipe_min: width / 8,
+            hold_ms: 800,
+        }
+    }
+
+    fn is_swipe(&self, start: (u16, u16), end: (u16, u16)) -> bool {
+        let min = self.swipe_min;
+        start.0.abs_diff(end.0) >= min || start.1.abs_diff(end.1) >= min
+    }
+
+    /// The button a finished touch from `start` to `end` stands for.
+    pub fn gesture(&self, start: (u16, u16), end: (u16, u16)) -> Buttons {
+        if self.is_swipe(start, end) {
+            let dx = end.0 as i32 - start.0 as i32;
+            let dy = end.1 as i32 - start.1 as i32;
+            return if dx.abs() >= dy.abs() {
+                // Dragging the page left brings in the next one.
+                if dx < 0 { Buttons::Right } else { Buttons::Left }
+            } else if dy < 0 {
+                Buttons::Down
+            } else {
+                Buttons::Up
+            };
+        }
+        if start.0 < self.back_before {
+            Buttons::Left
+        } else if start.0 >= self.forward_from {
+            Buttons::Right
+        } else {
+            Buttons::Confirm
+        }
+    }
+}
+
+/// Turns touch points into the button bits `ButtonState::update` takes, so a
+/// touch panel can drive the application like the buttons do.
+///
+/// A gesture is reported as a single press once the finger lifts, except a
+/// hold, which reports Back while the finger is still down.
+#[derive(Clone, Copy)]
+pub struct TouchInput {
+    zones: TouchZones,
+    start: Option<(u16, u16)>,
+    last: (u16, u16),
+    held_ms: u32,
+    fired: bool,
+}
+
+impl TouchInput {
+    pub const fn new(zones: TouchZones) -> Self {
+        Self {
+            zones,
+            start: None,
+            last: (0, 0),
+            held_ms: 0,
+            fired: false,
+        }
+    }
+
+    pub fn set_zones(&mut self, zones: TouchZones) {
+        self.zones = zones;
+    }
+
+    /// Feeds the current touch point, `None` once lifted, and returns the
+    /// button bits for this frame.
+    pub fn update(&mut self, point: Option<(u16, u16)>, elapsed_ms: u32) -> u8 {
+        let Some(start) = self.start else {
+            if let Some(point) = point {
+                self.start = Some(point);
+                self.last = point;
+                self.held_ms = 0;
+                self.fired = false;
+            }
+            return 0;
+        };
+        if let Some(point) = point {
+            self.last = point;
+            self.held_ms = self.held_ms.saturating_add(elapsed_ms);
+            if !self.fired
+                && self.held_ms >= self.zones.hold_ms
+                && !self.zones.is_swipe(start, point)
+            {
+                self.fired = true;
+                return 1 << (Buttons::Back as u8);
+            }
+            return 0;
+        }
+        self.start = None;
+        if self.fired {
+            return 0;
+        }
+        1 << (self.zones.gesture(start, self.last) as u8)
+    }
+}
+
+/// What an accelerometer noticed since the last poll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotionEvent {
+    /// Held the normal way up, buttons below the screen.
+    Upright,
+    /// Turned round so the buttons are above the screen.
+    UpsideDown,
+    /// Laid screen down on a surface.
+    FaceDown,
+    DoubleTap,
+}
+
+/// Optional motion input for boards with an IMU. The default reports
+/// nothing, which is what boards without one get from `NoMotion`.
+pub trait MotionSensor {
+    fn poll_motion(&mut self) -> Option<MotionEvent> {
+        None
+    }
+}
+
+/// Motion input for boards without an accelerometer, such as the X4.
+pub struct NoMotion;
+
+impl MotionSensor for NoMotion {}
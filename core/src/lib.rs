@@ -4,10 +4,15 @@ pub mod application;
 pub mod app;
 pub mod build_info;
 pub mod display;
+pub mod epub;
 pub mod fs;
 pub mod framebuffer;
+pub mod icons;
 pub mod image_viewer;
 pub mod input;
+pub mod memory_source;
+pub mod persistence;
+pub mod reflow;
 pub mod ui;
 pub mod trbk;
 pub mod test_image;
@@ -0,0 +1,609 @@
+//! Card held in memory, for driving [`crate::application::Application`]
+//! without a device or a directory on disk.
+//!
+//! Files are keyed by their path from the card root (`books/a.trbk`) and
+//! folders exist as long as something is stored under them. State, recents
+//! and profiles go through the same files the device writes, so a flow can
+//! sleep, wake or hand the card to a USB host and see what would have been
+//! saved. Errors can be scripted per file, and [`canned_trbk`] makes books
+//! to open.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::app::profiles::{read_profiles, Profile};
+use crate::fs::is_system_clutter;
+use crate::image_viewer::{
+    BookSource, DiagnosticsSource, EntryKind, Gray2StreamSource, ImageData, ImageEntry,
+    ImageError, ImageSource, PersistenceSource, PowerSource, PrefetchSource,
+};
+use crate::persistence::{StateStorage, StateStore};
+use crate::trbk::TrbkBook;
+
+/// Files on the card, which also hold the state blob.
+#[derive(Default)]
+struct Card {
+    files: BTreeMap<String, Vec<u8>>,
+    /// Writes are refused, as on a full or write-protected card.
+    read_only: bool,
+}
+
+impl StateStorage for Card {
+    fn read_state_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.files.get(name).cloned()
+    }
+
+    fn write_state_file(&mut self, name: &str, data: &[u8]) -> bool {
+        if self.read_only {
+            return false;
+        }
+        self.files.insert(name.to_string(), data.to_vec());
+        true
+    }
+}
+
+/// The open book, as parsed from its file.
+struct OpenBook {
+    book: TrbkBook,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct MemoryImageSource {
+    card: Card,
+    /// Errors the next access to a path returns instead of its file.
+    errors: Vec<(String, ImageError)>,
+    book: Option<OpenBook>,
+    state: StateStore,
+    thumbnails: BTreeMap<String, ImageData>,
+    thumbnail_titles: BTreeMap<String, String>,
+    sleeps: usize,
+    wakes: usize,
+    /// Whether opens wait for [`MemoryImageSource::finish_read_ahead`].
+    read_ahead: bool,
+    /// File being read ahead.
+    prefetching: Option<String>,
+}
+
+impl MemoryImageSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `data` at `path`, replacing what was there.
+    pub fn insert(&mut self, path: &str, data: Vec<u8>) {
+        self.card.files.insert(path.to_string(), data);
+    }
+
+    /// Takes the file at `path` off the card, as a USB host deleting it.
+    pub fn remove(&mut self, path: &str) -> Option<Vec<u8>> {
+        self.card.files.remove(path)
+    }
+
+    pub fn file(&self, path: &str) -> Option<&[u8]> {
+        self.card.files.get(path).map(|data| data.as_slice())
+    }
+
+    /// Makes the next load or open of `path` fail with `error`. Errors for
+    /// the same path are returned in the order they were scripted.
+    pub fn fail_next(&mut self, path: &str, error: ImageError) {
+        self.errors.push((path.to_string(), error));
+    }
+
+    /// Refuses every write to the card until called with `false`.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.card.read_only = read_only;
+    }
+
+    /// Whether a book is open between `open_trbk` and `close_trbk`.
+    pub fn book_open(&self) -> bool {
+        self.book.is_some()
+    }
+
+    pub fn sleeps(&self) -> usize {
+        self.sleeps
+    }
+
+    pub fn wakes(&self) -> usize {
+        self.wakes
+    }
+
+    /// Makes every open of a file wait for its read ahead, as on a device
+    /// that reads the card between main loop passes.
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.read_ahead = read_ahead;
+    }
+
+    /// Completes the pending read ahead and returns the file it was for.
+    pub fn finish_read_ahead(&mut self) -> Option<String> {
+        self.prefetching.take()
+    }
+
+    fn is_supported(name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        name.ends_with(".trimg")
+            || name.ends_with(".tri")
+            || name.ends_with(".trbk")
+            || name.ends_with(".tbk")
+            || name.ends_with(".epub")
+            || name.ends_with(".epb")
+    }
+
+    fn build_path(path: &[String], name: &str) -> String {
+        let mut parts = path.to_vec();
+        parts.push(name.to_string());
+        parts.join("/")
+    }
+
+    /// The file for `entry` in folder `path`, or the error scripted for it.
+    fn read(&mut self, path: &[String], entry: &ImageEntry) -> Result<&[u8], ImageError> {
+        if entry.kind != EntryKind::File {
+            return Err(ImageError::Unsupported);
+        }
+        let file_path = Self::build_path(path, &entry.name);
+        if let Some(index) = self.errors.iter().position(|(path, _)| *path == file_path) {
+            return Err(self.errors.remove(index).1);
+        }
+        self.card
+            .files
+            .get(&file_path)
+            .map(|data| data.as_slice())
+            .ok_or(ImageError::Io)
+    }
+
+    fn ensure_state(&mut self) {
+        if !self.state.is_loaded() {
+            self.state.load(&mut self.card);
+        }
+    }
+
+    fn save_state(&mut self) {
+        self.state.save(&mut self.card);
+    }
+}
+
+impl ImageSource for MemoryImageSource {
+    fn refresh(&mut self, path: &[String]) -> Result<Vec<ImageEntry>, ImageError> {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path.join("/"))
+        };
+        let mut entries: Vec<ImageEntry> = Vec::new();
+        for file_path in self.card.files.keys() {
+            let Some(rest) = file_path.strip_prefix(prefix.as_str()) else {
+                continue;
+            };
+            let (name, kind) = match rest.split_once('/') {
+                Some((dir, _)) => (dir, EntryKind::Dir),
+                None => (rest, EntryKind::File),
+            };
+            if is_system_clutter(name) || (kind == EntryKind::File && !Self::is_supported(name)) {
+                continue;
+            }
+            if entries.last().is_some_and(|last| last.name == name) {
+                continue;
+            }
+            entries.push(ImageEntry {
+                name: name.to_string(),
+                kind,
+            });
+        }
+        entries.sort_by(|a, b| match (a.kind, b.kind) {
+            (EntryKind::Dir, EntryKind::File) => core::cmp::Ordering::Less,
+            (EntryKind::File, EntryKind::Dir) => core::cmp::Ordering::Greater,
+            _ => a.name.cmp(&b.name),
+        });
+        Ok(entries)
+    }
+
+    fn load(&mut self, path: &[String], entry: &ImageEntry) -> Result<ImageData, ImageError> {
+        let lower = entry.name.to_ascii_lowercase();
+        let data = self.read(path, entry)?;
+        if lower.ends_with(".trimg") || lower.ends_with(".tri") {
+            return crate::trbk::parse_trimg(data);
+        }
+        Err(ImageError::Unsupported)
+    }
+}
+
+impl BookSource for MemoryImageSource {
+    fn load_trbk(&mut self, path: &[String], entry: &ImageEntry) -> Result<TrbkBook, ImageError> {
+        crate::trbk::parse_trbk(self.read(path, entry)?)
+    }
+
+    fn open_trbk(
+        &mut self,
+        path: &[String],
+        entry: &ImageEntry,
+    ) -> Result<Rc<crate::trbk::TrbkBookInfo>, ImageError> {
+        self.book = None;
+        let data = self.read(path, entry)?.to_vec();
+        let book = crate::trbk::parse_trbk(&data)?;
+        let info = Rc::new(book.info());
+        self.book = Some(OpenBook { book, data });
+        Ok(info)
+    }
+
+    fn trbk_page(&mut self, page_index: usize) -> Result<crate::trbk::TrbkPage, ImageError> {
+        let Some(open) = &self.book else {
+            return Err(ImageError::Decode);
+        };
+        open.book
+            .pages
+            .get(page_index)
+            .cloned()
+            .ok_or(ImageError::Decode)
+    }
+
+    fn trbk_image(&mut self, image_index: usize) -> Result<ImageData, ImageError> {
+        let Some(open) = &self.book else {
+            return Err(ImageError::Decode);
+        };
+        let image = open.book.images.get(image_index).ok_or(ImageError::Decode)?;
+        let start = image.data_offset as usize;
+        let end = start + image.data_len as usize;
+        if end > open.data.len() {
+            return Err(ImageError::Decode);
+        }
+        crate::trbk::parse_trimg(&open.data[start..end])
+    }
+
+    fn close_trbk(&mut self) {
+        self.book = None;
+    }
+
+    fn trbk_text(
+        &mut self,
+        first: usize,
+        visit: &mut dyn FnMut(usize, crate::reflow::TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        let Some(open) = &self.book else {
+            return Err(ImageError::Unsupported);
+        };
+        let Some(text) = open.book.text else {
+            return Err(ImageError::Unsupported);
+        };
+        crate::reflow::read_text_items(&open.data, text, first, visit)
+    }
+}
+
+impl Gray2StreamSource for MemoryImageSource {}
+
+impl PersistenceSource for MemoryImageSource {
+    fn save_resume(&mut self, name: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().resume = name.map(|name| name.to_string());
+        self.save_state();
+    }
+
+    fn load_resume(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().resume.clone()
+    }
+
+    fn save_book_positions(&mut self, entries: &[(String, usize)]) {
+        self.ensure_state();
+        self.state.state_mut().book_positions = entries.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_positions(&mut self) -> Vec<(String, usize)> {
+        self.ensure_state();
+        self.state.state().book_positions.clone()
+    }
+
+    fn save_recent_entries(&mut self, entries: &[String]) {
+        self.ensure_state();
+        self.state.state_mut().recent_entries = entries.to_vec();
+        self.save_state();
+    }
+
+    fn load_recent_entries(&mut self) -> Vec<String> {
+        self.ensure_state();
+        self.state.state().recent_entries.clone()
+    }
+
+    fn load_thumbnail(&mut self, key: &str) -> Option<ImageData> {
+        self.thumbnails.get(key).cloned()
+    }
+
+    fn save_thumbnail(&mut self, key: &str, image: &ImageData) {
+        if !self.card.read_only {
+            self.thumbnails.insert(key.to_string(), image.clone());
+        }
+    }
+
+    fn load_thumbnail_title(&mut self, key: &str) -> Option<String> {
+        self.thumbnail_titles.get(key).cloned()
+    }
+
+    fn save_thumbnail_title(&mut self, key: &str, title: &str) {
+        if !self.card.read_only {
+            self.thumbnail_titles.insert(key.to_string(), title.to_string());
+        }
+    }
+
+    fn save_reading_font(&mut self, name: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().reading_font = name.map(|name| name.to_string());
+        self.save_state();
+    }
+
+    fn load_reading_font(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().reading_font.clone()
+    }
+
+    fn save_reading_layout(&mut self, layout: crate::reflow::ReadingLayout) {
+        self.ensure_state();
+        self.state.state_mut().reading_layout = layout;
+        self.save_state();
+    }
+
+    fn load_reading_layout(&mut self) -> crate::reflow::ReadingLayout {
+        self.ensure_state();
+        self.state.state().reading_layout
+    }
+
+    fn save_usb_hosts(&mut self, hosts: &[String]) {
+        self.ensure_state();
+        self.state.state_mut().usb_hosts = hosts.to_vec();
+        self.save_state();
+    }
+
+    fn load_usb_hosts(&mut self) -> Vec<String> {
+        self.ensure_state();
+        self.state.state().usb_hosts.clone()
+    }
+
+    fn save_book_crops(&mut self, crops: &[(String, u8)]) {
+        self.ensure_state();
+        self.state.state_mut().book_crops = crops.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_crops(&mut self) -> Vec<(String, u8)> {
+        self.ensure_state();
+        self.state.state().book_crops.clone()
+    }
+
+    fn save_show_front_matter(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().show_front_matter = show;
+        self.save_state();
+    }
+
+    fn load_show_front_matter(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().show_front_matter
+    }
+
+    fn save_refresh_tuning(&mut self, tuning: crate::display::RefreshTuning) {
+        self.ensure_state();
+        self.state.state_mut().refresh_tuning = tuning;
+        self.save_state();
+    }
+
+    fn load_refresh_tuning(&mut self) -> crate::display::RefreshTuning {
+        self.ensure_state();
+        self.state.state().refresh_tuning
+    }
+
+    fn save_image_order(&mut self, order: crate::app::image_viewer::ImageOrder) {
+        self.ensure_state();
+        self.state.state_mut().image_order = order;
+        self.save_state();
+    }
+
+    fn load_image_order(&mut self) -> crate::app::image_viewer::ImageOrder {
+        self.ensure_state();
+        self.state.state().image_order
+    }
+
+    fn save_clean_page(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_page = clean;
+        self.save_state();
+    }
+
+    fn load_clean_page(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_page
+    }
+
+    fn save_book_paces(&mut self, paces: &[(String, u32)]) {
+        self.ensure_state();
+        self.state.state_mut().book_paces = paces.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_paces(&mut self) -> Vec<(String, u32)> {
+        self.ensure_state();
+        self.state.state().book_paces.clone()
+    }
+
+    fn save_chapter_progress(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().chapter_progress = show;
+        self.save_state();
+    }
+
+    fn load_chapter_progress(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().chapter_progress
+    }
+
+    fn save_simple_mode(&mut self, folder: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().simple_mode = folder.map(|folder| folder.to_string());
+        self.save_state();
+    }
+
+    fn load_simple_mode(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().simple_mode.clone()
+    }
+
+    fn save_lock_code(&mut self, code: &[u8]) {
+        self.ensure_state();
+        self.state.state_mut().lock_code = code.to_vec();
+        self.save_state();
+    }
+
+    fn load_lock_code(&mut self) -> Vec<u8> {
+        self.ensure_state();
+        self.state.state().lock_code.clone()
+    }
+
+    fn save_clean_system_files(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_system_files = clean;
+        self.save_state();
+    }
+
+    fn load_clean_system_files(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_system_files
+    }
+
+    fn save_frontlight(&mut self, level: crate::display::FrontlightLevel) {
+        self.ensure_state();
+        self.state.state_mut().frontlight = level;
+        self.save_state();
+    }
+
+    fn load_frontlight(&mut self) -> crate::display::FrontlightLevel {
+        self.ensure_state();
+        self.state.state().frontlight
+    }
+
+    fn save_power_button_mode(&mut self, mode: crate::app::power_menu::PowerButtonMode) {
+        self.ensure_state();
+        self.state.state_mut().power_button = mode;
+        self.save_state();
+    }
+
+    fn load_power_button_mode(&mut self) -> crate::app::power_menu::PowerButtonMode {
+        self.ensure_state();
+        self.state.state().power_button
+    }
+
+    fn load_profiles(&mut self) -> Vec<Profile> {
+        read_profiles(&mut self.card)
+    }
+
+    fn active_profile(&mut self) -> usize {
+        self.ensure_state();
+        self.state.profile() as usize
+    }
+
+    fn switch_profile(&mut self, index: usize) {
+        self.state.set_profile(&mut self.card, index as u8);
+        self.ensure_state();
+    }
+}
+
+impl PowerSource for MemoryImageSource {
+    fn sleep(&mut self) {
+        self.sleeps += 1;
+    }
+
+    fn wake(&mut self) {
+        self.wakes += 1;
+    }
+}
+
+impl DiagnosticsSource for MemoryImageSource {}
+
+impl PrefetchSource for MemoryImageSource {
+    fn prefetch(&mut self, path: &[String], entry: &ImageEntry) -> bool {
+        let file_path = Self::build_path(path, &entry.name);
+        self.prefetching = (self.read_ahead && self.card.files.contains_key(&file_path))
+            .then_some(file_path);
+        self.prefetching.is_some()
+    }
+
+    fn prefetch_pending(&self) -> bool {
+        self.prefetching.is_some()
+    }
+
+    fn cancel_prefetch(&mut self) {
+        self.prefetching = None;
+    }
+}
+
+/// A v2 book of `page_count` prerendered pages, each a line of text saying
+/// which page it is, with a ToC entry for every chapter title in `chapters`
+/// spread evenly over the pages. It carries no glyphs, so pages draw blank,
+/// but it opens, pages and saves its position like a converted book.
+pub fn canned_trbk(title: &str, chapters: &[&str], page_count: usize) -> Vec<u8> {
+    const FIXED_HEADER: usize = 0x30;
+    fn push_string(out: &mut Vec<u8>, value: &str) {
+        out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    let mut metadata = Vec::new();
+    for value in [title, "Tern", "en", title, "Canned"] {
+        push_string(&mut metadata, value);
+    }
+    // Char width, line height, ascent and the four margins.
+    for value in [10u16, 20, 15, 16, 16, 60, 60] {
+        metadata.extend_from_slice(&value.to_le_bytes());
+    }
+    let header_size = FIXED_HEADER + metadata.len();
+
+    let mut toc = Vec::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        push_string(&mut toc, chapter);
+        let page = index * page_count / chapters.len();
+        toc.extend_from_slice(&(page as u32).to_le_bytes());
+        toc.extend_from_slice(&[0, 0, 0, 0]);
+    }
+
+    let mut lut = Vec::new();
+    let mut pages = Vec::new();
+    for page in 0..page_count {
+        lut.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+        let text = format!("Page {} of {}", page + 1, page_count);
+        pages.push(0x01);
+        pages.extend_from_slice(&((6 + text.len()) as u16).to_le_bytes());
+        pages.extend_from_slice(&16u16.to_le_bytes());
+        pages.extend_from_slice(&80u16.to_le_bytes());
+        pages.extend_from_slice(&[0, 0]);
+        pages.extend_from_slice(text.as_bytes());
+    }
+
+    let lut_offset = header_size + toc.len();
+    let page_data_offset = lut_offset + lut.len();
+    let mut out = Vec::with_capacity(page_data_offset + pages.len());
+    out.extend_from_slice(b"TRBK");
+    out.extend_from_slice(&[2, 0]);
+    out.extend_from_slice(&(header_size as u16).to_le_bytes());
+    out.extend_from_slice(&(crate::framebuffer::HEIGHT as u16).to_le_bytes());
+    out.extend_from_slice(&(crate::framebuffer::WIDTH as u16).to_le_bytes());
+    for value in [
+        page_count,
+        chapters.len(),
+        lut_offset,
+        header_size,
+        page_data_offset,
+        0,
+        0,
+        0,
+        0,
+    ] {
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    out.extend_from_slice(&metadata);
+    out.extend_from_slice(&toc);
+    out.extend_from_slice(&lut);
+    out.extend_from_slice(&pages);
+    out
+}
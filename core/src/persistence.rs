@@ -0,0 +1,786 @@
+//! Versioned, checksummed state blob shared by the device and desktop builds.
+//!
+//! Resume position, book positions, recents and settings live in one blob that
+//! is written alternately to two files. A save always goes to the copy that is
+//! not current, so a partial write leaves the previous copy intact and the
+//! loader falls back to it.
+//!
+//! Each profile has its own pair of copies; the first profile keeps the
+//! original file names so state from before profiles carries over.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::app::image_viewer::ImageOrder;
+use crate::app::power_menu::PowerButtonMode;
+use crate::display::{BorderMode, FrontlightLevel, RefreshTuning};
+use crate::reflow::ReadingLayout;
+
+pub const STATE_MAGIC: [u8; 4] = *b"TRST";
+pub const STATE_VERSION: u16 = 1;
+pub const STATE_FILE_A: &str = "TRSTATE.A";
+pub const STATE_FILE_B: &str = "TRSTATE.B";
+/// Index of the profile in use, as decimal text.
+pub const ACTIVE_PROFILE_FILE: &str = "TRPROF.ACT";
+
+/// Book positions kept per profile; the least recently read go first.
+pub const MAX_BOOK_POSITIONS: usize = 200;
+
+const HEADER_LEN: usize = 20;
+const MAX_STRING_LEN: usize = u16::MAX as usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Slot::A => STATE_FILE_A,
+            Slot::B => STATE_FILE_B,
+        }
+    }
+
+    /// Copies of profile `n` after the first are `TRSTATn.A` and `TRSTATn.B`.
+    pub fn profile_file_name(self, profile: u8) -> String {
+        if profile == 0 {
+            return self.file_name().into();
+        }
+        let suffix = match self {
+            Slot::A => 'A',
+            Slot::B => 'B',
+        };
+        format!("TRSTAT{}.{}", profile, suffix)
+    }
+
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PersistError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u16),
+    Checksum,
+    Malformed,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PersistedState {
+    pub generation: u32,
+    pub resume: Option<String>,
+    pub book_positions: Vec<(String, usize)>,
+    pub recent_entries: Vec<String>,
+    /// Typeface of the font pack books are drawn in, instead of their own.
+    pub reading_font: Option<String>,
+    /// Text size and margins reflowable books are laid out with.
+    pub reading_layout: ReadingLayout,
+    /// Host identifiers allowed USB file access without the prompt.
+    pub usb_hosts: Vec<String>,
+    /// Zoom crop of prerendered pages by book, in pixels per side.
+    pub book_crops: Vec<(String, u8)>,
+    /// New books open at their first page instead of their first chapter.
+    pub show_front_matter: bool,
+    pub refresh_tuning: RefreshTuning,
+    /// Order Left/Right steps through the images of a folder.
+    pub image_order: ImageOrder,
+    /// The page number is only shown for a moment after each page turn.
+    pub clean_page: bool,
+    /// The page number counts pages within the chapter as well.
+    pub chapter_progress: bool,
+    /// Reading pace by book, in milliseconds per page.
+    pub book_paces: Vec<(String, u32)>,
+    /// Folder the library is locked to in simple mode.
+    pub simple_mode: Option<String>,
+    /// Button presses that unlock the reader on wake; empty when off.
+    pub lock_code: Vec<u8>,
+    /// macOS and Windows clutter is deleted from the card after USB access.
+    pub clean_system_files: bool,
+    pub frontlight: FrontlightLevel,
+    pub power_button: PowerButtonMode,
+}
+
+impl PersistedState {
+    /// Layout: magic, u16 version, u16 reserved, u32 generation,
+    /// u32 payload length, u32 crc32 of the payload, then the payload.
+    /// Settings follow the recents and may be missing from older blobs;
+    /// fields a newer build appended after them are skipped, so going back to
+    /// older firmware keeps everything it knows about.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match &self.resume {
+            Some(name) => {
+                payload.push(1);
+                push_str(&mut payload, name);
+            }
+            None => payload.push(0),
+        }
+        payload.extend_from_slice(&(self.book_positions.len() as u32).to_le_bytes());
+        for (name, page) in &self.book_positions {
+            push_str(&mut payload, name);
+            payload.extend_from_slice(&(*page as u32).to_le_bytes());
+        }
+        payload.extend_from_slice(&(self.recent_entries.len() as u32).to_le_bytes());
+        for entry in &self.recent_entries {
+            push_str(&mut payload, entry);
+        }
+        match &self.reading_font {
+            Some(name) => {
+                payload.push(1);
+                push_str(&mut payload, name);
+            }
+            None => payload.push(0),
+        }
+        payload.push(self.reading_layout.text_size);
+        payload.push(self.reading_layout.margins);
+        payload.extend_from_slice(&(self.usb_hosts.len() as u32).to_le_bytes());
+        for host in &self.usb_hosts {
+            push_str(&mut payload, host);
+        }
+        payload.extend_from_slice(&(self.book_crops.len() as u32).to_le_bytes());
+        for (name, crop) in &self.book_crops {
+            push_str(&mut payload, name);
+            payload.push(*crop);
+        }
+        payload.push(self.show_front_matter as u8);
+        let border = BorderMode::ALL
+            .iter()
+            .position(|mode| *mode == self.refresh_tuning.border)
+            .unwrap_or(0);
+        payload.push(self.refresh_tuning.gray_after_fast as u8);
+        payload.push(border as u8);
+        payload.push(self.refresh_tuning.double_fast as u8);
+        payload.push(self.refresh_tuning.idle_deep_clean as u8);
+        payload.push(self.image_order as u8);
+        payload.push(self.clean_page as u8);
+        payload.push(self.chapter_progress as u8);
+        payload.extend_from_slice(&(self.book_paces.len() as u32).to_le_bytes());
+        for (name, page_ms) in &self.book_paces {
+            push_str(&mut payload, name);
+            payload.extend_from_slice(&page_ms.to_le_bytes());
+        }
+        match &self.simple_mode {
+            Some(folder) => {
+                payload.push(1);
+                push_str(&mut payload, folder);
+            }
+            None => payload.push(0),
+        }
+        payload.push(self.lock_code.len() as u8);
+        payload.extend_from_slice(&self.lock_code);
+        payload.push(self.clean_system_files as u8);
+        payload.push(self.frontlight.brightness);
+        payload.push(self.frontlight.warmth);
+        let power_button = PowerButtonMode::ALL
+            .iter()
+            .position(|mode| *mode == self.power_button)
+            .unwrap_or(0);
+        payload.push(power_button as u8);
+
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        out.extend_from_slice(&STATE_MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32(&payload).to_le_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, PersistError> {
+        if data.len() < HEADER_LEN {
+            return Err(PersistError::Truncated);
+        }
+        if data[0..4] != STATE_MAGIC {
+            return Err(PersistError::BadMagic);
+        }
+        let mut cursor = 4;
+        let version = read_u16(data, &mut cursor)?;
+        if version != STATE_VERSION {
+            return Err(PersistError::UnsupportedVersion(version));
+        }
+        let _reserved = read_u16(data, &mut cursor)?;
+        let generation = read_u32(data, &mut cursor)?;
+        let payload_len = read_u32(data, &mut cursor)? as usize;
+        let crc = read_u32(data, &mut cursor)?;
+        let payload_end = HEADER_LEN
+            .checked_add(payload_len)
+            .ok_or(PersistError::Malformed)?;
+        let payload = data
+            .get(HEADER_LEN..payload_end)
+            .ok_or(PersistError::Truncated)?;
+        if crc32(payload) != crc {
+            return Err(PersistError::Checksum);
+        }
+
+        let mut cursor = 0;
+        let resume = match read_u8(payload, &mut cursor)? {
+            0 => None,
+            1 => Some(read_str(payload, &mut cursor)?),
+            _ => return Err(PersistError::Malformed),
+        };
+        let count = read_u32(payload, &mut cursor)? as usize;
+        let mut book_positions = Vec::new();
+        for _ in 0..count {
+            let name = read_str(payload, &mut cursor)?;
+            let page = read_u32(payload, &mut cursor)? as usize;
+            book_positions.push((name, page));
+        }
+        let count = read_u32(payload, &mut cursor)? as usize;
+        let mut recent_entries = Vec::new();
+        for _ in 0..count {
+            recent_entries.push(read_str(payload, &mut cursor)?);
+        }
+        let reading_font = if cursor == payload.len() {
+            None
+        } else {
+            match read_u8(payload, &mut cursor)? {
+                0 => None,
+                1 => Some(read_str(payload, &mut cursor)?),
+                _ => return Err(PersistError::Malformed),
+            }
+        };
+        let reading_layout = if cursor == payload.len() {
+            ReadingLayout::default()
+        } else {
+            ReadingLayout {
+                text_size: read_u8(payload, &mut cursor)?,
+                margins: read_u8(payload, &mut cursor)?,
+            }
+        };
+        let mut usb_hosts = Vec::new();
+        if cursor != payload.len() {
+            let count = read_u32(payload, &mut cursor)? as usize;
+            for _ in 0..count {
+                usb_hosts.push(read_str(payload, &mut cursor)?);
+            }
+        }
+        let mut book_crops = Vec::new();
+        if cursor != payload.len() {
+            let count = read_u32(payload, &mut cursor)? as usize;
+            for _ in 0..count {
+                let name = read_str(payload, &mut cursor)?;
+                book_crops.push((name, read_u8(payload, &mut cursor)?));
+            }
+        }
+        let show_front_matter = cursor != payload.len() && read_u8(payload, &mut cursor)? != 0;
+        let refresh_tuning = if cursor == payload.len() {
+            RefreshTuning::default()
+        } else {
+            RefreshTuning {
+                gray_after_fast: read_u8(payload, &mut cursor)? != 0,
+                border: *BorderMode::ALL
+                    .get(read_u8(payload, &mut cursor)? as usize)
+                    .ok_or(PersistError::Malformed)?,
+                double_fast: read_u8(payload, &mut cursor)? != 0,
+                idle_deep_clean: cursor != payload.len() && read_u8(payload, &mut cursor)? != 0,
+            }
+        };
+        let image_order = if cursor == payload.len() {
+            ImageOrder::default()
+        } else {
+            *ImageOrder::ALL
+                .get(read_u8(payload, &mut cursor)? as usize)
+                .ok_or(PersistError::Malformed)?
+        };
+        let clean_page = cursor != payload.len() && read_u8(payload, &mut cursor)? != 0;
+        let chapter_progress = cursor != payload.len() && read_u8(payload, &mut cursor)? != 0;
+        let mut book_paces = Vec::new();
+        if cursor != payload.len() {
+            let count = read_u32(payload, &mut cursor)? as usize;
+            for _ in 0..count {
+                let name = read_str(payload, &mut cursor)?;
+                book_paces.push((name, read_u32(payload, &mut cursor)?));
+            }
+        }
+        let simple_mode = if cursor != payload.len() && read_u8(payload, &mut cursor)? != 0 {
+            Some(read_str(payload, &mut cursor)?)
+        } else {
+            None
+        };
+        let mut lock_code = Vec::new();
+        if cursor != payload.len() {
+            let len = read_u8(payload, &mut cursor)? as usize;
+            let bytes = payload
+                .get(cursor..cursor + len)
+                .ok_or(PersistError::Truncated)?;
+            lock_code.extend_from_slice(bytes);
+            cursor += len;
+        }
+        let clean_system_files = cursor != payload.len() && read_u8(payload, &mut cursor)? != 0;
+        let frontlight = if cursor == payload.len() {
+            FrontlightLevel::default()
+        } else {
+            FrontlightLevel {
+                brightness: read_u8(payload, &mut cursor)?,
+                warmth: read_u8(payload, &mut cursor)?,
+            }
+        };
+        let power_button = if cursor == payload.len() {
+            PowerButtonMode::default()
+        } else {
+            *PowerButtonMode::ALL
+                .get(read_u8(payload, &mut cursor)? as usize)
+                .ok_or(PersistError::Malformed)?
+        };
+        if cursor != payload.len() {
+            log::info!(
+                "Skipping {} bytes of newer state fields",
+                payload.len() - cursor
+            );
+        }
+        Ok(Self {
+            generation,
+            resume,
+            book_positions,
+            recent_entries,
+            reading_font,
+            reading_layout,
+            usb_hosts,
+            book_crops,
+            show_front_matter,
+            refresh_tuning,
+            image_order,
+            clean_page,
+            chapter_progress,
+            book_paces,
+            simple_mode,
+            lock_code,
+            clean_system_files,
+            frontlight,
+            power_button,
+        })
+    }
+}
+
+/// Raw file access for the two state copies; implemented per platform.
+pub trait StateStorage {
+    fn read_state_file(&mut self, name: &str) -> Option<Vec<u8>>;
+    fn write_state_file(&mut self, name: &str, data: &[u8]) -> bool;
+}
+
+#[derive(Default)]
+pub struct StateStore {
+    state: PersistedState,
+    current: Option<Slot>,
+    loaded: bool,
+    /// Read from `ACTIVE_PROFILE_FILE` on the first load.
+    profile: Option<u8>,
+}
+
+impl StateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn profile(&self) -> u8 {
+        self.profile.unwrap_or(0)
+    }
+
+    /// Makes `profile` the one in use from now on, including after a restart.
+    /// Its copies are read on the next load.
+    pub fn set_profile(&mut self, storage: &mut impl StateStorage, profile: u8) {
+        if !storage.write_state_file(ACTIVE_PROFILE_FILE, format!("{}", profile).as_bytes()) {
+            log::warn!("Failed to write {}", ACTIVE_PROFILE_FILE);
+        }
+        self.profile = Some(profile);
+        self.state = PersistedState::default();
+        self.current = None;
+        self.loaded = false;
+    }
+
+    pub fn state(&self) -> &PersistedState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut PersistedState {
+        &mut self.state
+    }
+
+    /// Reads both copies and keeps the newest valid one. Returns false when
+    /// neither copy is usable, in which case the in-memory state is left as is
+    /// so callers can seed it from legacy files.
+    pub fn load(&mut self, storage: &mut impl StateStorage) -> bool {
+        self.loaded = true;
+        let profile = match self.profile {
+            Some(profile) => profile,
+            None => {
+                let profile = storage
+                    .read_state_file(ACTIVE_PROFILE_FILE)
+                    .and_then(|data| core::str::from_utf8(&data).ok()?.trim().parse().ok())
+                    .unwrap_or(0);
+                self.profile = Some(profile);
+                profile
+            }
+        };
+        let mut best: Option<(Slot, PersistedState)> = None;
+        for slot in [Slot::A, Slot::B] {
+            let name = slot.profile_file_name(profile);
+            let Some(data) = storage.read_state_file(&name) else {
+                continue;
+            };
+            match PersistedState::decode(&data) {
+                Ok(state) => {
+                    let newer = best
+                        .as_ref()
+                        .is_none_or(|(_, current)| state.generation > current.generation);
+                    if newer {
+                        best = Some((slot, state));
+                    }
+                }
+                Err(err) => log::warn!("State copy {} unusable: {:?}", name, err),
+            }
+        }
+        match best {
+            Some((slot, state)) => {
+                self.current = Some(slot);
+                self.state = state;
+                true
+            }
+            None => {
+                self.current = None;
+                false
+            }
+        }
+    }
+
+    /// Writes the state to the copy that is not current and makes it current.
+    pub fn save(&mut self, storage: &mut impl StateStorage) -> bool {
+        let slot = self.current.map(Slot::other).unwrap_or(Slot::A);
+        self.state.generation = self.state.generation.wrapping_add(1);
+        let data = self.state.encode();
+        let name = slot.profile_file_name(self.profile());
+        if storage.write_state_file(&name, &data) {
+            self.current = Some(slot);
+            true
+        } else {
+            log::warn!("Failed to write state copy {}", name);
+            false
+        }
+    }
+}
+
+/// Parses the legacy `TRBOOKS` text, one `name<TAB>page` per line, oldest
+/// first. A damaged line is logged and skipped instead of ending the list, a
+/// later line for the same book replaces an earlier one, and only the last
+/// `MAX_BOOK_POSITIONS` books are kept. The flag is set when anything was
+/// dropped, so the caller can write the cleaned list back.
+pub fn parse_book_positions(text: &str) -> (Vec<(String, usize)>, bool) {
+    let mut entries: Vec<(String, usize)> = Vec::new();
+    let mut cleaned = false;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = line.split_once('\t').and_then(|(name, page)| {
+            let name = name.trim();
+            let page = page.trim().parse::<usize>().ok()?;
+            (!name.is_empty()).then_some((name, page))
+        });
+        let Some((name, page)) = parsed else {
+            log::warn!("Skipping book position line {}: {:?}", index + 1, line);
+            cleaned = true;
+            continue;
+        };
+        if let Some(existing) = entries.iter().position(|(entry, _)| entry == name) {
+            entries.remove(existing);
+            cleaned = true;
+        }
+        entries.push((String::from(name), page));
+    }
+    if cap_book_positions(&mut entries) > 0 {
+        cleaned = true;
+    }
+    (entries, cleaned)
+}
+
+/// Drops the oldest positions past `MAX_BOOK_POSITIONS` from the front of
+/// `entries` and returns how many went.
+pub fn cap_book_positions(entries: &mut Vec<(String, usize)>) -> usize {
+    let excess = entries.len().saturating_sub(MAX_BOOK_POSITIONS);
+    if excess > 0 {
+        log::info!("Dropping {} least recently read book positions", excess);
+        entries.drain(..excess);
+    }
+    excess
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(0xFFFF_FFFF, data)
+}
+
+/// Feeds `data` into a running CRC-32, for data that arrives in pieces.
+/// Start from `0xFFFF_FFFF` and invert the final value.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    crc
+}
+
+fn push_str(out: &mut Vec<u8>, value: &str) {
+    let mut end = value.len().min(MAX_STRING_LEN);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    let bytes = &value.as_bytes()[..end];
+    out.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn read_u8(data: &[u8], cursor: &mut usize) -> Result<u8, PersistError> {
+    let value = *data.get(*cursor).ok_or(PersistError::Truncated)?;
+    *cursor += 1;
+    Ok(value)
+}
+
+fn read_u16(data: &[u8], cursor: &mut usize) -> Result<u16, PersistError> {
+    let bytes = data.get(*cursor..*cursor + 2).ok_or(PersistError::Truncated)?;
+    *cursor += 2;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], cursor: &mut usize) -> Result<u32, PersistError> {
+    let bytes = data.get(*cursor..*cursor + 4).ok_or(PersistError::Truncated)?;
+    *cursor += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_str(data: &[u8], cursor: &mut usize) -> Result<String, PersistError> {
+    let len = read_u16(data, cursor)? as usize;
+    let bytes = data.get(*cursor..*cursor + len).ok_or(PersistError::Truncated)?;
+    *cursor += len;
+    core::str::from_utf8(bytes)
+        .map(String::from)
+        .map_err(|_| PersistError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[derive(Default)]
+    struct Card {
+        files: BTreeMap<String, Vec<u8>>,
+    }
+
+    impl StateStorage for Card {
+        fn read_state_file(&mut self, name: &str) -> Option<Vec<u8>> {
+            self.files.get(name).cloned()
+        }
+
+        fn write_state_file(&mut self, name: &str, data: &[u8]) -> bool {
+            self.files.insert(name.to_string(), data.to_vec());
+            true
+        }
+    }
+
+    fn full_state() -> PersistedState {
+        PersistedState {
+            generation: 7,
+            resume: Some("books/a.trbk".into()),
+            book_positions: vec![("books/a.trbk".into(), 12), ("b.trbk".into(), 0)],
+            recent_entries: vec!["books/a.trbk".into()],
+            reading_font: Some("Serif".into()),
+            reading_layout: ReadingLayout {
+                text_size: 2,
+                margins: 1,
+            },
+            usb_hosts: vec!["host-1".into()],
+            book_crops: vec![("scan.trbk".into(), 24)],
+            show_front_matter: true,
+            refresh_tuning: RefreshTuning {
+                gray_after_fast: true,
+                border: BorderMode::ALL[1],
+                double_fast: true,
+                idle_deep_clean: true,
+            },
+            image_order: ImageOrder::Shuffle,
+            clean_page: true,
+            chapter_progress: true,
+            book_paces: vec![("books/a.trbk".into(), 45_000)],
+            simple_mode: Some("kids".into()),
+            lock_code: vec![1, 2, 3, 4],
+            clean_system_files: true,
+            frontlight: FrontlightLevel {
+                brightness: 5,
+                warmth: 3,
+            },
+            power_button: PowerButtonMode::SleepOnPress,
+        }
+    }
+
+    /// Wraps `payload` in a header with a matching length and checksum.
+    fn blob(payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&STATE_MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&3u32.to_le_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32(payload).to_le_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let state = full_state();
+        assert_eq!(PersistedState::decode(&state.encode()), Ok(state));
+        let empty = PersistedState::default();
+        assert_eq!(PersistedState::decode(&empty.encode()), Ok(empty));
+    }
+
+    #[test]
+    fn blob_from_before_the_settings_decodes_with_defaults() {
+        // No resume, no book positions, no recents.
+        let state = PersistedState::decode(&blob(&[0, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(state.generation, 3);
+        assert_eq!(
+            state,
+            PersistedState {
+                generation: 3,
+                ..PersistedState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn fields_from_newer_firmware_are_skipped() {
+        let state = full_state();
+        let encoded = state.encode();
+        let mut payload = encoded[HEADER_LEN..].to_vec();
+        payload.extend_from_slice(&[9, 9, 9]);
+        let decoded = PersistedState::decode(&blob(&payload)).unwrap();
+        assert_eq!(decoded.power_button, state.power_button);
+        assert_eq!(decoded.lock_code, state.lock_code);
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut data = full_state().encode();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        assert_eq!(PersistedState::decode(&data), Err(PersistError::Checksum));
+    }
+
+    #[test]
+    fn truncated_blobs_are_rejected() {
+        let data = full_state().encode();
+        assert_eq!(
+            PersistedState::decode(&data[..HEADER_LEN - 1]),
+            Err(PersistError::Truncated)
+        );
+        assert_eq!(
+            PersistedState::decode(&data[..data.len() - 1]),
+            Err(PersistError::Truncated)
+        );
+        // A string length that runs past the end of a payload with a good
+        // checksum.
+        assert_eq!(
+            PersistedState::decode(&blob(&[1, 40, 0, b'a'])),
+            Err(PersistError::Truncated)
+        );
+    }
+
+    #[test]
+    fn huge_payload_length_is_rejected() {
+        let mut data = full_state().encode();
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PersistedState::decode(&data).is_err());
+    }
+
+    #[test]
+    fn wrong_magic_and_version_are_rejected() {
+        let mut data = full_state().encode();
+        data[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            PersistedState::decode(&data),
+            Err(PersistError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+        data[0] = b'X';
+        assert_eq!(PersistedState::decode(&data), Err(PersistError::BadMagic));
+    }
+
+    #[test]
+    fn saves_alternate_and_load_keeps_the_newest_copy() {
+        let mut card = Card::default();
+        let mut store = StateStore::new();
+        assert!(!store.load(&mut card));
+        store.state_mut().resume = Some("first".into());
+        assert!(store.save(&mut card));
+        store.state_mut().resume = Some("second".into());
+        assert!(store.save(&mut card));
+        assert!(card.files.contains_key(STATE_FILE_A));
+        assert!(card.files.contains_key(STATE_FILE_B));
+
+        let mut reloaded = StateStore::new();
+        assert!(reloaded.load(&mut card));
+        assert_eq!(reloaded.state().resume.as_deref(), Some("second"));
+    }
+
+    #[test]
+    fn load_falls_back_to_the_other_copy_when_one_is_corrupt() {
+        let mut card = Card::default();
+        let mut store = StateStore::new();
+        store.load(&mut card);
+        store.state_mut().resume = Some("first".into());
+        store.save(&mut card);
+        store.state_mut().resume = Some("second".into());
+        store.save(&mut card);
+
+        // The newer copy was only partly written.
+        let newer = card.files.get_mut(STATE_FILE_B).unwrap();
+        newer.truncate(newer.len() / 2);
+        let mut reloaded = StateStore::new();
+        assert!(reloaded.load(&mut card));
+        assert_eq!(reloaded.state().resume.as_deref(), Some("first"));
+
+        // The next save replaces the broken copy, not the good one.
+        reloaded.state_mut().resume = Some("third".into());
+        reloaded.save(&mut card);
+        assert!(PersistedState::decode(&card.files[STATE_FILE_B]).is_ok());
+        assert_eq!(
+            PersistedState::decode(&card.files[STATE_FILE_A])
+                .unwrap()
+                .resume
+                .as_deref(),
+            Some("first")
+        );
+    }
+
+    #[test]
+    fn other_profiles_keep_their_own_copies() {
+        let mut card = Card::default();
+        let mut store = StateStore::new();
+        store.load(&mut card);
+        store.set_profile(&mut card, 2);
+        store.load(&mut card);
+        store.state_mut().resume = Some("theirs".into());
+        store.save(&mut card);
+        assert!(card.files.contains_key("TRSTAT2.A"));
+        assert!(!card.files.contains_key(STATE_FILE_A));
+    }
+}
@@ -0,0 +1,931 @@
+//! On-device layout of a book's logical text (TRBK v3).
+//!
+//! Books converted with `--reflow` carry their paragraphs as styled runs next
+//! to the prerendered pages. When the reader's margins or text size differ
+//! from what the book was converted with, [`paginate`] wraps the paragraphs
+//! again with the book's glyphs and keeps only where each page starts;
+//! [`Reflow::page`] lays out one page at a time from there. Books read as
+//! converted keep using the prerendered pages.
+//!
+//! Section layout: a u32 offset per item, relative to the section start,
+//! then the items. An item is its kind, heading level, two reserved bytes,
+//! the prerendered page it starts on (u32) and its payload length (u32),
+//! followed by the payload.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::image_viewer::{BookSource, ImageError};
+use crate::trbk::{TrbkBookInfo, TrbkMetadata, TrbkOp, TrbkPage, TrbkTextInfo, TrbkTocEntry};
+
+pub const TEXT_ITEM_HEADER_LEN: usize = 12;
+const ITEM_PARAGRAPH: u8 = 1;
+const ITEM_IMAGE: u8 = 2;
+const ITEM_PAGE_BREAK: u8 = 3;
+const ITEM_PAGE: u8 = 4;
+const RUN_TEXT: u8 = 0;
+const RUN_IMAGE: u8 = 1;
+
+/// Text sizes as a label and a percentage of the book's own.
+pub const TEXT_SIZES: [(&str, u16); 4] = [("Book", 100), ("Small", 85), ("Large", 120), ("Extra large", 145)];
+/// Margins as a label and the horizontal and vertical margin, or `None` for
+/// the book's own.
+pub const MARGINS: [(&str, Option<(u16, u16)>); 4] = [
+    ("Book", None),
+    ("Narrow", Some((8, 32))),
+    ("Normal", Some((24, 48))),
+    ("Wide", Some((48, 72))),
+];
+
+/// Reader-chosen text size and margins, as indices into [`TEXT_SIZES`] and
+/// [`MARGINS`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadingLayout {
+    pub text_size: u8,
+    pub margins: u8,
+}
+
+impl ReadingLayout {
+    pub fn text_size_label(&self) -> &'static str {
+        TEXT_SIZES[self.text_size as usize % TEXT_SIZES.len()].0
+    }
+
+    pub fn margins_label(&self) -> &'static str {
+        MARGINS[self.margins as usize % MARGINS.len()].0
+    }
+
+    /// Advance of the regular `n` a book with `char_width` should be drawn
+    /// at, which picks the font pack size.
+    pub fn text_width(&self, char_width: u16) -> u16 {
+        let percent = TEXT_SIZES[self.text_size as usize % TEXT_SIZES.len()].1;
+        ((char_width as u32 * percent as u32 + 50) / 100).max(1) as u16
+    }
+}
+
+/// Typeface and advance to draw a book in. A chosen reading font always
+/// applies; otherwise a reflowable book that should be drawn at another size
+/// uses another size of its own font pack.
+pub fn reading_font_for<'a>(
+    metadata: &'a TrbkMetadata,
+    reflowable: bool,
+    reading_font: Option<&'a str>,
+    reading: ReadingLayout,
+) -> Option<(&'a str, u16)> {
+    let char_width = if reflowable {
+        reading.text_width(metadata.char_width)
+    } else {
+        metadata.char_width
+    };
+    let typeface = match reading_font {
+        Some(typeface) => typeface,
+        None if reflowable && char_width != metadata.char_width => metadata
+            .font_pack
+            .as_ref()
+            .and_then(|pack| crate::trbk::font_pack_typeface(&pack.name))?,
+        None => return None,
+    };
+    Some((typeface, char_width))
+}
+
+#[derive(Clone, Debug)]
+pub struct TextItem {
+    /// Prerendered page the item starts on.
+    pub page: u32,
+    pub heading_level: u8,
+    pub kind: TextItemKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum TextItemKind {
+    Paragraph(Vec<TextRun>),
+    Image(TextImage),
+    PageBreak,
+    /// The prerendered page shown as is, such as a cover or a fixed-layout page.
+    Page,
+}
+
+#[derive(Clone, Debug)]
+pub enum TextRun {
+    Text { style: u8, text: String },
+    /// A rendered equation; display equations get a line of their own.
+    Image {
+        style: u8,
+        image: TextImage,
+        display: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextImage {
+    pub index: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Rows above the text baseline when drawn inline.
+    pub baseline: u16,
+}
+
+/// Payload length of the item starting with `header`.
+pub fn text_item_len(header: &[u8]) -> Result<usize, ImageError> {
+    Ok(read_u32(header, 8)? as usize)
+}
+
+/// Parses an item from its header and payload.
+pub fn parse_text_item(data: &[u8]) -> Result<TextItem, ImageError> {
+    let len = text_item_len(data)?;
+    let payload = data
+        .get(TEXT_ITEM_HEADER_LEN..TEXT_ITEM_HEADER_LEN + len)
+        .ok_or(ImageError::Decode)?;
+    let kind = match data[0] {
+        ITEM_PARAGRAPH => {
+            let mut runs = Vec::new();
+            let mut cursor = 0;
+            while cursor + 4 <= payload.len() {
+                let kind = payload[cursor];
+                let style = payload[cursor + 1];
+                let len = read_u16(payload, cursor + 2)? as usize;
+                let body = payload
+                    .get(cursor + 4..cursor + 4 + len)
+                    .ok_or(ImageError::Decode)?;
+                cursor += 4 + len;
+                match kind {
+                    RUN_TEXT => runs.push(TextRun::Text {
+                        style,
+                        text: core::str::from_utf8(body)
+                            .map_err(|_| ImageError::Decode)?
+                            .to_string(),
+                    }),
+                    RUN_IMAGE => runs.push(TextRun::Image {
+                        style,
+                        image: read_image(body)?,
+                        display: body.get(8).is_some_and(|display| *display != 0),
+                    }),
+                    // Unknown runs are skipped for forward compatibility.
+                    _ => {}
+                }
+            }
+            TextItemKind::Paragraph(runs)
+        }
+        ITEM_IMAGE => TextItemKind::Image(read_image(payload)?),
+        ITEM_PAGE => TextItemKind::Page,
+        ITEM_PAGE_BREAK => TextItemKind::PageBreak,
+        // Unknown items only end the page, for forward compatibility.
+        _ => TextItemKind::PageBreak,
+    };
+    Ok(TextItem {
+        page: read_u32(data, 4)?,
+        heading_level: data[1],
+        kind,
+    })
+}
+
+/// Streams the items of a book held in memory from `first`, for sources that
+/// keep the whole file.
+pub fn read_text_items(
+    data: &[u8],
+    text: TrbkTextInfo,
+    first: usize,
+    visit: &mut dyn FnMut(usize, TextItem) -> bool,
+) -> Result<(), ImageError> {
+    let section = text.offset as usize;
+    for index in first..text.item_count as usize {
+        let offset = section + read_u32(data, section + index * 4)? as usize;
+        let item = parse_text_item(data.get(offset..).ok_or(ImageError::Decode)?)?;
+        if !visit(index, item) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Page geometry and line metrics to lay text out with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflowLayout {
+    pub screen_width: u16,
+    pub screen_height: u16,
+    pub margin_x: u16,
+    pub margin_y: u16,
+    pub line_height: u16,
+    pub ascent: i16,
+    pub word_spacing: i16,
+    /// Advance of characters the book has no glyph for.
+    pub missing_advance: u16,
+}
+
+impl ReflowLayout {
+    /// The layout the book was converted with. The word spacing follows the
+    /// converter's rule of a third of the character width.
+    pub fn converted(info: &TrbkBookInfo) -> Self {
+        let metadata = &info.metadata;
+        Self {
+            screen_width: info.screen_width,
+            screen_height: info.screen_height,
+            margin_x: metadata.margin_left,
+            margin_y: metadata.margin_top,
+            line_height: metadata.line_height,
+            ascent: metadata.ascent,
+            word_spacing: (metadata.char_width as i16 / 3).max(2),
+            missing_advance: metadata.char_width,
+        }
+    }
+
+    /// The converted layout with the reader's margins, and line metrics
+    /// scaled to the size the glyphs are now drawn at, which differs when a
+    /// reading font of another size replaced them.
+    pub fn for_book(info: &TrbkBookInfo, reading: ReadingLayout) -> Self {
+        let mut layout = Self::converted(info);
+        let char_width = info.metadata.char_width.max(1) as i32;
+        let drawn = info
+            .glyphs
+            .iter()
+            .find(|glyph| glyph.style == 0 && glyph.codepoint == 'n' as u32)
+            .map_or(char_width, |glyph| (glyph.x_advance as i32).max(1));
+        if drawn != char_width {
+            layout.line_height = (layout.line_height as i32 * drawn / char_width) as u16;
+            layout.ascent = (layout.ascent as i32 * drawn / char_width) as i16;
+            layout.word_spacing = (drawn as i16 / 3).max(2);
+        }
+        if let Some((margin_x, margin_y)) = MARGINS[reading.margins as usize % MARGINS.len()].1 {
+            layout.margin_x = margin_x;
+            layout.margin_y = margin_y;
+        }
+        layout
+    }
+}
+
+/// Where a reflowed page starts: a line of an item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageStart {
+    pub item: u32,
+    pub line: u32,
+    /// Prerendered page of the item, for carrying positions over.
+    pub source_page: u32,
+}
+
+/// A book laid out again for a [`ReflowLayout`].
+#[derive(Clone, Debug)]
+pub struct Reflow {
+    pub layout: ReflowLayout,
+    pub pages: Vec<PageStart>,
+    advances: BTreeMap<(u8, u32), i16>,
+}
+
+impl Reflow {
+    /// Reflowed page showing prerendered page `source_page`: the first page
+    /// of an item starting there, else the page holding it.
+    pub fn page_for_source(&self, source_page: usize) -> usize {
+        let source_page = source_page as u32;
+        let index = self.pages.partition_point(|start| start.source_page < source_page);
+        if self.pages.get(index).is_some_and(|start| start.source_page == source_page) {
+            index
+        } else {
+            index.saturating_sub(1)
+        }
+    }
+
+    /// Prerendered page a reflowed page starts in, which is what positions
+    /// are saved as.
+    pub fn source_page(&self, page: usize) -> usize {
+        self.pages.get(page).map_or(0, |start| start.source_page as usize)
+    }
+
+    /// The book as shown after reflow: its page count and TOC pages follow
+    /// the reflowed pages.
+    pub fn book_info(&self, info: &TrbkBookInfo) -> TrbkBookInfo {
+        let mut reflowed = info.clone();
+        reflowed.page_count = self.pages.len();
+        reflowed.toc = info
+            .toc
+            .iter()
+            .map(|entry| TrbkTocEntry {
+                title: entry.title.clone(),
+                page_index: self.page_for_source(entry.page_index as usize) as u32,
+                level: entry.level,
+            })
+            .collect();
+        reflowed
+    }
+
+    /// Lays out page `page_index`. Pages kept as prerendered are read from
+    /// the book as they are.
+    pub fn page<S: BookSource + ?Sized>(
+        &self,
+        source: &mut S,
+        page_index: usize,
+    ) -> Result<TrbkPage, ImageError> {
+        let start = *self.pages.get(page_index).ok_or(ImageError::Decode)?;
+        let mut flow = Flow::new(&self.layout, &self.advances, Some(start));
+        source.trbk_text(start.item as usize, &mut |index, item| flow.push(index as u32, &item))?;
+        match flow.prerendered {
+            Some(page) => source.trbk_page(page as usize),
+            None => Ok(TrbkPage {
+                ops: flow.ops.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+/// Wraps the whole text of the open book for `layout`. Books whose text
+/// lays out to no pages are an error, so callers fall back to the
+/// prerendered pages.
+pub fn paginate<S: BookSource + ?Sized>(
+    source: &mut S,
+    info: &TrbkBookInfo,
+    layout: ReflowLayout,
+) -> Result<Reflow, ImageError> {
+    let advances = info
+        .glyphs
+        .iter()
+        .map(|glyph| ((glyph.style, glyph.codepoint), glyph.x_advance))
+        .collect();
+    let pages = {
+        let mut flow = Flow::new(&layout, &advances, None);
+        source.trbk_text(0, &mut |index, item| flow.push(index as u32, &item))?;
+        flow.starts
+    };
+    if pages.is_empty() {
+        return Err(ImageError::Decode);
+    }
+    Ok(Reflow {
+        layout,
+        pages,
+        advances,
+    })
+}
+
+/// One wrapped line; piece positions are relative to the line start.
+struct Line {
+    pieces: Vec<(i32, Piece)>,
+    /// Rows inline images reach above the ascent and below the descent.
+    above: i32,
+    below: i32,
+    /// A display equation, centred on the page.
+    centered: bool,
+}
+
+enum Piece {
+    Text { style: u8, text: String },
+    Image(TextImage),
+}
+
+/// Layout state walking items in order. Without `draw` it records every page
+/// start; with it, it collects the ops of the page starting there and stops.
+struct Flow<'a> {
+    layout: &'a ReflowLayout,
+    advances: &'a BTreeMap<(u8, u32), i16>,
+    draw: Option<PageStart>,
+    starts: Vec<PageStart>,
+    ops: Option<Vec<TrbkOp>>,
+    prerendered: Option<u32>,
+    cursor: i32,
+    /// The next content starts a new page.
+    page_full: bool,
+    done: bool,
+}
+
+impl<'a> Flow<'a> {
+    fn new(
+        layout: &'a ReflowLayout,
+        advances: &'a BTreeMap<(u8, u32), i16>,
+        draw: Option<PageStart>,
+    ) -> Self {
+        Self {
+            layout,
+            advances,
+            draw,
+            starts: Vec::new(),
+            ops: None,
+            prerendered: None,
+            cursor: 0,
+            page_full: true,
+            done: false,
+        }
+    }
+
+    /// Adds item `index`; returns false once the drawn page is complete.
+    fn push(&mut self, index: u32, item: &TextItem) -> bool {
+        match &item.kind {
+            TextItemKind::Page => {
+                self.page_full = true;
+                if self.begin_page(index, 0, item.page, 0) && self.draw.is_some() {
+                    self.prerendered = Some(item.page);
+                }
+                // Nothing else goes on a prerendered page.
+                self.page_full = true;
+            }
+            TextItemKind::PageBreak => self.page_full = true,
+            TextItemKind::Image(image) => {
+                let height = image.height as i32;
+                if self.begin_page(index, 0, item.page, height) {
+                    if let Some(ops) = &mut self.ops {
+                        ops.push(TrbkOp::Image {
+                            x: 0,
+                            y: self.cursor,
+                            width: image.width,
+                            height: image.height,
+                            image_index: image.index,
+                        });
+                    }
+                    self.cursor += height + (self.layout.line_height as i32 / 2);
+                    self.blank_line();
+                }
+            }
+            TextItemKind::Paragraph(runs) => {
+                let lines = self.wrap(runs);
+                let skip = match self.draw {
+                    Some(start) if start.item == index => start.line as usize,
+                    _ => 0,
+                };
+                for (line_index, line) in lines.iter().enumerate().skip(skip) {
+                    let height = line.above + self.layout.line_height as i32 + line.below;
+                    if !self.begin_page(index, line_index as u32, item.page, height) {
+                        break;
+                    }
+                    self.place_line(line);
+                    self.cursor += height;
+                }
+                if !self.done {
+                    self.blank_line();
+                }
+            }
+        }
+        !self.done
+    }
+
+    /// Makes room for `height` rows, starting a page at this line when the
+    /// current one is full. Returns false when the drawn page is complete.
+    fn begin_page(&mut self, item: u32, line: u32, source_page: u32, height: i32) -> bool {
+        if self.done {
+            return false;
+        }
+        let bottom = self.layout.screen_height as i32 - self.layout.margin_y as i32;
+        if !self.page_full && self.cursor + height <= bottom {
+            return true;
+        }
+        match self.draw {
+            // Drawing starts at the requested page; the next start ends it.
+            Some(start) if self.ops.is_none() && self.prerendered.is_none() => {
+                if item != start.item || line < start.line {
+                    // Content before the page start is skipped.
+                    return false;
+                }
+                self.ops = Some(Vec::new());
+            }
+            Some(_) => {
+                self.done = true;
+                return false;
+            }
+            None => self.starts.push(PageStart {
+                item,
+                line,
+                source_page,
+            }),
+        }
+        self.cursor = self.layout.margin_y as i32;
+        self.page_full = false;
+        true
+    }
+
+    /// The gap after a paragraph or image; dropped at the foot of a page.
+    fn blank_line(&mut self) {
+        let bottom = self.layout.screen_height as i32 - self.layout.margin_y as i32;
+        if self.cursor + self.layout.line_height as i32 > bottom {
+            self.page_full = true;
+        } else {
+            self.cursor += self.layout.line_height as i32;
+        }
+    }
+
+    fn place_line(&mut self, line: &Line) {
+        let Some(ops) = &mut self.ops else {
+            return;
+        };
+        let baseline = self.cursor + line.above + self.layout.ascent as i32;
+        for (x, piece) in &line.pieces {
+            match piece {
+                Piece::Text { style, text } => ops.push(TrbkOp::TextRun {
+                    x: self.layout.margin_x as i32 + x,
+                    y: baseline,
+                    style: *style,
+                    text: text.clone(),
+                }),
+                Piece::Image(image) => {
+                    let x = if line.centered {
+                        (self.layout.screen_width as i32 - image.width as i32) / 2
+                    } else {
+                        self.layout.margin_x as i32 + x
+                    };
+                    ops.push(TrbkOp::Image {
+                        x: x.max(0),
+                        y: (baseline - image.baseline as i32).max(0),
+                        width: image.width,
+                        height: image.height,
+                        image_index: image.index,
+                    });
+                }
+            }
+        }
+    }
+
+    fn measure(&self, style: u8, text: &str) -> i32 {
+        text.chars()
+            .map(|ch| {
+                self.advances
+                    .get(&(style, ch as u32))
+                    .map_or(self.layout.missing_advance as i32, |advance| *advance as i32)
+            })
+            .sum()
+    }
+
+    /// Greedy word wrap, as the converter does it: words keep their run's
+    /// style, a newline ends the line, and punctuation straight after an
+    /// equation stays on its line.
+    fn wrap(&self, runs: &[TextRun]) -> Vec<Line> {
+        let max_width = (self.layout.screen_width as i32 - self.layout.margin_x as i32 * 2).max(1);
+        let mut lines = Vec::new();
+        let mut line = self.empty_line();
+        let mut width = 0i32;
+        let mut after_math = false;
+        for run in runs {
+            match run {
+                TextRun::Image {
+                    style,
+                    image,
+                    display,
+                } => {
+                    let space = self.measure(*style, " ") + self.layout.word_spacing as i32;
+                    if !line.pieces.is_empty()
+                        && (*display || width + space + image.width as i32 > max_width)
+                    {
+                        lines.push(core::mem::replace(&mut line, self.empty_line()));
+                        width = 0;
+                    }
+                    if !line.pieces.is_empty() {
+                        width += space;
+                    }
+                    let ascent = self.layout.ascent as i32;
+                    let descent = self.layout.line_height as i32 - ascent;
+                    line.above = line.above.max(image.baseline as i32 - ascent);
+                    line.below = line.below.max(image.height as i32 - image.baseline as i32 - descent);
+                    line.pieces.push((width, Piece::Image(*image)));
+                    width += image.width as i32;
+                    if *display {
+                        line.centered = true;
+                        lines.push(core::mem::replace(&mut line, self.empty_line()));
+                        width = 0;
+                    }
+                    after_math = true;
+                }
+                TextRun::Text { style, text } => {
+                    let attached = after_math && !text.starts_with(char::is_whitespace);
+                    after_math = false;
+                    for (token_index, token) in split_words(text).enumerate() {
+                        let token_width = self.measure(*style, token);
+                        let piece = Piece::Text {
+                            style: *style,
+                            text: token.to_string(),
+                        };
+                        if attached && token_index == 0 && width > 0 && width + token_width <= max_width {
+                            line.pieces.push((width, piece));
+                            width += token_width;
+                            continue;
+                        }
+                        if line.pieces.is_empty() {
+                            line.pieces.push((0, piece));
+                            width = token_width;
+                            continue;
+                        }
+                        let space = self.measure(*style, " ") + self.layout.word_spacing as i32;
+                        if width + space + token_width <= max_width {
+                            line.pieces.push((width + space, piece));
+                            width += space + token_width;
+                            continue;
+                        }
+                        lines.push(core::mem::replace(&mut line, self.empty_line()));
+                        line.pieces.push((0, piece));
+                        width = token_width;
+                    }
+                    if text.contains('\n') && !line.pieces.is_empty() {
+                        lines.push(core::mem::replace(&mut line, self.empty_line()));
+                        width = 0;
+                    }
+                }
+            }
+        }
+        if !line.pieces.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn empty_line(&self) -> Line {
+        Line {
+            pieces: Vec::new(),
+            above: 0,
+            below: 0,
+            centered: false,
+        }
+    }
+}
+
+/// Words of `text`, split at whitespace other than no-break spaces.
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|ch: char| ch.is_whitespace() && !matches!(ch, '\u{a0}' | '\u{2007}' | '\u{202f}'))
+        .filter(|word| !word.is_empty())
+}
+
+fn read_image(data: &[u8]) -> Result<TextImage, ImageError> {
+    Ok(TextImage {
+        index: read_u16(data, 0)?,
+        width: read_u16(data, 2)?,
+        height: read_u16(data, 4)?,
+        baseline: read_u16(data, 6)?,
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, ImageError> {
+    let bytes = data.get(offset..offset + 2).ok_or(ImageError::Decode)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ImageError::Decode)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec;
+
+    use crate::trbk::TrbkGlyph;
+
+    /// 100 by 100 pages with 10-pixel margins and 20-pixel lines, so 80
+    /// pixels of width and four lines fit; characters are 10 pixels wide.
+    fn layout() -> ReflowLayout {
+        ReflowLayout {
+            screen_width: 100,
+            screen_height: 100,
+            margin_x: 10,
+            margin_y: 10,
+            line_height: 20,
+            ascent: 15,
+            word_spacing: 0,
+            missing_advance: 10,
+        }
+    }
+
+    fn paragraph(page: u32, texts: &[&str]) -> TextItem {
+        TextItem {
+            page,
+            heading_level: 0,
+            kind: TextItemKind::Paragraph(
+                texts
+                    .iter()
+                    .map(|text| TextRun::Text {
+                        style: 0,
+                        text: text.to_string(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn item(page: u32, kind: TextItemKind) -> TextItem {
+        TextItem {
+            page,
+            heading_level: 0,
+            kind,
+        }
+    }
+
+    /// A v3 book's text items, with its prerendered pages marked by index.
+    struct Text(Vec<TextItem>);
+
+    impl BookSource for Text {
+        fn trbk_page(&mut self, page_index: usize) -> Result<TrbkPage, ImageError> {
+            Ok(TrbkPage {
+                ops: vec![TrbkOp::TextRun {
+                    x: 0,
+                    y: 0,
+                    style: 0,
+                    text: alloc::format!("prerendered {}", page_index),
+                }],
+            })
+        }
+
+        fn trbk_text(
+            &mut self,
+            first: usize,
+            visit: &mut dyn FnMut(usize, TextItem) -> bool,
+        ) -> Result<(), ImageError> {
+            for (index, item) in self.0.iter().enumerate().skip(first) {
+                if !visit(index, item.clone()) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn info(glyphs: Vec<TrbkGlyph>) -> TrbkBookInfo {
+        TrbkBookInfo {
+            screen_width: 100,
+            screen_height: 100,
+            page_count: 3,
+            metadata: TrbkMetadata {
+                title: String::new(),
+                author: String::new(),
+                language: String::new(),
+                identifier: String::new(),
+                font_name: String::new(),
+                char_width: 10,
+                line_height: 20,
+                ascent: 15,
+                margin_left: 10,
+                margin_right: 10,
+                margin_top: 10,
+                margin_bottom: 10,
+                part: None,
+                series: None,
+                font_pack: None,
+            },
+            glyphs: Rc::new(glyphs),
+            toc: vec![TrbkTocEntry {
+                title: "Two".into(),
+                page_index: 2,
+                level: 0,
+            }],
+            images: Vec::new(),
+            text: Some(TrbkTextInfo {
+                offset: 0,
+                item_count: 0,
+            }),
+        }
+    }
+
+    fn glyph(codepoint: char, x_advance: i16) -> TrbkGlyph {
+        TrbkGlyph {
+            codepoint: codepoint as u32,
+            style: 0,
+            width: 0,
+            height: 0,
+            x_advance,
+            x_offset: 0,
+            y_offset: 0,
+            bitmap_bw: Vec::new(),
+            bitmap_lsb: None,
+            bitmap_msb: None,
+        }
+    }
+
+    /// Each line's words and where they start.
+    fn wrapped(layout: &ReflowLayout, runs: &[&str]) -> Vec<Vec<(i32, String)>> {
+        let advances = BTreeMap::new();
+        let flow = Flow::new(layout, &advances, None);
+        let TextItemKind::Paragraph(runs) = paragraph(0, runs).kind else {
+            unreachable!()
+        };
+        flow.wrap(&runs)
+            .into_iter()
+            .map(|line| {
+                line.pieces
+                    .into_iter()
+                    .map(|(x, piece)| match piece {
+                        Piece::Text { text, .. } => (x, text),
+                        Piece::Image(_) => (x, String::new()),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn starts(pages: &[PageStart]) -> Vec<(u32, u32)> {
+        pages.iter().map(|start| (start.item, start.line)).collect()
+    }
+
+    #[test]
+    fn words_wrap_greedily_at_the_text_width() {
+        assert_eq!(
+            wrapped(&layout(), &["aaa bbb ccc"]),
+            vec![
+                vec![(0, "aaa".to_string()), (40, "bbb".to_string())],
+                vec![(0, "ccc".to_string())],
+            ]
+        );
+        // A word wider than the text stays whole on a line of its own.
+        assert_eq!(wrapped(&layout(), &["a aaaaaaaaaa a"]).len(), 3);
+    }
+
+    #[test]
+    fn a_newline_ends_the_line_its_run_ends() {
+        assert_eq!(
+            wrapped(&layout(), &["aa\n", "bb"]),
+            vec![vec![(0, "aa".to_string())], vec![(0, "bb".to_string())]]
+        );
+    }
+
+    #[test]
+    fn no_break_spaces_keep_words_together() {
+        assert_eq!(
+            wrapped(&layout(), &["aaa bb\u{a0}cc"]),
+            vec![
+                vec![(0, "aaa".to_string())],
+                vec![(0, "bb\u{a0}cc".to_string())]
+            ]
+        );
+    }
+
+    #[test]
+    fn narrower_margins_fit_more_on_a_line() {
+        assert_eq!(wrapped(&layout(), &["aaaa bbbb"]).len(), 2);
+        let mut wide = layout();
+        wide.margin_x = 0;
+        assert_eq!(wrapped(&wide, &["aaaa bbbb"]).len(), 1);
+    }
+
+    #[test]
+    fn pages_fill_before_a_new_one_starts() {
+        let words = ["aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa"];
+        let mut book = Text(vec![paragraph(0, &words)]);
+        let reflow = paginate(&mut book, &info(Vec::new()), layout()).unwrap();
+        assert_eq!(starts(&reflow.pages), [(0, 0), (0, 4)]);
+    }
+
+    #[test]
+    fn a_paragraph_carries_over_after_its_gap() {
+        let mut book = Text(vec![
+            paragraph(0, &["aaaaaaaa aaaaaaaa"]),
+            paragraph(1, &["bbbbbbbb bbbbbbbb"]),
+        ]);
+        let reflow = paginate(&mut book, &info(Vec::new()), layout()).unwrap();
+        // Two lines, the gap, then only one line of the second fits.
+        assert_eq!(starts(&reflow.pages), [(0, 0), (1, 1)]);
+        assert_eq!(reflow.source_page(1), 1);
+    }
+
+    #[test]
+    fn breaks_and_prerendered_pages_start_pages() {
+        let mut book = Text(vec![
+            paragraph(0, &["a"]),
+            item(0, TextItemKind::PageBreak),
+            paragraph(1, &["b"]),
+            item(2, TextItemKind::Page),
+            paragraph(2, &["c"]),
+        ]);
+        let reflow = paginate(&mut book, &info(Vec::new()), layout()).unwrap();
+        assert_eq!(starts(&reflow.pages), [(0, 0), (2, 0), (3, 0), (4, 0)]);
+        assert_eq!(reflow.page_for_source(2), 2);
+        assert_eq!(reflow.book_info(&info(Vec::new())).toc[0].page_index, 2);
+
+        let page = reflow.page(&mut book, 2).unwrap();
+        assert!(matches!(
+            &page.ops[..],
+            [TrbkOp::TextRun { text, .. }] if text == "prerendered 2"
+        ));
+    }
+
+    #[test]
+    fn a_page_draws_its_lines_at_the_baselines() {
+        let words = ["aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa aaaaaaaa"];
+        let mut book = Text(vec![paragraph(0, &words)]);
+        let reflow = paginate(&mut book, &info(Vec::new()), layout()).unwrap();
+        let page = reflow.page(&mut book, 1).unwrap();
+        let placed: Vec<(i32, i32)> = page
+            .ops
+            .iter()
+            .map(|op| match op {
+                TrbkOp::TextRun { x, y, .. } => (*x, *y),
+                TrbkOp::Image { x, y, .. } => (*x, *y),
+            })
+            .collect();
+        assert_eq!(placed, [(10, 25), (10, 45)]);
+        assert!(reflow.page(&mut book, 2).is_err());
+    }
+
+    #[test]
+    fn reader_margins_and_drawn_size_change_the_layout() {
+        let book = info(vec![glyph('n', 20)]);
+        let reading = ReadingLayout {
+            text_size: 0,
+            margins: 1,
+        };
+        let layout = ReflowLayout::for_book(&book, reading);
+        assert_eq!((layout.margin_x, layout.margin_y), (8, 32));
+        // Glyphs drawn twice the converted size double the line metrics.
+        assert_eq!((layout.line_height, layout.ascent), (40, 30));
+        assert_eq!(layout.word_spacing, 6);
+
+        let same = ReflowLayout::for_book(&info(vec![glyph('n', 10)]), ReadingLayout::default());
+        assert_eq!(same, ReflowLayout::converted(&info(Vec::new())));
+    }
+
+    #[test]
+    fn text_that_lays_out_to_nothing_is_an_error() {
+        let mut book = Text(vec![item(0, TextItemKind::PageBreak)]);
+        assert!(paginate(&mut book, &info(Vec::new()), layout()).is_err());
+    }
+}
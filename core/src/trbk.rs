@@ -1,10 +1,89 @@
 extern crate alloc;
 
+use alloc::format;
 use alloc::rc::Rc;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-use crate::image_viewer::ImageError;
+use crate::image_viewer::{ImageData, ImageError, ImageInfo};
+
+/// TRBK and TRI versions this reader opens, oldest and newest.
+pub const TRBK_VERSIONS: (u8, u8) = (1, 3);
+pub const TRIMG_VERSIONS: (u8, u8) = (1, 2);
+
+pub const TRBK_FLAG_MULTIPART: u8 = 0x01;
+pub const TRBK_FLAG_SERIES: u8 = 0x02;
+/// Glyph records whose bitmap length has the top bit set reuse the bitmap of
+/// the earlier glyph at the index in the remaining bits.
+pub const TRBK_FLAG_SHARED_GLYPHS: u8 = 0x04;
+pub const TRBK_SHARED_GLYPH_REF: u32 = 0x8000_0000;
+/// The book takes some glyph bitmaps from a shared font pack (`.trfont`),
+/// named in the metadata after the series.
+pub const TRBK_FLAG_FONT_PACK: u8 = 0x08;
+/// Bitmap length of a glyph whose bitmap is in the font pack. The record keeps
+/// its metrics, so pages still lay out when the pack is missing.
+pub const TRBK_FONT_PACK_GLYPH: u32 = 0xFFFF_FFFF;
+/// Directory on the SD card that font packs are loaded from.
+pub const FONT_PACK_DIR: &str = "fonts";
+
+/// Capability bits in the u32 at header offset 0x24. The low half names
+/// features a reader must understand to show the book at all; the high half
+/// names extras it may go without. Books from before the field have it zero
+/// and say what they use in the flags byte instead.
+pub const TRBK_CAP_SHARED_GLYPHS: u32 = 0x0000_0001;
+pub const TRBK_CAP_FONT_PACK: u32 = 0x0000_0002;
+/// Page ops and glyph bitmaps are compressed.
+pub const TRBK_CAP_COMPRESSION: u32 = 0x0000_0004;
+/// A v3 text section for reflow; without it the prerendered pages are shown.
+pub const TRBK_CAP_REFLOW_TEXT: u32 = 0x0001_0000;
+/// Kerning pairs for reflow; without them reflowed text is not kerned.
+pub const TRBK_CAP_KERNING: u32 = 0x0002_0000;
+pub const TRBK_CAPS_REQUIRED: u32 = 0x0000_FFFF;
+/// Capabilities this firmware reads.
+pub const TRBK_CAPS_SUPPORTED: u32 =
+    TRBK_CAP_SHARED_GLYPHS | TRBK_CAP_FONT_PACK | TRBK_CAP_REFLOW_TEXT;
+
+/// Name of capability `bit` for messages and logs.
+pub fn capability_name(bit: u32) -> Option<&'static str> {
+    match bit {
+        TRBK_CAP_SHARED_GLYPHS => Some("shared glyphs"),
+        TRBK_CAP_FONT_PACK => Some("font packs"),
+        TRBK_CAP_COMPRESSION => Some("compression"),
+        TRBK_CAP_REFLOW_TEXT => Some("reflow text"),
+        TRBK_CAP_KERNING => Some("kerning"),
+        _ => None,
+    }
+}
+
+/// Refuses a book that needs a capability this firmware lacks, naming it,
+/// and logs the optional ones that will be left out.
+pub fn check_trbk_capabilities(capabilities: u32) -> Result<(), ImageError> {
+    let missing = capabilities & !TRBK_CAPS_SUPPORTED;
+    let required = missing & TRBK_CAPS_REQUIRED;
+    if required != 0 {
+        return Err(ImageError::Message(format!(
+            "Book uses {}; update the firmware to read it.",
+            capability_label(required)
+        )));
+    }
+    if missing != 0 {
+        log::info!(
+            "Book capabilities {:#010x}: showing it without {}",
+            capabilities,
+            capability_label(missing)
+        );
+    }
+    Ok(())
+}
+
+/// Name of the lowest capability set in `bits`.
+fn capability_label(bits: u32) -> String {
+    let lowest = bits & bits.wrapping_neg();
+    match capability_name(lowest) {
+        Some(name) => name.to_string(),
+        None => format!("feature bit {}", lowest.trailing_zeros()),
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct TrbkMetadata {
@@ -20,6 +99,37 @@ pub struct TrbkMetadata {
     pub margin_right: u16,
     pub margin_top: u16,
     pub margin_bottom: u16,
+    pub part: Option<TrbkPartInfo>,
+    pub series: Option<String>,
+    pub font_pack: Option<TrbkFontPackRef>,
+}
+
+/// The font pack a book takes glyph bitmaps from, by file name and the hash
+/// stored in the pack.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrbkFontPackRef {
+    pub name: String,
+    pub hash: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrbkPartInfo {
+    pub index: u16,
+    pub count: u16,
+    pub next: String,
+}
+
+impl TrbkPartInfo {
+    pub fn has_next(&self) -> bool {
+        self.index < self.count && !self.next.is_empty()
+    }
+}
+
+/// Where a v3 book keeps its logical text; see [`crate::reflow`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrbkTextInfo {
+    pub offset: u32,
+    pub item_count: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -32,6 +142,7 @@ pub struct TrbkBook {
     pub page_count: usize,
     pub toc: Vec<TrbkTocEntry>,
     pub images: Vec<TrbkImageInfo>,
+    pub text: Option<TrbkTextInfo>,
 }
 
 #[derive(Clone, Debug)]
@@ -43,6 +154,7 @@ pub struct TrbkBookInfo {
     pub glyphs: Rc<Vec<TrbkGlyph>>,
     pub toc: Vec<TrbkTocEntry>,
     pub images: Vec<TrbkImageInfo>,
+    pub text: Option<TrbkTextInfo>,
 }
 
 #[derive(Clone, Debug)]
@@ -91,15 +203,94 @@ pub struct TrbkImageInfo {
     pub height: u16,
 }
 
-pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
-    if data.len() < 0x2C || &data[0..4] != b"TRBK" {
-        return Err(ImageError::Decode);
+/// Checks the fixed header of a `file_len` byte book before anything else is
+/// read, so the error screen can say what is wrong with it: a format this
+/// firmware does not read, or the section where the file was cut short.
+/// `header` is at least the first 0x30 bytes of the file where there are
+/// that many; v3 text is only checked when the first 0x38 are there.
+pub fn check_trbk_header(header: &[u8], file_len: usize) -> Result<(), ImageError> {
+    let truncated = |section: &str| {
+        Err(ImageError::Message(format!("File is truncated at the {}.", section)))
+    };
+    if header.len() < 4 || &header[0..4] != b"TRBK" {
+        return Err(ImageError::Message("Not a TRBK book.".to_string()));
+    }
+    if header.len() < 6 {
+        return truncated("header");
+    }
+    let version = header[4];
+    if version < TRBK_VERSIONS.0 {
+        return Err(ImageError::Message(format!(
+            "Book format v{} is too old; convert it again.",
+            version
+        )));
+    }
+    if version > TRBK_VERSIONS.1 {
+        return Err(ImageError::Message(format!(
+            "Book requires newer firmware (format v{}, this reads up to v{}).",
+            version, TRBK_VERSIONS.1
+        )));
+    }
+    if header.len() < 0x2C || (version >= 2 && header.len() < 0x30) {
+        return truncated("header");
+    }
+    check_trbk_capabilities(read_u32(header, 0x24)?)?;
+    let header_size = read_u16(header, 0x06)? as usize;
+    let page_count = read_u32(header, 0x0C)? as usize;
+    let toc_count = read_u32(header, 0x10)? as usize;
+    let page_lut_offset = read_u32(header, 0x14)? as usize;
+    let toc_offset = read_u32(header, 0x18)? as usize;
+    let page_data_offset = read_u32(header, 0x1C)? as usize;
+    if header_size < metadata_offset(version) || header_size > file_len {
+        return truncated("header");
+    }
+    if toc_count != 0 && toc_offset > file_len {
+        return truncated("table of contents");
+    }
+    if page_lut_offset.saturating_add(page_count.saturating_mul(4)) > file_len {
+        return truncated("page table");
+    }
+    if page_data_offset > file_len {
+        return truncated("page data");
+    }
+    if version >= 2 {
+        let images_offset = read_u32(header, 0x20)? as usize;
+        if images_offset != 0 && images_offset.saturating_add(4) > file_len {
+            return truncated("image table");
+        }
+        let glyph_count = read_u32(header, 0x28)?;
+        let glyph_table_offset = read_u32(header, 0x2C)? as usize;
+        if glyph_count != 0 && glyph_table_offset > file_len {
+            return truncated("glyph table");
+        }
+    }
+    if version >= 3 && header.len() >= 0x38 {
+        let text_offset = read_u32(header, 0x30)? as usize;
+        if text_offset > file_len {
+            return truncated("text");
+        }
     }
+    Ok(())
+}
 
-    let version = data[4];
-    if version != 1 && version != 2 {
-        return Err(ImageError::Unsupported);
+/// Checks that a book was laid out for this screen, which is
+/// `framebuffer::HEIGHT` wide in the reader's portrait rotation.
+pub fn check_trbk_screen(screen_width: u16, screen_height: u16) -> Result<(), ImageError> {
+    let (width, height) = (crate::framebuffer::HEIGHT, crate::framebuffer::WIDTH);
+    if screen_width as usize == width && screen_height as usize == height {
+        return Ok(());
     }
+    Err(ImageError::Message(format!(
+        "This book was made for a {}x{} screen, not {}x{}.",
+        screen_width, screen_height, width, height
+    )))
+}
+
+pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
+    check_trbk_header(data, data.len())?;
+
+    let version = data[4];
+    let flags = data[5];
 
     let header_size = read_u16(data, 0x06)? as usize;
     let screen_width = read_u16(data, 0x08)?;
@@ -130,7 +321,9 @@ pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
         return Err(ImageError::Decode);
     }
 
-    let mut cursor = if version >= 2 { 0x30 } else { 0x2C };
+    let text = parse_text_info(data, version)?;
+
+    let mut cursor = metadata_offset(version);
     let title = read_string(data, &mut cursor)?;
     let author = read_string(data, &mut cursor)?;
     let language = read_string(data, &mut cursor)?;
@@ -158,6 +351,9 @@ pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
     if cursor > data.len() || cursor > header_size {
         return Err(ImageError::Decode);
     }
+    let part = parse_part_info(&data[..header_size], cursor, flags);
+    let series = parse_series(&data[..header_size], cursor, flags);
+    let font_pack = parse_font_pack_ref(&data[..header_size], cursor, flags);
 
     let toc = if toc_count > 0 {
         parse_trbk_toc(data, toc_offset as usize, toc_count)?
@@ -200,7 +396,7 @@ pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
     }
 
     let glyphs = if version >= 2 && glyph_count > 0 {
-        Rc::new(parse_glyphs(data, glyph_table_offset, glyph_count)?)
+        Rc::new(parse_glyphs(data, glyph_table_offset, glyph_count, flags)?)
     } else {
         Rc::new(Vec::new())
     };
@@ -222,14 +418,39 @@ pub fn parse_trbk(data: &[u8]) -> Result<TrbkBook, ImageError> {
             margin_right,
             margin_top,
             margin_bottom,
+            part,
+            series,
+            font_pack,
         },
         glyphs,
         page_count,
         toc,
         images,
+        text,
     })
 }
 
+/// Start of the metadata block, after the fixed header of `version`.
+pub fn metadata_offset(version: u8) -> usize {
+    match version {
+        1 => 0x2C,
+        2 => 0x30,
+        _ => 0x38,
+    }
+}
+
+/// Text section offset and item count from a v3 fixed header; `None` for
+/// older versions.
+pub fn parse_text_info(header: &[u8], version: u8) -> Result<Option<TrbkTextInfo>, ImageError> {
+    if version < 3 {
+        return Ok(None);
+    }
+    Ok(Some(TrbkTextInfo {
+        offset: read_u32(header, 0x30)?,
+        item_count: read_u32(header, 0x34)?,
+    }))
+}
+
 impl TrbkBook {
     pub fn info(&self) -> TrbkBookInfo {
         TrbkBookInfo {
@@ -240,10 +461,58 @@ impl TrbkBook {
             glyphs: self.glyphs.clone(),
             toc: self.toc.clone(),
             images: self.images.clone(),
+            text: self.text,
         }
     }
 }
 
+/// Continuation metadata for books split into `name.partN.trbk` sets. It
+/// follows the margins in the metadata block when `TRBK_FLAG_MULTIPART` is set.
+pub fn parse_part_info(header: &[u8], cursor: usize, flags: u8) -> Option<TrbkPartInfo> {
+    if flags & TRBK_FLAG_MULTIPART == 0 {
+        return None;
+    }
+    let mut cursor = cursor;
+    let index = read_u16_from(header, &mut cursor).ok()?;
+    let count = read_u16_from(header, &mut cursor).ok()?;
+    let next = read_string(header, &mut cursor).ok()?;
+    Some(TrbkPartInfo { index, count, next })
+}
+
+/// Series name, stored after the part info (if any) when `TRBK_FLAG_SERIES`
+/// is set. `cursor` is the same offset `parse_part_info` takes.
+pub fn parse_series(header: &[u8], cursor: usize, flags: u8) -> Option<String> {
+    if flags & TRBK_FLAG_SERIES == 0 {
+        return None;
+    }
+    let mut cursor = cursor;
+    if flags & TRBK_FLAG_MULTIPART != 0 {
+        cursor += 4;
+        read_string(header, &mut cursor).ok()?;
+    }
+    read_string(header, &mut cursor).ok()
+}
+
+/// Font pack reference, stored after the series name (if any) when
+/// `TRBK_FLAG_FONT_PACK` is set. `cursor` is the same offset `parse_part_info`
+/// takes.
+pub fn parse_font_pack_ref(header: &[u8], cursor: usize, flags: u8) -> Option<TrbkFontPackRef> {
+    if flags & TRBK_FLAG_FONT_PACK == 0 {
+        return None;
+    }
+    let mut cursor = cursor;
+    if flags & TRBK_FLAG_MULTIPART != 0 {
+        cursor += 4;
+        read_string(header, &mut cursor).ok()?;
+    }
+    if flags & TRBK_FLAG_SERIES != 0 {
+        read_string(header, &mut cursor).ok()?;
+    }
+    let name = read_string(header, &mut cursor).ok()?;
+    let hash = read_u32(header, cursor).ok()?;
+    Some(TrbkFontPackRef { name, hash })
+}
+
 fn parse_trbk_toc(
     data: &[u8],
     offset: usize,
@@ -421,6 +690,7 @@ fn parse_glyphs(
     data: &[u8],
     offset: usize,
     count: usize,
+    flags: u8,
 ) -> Result<Vec<TrbkGlyph>, ImageError> {
     if offset > data.len() {
         return Err(ImageError::Decode);
@@ -445,8 +715,31 @@ fn parse_glyphs(
         cursor += 2;
         let y_offset = i16::from_le_bytes([data[cursor], data[cursor + 1]]);
         cursor += 2;
-        let bitmap_len = read_u32(data, cursor)? as usize;
+        let bitmap_len = read_u32(data, cursor)?;
         cursor += 4;
+        if flags & TRBK_FLAG_FONT_PACK != 0 && bitmap_len == TRBK_FONT_PACK_GLYPH {
+            glyphs.push(TrbkGlyph {
+                codepoint,
+                style,
+                width,
+                height,
+                x_advance,
+                x_offset,
+                y_offset,
+                bitmap_bw: Vec::new(),
+                bitmap_lsb: None,
+                bitmap_msb: None,
+            });
+            continue;
+        }
+        if flags & TRBK_FLAG_SHARED_GLYPHS != 0 && bitmap_len & TRBK_SHARED_GLYPH_REF != 0 {
+            let source = glyphs
+                .get((bitmap_len & !TRBK_SHARED_GLYPH_REF) as usize)
+                .ok_or(ImageError::Decode)?;
+            glyphs.push(shared_glyph(source, codepoint, style, x_advance, x_offset, y_offset));
+            continue;
+        }
+        let bitmap_len = bitmap_len as usize;
         if cursor + bitmap_len > data.len() {
             return Err(ImageError::Decode);
         }
@@ -476,3 +769,234 @@ fn parse_glyphs(
     }
     Ok(glyphs)
 }
+
+/// Header of a font pack: magic `TRFN`, version, reserved byte, font size
+/// (u16), hash (u32), glyph count (u32), char width (u16) and two reserved
+/// bytes, followed by glyph records laid out as in the TRBK glyph table.
+pub const FONT_PACK_HEADER_LEN: usize = 20;
+pub const FONT_PACK_EXTENSION: &str = "trfont";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrbkFontPackHeader {
+    pub size: u16,
+    pub hash: u32,
+    pub glyph_count: usize,
+    /// Advance of the regular `n`, comparable to [`TrbkMetadata::char_width`].
+    pub char_width: u16,
+}
+
+pub fn parse_font_pack_header(header: &[u8]) -> Result<TrbkFontPackHeader, ImageError> {
+    if header.len() < FONT_PACK_HEADER_LEN || &header[0..4] != b"TRFN" {
+        return Err(ImageError::Decode);
+    }
+    if header[4] != 1 {
+        return Err(ImageError::Unsupported);
+    }
+    Ok(TrbkFontPackHeader {
+        size: read_u16(header, 6)?,
+        hash: read_u32(header, 8)?,
+        glyph_count: read_u32(header, 12)? as usize,
+        char_width: read_u16(header, 16)?,
+    })
+}
+
+/// Typeface a pack file renders, from its `<typeface>-<size>-<hash>.trfont`
+/// name.
+pub fn font_pack_typeface(file_name: &str) -> Option<&str> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if !extension.eq_ignore_ascii_case(FONT_PACK_EXTENSION) {
+        return None;
+    }
+    let mut parts = stem.rsplitn(3, '-');
+    let _hash = parts.next()?;
+    let _size = parts.next()?;
+    parts.next().filter(|typeface| !typeface.is_empty())
+}
+
+/// Of the packs of one typeface, given as file name and header, the one whose
+/// size is closest to a book laid out with `char_width`.
+pub fn pick_reading_font_pack(
+    packs: &[(String, TrbkFontPackHeader)],
+    char_width: u16,
+) -> Option<&str> {
+    packs
+        .iter()
+        .min_by_key(|(_, header)| header.char_width.abs_diff(char_width))
+        .map(|(name, _)| name.as_str())
+}
+
+/// Fills in the bitmaps of `glyphs` that the book left to the font pack in
+/// `pack`. Returns how many glyphs are still missing; packs with another hash
+/// than the book expects are rejected.
+pub fn apply_font_pack(
+    glyphs: &mut [TrbkGlyph],
+    pack: &[u8],
+    reference: &TrbkFontPackRef,
+) -> Result<usize, ImageError> {
+    let header = parse_font_pack_header(pack)?;
+    if header.hash != reference.hash {
+        return Err(ImageError::Message("font pack does not match the book".into()));
+    }
+    for pack_glyph in parse_glyphs(pack, FONT_PACK_HEADER_LEN, header.glyph_count, 0)? {
+        fill_pack_glyph(glyphs, pack_glyph);
+    }
+    Ok(glyphs.iter().filter(|glyph| needs_pack_bitmap(glyph)).count())
+}
+
+/// Every glyph of font `pack`, for a book that brings none of its own.
+pub fn font_pack_glyphs(pack: &[u8]) -> Result<Vec<TrbkGlyph>, ImageError> {
+    let header = parse_font_pack_header(pack)?;
+    parse_glyphs(pack, FONT_PACK_HEADER_LEN, header.glyph_count, 0)
+}
+
+/// Draws the book in the typeface of `pack` instead of its own: every glyph
+/// the pack has replaces the book's, metrics included. Pages keep their
+/// layout, so each word still starts where the book put it. Returns how many
+/// glyphs were replaced.
+pub fn apply_reading_font(glyphs: &mut [TrbkGlyph], pack: &[u8]) -> Result<usize, ImageError> {
+    let header = parse_font_pack_header(pack)?;
+    let mut replaced = 0;
+    for pack_glyph in parse_glyphs(pack, FONT_PACK_HEADER_LEN, header.glyph_count, 0)? {
+        if replace_glyph(glyphs, pack_glyph) {
+            replaced += 1;
+        }
+    }
+    Ok(replaced)
+}
+
+/// Swaps `pack_glyph` in for the book glyph with the same codepoint and style.
+pub fn replace_glyph(glyphs: &mut [TrbkGlyph], pack_glyph: TrbkGlyph) -> bool {
+    match glyphs
+        .iter_mut()
+        .find(|glyph| glyph.codepoint == pack_glyph.codepoint && glyph.style == pack_glyph.style)
+    {
+        Some(glyph) => {
+            *glyph = pack_glyph;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether `glyph` is waiting for its bitmap from a font pack.
+pub fn needs_pack_bitmap(glyph: &TrbkGlyph) -> bool {
+    glyph.bitmap_bw.is_empty() && glyph.width > 0 && glyph.height > 0
+}
+
+/// Gives the book glyph with the same codepoint and style the bitmap of
+/// `pack_glyph`, when it is waiting for one of that size.
+pub fn fill_pack_glyph(glyphs: &mut [TrbkGlyph], pack_glyph: TrbkGlyph) {
+    if let Some(glyph) = glyphs.iter_mut().find(|glyph| {
+        glyph.codepoint == pack_glyph.codepoint
+            && glyph.style == pack_glyph.style
+            && glyph.width == pack_glyph.width
+            && glyph.height == pack_glyph.height
+            && needs_pack_bitmap(glyph)
+    }) {
+        glyph.bitmap_bw = pack_glyph.bitmap_bw;
+        glyph.bitmap_lsb = pack_glyph.bitmap_lsb;
+        glyph.bitmap_msb = pack_glyph.bitmap_msb;
+    }
+}
+
+/// A glyph drawn with the bitmap of an earlier one (`TRBK_FLAG_SHARED_GLYPHS`).
+pub fn shared_glyph(
+    source: &TrbkGlyph,
+    codepoint: u32,
+    style: u8,
+    x_advance: i16,
+    x_offset: i16,
+    y_offset: i16,
+) -> TrbkGlyph {
+    TrbkGlyph {
+        codepoint,
+        style,
+        x_advance,
+        x_offset,
+        y_offset,
+        ..source.clone()
+    }
+}
+
+/// Capture date tern-image stored in a TRI header, packed like a FAT
+/// timestamp (years since 1980, month, day, hour, minute, seconds / 2) so
+/// later dates compare greater. Images converted without EXIF data have none.
+pub fn trimg_capture_date(header: &[u8]) -> Option<u32> {
+    if header.len() < 16 || &header[0..4] != b"TRIM" {
+        return None;
+    }
+    let date = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+    (date != 0).then_some(date)
+}
+
+/// Length of the metadata section that follows a TRI image's pixels.
+pub fn trimg_metadata_len(header: &[u8]) -> usize {
+    if header.len() < 16 {
+        return 0;
+    }
+    u16::from_le_bytes([header[10], header[11]]) as usize
+}
+
+/// Details from a TRI header and its metadata section: tag, u16 length and
+/// value entries for the source name (1), conversion date (2) and
+/// description (3). Unknown tags are skipped.
+pub fn parse_trimg_info(header: &[u8], mut metadata: &[u8]) -> ImageInfo {
+    let mut info = ImageInfo {
+        captured: trimg_capture_date(header),
+        ..ImageInfo::default()
+    };
+    while metadata.len() >= 3 {
+        let tag = metadata[0];
+        let len = u16::from_le_bytes([metadata[1], metadata[2]]) as usize;
+        let Some(value) = metadata.get(3..3 + len) else {
+            break;
+        };
+        match tag {
+            1 => info.source_name = Some(String::from_utf8_lossy(value).into_owned()),
+            2 => info.converted = value.try_into().ok().map(u32::from_le_bytes),
+            3 => info.description = Some(String::from_utf8_lossy(value).into_owned()),
+            _ => {}
+        }
+        metadata = &metadata[3 + len..];
+    }
+    info
+}
+
+/// Decodes a TRI image, standalone or embedded in a book (Mono1 and Gray2 only).
+pub fn parse_trimg(data: &[u8]) -> Result<ImageData, ImageError> {
+    if data.len() < 16 || &data[0..4] != b"TRIM" {
+        return Err(ImageError::Decode);
+    }
+    let width = u16::from_le_bytes([data[6], data[7]]) as u32;
+    let height = u16::from_le_bytes([data[8], data[9]]) as u32;
+    let end = data
+        .len()
+        .checked_sub(trimg_metadata_len(data))
+        .filter(|&end| end >= 16)
+        .ok_or(ImageError::Decode)?;
+    let payload = &data[16..end];
+    let plane = (width as usize * height as usize).div_ceil(8);
+    match (data[4], data[5]) {
+        (1, 1) => {
+            if payload.len() != plane {
+                return Err(ImageError::Decode);
+            }
+            Ok(ImageData::Mono1 {
+                width,
+                height,
+                bits: payload.to_vec(),
+            })
+        }
+        (2, 2) => {
+            if payload.len() != plane * 3 {
+                return Err(ImageError::Decode);
+            }
+            Ok(ImageData::Gray2 {
+                width,
+                height,
+                data: payload.to_vec(),
+            })
+        }
+        _ => Err(ImageError::Unsupported),
+    }
+}
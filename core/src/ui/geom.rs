@@ -65,3 +65,9 @@ impl Rect {
         Some(Rect::new(x0, y0, x1 - x0, y1 - y0))
     }
 }
+
+/// `per_mille` of `total`, kept within `min..=max`, so layouts scale with the
+/// panel without shrinking below what text and icons need.
+pub fn relative(total: i32, per_mille: i32, min: i32, max: i32) -> i32 {
+    (total * per_mille / 1000).clamp(min, max.max(min))
+}
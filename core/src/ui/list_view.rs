@@ -1,3 +1,7 @@
+extern crate alloc;
+
+use alloc::{borrow::Cow, format};
+
 use embedded_graphics::{
     draw_target::DrawTarget,
     geometry::Size,
@@ -12,10 +16,52 @@ use embedded_graphics::{
 use super::geom::Rect;
 use super::view::{RenderQueue, UiContext, View};
 
+const CHAR_WIDTH: i32 = 10;
+const ELLIPSIS: &str = "...";
+/// Panel the list chrome constants are drawn against (portrait X4).
+const REFERENCE_WIDTH: i32 = 480;
+const REFERENCE_HEIGHT: i32 = 800;
+/// A 10x20 row, with room for the selection bar above the baseline.
+const MIN_LINE_HEIGHT: i32 = 22;
+const MIN_HEADER_Y: i32 = 20;
+const MIN_MARGIN_X: i32 = 8;
+
 pub struct ListItem<'a> {
     pub label: &'a str,
 }
 
+/// Margins and rows of a list screen. Callers write them for the portrait
+/// 480x800 panel and [`ListMetrics::scaled`] fits them to the real one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListMetrics {
+    pub margin_x: i32,
+    pub header_y: i32,
+    pub list_top: i32,
+    pub line_height: i32,
+}
+
+impl ListMetrics {
+    /// These metrics scaled from the reference panel to `rect`. Rows and the
+    /// header never shrink below the font and grow at most by half, so short
+    /// landscape panels keep readable rows and tall ones show more of them.
+    pub fn scaled(self, rect: Rect) -> Self {
+        let vertical = |value: i32, min: i32| {
+            (value * rect.h / REFERENCE_HEIGHT).clamp(min, (value * 3 / 2).max(min))
+        };
+        let line_height = vertical(self.line_height, MIN_LINE_HEIGHT);
+        let header_y = vertical(self.header_y, MIN_HEADER_Y);
+        let list_top = header_y + vertical(self.list_top - self.header_y, line_height);
+        let margin_x = (self.margin_x * rect.w.min(rect.h) / REFERENCE_WIDTH)
+            .clamp(MIN_MARGIN_X, (self.margin_x * 3 / 2).max(MIN_MARGIN_X));
+        Self {
+            margin_x,
+            header_y,
+            list_top,
+            line_height,
+        }
+    }
+}
+
 pub struct ListView<'a> {
     pub title: Option<&'a str>,
     pub footer: Option<&'a str>,
@@ -26,6 +72,8 @@ pub struct ListView<'a> {
     pub header_y: i32,
     pub list_top: i32,
     pub line_height: i32,
+    /// Space left before each label, e.g. for icons the caller draws.
+    pub label_indent: i32,
     pub clear: bool,
 }
 
@@ -41,9 +89,72 @@ impl<'a> ListView<'a> {
             header_y: 24,
             list_top: 60,
             line_height: 24,
+            label_indent: 0,
             clear: true,
         }
     }
+
+    /// Takes margins and rows from `metrics`.
+    pub fn set_metrics(&mut self, metrics: ListMetrics) {
+        self.margin_x = metrics.margin_x;
+        self.header_y = metrics.header_y;
+        self.list_top = metrics.list_top;
+        self.line_height = metrics.line_height;
+    }
+
+    /// Items shown in `rect`: a window around the selection.
+    pub fn visible_range(&self, rect: Rect) -> core::ops::Range<usize> {
+        let mut max_lines = ((rect.h - self.list_top - 40) / self.line_height).max(1) as usize;
+        if self.selected_wraps(rect) && max_lines > 1 {
+            max_lines -= 1;
+        }
+        let start = self.selected.saturating_sub(max_lines / 2);
+        let end = (start + max_lines).min(self.items.len());
+        start..end
+    }
+
+    /// Baseline of the first line of visible item `index`.
+    pub fn row_y(&self, rect: Rect, index: usize) -> i32 {
+        let start = self.visible_range(rect).start;
+        let mut y = self.list_top + (index.saturating_sub(start) as i32 * self.line_height);
+        if index > self.selected && self.selected >= start && self.selected_wraps(rect) {
+            y += self.line_height;
+        }
+        y
+    }
+
+    /// Characters of a label that fit on one row.
+    fn max_chars(&self, rect: Rect) -> usize {
+        ((rect.w - self.margin_x * 2 - self.label_indent) / CHAR_WIDTH).max(1) as usize
+    }
+
+    /// The selected label is too long for one row and takes two.
+    fn selected_wraps(&self, rect: Rect) -> bool {
+        self.items
+            .get(self.selected)
+            .is_some_and(|item| item.label.chars().count() > self.max_chars(rect))
+    }
+}
+
+/// `text` cut to `max_chars`, ending in an ellipsis when shortened.
+pub fn ellipsize(text: &str, max_chars: usize) -> Cow<'_, str> {
+    if text.chars().count() <= max_chars {
+        return Cow::Borrowed(text);
+    }
+    let keep = max_chars.saturating_sub(ELLIPSIS.len());
+    let end = text.char_indices().nth(keep).map(|(at, _)| at).unwrap_or(text.len());
+    Cow::Owned(format!("{}{}", text[..end].trim_end(), ELLIPSIS))
+}
+
+/// Splits `text` over at most two lines of `max_chars`, at the last space
+/// that fits when there is one. The second line is ellipsized.
+pub fn wrap_two_lines(text: &str, max_chars: usize) -> (&str, Option<Cow<'_, str>>) {
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return (text, None);
+    };
+    let split = text[..=cut].rfind(' ').filter(|&at| at > 0).unwrap_or(cut);
+    let rest = text[split..].trim_start();
+    (&text[..split], Some(ellipsize(rest, max_chars)))
 }
 
 impl View for ListView<'_> {
@@ -75,27 +186,36 @@ impl View for ListView<'_> {
             .draw(ctx.buffers)
             .ok();
         } else {
-            let max_lines = ((rect.h - self.list_top - 40) / self.line_height).max(1) as usize;
-            let start = self.selected.saturating_sub(max_lines / 2);
-            let end = (start + max_lines).min(self.items.len());
+            let range = self.visible_range(rect);
+            let label_x = self.margin_x + self.label_indent;
+            let max_chars = self.max_chars(rect);
 
-            for (idx, item) in self.items[start..end].iter().enumerate() {
-                let actual_idx = start + idx;
-                let y = self.list_top + (idx as i32 * self.line_height);
+            for actual_idx in range {
+                let item = &self.items[actual_idx];
+                let y = self.row_y(rect, actual_idx);
                 if actual_idx == self.selected {
+                    // The selection shows its whole label, over two rows if needed.
+                    let (first, second) = wrap_two_lines(item.label, max_chars);
+                    let rows = if second.is_some() { 2 } else { 1 };
                     Rectangle::new(
                         Point::new(rect.x, y - 18),
-                        Size::new(rect.w as u32, self.line_height as u32),
+                        Size::new(rect.w as u32, (self.line_height * rows) as u32),
                     )
                     .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
                     .draw(ctx.buffers)
                     .ok();
                     let selected_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
-                    Text::new(item.label, Point::new(self.margin_x, y), selected_style)
+                    Text::new(first, Point::new(label_x, y), selected_style)
                         .draw(ctx.buffers)
                         .ok();
+                    if let Some(second) = second {
+                        Text::new(&second, Point::new(label_x, y + self.line_height), selected_style)
+                            .draw(ctx.buffers)
+                            .ok();
+                    }
                 } else {
-                    Text::new(item.label, Point::new(self.margin_x, y), header_style)
+                    let label = ellipsize(item.label, max_chars);
+                    Text::new(&label, Point::new(label_x, y), header_style)
                         .draw(ctx.buffers)
                         .ok();
                 }
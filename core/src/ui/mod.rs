@@ -1,11 +1,13 @@
 pub mod geom;
 pub mod list_view;
+pub mod progress_view;
 pub mod reader_view;
 pub mod text_view;
 pub mod view;
 
-pub use geom::{Point, Rect, Size};
-pub use list_view::{ListItem, ListView};
+pub use geom::{relative, Point, Rect, Size};
+pub use list_view::{ellipsize, wrap_two_lines, ListItem, ListMetrics, ListView};
+pub use progress_view::ProgressView;
 pub use reader_view::ReaderView;
 pub use text_view::TextView;
 pub use view::{flush_queue, RenderQueue, UiContext, View};
@@ -0,0 +1,54 @@
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::{Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
+    Drawable,
+};
+
+use super::geom::Rect;
+use super::view::{RenderQueue, UiContext, View};
+
+/// Outlined bar filled in proportion to `done` of `total`.
+pub struct ProgressView {
+    pub done: u64,
+    pub total: u64,
+    pub refresh: crate::display::RefreshMode,
+}
+
+impl ProgressView {
+    pub fn new(done: u64, total: u64) -> Self {
+        Self {
+            done,
+            total,
+            refresh: crate::display::RefreshMode::Fast,
+        }
+    }
+}
+
+impl View for ProgressView {
+    fn render(&mut self, ctx: &mut UiContext<'_>, rect: Rect, rq: &mut RenderQueue) {
+        let outline = Rectangle::new(Point::new(rect.x, rect.y), Size::new(rect.w as u32, rect.h as u32));
+        outline
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(ctx.buffers)
+            .ok();
+        outline
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+            .draw(ctx.buffers)
+            .ok();
+        let inner = (rect.w - 8).max(0) as u64;
+        let filled = (inner * self.done.min(self.total))
+            .checked_div(self.total)
+            .unwrap_or(0);
+        if filled > 0 {
+            Rectangle::new(
+                Point::new(rect.x + 4, rect.y + 4),
+                Size::new(filled as u32, (rect.h - 8).max(0) as u32),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(ctx.buffers)
+            .ok();
+        }
+        rq.push(rect, self.refresh);
+    }
+}
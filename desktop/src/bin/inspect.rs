@@ -0,0 +1,182 @@
+//! Looks inside TRBK books when the source they were converted from is gone.
+//!
+//! `text` rebuilds plain text from the page ops: runs on one baseline make a
+//! line, and lines are joined into paragraphs that end at a blank line, an
+//! image or a line stopping well short of the right margin.
+
+use std::collections::HashMap;
+use std::env;
+use std::ops::Range;
+use std::process::ExitCode;
+
+use tern_core::{
+    image_viewer::ImageError,
+    trbk::{parse_trbk, TrbkBook, TrbkOp},
+};
+
+const USAGE: &str = "Usage: trbk-inspect text <book.trbk> [--pages N-M]";
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 2 || args[0] != "text" {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let book_path = &args[1];
+    let mut pages = None;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--pages" => {
+                i += 1;
+                pages = args.get(i).and_then(|s| parse_page_range(s));
+                if pages.is_none() {
+                    eprintln!("--pages takes a page or a range of pages, e.g. 10-20");
+                    return ExitCode::FAILURE;
+                }
+            }
+            other => {
+                eprintln!("unknown option {other}\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+        i += 1;
+    }
+
+    let data = match std::fs::read(book_path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("failed to read {book_path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let book = match parse_trbk(&data) {
+        Ok(book) => book,
+        Err(err) => {
+            eprintln!("{book_path}: {}", describe_error(&err));
+            return ExitCode::FAILURE;
+        }
+    };
+    let page_count = book.pages.len();
+    let (first, last) = pages.unwrap_or((1, page_count));
+    if first > page_count {
+        eprintln!("{book_path} has {page_count} pages");
+        return ExitCode::FAILURE;
+    }
+    print!("{}", book_text(&book, first - 1..last.min(page_count)));
+    ExitCode::SUCCESS
+}
+
+/// `N` or `N-M`, counting pages from 1.
+fn parse_page_range(value: &str) -> Option<(usize, usize)> {
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first = first.trim().parse::<usize>().ok()?;
+    let last = last.trim().parse::<usize>().ok()?;
+    (first >= 1 && first <= last).then_some((first, last))
+}
+
+fn describe_error(err: &ImageError) -> String {
+    match err {
+        ImageError::Message(message) => message.clone(),
+        _ => format!("not a readable TRBK book ({err:?})"),
+    }
+}
+
+/// Text of `pages`, one paragraph per line with blank lines between them.
+fn book_text(book: &TrbkBook, pages: Range<usize>) -> String {
+    let metadata = &book.metadata;
+    let advances = book
+        .glyphs
+        .iter()
+        .map(|glyph| ((glyph.codepoint, glyph.style), glyph.x_advance as i32))
+        .collect::<HashMap<_, _>>();
+    let left = metadata.margin_left as i32;
+    let right = book.screen_width as i32 - metadata.margin_right as i32;
+    let mut text = TextBuilder {
+        paragraphs: Vec::new(),
+        paragraph: String::new(),
+        line: String::new(),
+        line_y: None,
+        line_end: 0,
+        // A wider gap than one line is a blank line between paragraphs.
+        max_line_gap: metadata.line_height.max(1) as i32 * 3 / 2,
+        short_line: left + (right - left) * 3 / 4,
+    };
+    for page in &book.pages[pages] {
+        for op in &page.ops {
+            match op {
+                TrbkOp::TextRun { x, y, style, text: run } => {
+                    let width = run
+                        .chars()
+                        .map(|ch| advances.get(&(ch as u32, *style)).copied().unwrap_or(0))
+                        .sum::<i32>();
+                    text.push_run(*x, *y, run, width);
+                }
+                TrbkOp::Image { .. } => {
+                    text.end_line();
+                    text.end_paragraph();
+                    text.line_y = None;
+                }
+            }
+        }
+        text.end_line();
+        text.line_y = None;
+    }
+    text.end_line();
+    text.end_paragraph();
+    let mut out = text.paragraphs.join("\n\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+struct TextBuilder {
+    paragraphs: Vec<String>,
+    paragraph: String,
+    line: String,
+    /// Baseline of `line`; `None` at the top of a page.
+    line_y: Option<i32>,
+    line_end: i32,
+    max_line_gap: i32,
+    short_line: i32,
+}
+
+impl TextBuilder {
+    fn push_run(&mut self, x: i32, y: i32, run: &str, width: i32) {
+        match self.line_y {
+            Some(line_y) if line_y == y => {}
+            Some(line_y) => {
+                self.end_line();
+                if y - line_y > self.max_line_gap {
+                    self.end_paragraph();
+                }
+            }
+            None => {}
+        }
+        self.line_y = Some(y);
+        self.line.push_str(run);
+        self.line_end = x + width;
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        if !self.paragraph.is_empty() {
+            self.paragraph.push(' ');
+        }
+        self.paragraph.push_str(line);
+        if self.line_end < self.short_line {
+            self.end_paragraph();
+        }
+    }
+
+    fn end_paragraph(&mut self) {
+        if !self.paragraph.is_empty() {
+            self.paragraphs.push(std::mem::take(&mut self.paragraph));
+        }
+    }
+}
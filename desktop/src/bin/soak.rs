@@ -0,0 +1,246 @@
+//! Headless soak test for the book reader.
+//!
+//! Opens a TRBK, pages through it forward and backward several times and
+//! checks that nothing panics, that heap usage stays flat once caches are
+//! warm, and that every page renders to the same frame on every pass.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tern_core::{
+    app::book_reader::{BookReaderContext, BookReaderState},
+    framebuffer::{BUFFER_SIZE, DisplayBuffers, Rotation},
+    image_viewer::{EntryKind, ImageEntry, PersistenceSource},
+    input::Buttons,
+};
+use tern_desktop::{
+    headless::{press, HeadlessDisplay},
+    image_source::DesktopImageSource,
+};
+
+const DEVICE_HEAP_BYTES: usize = 300 * 1024;
+
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+struct Soak {
+    reader: BookReaderState,
+    source: DesktopImageSource,
+    display: HeadlessDisplay,
+    buffers: Box<DisplayBuffers>,
+    gray2_lsb: Vec<u8>,
+    gray2_msb: Vec<u8>,
+    full_refresh: bool,
+    checksums: Vec<Option<u64>>,
+}
+
+impl Soak {
+    fn draw(&mut self) -> Result<(), String> {
+        let mut ctx = BookReaderContext {
+            display_buffers: &mut self.buffers,
+            gray2_lsb: self.gray2_lsb.as_mut_slice(),
+            gray2_msb: self.gray2_msb.as_mut_slice(),
+            source: &mut self.source,
+            full_refresh: &mut self.full_refresh,
+            refresh_tuning: Default::default(),
+        };
+        self.reader
+            .draw_book(&mut ctx, &mut self.display)
+            .map_err(|err| format!("page {}: draw failed: {:?}", self.reader.current_page, err))?;
+        self.full_refresh = false;
+        let page = self.reader.current_page;
+        let checksum = self.display.checksum();
+        match self.checksums[page] {
+            Some(expected) if expected != checksum => Err(format!(
+                "page {}: frame checksum changed ({:016x} != {:016x})",
+                page, checksum, expected
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.checksums[page] = Some(checksum);
+                Ok(())
+            }
+        }
+    }
+
+    fn turn(&mut self, button: Buttons) -> Result<(), String> {
+        let buttons = press(button);
+        let result = self.reader.handle_view_input(&mut self.source, &buttons);
+        if result.exit || result.open_toc {
+            return Err(format!("unexpected navigation on {:?}", button as u8));
+        }
+        self.reader.take_page_turn_indicator();
+        self.draw()
+    }
+
+    fn pass(&mut self, page_count: usize) -> Result<(), String> {
+        for _ in 1..page_count {
+            self.turn(Buttons::Right)?;
+        }
+        for _ in 1..page_count {
+            self.turn(Buttons::Left)?;
+        }
+        if self.reader.current_page != 0 {
+            return Err(format!(
+                "expected to be back on page 1, ended on page {}",
+                self.reader.current_page + 1
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let Some(book_path) = args.first() else {
+        eprintln!("Usage: tern-soak <book.trbk> [--passes N] [--max-growth BYTES]");
+        return ExitCode::FAILURE;
+    };
+    let mut passes = 3usize;
+    let mut max_growth = 16 * 1024usize;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--passes" => {
+                i += 1;
+                passes = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(passes);
+            }
+            "--max-growth" => {
+                i += 1;
+                max_growth = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(max_growth);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let path = Path::new(book_path);
+    let root = path.parent().unwrap_or_else(|| Path::new("."));
+    let entry = ImageEntry {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        kind: EntryKind::File,
+    };
+
+    let mut buffers = Box::new(DisplayBuffers::default());
+    buffers.set_rotation(Rotation::Rotate90);
+    let mut soak = Soak {
+        reader: BookReaderState::new(),
+        source: DesktopImageSource::new(root),
+        display: HeadlessDisplay::new(),
+        buffers,
+        gray2_lsb: vec![0u8; BUFFER_SIZE],
+        gray2_msb: vec![0u8; BUFFER_SIZE],
+        full_refresh: true,
+        checksums: Vec::new(),
+    };
+
+    let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run(&mut soak, &entry, passes, max_growth)
+    }));
+    soak.reader.close(&mut soak.source);
+    match outcome {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(message)) => {
+            eprintln!("FAIL: {}", message);
+            ExitCode::FAILURE
+        }
+        Err(_) => {
+            eprintln!("FAIL: panic on page {}", soak.reader.current_page + 1);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(soak: &mut Soak, entry: &ImageEntry, passes: usize, max_growth: usize) -> Result<(), String> {
+    let before_open = CURRENT.load(Ordering::Relaxed);
+    let reading_layout = soak.source.load_reading_layout();
+    soak.reader
+        .open(
+            &mut soak.source,
+            &[],
+            entry,
+            &entry.name,
+            &BTreeMap::new(),
+            reading_layout,
+        )
+        .map_err(|err| format!("open failed: {:?}", err))?;
+    let page_count = soak
+        .reader
+        .current_book
+        .as_ref()
+        .map(|book| book.page_count)
+        .unwrap_or(0);
+    if page_count == 0 {
+        return Err("book has no pages".into());
+    }
+    soak.checksums = vec![None; page_count];
+    println!(
+        "{}: {} pages, {} bytes after open",
+        entry.name,
+        page_count,
+        CURRENT.load(Ordering::Relaxed).saturating_sub(before_open)
+    );
+
+    soak.draw()?;
+    // The first pass fills whatever caches the reader keeps.
+    soak.pass(page_count)?;
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    for pass in 1..=passes {
+        soak.pass(page_count)?;
+        let now = CURRENT.load(Ordering::Relaxed);
+        let growth = now.saturating_sub(baseline);
+        println!(
+            "pass {}/{}: heap {} bytes ({:+} since warm-up), peak {} bytes",
+            pass,
+            passes,
+            now,
+            now as i64 - baseline as i64,
+            PEAK.load(Ordering::Relaxed)
+        );
+        if growth > max_growth {
+            return Err(format!(
+                "heap grew by {} bytes after {} passes (limit {})",
+                growth, pass, max_growth
+            ));
+        }
+    }
+    let peak = PEAK.load(Ordering::Relaxed).saturating_sub(before_open);
+    if peak > DEVICE_HEAP_BYTES {
+        println!(
+            "note: peak book allocation {} bytes exceeds the {} byte device heap (the desktop source keeps the whole file in memory)",
+            peak, DEVICE_HEAP_BYTES
+        );
+    }
+    println!("OK: {} pages x {} passes, checksums stable", page_count, passes + 1);
+    Ok(())
+}
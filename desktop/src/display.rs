@@ -1,13 +1,29 @@
 use log::info;
 use tern_core::{
-    display::{GrayscaleMode, HEIGHT, RefreshMode, WIDTH},
+    display::{Frontlight, FrontlightLevel, GrayscaleMode, HEIGHT, RefreshMode, WIDTH},
     framebuffer::DisplayBuffers,
-    input::{ButtonState, Buttons},
+    input::{ButtonState, TouchInput, TouchZones},
 };
 
+use crate::keymap::KeyMap;
+
 const BUFFER_SIZE: usize = WIDTH * HEIGHT / 8;
 const DISPLAY_BUFFER_SIZE: usize = WIDTH * HEIGHT;
 
+// Rough panel timings used when latency emulation is on.
+const FULL_FLASH_MS: u64 = 450;
+const FULL_SETTLE_MS: u64 = 350;
+const PARTIAL_DELAY_MS: u64 = 250;
+const FLASH_COLOR: u32 = 0xFF808080;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplayOptions {
+    /// Integer upscaling of the 480x800 panel.
+    pub scale: usize,
+    /// Flash grey on full refreshes and hold partial updates back like the panel does.
+    pub emulate_latency: bool,
+}
+
 pub struct MinifbDisplay {
     is_grayscale: bool,
     // Simulated EInk buffers
@@ -17,6 +33,16 @@ pub struct MinifbDisplay {
     display_buffer: [u32; DISPLAY_BUFFER_SIZE],
     window: minifb::Window,
     buttons: ButtonState,
+    // The mouse stands in for a touch panel.
+    touch: TouchInput,
+    last_update: std::time::Instant,
+    keymap: KeyMap,
+    options: DisplayOptions,
+    scaled_buffer: Vec<u32>,
+    // Frame under a differential grayscale overlay.
+    gray_base: Vec<u32>,
+    // The window shows a full grayscale frame, so the next partial update must redraw everything.
+    gray_oneshot_shown: bool,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -33,7 +59,11 @@ enum BlitMode {
 }
 
 impl MinifbDisplay {
-    pub fn new(window: minifb::Window) -> Self {
+    pub fn new(window: minifb::Window, keymap: KeyMap, options: DisplayOptions) -> Self {
+        let options = DisplayOptions {
+            scale: options.scale.max(1),
+            ..options
+        };
         let mut ret = Self {
             is_grayscale: false,
             lsb_buffer: [0; BUFFER_SIZE],
@@ -41,6 +71,13 @@ impl MinifbDisplay {
             display_buffer: [0; DISPLAY_BUFFER_SIZE],
             window,
             buttons: ButtonState::default(),
+            touch: TouchInput::new(TouchZones::thirds(HEIGHT as u16)),
+            last_update: std::time::Instant::now(),
+            keymap,
+            options,
+            gray_base: vec![0xFFFFFFFF; DISPLAY_BUFFER_SIZE],
+            gray_oneshot_shown: false,
+            scaled_buffer: vec![0; DISPLAY_BUFFER_SIZE * options.scale.max(1) * options.scale.max(1)],
         };
 
         ret.display_buffer.fill(0xFFFFFFFF);
@@ -49,39 +86,73 @@ impl MinifbDisplay {
     }
 
     pub fn is_open(&self) -> bool {
-        self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape)
+        // Escape quits unless it has been bound to a button.
+        self.window.is_open()
+            && (self.keymap.is_bound(minifb::Key::Escape)
+                || !self.window.is_key_down(minifb::Key::Escape))
     }
 
     pub fn update_display(&mut self /*, window: &mut minifb::Window */) {
+        self.present(None);
+    }
+
+    /// Pushes the display buffer, or a solid `fill`, to the window at the configured scale.
+    fn present(&mut self, fill: Option<u32>) {
+        let scale = self.options.scale;
+        if scale == 1 && fill.is_none() {
+            self.window
+                .update_with_buffer(&self.display_buffer, HEIGHT, WIDTH)
+                .unwrap();
+            return;
+        }
+        let out_width = HEIGHT * scale;
+        for (y, row) in self.display_buffer.chunks_exact(HEIGHT).enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let color = fill.unwrap_or(*pixel);
+                for dy in 0..scale {
+                    let start = (y * scale + dy) * out_width + x * scale;
+                    self.scaled_buffer[start..start + scale].fill(color);
+                }
+            }
+        }
         self.window
-            .update_with_buffer(&self.display_buffer, HEIGHT, WIDTH)
+            .update_with_buffer(&self.scaled_buffer, out_width, WIDTH * scale)
             .unwrap();
     }
 
+    fn emulate_refresh(&mut self, mode: RefreshMode) {
+        if !self.options.emulate_latency {
+            return;
+        }
+        if mode == RefreshMode::Fast {
+            std::thread::sleep(std::time::Duration::from_millis(PARTIAL_DELAY_MS));
+        } else {
+            self.present(Some(FLASH_COLOR));
+            std::thread::sleep(std::time::Duration::from_millis(FULL_FLASH_MS));
+            self.present(Some(0xFFFFFFFF));
+            std::thread::sleep(std::time::Duration::from_millis(FULL_SETTLE_MS));
+        }
+    }
+
     pub fn update(&mut self) {
         self.window.update();
         let mut current: u8 = 0;
-        if self.window.is_key_down(minifb::Key::Left) {
-            current |= 1 << (Buttons::Left as u8);
-        }
-        if self.window.is_key_down(minifb::Key::Right) {
-            current |= 1 << (Buttons::Right as u8);
-        }
-        if self.window.is_key_down(minifb::Key::Up) {
-            current |= 1 << (Buttons::Up as u8);
-        }
-        if self.window.is_key_down(minifb::Key::Down) {
-            current |= 1 << (Buttons::Down as u8);
-        }
-        if self.window.is_key_down(minifb::Key::Enter) {
-            current |= 1 << (Buttons::Confirm as u8);
-        }
-        if self.window.is_key_down(minifb::Key::Backspace) {
-            current |= 1 << (Buttons::Back as u8);
-        }
-        if self.window.is_key_down(minifb::Key::P) {
-            current |= 1 << (Buttons::Power as u8);
+        for (key, button) in self.keymap.bindings() {
+            if self.window.is_key_down(key) {
+                current |= 1 << (button as u8);
+            }
         }
+        let touch = if self.window.get_mouse_down(minifb::MouseButton::Left) {
+            let scale = self.options.scale as f32;
+            self.window
+                .get_mouse_pos(minifb::MouseMode::Clamp)
+                .map(|(x, y)| ((x / scale) as u16, (y / scale) as u16))
+        } else {
+            None
+        };
+        let elapsed_ms = self.last_update.elapsed().as_millis() as u32;
+        self.last_update = std::time::Instant::now();
+        current |= self.touch.update(touch, elapsed_ms);
         self.buttons.update(current);
     }
 
@@ -127,22 +198,18 @@ impl MinifbDisplay {
                 }
             }
             BlitMode::Grayscale => {
+                // Shade on top of the black/white frame; keep it so the revert can restore it.
+                self.gray_base.copy_from_slice(&self.display_buffer);
                 for i in 0..self.lsb_buffer.len() {
                     let lsb_byte = self.lsb_buffer[i];
                     let msb_byte = self.msb_buffer[i];
                     for bit in 0..8 {
-                        let pixel_index = i * 8 + bit;
                         let lsb_bit = (lsb_byte >> (7 - bit)) & 0x01;
                         let msb_bit = (msb_byte >> (7 - bit)) & 0x01;
-                        let current_pixel = self.get_portrait_pixel(pixel_index);
-                        let new_pixel = match (msb_bit, lsb_bit) {
-                            (0, 0) => continue,
-                            (0, 1) => current_pixel.saturating_sub(0x555555), // Black -> Dark Gray
-                            (1, 0) => current_pixel.saturating_sub(0xAAAAAA), // Black -> Gray
-                            (1, 1) => current_pixel.saturating_add(0x333333), // White -> Light Gray
-                            _ => unreachable!(),
-                        };
-                        self.set_portrait_pixel(pixel_index, new_pixel);
+                        if lsb_bit == 0 && msb_bit == 0 {
+                            continue;
+                        }
+                        self.set_portrait_pixel(i * 8 + bit, gray2_shade(msb_bit, lsb_bit));
                     }
                 }
             }
@@ -151,39 +218,14 @@ impl MinifbDisplay {
                     let lsb_byte = self.lsb_buffer[i];
                     let msb_byte = self.msb_buffer[i];
                     for bit in 0..8 {
-                        let pixel_index = i * 8 + bit;
                         let lsb_bit = (lsb_byte >> (7 - bit)) & 0x01;
                         let msb_bit = (msb_byte >> (7 - bit)) & 0x01;
-                        let new_pixel = match (msb_bit, lsb_bit) {
-                            (0, 0) => 0xFFFFFFFF,
-                            (0, 1) => 0xFFAAAAAA,
-                            (1, 0) => 0xFF555555,
-                            (1, 1) => 0xFF000000,
-                            _ => unreachable!(),
-                        };
-                        self.set_portrait_pixel(pixel_index, new_pixel);
+                        self.set_portrait_pixel(i * 8 + bit, gray2_shade(msb_bit, lsb_bit));
                     }
                 }
             }
             BlitMode::GrayscaleRevert => {
-                for i in 0..self.lsb_buffer.len() {
-                    let lsb_byte = self.lsb_buffer[i];
-                    let msb_byte = self.msb_buffer[i];
-                    for bit in 0..8 {
-                        let pixel_index = i * 8 + bit;
-                        let lsb_bit = (lsb_byte >> (7 - bit)) & 0x01;
-                        let msb_bit = (msb_byte >> (7 - bit)) & 0x01;
-                        let current_pixel = self.get_portrait_pixel(pixel_index);
-                        let new_pixel = match (msb_bit, lsb_bit) {
-                            (0, 0) => continue,
-                            (0, 1) => current_pixel.saturating_add(0x555555), // Dark Gray  -> Black
-                            (1, 0) => current_pixel.saturating_add(0xAAAAAA), // Gray       -> Black
-                            (1, 1) => current_pixel.saturating_sub(0x333333), // Light Gray -> White
-                            _ => unreachable!(),
-                        };
-                        self.set_portrait_pixel(pixel_index, new_pixel);
-                    }
-                }
+                self.display_buffer.copy_from_slice(&self.gray_base);
             }
         }
         self.update_display();
@@ -204,23 +246,21 @@ impl MinifbDisplay {
             self.display_buffer[idx] = color;
         }
     }
+}
 
-    fn get_portrait_pixel(&self, landscape_index: usize) -> u32 {
-        let x_land = (landscape_index % WIDTH) as i32;
-        let y_land = (landscape_index / WIDTH) as i32;
-        let x_portrait = (HEIGHT as i32 - 1) - y_land;
-        let y_portrait = x_land;
-        if x_portrait < 0 || y_portrait < 0 {
-            return 0xFFFFFFFF;
-        }
-        let x_portrait = x_portrait as usize;
-        let y_portrait = y_portrait as usize;
-        let idx = y_portrait * HEIGHT + x_portrait;
-        if idx < self.display_buffer.len() {
-            self.display_buffer[idx]
-        } else {
-            0xFFFFFFFF
-        }
+// Lets the frontlight settings be tried out; changes are only logged.
+impl Frontlight for MinifbDisplay {
+    fn has_frontlight(&self) -> bool {
+        true
+    }
+    fn has_warmth(&self) -> bool {
+        true
+    }
+    fn set_frontlight(&mut self, level: FrontlightLevel, fade_ms: u32) {
+        info!(
+            "Frontlight: brightness {} warmth {} over {} ms",
+            level.brightness, level.warmth, fade_ms
+        );
     }
 }
 
@@ -236,11 +276,13 @@ impl tern_core::display::Display for MinifbDisplay {
         let previous = buffers.get_inactive_buffer();
         self.lsb_buffer.copy_from_slice(&current[..]);
         self.msb_buffer.copy_from_slice(&previous[..]);
-        if mode == RefreshMode::Fast {
+        self.emulate_refresh(mode);
+        if mode == RefreshMode::Fast && !self.gray_oneshot_shown {
             self.blit_internal(BlitMode::Partial);
         } else {
             self.blit_internal(BlitMode::Full);
         }
+        self.gray_oneshot_shown = false;
         buffers.swap_buffers();
     }
     fn copy_to_lsb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
@@ -258,6 +300,18 @@ impl tern_core::display::Display for MinifbDisplay {
         self.blit_internal(BlitMode::Grayscale);
     }
     fn display_absolute_grayscale(&mut self, _: GrayscaleMode) {
+        self.emulate_refresh(RefreshMode::Full);
         self.blit_internal(BlitMode::GrayscaleOneshot);
+        self.gray_oneshot_shown = true;
+    }
+}
+
+/// Window colour for a 2-bit pixel, matching the TRI Gray2 encoding.
+fn gray2_shade(msb_bit: u8, lsb_bit: u8) -> u32 {
+    match (msb_bit, lsb_bit) {
+        (0, 0) => 0xFFFFFFFF,
+        (0, _) => 0xFFAAAAAA,
+        (_, 0) => 0xFF555555,
+        _ => 0xFF000000,
     }
 }
@@ -0,0 +1,144 @@
+//! Window-less display and input helpers for running the app off-device.
+
+use tern_core::{
+    application::Application,
+    display::{Display, GrayscaleMode, RefreshMode},
+    framebuffer::{BUFFER_SIZE, DisplayBuffers},
+    image_viewer::AppSource,
+    input::{ButtonState, Buttons},
+};
+
+/// Display that only hashes what it is asked to show.
+pub struct HeadlessDisplay {
+    frame: u64,
+    gray: u64,
+    refreshes: usize,
+    lsb: Box<[u8; BUFFER_SIZE]>,
+    msb: Box<[u8; BUFFER_SIZE]>,
+}
+
+impl Default for HeadlessDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadlessDisplay {
+    pub fn new() -> Self {
+        Self {
+            frame: 0,
+            gray: 0,
+            refreshes: 0,
+            lsb: Box::new([0; BUFFER_SIZE]),
+            msb: Box::new([0; BUFFER_SIZE]),
+        }
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.frame ^ self.gray.rotate_left(1)
+    }
+
+    /// Number of black/white and grayscale refreshes so far.
+    pub fn refreshes(&self) -> usize {
+        self.refreshes
+    }
+}
+
+impl Display for HeadlessDisplay {
+    fn display(&mut self, buffers: &mut DisplayBuffers, _mode: RefreshMode) {
+        self.frame = fnv1a(buffers.get_active_buffer());
+        self.gray = 0;
+        self.refreshes += 1;
+        buffers.swap_buffers();
+    }
+    fn copy_to_lsb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
+        self.lsb.copy_from_slice(buffers);
+    }
+    fn copy_to_msb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
+        self.msb.copy_from_slice(buffers);
+    }
+    fn copy_grayscale_buffers(&mut self, lsb: &[u8; BUFFER_SIZE], msb: &[u8; BUFFER_SIZE]) {
+        self.lsb.copy_from_slice(lsb);
+        self.msb.copy_from_slice(msb);
+    }
+    fn display_differential_grayscale(&mut self, _turn_off_screen: bool) {
+        self.gray = fnv1a(&self.lsb[..]) ^ fnv1a(&self.msb[..]).rotate_left(7);
+        self.refreshes += 1;
+    }
+    fn display_absolute_grayscale(&mut self, _: GrayscaleMode) {
+        self.gray = fnv1a(&self.lsb[..]) ^ fnv1a(&self.msb[..]).rotate_left(7);
+        self.refreshes += 1;
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Button state for a fresh press of `button`.
+pub fn press(button: Buttons) -> ButtonState {
+    let mut state = ButtonState::default();
+    state.update(1 << (button as u8));
+    state
+}
+
+/// Scripted button input that mirrors the device's per-frame button updates.
+#[derive(Default)]
+pub struct InputScript {
+    state: ButtonState,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances one frame with `buttons` down.
+    pub fn frame(&mut self, buttons: &[Buttons]) -> ButtonState {
+        let mask = buttons
+            .iter()
+            .fold(0u8, |mask, button| mask | (1 << (*button as u8)));
+        self.state.update(mask);
+        self.state
+    }
+
+    /// Advances one frame with nothing pressed.
+    pub fn idle(&mut self) -> ButtonState {
+        self.frame(&[])
+    }
+}
+
+/// Drives an [`Application`] a press at a time, drawing every frame.
+#[derive(Default)]
+pub struct Harness {
+    pub display: HeadlessDisplay,
+    pub input: InputScript,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presses and releases `button`, then settles.
+    pub fn tap<S: AppSource>(&mut self, app: &mut Application<'_, S>, button: Buttons) {
+        let down = self.input.frame(&[button]);
+        app.update(&down, 10);
+        app.draw(&mut self.display);
+        self.settle(app);
+    }
+
+    /// Runs idle frames so multi-step transitions (exit overlay, sleep) finish.
+    pub fn settle<S: AppSource>(&mut self, app: &mut Application<'_, S>) {
+        for _ in 0..4 {
+            let idle = self.input.idle();
+            app.update(&idle, 10);
+            app.draw(&mut self.display);
+        }
+    }
+}
@@ -1,17 +1,43 @@
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
 
 use log::error;
 use tern_core::image_viewer::{
-    BookSource, EntryKind, Gray2StreamSource, ImageData, ImageEntry, ImageError, ImageSource,
-    PersistenceSource, PowerSource,
+    BookSource, DiagnosticsSource, EntryKind, Gray2StreamSource, ImageData, ImageEntry,
+    ImageError, ImageInfo, ImageSource, PersistenceSource, PowerSource, PrefetchSource,
 };
+use tern_core::app::diagnostics::FLIGHT_LOG_FILE;
+use tern_core::app::profiles::{read_profiles, Profile};
+use tern_core::fs::is_system_clutter;
+use tern_core::persistence::{parse_book_positions, StateStorage, StateStore};
+use tern_core::trbk::{parse_trimg, parse_trimg_info, trimg_capture_date, trimg_metadata_len};
+
+struct DirStateStorage<'a> {
+    root: &'a Path,
+}
+
+impl StateStorage for DirStateStorage<'_> {
+    fn read_state_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        fs::read(self.root.join(name)).ok()
+    }
+
+    fn write_state_file(&mut self, name: &str, data: &[u8]) -> bool {
+        fs::write(self.root.join(name), data).is_ok()
+    }
+}
 
 pub struct DesktopImageSource {
     root: PathBuf,
     trbk_pages: Option<Vec<tern_core::trbk::TrbkPage>>,
     trbk_data: Option<Vec<u8>>,
     trbk_images: Option<Vec<tern_core::trbk::TrbkImageInfo>>,
+    trbk_text: Option<tern_core::trbk::TrbkTextInfo>,
+    epub: Option<tern_core::epub::EpubBook>,
+    state: StateStore,
+    started: Instant,
 }
 
 impl DesktopImageSource {
@@ -21,6 +47,10 @@ impl DesktopImageSource {
             trbk_pages: None,
             trbk_data: None,
             trbk_images: None,
+            trbk_text: None,
+            epub: None,
+            state: StateStore::new(),
+            started: Instant::now(),
         }
     }
 
@@ -32,6 +62,9 @@ impl DesktopImageSource {
             || name.ends_with(".trimg")
             || name.ends_with(".tri")
             || name.ends_with(".trbk")
+            || name.ends_with(".tbk")
+            || name.ends_with(".epub")
+            || name.ends_with(".epb")
     }
 
     fn resume_path(&self) -> PathBuf {
@@ -58,6 +91,76 @@ impl DesktopImageSource {
         self.root.join(".trusty_recents")
     }
 
+    fn read_legacy_resume(&self) -> Option<String> {
+        let data = fs::read(self.resume_path())
+            .or_else(|_| fs::read(self.resume_path_legacy()))
+            .ok()?;
+        let name = String::from_utf8_lossy(&data).trim().to_string();
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    fn read_legacy_book_positions(&self) -> (Vec<(String, usize)>, bool) {
+        let data = match fs::read(self.book_positions_path())
+            .or_else(|_| fs::read(self.book_positions_path_legacy()))
+        {
+            Ok(data) => data,
+            Err(_) => return (Vec::new(), false),
+        };
+        parse_book_positions(&String::from_utf8_lossy(&data))
+    }
+
+    fn read_legacy_recent_entries(&self) -> Vec<String> {
+        let data = match fs::read(self.recent_entries_path())
+            .or_else(|_| fs::read(self.recent_entries_path_legacy()))
+        {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+        let text = String::from_utf8_lossy(&data);
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let value = line.trim();
+            if !value.is_empty() {
+                entries.push(value.to_string());
+            }
+        }
+        entries
+    }
+
+    fn ensure_state(&mut self) {
+        if !self.state.is_loaded() {
+            self.reload_state();
+        }
+    }
+
+    fn reload_state(&mut self) {
+        let first_load = !self.state.is_loaded();
+        let loaded = self.state.load(&mut DirStateStorage { root: &self.root });
+        // Only the first profile predates the state blob.
+        if loaded || !first_load || self.state.profile() != 0 {
+            return;
+        }
+        let resume = self.read_legacy_resume();
+        let (book_positions, cleaned) = self.read_legacy_book_positions();
+        let recent_entries = self.read_legacy_recent_entries();
+        let state = self.state.state_mut();
+        state.resume = resume;
+        state.book_positions = book_positions;
+        state.recent_entries = recent_entries;
+        if cleaned {
+            log::info!("Saving repaired book positions");
+            self.save_state();
+        }
+    }
+
+    fn save_state(&mut self) {
+        self.state.save(&mut DirStateStorage { root: &self.root });
+    }
+
     fn thumbnail_dir(&self) -> PathBuf {
         self.root.join(".tern_cache")
     }
@@ -88,13 +191,140 @@ impl DesktopImageSource {
         let path = base.join(&entry.name);
         let data = fs::read(&path).map_err(|_| ImageError::Io)?;
         match tern_core::trbk::parse_trbk(&data) {
-            Ok(book) => Ok((book, data)),
+            Ok(mut book) => {
+                if let Some(reference) = book.metadata.font_pack.clone() {
+                    self.fill_font_pack(&mut book, &reference);
+                }
+                self.ensure_state();
+                let state = self.state.state();
+                let reading_font = tern_core::reflow::reading_font_for(
+                    &book.metadata,
+                    book.text.is_some(),
+                    state.reading_font.as_deref(),
+                    state.reading_layout,
+                )
+                .map(|(typeface, char_width)| (typeface.to_string(), char_width));
+                if let Some((typeface, char_width)) = reading_font {
+                    self.apply_reading_font(&mut book, &typeface, char_width);
+                }
+                Ok((book, data))
+            }
             Err(err) => {
                 log_trbk_header(&data, &path);
                 Err(err)
             }
         }
     }
+
+    /// Fills in glyphs the book takes from a font pack in `fonts/` under the
+    /// root. Without the pack those glyphs are left blank.
+    fn fill_font_pack(
+        &self,
+        book: &mut tern_core::trbk::TrbkBook,
+        reference: &tern_core::trbk::TrbkFontPackRef,
+    ) {
+        let pack_path = self
+            .root
+            .join(tern_core::trbk::FONT_PACK_DIR)
+            .join(&reference.name);
+        let result = fs::read(&pack_path).map_err(|_| ImageError::Io).and_then(|pack| {
+            let glyphs = Rc::make_mut(&mut book.glyphs).as_mut_slice();
+            tern_core::trbk::apply_font_pack(glyphs, &pack, reference)
+        });
+        match result {
+            Ok(0) => {}
+            Ok(missing) => error!(
+                "Font pack {} is missing {} glyphs",
+                pack_path.display(),
+                missing
+            ),
+            Err(err) => error!("Font pack {} not loaded: {:?}", pack_path.display(), err),
+        }
+    }
+
+    /// Installed font packs as file name and header.
+    fn font_packs(&self) -> Vec<(String, tern_core::trbk::TrbkFontPackHeader)> {
+        let dir = self.root.join(tern_core::trbk::FONT_PACK_DIR);
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+        let mut packs = Vec::new();
+        for entry in read_dir.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_system_clutter(&name) || tern_core::trbk::font_pack_typeface(&name).is_none() {
+                continue;
+            }
+            let mut header = [0u8; tern_core::trbk::FONT_PACK_HEADER_LEN];
+            let read = fs::File::open(entry.path())
+                .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header));
+            if read.is_err() {
+                continue;
+            }
+            if let Ok(header) = tern_core::trbk::parse_font_pack_header(&header) {
+                packs.push((name, header));
+            }
+        }
+        packs
+    }
+
+    /// Reads an EPUB from the root and sets it in the font pack the reader
+    /// would pick for it.
+    fn open_epub(
+        &mut self,
+        path: &[String],
+        entry: &ImageEntry,
+    ) -> Result<Rc<tern_core::trbk::TrbkBookInfo>, ImageError> {
+        if entry.kind != EntryKind::File {
+            return Err(ImageError::Unsupported);
+        }
+        let base = path.iter().fold(self.root.clone(), |acc, part| acc.join(part));
+        let data = fs::read(base.join(&entry.name)).map_err(|_| ImageError::Io)?;
+        let book = tern_core::epub::parse_epub(&data)?;
+        self.ensure_state();
+        let state = self.state.state();
+        let reading_font = state.reading_font.clone();
+        let reading = state.reading_layout;
+        let packs = self.font_packs();
+        let Some(name) =
+            tern_core::epub::pick_epub_font_pack(&packs, reading_font.as_deref(), reading)
+        else {
+            return Err(ImageError::Message(tern_core::epub::NO_FONT_PACK.into()));
+        };
+        let typeface = tern_core::trbk::font_pack_typeface(name).unwrap_or_default();
+        let pack_path = self.root.join(tern_core::trbk::FONT_PACK_DIR).join(name);
+        let pack = fs::read(&pack_path).map_err(|_| ImageError::Io)?;
+        let glyphs = tern_core::trbk::font_pack_glyphs(&pack)?;
+        let info = Rc::new(book.book_info(typeface, glyphs));
+        self.epub = Some(book);
+        Ok(info)
+    }
+
+    /// Draws the book in the chosen typeface, from its pack closest in size
+    /// to `char_width`.
+    fn apply_reading_font(
+        &self,
+        book: &mut tern_core::trbk::TrbkBook,
+        typeface: &str,
+        char_width: u16,
+    ) {
+        let packs = self
+            .font_packs()
+            .into_iter()
+            .filter(|(name, _)| tern_core::trbk::font_pack_typeface(name) == Some(typeface))
+            .collect::<Vec<_>>();
+        let Some(name) = tern_core::trbk::pick_reading_font_pack(&packs, char_width) else {
+            error!("No font pack installed for reading font {}", typeface);
+            return;
+        };
+        let pack_path = self.root.join(tern_core::trbk::FONT_PACK_DIR).join(name);
+        let result = fs::read(&pack_path).map_err(|_| ImageError::Io).and_then(|pack| {
+            let glyphs = Rc::make_mut(&mut book.glyphs).as_mut_slice();
+            tern_core::trbk::apply_reading_font(glyphs, &pack)
+        });
+        if let Err(err) = result {
+            error!("Reading font {} not loaded: {:?}", pack_path.display(), err);
+        }
+    }
 }
 
 impl ImageSource for DesktopImageSource {
@@ -109,7 +339,8 @@ impl ImageSource for DesktopImageSource {
             let entry = entry.map_err(|_| ImageError::Io)?;
             let file_type = entry.file_type().map_err(|_| ImageError::Io)?;
             let name = entry.file_name().to_string_lossy().to_string();
-            if name == ".tern_resume"
+            if is_system_clutter(&name)
+                || name == ".tern_resume"
                 || name == ".trusty_resume"
                 || name == ".tern_books"
                 || name == ".trusty_books"
@@ -154,7 +385,7 @@ impl ImageSource for DesktopImageSource {
         let base = path.iter().fold(self.root.clone(), |acc, part| acc.join(part));
         let path = base.join(&entry.name);
         let lower = entry.name.to_ascii_lowercase();
-        if lower.ends_with(".trbk") {
+        if lower.ends_with(".trbk") || lower.ends_with(".epub") || lower.ends_with(".epb") {
             return Err(ImageError::Unsupported);
         }
         if lower.ends_with(".trimg") || lower.ends_with(".tri") {
@@ -171,102 +402,239 @@ impl ImageSource for DesktopImageSource {
             pixels: luma.into_raw(),
         })
     }
+
+    fn capture_date(&mut self, path: &[String], entry: &ImageEntry) -> Option<u32> {
+        let base = path.iter().fold(self.root.clone(), |acc, part| acc.join(part));
+        let mut header = [0u8; 16];
+        fs::File::open(base.join(&entry.name))
+            .ok()?
+            .read_exact(&mut header)
+            .ok()?;
+        trimg_capture_date(&header)
+    }
+
+    fn image_info(&mut self, path: &[String], entry: &ImageEntry) -> Option<ImageInfo> {
+        let base = path.iter().fold(self.root.clone(), |acc, part| acc.join(part));
+        let data = fs::read(base.join(&entry.name)).ok()?;
+        if data.len() < 16 || &data[0..4] != b"TRIM" {
+            return None;
+        }
+        let metadata = data.len().checked_sub(trimg_metadata_len(&data))?;
+        Some(parse_trimg_info(&data[..16], &data[metadata.max(16)..]))
+    }
 }
 
 impl PersistenceSource for DesktopImageSource {
     fn save_resume(&mut self, name: Option<&str>) {
-        let path = self.resume_path();
-        if let Some(name) = name {
-            let _ = fs::write(path, name.as_bytes());
-        } else {
-            let _ = fs::remove_file(path);
-        }
+        self.ensure_state();
+        self.state.state_mut().resume = name.map(|name| name.to_string());
+        self.save_state();
     }
 
     fn load_resume(&mut self) -> Option<String> {
-        let data = fs::read(self.resume_path())
-            .or_else(|_| fs::read(self.resume_path_legacy()))
-            .ok()?;
-        let name = String::from_utf8_lossy(&data).trim().to_string();
-        if name.is_empty() {
-            None
-        } else {
-            Some(name)
-        }
+        self.reload_state();
+        self.state.state().resume.clone()
     }
 
     fn save_book_positions(&mut self, entries: &[(String, usize)]) {
-        let path = self.book_positions_path();
-        if entries.is_empty() {
-            let _ = fs::remove_file(path);
-            return;
-        }
-        let mut contents = String::new();
-        for (name, page) in entries {
-            contents.push_str(name);
-            contents.push('\t');
-            contents.push_str(&page.to_string());
-            contents.push('\n');
-        }
-        let _ = fs::write(path, contents.as_bytes());
+        self.ensure_state();
+        self.state.state_mut().book_positions = entries.to_vec();
+        self.save_state();
     }
 
     fn load_book_positions(&mut self) -> Vec<(String, usize)> {
-        let data = match fs::read(self.book_positions_path())
-            .or_else(|_| fs::read(self.book_positions_path_legacy()))
-        {
-            Ok(data) => data,
-            Err(_) => return Vec::new(),
-        };
-        let text = String::from_utf8_lossy(&data);
-        let mut entries = Vec::new();
-        for line in text.lines() {
-            let Some((name, page_str)) = line.split_once('\t') else {
-                continue;
-            };
-            let name = name.trim();
-            let page_str = page_str.trim();
-            if name.is_empty() {
-                continue;
-            }
-            let Ok(page) = page_str.parse::<usize>() else {
-                continue;
-            };
-            entries.push((name.to_string(), page));
-        }
-        entries
+        self.ensure_state();
+        self.state.state().book_positions.clone()
     }
 
     fn save_recent_entries(&mut self, entries: &[String]) {
-        let path = self.recent_entries_path();
-        if entries.is_empty() {
-            let _ = fs::remove_file(path);
-            return;
-        }
-        let mut contents = String::new();
-        for entry in entries {
-            contents.push_str(entry);
-            contents.push('\n');
-        }
-        let _ = fs::write(path, contents.as_bytes());
+        self.ensure_state();
+        self.state.state_mut().recent_entries = entries.to_vec();
+        self.save_state();
     }
 
     fn load_recent_entries(&mut self) -> Vec<String> {
-        let data = match fs::read(self.recent_entries_path())
-            .or_else(|_| fs::read(self.recent_entries_path_legacy()))
-        {
-            Ok(data) => data,
-            Err(_) => return Vec::new(),
-        };
-        let text = String::from_utf8_lossy(&data);
-        let mut entries = Vec::new();
-        for line in text.lines() {
-            let value = line.trim();
-            if !value.is_empty() {
-                entries.push(value.to_string());
-            }
-        }
-        entries
+        self.ensure_state();
+        self.state.state().recent_entries.clone()
+    }
+
+    fn save_reading_font(&mut self, name: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().reading_font = name.map(|name| name.to_string());
+        self.save_state();
+    }
+
+    fn load_reading_font(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().reading_font.clone()
+    }
+
+    fn save_reading_layout(&mut self, layout: tern_core::reflow::ReadingLayout) {
+        self.ensure_state();
+        self.state.state_mut().reading_layout = layout;
+        self.save_state();
+    }
+
+    fn load_reading_layout(&mut self) -> tern_core::reflow::ReadingLayout {
+        self.ensure_state();
+        self.state.state().reading_layout
+    }
+
+    fn save_usb_hosts(&mut self, hosts: &[String]) {
+        self.ensure_state();
+        self.state.state_mut().usb_hosts = hosts.to_vec();
+        self.save_state();
+    }
+
+    fn load_usb_hosts(&mut self) -> Vec<String> {
+        self.ensure_state();
+        self.state.state().usb_hosts.clone()
+    }
+
+    fn save_book_crops(&mut self, crops: &[(String, u8)]) {
+        self.ensure_state();
+        self.state.state_mut().book_crops = crops.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_crops(&mut self) -> Vec<(String, u8)> {
+        self.ensure_state();
+        self.state.state().book_crops.clone()
+    }
+
+    fn save_show_front_matter(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().show_front_matter = show;
+        self.save_state();
+    }
+
+    fn load_show_front_matter(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().show_front_matter
+    }
+
+    fn save_refresh_tuning(&mut self, tuning: tern_core::display::RefreshTuning) {
+        self.ensure_state();
+        self.state.state_mut().refresh_tuning = tuning;
+        self.save_state();
+    }
+
+    fn load_refresh_tuning(&mut self) -> tern_core::display::RefreshTuning {
+        self.ensure_state();
+        self.state.state().refresh_tuning
+    }
+
+    fn save_image_order(&mut self, order: tern_core::app::image_viewer::ImageOrder) {
+        self.ensure_state();
+        self.state.state_mut().image_order = order;
+        self.save_state();
+    }
+
+    fn load_image_order(&mut self) -> tern_core::app::image_viewer::ImageOrder {
+        self.ensure_state();
+        self.state.state().image_order
+    }
+
+    fn save_clean_page(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_page = clean;
+        self.save_state();
+    }
+
+    fn load_clean_page(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_page
+    }
+
+    fn save_book_paces(&mut self, paces: &[(String, u32)]) {
+        self.ensure_state();
+        self.state.state_mut().book_paces = paces.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_paces(&mut self) -> Vec<(String, u32)> {
+        self.ensure_state();
+        self.state.state().book_paces.clone()
+    }
+
+    fn save_chapter_progress(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().chapter_progress = show;
+        self.save_state();
+    }
+
+    fn load_chapter_progress(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().chapter_progress
+    }
+
+    fn save_simple_mode(&mut self, folder: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().simple_mode = folder.map(|folder| folder.to_string());
+        self.save_state();
+    }
+
+    fn load_simple_mode(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().simple_mode.clone()
+    }
+
+    fn save_lock_code(&mut self, code: &[u8]) {
+        self.ensure_state();
+        self.state.state_mut().lock_code = code.to_vec();
+        self.save_state();
+    }
+
+    fn load_lock_code(&mut self) -> Vec<u8> {
+        self.ensure_state();
+        self.state.state().lock_code.clone()
+    }
+
+    fn save_clean_system_files(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_system_files = clean;
+        self.save_state();
+    }
+
+    fn load_clean_system_files(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_system_files
+    }
+
+    fn save_frontlight(&mut self, level: tern_core::display::FrontlightLevel) {
+        self.ensure_state();
+        self.state.state_mut().frontlight = level;
+        self.save_state();
+    }
+
+    fn load_frontlight(&mut self) -> tern_core::display::FrontlightLevel {
+        self.ensure_state();
+        self.state.state().frontlight
+    }
+
+    fn save_power_button_mode(&mut self, mode: tern_core::app::power_menu::PowerButtonMode) {
+        self.ensure_state();
+        self.state.state_mut().power_button = mode;
+        self.save_state();
+    }
+
+    fn load_power_button_mode(&mut self) -> tern_core::app::power_menu::PowerButtonMode {
+        self.ensure_state();
+        self.state.state().power_button
+    }
+
+    fn load_profiles(&mut self) -> Vec<Profile> {
+        read_profiles(&mut DirStateStorage { root: &self.root })
+    }
+
+    fn active_profile(&mut self) -> usize {
+        self.ensure_state();
+        self.state.profile() as usize
+    }
+
+    fn switch_profile(&mut self, index: usize) {
+        self.state.set_profile(&mut DirStateStorage { root: &self.root }, index as u8);
+        self.ensure_state();
     }
 
     fn load_thumbnail(&mut self, key: &str) -> Option<ImageData> {
@@ -320,13 +688,31 @@ impl BookSource for DesktopImageSource {
         &mut self,
         path: &[String],
         entry: &ImageEntry,
-    ) -> Result<tern_core::trbk::TrbkBookInfo, ImageError> {
+    ) -> Result<Rc<tern_core::trbk::TrbkBookInfo>, ImageError> {
+        self.close_trbk();
+        let lower = entry.name.to_ascii_lowercase();
+        if lower.ends_with(".epub") || lower.ends_with(".epb") {
+            return self.open_epub(path, entry);
+        }
         let (book, data) = self.load_trbk_data(path, entry)?;
         let info = book.info();
         self.trbk_pages = Some(book.pages);
         self.trbk_images = Some(info.images.clone());
+        self.trbk_text = info.text;
         self.trbk_data = Some(data);
-        Ok(info)
+        Ok(Rc::new(info))
+    }
+
+    fn reading_fonts(&mut self) -> Vec<String> {
+        let mut typefaces = self
+            .font_packs()
+            .iter()
+            .filter_map(|(name, _)| tern_core::trbk::font_pack_typeface(name))
+            .map(|typeface| typeface.to_string())
+            .collect::<Vec<_>>();
+        typefaces.sort();
+        typefaces.dedup();
+        typefaces
     }
 
     fn trbk_page(&mut self, page_index: usize) -> Result<tern_core::trbk::TrbkPage, ImageError> {
@@ -339,6 +725,20 @@ impl BookSource for DesktopImageSource {
             .ok_or(ImageError::Decode)
     }
 
+    fn trbk_text(
+        &mut self,
+        first: usize,
+        visit: &mut dyn FnMut(usize, tern_core::reflow::TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        if let Some(book) = &self.epub {
+            return book.text(first, visit);
+        }
+        let (Some(data), Some(text)) = (self.trbk_data.as_ref(), self.trbk_text) else {
+            return Err(ImageError::Unsupported);
+        };
+        tern_core::reflow::read_text_items(data, text, first, visit)
+    }
+
     fn trbk_image(&mut self, image_index: usize) -> Result<ImageData, ImageError> {
         let Some(images) = self.trbk_images.as_ref() else {
             return Err(ImageError::Decode);
@@ -359,6 +759,8 @@ impl BookSource for DesktopImageSource {
         self.trbk_pages = None;
         self.trbk_data = None;
         self.trbk_images = None;
+        self.trbk_text = None;
+        self.epub = None;
     }
 }
 
@@ -366,6 +768,20 @@ impl Gray2StreamSource for DesktopImageSource {}
 
 impl PowerSource for DesktopImageSource {}
 
+impl PrefetchSource for DesktopImageSource {}
+
+impl DiagnosticsSource for DesktopImageSource {
+    fn now_us(&self) -> Option<u64> {
+        Some(self.started.elapsed().as_micros() as u64)
+    }
+
+    fn save_flight_log(&mut self, log: &str) {
+        if fs::write(self.root.join(FLIGHT_LOG_FILE), log).is_err() {
+            error!("Failed to write {}", FLIGHT_LOG_FILE);
+        }
+    }
+}
+
 fn log_trbk_header(data: &[u8], path: &Path) {
     if data.len() < 8 {
         error!(
@@ -424,39 +840,6 @@ fn log_trbk_header(data: &[u8], path: &Path) {
     );
 }
 
-fn parse_trimg(data: &[u8]) -> Result<ImageData, ImageError> {
-    if data.len() < 16 || &data[0..4] != b"TRIM" {
-        return Err(ImageError::Decode);
-    }
-    let width = u16::from_le_bytes([data[6], data[7]]) as u32;
-    let height = u16::from_le_bytes([data[8], data[9]]) as u32;
-    let payload = &data[16..];
-    let plane = ((width as usize * height as usize) + 7) / 8;
-    match (data[4], data[5]) {
-        (1, 1) => {
-            if payload.len() != plane {
-                return Err(ImageError::Decode);
-            }
-            Ok(ImageData::Mono1 {
-                width,
-                height,
-                bits: payload.to_vec(),
-            })
-        }
-        (2, 2) => {
-            if payload.len() != plane * 3 {
-                return Err(ImageError::Decode);
-            }
-            Ok(ImageData::Gray2 {
-                width,
-                height,
-                data: payload.to_vec(),
-            })
-        }
-        _ => Err(ImageError::Unsupported),
-    }
-}
-
 fn thumb_hash_hex(key: &str) -> String {
     let mut hash: u32 = 0x811c9dc5;
     for b in key.as_bytes() {
@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::Path;
+
+use minifb::Key;
+use tern_core::input::Buttons;
+
+const BUTTONS: [(Buttons, &str); 7] = [
+    (Buttons::Up, "up"),
+    (Buttons::Down, "down"),
+    (Buttons::Left, "left"),
+    (Buttons::Right, "right"),
+    (Buttons::Confirm, "confirm"),
+    (Buttons::Back, "back"),
+    (Buttons::Power, "power"),
+];
+
+const KEYS: [(Key, &str); 24] = [
+    (Key::Up, "Up"),
+    (Key::Down, "Down"),
+    (Key::Left, "Left"),
+    (Key::Right, "Right"),
+    (Key::PageUp, "PgUp"),
+    (Key::PageDown, "PgDn"),
+    (Key::Home, "Home"),
+    (Key::End, "End"),
+    (Key::Enter, "Enter"),
+    (Key::NumPadEnter, "KpEnter"),
+    (Key::Space, "Space"),
+    (Key::Tab, "Tab"),
+    (Key::Escape, "Esc"),
+    (Key::Backspace, "Backspace"),
+    (Key::Delete, "Delete"),
+    (Key::W, "W"),
+    (Key::A, "A"),
+    (Key::S, "S"),
+    (Key::D, "D"),
+    (Key::J, "J"),
+    (Key::K, "K"),
+    (Key::B, "B"),
+    (Key::P, "P"),
+    (Key::Q, "Q"),
+];
+
+/// Which keyboard keys drive which device button.
+///
+/// Config files and `--bind` use `button=Key,Key`, e.g. `back=Esc,Backspace`.
+/// Binding a button replaces all of its previous keys.
+pub struct KeyMap {
+    bindings: Vec<(Key, Buttons)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (Key::Up, Buttons::Up),
+                (Key::Down, Buttons::Down),
+                (Key::Left, Buttons::Left),
+                (Key::PageUp, Buttons::Left),
+                (Key::Right, Buttons::Right),
+                (Key::PageDown, Buttons::Right),
+                (Key::Enter, Buttons::Confirm),
+                (Key::Escape, Buttons::Back),
+                (Key::Backspace, Buttons::Back),
+                (Key::P, Buttons::Power),
+            ],
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut map = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            map.bind(line)
+                .map_err(|err| format!("{}:{}: {}", path.display(), number + 1, err))?;
+        }
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, spec: &str) -> Result<(), String> {
+        let (button, keys) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected button=Key[,Key], got '{}'", spec))?;
+        let button = button_from_name(button.trim())
+            .ok_or_else(|| format!("unknown button '{}'", button.trim()))?;
+        let mut keys_for_button = Vec::new();
+        for name in keys.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            keys_for_button.push(key_from_name(name).ok_or_else(|| format!("unknown key '{}'", name))?);
+        }
+        self.bindings
+            .retain(|(_, bound)| *bound as u8 != button as u8);
+        // A key drives one button only; the newest binding wins.
+        self.bindings
+            .retain(|(key, _)| !keys_for_button.contains(key));
+        self.bindings
+            .extend(keys_for_button.into_iter().map(|key| (key, button)));
+        Ok(())
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (Key, Buttons)> + '_ {
+        self.bindings.iter().copied()
+    }
+
+    pub fn is_bound(&self, key: Key) -> bool {
+        self.bindings.iter().any(|(bound, _)| *bound == key)
+    }
+
+    /// Short mapping summary for the window title.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        for (button, name) in BUTTONS {
+            let keys = self
+                .bindings
+                .iter()
+                .filter(|(_, bound)| *bound as u8 == button as u8)
+                .map(|(key, _)| key_name(*key))
+                .collect::<Vec<_>>();
+            if !keys.is_empty() {
+                parts.push(format!("{} {}", name, keys.join("/")));
+            }
+        }
+        parts.join(" | ")
+    }
+}
+
+fn button_from_name(name: &str) -> Option<Buttons> {
+    let name = name.to_ascii_lowercase();
+    let name = match name.as_str() {
+        "ok" | "enter" | "select" => "confirm",
+        "prev" => "left",
+        "next" => "right",
+        other => other,
+    };
+    BUTTONS
+        .iter()
+        .find(|(_, button_name)| *button_name == name)
+        .map(|(button, _)| *button)
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let alias = match name.to_ascii_lowercase().as_str() {
+        "pageup" => "pgup",
+        "pagedown" => "pgdn",
+        "escape" => "esc",
+        "return" => "enter",
+        _ => "",
+    };
+    let wanted = if alias.is_empty() { name } else { alias };
+    KEYS.iter()
+        .find(|(_, key_name)| key_name.eq_ignore_ascii_case(wanted))
+        .map(|(key, _)| *key)
+}
+
+fn key_name(key: Key) -> &'static str {
+    KEYS.iter()
+        .find(|(known, _)| *known == key)
+        .map(|(_, name)| *name)
+        .unwrap_or("?")
+}
@@ -0,0 +1,2 @@
+pub mod headless;
+pub mod image_source;
@@ -1,31 +1,79 @@
 use tern_core::{
+    app::system::BootMode,
     application::Application,
     display::{HEIGHT, WIDTH},
     framebuffer::DisplayBuffers,
 };
 
-use crate::display::MinifbDisplay;
-use crate::image_source::DesktopImageSource;
+use tern_desktop::image_source::DesktopImageSource;
+
+use crate::display::{DisplayOptions, MinifbDisplay};
+use crate::keymap::KeyMap;
 
 mod display;
-mod image_source;
+mod keymap;
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     log::info!("TernReader desktop application started");
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut boot_mode = BootMode::Normal;
+    let mut keymap = KeyMap::default();
+    let mut scale = 2usize;
+    let mut emulate_latency = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--safe-mode" => boot_mode = BootMode::Safe,
+            "--scale" => {
+                i += 1;
+                scale = match args.get(i).and_then(|s| s.parse().ok()) {
+                    Some(value @ 1..=4) => value,
+                    _ => exit_with_usage("--scale takes 1, 2, 3 or 4"),
+                };
+            }
+            "--eink-latency" => emulate_latency = true,
+            "--keys" => {
+                i += 1;
+                let Some(path) = args.get(i) else {
+                    exit_with_usage("--keys needs a file");
+                };
+                keymap = KeyMap::load(std::path::Path::new(path))
+                    .unwrap_or_else(|err| exit_with_usage(&err));
+            }
+            "--bind" => {
+                i += 1;
+                let spec = args.get(i).map(String::as_str).unwrap_or("");
+                if let Err(err) = keymap.bind(spec) {
+                    exit_with_usage(&err);
+                }
+            }
+            other => exit_with_usage(&format!("unknown option '{}'", other)),
+        }
+        i += 1;
+    }
+    log::info!("Key mapping: {}", keymap.summary());
+
+    // minifb only scales by powers of two; other factors are scaled in MinifbDisplay.
+    let (window_scale, software_scale) = match scale {
+        1 => (minifb::Scale::X1, 1),
+        2 => (minifb::Scale::X2, 1),
+        4 => (minifb::Scale::X4, 1),
+        other => (minifb::Scale::X1, other),
+    };
     let options = minifb::WindowOptions {
         borderless: false,
         title: true,
         resize: true,
-        scale: minifb::Scale::X2,
+        scale: window_scale,
         ..minifb::WindowOptions::default()
     };
     let mut window = minifb::Window::new(
         "TernReader Desktop",
-        HEIGHT,
-        WIDTH,
+        HEIGHT * software_scale,
+        WIDTH * software_scale,
         options,
     )
     .unwrap_or_else(|e| {
@@ -33,11 +81,20 @@ fn main() {
     });
 
     window.set_target_fps(5);
+    window.set_title(&format!("TernReader Desktop - {}", keymap.summary()));
 
     let mut display_buffers = Box::new(DisplayBuffers::default());
-    let mut display = Box::new(MinifbDisplay::new(window));
+    let mut display = Box::new(MinifbDisplay::new(
+        window,
+        keymap,
+        DisplayOptions {
+            scale: software_scale,
+            emulate_latency,
+        },
+    ));
     let mut image_source = DesktopImageSource::new("sdcard");
-    let mut application = Application::new(&mut display_buffers, &mut image_source);
+    let mut application =
+        Application::with_boot_mode(&mut display_buffers, &mut image_source, boot_mode);
     let mut last_tick = std::time::Instant::now();
 
     while display.is_open() {
@@ -46,5 +103,17 @@ fn main() {
         last_tick = std::time::Instant::now();
         application.update(&display.get_buttons(), elapsed_ms);
         application.draw(&mut *display);
+        application.apply_frontlight(&mut *display);
+        if let Some(request) = application.take_power_request() {
+            log::info!("Power menu request {:?} is not supported on desktop", request);
+        }
     }
 }
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!(
+        "Usage: tern-desktop [--safe-mode] [--scale 1-4] [--eink-latency] [--keys FILE] [--bind button=Key[,Key]]..."
+    );
+    std::process::exit(2);
+}
@@ -0,0 +1,106 @@
+//! Drives the full application against a temporary SD card directory.
+
+use std::fs;
+use std::path::Path;
+
+use tern_core::{
+    application::Application,
+    framebuffer::DisplayBuffers,
+    image_viewer::PersistenceSource,
+    input::Buttons,
+    persistence::{PersistedState, STATE_FILE_A, STATE_FILE_B},
+};
+use tern_desktop::{headless::Harness, image_source::DesktopImageSource};
+
+/// Writes a 1-bit TRI checkerboard; thumbnails are only made for TRI/TRBK files.
+fn write_tri(path: &Path, pattern: u8) {
+    let (width, height) = (96u16, 96u16);
+    let mut data = Vec::new();
+    data.extend_from_slice(b"TRIM");
+    data.extend_from_slice(&[1, 1]);
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.resize(16, 0);
+    for row in 0..height as usize {
+        let byte = if (row / 16) % 2 == 0 {
+            pattern
+        } else {
+            !pattern
+        };
+        data.extend(std::iter::repeat_n(byte, width as usize / 8));
+    }
+    fs::write(path, data).unwrap();
+}
+
+/// Home -> file browser -> first image -> back home -> sleep.
+fn open_first_image_and_sleep(card: &Path) -> DesktopImageSource {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = DesktopImageSource::new(card);
+    {
+        let mut app = Application::new(&mut buffers, &mut source);
+        let mut harness = Harness::new();
+        harness.settle(&mut app);
+        harness.tap(&mut app, Buttons::Down);
+        harness.tap(&mut app, Buttons::Confirm);
+        harness.tap(&mut app, Buttons::Confirm);
+        harness.tap(&mut app, Buttons::Back);
+        harness.tap(&mut app, Buttons::Power);
+        assert!(
+            app.take_sleep_transition(),
+            "short Power press should sleep"
+        );
+        assert!(harness.display.refreshes() > 0);
+    }
+    source
+}
+
+#[test]
+fn opening_an_image_records_recents_thumbnail_and_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    write_tri(&card.join("a.tri"), 0xF0);
+    write_tri(&card.join("b.tri"), 0x0F);
+
+    let mut source = open_first_image_and_sleep(card);
+    assert_eq!(source.load_recent_entries(), vec!["a.tri".to_string()]);
+    assert!(source.load_thumbnail("a.tri").is_some());
+    assert_eq!(source.load_resume().as_deref(), Some("HOME"));
+
+    // A fresh source sees the same state from disk.
+    let mut reopened = DesktopImageSource::new(card);
+    assert_eq!(reopened.load_recent_entries(), vec!["a.tri".to_string()]);
+}
+
+#[test]
+fn corrupt_state_copy_falls_back_to_the_previous_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    write_tri(&card.join("a.tri"), 0xF0);
+
+    let mut source = open_first_image_and_sleep(card);
+    source.save_resume(Some("a.tri"));
+    assert!(card.join(STATE_FILE_A).exists() && card.join(STATE_FILE_B).exists());
+
+    // Whichever copy was written last is now damaged.
+    let newest = [STATE_FILE_A, STATE_FILE_B]
+        .into_iter()
+        .max_by_key(|name| {
+            let data = fs::read(card.join(name)).unwrap();
+            PersistedState::decode(&data).unwrap().generation
+        })
+        .unwrap();
+    let mut data = fs::read(card.join(newest)).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xFF;
+    fs::write(card.join(newest), data).unwrap();
+
+    let mut reopened = DesktopImageSource::new(card);
+    assert_eq!(reopened.load_resume().as_deref(), Some("HOME"));
+    assert_eq!(reopened.load_recent_entries(), vec!["a.tri".to_string()]);
+
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut app = Application::new(&mut buffers, &mut reopened);
+    let mut harness = Harness::new();
+    harness.settle(&mut app);
+    assert!(harness.display.refreshes() > 0);
+}
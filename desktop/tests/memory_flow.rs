@@ -0,0 +1,200 @@
+//! Drives the application through whole sessions on a card held in memory.
+
+use tern_core::{
+    application::Application,
+    framebuffer::DisplayBuffers,
+    image_viewer::{ImageError, PersistenceSource},
+    input::Buttons,
+    memory_source::{MemoryImageSource, canned_trbk},
+};
+use tern_desktop::headless::Harness;
+
+const BOOK: &str = "books/a.trbk";
+
+fn card() -> MemoryImageSource {
+    let mut source = MemoryImageSource::new();
+    source.insert(BOOK, canned_trbk("A", &["One", "Two", "Three"], 30));
+    source.insert("books/b.trbk", canned_trbk("B", &["One"], 5));
+    source
+}
+
+/// Home -> file browser -> `books` -> the first book.
+fn open_book(harness: &mut Harness, app: &mut Application<'_, MemoryImageSource>) {
+    harness.settle(app);
+    harness.tap(app, Buttons::Down);
+    harness.tap(app, Buttons::Confirm);
+    assert_eq!(app.state_label(), "menu");
+    harness.tap(app, Buttons::Confirm);
+    // Past the `..` entry.
+    harness.tap(app, Buttons::Down);
+    harness.tap(app, Buttons::Confirm);
+}
+
+fn saved_page(source: &mut MemoryImageSource) -> Option<usize> {
+    source
+        .load_book_positions()
+        .into_iter()
+        .find(|(name, _)| name == BOOK)
+        .map(|(_, page)| page)
+}
+
+#[test]
+fn sleep_and_wake_keep_the_book_open_at_its_page() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    {
+        let mut app = Application::new(&mut buffers, &mut source);
+        let mut harness = Harness::new();
+        open_book(&mut harness, &mut app);
+        assert_eq!(app.state_label(), "book_viewing");
+        for _ in 0..3 {
+            harness.tap(&mut app, Buttons::Right);
+        }
+
+        harness.tap(&mut app, Buttons::Power);
+        assert!(
+            app.take_sleep_transition(),
+            "short Power press should sleep"
+        );
+        assert_eq!(app.state_label(), "sleeping");
+        harness.tap(&mut app, Buttons::Power);
+        assert!(app.take_wake_transition());
+        // Idle frames after waking must not fall back to Home or the browser.
+        harness.settle(&mut app);
+        assert_eq!(app.state_label(), "book_viewing");
+        assert!(app.source_mut().book_open());
+        assert_eq!(app.source_mut().wakes(), 1);
+    }
+    assert_eq!(source.sleeps(), 1);
+    assert_eq!(saved_page(&mut source), Some(3));
+    assert_eq!(source.load_resume().as_deref(), Some(BOOK));
+    assert_eq!(source.load_recent_entries(), vec![BOOK.to_string()]);
+}
+
+#[test]
+fn power_on_resumes_the_book_that_was_open() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    {
+        let mut app = Application::new(&mut buffers, &mut source);
+        let mut harness = Harness::new();
+        open_book(&mut harness, &mut app);
+        harness.tap(&mut app, Buttons::Right);
+        harness.tap(&mut app, Buttons::Right);
+        harness.tap(&mut app, Buttons::Power);
+    }
+    let app = Application::new(&mut buffers, &mut source);
+    assert_eq!(app.state_label(), "book_viewing");
+}
+
+#[test]
+fn usb_session_returns_to_the_book_or_its_folder() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    let mut app = Application::new(&mut buffers, &mut source);
+    let mut harness = Harness::new();
+    open_book(&mut harness, &mut app);
+    harness.tap(&mut app, Buttons::Right);
+
+    app.enter_usb_mode();
+    assert!(
+        !app.source_mut().book_open(),
+        "the host gets the card with the book closed"
+    );
+    assert_eq!(saved_page(app.source_mut()), Some(1));
+    app.leave_usb_mode();
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "book_viewing");
+    assert!(app.source_mut().book_open());
+
+    app.enter_usb_mode();
+    app.source_mut().remove(BOOK);
+    app.leave_usb_mode();
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "menu");
+    assert!(!app.source_mut().book_open());
+}
+
+#[test]
+fn books_read_ahead_open_once_the_read_is_done() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    source.set_read_ahead(true);
+    let mut app = Application::new(&mut buffers, &mut source);
+    let mut harness = Harness::new();
+    open_book(&mut harness, &mut app);
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "menu", "opened before the read finished");
+    assert!(!app.source_mut().book_open());
+
+    assert_eq!(app.source_mut().finish_read_ahead().as_deref(), Some(BOOK));
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "book_viewing");
+    assert!(app.source_mut().book_open());
+}
+
+#[test]
+fn back_gives_up_on_a_book_being_read_ahead() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    source.set_read_ahead(true);
+    let mut app = Application::new(&mut buffers, &mut source);
+    let mut harness = Harness::new();
+    open_book(&mut harness, &mut app);
+    harness.tap(&mut app, Buttons::Back);
+    assert_eq!(app.source_mut().finish_read_ahead(), None, "the read was not dropped");
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "menu");
+    assert!(!app.source_mut().book_open());
+
+    // The browser works as before.
+    harness.tap(&mut app, Buttons::Confirm);
+    app.source_mut().finish_read_ahead();
+    harness.settle(&mut app);
+    assert_eq!(app.state_label(), "book_viewing");
+}
+
+#[test]
+fn file_browser_stays_put_across_usb() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    let mut app = Application::new(&mut buffers, &mut source);
+    let mut harness = Harness::new();
+    harness.settle(&mut app);
+    harness.tap(&mut app, Buttons::Down);
+    harness.tap(&mut app, Buttons::Confirm);
+    harness.tap(&mut app, Buttons::Confirm);
+    harness.tap(&mut app, Buttons::Down);
+
+    app.enter_usb_mode();
+    app.leave_usb_mode();
+    for _ in 0..3 {
+        harness.settle(&mut app);
+        assert_eq!(app.state_label(), "menu", "browser flipped back after USB");
+    }
+
+    harness.tap(&mut app, Buttons::Confirm);
+    assert_eq!(app.state_label(), "book_viewing");
+    harness.tap(&mut app, Buttons::Back);
+    assert_eq!(app.state_label(), "start_menu");
+}
+
+#[test]
+fn scripted_open_errors_show_once_and_clear() {
+    let mut buffers = Box::new(DisplayBuffers::default());
+    let mut source = card();
+    source.fail_next(BOOK, ImageError::Io);
+    let mut app = Application::new(&mut buffers, &mut source);
+    let mut harness = Harness::new();
+    open_book(&mut harness, &mut app);
+    assert_eq!(app.state_label(), "error");
+    assert!(!app.source_mut().book_open());
+
+    harness.tap(&mut app, Buttons::Confirm);
+    assert_eq!(app.state_label(), "start_menu");
+    // Home still has the file browser selected, and it reopens in `books`.
+    harness.tap(&mut app, Buttons::Confirm);
+    harness.tap(&mut app, Buttons::Down);
+    harness.tap(&mut app, Buttons::Confirm);
+    assert_eq!(app.state_label(), "book_viewing");
+}
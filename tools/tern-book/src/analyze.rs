@@ -0,0 +1,72 @@
+//! Pages likely to be slow to show on the device. The reader draws every
+//! glyph of a page one at a time and reads and decodes each of its images
+//! from the SD card on every page turn, so both add up on dense pages.
+
+use crate::paginate::PageOp;
+use crate::serialize::RenderedBook;
+
+/// Glyphs drawn on one page before it counts as slow.
+pub const MAX_GLYPH_DRAWS: usize = 5000;
+/// Image data read for one page before it counts as slow.
+pub const MAX_IMAGE_BYTES: usize = 192 * 1024;
+/// Images drawn on one page before it counts as slow.
+pub const MAX_IMAGES: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct SlowPage {
+    /// Font size of the book the page is in.
+    pub size: u16,
+    pub page: usize,
+    pub glyph_draws: usize,
+    pub images: usize,
+    pub image_bytes: usize,
+}
+
+impl SlowPage {
+    /// What makes the page slow, e.g. `3120 glyphs, 2 images (410 KiB)`.
+    pub fn describe(&self) -> String {
+        let mut reasons = Vec::new();
+        if self.glyph_draws > MAX_GLYPH_DRAWS {
+            reasons.push(format!("{} glyphs", self.glyph_draws));
+        }
+        if self.images > MAX_IMAGES || self.image_bytes > MAX_IMAGE_BYTES {
+            reasons.push(format!("{} images ({} KiB)", self.images, self.image_bytes / 1024));
+        }
+        reasons.join(", ")
+    }
+}
+
+/// Pages of `book` over any of the limits above, in page order.
+pub fn slow_pages(book: &RenderedBook, size: u16) -> Vec<SlowPage> {
+    book.pages
+        .iter()
+        .enumerate()
+        .filter_map(|(page, data)| {
+            let mut slow = SlowPage {
+                size,
+                page,
+                glyph_draws: 0,
+                images: 0,
+                image_bytes: 0,
+            };
+            for op in &data.ops {
+                match op {
+                    PageOp::Text { text, .. } => {
+                        slow.glyph_draws += text.chars().filter(|ch| !ch.is_whitespace()).count();
+                    }
+                    PageOp::Image { image_index, .. } => {
+                        slow.images += 1;
+                        slow.image_bytes += book
+                            .images
+                            .get(*image_index as usize)
+                            .map_or(0, |image| image.data.len());
+                    }
+                }
+            }
+            let over = slow.glyph_draws > MAX_GLYPH_DRAWS
+                || slow.images > MAX_IMAGES
+                || slow.image_bytes > MAX_IMAGE_BYTES;
+            over.then_some(slow)
+        })
+        .collect()
+}
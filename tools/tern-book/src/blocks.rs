@@ -0,0 +1,147 @@
+//! Stage 2: turn spine documents into styled blocks.
+//!
+//! Blocks are plain data, so callers can drop, rewrite or add entries (for
+//! example a generated title page) before handing them to layout.
+
+use std::collections::{BTreeSet, HashMap};
+
+pub use tern_epub::{HtmlBlock, TextRun, TextStyle};
+
+use crate::fonts::{style_id_from_style, StyleId};
+use crate::images::{placeholder_text, PLACEHOLDER_STYLE};
+use crate::input::BookInput;
+use crate::BookError;
+
+/// Blocks of one spine document. Pages are split between documents, and
+/// `spine_index` ties pages back to the TOC.
+#[derive(Clone, Debug)]
+pub struct SpineBlocks {
+    pub spine_index: i32,
+    pub blocks: Vec<HtmlBlock>,
+}
+
+/// Reads up to `max_spine_items` spine items. Items that fail to parse or have
+/// no content are skipped.
+pub fn extract_blocks(
+    input: &dyn BookInput,
+    max_spine_items: usize,
+) -> Result<Vec<SpineBlocks>, BookError> {
+    let mut out = Vec::new();
+    let max_try = input.spine().len().min(max_spine_items).max(1);
+    for index in 0..max_try {
+        let blocks = match input.blocks(index) {
+            Ok(blocks) => blocks,
+            Err(_) => continue,
+        };
+        if !blocks.is_empty() {
+            out.push(SpineBlocks {
+                spine_index: index as i32,
+                blocks,
+            });
+        }
+        if out.len() > 500 {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+pub fn collect_used_codepoints_from_blocks(
+    blocks: &[SpineBlocks],
+) -> HashMap<StyleId, BTreeSet<u32>> {
+    let mut used: HashMap<StyleId, BTreeSet<u32>> = HashMap::new();
+    for spine in blocks {
+        for block in &spine.blocks {
+            match block {
+                tern_epub::HtmlBlock::Paragraph { runs, .. } => {
+                    for run in runs {
+                        let style = style_id_from_style(run.style);
+                        let entry = used.entry(style).or_default();
+                        for ch in run.text.chars() {
+                            entry.insert(ch as u32);
+                        }
+                    }
+                }
+                // In case the image is missing and its placeholder is drawn.
+                tern_epub::HtmlBlock::Image { alt, .. } => {
+                    let entry = used.entry(style_id_from_style(PLACEHOLDER_STYLE)).or_default();
+                    for ch in placeholder_text(alt.as_deref()).chars() {
+                        entry.insert(ch as u32);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    used
+}
+
+pub(crate) fn strip_fragment(path: &str) -> String {
+    let mut end = path.len();
+    for (idx, ch) in path.char_indices() {
+        if ch == '#' || ch == '?' {
+            end = idx;
+            break;
+        }
+    }
+    path[..end].to_string()
+}
+
+pub(crate) fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        if part.is_empty() || part == "." {
+            continue;
+        }
+        if part == ".." {
+            if !parts.is_empty() {
+                parts.pop();
+            }
+            continue;
+        }
+        parts.push(part);
+    }
+    parts.join("/")
+}
+
+pub(crate) fn collapse_double_prefix(path: &str, prefix: &str) -> String {
+    if prefix.is_empty() {
+        return path.to_string();
+    }
+    let prefix = prefix.trim_end_matches('/');
+    let double = format!("{}/{}", prefix, prefix);
+    if path.starts_with(&double) {
+        format!("{}/{}", prefix, path[double.len()..].trim_start_matches('/'))
+    } else {
+        path.to_string()
+    }
+}
+
+pub(crate) fn percent_decode(input: &str) -> String {
+    let mut out = Vec::with_capacity(input.len());
+    let bytes = input.as_bytes();
+    let mut i = 0usize;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hi = bytes[i + 1];
+            let lo = bytes[i + 2];
+            if let (Some(hi), Some(lo)) = (hex_val(hi), hex_val(lo)) {
+                out.push((hi << 4) | lo);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn hex_val(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
@@ -0,0 +1,1176 @@
+//! Fixed-layout (pre-paginated) spine items, as used by comics and picture
+//! books.
+//!
+//! Each item is drawn at its declared viewport size, scaled to fit the screen
+//! and stored as one full-screen image page. Pages are read the way such books
+//! are usually built: images and inline SVG placed with `position: absolute`
+//! and `left`/`top`/`width`/`height` (inline or from simple tag, class and id
+//! rules), plus text boxes drawn with the book font. Everything else flows top
+//! to bottom inside its parent.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::blocks::{normalize_path, strip_fragment};
+use crate::fonts::{FontSet, StyleId};
+use crate::images::{render_svg, system_fontdb, trimg_to_bytes, ImageAsset};
+use crate::input::BookInput;
+use crate::paginate::{PageData, PageOp};
+use crate::RenderOptions;
+
+const INLINE_TAGS: &[&str] = &[
+    "a", "abbr", "b", "cite", "code", "em", "font", "i", "small", "span", "strong", "sub", "sup",
+    "u",
+];
+const SKIPPED_TAGS: &[&str] = &["head", "link", "meta", "script", "style", "title"];
+
+/// Renders every fixed-layout spine item to a page holding one new asset, or
+/// one per scanned page it provides. Items that cannot be read or drawn are
+/// skipped with a warning.
+pub fn build_fixed_pages(
+    input: &dyn BookInput,
+    fonts: &FontSet,
+    options: &RenderOptions,
+    assets: &mut Vec<ImageAsset>,
+) -> Vec<PageData> {
+    let mut pages = Vec::new();
+    // System fonts for SVG text, loaded on the first page.
+    let mut fontdb: Option<usvg::fontdb::Database> = None;
+    for (index, item) in input.spine().iter().enumerate() {
+        if !input.is_fixed_layout(index) {
+            continue;
+        }
+        let scans = input.page_images(index);
+        if !scans.is_empty() {
+            for scan in scans {
+                let image = image::DynamicImage::ImageLuma8(scan);
+                pages.push(image_page(index as i32, &image, true, options, assets));
+            }
+            continue;
+        }
+        let Some((markup, path)) = input.document(index) else {
+            eprintln!("[tern-book] warning: fixed-layout page not found: {}", item.name);
+            continue;
+        };
+        let fontdb = fontdb.get_or_insert_with(system_fontdb);
+        let Some(canvas) = render_page(input, &markup, &path, fonts, options, fontdb) else {
+            eprintln!("[tern-book] warning: failed to render fixed-layout page: {}", item.name);
+            continue;
+        };
+        let image = image::DynamicImage::ImageRgba8(canvas);
+        pages.push(image_page(index as i32, &image, false, options, assets));
+    }
+    pages
+}
+
+/// Puts `cover` in front of the book as a full-screen page. Its image becomes
+/// the first asset, which the library uses for the book's thumbnail.
+pub fn insert_cover_page(
+    cover: &image::DynamicImage,
+    options: &RenderOptions,
+    pages: &mut Vec<PageData>,
+    assets: &mut Vec<ImageAsset>,
+) {
+    for page in pages.iter_mut() {
+        for op in &mut page.ops {
+            if let PageOp::Image { image_index, .. } = op {
+                *image_index += 1;
+            }
+        }
+    }
+    let mut cover_assets = Vec::new();
+    // Outside every spine item, so TOC entries keep their pages.
+    let page = image_page(-1, cover, false, options, &mut cover_assets);
+    assets.splice(0..0, cover_assets);
+    pages.insert(0, page);
+}
+
+/// Scales `image` to the screen as a new asset and a page showing it. Scans
+/// are stored as 1-bit images, where [`tern_image::RegionMode::Crisp`]
+/// thresholds text and only dithers pictures; other pages keep gray levels
+/// unless the image limits ask for 1-bit.
+fn image_page(
+    spine_index: i32,
+    image: &image::DynamicImage,
+    scanned: bool,
+    options: &RenderOptions,
+    assets: &mut Vec<ImageAsset>,
+) -> PageData {
+    let convert = tern_image::ConvertOptions {
+        width: options.screen_width as u32,
+        height: options.screen_height as u32,
+        fit: tern_image::FitMode::Contain,
+        dither: tern_image::DitherMode::Bayer,
+        region_mode: if scanned {
+            tern_image::RegionMode::Crisp
+        } else {
+            tern_image::RegionMode::None
+        },
+        trimg_version: if scanned {
+            1
+        } else {
+            options.image_limits.trimg_version()
+        },
+        ..Default::default()
+    };
+    let trimg = tern_image::convert_image(image, convert);
+    let image_index = assets.len() as u16;
+    assets.push(ImageAsset {
+        width: trimg.width as u16,
+        height: trimg.height as u16,
+        data: trimg_to_bytes(&trimg),
+    });
+    PageData {
+        spine_index,
+        ops: vec![PageOp::Image {
+            x: 0,
+            y: 0,
+            width: trimg.width as u16,
+            height: trimg.height as u16,
+            image_index,
+        }],
+        anchors: Vec::new(),
+    }
+}
+
+fn render_page(
+    input: &dyn BookInput,
+    markup: &str,
+    path: &str,
+    fonts: &FontSet,
+    options: &RenderOptions,
+    fontdb: &usvg::fontdb::Database,
+) -> Option<image::RgbaImage> {
+    let root = parse_document(markup)?;
+    let mut page = PageLayout {
+        input,
+        fonts,
+        fontdb,
+        dir: tern_epub::opf_base_dir(path),
+        sheet: Vec::new(),
+        resources: HashMap::new(),
+        items: Vec::new(),
+    };
+    page.collect_styles(&root);
+
+    let body = root.find("body").unwrap_or(&root);
+    let (width, height) = viewport_size(&root)
+        .or_else(|| page.first_image_size(body))
+        .unwrap_or((options.screen_width as f32, options.screen_height as f32));
+    let viewport = Rect {
+        x: 0.0,
+        y: 0.0,
+        width,
+        height,
+    };
+    page.element(body, viewport, 0.0, Font::default());
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">"
+    );
+    let mut texts = Vec::new();
+    for item in page.items {
+        match item {
+            Item::Image { href, rect } => svg.push_str(&format!(
+                "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" xlink:href=\"{}\"/>",
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                escape(&href)
+            )),
+            Item::Svg(markup) => svg.push_str(&markup),
+            Item::Text(text) => texts.push(text),
+        }
+    }
+    svg.push_str("</svg>");
+
+    let resources = page.resources;
+    let svg_options = usvg::Options {
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(move |href, options, fontdb| {
+                image_kind(resources.get(href)?.clone(), options, fontdb)
+            }),
+        },
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_data(svg.as_bytes(), &svg_options, fontdb).ok()?;
+    let screen_w = options.screen_width as u32;
+    let screen_h = options.screen_height as u32;
+    let mut canvas = render_svg(&tree, screen_w, screen_h).to_rgba8();
+
+    // Same fit as `render_svg`, so text lands on top of the artwork it belongs to.
+    let scale = (screen_w as f32 / width).min(screen_h as f32 / height);
+    let dx = (screen_w as f32 - width * scale) * 0.5;
+    let dy = (screen_h as f32 - height * scale) * 0.5;
+    for text in &texts {
+        draw_text(&mut canvas, text, fonts, scale, dx, dy);
+    }
+    Some(canvas)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Rect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum LineHeight {
+    Factor(f32),
+    Px(f32),
+}
+
+/// Inherited text properties, in page pixels.
+#[derive(Clone, Copy, Debug)]
+struct Font {
+    size: f32,
+    bold: bool,
+    italic: bool,
+    luma: u8,
+    line_height: LineHeight,
+    align: Align,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            bold: false,
+            italic: false,
+            luma: 0,
+            line_height: LineHeight::Factor(1.2),
+            align: Align::Left,
+        }
+    }
+}
+
+impl Font {
+    fn style(&self) -> StyleId {
+        match (self.bold, self.italic) {
+            (false, false) => StyleId::Regular,
+            (true, false) => StyleId::Bold,
+            (false, true) => StyleId::Italic,
+            (true, true) => StyleId::BoldItalic,
+        }
+    }
+
+    fn line_height(&self) -> f32 {
+        match self.line_height {
+            LineHeight::Factor(factor) => factor * self.size,
+            LineHeight::Px(px) => px,
+        }
+    }
+}
+
+/// A piece of text on one line; `x` and `baseline` are in page pixels.
+struct PlacedText {
+    x: f32,
+    baseline: f32,
+    text: String,
+    font: Font,
+}
+
+enum Item {
+    Image { href: String, rect: Rect },
+    Svg(String),
+    Text(PlacedText),
+}
+
+enum Run {
+    Text(String, Font),
+    Break,
+}
+
+struct PageLayout<'a> {
+    input: &'a dyn BookInput,
+    fonts: &'a FontSet,
+    fontdb: &'a usvg::fontdb::Database,
+    /// Directory of the page document, for relative links.
+    dir: String,
+    sheet: Vec<Rule>,
+    resources: HashMap<String, Arc<Vec<u8>>>,
+    items: Vec<Item>,
+}
+
+impl PageLayout<'_> {
+    fn collect_styles(&mut self, element: &Element) {
+        for child in element.elements() {
+            match child.name.as_str() {
+                "link" => {
+                    let is_stylesheet = child
+                        .attr("rel")
+                        .is_some_and(|rel| rel.to_ascii_lowercase().contains("stylesheet"));
+                    let Some(href) = child.attr("href").filter(|_| is_stylesheet) else {
+                        continue;
+                    };
+                    let key = resource_name(&self.dir, href);
+                    if let Some(bytes) = self.input.resource(&key) {
+                        parse_css(&String::from_utf8_lossy(&bytes), &mut self.sheet);
+                    }
+                }
+                "style" => parse_css(&child.text(), &mut self.sheet),
+                _ => self.collect_styles(child),
+            }
+        }
+    }
+
+    /// Lays out `element` in `container` and returns the height it takes in
+    /// the flow, which is zero for positioned boxes.
+    fn element(&mut self, element: &Element, container: Rect, cursor: f32, parent: Font) -> f32 {
+        if SKIPPED_TAGS.contains(&element.name.as_str()) {
+            return 0.0;
+        }
+        let props = computed_style(element, &self.sheet);
+        if props.get("display").is_some_and(|value| value == "none") {
+            return 0.0;
+        }
+        let font = inherit_font(parent, element, &props);
+        let absolute = props
+            .get("position")
+            .is_some_and(|value| value == "absolute" || value == "fixed");
+        let prop = |name: &str, reference: f32| {
+            props
+                .get(name)
+                .and_then(|value| parse_length(value, font.size, reference))
+        };
+        let attr = |name: &str, reference: f32| {
+            element
+                .attr(name)
+                .and_then(|value| parse_length(value, font.size, reference))
+        };
+        let mut width = prop("width", container.width);
+        let mut height = prop("height", container.height);
+        if matches!(element.name.as_str(), "img" | "image" | "svg") {
+            width = width.or_else(|| attr("width", container.width));
+            height = height.or_else(|| attr("height", container.height));
+        }
+
+        if matches!(element.name.as_str(), "img" | "image") {
+            let Some(href) = image_href(element) else {
+                return 0.0;
+            };
+            let href = self.resource_key(href);
+            let natural = self
+                .resources
+                .get(&href)
+                .and_then(|bytes| natural_size(bytes, self.fontdb));
+            let (width, height) = match (width, height, natural) {
+                (Some(w), Some(h), _) => (w, h),
+                (Some(w), None, Some((nw, nh))) => (w, w * nh / nw.max(1.0)),
+                (None, Some(h), Some((nw, nh))) => (h * nw / nh.max(1.0), h),
+                (None, None, Some((nw, nh))) => (nw, nh),
+                _ => (container.width, container.height),
+            };
+            let rect = place(&props, &prop, container, cursor, absolute, width, height);
+            self.items.push(Item::Image { href, rect });
+            return if absolute { 0.0 } else { height };
+        }
+        if element.name == "svg" {
+            let width = width.unwrap_or(container.width);
+            let height = height.unwrap_or(container.height);
+            let rect = place(&props, &prop, container, cursor, absolute, width, height);
+            let mut markup = String::new();
+            self.write_svg(element, Some(rect), &mut markup);
+            self.items.push(Item::Svg(markup));
+            return if absolute { 0.0 } else { height };
+        }
+
+        let box_width = width.unwrap_or(container.width);
+        let rect = place(
+            &props,
+            &prop,
+            container,
+            cursor,
+            absolute,
+            box_width,
+            height.unwrap_or(0.0),
+        );
+        let area = Rect {
+            width: width.unwrap_or((container.x + container.width - rect.x).max(0.0)),
+            height: height.unwrap_or(container.height),
+            ..rect
+        };
+        let content = self.children(element, area, font);
+        if absolute {
+            0.0
+        } else {
+            height.unwrap_or(content)
+        }
+    }
+
+    /// Lays out the inline text of `element` followed by its block and
+    /// positioned children, returning the height they flow over.
+    fn children(&mut self, element: &Element, area: Rect, font: Font) -> f32 {
+        let mut cursor = area.y;
+        let mut runs = Vec::new();
+        self.collect_runs(element, font, &mut runs);
+        if runs
+            .iter()
+            .any(|run| matches!(run, Run::Text(text, _) if !text.trim().is_empty()))
+        {
+            cursor += self.layout_text(&runs, area, font);
+        }
+        for child in element.elements() {
+            if child.name == "br" || self.is_inline(child) {
+                continue;
+            }
+            cursor += self.element(child, area, cursor, font);
+        }
+        cursor - area.y
+    }
+
+    fn is_inline(&self, element: &Element) -> bool {
+        if !INLINE_TAGS.contains(&element.name.as_str()) {
+            return false;
+        }
+        let props = computed_style(element, &self.sheet);
+        !props
+            .get("position")
+            .is_some_and(|value| value == "absolute" || value == "fixed")
+            && !props
+                .get("display")
+                .is_some_and(|value| value == "block" || value == "none")
+    }
+
+    fn collect_runs(&self, element: &Element, font: Font, runs: &mut Vec<Run>) {
+        for child in &element.children {
+            match child {
+                Node::Text(text) => runs.push(Run::Text(text.clone(), font)),
+                Node::Element(child) if child.name == "br" => runs.push(Run::Break),
+                Node::Element(child) if self.is_inline(child) => {
+                    let props = computed_style(child, &self.sheet);
+                    self.collect_runs(child, inherit_font(font, child, &props), runs);
+                }
+                Node::Element(_) => {}
+            }
+        }
+    }
+
+    /// Wraps `runs` to the width of `area` and returns the height of the lines.
+    fn layout_text(&mut self, runs: &[Run], area: Rect, block_font: Font) -> f32 {
+        let mut lines: Vec<Vec<(String, Font)>> = vec![Vec::new()];
+        let mut line_width = 0.0f32;
+        let mut pending_space: Option<Font> = None;
+        for run in runs {
+            let (text, font) = match run {
+                Run::Break => {
+                    lines.push(Vec::new());
+                    line_width = 0.0;
+                    pending_space = None;
+                    continue;
+                }
+                Run::Text(text, font) => (text, *font),
+            };
+            let mut rest = text.as_str();
+            while !rest.is_empty() {
+                let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                if word_len == 0 {
+                    let ch = rest.chars().next().unwrap_or(' ');
+                    rest = &rest[ch.len_utf8()..];
+                    if !lines.last().is_some_and(|line| line.is_empty()) {
+                        pending_space = Some(font);
+                    }
+                    continue;
+                }
+                let word = &rest[..word_len];
+                rest = &rest[word_len..];
+                let word_width = self.measure(word, font);
+                let space_width = pending_space.map_or(0.0, |space| self.measure(" ", space));
+                let line = lines.last_mut().expect("at least one line");
+                if !line.is_empty() && line_width + space_width + word_width > area.width {
+                    lines.push(vec![(word.to_string(), font)]);
+                    line_width = word_width;
+                } else {
+                    if let Some(space) = pending_space {
+                        line.push((" ".to_string(), space));
+                        line_width += space_width;
+                    }
+                    line.push((word.to_string(), font));
+                    line_width += word_width;
+                }
+                pending_space = None;
+            }
+        }
+
+        let mut y = area.y;
+        for line in lines {
+            let fonts = if line.is_empty() {
+                vec![block_font]
+            } else {
+                line.iter().map(|(_, font)| *font).collect()
+            };
+            let line_height = fonts.iter().map(Font::line_height).fold(0.0, f32::max);
+            let (ascent, descent) = fonts
+                .iter()
+                .map(|font| self.line_metrics(*font))
+                .fold((0.0f32, 0.0f32), |(a, d), (ascent, descent)| (a.max(ascent), d.min(descent)));
+            let baseline = y + (line_height - (ascent - descent)) * 0.5 + ascent;
+            let width = line
+                .iter()
+                .map(|(text, font)| self.measure(text, *font))
+                .sum::<f32>();
+            let mut x = match block_font.align {
+                Align::Left => area.x,
+                Align::Center => area.x + (area.width - width) * 0.5,
+                Align::Right => area.x + area.width - width,
+            };
+            for (text, font) in line {
+                let advance = self.measure(&text, font);
+                self.items.push(Item::Text(PlacedText {
+                    x,
+                    baseline,
+                    text,
+                    font,
+                }));
+                x += advance;
+            }
+            y += line_height;
+        }
+        y - area.y
+    }
+
+    fn measure(&self, text: &str, font: Font) -> f32 {
+        let Some(face) = font_for(self.fonts, font) else {
+            return 0.0;
+        };
+        text.chars()
+            .map(|ch| face.metrics(ch, font.size).advance_width)
+            .sum()
+    }
+
+    fn line_metrics(&self, font: Font) -> (f32, f32) {
+        font_for(self.fonts, font)
+            .and_then(|face| face.horizontal_line_metrics(font.size))
+            .map(|metrics| (metrics.ascent, metrics.descent))
+            .unwrap_or((font.size * 0.8, -font.size * 0.2))
+    }
+
+    /// Archive name for `href`, loading the resource for the SVG renderer.
+    fn resource_key(&mut self, href: &str) -> String {
+        if href.starts_with("data:") {
+            return href.to_string();
+        }
+        let key = resource_name(&self.dir, href);
+        if !self.resources.contains_key(&key) {
+            match self.input.resource(&key) {
+                Some(bytes) => {
+                    self.resources.insert(key.clone(), Arc::new(bytes));
+                }
+                None => eprintln!("[tern-book] warning: image not found in book: {key}"),
+            }
+        }
+        key
+    }
+
+    fn first_image_size(&mut self, element: &Element) -> Option<(f32, f32)> {
+        for child in element.elements() {
+            if matches!(child.name.as_str(), "img" | "image") {
+                let key = self.resource_key(image_href(child)?);
+                return self
+                    .resources
+                    .get(&key)
+                    .and_then(|bytes| natural_size(bytes, self.fontdb));
+            }
+            if let Some(size) = self.first_image_size(child) {
+                return Some(size);
+            }
+        }
+        None
+    }
+
+    /// Writes `element` back out as SVG. The outermost element gets `rect` as
+    /// its position, and image links are rewritten to archive names.
+    fn write_svg(&mut self, element: &Element, rect: Option<Rect>, out: &mut String) {
+        out.push('<');
+        out.push_str(&element.qname);
+        for (key, value) in &element.attrs {
+            if rect.is_some() && matches!(key.as_str(), "x" | "y" | "width" | "height" | "style" | "class") {
+                continue;
+            }
+            let value = if element.name == "image" && (key == "href" || key == "xlink:href") {
+                self.resource_key(value)
+            } else {
+                value.clone()
+            };
+            out.push_str(&format!(" {key}=\"{}\"", escape(&value)));
+        }
+        if let Some(rect) = rect {
+            out.push_str(&format!(
+                " x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"",
+                rect.x, rect.y, rect.width, rect.height
+            ));
+        }
+        out.push('>');
+        for child in &element.children {
+            match child {
+                Node::Text(text) => out.push_str(&escape(text)),
+                Node::Element(child) => self.write_svg(child, None, out),
+            }
+        }
+        out.push_str("</");
+        out.push_str(&element.qname);
+        out.push('>');
+    }
+}
+
+/// Places a `width` x `height` box: positioned boxes by their offsets from the
+/// container, others at the flow cursor.
+fn place(
+    props: &HashMap<String, String>,
+    prop: &dyn Fn(&str, f32) -> Option<f32>,
+    container: Rect,
+    cursor: f32,
+    absolute: bool,
+    width: f32,
+    height: f32,
+) -> Rect {
+    let relative = props.get("position").is_some_and(|value| value == "relative");
+    let left = prop("left", container.width);
+    let top = prop("top", container.height);
+    let (x, y) = if absolute {
+        let x = match (left, prop("right", container.width)) {
+            (Some(left), _) => container.x + left,
+            (None, Some(right)) => container.x + container.width - right - width,
+            (None, None) => container.x,
+        };
+        let y = match (top, prop("bottom", container.height)) {
+            (Some(top), _) => container.y + top,
+            (None, Some(bottom)) => container.y + container.height - bottom - height,
+            (None, None) => container.y,
+        };
+        (x, y)
+    } else if relative {
+        (container.x + left.unwrap_or(0.0), cursor + top.unwrap_or(0.0))
+    } else {
+        (container.x, cursor)
+    };
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+fn font_for(fonts: &FontSet, font: Font) -> Option<&fontdue::Font> {
+    fonts
+        .get(&font.style())
+        .or_else(|| fonts.get(&StyleId::Regular))
+}
+
+fn draw_text(
+    canvas: &mut image::RgbaImage,
+    text: &PlacedText,
+    fonts: &FontSet,
+    scale: f32,
+    dx: f32,
+    dy: f32,
+) {
+    let Some(face) = font_for(fonts, text.font) else {
+        return;
+    };
+    let size = text.font.size * scale;
+    let baseline = (dy + text.baseline * scale).round() as i32;
+    let mut pen = dx + text.x * scale;
+    let (width, height) = canvas.dimensions();
+    for ch in text.text.chars() {
+        let (metrics, bitmap) = face.rasterize(ch, size);
+        let left = pen.round() as i32 + metrics.xmin;
+        let top = baseline - (metrics.ymin + metrics.height as i32);
+        for row in 0..metrics.height {
+            for col in 0..metrics.width {
+                let (x, y) = (left + col as i32, top + row as i32);
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+                let alpha = bitmap[row * metrics.width + col] as f32 / 255.0;
+                let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+                for channel in 0..3 {
+                    let value = pixel[channel] as f32 * (1.0 - alpha) + text.font.luma as f32 * alpha;
+                    pixel[channel] = value.round() as u8;
+                }
+            }
+        }
+        pen += metrics.advance_width;
+    }
+}
+
+fn resource_name(dir: &str, href: &str) -> String {
+    normalize_path(&tern_epub::resolve_href(dir, &strip_fragment(href)))
+}
+
+fn image_href(element: &Element) -> Option<&str> {
+    element
+        .attr("src")
+        .or_else(|| element.attr("href"))
+        .or_else(|| element.attr("xlink:href"))
+}
+
+fn natural_size(bytes: &[u8], fontdb: &usvg::fontdb::Database) -> Option<(f32, f32)> {
+    let dimensions = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    if let Some((width, height)) = dimensions {
+        return Some((width as f32, height as f32));
+    }
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default(), fontdb).ok()?;
+    Some((tree.size().width(), tree.size().height()))
+}
+
+/// Hands raster data to resvg in a format it decodes, converting the rest to PNG.
+fn image_kind(
+    data: Arc<Vec<u8>>,
+    options: &usvg::Options,
+    fontdb: &usvg::fontdb::Database,
+) -> Option<usvg::ImageKind> {
+    match image::guess_format(&data) {
+        Ok(image::ImageFormat::Jpeg) => Some(usvg::ImageKind::JPEG(data)),
+        Ok(image::ImageFormat::Png) => Some(usvg::ImageKind::PNG(data)),
+        Ok(image::ImageFormat::Gif) => Some(usvg::ImageKind::GIF(data)),
+        Ok(_) => {
+            let decoded = image::load_from_memory(&data).ok()?;
+            let mut png = Vec::new();
+            decoded
+                .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+                .ok()?;
+            Some(usvg::ImageKind::PNG(Arc::new(png)))
+        }
+        Err(_) => usvg::Tree::from_data(&data, options, fontdb)
+            .ok()
+            .map(usvg::ImageKind::SVG),
+    }
+}
+
+/// Page size from `<meta name="viewport" content="width=…, height=…">`, or
+/// the first inline SVG's `viewBox` or size.
+fn viewport_size(root: &Element) -> Option<(f32, f32)> {
+    let mut metas = Vec::new();
+    root.find_all("meta", &mut metas);
+    let viewport = metas
+        .iter()
+        .find(|meta| meta.attr("name").is_some_and(|name| name.eq_ignore_ascii_case("viewport")))
+        .and_then(|meta| meta.attr("content"));
+    if let Some(content) = viewport {
+        let mut width = None;
+        let mut height = None;
+        for part in content.split([',', ';']) {
+            let Some((key, value)) = part.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_end_matches("px").parse::<f32>().ok();
+            match key.trim() {
+                "width" => width = value,
+                "height" => height = value,
+                _ => {}
+            }
+        }
+        if let (Some(width), Some(height)) = (width, height) {
+            return Some((width, height));
+        }
+    }
+    let svg = root.find("svg")?;
+    if let Some(view_box) = svg.attr("viewBox") {
+        let numbers = view_box
+            .split([' ', ','])
+            .filter_map(|value| value.parse::<f32>().ok())
+            .collect::<Vec<_>>();
+        if numbers.len() == 4 {
+            return Some((numbers[2], numbers[3]));
+        }
+    }
+    let width = svg.attr("width")?.trim_end_matches("px").parse().ok()?;
+    let height = svg.attr("height")?.trim_end_matches("px").parse().ok()?;
+    Some((width, height))
+}
+
+fn parse_length(value: &str, em: f32, reference: f32) -> Option<f32> {
+    let value = value.trim();
+    let (number, factor) = if let Some(number) = value.strip_suffix("px") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix("pt") {
+        (number, 4.0 / 3.0)
+    } else if let Some(number) = value.strip_suffix("rem") {
+        (number, 16.0)
+    } else if let Some(number) = value.strip_suffix("em") {
+        (number, em)
+    } else if let Some(number) = value.strip_suffix('%') {
+        (number, reference / 100.0)
+    } else {
+        (value, 1.0)
+    };
+    number.trim().parse::<f32>().ok().map(|number| number * factor)
+}
+
+fn inherit_font(parent: Font, element: &Element, props: &HashMap<String, String>) -> Font {
+    let mut font = parent;
+    match element.name.as_str() {
+        "b" | "strong" => font.bold = true,
+        "i" | "em" | "cite" => font.italic = true,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            font.bold = true;
+            font.size *= match element.name.as_str() {
+                "h1" => 2.0,
+                "h2" => 1.5,
+                "h3" => 1.17,
+                _ => 1.0,
+            };
+        }
+        _ => {}
+    }
+    if let Some(size) = props
+        .get("font-size")
+        .and_then(|value| parse_length(value, parent.size, parent.size))
+    {
+        font.size = size;
+    }
+    if let Some(weight) = props.get("font-weight") {
+        font.bold = match weight.as_str() {
+            "bold" | "bolder" => true,
+            "normal" | "lighter" => false,
+            value => value.parse::<u32>().map_or(font.bold, |weight| weight >= 600),
+        };
+    }
+    if let Some(style) = props.get("font-style") {
+        font.italic = style == "italic" || style == "oblique";
+    }
+    if let Some(luma) = props.get("color").and_then(|value| parse_color(value)) {
+        font.luma = luma;
+    }
+    if let Some(value) = props.get("line-height") {
+        if value == "normal" {
+            font.line_height = LineHeight::Factor(1.2);
+        } else if let Ok(factor) = value.parse::<f32>() {
+            font.line_height = LineHeight::Factor(factor);
+        } else if let Some(px) = parse_length(value, font.size, font.size) {
+            font.line_height = LineHeight::Px(px);
+        }
+    }
+    if let Some(align) = props.get("text-align") {
+        font.align = match align.as_str() {
+            "center" => Align::Center,
+            "right" | "end" => Align::Right,
+            _ => Align::Left,
+        };
+    }
+    font
+}
+
+/// Luma of a CSS colour; only hex, `rgb()` and a few names are understood.
+fn parse_color(value: &str) -> Option<u8> {
+    let value = value.trim().to_ascii_lowercase();
+    let (r, g, b) = if let Some(hex) = value.strip_prefix('#') {
+        let channel = |range: std::ops::Range<usize>| u8::from_str_radix(hex.get(range)?, 16).ok();
+        match hex.len() {
+            3 => (
+                channel(0..1)? * 17,
+                channel(1..2)? * 17,
+                channel(2..3)? * 17,
+            ),
+            6 => (channel(0..2)?, channel(2..4)?, channel(4..6)?),
+            _ => return None,
+        }
+    } else if let Some(args) = value
+        .strip_prefix("rgb(")
+        .or_else(|| value.strip_prefix("rgba("))
+    {
+        let mut channels = args
+            .trim_end_matches(')')
+            .split(',')
+            .map(|part| part.trim().parse::<f32>().ok().map(|v| v.clamp(0.0, 255.0) as u8));
+        (channels.next()??, channels.next()??, channels.next()??)
+    } else {
+        match value.as_str() {
+            "black" => (0, 0, 0),
+            "white" => (255, 255, 255),
+            "gray" | "grey" => (128, 128, 128),
+            "silver" => (192, 192, 192),
+            "red" => (255, 0, 0),
+            "green" => (0, 128, 0),
+            "blue" => (0, 0, 255),
+            _ => return None,
+        }
+    };
+    Some(((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8)
+}
+
+/// Declarations from matching rules in order, then the `style` attribute.
+fn computed_style(element: &Element, sheet: &[Rule]) -> HashMap<String, String> {
+    let mut props = HashMap::new();
+    for rule in sheet {
+        if rule.selector.matches(element) {
+            for (name, value) in &rule.declarations {
+                props.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    if let Some(style) = element.attr("style") {
+        props.extend(parse_declarations(style));
+    }
+    props
+}
+
+struct Rule {
+    selector: Selector,
+    declarations: Vec<(String, String)>,
+}
+
+/// The last compound of a selector; ancestors are not checked.
+#[derive(Default)]
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    fn parse(text: &str) -> Option<Self> {
+        let compound = text
+            .trim()
+            .rsplit(|ch: char| ch.is_whitespace() || matches!(ch, '>' | '+' | '~'))
+            .next()?;
+        if compound.is_empty() || compound.contains([':', '[']) {
+            return None;
+        }
+        let mut selector = Selector::default();
+        let mut rest = compound;
+        let tag_len = rest.find(['.', '#']).unwrap_or(rest.len());
+        let tag = &rest[..tag_len];
+        if !tag.is_empty() && tag != "*" {
+            selector.tag = Some(tag.to_ascii_lowercase());
+        }
+        rest = &rest[tag_len..];
+        while let Some(kind) = rest.chars().next() {
+            let end = rest[1..].find(['.', '#']).map_or(rest.len(), |idx| idx + 1);
+            let name = rest[1..end].to_string();
+            if kind == '#' {
+                selector.id = Some(name);
+            } else {
+                selector.classes.push(name);
+            }
+            rest = &rest[end..];
+        }
+        Some(selector)
+    }
+
+    fn matches(&self, element: &Element) -> bool {
+        if self.tag.as_ref().is_some_and(|tag| *tag != element.name) {
+            return false;
+        }
+        if self.id.as_deref().is_some_and(|id| element.attr("id") != Some(id)) {
+            return false;
+        }
+        let classes = element.attr("class").unwrap_or("");
+        self.classes
+            .iter()
+            .all(|class| classes.split_whitespace().any(|name| name == class))
+    }
+}
+
+/// Adds the style rules of `css`; at-rules such as `@media` are skipped.
+fn parse_css(css: &str, rules: &mut Vec<Rule>) {
+    let mut css = css.to_string();
+    while let Some(start) = css.find("/*") {
+        let end = css[start + 2..].find("*/").map_or(css.len(), |idx| start + idx + 4);
+        css.replace_range(start..end, "");
+    }
+    let mut rest = css.as_str();
+    while let Some(open) = rest.find('{') {
+        let prelude = rest[..open].rsplit(';').next().unwrap_or("").trim();
+        let mut depth = 0;
+        let mut close = None;
+        for (idx, ch) in rest[open..].char_indices() {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + idx);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            break;
+        };
+        let body = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+        if prelude.starts_with('@') {
+            continue;
+        }
+        let declarations = parse_declarations(body);
+        for selector in prelude.split(',').filter_map(Selector::parse) {
+            rules.push(Rule {
+                selector,
+                declarations: declarations.clone(),
+            });
+        }
+    }
+}
+
+fn parse_declarations(text: &str) -> Vec<(String, String)> {
+    text.split(';')
+        .filter_map(|declaration| {
+            let (name, value) = declaration.split_once(':')?;
+            let value = value.trim().trim_end_matches("!important").trim();
+            Some((name.trim().to_ascii_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Element {
+    /// Lowercase local name.
+    name: String,
+    /// Name as written, for writing SVG back out.
+    qname: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    fn text(&self) -> String {
+        let mut out = String::new();
+        for child in &self.children {
+            match child {
+                Node::Text(text) => out.push_str(text),
+                Node::Element(element) => out.push_str(&element.text()),
+            }
+        }
+        out
+    }
+
+    fn find(&self, name: &str) -> Option<&Element> {
+        self.elements()
+            .find_map(|child| if child.name == name { Some(child) } else { child.find(name) })
+    }
+
+    fn find_all<'a>(&'a self, name: &str, out: &mut Vec<&'a Element>) {
+        for child in self.elements() {
+            if child.name == name {
+                out.push(child);
+            }
+            child.find_all(name, out);
+        }
+    }
+}
+
+/// Parses XHTML into a tree under an unnamed root, tolerating unclosed tags.
+fn parse_document(markup: &str) -> Option<Element> {
+    let mut reader = Reader::from_str(markup);
+    reader.config_mut().check_end_names = false;
+    let mut stack = vec![Element::default()];
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) => stack.push(element_from(&e)),
+            Event::Empty(e) => push_child(&mut stack, Node::Element(element_from(&e))),
+            Event::End(_) if stack.len() > 1 => {
+                let element = stack.pop().expect("stack has an open element");
+                push_child(&mut stack, Node::Element(element));
+            }
+            Event::Text(e) => {
+                let text = e.decode().ok()?.into_owned();
+                push_child(&mut stack, Node::Text(text));
+            }
+            Event::CData(e) => {
+                let text = String::from_utf8_lossy(&e.into_inner()).into_owned();
+                push_child(&mut stack, Node::Text(text));
+            }
+            Event::GeneralRef(e) => {
+                let text = match e.resolve_char_ref().ok()? {
+                    Some(ch) => ch.to_string(),
+                    None => {
+                        let name = e.decode().ok()?;
+                        match name.as_ref() {
+                            "nbsp" => "\u{a0}".to_string(),
+                            name => quick_xml::escape::resolve_predefined_entity(name)
+                                .unwrap_or_default()
+                                .to_string(),
+                        }
+                    }
+                };
+                push_child(&mut stack, Node::Text(text));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    while stack.len() > 1 {
+        let element = stack.pop().expect("stack has an open element");
+        push_child(&mut stack, Node::Element(element));
+    }
+    stack.pop()
+}
+
+fn element_from(e: &BytesStart<'_>) -> Element {
+    let attrs = e
+        .attributes()
+        .with_checks(false)
+        .flatten()
+        .map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr
+                .unescape_value()
+                .map(|value| value.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+            (key, value)
+        })
+        .collect();
+    Element {
+        name: String::from_utf8_lossy(e.local_name().as_ref()).to_ascii_lowercase(),
+        qname: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+        attrs,
+        children: Vec::new(),
+    }
+}
+
+fn push_child(stack: &mut [Element], child: Node) {
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(child);
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,177 @@
+//! Shared font packs (`.trfont`).
+//!
+//! A pack holds the common glyphs of one font set at one size, so books
+//! converted with the same fonts can leave those bitmaps out and the reader
+//! loads them once from `/fonts` on the SD card. Books keep the metrics of
+//! every glyph, and anything outside the pack stays embedded.
+//!
+//! Layout: magic `TRFN`, version (1), a reserved byte, font size (u16), hash
+//! (u32), glyph count (u32), char width (u16) and two reserved bytes, then
+//! glyph records as in the TRBK glyph table. The hash covers the records;
+//! books store it next to the pack name. The char width lets the reader pick
+//! the pack size closest to a book when it is chosen as the reading font.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::fonts::{self, FontPaths, FontSet, Glyph, StyleId};
+use crate::serialize::{write_atomically, write_glyph_table, GlyphBitmap};
+use crate::BookError;
+
+const MAGIC: &[u8; 4] = b"TRFN";
+const VERSION: u8 = 1;
+pub const EXTENSION: &str = "trfont";
+
+/// Codepoints put in every pack: Latin-1, Latin Extended-A, general
+/// punctuation and the euro sign.
+const PACK_RANGES: &[(u32, u32)] = &[
+    (0x20, 0x7E),
+    (0xA0, 0x17F),
+    (0x2010, 0x2027),
+    (0x2030, 0x203A),
+    (0x20AC, 0x20AC),
+];
+
+#[derive(Clone, Debug)]
+pub struct FontPack {
+    /// File name, `<regular font>-<size>-<hash>.trfont`.
+    pub name: String,
+    pub size: u16,
+    pub hash: u32,
+    pub glyphs: Vec<Glyph>,
+}
+
+/// What a book needs to know about the pack it was converted against.
+#[derive(Clone, Debug)]
+pub struct FontPackRef {
+    pub name: String,
+    pub hash: u32,
+    /// The pack's glyphs by codepoint and style.
+    glyphs: HashMap<(u32, StyleId), Glyph>,
+}
+
+impl FontPackRef {
+    /// Whether the pack has `glyph` with the same bitmap and metrics, so the
+    /// book can leave it out.
+    pub fn contains(&self, glyph: &Glyph) -> bool {
+        self.glyphs
+            .get(&(glyph.codepoint, glyph.style))
+            .is_some_and(|packed| same_glyph(packed, glyph))
+    }
+}
+
+impl FontPack {
+    /// Advance of the regular `n`, as in [`crate::RenderOptions::char_width`].
+    pub fn char_width(&self) -> u16 {
+        self.glyphs
+            .iter()
+            .find(|glyph| glyph.style == StyleId::Regular && glyph.codepoint == 'n' as u32)
+            .map(|glyph| glyph.x_advance.max(1) as u16)
+            .unwrap_or(self.size)
+    }
+
+    pub fn reference(&self) -> FontPackRef {
+        FontPackRef {
+            name: self.name.clone(),
+            hash: self.hash,
+            glyphs: self
+                .glyphs
+                .iter()
+                .map(|glyph| ((glyph.codepoint, glyph.style), glyph.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Rasterises the pack coverage for every style at `size`. Codepoints a
+/// style's font lacks are left to the books.
+pub fn build_font_pack(
+    fonts: &FontSet,
+    font_paths: &FontPaths,
+    size: u16,
+) -> Result<FontPack, BookError> {
+    let mut used = HashMap::new();
+    for style in [StyleId::Regular, StyleId::Bold, StyleId::Italic, StyleId::BoldItalic] {
+        let font = fonts
+            .get(&style)
+            .or_else(|| fonts.get(&StyleId::Regular))
+            .ok_or(BookError::InvalidOutput)?;
+        let codepoints = PACK_RANGES
+            .iter()
+            .flat_map(|&(first, last)| first..=last)
+            .filter(|&codepoint| {
+                char::from_u32(codepoint).is_some_and(|ch| font.lookup_glyph_index(ch) != 0)
+            })
+            .collect::<BTreeSet<_>>();
+        used.insert(style, codepoints);
+    }
+    let mut glyphs = fonts::build_glyphs(fonts, size, &used)?;
+    glyphs.sort_by_key(|glyph| (glyph.style, glyph.codepoint));
+    let hash = fnv1a(&glyph_records(&glyphs)?);
+    let stem = font_paths
+        .regular
+        .as_deref()
+        .and_then(|path| Path::new(path).file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "default".to_string());
+    Ok(FontPack {
+        name: format!("{stem}-{size}-{hash:08x}.{EXTENSION}"),
+        size,
+        hash,
+        glyphs,
+    })
+}
+
+pub fn serialize_font_pack(pack: &FontPack) -> Result<Vec<u8>, BookError> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(0);
+    bytes.extend_from_slice(&pack.size.to_le_bytes());
+    bytes.extend_from_slice(&pack.hash.to_le_bytes());
+    bytes.extend_from_slice(&(pack.glyphs.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&pack.char_width().to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&glyph_records(&pack.glyphs)?);
+    Ok(bytes)
+}
+
+/// Writes the pack into `dir` under its own name, unless it is already there.
+/// The name carries the hash, so an existing file with it is the same pack.
+pub fn write_font_pack(dir: &Path, pack: &FontPack) -> Result<PathBuf, BookError> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(&pack.name);
+    if !path.is_file() {
+        let bytes = serialize_font_pack(pack)?;
+        write_atomically(&path, |writer| Ok(writer.write_all(&bytes)?))?;
+    }
+    Ok(path)
+}
+
+fn glyph_records(glyphs: &[Glyph]) -> Result<Vec<u8>, BookError> {
+    let mut records = Vec::new();
+    write_glyph_table(&mut records, glyphs, &vec![GlyphBitmap::Inline; glyphs.len()])?;
+    Ok(records)
+}
+
+fn same_glyph(a: &Glyph, b: &Glyph) -> bool {
+    a.width == b.width
+        && a.height == b.height
+        && a.x_advance == b.x_advance
+        && a.x_offset == b.x_offset
+        && a.y_offset == b.y_offset
+        && a.bitmap_bw == b.bitmap_bw
+        && a.bitmap_lsb == b.bitmap_lsb
+        && a.bitmap_msb == b.bitmap_msb
+}
+
+/// 32-bit FNV-1a, which is stable across builds unlike `std`'s hasher.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
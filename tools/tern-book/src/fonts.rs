@@ -0,0 +1,322 @@
+//! Font loading, glyph rasterisation and the metrics derived from them.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use crate::{vertical, BookError, RenderOptions};
+
+/// Loaded fonts by style; styles without a font fall back to regular.
+pub type FontSet = HashMap<StyleId, fontdue::Font>;
+
+#[derive(Clone, Debug, Default)]
+pub struct FontPaths {
+    pub regular: Option<String>,
+    pub bold: Option<String>,
+    pub italic: Option<String>,
+    pub bold_italic: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum StyleId {
+    Regular = 0,
+    Bold = 1,
+    Italic = 2,
+    BoldItalic = 3,
+}
+
+#[derive(Clone, Debug)]
+pub struct Glyph {
+    pub codepoint: u32,
+    pub style: StyleId,
+    pub width: u8,
+    pub height: u8,
+    pub x_advance: i16,
+    pub x_offset: i16,
+    pub y_offset: i16,
+    pub bitmap_bw: Vec<u8>,
+    pub bitmap_lsb: Vec<u8>,
+    pub bitmap_msb: Vec<u8>,
+}
+
+pub fn style_id_from_style(style: tern_epub::TextStyle) -> StyleId {
+    match (style.bold, style.italic) {
+        (false, false) => StyleId::Regular,
+        (true, false) => StyleId::Bold,
+        (false, true) => StyleId::Italic,
+        (true, true) => StyleId::BoldItalic,
+    }
+}
+
+pub fn load_fonts(paths: &FontPaths) -> Result<FontSet, BookError> {
+    let mut map = HashMap::new();
+    let regular_path = paths
+        .regular
+        .as_deref()
+        .unwrap_or("fonts/DejaVuSans.ttf");
+    let regular_bytes = std::fs::read(regular_path).map_err(|err| {
+        BookError::Io(std::io::Error::new(
+            err.kind(),
+            format!("missing font file: {regular_path}"),
+        ))
+    })?;
+    let regular = fontdue::Font::from_bytes(regular_bytes, fontdue::FontSettings::default())
+        .map_err(|_| BookError::InvalidOutput)?;
+    map.insert(StyleId::Regular, regular.clone());
+
+    let auto_bold = if paths.bold.is_none() {
+        guess_font_variant(regular_path, FontVariant::Bold)
+    } else {
+        None
+    };
+    let auto_italic = if paths.italic.is_none() {
+        guess_font_variant(regular_path, FontVariant::Italic)
+    } else {
+        None
+    };
+    let auto_bold_italic = if paths.bold_italic.is_none() {
+        guess_font_variant(regular_path, FontVariant::BoldItalic)
+    } else {
+        None
+    };
+
+    if let Some(path) = paths.bold.as_deref().or(auto_bold.as_deref()) {
+        let bytes = std::fs::read(path).map_err(|err| {
+            BookError::Io(std::io::Error::new(
+                err.kind(),
+                format!("missing font file: {path}"),
+            ))
+        })?;
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|_| BookError::InvalidOutput)?;
+        map.insert(StyleId::Bold, font);
+    }
+    if let Some(path) = paths.italic.as_deref().or(auto_italic.as_deref()) {
+        let bytes = std::fs::read(path).map_err(|err| {
+            BookError::Io(std::io::Error::new(
+                err.kind(),
+                format!("missing font file: {path}"),
+            ))
+        })?;
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|_| BookError::InvalidOutput)?;
+        map.insert(StyleId::Italic, font);
+    }
+    if let Some(path) = paths.bold_italic.as_deref().or(auto_bold_italic.as_deref()) {
+        let bytes = std::fs::read(path).map_err(|err| {
+            BookError::Io(std::io::Error::new(
+                err.kind(),
+                format!("missing font file: {path}"),
+            ))
+        })?;
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|_| BookError::InvalidOutput)?;
+        map.insert(StyleId::BoldItalic, font);
+    }
+
+    Ok(map)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FontVariant {
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+fn guess_font_variant(regular_path: &str, variant: FontVariant) -> Option<String> {
+    let path = Path::new(regular_path);
+    let stem = path.file_stem()?.to_string_lossy();
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("ttf");
+    let mut candidates = Vec::new();
+
+    // Common patterns: Foo-Regular -> Foo-Bold / Foo-Italic / Foo-BoldItalic
+    let base = stem
+        .replace("Regular", "")
+        .replace("regular", "")
+        .replace("Roman", "")
+        .replace("roman", "")
+        .trim_end_matches('-')
+        .trim_end_matches('_')
+        .to_string();
+    let suffix = match variant {
+        FontVariant::Bold => "Bold",
+        FontVariant::Italic => "Italic",
+        FontVariant::BoldItalic => "Bold Italic",
+    };
+    if !base.is_empty() {
+        candidates.push(format!("{}-{}.{}", base, suffix, ext));
+        candidates.push(format!("{}_{}.{}", base, suffix, ext));
+        candidates.push(format!("{} {}.{}", base, suffix, ext));
+        candidates.push(format!("{}{}.{}", base, suffix.replace(' ', ""), ext));
+    }
+    // Also try replacing Regular in the original stem.
+    let replaced = match variant {
+        FontVariant::Bold => stem.replace("Regular", "Bold").replace("regular", "Bold"),
+        FontVariant::Italic => stem.replace("Regular", "Italic").replace("regular", "Italic"),
+        FontVariant::BoldItalic => stem
+            .replace("Regular", "Bold Italic")
+            .replace("regular", "Bold Italic"),
+    };
+    if replaced != stem {
+        candidates.push(format!("{}.{}", replaced, ext));
+    }
+
+    for name in candidates {
+        let candidate = path.with_file_name(name);
+        if candidate.is_file() {
+            return Some(candidate.to_string_lossy().to_string());
+        }
+    }
+    None
+}
+
+/// Layout metrics for the regular font at `size`; `used` picks the glyphs the
+/// ascent is measured from.
+pub fn options_for_size(
+    fonts: &FontSet,
+    size: u16,
+    used: &HashMap<StyleId, BTreeSet<u32>>,
+) -> Result<RenderOptions, BookError> {
+    let mut options = RenderOptions::default();
+    let regular = fonts
+        .get(&StyleId::Regular)
+        .ok_or(BookError::InvalidOutput)?;
+    let (metrics, _) = regular.rasterize('n', size as f32);
+    options.char_width = metrics.advance_width.round().max(1.0) as u16;
+    let mut codepoints = used
+        .get(&StyleId::Regular)
+        .cloned()
+        .unwrap_or_default();
+    if codepoints.is_empty() {
+        for set in used.values() {
+            codepoints.extend(set.iter().copied());
+        }
+    }
+    options.ascent = compute_ascent(regular, size, &codepoints);
+    if let Some(lines) = regular.horizontal_line_metrics(size as f32) {
+        let height = (lines.ascent - lines.descent + lines.line_gap)
+            .ceil()
+            .max(1.0) as u16;
+        let extra = (height / 6).max(2);
+        options.line_height = height.saturating_add(extra);
+    } else {
+        options.line_height = size.saturating_mul(2);
+    }
+    options.word_spacing = (options.char_width as i16 / 3).max(2);
+    Ok(options)
+}
+
+/// Rasterises every codepoint in `used` at `size` pixels.
+pub fn build_glyphs(
+    fonts: &FontSet,
+    size: u16,
+    used: &HashMap<StyleId, BTreeSet<u32>>,
+) -> Result<Vec<Glyph>, BookError> {
+    let mut glyphs = Vec::new();
+    for (style, codepoints) in used {
+        let font = fonts
+            .get(style)
+            .or_else(|| fonts.get(&StyleId::Regular))
+            .ok_or(BookError::InvalidOutput)?;
+        for codepoint in codepoints {
+            let rotated = vertical::rotated_source(*codepoint);
+            if let Some(ch) = char::from_u32(rotated.unwrap_or(*codepoint)) {
+                let (metrics, mut bitmap) = font.rasterize(ch, size as f32);
+                let (mut width, mut height) = (metrics.width, metrics.height);
+                let mut x_offset = metrics.xmin as i16;
+                let mut y_offset = (metrics.ymin + metrics.height as i32) as i16;
+                if rotated.is_some() {
+                    // Drawn with the origin on the column centre line and the
+                    // baseline at the top of the character cell.
+                    bitmap = vertical::rotate_clockwise(&bitmap, width, height);
+                    x_offset = metrics.ymin as i16 - vertical::column_center(font, size);
+                    y_offset = -(metrics.xmin as i16);
+                    (width, height) = (height, width);
+                }
+                let (bw, lsb, msb) = pack_gray2_bitmap(&bitmap, width, height);
+                glyphs.push(Glyph {
+                    codepoint: *codepoint,
+                    style: *style,
+                    width: width as u8,
+                    height: height as u8,
+                    x_advance: metrics.advance_width.round() as i16,
+                    x_offset,
+                    y_offset,
+                    bitmap_bw: bw,
+                    bitmap_lsb: lsb,
+                    bitmap_msb: msb,
+                });
+            }
+        }
+    }
+    Ok(glyphs)
+}
+
+fn pack_gray2_bitmap(bitmap: &[u8], width: usize, height: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let total = width * height;
+    let mut bw = vec![0u8; (total + 7) / 8];
+    let mut lsb = vec![0u8; (total + 7) / 8];
+    let mut msb = vec![0u8; (total + 7) / 8];
+    for i in 0..total {
+        let byte = i / 8;
+        let bit = 7 - (i % 8);
+        let val = 255u8.saturating_sub(bitmap[i]);
+        let (bw_bit, msb_bit, lsb_bit) = if val >= 205 {
+            (1u8, 0u8, 0u8)
+        } else if val >= 154 {
+            (1u8, 0u8, 1u8)
+        } else if val >= 103 {
+            (0u8, 1u8, 0u8)
+        } else if val >= 52 {
+            (0u8, 1u8, 1u8)
+        } else {
+            (0u8, 0u8, 0u8)
+        };
+        if bw_bit != 0 {
+            bw[byte] |= 1 << bit;
+        }
+        if lsb_bit != 0 {
+            lsb[byte] |= 1 << bit;
+        }
+        if msb_bit != 0 {
+            msb[byte] |= 1 << bit;
+        }
+    }
+    (bw, lsb, msb)
+}
+
+fn compute_ascent(font: &fontdue::Font, size: u16, codepoints: &BTreeSet<u32>) -> i16 {
+    let mut cap_ascent = 0i16;
+    let mut ascent = 0i16;
+    for cp in codepoints {
+        if let Some(ch) = char::from_u32(*cp) {
+            let (metrics, _) = font.rasterize(ch, size as f32);
+            let candidate = (metrics.ymin + metrics.height as i32).max(0) as i16;
+            if ch.is_ascii_uppercase() && candidate > cap_ascent {
+                cap_ascent = candidate;
+            }
+            if candidate > ascent {
+                ascent = candidate;
+            }
+        }
+    }
+    let picked = if cap_ascent > 0 { cap_ascent } else { ascent };
+    if picked == 0 {
+        size as i16
+    } else {
+        picked
+    }
+}
+
+pub fn warn_missing_style_fonts(used: &HashMap<StyleId, BTreeSet<u32>>, fonts: &FontSet) {
+    let warn = |style: StyleId, label: &str| {
+        if used.get(&style).map_or(false, |set| !set.is_empty()) && !fonts.contains_key(&style) {
+            eprintln!(
+                "[tern-book] warning: {label} text found but no {label} font was loaded; using regular"
+            );
+        }
+    };
+    warn(StyleId::Bold, "bold");
+    warn(StyleId::Italic, "italic");
+    warn(StyleId::BoldItalic, "bold-italic");
+}
@@ -0,0 +1,367 @@
+//! Embedded images, converted to TRI at the size they are laid out at.
+
+use std::collections::HashMap;
+
+use image::GenericImageView;
+
+use crate::blocks::{HtmlBlock, SpineBlocks, TextRun, TextStyle};
+use crate::input::BookInput;
+use crate::{BookError, RenderOptions};
+
+/// A converted image; `data` is a complete TRI file.
+#[derive(Clone, Debug)]
+pub struct ImageAsset {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+/// An image that could not be found or decoded. Its page gets a bracketed
+/// placeholder with the alt text instead.
+#[derive(Clone, Debug)]
+pub struct MissingImage {
+    pub src: String,
+    pub alt: Option<String>,
+    pub reason: &'static str,
+}
+
+/// Text standing in for an image that is not in the book.
+pub fn placeholder_text(alt: Option<&str>) -> String {
+    match alt.map(str::trim).filter(|alt| !alt.is_empty()) {
+        Some(alt) => format!("[Image: {alt}]"),
+        None => "[Image]".to_string(),
+    }
+}
+
+/// Style placeholders are set in, so they stand apart from the text.
+pub(crate) const PLACEHOLDER_STYLE: TextStyle = TextStyle {
+    bold: false,
+    italic: true,
+};
+
+/// `blocks` with each missing image replaced by a paragraph of its
+/// placeholder text.
+pub fn with_placeholders(blocks: &[SpineBlocks], missing: &[MissingImage]) -> Vec<SpineBlocks> {
+    blocks
+        .iter()
+        .map(|spine| SpineBlocks {
+            spine_index: spine.spine_index,
+            blocks: spine
+                .blocks
+                .iter()
+                .map(|block| match block {
+                    HtmlBlock::Image { alt, src } if missing.iter().any(|image| image.src == *src) => {
+                        HtmlBlock::Paragraph {
+                            runs: vec![TextRun {
+                                text: placeholder_text(alt.as_deref()),
+                                style: PLACEHOLDER_STYLE,
+                                math: None,
+                            }],
+                            heading_level: None,
+                        }
+                    }
+                    _ => block.clone(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Caps on converted images, for devices short on memory. The defaults
+/// leave images at the size they are laid out at with four gray levels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageLimits {
+    /// Longest side of an embedded image, in pixels. Full-screen pages are
+    /// not capped.
+    pub max_dim: Option<u16>,
+    /// 1 for black and white, 2 for four gray levels.
+    pub gray_bits: u8,
+    /// Largest TRI file for an embedded image, in KiB. Images are scaled
+    /// down until they fit; full-screen pages are not capped.
+    pub max_kb: Option<u32>,
+    /// Leaves embedded images and the cover out of the book. Fixed-layout
+    /// pages are the book's content and are kept.
+    pub strip: bool,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_dim: None,
+            gray_bits: 2,
+            max_kb: None,
+            strip: false,
+        }
+    }
+}
+
+impl ImageLimits {
+    /// TRI version to write: 1 is 1-bit, 2 is 2-bit gray.
+    pub(crate) fn trimg_version(&self) -> u8 {
+        if self.gray_bits == 1 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Most pixels an image can have and still fit in `max_kb`. Gray images
+    /// take three bit planes.
+    fn max_pixels(&self) -> Option<u32> {
+        let bits_per_pixel = if self.gray_bits == 1 { 1 } else { 3 };
+        self.max_kb.map(|max_kb| {
+            let bytes = (max_kb as u64 * 1024).saturating_sub(TRI_HEADER_LEN);
+            (bytes * 8 / bits_per_pixel).clamp(1, u32::MAX as u64) as u32
+        })
+    }
+}
+
+/// Bytes before the pixels in a TRI written by [`trimg_to_bytes`].
+const TRI_HEADER_LEN: u64 = 16;
+
+/// Where an image block or equation ended up in the asset table.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageRef {
+    pub index: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Rows above the text baseline when drawn inline.
+    pub baseline: u16,
+}
+
+/// Image table index of each converted image, keyed by block `src`.
+pub type ImageMap = HashMap<String, ImageRef>;
+
+/// Converts every image referenced by `blocks`, keyed by block `src`. SVGs are
+/// rasterised at the size they are laid out at. Images that are missing or
+/// fail to decode are left out with a warning and listed last; see
+/// [`with_placeholders`].
+pub fn build_image_assets(
+    input: &dyn BookInput,
+    blocks: &[SpineBlocks],
+    options: &RenderOptions,
+) -> Result<(Vec<ImageAsset>, ImageMap, Vec<MissingImage>), BookError> {
+    let mut assets: Vec<ImageAsset> = Vec::new();
+    let mut map: HashMap<String, ImageRef> = HashMap::new();
+    let mut missing: Vec<MissingImage> = Vec::new();
+    // System fonts for SVG text, loaded on the first SVG.
+    let mut fontdb: Option<usvg::fontdb::Database> = None;
+    if options.image_limits.strip {
+        return Ok((assets, map, missing));
+    }
+
+    for spine in blocks {
+        for block in &spine.blocks {
+            let HtmlBlock::Image { src, alt } = block else {
+                continue;
+            };
+            if map.contains_key(src) || missing.iter().any(|image| image.src == *src) {
+                continue;
+            }
+            let mut skip = |reason: &'static str| {
+                eprintln!("[tern-book] warning: {reason}: {src}; a placeholder takes its place");
+                missing.push(MissingImage {
+                    src: src.clone(),
+                    alt: alt.clone(),
+                    reason,
+                });
+            };
+            let Some(bytes) = input.resource(src) else {
+                skip("image not found in book");
+                continue;
+            };
+            let source = if is_svg(src, &bytes) {
+                let fontdb = fontdb.get_or_insert_with(system_fontdb);
+                match usvg::Tree::from_data(&bytes, &usvg::Options::default(), fontdb) {
+                    Ok(tree) => ImageSource::Svg(Box::new(tree)),
+                    Err(_) => {
+                        skip("failed to parse svg image");
+                        continue;
+                    }
+                }
+            } else {
+                match tern_image::decode_frames(&bytes) {
+                    Ok(mut frames) => {
+                        if frames.len() > 1 {
+                            eprintln!(
+                                "[tern-book] warning: animated image {src} has {} frames, using the first",
+                                frames.len()
+                            );
+                        }
+                        ImageSource::Raster(frames.swap_remove(0))
+                    }
+                    Err(_) => {
+                        skip("failed to decode image");
+                        continue;
+                    }
+                }
+            };
+            let (src_w, src_h) = match &source {
+                ImageSource::Raster(img) => img.dimensions(),
+                ImageSource::Svg(tree) => (
+                    tree.size().width().ceil() as u32,
+                    tree.size().height().ceil() as u32,
+                ),
+            };
+            let max_w = options.screen_width.max(1) as u32;
+            let max_h =
+                (options.screen_height as i32 - options.margin_y as i32 * 2).max(1) as u32;
+            let mut scale = if src_w >= max_w {
+                max_w as f64 / src_w.max(1) as f64
+            } else {
+                let up = max_w as f64 / src_w.max(1) as f64;
+                up.min(2.0)
+            };
+            let max_scale_h = max_h as f64 / src_h.max(1) as f64;
+            if scale > max_scale_h {
+                scale = max_scale_h;
+            }
+            if let Some(max_dim) = options.image_limits.max_dim {
+                let max_scale_dim = max_dim.max(1) as f64 / src_w.max(src_h).max(1) as f64;
+                scale = scale.min(max_scale_dim);
+            }
+            if let Some(max_pixels) = options.image_limits.max_pixels() {
+                let max_scale_pixels = (max_pixels as f64 / (src_w.max(1) as f64 * src_h.max(1) as f64)).sqrt();
+                scale = scale.min(max_scale_pixels);
+            }
+            let target_w = (src_w as f64 * scale).round().max(1.0) as u32;
+            let mut target_h = (src_h as f64 * scale).round().max(1.0) as u32;
+            if let Some(max_pixels) = options.image_limits.max_pixels() {
+                // Rounding up can leave it a few pixels over.
+                target_h = target_h.min((max_pixels / target_w).max(1));
+            }
+            let dyn_image = match source {
+                ImageSource::Raster(img) => img,
+                ImageSource::Svg(tree) => render_svg(&tree, target_w, target_h),
+            };
+            let mut convert = tern_image::ConvertOptions::default();
+            convert.width = target_w;
+            convert.height = target_h;
+            convert.fit = tern_image::FitMode::Contain;
+            convert.dither = tern_image::DitherMode::Bayer;
+            convert.region_mode = tern_image::RegionMode::None;
+            convert.invert = false;
+            convert.debug = false;
+            convert.yolo_model = None;
+            convert.trimg_version = options.image_limits.trimg_version();
+            let trimg = tern_image::convert_image(&dyn_image, convert);
+            let data = trimg_to_bytes(&trimg);
+            let index = assets.len() as u16;
+            let image_ref = ImageRef {
+                index,
+                width: trimg.width as u16,
+                height: trimg.height as u16,
+                baseline: trimg.height as u16,
+            };
+            assets.push(ImageAsset {
+                width: image_ref.width,
+                height: image_ref.height,
+                data,
+            });
+            map.insert(src.clone(), image_ref);
+        }
+    }
+
+    Ok((assets, map, missing))
+}
+
+enum ImageSource {
+    Raster(image::DynamicImage),
+    Svg(Box<usvg::Tree>),
+}
+
+/// Goes by the extension, then sniffs for an `<svg` root near the start.
+fn is_svg(src: &str, bytes: &[u8]) -> bool {
+    let lower = src.to_ascii_lowercase();
+    if lower.ends_with(".svg") || lower.ends_with(".svgz") {
+        return true;
+    }
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let head = head.trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<!DOCTYPE") || head.starts_with("<svg"))
+        && head.contains("<svg")
+}
+
+/// System fonts for SVG text. Generic families whose default face is not
+/// installed (Times New Roman and Arial on most Linux systems) are pointed at
+/// one that is, so text is not dropped.
+pub(crate) fn system_fontdb() -> usvg::fontdb::Database {
+    use usvg::fontdb::{Family, Query};
+
+    let mut db = usvg::fontdb::Database::new();
+    db.load_system_fonts();
+    let names = db
+        .faces()
+        .filter_map(|face| face.families.first().map(|(name, _)| name.clone()))
+        .collect::<Vec<_>>();
+    // Computed up front: the closure borrows `db`, which is changed below.
+    let fallback = |family: Family, wanted: &dyn Fn(&str) -> bool| {
+        let installed = db
+            .query(&Query {
+                families: &[family],
+                ..Default::default()
+            })
+            .is_some();
+        if installed {
+            return None;
+        }
+        names
+            .iter()
+            .find(|name| wanted(name))
+            .or_else(|| names.first())
+            .cloned()
+    };
+    let serif = fallback(Family::Serif, &|name| name.contains("Serif") && !name.contains("Sans"));
+    let sans_serif = fallback(Family::SansSerif, &|name| name.contains("Sans") && !name.contains("Mono"));
+    let monospace = fallback(Family::Monospace, &|name| name.contains("Mono"));
+    if let Some(name) = serif {
+        db.set_serif_family(name);
+    }
+    if let Some(name) = sans_serif {
+        db.set_sans_serif_family(name);
+    }
+    if let Some(name) = monospace {
+        db.set_monospace_family(name);
+    }
+    db
+}
+
+/// Renders `tree` scaled to fit `width` x `height`, centred on white.
+pub(crate) fn render_svg(tree: &usvg::Tree, width: u32, height: u32) -> image::DynamicImage {
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).expect("non-zero image size");
+    pixmap.fill(tiny_skia::Color::WHITE);
+    let size = tree.size();
+    let scale = (width as f32 / size.width()).min(height as f32 / size.height());
+    let tx = (width as f32 - size.width() * scale) * 0.5;
+    let ty = (height as f32 - size.height() * scale) * 0.5;
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(tx, ty);
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    // Opaque after the white fill, so the premultiplied data is plain RGBA.
+    let rgba = image::RgbaImage::from_raw(width, height, pixmap.take())
+        .expect("pixmap matches image size");
+    image::DynamicImage::ImageRgba8(rgba)
+}
+
+pub(crate) fn trimg_to_bytes(trimg: &tern_image::Trimg) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"TRIM");
+    match &trimg.data {
+        tern_image::TrimgData::Mono1 { bits } => {
+            out.push(1);
+            out.push(1);
+            out.extend_from_slice(&(trimg.width as u16).to_le_bytes());
+            out.extend_from_slice(&(trimg.height as u16).to_le_bytes());
+            out.extend_from_slice(&[0u8; 6]);
+            out.extend_from_slice(bits);
+        }
+        tern_image::TrimgData::Gray2 { data } => {
+            out.push(2);
+            out.push(2);
+            out.extend_from_slice(&(trimg.width as u16).to_le_bytes());
+            out.extend_from_slice(&(trimg.height as u16).to_le_bytes());
+            out.extend_from_slice(&[0u8; 6]);
+            out.extend_from_slice(data);
+        }
+    }
+    out
+}
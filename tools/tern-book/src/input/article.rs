@@ -0,0 +1,596 @@
+//! Web article backend, for read-it-later clippings: a page saved as HTML, or
+//! fetched with `curl` when the input is an `http(s)://` URL.
+//!
+//! A readability pass picks the element holding the article. Navigation,
+//! sidebars, comments and the like are dropped first by element name and by
+//! class and id words, then each paragraph scores its parent and grandparent
+//! by length and commas. The best-scoring element, weighed against how much
+//! of its text is links, becomes the book along with any siblings that score
+//! nearly as well. Images in it are fetched up front so the conversion does
+//! not touch the network again.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use super::html::{decode_text, tidy};
+use super::{BookInput, InputFormat, SpineItem};
+use crate::blocks::{normalize_path, percent_decode, HtmlBlock, TextRun, TextStyle};
+use crate::toc::normalize_title;
+use crate::{BookError, TrbkMetadata};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "html",
+    extensions: &["html", "htm"],
+    sniff: |head| {
+        let head = String::from_utf8_lossy(head).trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+        head.starts_with("<!doctype html") || head.starts_with("<html")
+    },
+    open: |path| Ok(Box::new(ArticleInput::open(path)?)),
+};
+
+const CURL: &str = "curl";
+const USER_AGENT: &str = "Mozilla/5.0 (compatible; tern-book)";
+/// Seconds a page or image may take to download.
+const FETCH_TIMEOUT: &str = "60";
+/// Images fetched for one article at most.
+const MAX_IMAGES: usize = 64;
+
+/// Paragraph elements that score their ancestors.
+const SCORED_ELEMENTS: &[&str] = &["p", "pre", "td", "section", "h2", "h3", "h4", "h5", "h6"];
+/// Paragraphs shorter than this, in characters, do not count.
+const MIN_PARAGRAPH: usize = 25;
+/// Elements that are never part of the article.
+const UNLIKELY_ELEMENTS: &[&str] = &[
+    "nav", "header", "footer", "aside", "form", "button", "iframe", "noscript", "svg", "select", "textarea",
+    "input", "menu", "dialog", "canvas", "object", "embed", "video", "audio",
+];
+/// Class and id words of page furniture, unless a `MAYBE_ARTICLE` word also matches.
+const UNLIKELY_NAMES: &[&str] = &[
+    "-ad-", "banner", "breadcrumb", "combx", "comment", "community", "cookie", "disqus", "extra", "footer",
+    "gdpr", "header", "menu", "modal", "newsletter", "pager", "pagination", "popup", "promo", "related",
+    "remark", "replies", "rss", "share", "shoutbox", "sidebar", "skyscraper", "social", "sponsor",
+    "subscribe", "supplemental",
+];
+const MAYBE_ARTICLE: &[&str] = &["and", "article", "body", "column", "content", "main", "shadow"];
+const POSITIVE_NAMES: &[&str] = &[
+    "article", "blog", "body", "content", "entry", "h-entry", "hentry", "main", "page", "post", "story", "text",
+];
+const NEGATIVE_NAMES: &[&str] = &[
+    "-ad-", "banner", "byline", "combx", "comment", "com-", "contact", "foot", "footnote", "hidden",
+    "masthead", "media", "meta", "outbrain", "promo", "related", "scroll", "share", "shoutbox", "sidebar",
+    "skyscraper", "sponsor", "shopping", "tags", "tool", "widget",
+];
+/// Attributes lazy-loading pages keep the real image address in.
+const IMAGE_ATTRIBUTES: &[&str] = &["data-src", "data-original", "data-lazy-src", "src"];
+
+#[derive(Clone, Debug)]
+pub struct ArticleInput {
+    /// URL or path the page was read from.
+    pub source: String,
+    metadata: TrbkMetadata,
+    blocks: Vec<HtmlBlock>,
+    images: HashMap<String, Vec<u8>>,
+    spine: Vec<SpineItem>,
+}
+
+impl ArticleInput {
+    /// Reads a saved page, or downloads it when `path` is a URL. Images
+    /// next to a saved page are read from disk.
+    pub fn open(path: &Path) -> Result<Self, BookError> {
+        if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+            return Self::fetch(url);
+        }
+        let bytes = std::fs::read(path)?;
+        let base = match path.parent().map(|dir| dir.display().to_string()) {
+            Some(dir) if !dir.is_empty() => format!("{dir}/"),
+            _ => String::new(),
+        };
+        Self::from_html(&bytes, &path.display().to_string(), &base)
+    }
+
+    /// Downloads the page at `url` with `curl`, which has to be on the `PATH`.
+    pub fn fetch(url: &str) -> Result<Self, BookError> {
+        let bytes = fetch(url)?;
+        Self::from_html(&bytes, url, url)
+    }
+
+    /// Extracts the article from a page. Relative image addresses resolve
+    /// against `base`, a URL or a directory ending in `/`.
+    pub fn from_html(bytes: &[u8], source: &str, base: &str) -> Result<Self, BookError> {
+        let dom = Dom::parse(&tidy(&decode_page(bytes), |_, _, _| None));
+        let meta = |key: &str, name: &str| {
+            dom.elements("meta")
+                .find(|&id| dom.attribute(id, key).is_some_and(|value| value.eq_ignore_ascii_case(name)))
+                .and_then(|id| dom.attribute(id, "content"))
+                .filter(|value| !value.trim().is_empty())
+                .map(str::to_string)
+        };
+        let base = dom
+            .elements("base")
+            .find_map(|id| dom.attribute(id, "href"))
+            .map(|href| resolve(base, &unescape(href)))
+            .unwrap_or_else(|| base.to_string());
+        let title = meta("property", "og:title")
+            .or_else(|| {
+                let title = dom.elements("title").next()?;
+                let mut text = String::new();
+                dom.text(title, &mut text);
+                // Drop the site name pages append to their titles.
+                Some(text.split(" | ").next().unwrap_or_default().to_string())
+            })
+            .map(|title| normalize_title(&title))
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| source.rsplit('/').find(|part| !part.is_empty()).unwrap_or(source).to_string());
+        let author = meta("name", "author")
+            .or_else(|| meta("property", "article:author").filter(|author| !is_url(author)))
+            .map(|author| unescape(&author))
+            .unwrap_or_else(|| "<unknown>".to_string());
+        let language = dom
+            .elements("html")
+            .find_map(|id| dom.attribute(id, "lang"))
+            .map(unescape)
+            .unwrap_or_else(|| "<unknown>".to_string());
+
+        let mut dom = dom;
+        let content = dom.article();
+        let mut images = HashMap::new();
+        let mut xhtml = String::from("<html><body>");
+        for id in content {
+            dom.write(id, &base, &mut images, &mut xhtml);
+        }
+        xhtml.push_str("</body></html>");
+        let mut blocks = tern_epub::parse_xhtml_blocks(&xhtml)?;
+        // Layout whitespace between the page's elements.
+        blocks.retain(|block| match block {
+            HtmlBlock::Paragraph { runs, .. } => runs.iter().any(|run| !run.text.trim().is_empty()),
+            _ => true,
+        });
+        if blocks.is_empty() {
+            return Err(BookError::Article(format!("no article text found in {source}")));
+        }
+        let starts_with_title = blocks.iter().find_map(|block| match block {
+            HtmlBlock::Paragraph { runs, heading_level } => Some(
+                heading_level.is_some()
+                    && runs.iter().map(|run| run.text.as_str()).collect::<String>().trim().eq_ignore_ascii_case(&title),
+            ),
+            _ => None,
+        });
+        if starts_with_title != Some(true) {
+            blocks.insert(
+                0,
+                HtmlBlock::Paragraph {
+                    runs: vec![TextRun {
+                        text: title.clone(),
+                        style: TextStyle::default(),
+                        math: None,
+                    }],
+                    heading_level: Some(1),
+                },
+            );
+        }
+
+        let images = images
+            .into_iter()
+            .take(MAX_IMAGES)
+            .filter_map(|(name, location): (String, String)| {
+                let bytes = if is_url(&location) {
+                    fetch(&location).ok()
+                } else {
+                    std::fs::read(&location).ok()
+                };
+                Some((name, bytes?))
+            })
+            .collect();
+        Ok(Self {
+            source: source.to_string(),
+            metadata: TrbkMetadata {
+                title,
+                author,
+                language,
+                identifier: source.to_string(),
+                part: None,
+                series: None,
+            },
+            blocks,
+            images,
+            spine: vec![SpineItem {
+                name: source.to_string(),
+            }],
+        })
+    }
+}
+
+impl BookInput for ArticleInput {
+    fn metadata(&self) -> TrbkMetadata {
+        self.metadata.clone()
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        if index != 0 {
+            return Err(BookError::SpineIndex(index));
+        }
+        Ok(self.blocks.clone())
+    }
+
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        self.images.get(name).cloned()
+    }
+
+    fn spine_title(&self, _index: usize) -> Option<String> {
+        Some(self.metadata.title.clone())
+    }
+}
+
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Downloads `url` with `curl`.
+pub fn fetch(url: &str) -> Result<Vec<u8>, BookError> {
+    let output = Command::new(CURL)
+        .args(["--silent", "--show-error", "--location", "--fail", "--compressed"])
+        .args(["--max-time", FETCH_TIMEOUT, "--user-agent", USER_AGENT])
+        .arg(url)
+        .output();
+    let output = match output {
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(BookError::Article(format!("`{CURL}` was not found; install it to convert web pages")));
+        }
+        Err(err) => return Err(err.into()),
+        Ok(output) => output,
+    };
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(BookError::Article(format!("fetching {url} failed: {}", message.trim())));
+    }
+    Ok(output.stdout)
+}
+
+/// Page text in the charset its `<meta>` declares; UTF-8 unless it names a
+/// Latin-1 family one.
+fn decode_page(bytes: &[u8]) -> String {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(2048)]).to_ascii_lowercase();
+    let charset = head
+        .find("charset=")
+        .map(|at| head[at + 8..].trim_start_matches(['"', '\'']))
+        .map(|rest| rest.split(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')).next().unwrap_or(""));
+    let latin1 = matches!(charset, Some("windows-1252" | "iso-8859-1" | "latin1" | "us-ascii" | "cp1252"));
+    decode_text(bytes, !latin1)
+}
+
+fn unescape(text: &str) -> String {
+    quick_xml::escape::unescape(text)
+        .map(|text| text.into_owned())
+        .unwrap_or_else(|_| text.to_string())
+}
+
+/// Resolves `href` against a page URL or a directory ending in `/`.
+fn resolve(base: &str, href: &str) -> String {
+    if is_url(href) {
+        return href.to_string();
+    }
+    if !is_url(base) {
+        return format!("{base}{}", percent_decode(href));
+    }
+    let scheme_end = base.find("://").map(|at| at + 3).unwrap_or(0);
+    if let Some(rest) = href.strip_prefix("//") {
+        return format!("{}{rest}", &base[..scheme_end]);
+    }
+    let host_end = base[scheme_end..].find('/').map(|at| scheme_end + at).unwrap_or(base.len());
+    let path = base[host_end..].split(['?', '#']).next().unwrap_or("");
+    let joined = if href.starts_with('/') {
+        href.to_string()
+    } else {
+        format!("{}{href}", &path[..path.rfind('/').map(|at| at + 1).unwrap_or(0)])
+    };
+    format!("{}/{}", &base[..host_end], normalize_path(&joined))
+}
+
+fn has_word(name: &str, words: &[&str]) -> bool {
+    words.iter().any(|word| name.contains(word))
+}
+
+/// A page as a tree of elements and text. Text and attribute values are kept
+/// escaped, as they came from the tidied markup.
+struct Dom {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    parent: usize,
+    /// Element name; empty for text.
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<usize>,
+    removed: bool,
+}
+
+impl Dom {
+    fn parse(xhtml: &str) -> Self {
+        let mut dom = Dom {
+            nodes: vec![Node {
+                parent: 0,
+                name: "#document".to_string(),
+                attributes: Vec::new(),
+                text: String::new(),
+                children: Vec::new(),
+                removed: false,
+            }],
+        };
+        let mut reader = Reader::from_str(xhtml);
+        reader.config_mut().check_end_names = false;
+        let mut stack = vec![0];
+        loop {
+            let parent = *stack.last().unwrap_or(&0);
+            match reader.read_event() {
+                Ok(Event::Start(e)) => stack.push(dom.push_element(parent, &e)),
+                Ok(Event::Empty(e)) => {
+                    dom.push_element(parent, &e);
+                }
+                Ok(Event::End(_)) if stack.len() > 1 => {
+                    stack.pop();
+                }
+                Ok(Event::Text(e)) => dom.push_text(parent, &String::from_utf8_lossy(&e)),
+                Ok(Event::GeneralRef(e)) => dom.push_text(parent, &format!("&{};", String::from_utf8_lossy(&e))),
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+        dom
+    }
+
+    fn push_element(&mut self, parent: usize, e: &BytesStart<'_>) -> usize {
+        let attributes = e
+            .attributes()
+            .with_checks(false)
+            .filter_map(Result::ok)
+            .map(|attr| {
+                (
+                    String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                    String::from_utf8_lossy(&attr.value).into_owned(),
+                )
+            })
+            .collect();
+        self.push(parent, String::from_utf8_lossy(e.name().as_ref()).into_owned(), attributes, String::new())
+    }
+
+    fn push_text(&mut self, parent: usize, text: &str) {
+        self.push(parent, String::new(), Vec::new(), text.to_string());
+    }
+
+    fn push(&mut self, parent: usize, name: String, attributes: Vec<(String, String)>, text: String) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent,
+            name,
+            attributes,
+            text,
+            children: Vec::new(),
+            removed: false,
+        });
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    fn elements<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        (0..self.nodes.len()).filter(move |&id| self.nodes[id].name == name)
+    }
+
+    fn attribute(&self, id: usize, key: &str) -> Option<&str> {
+        self.nodes[id]
+            .attributes
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Lower-cased class and id, for matching furniture and content words.
+    fn class_and_id(&self, id: usize) -> String {
+        let class = self.attribute(id, "class").unwrap_or("");
+        let element_id = self.attribute(id, "id").unwrap_or("");
+        format!("{class} {element_id}").to_ascii_lowercase()
+    }
+
+    fn text(&self, id: usize, out: &mut String) {
+        let node = &self.nodes[id];
+        if node.removed {
+            return;
+        }
+        out.push_str(&node.text);
+        for &child in &node.children {
+            self.text(child, out);
+        }
+    }
+
+    fn text_length(&self, id: usize) -> usize {
+        let mut text = String::new();
+        self.text(id, &mut text);
+        text.trim().chars().count()
+    }
+
+    /// Share of an element's text that sits in links.
+    fn link_density(&self, id: usize) -> f32 {
+        let total = self.text_length(id);
+        if total == 0 {
+            return 0.0;
+        }
+        let mut links = 0;
+        let mut pending = vec![id];
+        while let Some(next) = pending.pop() {
+            if self.nodes[next].removed {
+                continue;
+            }
+            if self.nodes[next].name == "a" {
+                links += self.text_length(next);
+            } else {
+                pending.extend(&self.nodes[next].children);
+            }
+        }
+        links as f32 / total as f32
+    }
+
+    fn class_weight(&self, id: usize) -> f32 {
+        let names = self.class_and_id(id);
+        let mut weight = 0.0;
+        if has_word(&names, NEGATIVE_NAMES) {
+            weight -= 25.0;
+        }
+        if has_word(&names, POSITIVE_NAMES) {
+            weight += 25.0;
+        }
+        weight
+    }
+
+    fn element_weight(&self, id: usize) -> f32 {
+        let base = match self.nodes[id].name.as_str() {
+            "div" | "article" | "main" => 5.0,
+            "pre" | "td" | "blockquote" => 3.0,
+            "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+            _ => 0.0,
+        };
+        base + self.class_weight(id)
+    }
+
+    /// Elements that make up the article, in page order.
+    fn article(&mut self) -> Vec<usize> {
+        for id in 1..self.nodes.len() {
+            let node = &self.nodes[id];
+            if node.name.is_empty() || matches!(node.name.as_str(), "html" | "body" | "article" | "main") {
+                continue;
+            }
+            let names = self.class_and_id(id);
+            let unlikely = UNLIKELY_ELEMENTS.contains(&node.name.as_str())
+                || (has_word(&names, UNLIKELY_NAMES) && !has_word(&names, MAYBE_ARTICLE));
+            if unlikely {
+                self.nodes[id].removed = true;
+            }
+        }
+
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for id in 1..self.nodes.len() {
+            if !SCORED_ELEMENTS.contains(&self.nodes[id].name.as_str()) || self.is_removed(id) {
+                continue;
+            }
+            let mut text = String::new();
+            self.text(id, &mut text);
+            let length = text.trim().chars().count();
+            if length < MIN_PARAGRAPH {
+                continue;
+            }
+            let score = 1.0 + text.matches(',').count() as f32 + (length / 100).min(3) as f32;
+            let mut ancestor = self.nodes[id].parent;
+            for level in 0..3 {
+                if ancestor == 0 {
+                    break;
+                }
+                let divider = match level {
+                    0 => 1.0,
+                    1 => 2.0,
+                    _ => level as f32 * 3.0,
+                };
+                let initial = self.element_weight(ancestor);
+                *scores.entry(ancestor).or_insert(initial) += score / divider;
+                ancestor = self.nodes[ancestor].parent;
+            }
+        }
+        let scored = scores
+            .iter()
+            .map(|(&id, &score)| (id, score * (1.0 - self.link_density(id))))
+            .collect::<HashMap<_, _>>();
+        let Some((top, top_score)) = scored
+            .iter()
+            .map(|(&id, &score)| (id, score))
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+        else {
+            return self.elements("body").next().into_iter().collect();
+        };
+
+        // Siblings that score nearly as well, or read like article paragraphs,
+        // are split-off parts of the same article.
+        let threshold = (top_score * 0.2).max(10.0);
+        let parent = self.nodes[top].parent;
+        if parent == 0 {
+            return vec![top];
+        }
+        self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .filter(|&sibling| {
+                if sibling == top {
+                    return true;
+                }
+                let node = &self.nodes[sibling];
+                if node.removed || node.name.is_empty() {
+                    return false;
+                }
+                if scored.get(&sibling).is_some_and(|&score| score + self.class_weight(sibling) >= threshold) {
+                    return true;
+                }
+                node.name == "p" && self.text_length(sibling) > 80 && self.link_density(sibling) < 0.25
+            })
+            .collect()
+    }
+
+    fn is_removed(&self, mut id: usize) -> bool {
+        while id != 0 {
+            if self.nodes[id].removed {
+                return true;
+            }
+            id = self.nodes[id].parent;
+        }
+        false
+    }
+
+    /// Writes an element back out as XHTML. Images get their resolved
+    /// address as `src`, recorded in `images` by that name.
+    fn write(&self, id: usize, base: &str, images: &mut HashMap<String, String>, out: &mut String) {
+        let node = &self.nodes[id];
+        if node.removed {
+            return;
+        }
+        if node.name.is_empty() {
+            out.push_str(&node.text);
+            return;
+        }
+        if node.name == "img" {
+            let src = IMAGE_ATTRIBUTES
+                .iter()
+                .filter_map(|key| self.attribute(id, key))
+                .map(unescape)
+                .find(|src| !src.trim().is_empty() && !src.starts_with("data:"));
+            if let Some(src) = src {
+                let location = resolve(base, src.trim());
+                let name = format!("images/{}", normalize_path(&location.replace("://", "/")));
+                out.push_str(&format!("<img src=\"{}\"", quick_xml::escape::escape(&name)));
+                if let Some(alt) = self.attribute(id, "alt") {
+                    out.push_str(&format!(" alt=\"{alt}\""));
+                }
+                out.push_str("/>");
+                images.insert(name, location);
+            }
+            return;
+        }
+        out.push('<');
+        out.push_str(&node.name);
+        for (key, value) in &node.attributes {
+            out.push_str(&format!(" {key}=\"{value}\""));
+        }
+        out.push('>');
+        for &child in &node.children {
+            self.write(child, base, images, out);
+        }
+        out.push_str(&format!("</{}>", node.name));
+    }
+}
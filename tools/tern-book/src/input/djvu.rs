@@ -0,0 +1,181 @@
+//! DjVu backend for scanned books. Pages are counted from the IFF structure
+//! and rendered by `ddjvu` from DjVuLibre, which has to be on the `PATH`;
+//! each page becomes a scanned fixed-layout item (see [`crate::scan`]).
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::{BookInput, InputFormat, SpineItem};
+use crate::blocks::HtmlBlock;
+use crate::scan::{prepare_page, ScanOptions};
+use crate::{BookError, TrbkMetadata};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "djvu",
+    extensions: &["djvu", "djv"],
+    sniff: |head| head.starts_with(b"AT&TFORM"),
+    open: |path| Ok(Box::new(DjvuInput::open(path)?)),
+};
+
+const DDJVU: &str = "ddjvu";
+/// Resolution pages are rendered at, in dots per inch. Well above the panel's
+/// so margins can be trimmed before scaling down.
+const RENDER_DPI: u32 = 150;
+
+#[derive(Clone, Debug)]
+pub struct DjvuInput {
+    pub path: PathBuf,
+    pub scan: ScanOptions,
+    spine: Vec<SpineItem>,
+}
+
+impl DjvuInput {
+    /// Counts the pages and checks that `ddjvu` can be run. Indirect
+    /// documents, split over several files, are not supported.
+    pub fn open(path: &Path) -> Result<Self, BookError> {
+        let bytes = std::fs::read(path)?;
+        let page_count = page_count(&bytes)?;
+        if page_count == 0 {
+            return Err(BookError::Djvu("the document has no pages".to_string()));
+        }
+        match Command::new(DDJVU)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+        {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(BookError::Djvu(format!(
+                    "`{DDJVU}` was not found; install DjVuLibre to convert DjVu files"
+                )));
+            }
+            Err(err) => return Err(err.into()),
+            Ok(_) => {}
+        }
+        let spine = (1..=page_count)
+            .map(|page| SpineItem {
+                name: format!("page-{page:04}"),
+            })
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            scan: ScanOptions::default(),
+            spine,
+        })
+    }
+
+    fn title(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "<unknown>".to_string())
+    }
+
+    fn render_page(&self, index: usize) -> Result<image::GrayImage, String> {
+        let output = Command::new(DDJVU)
+            .arg("-format=pgm")
+            .arg(format!("-page={}", index + 1))
+            .arg(format!("-scale={RENDER_DPI}"))
+            .arg(&self.path)
+            .output()
+            .map_err(|err| err.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        image::load_from_memory(&output.stdout)
+            .map(|image| image.to_luma8())
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl BookInput for DjvuInput {
+    fn metadata(&self) -> TrbkMetadata {
+        TrbkMetadata {
+            title: self.title(),
+            author: "<unknown>".to_string(),
+            language: "<unknown>".to_string(),
+            identifier: self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            part: None,
+            series: None,
+        }
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        if index >= self.spine.len() {
+            return Err(BookError::SpineIndex(index));
+        }
+        Ok(Vec::new())
+    }
+
+    fn spine_title(&self, index: usize) -> Option<String> {
+        Some(format!("Page {}", index + 1))
+    }
+
+    fn is_fixed_layout(&self, _index: usize) -> bool {
+        true
+    }
+
+    fn page_images(&self, index: usize) -> Vec<image::GrayImage> {
+        match self.render_page(index) {
+            Ok(page) => prepare_page(page, self.scan),
+            Err(err) => {
+                eprintln!(
+                    "[tern-book] warning: failed to render DjVu page {}: {err}",
+                    index + 1
+                );
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Pages in a single-page `FORM:DJVU` file or a bundled `FORM:DJVM`
+/// document, where every page is a `FORM:DJVU` child.
+fn page_count(bytes: &[u8]) -> Result<usize, BookError> {
+    let invalid = || BookError::Djvu("not a DjVu file".to_string());
+    let body = bytes.strip_prefix(b"AT&T").ok_or_else(invalid)?;
+    let (id, form, _) = chunk(body).ok_or_else(invalid)?;
+    if id != b"FORM" || form.len() < 4 {
+        return Err(invalid());
+    }
+    match &form[..4] {
+        b"DJVU" => Ok(1),
+        b"DJVM" => {
+            let mut rest = &form[4..];
+            let mut pages = 0;
+            while let Some((id, data, next)) = chunk(rest) {
+                // The top bit of the directory's first byte marks a bundle.
+                if id == b"DIRM" && data.first().is_some_and(|flags| flags & 0x80 == 0) {
+                    return Err(BookError::Djvu(
+                        "indirect DjVu documents are not supported; bundle the pages with djvmcvt first"
+                            .to_string(),
+                    ));
+                }
+                if id == b"FORM" && data.starts_with(b"DJVU") {
+                    pages += 1;
+                }
+                rest = next;
+            }
+            Ok(pages)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Splits the IFF chunk at the start of `bytes` into its id, its data and the
+/// bytes after it, which start on an even offset.
+fn chunk(bytes: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let id = bytes.get(..4)?;
+    let len = u32::from_be_bytes(bytes.get(4..8)?.try_into().ok()?) as usize;
+    let data = bytes.get(8..8 + len)?;
+    let next = bytes.get(8 + len + (len & 1)..).unwrap_or(&[]);
+    Some((id, data, next))
+}
@@ -0,0 +1,200 @@
+//! EPUB backend, read through the `tern-epub` cache.
+
+use std::path::{Path, PathBuf};
+
+use super::{BookInput, InputFormat, NavEntry, SpineItem};
+use crate::blocks::{HtmlBlock, collapse_double_prefix, normalize_path, percent_decode, strip_fragment};
+use crate::toc::{normalize_title, title_from_blocks};
+use crate::{BookError, TrbkMetadata, WritingMode};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "epub",
+    extensions: &["epub"],
+    sniff: is_epub,
+    open: |path| Ok(Box::new(EpubInput::open(path)?)),
+};
+
+#[derive(Clone, Debug)]
+pub struct EpubInput {
+    pub path: PathBuf,
+    pub cache: tern_epub::BookCache,
+    pub writing_mode: WritingMode,
+    /// Pre-paginated flag of each spine item.
+    pub fixed_layout: Vec<bool>,
+    spine: Vec<SpineItem>,
+}
+
+impl EpubInput {
+    /// Reads the package through the on-disk cache next to the EPUB, building
+    /// the cache if needed. Spine documents are parsed on demand. Encrypted
+    /// books fail with [`BookError::Drm`] before anything else is read.
+    pub fn open(epub_path: &Path) -> Result<Self, BookError> {
+        if let Some(scheme) = tern_epub::detect_drm(epub_path)? {
+            return Err(BookError::Drm(scheme));
+        }
+        let cache_dir = tern_epub::default_cache_dir(epub_path);
+        let (cache, _) = tern_epub::load_or_build_cache(epub_path, &cache_dir)?;
+        let spine = cache
+            .spine
+            .iter()
+            .map(|entry| SpineItem {
+                name: entry.href.clone(),
+            })
+            .collect();
+        let writing_mode = match tern_epub::declares_vertical_writing(epub_path) {
+            Ok(true) => WritingMode::VerticalRl,
+            _ => WritingMode::Horizontal,
+        };
+        let fixed_layout = tern_epub::fixed_layout_spine(epub_path).unwrap_or_default();
+        Ok(Self {
+            path: epub_path.to_path_buf(),
+            cache,
+            writing_mode,
+            fixed_layout,
+            spine,
+        })
+    }
+
+    /// Archive path of spine item `index`.
+    fn spine_path(&self, index: usize) -> String {
+        let opf_dir = tern_epub::opf_base_dir(&self.cache.opf_path);
+        let spine_href = self
+            .cache
+            .spine
+            .get(index)
+            .map(|entry| entry.href.clone())
+            .unwrap_or_default();
+        let mut spine_path = strip_fragment(&spine_href);
+        if spine_path.starts_with('/') {
+            spine_path = spine_path.trim_start_matches('/').to_string();
+        }
+        let spine_path = if !opf_dir.is_empty() && spine_path.starts_with(&opf_dir) {
+            spine_path
+        } else {
+            tern_epub::resolve_href(&opf_dir, &spine_path)
+        };
+        collapse_double_prefix(&normalize_path(&spine_path), &opf_dir)
+    }
+}
+
+impl BookInput for EpubInput {
+    fn metadata(&self) -> TrbkMetadata {
+        let field = |value: &Option<String>| value.as_deref().unwrap_or("<unknown>").to_string();
+        TrbkMetadata {
+            title: field(&self.cache.metadata.title),
+            author: field(&self.cache.metadata.creator),
+            language: field(&self.cache.metadata.language),
+            identifier: field(&self.cache.metadata.identifier),
+            part: None,
+            series: None,
+        }
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    /// Image sources are resolved against the spine document to archive paths.
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        let cache = &self.cache;
+        let xhtml = tern_epub::read_spine_xhtml(&self.path, index)?;
+        let mut blocks = tern_epub::parse_xhtml_blocks(&xhtml)?;
+        let opf_dir = tern_epub::opf_base_dir(&cache.opf_path);
+        let spine_dir = tern_epub::opf_base_dir(&self.spine_path(index));
+        for block in &mut blocks {
+            if let HtmlBlock::Image { src, .. } = block {
+                let mut cleaned = strip_fragment(src);
+                if cleaned.starts_with('/') {
+                    cleaned = cleaned.trim_start_matches('/').to_string();
+                }
+                let resolved = if !opf_dir.is_empty() && cleaned.starts_with(&opf_dir) {
+                    cleaned
+                } else {
+                    tern_epub::resolve_href(&spine_dir, &cleaned)
+                };
+                let resolved = collapse_double_prefix(&normalize_path(&resolved), &opf_dir);
+                *src = resolved;
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Falls back to the landmarks nav when the book has no usable TOC.
+    fn toc(&self) -> Vec<NavEntry> {
+        let entries = if self.cache.toc.iter().any(|entry| entry.spine_index >= 0) {
+            &self.cache.toc
+        } else {
+            &self.cache.landmarks
+        };
+        entries
+            .iter()
+            .filter(|entry| entry.spine_index >= 0)
+            .map(|entry| NavEntry {
+                title: entry.title.clone(),
+                spine_index: entry.spine_index as usize,
+                // Fragments are URL-encoded; element ids are not.
+                anchor: (!entry.anchor.is_empty()).then(|| percent_decode(&entry.anchor)),
+                level: entry.level,
+            })
+            .collect()
+    }
+
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        let mut candidates = vec![normalize_path(&strip_fragment(name))];
+        let decoded = percent_decode(name);
+        if decoded != name {
+            candidates.push(normalize_path(&strip_fragment(&decoded)));
+        }
+        candidates
+            .iter()
+            .filter(|candidate| !candidate.is_empty())
+            .find_map(|candidate| tern_epub::read_epub_resource_bytes(&self.path, candidate).ok())
+    }
+
+    fn spine_title(&self, index: usize) -> Option<String> {
+        let xhtml = tern_epub::read_spine_xhtml(&self.path, index).ok()?;
+        if let Ok(blocks) = tern_epub::parse_xhtml_blocks(&xhtml) {
+            if let Some(title) = title_from_blocks(&blocks) {
+                return Some(title);
+            }
+        }
+        title_from_title_tag(&xhtml)
+    }
+
+    fn writing_mode(&self) -> WritingMode {
+        self.writing_mode
+    }
+
+    fn is_fixed_layout(&self, index: usize) -> bool {
+        self.fixed_layout.get(index).copied().unwrap_or(false)
+    }
+
+    fn document(&self, index: usize) -> Option<(String, String)> {
+        let xhtml = tern_epub::read_spine_xhtml(&self.path, index).ok()?;
+        Some((xhtml, self.spine_path(index)))
+    }
+}
+
+/// An OCF container starts with an uncompressed `mimetype` entry.
+fn is_epub(head: &[u8]) -> bool {
+    head.starts_with(b"PK\x03\x04")
+        && head.get(30..38) == Some(b"mimetype".as_slice())
+        && head
+            .get(38..)
+            .is_some_and(|rest| rest.starts_with(b"application/epub+zip"))
+}
+
+fn title_from_title_tag(xhtml: &str) -> Option<String> {
+    let lower = xhtml.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let gt = lower[start..].find('>')?;
+    let title_start = start + gt + 1;
+    let end = lower[title_start..].find("</title>")?;
+    let raw = &xhtml[title_start..title_start + end];
+    let cleaned = normalize_title(raw);
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
@@ -0,0 +1,244 @@
+//! Tidying of HTML that is not well-formed XHTML, so it can go through
+//! [`tern_epub::parse_xhtml_blocks`] like EPUB content.
+
+/// HTML elements that never have content.
+const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "hr", "img", "input", "link", "meta", "wbr"];
+
+/// Named entities beyond the ones XML predefines that books and web pages use.
+const ENTITIES: &[(&str, u32)] = &[
+    ("nbsp", 0xA0),
+    ("shy", 0xAD),
+    ("ndash", 0x2013),
+    ("mdash", 0x2014),
+    ("lsquo", 0x2018),
+    ("rsquo", 0x2019),
+    ("ldquo", 0x201C),
+    ("rdquo", 0x201D),
+    ("hellip", 0x2026),
+    ("copy", 0xA9),
+];
+
+/// Elements whose content is not markup and is dropped along with them.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Rewrites HTML as XHTML: names lower-cased, attribute values quoted, void
+/// elements closed, stray end tags dropped, open elements closed at the end,
+/// and scripts, styles, comments and Kindle `mbp:` tags removed.
+/// `attribute` may replace an attribute, given the element name, key and value.
+pub(crate) fn tidy(html: &str, attribute: impl Fn(&str, &str, &str) -> Option<(String, String)>) -> String {
+    let mut out = String::with_capacity(html.len() + html.len() / 8);
+    let mut open: Vec<String> = Vec::new();
+    let mut rest = html;
+    while let Some(lt) = rest.find('<') {
+        push_text(&mut out, &rest[..lt]);
+        rest = &rest[lt..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map(|end| &comment[end + 3..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+            continue;
+        }
+        let Some(tag) = parse_tag(rest) else {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.length..];
+        if tag.name.starts_with("mbp:") {
+            continue;
+        }
+        if !tag.end && !tag.self_closing && RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) {
+            let close = format!("</{}", tag.name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(end) => &rest[end..],
+                None => "",
+            };
+            continue;
+        }
+        if tag.end {
+            if let Some(depth) = open.iter().rposition(|name| *name == tag.name) {
+                for name in open.drain(depth..).rev() {
+                    out.push_str(&format!("</{name}>"));
+                }
+            }
+            continue;
+        }
+        out.push('<');
+        out.push_str(&tag.name);
+        for (key, value) in &tag.attributes {
+            let (key, value) = attribute(&tag.name, key, value).unwrap_or_else(|| (key.clone(), value.clone()));
+            out.push(' ');
+            out.push_str(&key);
+            out.push_str("=\"");
+            push_text(&mut out, &value.replace('"', "&quot;"));
+            out.push('"');
+        }
+        if tag.self_closing || VOID_ELEMENTS.contains(&tag.name.as_str()) {
+            out.push_str("/>");
+        } else {
+            out.push('>');
+            open.push(tag.name);
+        }
+    }
+    push_text(&mut out, rest);
+    for name in open.iter().rev() {
+        out.push_str(&format!("</{name}>"));
+    }
+    out
+}
+
+struct Tag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    end: bool,
+    self_closing: bool,
+    length: usize,
+}
+
+/// Parses the tag `text` starts with, allowing unquoted and bare attributes.
+fn parse_tag(text: &str) -> Option<Tag> {
+    let bytes = text.as_bytes();
+    let skip_space = |mut at: usize| {
+        while bytes.get(at).is_some_and(u8::is_ascii_whitespace) {
+            at += 1;
+        }
+        at
+    };
+    let end = bytes.get(1) == Some(&b'/');
+    let mut at = if end { 2 } else { 1 };
+    let name_start = at;
+    while bytes
+        .get(at)
+        .is_some_and(|&byte| byte.is_ascii_alphanumeric() || matches!(byte, b':' | b'-' | b'_'))
+    {
+        at += 1;
+    }
+    if at == name_start {
+        return None;
+    }
+    let name = text[name_start..at].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        at = skip_space(at);
+        match *bytes.get(at)? {
+            b'>' => break,
+            b'/' => {
+                self_closing = true;
+                at += 1;
+                continue;
+            }
+            _ => self_closing = false,
+        }
+        let key_start = at;
+        while bytes
+            .get(at)
+            .is_some_and(|&byte| !byte.is_ascii_whitespace() && !matches!(byte, b'=' | b'>' | b'/'))
+        {
+            at += 1;
+        }
+        let key = text[key_start..at].to_ascii_lowercase();
+        if key.is_empty() {
+            at += 1;
+            continue;
+        }
+        at = skip_space(at);
+        let mut value = String::new();
+        if bytes.get(at) == Some(&b'=') {
+            at = skip_space(at + 1);
+            match bytes.get(at) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let close = at + 1 + text[at + 1..].find(quote as char)?;
+                    value = text[at + 1..close].to_string();
+                    at = close + 1;
+                }
+                _ => {
+                    let start = at;
+                    while bytes
+                        .get(at)
+                        .is_some_and(|&byte| !byte.is_ascii_whitespace() && byte != b'>')
+                    {
+                        at += 1;
+                    }
+                    value = text[start..at].to_string();
+                }
+            }
+        }
+        let valid_key = key
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b':' | b'-' | b'_' | b'.'));
+        if valid_key && !attributes.iter().any(|(existing, _)| *existing == key) {
+            attributes.push((key, value));
+        }
+    }
+    Some(Tag {
+        name,
+        attributes,
+        end,
+        self_closing,
+        length: at + 1,
+    })
+}
+
+/// Appends text, escaping `<` and any `&` that does not start an entity XML
+/// knows. Common HTML entities become character references.
+fn push_text(out: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(at) = rest.find(['&', '<']) {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        if rest.starts_with('<') {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        let entity = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..1 + end])
+            .filter(|name| {
+                !name.is_empty() && name.len() <= 10 && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'#')
+            });
+        match entity {
+            Some(name) if name.starts_with('#') || matches!(name, "amp" | "lt" | "gt" | "quot" | "apos") => {
+                out.push('&');
+                out.push_str(name);
+                out.push(';');
+                rest = &rest[name.len() + 2..];
+            }
+            Some(name) => {
+                if let Some((_, code)) = ENTITIES.iter().find(|(entity, _)| *entity == name) {
+                    out.push_str(&format!("&#{code};"));
+                }
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                out.push_str("&amp;");
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+pub(crate) fn decode_text(bytes: &[u8], utf8: bool) -> String {
+    if utf8 {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x80..=0x9F => CP1252[(byte - 0x80) as usize],
+            _ => byte as char,
+        })
+        .collect()
+}
+
+/// Windows-1252 characters for 0x80 to 0x9F; the rest match Latin-1.
+const CP1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
@@ -0,0 +1,1133 @@
+//! Kindle MOBI and AZW3 backend for DRM-free books.
+//!
+//! Both are Palm databases: record 0 holds the headers, then come the
+//! compressed text records and the images. Old-style MOBI keeps the book as one
+//! HTML stream with `<mbp:pagebreak/>` between chapters, which becomes one
+//! spine item per section. KF8 (AZW3, and the second half of combined MOBI
+//! files) stores skeleton files and the fragments inserted into them, which
+//! are put back together into one XHTML file per spine item. Either way the
+//! markup is tidied into XHTML and parsed like EPUB content.
+//!
+//! Images are the records from the first resource on, named `images/NNNNN` by
+//! their 1-based number, as `recindex` and `kindle:embed` count them. The TOC
+//! comes from the NCX index; its positions are marked with empty anchors
+//! before the markup is split up.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::html::{decode_text, tidy};
+use super::{BookInput, InputFormat, NavEntry, SpineItem};
+use crate::blocks::HtmlBlock;
+use crate::{BookError, TrbkMetadata};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "mobi",
+    extensions: &["mobi", "azw", "azw3", "prc"],
+    sniff: |head| head.get(60..68) == Some(b"BOOKMOBI".as_slice()),
+    open: |path| Ok(Box::new(MobiInput::open(path)?)),
+};
+
+const NO_INDEX: u32 = 0xFFFF_FFFF;
+/// Record that separates the old-style half of a combined file from the KF8 half.
+const BOUNDARY: &[u8] = b"BOUNDARY";
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_PALMDOC: u16 = 2;
+const COMPRESSION_HUFFCDIC: u16 = 17480;
+const ENCODING_UTF8: u32 = 65001;
+
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_ISBN: u32 = 104;
+const EXTH_ASIN: u32 = 113;
+const EXTH_COVER: u32 = 201;
+const EXTH_TITLE: u32 = 503;
+const EXTH_LANGUAGE: u32 = 524;
+
+/// NCX index tags: text position, label offset into the CNCX records, depth,
+/// and the KF8 fragment and offset within it.
+const TAG_POSITION: u8 = 1;
+const TAG_LABEL: u8 = 3;
+const TAG_LEVEL: u8 = 4;
+const TAG_POS_FID: u8 = 6;
+/// Skeleton and fragment index tags.
+const TAG_FRAGMENT_COUNT: u8 = 1;
+const TAG_SPAN: u8 = 6;
+
+#[derive(Clone, Debug)]
+pub struct MobiInput {
+    pub path: PathBuf,
+    /// Whether the book was read from KF8 records rather than old-style MOBI.
+    pub kf8: bool,
+    metadata: TrbkMetadata,
+    data: Vec<u8>,
+    records: Vec<Range<usize>>,
+    first_image: usize,
+    cover: Option<usize>,
+    documents: Vec<String>,
+    toc: Vec<NavEntry>,
+    spine: Vec<SpineItem>,
+}
+
+impl MobiInput {
+    /// Reads and decompresses the whole book. Encrypted books fail with
+    /// [`BookError::Drm`].
+    pub fn open(path: &Path) -> Result<Self, BookError> {
+        Self::parse(path, std::fs::read(path)?)
+    }
+
+    fn parse(path: &Path, data: Vec<u8>) -> Result<Self, BookError> {
+        if data.get(60..68) != Some(b"BOOKMOBI".as_slice()) {
+            return Err(BookError::Mobi("not a MOBI book".to_string()));
+        }
+        let records = read_records(&data)?;
+        let record = |index: usize| records.get(index).map(|range| &data[range.clone()]);
+        let first = Header::parse(record(0).ok_or_else(|| malformed("record 0"))?, 0)?;
+        if first.encryption != 0 {
+            return Err(BookError::Drm(tern_epub::DrmScheme::Kindle));
+        }
+        let boundary = (0..records.len()).find(|&index| record(index) == Some(BOUNDARY));
+        let header = match boundary.and_then(|index| Some((index + 1, record(index + 1)?))) {
+            Some((start, bytes)) => Header::parse(bytes, start)?,
+            None => first.clone(),
+        };
+        let kf8 = header.version >= 8;
+
+        let text = read_text(&header, &record)?;
+        let ncx = match header.index(header.ncx) {
+            Some(index) => Index::read(&record, index)?,
+            None => Index::default(),
+        };
+        let mut targets = Vec::new();
+        let parts = if kf8 {
+            kf8_parts(&header, &record, text, &ncx, &mut targets)?
+        } else {
+            for entry in &ncx.entries {
+                targets.push(entry.value(TAG_POSITION).unwrap_or(0) as usize);
+            }
+            vec![Part { start: 0, bytes: text }]
+        };
+        let raw_documents = mark_targets(parts, &targets, kf8);
+
+        let decode = |bytes: &[u8]| decode_text(bytes, header.utf8);
+        let mut documents = Vec::new();
+        let mut spine = Vec::new();
+        for (index, raw) in raw_documents.into_iter().enumerate() {
+            spine.push(SpineItem {
+                name: if kf8 {
+                    format!("part{index:04}.xhtml")
+                } else {
+                    format!("part{index:04}.html")
+                },
+            });
+            documents.push(tidy(&decode(&raw), kindle_image));
+        }
+        let toc = ncx
+            .entries
+            .iter()
+            .zip(&targets)
+            .filter_map(|(entry, target)| {
+                let marker = marker_id(*target);
+                let spine_index = documents
+                    .iter()
+                    .position(|document| document.contains(&format!("id=\"{marker}\"")))?;
+                let title = decode(&ncx.label(entry.value(TAG_LABEL)? as usize)?);
+                Some(NavEntry {
+                    title: title.trim().to_string(),
+                    spine_index,
+                    anchor: Some(marker),
+                    level: entry.value(TAG_LEVEL).unwrap_or(0).min(u8::MAX as u32) as u8,
+                })
+            })
+            .collect();
+
+        let exth_text = |kind: u32| {
+            header
+                .exth
+                .iter()
+                .filter(|(key, _)| *key == kind)
+                .map(|(_, value)| decode(value).trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let title = exth_text(EXTH_TITLE)
+            .into_iter()
+            .next()
+            .or_else(|| Some(header.title.clone()).filter(|title| !title.is_empty()))
+            .unwrap_or_else(|| decode(data[..32].split(|&byte| byte == 0).next().unwrap_or(&[])));
+        let authors = exth_text(EXTH_AUTHOR);
+        let identifier = exth_text(EXTH_ISBN)
+            .into_iter()
+            .chain(exth_text(EXTH_ASIN))
+            .next()
+            .unwrap_or_else(|| header.uid.to_string());
+        let metadata = TrbkMetadata {
+            title,
+            author: if authors.is_empty() {
+                "<unknown>".to_string()
+            } else {
+                authors.join(", ")
+            },
+            language: exth_text(EXTH_LANGUAGE)
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| "<unknown>".to_string()),
+            identifier,
+            part: None,
+            series: None,
+        };
+        // Image numbers count from the first resource of the old-style half,
+        // which combined files share with the KF8 half.
+        let first_image = if first.first_image != NO_INDEX {
+            first.first_image as usize
+        } else {
+            header.start + header.first_image as usize
+        };
+        let cover = header
+            .exth
+            .iter()
+            .find(|(key, _)| *key == EXTH_COVER)
+            .and_then(|(_, value)| be32(value, 0))
+            .filter(|&offset| offset != NO_INDEX)
+            .map(|offset| offset as usize + 1);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            kf8,
+            metadata,
+            data,
+            records,
+            first_image,
+            cover,
+            documents,
+            toc,
+            spine,
+        })
+    }
+
+    fn image(&self, number: usize) -> Option<Vec<u8>> {
+        let range = self.records.get(self.first_image.checked_add(number)?.checked_sub(1)?)?;
+        let bytes = &self.data[range.clone()];
+        image::guess_format(bytes).ok()?;
+        Some(bytes.to_vec())
+    }
+}
+
+impl BookInput for MobiInput {
+    fn metadata(&self) -> TrbkMetadata {
+        self.metadata.clone()
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        let document = self.documents.get(index).ok_or(BookError::SpineIndex(index))?;
+        Ok(tern_epub::parse_xhtml_blocks(document)?)
+    }
+
+    fn toc(&self) -> Vec<NavEntry> {
+        self.toc.clone()
+    }
+
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        self.image(name.strip_prefix("images/")?.parse().ok()?)
+    }
+
+    /// Old-style books leave the cover out of the text; KF8 books normally
+    /// have a cover page of their own.
+    fn cover(&self) -> Option<Vec<u8>> {
+        if self.kf8 {
+            return None;
+        }
+        self.image(self.cover?)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Header {
+    /// Record number of this header; KF8 record numbers count from here.
+    start: usize,
+    compression: u16,
+    text_length: usize,
+    text_records: usize,
+    encryption: u16,
+    utf8: bool,
+    uid: u32,
+    version: u32,
+    title: String,
+    first_image: u32,
+    huff: (u32, u32),
+    extra_flags: u16,
+    fdst: u32,
+    ncx: u32,
+    fragments: u32,
+    skeletons: u32,
+    exth: Vec<(u32, Vec<u8>)>,
+}
+
+impl Header {
+    fn parse(record: &[u8], start: usize) -> Result<Self, BookError> {
+        let u16_at = |at| be16(record, at).ok_or_else(|| malformed("header"));
+        let u32_at = |at| be32(record, at).ok_or_else(|| malformed("header"));
+        // Fields past the end of a short header are absent.
+        let optional = |at| be32(record, at).unwrap_or(NO_INDEX);
+        if record.get(16..20) != Some(b"MOBI".as_slice()) {
+            return Err(malformed("MOBI header"));
+        }
+        let length = u32_at(20)? as usize;
+        let version = u32_at(36)?;
+        let utf8 = u32_at(28)? == ENCODING_UTF8;
+        let name_start = u32_at(84)? as usize;
+        let name_end = name_start.saturating_add(u32_at(88)? as usize);
+        let title = record
+            .get(name_start..name_end)
+            .map(|name| decode_text(name, utf8))
+            .unwrap_or_default();
+        let extra_flags = if length >= 0xE4 && version >= 5 {
+            be16(record, 0xF2).unwrap_or(0)
+        } else {
+            0
+        };
+        let exth = if u32_at(128)? & 0x40 != 0 {
+            parse_exth(record.get(length.saturating_add(16)..).unwrap_or(&[]))
+        } else {
+            Vec::new()
+        };
+        let field = |at: usize| if length.saturating_add(16) > at { optional(at) } else { NO_INDEX };
+        Ok(Self {
+            start,
+            compression: u16_at(0)?,
+            text_length: u32_at(4)? as usize,
+            text_records: u16_at(8)? as usize,
+            encryption: u16_at(12)?,
+            utf8,
+            uid: u32_at(32)?,
+            version,
+            title,
+            first_image: u32_at(108)?,
+            huff: (optional(112), optional(116)),
+            extra_flags,
+            fdst: if version >= 8 { field(0xC0) } else { NO_INDEX },
+            ncx: field(0xF4),
+            fragments: if version >= 8 { field(0xF8) } else { NO_INDEX },
+            skeletons: if version >= 8 { field(0xFC) } else { NO_INDEX },
+            exth,
+        })
+    }
+
+    /// Absolute record number of an index named in this header.
+    fn index(&self, value: u32) -> Option<usize> {
+        (value != NO_INDEX).then(|| self.start + value as usize)
+    }
+}
+
+fn parse_exth(bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut entries = Vec::new();
+    if bytes.get(..4) != Some(b"EXTH".as_slice()) {
+        return entries;
+    }
+    let count = be32(bytes, 8).unwrap_or(0);
+    let mut at = 12;
+    for _ in 0..count {
+        let (Some(kind), Some(length)) = (be32(bytes, at), be32(bytes, at.saturating_add(4))) else {
+            break;
+        };
+        let Some(value) = bytes.get(at + 8..at.saturating_add(length as usize)) else {
+            break;
+        };
+        entries.push((kind, value.to_vec()));
+        at = at.saturating_add((length as usize).max(8));
+    }
+    entries
+}
+
+fn read_records(data: &[u8]) -> Result<Vec<Range<usize>>, BookError> {
+    let count = be16(data, 76).ok_or_else(|| malformed("record list"))? as usize;
+    let starts = (0..count)
+        .map(|index| be32(data, 78 + index * 8).map(|start| start as usize))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| malformed("record list"))?;
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(data.len());
+            if start <= end && end <= data.len() {
+                Ok(start..end)
+            } else {
+                Err(malformed("record list"))
+            }
+        })
+        .collect()
+}
+
+/// Decompressed text records, without their trailing entries.
+fn read_text<'a>(header: &Header, record: &impl Fn(usize) -> Option<&'a [u8]>) -> Result<Vec<u8>, BookError> {
+    let mut huff = match header.compression {
+        COMPRESSION_HUFFCDIC => {
+            let (first, count) = header.huff;
+            let tables = (0..count as usize)
+                .map(|offset| record(header.start + first as usize + offset))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| malformed("HUFF/CDIC records"))?;
+            Some(HuffCdic::new(&tables)?)
+        }
+        COMPRESSION_NONE | COMPRESSION_PALMDOC => None,
+        other => return Err(BookError::Mobi(format!("unknown compression {other}"))),
+    };
+    // Text records hold up to 4096 bytes each, whatever the header claims.
+    let mut text = Vec::with_capacity(header.text_length.min(header.text_records * 4096));
+    for index in 1..=header.text_records {
+        let bytes = record(header.start + index).ok_or_else(|| malformed("text record"))?;
+        let bytes = &bytes[..bytes.len() - trailing_size(bytes, header.extra_flags)];
+        match (&mut huff, header.compression) {
+            (Some(huff), _) => text.extend(huff.unpack(bytes, 0)?),
+            (None, COMPRESSION_PALMDOC) => text.extend(palmdoc_decompress(bytes)),
+            _ => text.extend_from_slice(bytes),
+        }
+    }
+    text.truncate(header.text_length);
+    Ok(text)
+}
+
+/// Size of the entries appended to a text record, as announced by the
+/// header's extra data flags.
+fn trailing_size(record: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    for bit in 1..16 {
+        if flags & (1 << bit) != 0 {
+            size += backward_varint(&record[..record.len().saturating_sub(size)]);
+        }
+    }
+    let multibyte = record.len().checked_sub(size + 1).and_then(|at| record.get(at));
+    if let Some(&byte) = multibyte.filter(|_| flags & 1 != 0) {
+        size += (byte & 3) as usize + 1;
+    }
+    size.min(record.len())
+}
+
+fn backward_varint(bytes: &[u8]) -> usize {
+    let mut value = 0;
+    for &byte in &bytes[bytes.len().saturating_sub(4)..] {
+        if byte & 0x80 != 0 {
+            value = 0;
+        }
+        value = (value << 7) | (byte & 0x7F) as usize;
+    }
+    value
+}
+
+fn forward_varint(bytes: &[u8], at: usize) -> (u32, usize) {
+    let mut value = 0u32;
+    let mut used = 0;
+    while let Some(&byte) = bytes.get(at + used) {
+        used += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 != 0 {
+            break;
+        }
+    }
+    (value, used)
+}
+
+/// PalmDoc LZ77: literals, runs copied from earlier output, and space pairs.
+fn palmdoc_decompress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() * 2);
+    let mut at = 0;
+    while at < input.len() {
+        let byte = input[at];
+        at += 1;
+        match byte {
+            1..=8 => {
+                let end = (at + byte as usize).min(input.len());
+                out.extend_from_slice(&input[at..end]);
+                at = end;
+            }
+            0x80..=0xBF => {
+                let Some(&next) = input.get(at) else {
+                    break;
+                };
+                at += 1;
+                let pair = u16::from_be_bytes([byte, next]);
+                let distance = ((pair >> 3) & 0x7FF) as usize;
+                let length = (pair & 7) as usize + 3;
+                if distance == 0 || distance > out.len() {
+                    continue;
+                }
+                for _ in 0..length {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+            0xC0..=0xFF => {
+                out.push(b' ');
+                out.push(byte ^ 0x80);
+            }
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Huffman coding over a phrase dictionary, used by books from the Kindle store.
+struct HuffCdic {
+    /// Code length, whether the length is final, and the largest code, by the
+    /// first byte of a code.
+    lookup: Vec<(u32, bool, u64)>,
+    min_codes: [u64; 33],
+    max_codes: [u64; 33],
+    /// Phrases, and whether each is already expanded.
+    phrases: Vec<(Vec<u8>, bool)>,
+}
+
+impl HuffCdic {
+    fn new(tables: &[&[u8]]) -> Result<Self, BookError> {
+        let (huff, cdics) = tables.split_first().ok_or_else(|| malformed("HUFF record"))?;
+        if huff.get(..8) != Some(b"HUFF\x00\x00\x00\x18".as_slice()) {
+            return Err(malformed("HUFF record"));
+        }
+        let bad = || malformed("HUFF record");
+        let lookup_at = be32(huff, 8).ok_or_else(bad)? as usize;
+        let ranges_at = be32(huff, 12).ok_or_else(bad)? as usize;
+        let lookup = (0..256)
+            .map(|index| {
+                let value = be32(huff, lookup_at + index * 4).ok_or_else(bad)?;
+                let length = value & 0x1F;
+                if length == 0 {
+                    return Err(bad());
+                }
+                let max = ((((value >> 8) as u64) + 1) << (32 - length)) - 1;
+                Ok((length, value & 0x80 != 0, max))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut min_codes = [0; 33];
+        let mut max_codes = [u32::MAX as u64; 33];
+        for length in 1..33 {
+            let at = ranges_at + (length - 1) * 8;
+            let min = be32(huff, at).ok_or_else(bad)? as u64;
+            let max = be32(huff, at + 4).ok_or_else(bad)? as u64;
+            min_codes[length] = min << (32 - length);
+            max_codes[length] = ((max + 1) << (32 - length)) - 1;
+        }
+
+        let mut phrases = Vec::new();
+        for cdic in cdics {
+            if cdic.get(..8) != Some(b"CDIC\x00\x00\x00\x10".as_slice()) {
+                return Err(malformed("CDIC record"));
+            }
+            let total = be32(cdic, 8).ok_or_else(|| malformed("CDIC record"))? as usize;
+            let bits = be32(cdic, 12).ok_or_else(|| malformed("CDIC record"))?.min(16);
+            let count = (1usize << bits).min(total.saturating_sub(phrases.len()));
+            for index in 0..count {
+                let offset = be16(cdic, 16 + index * 2).ok_or_else(|| malformed("CDIC record"))? as usize;
+                let length = be16(cdic, 16 + offset).ok_or_else(|| malformed("CDIC record"))?;
+                let start = 18 + offset;
+                let phrase = cdic
+                    .get(start..start + (length & 0x7FFF) as usize)
+                    .ok_or_else(|| malformed("CDIC record"))?;
+                phrases.push((phrase.to_vec(), length & 0x8000 != 0));
+            }
+        }
+        Ok(Self {
+            lookup,
+            min_codes,
+            max_codes,
+            phrases,
+        })
+    }
+
+    fn unpack(&mut self, data: &[u8], depth: usize) -> Result<Vec<u8>, BookError> {
+        if depth > 32 {
+            return Err(malformed("CDIC phrase"));
+        }
+        let window = |at: usize| {
+            let mut bytes = [0u8; 8];
+            for (offset, byte) in bytes.iter_mut().enumerate() {
+                *byte = data.get(at + offset).copied().unwrap_or(0);
+            }
+            u64::from_be_bytes(bytes)
+        };
+        let mut bits_left = data.len() as i64 * 8;
+        let mut at = 0;
+        let mut bits = window(at);
+        let mut shift = 32i32;
+        let mut out = Vec::new();
+        loop {
+            if shift <= 0 {
+                at += 4;
+                bits = window(at);
+                shift += 32;
+            }
+            let code = (bits >> shift) & 0xFFFF_FFFF;
+            let (mut length, final_length, mut max) = self.lookup[(code >> 24) as usize];
+            if !final_length {
+                while length < 32 && code < self.min_codes[length as usize] {
+                    length += 1;
+                }
+                max = self.max_codes[length as usize];
+            }
+            shift -= length as i32;
+            bits_left -= length as i64;
+            if bits_left < 0 {
+                break;
+            }
+            let index = (max.wrapping_sub(code) >> (32 - length)) as usize;
+            if index >= self.phrases.len() {
+                return Err(malformed("HUFF code"));
+            }
+            if !self.phrases[index].1 {
+                let packed = std::mem::take(&mut self.phrases[index].0);
+                let expanded = self.unpack(&packed, depth + 1)?;
+                self.phrases[index] = (expanded, true);
+            }
+            out.extend_from_slice(&self.phrases[index].0);
+        }
+        Ok(out)
+    }
+}
+
+/// One entry of an INDX table: its key and tag values.
+#[derive(Default)]
+struct IndexEntry {
+    key: Vec<u8>,
+    tags: Vec<(u8, Vec<u32>)>,
+}
+
+impl IndexEntry {
+    fn values(&self, tag: u8) -> Option<&[u32]> {
+        self.tags
+            .iter()
+            .find(|(key, _)| *key == tag)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    fn value(&self, tag: u8) -> Option<u32> {
+        self.values(tag)?.first().copied()
+    }
+}
+
+/// An INDX table and the CNCX records its string tags point into.
+#[derive(Default)]
+struct Index {
+    entries: Vec<IndexEntry>,
+    strings: Vec<Vec<u8>>,
+}
+
+impl Index {
+    fn read<'a>(record: &impl Fn(usize) -> Option<&'a [u8]>, first: usize) -> Result<Self, BookError> {
+        let bad = || malformed("index");
+        let header = record(first).ok_or_else(bad)?;
+        if header.get(..4) != Some(b"INDX".as_slice()) {
+            return Err(bad());
+        }
+        let header_length = be32(header, 4).ok_or_else(bad)? as usize;
+        let data_records = be32(header, 24).ok_or_else(bad)? as usize;
+        let string_records = be32(header, 52).ok_or_else(bad)? as usize;
+
+        // TAGX: tag, values per entry, control byte mask, end-of-control-byte flag.
+        let tagx = header.get(header_length..).ok_or_else(bad)?;
+        if tagx.get(..4) != Some(b"TAGX".as_slice()) {
+            return Err(bad());
+        }
+        let tagx_length = be32(tagx, 4).ok_or_else(bad)? as usize;
+        let control_bytes = be32(tagx, 8).ok_or_else(bad)? as usize;
+        let table = tagx
+            .get(12..tagx_length)
+            .ok_or_else(bad)?
+            .chunks_exact(4)
+            .map(|tag| [tag[0], tag[1], tag[2], tag[3]])
+            .collect::<Vec<_>>();
+
+        let mut entries = Vec::new();
+        for index in first + 1..=first + data_records {
+            let data = record(index).ok_or_else(bad)?;
+            let idxt = be32(data, 20).ok_or_else(bad)? as usize;
+            let count = be32(data, 24).ok_or_else(bad)? as usize;
+            let mut offsets = (0..count)
+                .map(|entry| be16(data, idxt + 4 + entry * 2).map(|offset| offset as usize))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(bad)?;
+            offsets.push(idxt);
+            for pair in offsets.windows(2) {
+                let (start, end) = (pair[0], pair[1].min(data.len()));
+                let key_length = *data.get(start).ok_or_else(bad)? as usize;
+                let key = data.get(start + 1..start + 1 + key_length).ok_or_else(bad)?;
+                let tags = read_tags(&table, control_bytes, data, start + 1 + key_length, end);
+                entries.push(IndexEntry { key: key.to_vec(), tags });
+            }
+        }
+        let strings = (0..string_records)
+            .filter_map(|offset| record(first + data_records + 1 + offset).map(<[u8]>::to_vec))
+            .collect();
+        Ok(Self { entries, strings })
+    }
+
+    /// String at a CNCX offset; each record covers 64 KiB of offsets.
+    fn label(&self, offset: usize) -> Option<Vec<u8>> {
+        let strings = self.strings.get(offset >> 16)?;
+        let (length, used) = forward_varint(strings, offset & 0xFFFF);
+        let start = (offset & 0xFFFF) + used;
+        strings.get(start..start + length as usize).map(<[u8]>::to_vec)
+    }
+}
+
+fn read_tags(table: &[[u8; 4]], control_bytes: usize, data: &[u8], start: usize, end: usize) -> Vec<(u8, Vec<u32>)> {
+    // Each tag either has a value count in its control bits or, when all of a
+    // multi-bit mask is set, a byte length read before the values.
+    let mut present = Vec::new();
+    let mut control = 0;
+    let mut at = start + control_bytes;
+    for &[tag, per_entry, mask, end_flag] in table {
+        if end_flag == 1 {
+            control += 1;
+            continue;
+        }
+        let Some(&byte) = data.get(start + control) else {
+            break;
+        };
+        let value = byte & mask;
+        if value == 0 {
+            continue;
+        }
+        if value == mask && mask.count_ones() > 1 {
+            let (length, used) = forward_varint(data, at);
+            at += used;
+            present.push((tag, None, Some(length as usize), per_entry));
+        } else {
+            present.push((tag, Some(value >> mask.trailing_zeros()), None, per_entry));
+        }
+    }
+    let mut tags = Vec::new();
+    for (tag, count, length, per_entry) in present {
+        let mut values = Vec::new();
+        match (count, length) {
+            (Some(count), _) => {
+                for _ in 0..count as usize * per_entry as usize {
+                    if at >= end {
+                        break;
+                    }
+                    let (value, used) = forward_varint(data, at);
+                    at += used;
+                    values.push(value);
+                }
+            }
+            (None, Some(length)) => {
+                let stop = (at + length).min(end);
+                while at < stop {
+                    let (value, used) = forward_varint(data, at);
+                    at += used;
+                    values.push(value);
+                }
+            }
+            (None, None) => {}
+        }
+        tags.push((tag, values));
+    }
+    tags
+}
+
+/// A file of the book and where it starts in the KF8 text.
+struct Part {
+    start: usize,
+    bytes: Vec<u8>,
+}
+
+/// Rebuilds the KF8 files: each skeleton is followed in the text by the
+/// fragments inserted into it. Also returns, per NCX entry, its position in
+/// the rebuilt text.
+fn kf8_parts<'a>(
+    header: &Header,
+    record: &impl Fn(usize) -> Option<&'a [u8]>,
+    mut text: Vec<u8>,
+    ncx: &Index,
+    targets: &mut Vec<usize>,
+) -> Result<Vec<Part>, BookError> {
+    // Only the first flow is markup; the others are stylesheets and SVG.
+    let flow_end = header
+        .index(header.fdst)
+        .and_then(record)
+        .filter(|fdst| fdst.starts_with(b"FDST"))
+        .and_then(|fdst| be32(fdst, 16));
+    if let Some(end) = flow_end {
+        text.truncate(end as usize);
+    }
+    let (Some(skeletons), Some(fragments)) = (header.index(header.skeletons), header.index(header.fragments)) else {
+        for entry in &ncx.entries {
+            targets.push(entry.value(TAG_POSITION).unwrap_or(0) as usize);
+        }
+        return Ok(vec![Part { start: 0, bytes: text }]);
+    };
+    let skeletons = Index::read(record, skeletons)?;
+    let fragments = Index::read(record, fragments)?;
+
+    let mut parts = Vec::new();
+    let mut insert_positions = Vec::new();
+    let mut next_fragment = 0;
+    for skeleton in &skeletons.entries {
+        let (Some(&[position, length]), Some(count)) =
+            (skeleton.values(TAG_SPAN).and_then(|span| span.get(..2)), skeleton.value(TAG_FRAGMENT_COUNT))
+        else {
+            return Err(malformed("skeleton index"));
+        };
+        let (position, length) = (position as usize, length as usize);
+        let mut bytes = text.get(position..position + length).ok_or_else(|| malformed("skeleton"))?.to_vec();
+        let mut read_at = position + length;
+        for fragment in fragments.entries.iter().skip(next_fragment).take(count as usize) {
+            let insert = std::str::from_utf8(&fragment.key)
+                .ok()
+                .and_then(|key| key.parse::<usize>().ok())
+                .ok_or_else(|| malformed("fragment index"))?;
+            let length = fragment.values(TAG_SPAN).and_then(|span| span.get(1)).copied().unwrap_or(0) as usize;
+            let slice = text.get(read_at..read_at + length).ok_or_else(|| malformed("fragment"))?;
+            let local = insert.saturating_sub(position).min(bytes.len());
+            bytes.splice(local..local, slice.iter().copied());
+            read_at += length;
+            insert_positions.push(insert);
+        }
+        next_fragment += count as usize;
+        parts.push(Part { start: position, bytes });
+    }
+    for entry in &ncx.entries {
+        let target = match entry.values(TAG_POS_FID) {
+            Some(&[fid, offset, ..]) => insert_positions.get(fid as usize).map(|insert| insert + offset as usize),
+            _ => None,
+        };
+        targets.push(target.or(entry.value(TAG_POSITION).map(|position| position as usize)).unwrap_or(0));
+    }
+    Ok(parts)
+}
+
+fn marker_id(position: usize) -> String {
+    format!("pos{position}")
+}
+
+/// Inserts an empty anchor at each target position, moved past any tag it
+/// falls inside, then splits old-style text at its page breaks.
+fn mark_targets(parts: Vec<Part>, targets: &[usize], kf8: bool) -> Vec<Vec<u8>> {
+    let mut sorted = targets.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let mut documents = Vec::new();
+    for mut part in parts {
+        let end = part.start + part.bytes.len();
+        for &target in sorted.iter().rev().filter(|&&target| target >= part.start && target < end) {
+            let mut local = target - part.start;
+            let before = &part.bytes[..local];
+            let open = before.iter().rposition(|&byte| byte == b'<');
+            let close = before.iter().rposition(|&byte| byte == b'>');
+            if open > close {
+                match part.bytes[local..].iter().position(|&byte| byte == b'>') {
+                    Some(offset) => local += offset + 1,
+                    None => continue,
+                }
+            }
+            let marker = format!("<a id=\"{}\"></a>", marker_id(target));
+            part.bytes.splice(local..local, marker.bytes());
+        }
+        if kf8 {
+            documents.push(part.bytes);
+        } else {
+            documents.extend(split_pagebreaks(&part.bytes));
+        }
+    }
+    documents
+}
+
+/// Sections between `<mbp:pagebreak/>` tags that have any content.
+fn split_pagebreaks(text: &[u8]) -> Vec<Vec<u8>> {
+    const PAGEBREAK: &[u8] = b"<mbp:pagebreak";
+    let mut sections = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    while at + PAGEBREAK.len() <= text.len() {
+        if !text[at..at + PAGEBREAK.len()].eq_ignore_ascii_case(PAGEBREAK) {
+            at += 1;
+            continue;
+        }
+        sections.push(text[start..at].to_vec());
+        at = text[at..]
+            .iter()
+            .position(|&byte| byte == b'>')
+            .map(|offset| at + offset + 1)
+            .unwrap_or(text.len());
+        start = at;
+    }
+    sections.push(text[start..].to_vec());
+    sections.retain(|section| has_content(section));
+    if sections.is_empty() {
+        sections.push(Vec::new());
+    }
+    sections
+}
+
+/// Whether a section has text, an image or a TOC anchor outside its tags.
+fn has_content(section: &[u8]) -> bool {
+    let mut in_tag = false;
+    for (at, &byte) in section.iter().enumerate() {
+        match byte {
+            b'<' => {
+                let tag = &section[at..];
+                let starts = |prefix: &[u8]| tag.get(..prefix.len()).is_some_and(|head| head.eq_ignore_ascii_case(prefix));
+                if starts(b"<img") || starts(b"<image") || starts(b"<a id=\"pos") {
+                    return true;
+                }
+                in_tag = true;
+            }
+            b'>' => in_tag = false,
+            _ if !in_tag && !byte.is_ascii_whitespace() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Renames `recindex` and `kindle:embed` image references to `images/NNNNN`.
+fn kindle_image(element: &str, key: &str, value: &str) -> Option<(String, String)> {
+    if element == "img" && key == "recindex" {
+        return Some(("src".to_string(), image_name(value.trim().parse().unwrap_or(0))));
+    }
+    let embed = value.strip_prefix("kindle:embed:")?;
+    Some((key.to_string(), image_name(base32(embed.get(..4).unwrap_or(embed)))))
+}
+
+fn image_name(number: usize) -> String {
+    format!("images/{number:05}")
+}
+
+/// Kindle's base 32: digits, then `A` to `V`.
+fn base32(text: &str) -> usize {
+    text.chars()
+        .filter_map(|ch| ch.to_digit(32))
+        .fold(0, |value, digit| value * 32 + digit as usize)
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn malformed(what: &str) -> BookError {
+    BookError::Mobi(format!("malformed {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_LENGTH: usize = 0xE8;
+
+    fn put16(bytes: &mut [u8], at: usize, value: u16) {
+        bytes[at..at + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn put32(bytes: &mut [u8], at: usize, value: u32) {
+        bytes[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Record 0 of an uncompressed, UTF-8, old-style book with one text record.
+    fn header(text_length: usize, exth: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut record = vec![0; 16 + HEADER_LENGTH];
+        put16(&mut record, 0, COMPRESSION_NONE);
+        put32(&mut record, 4, text_length as u32);
+        put16(&mut record, 8, 1);
+        record[16..20].copy_from_slice(b"MOBI");
+        put32(&mut record, 20, HEADER_LENGTH as u32);
+        put32(&mut record, 28, ENCODING_UTF8);
+        put32(&mut record, 32, 42);
+        put32(&mut record, 36, 6);
+        put32(&mut record, 108, NO_INDEX);
+        put32(&mut record, 128, 0x40);
+        put32(&mut record, 0xF4, NO_INDEX);
+
+        let mut block = Vec::new();
+        for (kind, value) in exth {
+            block.extend_from_slice(&kind.to_be_bytes());
+            block.extend_from_slice(&(value.len() as u32 + 8).to_be_bytes());
+            block.extend_from_slice(value);
+        }
+        record.extend_from_slice(b"EXTH");
+        record.extend_from_slice(&(block.len() as u32 + 12).to_be_bytes());
+        record.extend_from_slice(&(exth.len() as u32).to_be_bytes());
+        record.extend_from_slice(&block);
+
+        let name = b"Header Name";
+        let name_start = record.len();
+        put32(&mut record, 84, name_start as u32);
+        put32(&mut record, 88, name.len() as u32);
+        record.extend_from_slice(name);
+        record
+    }
+
+    /// A Palm database holding `records`.
+    fn palmdb(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0; 78];
+        data[..9].copy_from_slice(b"Palm_Name");
+        data[60..68].copy_from_slice(b"BOOKMOBI");
+        put16(&mut data, 76, records.len() as u16);
+        let mut start = 78 + records.len() * 8 + 2;
+        for record in records {
+            data.extend_from_slice(&(start as u32).to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+            start += record.len();
+        }
+        data.extend_from_slice(&[0; 2]);
+        for record in records {
+            data.extend_from_slice(record);
+        }
+        data
+    }
+
+    const TEXT: &[u8] = b"<html><body><p>One</p><mbp:pagebreak/><p>Two</p></body></html>";
+
+    fn book() -> Vec<u8> {
+        let exth: &[(u32, &[u8])] = &[
+            (EXTH_TITLE, b"A Small Book"),
+            (EXTH_AUTHOR, b"First Author"),
+            (EXTH_AUTHOR, b"Second Author"),
+            (EXTH_LANGUAGE, b"en"),
+        ];
+        palmdb(&[header(TEXT.len(), exth), TEXT.to_vec()])
+    }
+
+    fn parse(data: Vec<u8>) -> Result<MobiInput, BookError> {
+        MobiInput::parse(Path::new("book.mobi"), data)
+    }
+
+    #[test]
+    fn minimal_book_opens() {
+        let input = parse(book()).unwrap();
+        assert!(!input.kf8);
+        let metadata = input.metadata();
+        assert_eq!(metadata.title, "A Small Book");
+        assert_eq!(metadata.author, "First Author, Second Author");
+        assert_eq!(metadata.language, "en");
+        assert_eq!(metadata.identifier, "42");
+        let names: Vec<_> = input
+            .spine()
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect();
+        assert_eq!(names, ["part0000.html", "part0001.html"]);
+        assert!(input.documents[0].contains("One") && !input.documents[0].contains("Two"));
+        assert!(input.documents[1].contains("Two"));
+        assert!(input.toc().is_empty());
+        assert!(input.cover().is_none());
+    }
+
+    #[test]
+    fn title_falls_back_to_the_header_then_the_database_name() {
+        let data = palmdb(&[header(TEXT.len(), &[]), TEXT.to_vec()]);
+        let metadata = parse(data.clone()).unwrap().metadata();
+        assert_eq!(metadata.title, "Header Name");
+        assert_eq!(metadata.author, "<unknown>");
+        assert_eq!(metadata.language, "<unknown>");
+
+        let mut record = header(TEXT.len(), &[]);
+        put32(&mut record, 84, u32::MAX);
+        let data = palmdb(&[record, TEXT.to_vec()]);
+        assert_eq!(parse(data).unwrap().metadata().title, "Palm_Name");
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let record = header(TEXT.len(), &[]);
+        for length in 0..=128 {
+            assert!(
+                Header::parse(&record[..length], 0).is_err(),
+                "{length} bytes"
+            );
+        }
+        // A header that stops before the optional fields still parses.
+        let short = Header::parse(&record[..132], 0).unwrap();
+        assert_eq!(short.ncx, NO_INDEX);
+        assert!(short.exth.is_empty());
+    }
+
+    #[test]
+    fn truncated_files_do_not_panic() {
+        let data = book();
+        let record_one = 78 + 2 * 8 + 2 + header(TEXT.len(), &[]).len();
+        for length in 0..data.len() {
+            let result = parse(data[..length].to_vec());
+            if length < record_one {
+                assert!(result.is_err(), "{length} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn record_offset_out_of_range_is_rejected() {
+        let mut data = book();
+        let past_end = data.len() as u32 + 1;
+        put32(&mut data, 78 + 8, past_end);
+        assert!(matches!(parse(data), Err(BookError::Mobi(_))));
+
+        let mut data = book();
+        put32(&mut data, 78 + 8, u32::MAX);
+        assert!(matches!(parse(data), Err(BookError::Mobi(_))));
+
+        // Records out of order.
+        let mut data = book();
+        put32(&mut data, 78, 100);
+        put32(&mut data, 78 + 8, 90);
+        assert!(matches!(parse(data), Err(BookError::Mobi(_))));
+
+        // More records announced than listed.
+        let mut data = book();
+        put16(&mut data, 76, 0x7FFF);
+        assert!(matches!(parse(data), Err(BookError::Mobi(_))));
+    }
+
+    #[test]
+    fn missing_text_record_is_rejected() {
+        let mut record = header(TEXT.len(), &[]);
+        put16(&mut record, 8, 3);
+        let data = palmdb(&[record, TEXT.to_vec()]);
+        assert!(matches!(parse(data), Err(BookError::Mobi(_))));
+    }
+
+    #[test]
+    fn oversized_lengths_are_clamped() {
+        let mut record = header(usize::MAX >> 32, &[]);
+        put32(&mut record, 88, u32::MAX);
+        let data = palmdb(&[record, TEXT.to_vec()]);
+        let input = parse(data).unwrap();
+        assert_eq!(input.spine().len(), 2);
+
+        let mut exth = b"EXTH".to_vec();
+        exth.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 2]);
+        exth.extend_from_slice(&EXTH_TITLE.to_be_bytes());
+        exth.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse_exth(&exth).is_empty());
+    }
+
+    #[test]
+    fn encrypted_books_are_refused() {
+        let mut record = header(TEXT.len(), &[]);
+        put16(&mut record, 12, 2);
+        let data = palmdb(&[record, TEXT.to_vec()]);
+        assert!(matches!(parse(data), Err(BookError::Drm(_))));
+    }
+
+    #[test]
+    fn not_a_mobi_is_rejected() {
+        assert!(parse(Vec::new()).is_err());
+        let mut data = book();
+        data[60..68].copy_from_slice(b"TEXtREAd");
+        assert!(parse(data).is_err());
+    }
+
+    #[test]
+    fn palmdoc_decompresses() {
+        assert_eq!(
+            palmdoc_decompress(&[b'a', b'b', 0x80, 0x10, 0xE1]),
+            b"ababa a"
+        );
+        assert_eq!(palmdoc_decompress(&[2, 0, 9, b'x']), [0, 9, b'x']);
+        // Copies from before the start and cut-off input are dropped.
+        assert_eq!(palmdoc_decompress(&[b'a', 0x80, 0x80, 3, b'b']), b"ab");
+    }
+}
@@ -0,0 +1,241 @@
+//! Stage 1: open a source book in any supported format.
+//!
+//! Each format is a [`BookInput`] backend listed in [`FORMATS`]. The rest of the
+//! pipeline only talks to the trait, so adding a format means adding a backend
+//! and an entry in the table.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::blocks::HtmlBlock;
+use crate::toc::title_from_blocks;
+use crate::typography::{self, Typography};
+use crate::{BookError, ImageLimits, TrbkMetadata, WritingMode};
+
+pub mod article;
+pub mod djvu;
+pub mod epub;
+mod html;
+pub mod mobi;
+pub mod pdf;
+pub mod text;
+
+pub use article::ArticleInput;
+pub use djvu::DjvuInput;
+pub use epub::EpubInput;
+pub use mobi::MobiInput;
+pub use pdf::{PdfInput, PdfMode};
+pub use text::TextInput;
+
+/// One document in reading order.
+#[derive(Clone, Debug)]
+pub struct SpineItem {
+    /// Source name, e.g. the EPUB href. Its last path segment is the TOC title of
+    /// last resort.
+    pub name: String,
+}
+
+/// A navigation entry pointing into a spine item.
+#[derive(Clone, Debug)]
+pub struct NavEntry {
+    pub title: String,
+    pub spine_index: usize,
+    /// Element id within the item; the entry starts at the item's first page
+    /// when this is `None` or the id is never laid out.
+    pub anchor: Option<String>,
+    pub level: u8,
+}
+
+pub trait BookInput {
+    fn metadata(&self) -> TrbkMetadata;
+    fn spine(&self) -> &[SpineItem];
+    /// Blocks of spine item `index`. Image `src` values must be names that
+    /// [`BookInput::resource`] accepts.
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError>;
+    /// Navigation entries; when empty the TOC gets one entry per spine item.
+    fn toc(&self) -> Vec<NavEntry> {
+        Vec::new()
+    }
+    /// Raw bytes of an embedded resource such as an image.
+    fn resource(&self, _name: &str) -> Option<Vec<u8>> {
+        None
+    }
+    /// Title for a spine item, used when the TOC label is missing or unusable.
+    fn spine_title(&self, index: usize) -> Option<String> {
+        title_from_blocks(&self.blocks(index).ok()?)
+    }
+    /// Direction the book declares for its text.
+    fn writing_mode(&self) -> WritingMode {
+        WritingMode::Horizontal
+    }
+    /// Pre-paginated items are drawn as one full-screen image each by
+    /// [`crate::fixed`] instead of going through text layout.
+    fn is_fixed_layout(&self, _index: usize) -> bool {
+        false
+    }
+    /// Source markup of a spine item and its resource name, which relative
+    /// links in the markup resolve against.
+    fn document(&self, _index: usize) -> Option<(String, String)> {
+        None
+    }
+    /// Scanned pages of a fixed-layout item, already run through
+    /// [`crate::scan::prepare_page`]. Items without any are drawn from
+    /// [`BookInput::document`].
+    fn page_images(&self, _index: usize) -> Vec<image::GrayImage> {
+        Vec::new()
+    }
+    /// Image bytes for a cover page put in front of the book.
+    fn cover(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Caps on the size and gray levels of converted images.
+    fn image_limits(&self) -> ImageLimits {
+        ImageLimits::default()
+    }
+}
+
+/// Metadata that replaces what the book gets wrong. Fields left as `None`
+/// keep the book's own value.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataOverrides {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    pub series: Option<String>,
+}
+
+/// Wraps an input to override what the book declares, e.g. from command-line
+/// flags. Fields left as `None` keep the input's own answer.
+pub struct InputOverrides<'a> {
+    pub input: &'a dyn BookInput,
+    pub writing_mode: Option<WritingMode>,
+    pub metadata: MetadataOverrides,
+    /// Image bytes for a cover page, which also becomes the library thumbnail.
+    pub cover: Option<Vec<u8>>,
+    /// Caps images for devices short on memory.
+    pub image_limits: Option<ImageLimits>,
+    /// Cleanup of the text's typography, in the overridden language.
+    pub typography: Typography,
+}
+
+impl<'a> InputOverrides<'a> {
+    /// Overrides nothing until fields are set.
+    pub fn new(input: &'a dyn BookInput) -> Self {
+        Self {
+            input,
+            writing_mode: None,
+            metadata: MetadataOverrides::default(),
+            cover: None,
+            image_limits: None,
+            typography: Typography::Off,
+        }
+    }
+}
+
+impl BookInput for InputOverrides<'_> {
+    fn metadata(&self) -> TrbkMetadata {
+        let mut metadata = self.input.metadata();
+        let overrides = &self.metadata;
+        if let Some(title) = &overrides.title {
+            metadata.title = title.clone();
+        }
+        if let Some(author) = &overrides.author {
+            metadata.author = author.clone();
+        }
+        if let Some(language) = &overrides.language {
+            metadata.language = language.clone();
+        }
+        if overrides.series.is_some() {
+            metadata.series = overrides.series.clone();
+        }
+        metadata
+    }
+    fn spine(&self) -> &[SpineItem] {
+        self.input.spine()
+    }
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        let mut blocks = self.input.blocks(index)?;
+        if self.typography != Typography::Off {
+            typography::apply(&mut blocks, self.typography, &self.metadata().language);
+        }
+        Ok(blocks)
+    }
+    fn toc(&self) -> Vec<NavEntry> {
+        self.input.toc()
+    }
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        self.input.resource(name)
+    }
+    fn spine_title(&self, index: usize) -> Option<String> {
+        self.input.spine_title(index)
+    }
+    fn writing_mode(&self) -> WritingMode {
+        self.writing_mode.unwrap_or_else(|| self.input.writing_mode())
+    }
+    fn is_fixed_layout(&self, index: usize) -> bool {
+        self.input.is_fixed_layout(index)
+    }
+    fn document(&self, index: usize) -> Option<(String, String)> {
+        self.input.document(index)
+    }
+    fn page_images(&self, index: usize) -> Vec<image::GrayImage> {
+        self.input.page_images(index)
+    }
+    fn cover(&self) -> Option<Vec<u8>> {
+        self.cover.clone().or_else(|| self.input.cover())
+    }
+    fn image_limits(&self) -> ImageLimits {
+        self.image_limits.unwrap_or_else(|| self.input.image_limits())
+    }
+}
+
+/// A registered input backend.
+pub struct InputFormat {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Recognises the format from the first bytes of the file.
+    pub sniff: fn(&[u8]) -> bool,
+    pub open: fn(&Path) -> Result<Box<dyn BookInput>, BookError>,
+}
+
+/// Backends in detection order; content sniffing is tried before extensions.
+pub const FORMATS: &[InputFormat] = &[epub::FORMAT, mobi::FORMAT, pdf::FORMAT, djvu::FORMAT, article::FORMAT, text::FORMAT];
+
+const SNIFF_LEN: usize = 512;
+
+pub fn format_by_name(name: &str) -> Option<&'static InputFormat> {
+    FORMATS
+        .iter()
+        .find(|format| format.name.eq_ignore_ascii_case(name))
+}
+
+/// Picks a backend from the file contents, falling back to the extension.
+/// `http(s)://` addresses are web articles.
+pub fn detect_format(path: &Path) -> Result<&'static InputFormat, BookError> {
+    if path.to_str().is_some_and(article::is_url) {
+        return Ok(&article::FORMAT);
+    }
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    if let Some(format) = FORMATS.iter().find(|format| (format.sniff)(&head)) {
+        return Ok(format);
+    }
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    FORMATS
+        .iter()
+        .find(|format| format.extensions.contains(&extension.as_str()))
+        .ok_or_else(|| BookError::UnsupportedInput(path.display().to_string()))
+}
+
+/// Opens `path` with the detected backend.
+pub fn open_input(path: &Path) -> Result<Box<dyn BookInput>, BookError> {
+    let format = detect_format(path)?;
+    (format.open)(path)
+}
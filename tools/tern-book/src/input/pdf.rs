@@ -0,0 +1,1240 @@
+//! Experimental PDF backend for digitally-born PDFs. Text comes from
+//! `pdf-extract` as positioned characters; paths and images are read from the
+//! page content streams.
+//!
+//! In [`PdfMode::Reflow`] characters are grouped into lines and paragraphs by
+//! position and size and go through the normal layout, with the outline (when
+//! there is one) splitting the book into chapters. In [`PdfMode::Image`] every
+//! page becomes a fixed-layout item that [`crate::fixed`] draws as one
+//! full-screen image. [`PdfMode::Scan`] is for scanned books: the largest
+//! image on each page is taken as the scan and goes through [`crate::scan`].
+
+use std::io::Cursor;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use pdf_extract::{Dictionary, Document, MediaBox, Object, ObjectId, OutputDev, OutputError, Stream, Transform};
+
+use super::{BookInput, InputFormat, NavEntry, SpineItem};
+use crate::blocks::{HtmlBlock, TextRun, TextStyle};
+use crate::fixed::escape;
+use crate::scan::{prepare_page, ScanOptions};
+use crate::{BookError, TrbkMetadata};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "pdf",
+    extensions: &["pdf"],
+    sniff: |head| head.starts_with(b"%PDF-"),
+    open: |path| Ok(Box::new(PdfInput::open(path, PdfMode::default())?)),
+};
+
+/// Nesting limit for form XObjects drawn inside each other.
+const MAX_FORM_DEPTH: usize = 8;
+/// Images smaller than this on the page, in points, are rules and ornaments
+/// and are left out of reflowed text.
+const MIN_REFLOW_IMAGE: f64 = 24.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PdfMode {
+    /// Extract the text and lay it out again for the screen.
+    #[default]
+    Reflow,
+    /// Draw each page as it is, scaled to the screen.
+    Image,
+    /// Show the scanned image of each page, thresholded for text.
+    Scan,
+}
+
+#[derive(Clone, Debug)]
+pub struct PdfInput {
+    pub path: PathBuf,
+    pub mode: PdfMode,
+    /// Trimming and splitting of pages in [`PdfMode::Scan`].
+    pub scan: ScanOptions,
+    doc: Document,
+    /// Page object ids in page order.
+    pages: Vec<ObjectId>,
+    /// First page of each spine item.
+    starts: Vec<usize>,
+    outline: Vec<OutlineEntry>,
+    spine: Vec<SpineItem>,
+}
+
+#[derive(Clone, Debug)]
+struct OutlineEntry {
+    title: String,
+    level: u8,
+    page: usize,
+}
+
+impl PdfInput {
+    /// Loads the document and its outline. Encrypted PDFs are opened with the
+    /// empty user password, which is how most "copy protected" PDFs are set up.
+    pub fn open(path: &Path, mode: PdfMode) -> Result<Self, BookError> {
+        let mut doc = Document::load(path).map_err(|err| BookError::Pdf(err.to_string()))?;
+        if doc.is_encrypted() && doc.decrypt("").is_err() {
+            return Err(BookError::Pdf("the PDF is password protected".to_string()));
+        }
+        let pages = doc.get_pages().into_values().collect::<Vec<_>>();
+        if pages.is_empty() {
+            return Err(BookError::Pdf("the PDF has no pages".to_string()));
+        }
+        let outline = doc
+            .get_toc()
+            .map(|toc| toc.toc)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| entry.page >= 1 && entry.page <= pages.len())
+            .map(|entry| OutlineEntry {
+                title: entry.title.trim().to_string(),
+                level: entry.level.saturating_sub(1).min(u8::MAX as usize) as u8,
+                page: entry.page - 1,
+            })
+            .collect::<Vec<_>>();
+
+        // Reflowed text starts a new spine item at each top-level outline entry.
+        let starts = match mode {
+            PdfMode::Image | PdfMode::Scan => (0..pages.len()).collect(),
+            PdfMode::Reflow => {
+                let top = outline.iter().map(|entry| entry.level).min().unwrap_or(0);
+                let mut starts = vec![0];
+                starts.extend(
+                    outline
+                        .iter()
+                        .filter(|entry| entry.level == top)
+                        .map(|entry| entry.page),
+                );
+                starts.sort_unstable();
+                starts.dedup();
+                starts
+            }
+        };
+        let spine = starts
+            .iter()
+            .map(|start| SpineItem {
+                name: format!("page-{:04}.xhtml", start + 1),
+            })
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode,
+            scan: ScanOptions::default(),
+            doc,
+            pages,
+            starts,
+            outline,
+            spine,
+        })
+    }
+
+    fn title(&self) -> String {
+        info_string(&self.doc, b"Title").unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| "<unknown>".to_string())
+        })
+    }
+
+    /// Pages of spine item `index`.
+    fn page_range(&self, index: usize) -> std::ops::Range<usize> {
+        let start = self.starts[index];
+        let end = self.starts.get(index + 1).copied().unwrap_or(self.pages.len());
+        start..end
+    }
+
+    fn spine_index_of_page(&self, page: usize) -> usize {
+        self.starts
+            .iter()
+            .rposition(|start| *start <= page)
+            .unwrap_or(0)
+    }
+
+    /// Characters of `page` in content order, in page coordinates with y
+    /// pointing down. Pages `pdf-extract` cannot read come back empty.
+    fn page_chars(&self, page: usize) -> Vec<PdfChar> {
+        let page_box = page_box(&self.doc, self.pages[page]);
+        let mut collector = CharCollector::default();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            pdf_extract::output_doc_page(&self.doc, &mut collector, page as u32 + 1)
+        }));
+        if !matches!(result, Ok(Ok(()))) {
+            eprintln!(
+                "[tern-book] warning: failed to extract text from PDF page {}",
+                page + 1
+            );
+            return Vec::new();
+        }
+        collector
+            .chars
+            .into_iter()
+            .map(|ch| PdfChar {
+                x: ch.x - page_box.llx,
+                baseline: page_box.ury - ch.baseline,
+                ..ch
+            })
+            .collect()
+    }
+
+    /// Paths and images of `page` in painting order, and the image streams
+    /// the marks point into.
+    fn page_graphics(&self, page: usize) -> (Vec<Mark>, Vec<&Stream>) {
+        let page_id = self.pages[page];
+        let mut walker = GraphicsWalker {
+            doc: &self.doc,
+            marks: Vec::new(),
+            images: Vec::new(),
+        };
+        let content = self.doc.get_page_content(page_id).unwrap_or_default();
+        if let Some(resources) = inherited_dict(&self.doc, page_id, b"Resources") {
+            walker.walk(&content, resources, IDENTITY, 0);
+        }
+        (walker.marks, walker.images)
+    }
+
+    /// Reflowed blocks for the pages in `range`. Paragraphs carry on across
+    /// page breaks, and each page gets a `page-N` anchor for the outline.
+    fn reflow_blocks(&self, range: std::ops::Range<usize>) -> Vec<HtmlBlock> {
+        let pages = range
+            .map(|page| {
+                let lines = lines_from_words(&words_from_chars(&self.page_chars(page)));
+                (page, drop_page_numbers(lines))
+            })
+            .collect::<Vec<_>>();
+        let body = body_size(pages.iter().flat_map(|(_, lines)| lines.iter()));
+
+        let mut out = Reflow {
+            blocks: Vec::new(),
+            paragraph: None,
+            body,
+        };
+        for (page, lines) in &pages {
+            let mut anchor = Some(format!("page-{}", page + 1));
+            let right = lines.iter().map(|line| line.end).fold(0.0, f64::max);
+            let mut images = self.reflow_images(*page);
+            let mut previous: Option<&Line> = None;
+            for line in lines {
+                while images
+                    .first()
+                    .is_some_and(|(top, _)| *top < line.baseline - line.size)
+                {
+                    let (_, src) = images.remove(0);
+                    out.flush();
+                    out.push_anchor(&mut anchor);
+                    out.blocks.push(HtmlBlock::Image { alt: None, src });
+                }
+                let starts_paragraph = match previous {
+                    Some(previous) => breaks_paragraph(previous, line, right),
+                    None => !out.continues(line),
+                };
+                if starts_paragraph {
+                    out.flush();
+                    out.push_anchor(&mut anchor);
+                    out.paragraph = Some(Paragraph {
+                        text: line.text.clone(),
+                        size: line.size,
+                        leading: None,
+                        last_baseline: line.baseline,
+                    });
+                } else {
+                    out.append(line);
+                }
+                previous = Some(line);
+            }
+            if !images.is_empty() {
+                out.flush();
+                out.push_anchor(&mut anchor);
+                for (_, src) in images {
+                    out.blocks.push(HtmlBlock::Image { alt: None, src });
+                }
+            }
+            if let Some(paragraph) = out.paragraph.as_mut() {
+                // Line spacing does not carry over to the next page.
+                paragraph.leading = None;
+                paragraph.last_baseline = f64::NEG_INFINITY;
+            }
+            // A page that only continues a paragraph points at its start.
+            out.push_anchor(&mut anchor);
+        }
+        out.flush();
+        out.blocks
+    }
+
+    /// Images of `page` large enough to keep, by their top edge.
+    fn reflow_images(&self, page: usize) -> Vec<(f64, String)> {
+        let page_box = page_box(&self.doc, self.pages[page]);
+        let (marks, _) = self.page_graphics(page);
+        let mut images = marks
+            .iter()
+            .filter_map(|mark| {
+                let Mark::Image { ctm, index } = mark else {
+                    return None;
+                };
+                let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+                    .map(|(x, y)| apply(ctm, x, y));
+                let min_x = corners.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+                let max_x = corners.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+                let min_y = corners.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+                let max_y = corners.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+                if max_x - min_x < MIN_REFLOW_IMAGE || max_y - min_y < MIN_REFLOW_IMAGE {
+                    return None;
+                }
+                Some((page_box.ury - max_y, image_name(page, *index)))
+            })
+            .collect::<Vec<_>>();
+        images.sort_by(|a, b| a.0.total_cmp(&b.0));
+        images
+    }
+
+    /// The largest image drawn on `page`, which in a scanned book is the page
+    /// itself, as gray pixels.
+    fn scanned_page(&self, page: usize) -> Option<image::GrayImage> {
+        let (marks, images) = self.page_graphics(page);
+        let (_, index) = marks
+            .iter()
+            .filter_map(|mark| match mark {
+                Mark::Image { ctm, index } => Some(((ctm[0] * ctm[3] - ctm[1] * ctm[2]).abs(), *index)),
+                Mark::Path { .. } => None,
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))?;
+        let bytes = encode_image(&self.doc, images.get(index)?)?;
+        match image::load_from_memory(&bytes) {
+            Ok(image) => Some(image.to_luma8()),
+            Err(err) => {
+                eprintln!(
+                    "[tern-book] warning: failed to decode the scan of PDF page {}: {err}",
+                    page + 1
+                );
+                None
+            }
+        }
+    }
+
+    /// The page as XHTML for [`crate::fixed`]: one SVG with the paths, images
+    /// and words of text.
+    fn page_markup(&self, page: usize) -> String {
+        let page_box = page_box(&self.doc, self.pages[page]);
+        let width = page_box.urx - page_box.llx;
+        let height = page_box.ury - page_box.lly;
+        let (marks, _) = self.page_graphics(page);
+
+        let mut out = format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head>\
+             <meta name=\"viewport\" content=\"width={width}, height={height}\"/></head><body>\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+             style=\"position: absolute; left: 0; top: 0\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\"><g transform=\"matrix(1 0 0 -1 {} {})\">",
+            -page_box.llx, page_box.ury
+        );
+        for mark in &marks {
+            match mark {
+                Mark::Path {
+                    ctm,
+                    d,
+                    fill,
+                    stroke,
+                    even_odd,
+                } => {
+                    out.push_str(&format!("<path transform=\"{}\" d=\"{d}\"", matrix(ctm)));
+                    match fill {
+                        Some(color) => out.push_str(&format!(" fill=\"{color}\"")),
+                        None => out.push_str(" fill=\"none\""),
+                    }
+                    if *even_odd {
+                        out.push_str(" fill-rule=\"evenodd\"");
+                    }
+                    if let Some((color, width)) = stroke {
+                        out.push_str(&format!(
+                            " stroke=\"{color}\" stroke-width=\"{}\"",
+                            width.max(0.5)
+                        ));
+                    }
+                    out.push_str("/>");
+                }
+                // Image space is the unit square with its first row at the top.
+                Mark::Image { ctm, index } => out.push_str(&format!(
+                    "<image transform=\"{} matrix(1 0 0 -1 0 1)\" width=\"1\" height=\"1\" \
+                     preserveAspectRatio=\"none\" xlink:href=\"{}\"/>",
+                    matrix(ctm),
+                    image_name(page, *index)
+                )),
+            }
+        }
+        out.push_str("</g>");
+        // Each word is stretched to the width it has in the PDF, since the
+        // fonts that draw it have other metrics.
+        for word in words_from_chars(&self.page_chars(page)) {
+            out.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"{:.2}\" font-family=\"serif\" \
+                 textLength=\"{:.2}\" lengthAdjust=\"spacingAndGlyphs\">{}</text>",
+                word.x,
+                word.baseline,
+                word.size,
+                (word.end - word.x).max(1.0),
+                escape(&word.text)
+            ));
+        }
+        out.push_str("</svg>");
+        out.push_str("</body></html>");
+        out
+    }
+}
+
+impl BookInput for PdfInput {
+    fn metadata(&self) -> TrbkMetadata {
+        let language = self
+            .doc
+            .catalog()
+            .ok()
+            .and_then(|catalog| catalog.get(b"Lang").ok())
+            .and_then(|lang| text_string(&self.doc, lang));
+        TrbkMetadata {
+            title: self.title(),
+            author: info_string(&self.doc, b"Author").unwrap_or_else(|| "<unknown>".to_string()),
+            language: language.unwrap_or_else(|| "<unknown>".to_string()),
+            identifier: self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            part: None,
+            series: None,
+        }
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    /// Image pages have no blocks; they are drawn by [`crate::fixed`].
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        if index >= self.spine.len() {
+            return Err(BookError::SpineIndex(index));
+        }
+        match self.mode {
+            PdfMode::Image | PdfMode::Scan => Ok(Vec::new()),
+            PdfMode::Reflow => Ok(self.reflow_blocks(self.page_range(index))),
+        }
+    }
+
+    fn toc(&self) -> Vec<NavEntry> {
+        self.outline
+            .iter()
+            .map(|entry| NavEntry {
+                title: entry.title.clone(),
+                spine_index: self.spine_index_of_page(entry.page),
+                anchor: match self.mode {
+                    PdfMode::Reflow => Some(format!("page-{}", entry.page + 1)),
+                    PdfMode::Image | PdfMode::Scan => None,
+                },
+                level: entry.level,
+            })
+            .collect()
+    }
+
+    /// Images are named `pdf-image-<page>-<n>.png` after the page they are
+    /// drawn on and their order in it.
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        let rest = name.strip_prefix("pdf-image-")?;
+        let (page, rest) = rest.split_once('-')?;
+        let page = page.parse::<usize>().ok()?.checked_sub(1)?;
+        let index = rest.split('.').next()?.parse::<usize>().ok()?;
+        if page >= self.pages.len() {
+            return None;
+        }
+        let (_, images) = self.page_graphics(page);
+        encode_image(&self.doc, images.get(index)?)
+    }
+
+    fn spine_title(&self, index: usize) -> Option<String> {
+        let start = *self.starts.get(index)?;
+        self.outline
+            .iter()
+            .find(|entry| entry.page == start && !entry.title.is_empty())
+            .map(|entry| entry.title.clone())
+            .or_else(|| Some(format!("Page {}", start + 1)))
+    }
+
+    fn is_fixed_layout(&self, _index: usize) -> bool {
+        self.mode != PdfMode::Reflow
+    }
+
+    /// Also used for pages in [`PdfMode::Scan`] that have no image.
+    fn document(&self, index: usize) -> Option<(String, String)> {
+        if self.mode == PdfMode::Reflow || index >= self.pages.len() {
+            return None;
+        }
+        Some((self.page_markup(index), self.spine[index].name.clone()))
+    }
+
+    fn page_images(&self, index: usize) -> Vec<image::GrayImage> {
+        if self.mode != PdfMode::Scan || index >= self.pages.len() {
+            return Vec::new();
+        }
+        match self.scanned_page(index) {
+            Some(page) => prepare_page(page, self.scan),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// A character as `pdf-extract` reports it. `size` and `advance` are in points.
+#[derive(Clone, Debug)]
+struct PdfChar {
+    x: f64,
+    baseline: f64,
+    size: f64,
+    advance: f64,
+    text: String,
+}
+
+#[derive(Default)]
+struct CharCollector {
+    chars: Vec<PdfChar>,
+}
+
+impl OutputDev for CharCollector {
+    fn begin_page(
+        &mut self,
+        _page_num: u32,
+        _media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        spacing: f64,
+        font_size: f64,
+        text: &str,
+    ) -> Result<(), OutputError> {
+        let scale = (trm.m11 * trm.m22 - trm.m12 * trm.m21).abs().sqrt();
+        self.chars.push(PdfChar {
+            x: trm.m31,
+            baseline: trm.m32,
+            size: font_size * scale,
+            advance: (width * font_size + spacing) * trm.m11.hypot(trm.m12),
+            text: text.to_string(),
+        });
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// A run of characters without gaps on one baseline.
+struct Word {
+    x: f64,
+    end: f64,
+    baseline: f64,
+    size: f64,
+    text: String,
+    space_before: bool,
+}
+
+fn words_from_chars(chars: &[PdfChar]) -> Vec<Word> {
+    let mut words: Vec<Word> = Vec::new();
+    let mut space = false;
+    for ch in chars {
+        if ch.text.trim().is_empty() {
+            space = true;
+            continue;
+        }
+        if let Some(word) = words.last_mut() {
+            let same_baseline = (ch.baseline - word.baseline).abs() <= word.size * 0.2;
+            if !space && same_baseline && (ch.x - word.end).abs() <= word.size * 0.3 {
+                word.text.push_str(&ch.text);
+                word.end = ch.x + ch.advance;
+                continue;
+            }
+            space |= same_baseline && ch.x - word.end > word.size * 0.15;
+        }
+        words.push(Word {
+            x: ch.x,
+            end: ch.x + ch.advance,
+            baseline: ch.baseline,
+            size: ch.size,
+            text: ch.text.clone(),
+            space_before: space,
+        });
+        space = false;
+    }
+    words
+}
+
+/// A line of text; `size` is the font size averaged over its characters.
+struct Line {
+    x: f64,
+    end: f64,
+    baseline: f64,
+    size: f64,
+    text: String,
+}
+
+fn lines_from_words(words: &[Word]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut weight = 0.0;
+    for word in words {
+        let chars = word.text.chars().count() as f64;
+        if let Some(line) = lines.last_mut() {
+            let same_line = (word.baseline - line.baseline).abs() <= line.size.max(word.size) * 0.5
+                && word.x >= line.end - line.size * 0.5;
+            if same_line {
+                if word.space_before || word.x - line.end > line.size * 0.15 {
+                    line.text.push(' ');
+                }
+                line.text.push_str(&word.text);
+                line.end = line.end.max(word.end);
+                line.size = (line.size * weight + word.size * chars) / (weight + chars);
+                weight += chars;
+                continue;
+            }
+        }
+        lines.push(Line {
+            x: word.x,
+            end: word.end,
+            baseline: word.baseline,
+            size: word.size,
+            text: word.text.clone(),
+        });
+        weight = chars;
+    }
+    lines
+}
+
+/// Drops a bare number on the first or last line of a page.
+fn drop_page_numbers(mut lines: Vec<Line>) -> Vec<Line> {
+    let is_number = |line: &Line| {
+        let text = line.text.trim();
+        !text.is_empty() && text.len() <= 4 && text.chars().all(|ch| ch.is_ascii_digit())
+    };
+    if lines.last().is_some_and(is_number) {
+        lines.pop();
+    }
+    if lines.first().is_some_and(is_number) {
+        lines.remove(0);
+    }
+    lines
+}
+
+/// The most common font size by character count, to the nearest half point.
+fn body_size<'a>(lines: impl Iterator<Item = &'a Line>) -> f64 {
+    let mut counts: Vec<(i64, usize)> = Vec::new();
+    for line in lines {
+        let key = (line.size * 2.0).round() as i64;
+        let chars = line.text.chars().count();
+        match counts.iter_mut().find(|(size, _)| *size == key) {
+            Some((_, count)) => *count += chars,
+            None => counts.push((key, chars)),
+        }
+    }
+    counts
+        .iter()
+        .max_by_key(|(_, count)| *count)
+        .map_or(12.0, |(size, _)| *size as f64 / 2.0)
+}
+
+/// Whether `line` starts a new paragraph after `previous` on the same page.
+/// `right` is the right edge of the text on the page.
+fn breaks_paragraph(previous: &Line, line: &Line, right: f64) -> bool {
+    let gap = line.baseline - previous.baseline;
+    let size = previous.size.max(line.size);
+    if (line.size - previous.size).abs() > previous.size * 0.1 {
+        return true;
+    }
+    // Moving up the page means a new column or a float.
+    if gap <= 0.0 || gap > size * 1.8 {
+        return true;
+    }
+    // First-line indent.
+    if line.x > previous.x + size * 0.8 {
+        return true;
+    }
+    // A short line that ends a sentence.
+    previous.end < right - size * 4.0 && ends_sentence(&previous.text)
+}
+
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end()
+        .ends_with(['.', '!', '?', ':', '"', '\u{201d}', '\u{2019}', ')'])
+}
+
+struct Paragraph {
+    text: String,
+    size: f64,
+    /// Baseline distance between its lines once it has two.
+    leading: Option<f64>,
+    last_baseline: f64,
+}
+
+struct Reflow {
+    blocks: Vec<HtmlBlock>,
+    paragraph: Option<Paragraph>,
+    body: f64,
+}
+
+impl Reflow {
+    /// Whether the first line of a page carries on the paragraph from the
+    /// previous page.
+    fn continues(&self, line: &Line) -> bool {
+        let Some(paragraph) = &self.paragraph else {
+            return false;
+        };
+        (line.size - paragraph.size).abs() <= paragraph.size * 0.1
+            && !ends_sentence(&paragraph.text)
+    }
+
+    fn append(&mut self, line: &Line) {
+        let Some(paragraph) = self.paragraph.as_mut() else {
+            return;
+        };
+        if paragraph.last_baseline.is_finite() {
+            let gap = line.baseline - paragraph.last_baseline;
+            match paragraph.leading {
+                // Wider spacing than the paragraph so far means a new one.
+                Some(leading) if gap > leading * 1.3 + 1.0 => {
+                    let line_start = Paragraph {
+                        text: line.text.clone(),
+                        size: line.size,
+                        leading: None,
+                        last_baseline: line.baseline,
+                    };
+                    self.flush();
+                    self.paragraph = Some(line_start);
+                    return;
+                }
+                Some(_) => {}
+                None => paragraph.leading = Some(gap),
+            }
+        }
+        paragraph.last_baseline = line.baseline;
+        join_line(&mut paragraph.text, &line.text);
+    }
+
+    fn push_anchor(&mut self, anchor: &mut Option<String>) {
+        if let Some(id) = anchor.take() {
+            self.blocks.push(HtmlBlock::Anchor { id });
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(paragraph) = self.paragraph.take() else {
+            return;
+        };
+        let text = paragraph.text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let ratio = paragraph.size / self.body.max(1.0);
+        let heading_level = if ratio < 1.15 || text.chars().count() > 200 {
+            None
+        } else if ratio >= 1.8 {
+            Some(1)
+        } else if ratio >= 1.4 {
+            Some(2)
+        } else {
+            Some(3)
+        };
+        self.blocks.push(HtmlBlock::Paragraph {
+            runs: vec![TextRun {
+                text: text.to_string(),
+                style: TextStyle::default(),
+                math: None,
+            }],
+            heading_level,
+        });
+    }
+}
+
+/// Appends a line, joining a word hyphenated across the line break.
+fn join_line(text: &mut String, line: &str) {
+    let hyphenated = text.strip_suffix('-').is_some_and(|rest| {
+        rest.chars().last().is_some_and(char::is_alphabetic)
+    }) && line.chars().next().is_some_and(char::is_lowercase);
+    if hyphenated {
+        text.pop();
+    } else if !text.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+/// A painted path or image, in PDF user space with `ctm` applied.
+enum Mark {
+    Path {
+        ctm: Matrix,
+        d: String,
+        fill: Option<String>,
+        stroke: Option<(String, f64)>,
+        even_odd: bool,
+    },
+    Image {
+        ctm: Matrix,
+        index: usize,
+    },
+}
+
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// `m` applied first, then `ctm`, as the `cm` operator does.
+fn concat(m: &Matrix, ctm: &Matrix) -> Matrix {
+    [
+        m[0] * ctm[0] + m[1] * ctm[2],
+        m[0] * ctm[1] + m[1] * ctm[3],
+        m[2] * ctm[0] + m[3] * ctm[2],
+        m[2] * ctm[1] + m[3] * ctm[3],
+        m[4] * ctm[0] + m[5] * ctm[2] + ctm[4],
+        m[4] * ctm[1] + m[5] * ctm[3] + ctm[5],
+    ]
+}
+
+fn apply(m: &Matrix, x: f64, y: f64) -> (f64, f64) {
+    (x * m[0] + y * m[2] + m[4], x * m[1] + y * m[3] + m[5])
+}
+
+fn matrix(m: &Matrix) -> String {
+    format!(
+        "matrix({} {} {} {} {} {})",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Matrix,
+    fill: String,
+    stroke: String,
+    line_width: f64,
+}
+
+struct GraphicsWalker<'a> {
+    doc: &'a Document,
+    marks: Vec<Mark>,
+    images: Vec<&'a Stream>,
+}
+
+impl<'a> GraphicsWalker<'a> {
+    /// Follows the path, colour and XObject operators of a content stream.
+    /// Clipping, shadings and inline images are ignored.
+    fn walk(&mut self, content: &[u8], resources: &'a Dictionary, ctm: Matrix, depth: usize) {
+        let Ok(content) = pdf_extract::content::Content::decode(content) else {
+            return;
+        };
+        let mut state = GraphicsState {
+            ctm,
+            fill: "#000".to_string(),
+            stroke: "#000".to_string(),
+            line_width: 1.0,
+        };
+        let mut stack = Vec::new();
+        let mut d = String::new();
+        let mut current = (0.0, 0.0);
+        for op in &content.operations {
+            let nums = op
+                .operands
+                .iter()
+                .filter_map(|operand| operand.as_float().ok().map(f64::from))
+                .collect::<Vec<_>>();
+            match op.operator.as_str() {
+                "q" => stack.push(state.clone()),
+                "Q" => {
+                    if let Some(saved) = stack.pop() {
+                        state = saved;
+                    }
+                }
+                "cm" if nums.len() == 6 => {
+                    let m = [nums[0], nums[1], nums[2], nums[3], nums[4], nums[5]];
+                    state.ctm = concat(&m, &state.ctm);
+                }
+                "w" if !nums.is_empty() => state.line_width = nums[0],
+                "g" | "rg" | "k" | "sc" | "scn" => {
+                    if let Some(color) = css_color(&nums) {
+                        state.fill = color;
+                    }
+                }
+                "G" | "RG" | "K" | "SC" | "SCN" => {
+                    if let Some(color) = css_color(&nums) {
+                        state.stroke = color;
+                    }
+                }
+                "cs" => state.fill = "#000".to_string(),
+                "CS" => state.stroke = "#000".to_string(),
+                "m" if nums.len() == 2 => {
+                    d.push_str(&format!("M{} {} ", nums[0], nums[1]));
+                    current = (nums[0], nums[1]);
+                }
+                "l" if nums.len() == 2 => {
+                    d.push_str(&format!("L{} {} ", nums[0], nums[1]));
+                    current = (nums[0], nums[1]);
+                }
+                "c" if nums.len() == 6 => {
+                    d.push_str(&format!(
+                        "C{} {} {} {} {} {} ",
+                        nums[0], nums[1], nums[2], nums[3], nums[4], nums[5]
+                    ));
+                    current = (nums[4], nums[5]);
+                }
+                "v" if nums.len() == 4 => {
+                    d.push_str(&format!(
+                        "C{} {} {} {} {} {} ",
+                        current.0, current.1, nums[0], nums[1], nums[2], nums[3]
+                    ));
+                    current = (nums[2], nums[3]);
+                }
+                "y" if nums.len() == 4 => {
+                    d.push_str(&format!(
+                        "C{} {} {} {} {} {} ",
+                        nums[0], nums[1], nums[2], nums[3], nums[2], nums[3]
+                    ));
+                    current = (nums[2], nums[3]);
+                }
+                "h" => d.push_str("Z "),
+                "re" if nums.len() == 4 => {
+                    let (x, y, w, h) = (nums[0], nums[1], nums[2], nums[3]);
+                    d.push_str(&format!("M{x} {y} h{w} v{h} h{} Z ", -w));
+                    current = (x, y);
+                }
+                "f" | "F" | "f*" | "S" | "s" | "B" | "B*" | "b" | "b*" => {
+                    let operator = op.operator.as_str();
+                    if matches!(operator, "s" | "b" | "b*") {
+                        d.push_str("Z ");
+                    }
+                    let fills = !matches!(operator, "S" | "s");
+                    let strokes = matches!(operator, "S" | "s" | "B" | "B*" | "b" | "b*");
+                    if !d.is_empty() {
+                        self.marks.push(Mark::Path {
+                            ctm: state.ctm,
+                            d: std::mem::take(&mut d).trim_end().to_string(),
+                            fill: fills.then(|| state.fill.clone()),
+                            stroke: strokes.then(|| (state.stroke.clone(), state.line_width)),
+                            even_odd: operator.ends_with('*'),
+                        });
+                    }
+                }
+                "n" => d.clear(),
+                "Do" => {
+                    if let Some(name) = op.operands.first().and_then(|name| name.as_name().ok()) {
+                        self.xobject(name, resources, &state, depth);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn xobject(&mut self, name: &[u8], resources: &'a Dictionary, state: &GraphicsState, depth: usize) {
+        let Some(stream) = dict_entry(self.doc, resources, b"XObject")
+            .and_then(|xobjects| xobjects.as_dict().ok())
+            .and_then(|xobjects| dict_entry(self.doc, xobjects, name))
+            .and_then(|object| object.as_stream().ok())
+        else {
+            return;
+        };
+        let subtype = stream.dict.get(b"Subtype").and_then(Object::as_name).unwrap_or_default();
+        if subtype == b"Image" {
+            self.marks.push(Mark::Image {
+                ctm: state.ctm,
+                index: self.images.len(),
+            });
+            self.images.push(stream);
+        } else if subtype == b"Form" && depth < MAX_FORM_DEPTH {
+            let matrix = stream
+                .dict
+                .get(b"Matrix")
+                .and_then(Object::as_array)
+                .ok()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_float().ok().map(f64::from))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|values| values.len() == 6)
+                .map_or(IDENTITY, |v| [v[0], v[1], v[2], v[3], v[4], v[5]]);
+            let form_resources = dict_entry(self.doc, &stream.dict, b"Resources")
+                .and_then(|object| object.as_dict().ok())
+                .unwrap_or(resources);
+            let content = stream
+                .decompressed_content()
+                .unwrap_or_else(|_| stream.content.clone());
+            self.walk(&content, form_resources, concat(&matrix, &state.ctm), depth + 1);
+        }
+    }
+}
+
+/// CSS colour for gray, RGB or CMYK operands; pattern names give `None`.
+fn css_color(values: &[f64]) -> Option<String> {
+    let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let (r, g, b) = match values {
+        [gray] => (channel(*gray), channel(*gray), channel(*gray)),
+        [r, g, b] => (channel(*r), channel(*g), channel(*b)),
+        [c, m, y, k] => (
+            channel((1.0 - c) * (1.0 - k)),
+            channel((1.0 - m) * (1.0 - k)),
+            channel((1.0 - y) * (1.0 - k)),
+        ),
+        _ => return None,
+    };
+    Some(format!("#{r:02x}{g:02x}{b:02x}"))
+}
+
+fn image_name(page: usize, index: usize) -> String {
+    format!("pdf-image-{}-{}.png", page + 1, index)
+}
+
+/// An image XObject as PNG or JPEG bytes. JPEG data is passed through; other
+/// images are unpacked from their colour space and bit depth.
+fn encode_image(doc: &Document, stream: &Stream) -> Option<Vec<u8>> {
+    let filters = stream.filters().unwrap_or_default();
+    if filters.last().is_some_and(|filter| *filter == b"DCTDecode") {
+        if filters.len() == 1 {
+            return Some(stream.content.clone());
+        }
+        return None;
+    }
+    if filters.iter().any(|filter| *filter == b"JPXDecode" || *filter == b"CCITTFaxDecode" || *filter == b"JBIG2Decode") {
+        eprintln!("[tern-book] warning: unsupported PDF image compression, image skipped");
+        return None;
+    }
+    let data = if filters.is_empty() {
+        stream.content.clone()
+    } else {
+        stream.decompressed_content().ok()?
+    };
+    let dict = &stream.dict;
+    let number = |key: &[u8]| {
+        dict.get(key)
+            .ok()
+            .and_then(|value| doc.dereference(value).ok())
+            .and_then(|(_, value)| value.as_i64().ok())
+    };
+    let width = number(b"Width")?.max(0) as u32;
+    let height = number(b"Height")?.max(0) as u32;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let mask = dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false);
+    let bits = if mask { 1 } else { number(b"BitsPerComponent").unwrap_or(8) as u32 };
+    let space = if mask {
+        ColorSpace::Gray
+    } else {
+        dict.get(b"ColorSpace")
+            .ok()
+            .map_or(ColorSpace::Gray, |space| color_space(doc, space))
+    };
+    let components = space.components();
+    let stride = (width as usize * components * bits as usize).div_ceil(8);
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16) || data.len() < stride * height as usize {
+        return None;
+    }
+    let max = ((1u32 << bits.min(8)) - 1) as f32;
+    let mut rgb = image::RgbImage::new(width, height);
+    for (y, row) in data.chunks(stride).take(height as usize).enumerate() {
+        for x in 0..width as usize {
+            let sample = |component: usize| -> u32 {
+                let index = x * components + component;
+                match bits {
+                    16 => row[index * 2] as u32,
+                    8 => row[index] as u32,
+                    _ => {
+                        let bit = index * bits as usize;
+                        ((row[bit / 8] >> (8 - bits as usize - bit % 8)) as u32) & ((1 << bits) - 1)
+                    }
+                }
+            };
+            let scale = |value: u32| (value as f32 * 255.0 / max).round() as u8;
+            let pixel = match &space {
+                ColorSpace::Gray => {
+                    let v = scale(sample(0));
+                    [v, v, v]
+                }
+                ColorSpace::Rgb => [scale(sample(0)), scale(sample(1)), scale(sample(2))],
+                ColorSpace::Cmyk => {
+                    let [c, m, y, k] = [0, 1, 2, 3].map(|i| scale(sample(i)) as f32 / 255.0);
+                    [
+                        ((1.0 - c) * (1.0 - k) * 255.0) as u8,
+                        ((1.0 - m) * (1.0 - k) * 255.0) as u8,
+                        ((1.0 - y) * (1.0 - k) * 255.0) as u8,
+                    ]
+                }
+                ColorSpace::Indexed(palette) => {
+                    let at = sample(0) as usize * 3;
+                    match palette.get(at..at + 3) {
+                        Some(entry) => [entry[0], entry[1], entry[2]],
+                        None => [0, 0, 0],
+                    }
+                }
+            };
+            rgb.put_pixel(x as u32, y as u32, image::Rgb(pixel));
+        }
+    }
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(rgb)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
+
+enum ColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+    /// RGB palette entries.
+    Indexed(Vec<u8>),
+}
+
+impl ColorSpace {
+    fn components(&self) -> usize {
+        match self {
+            ColorSpace::Gray | ColorSpace::Indexed(_) => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+        }
+    }
+}
+
+fn color_space(doc: &Document, object: &Object) -> ColorSpace {
+    let Ok((_, object)) = doc.dereference(object) else {
+        return ColorSpace::Gray;
+    };
+    let (name, params): (&[u8], &[Object]) = match object {
+        Object::Name(name) => (name.as_slice(), &[]),
+        Object::Array(array) => match array.split_first() {
+            Some((first, rest)) => (first.as_name().unwrap_or_default(), rest),
+            None => return ColorSpace::Gray,
+        },
+        _ => return ColorSpace::Gray,
+    };
+    match name {
+        b"DeviceRGB" | b"CalRGB" | b"RGB" => ColorSpace::Rgb,
+        b"DeviceCMYK" | b"CMYK" => ColorSpace::Cmyk,
+        b"ICCBased" => {
+            let components = params
+                .first()
+                .and_then(|stream| doc.dereference(stream).ok())
+                .and_then(|(_, stream)| stream.as_stream().ok())
+                .and_then(|stream| stream.dict.get(b"N").and_then(Object::as_i64).ok());
+            match components {
+                Some(3) => ColorSpace::Rgb,
+                Some(4) => ColorSpace::Cmyk,
+                _ => ColorSpace::Gray,
+            }
+        }
+        b"Indexed" | b"I" => {
+            let base = params.first().map_or(ColorSpace::Rgb, |base| color_space(doc, base));
+            let lookup = params
+                .get(2)
+                .and_then(|lookup| doc.dereference(lookup).ok())
+                .and_then(|(_, lookup)| match lookup {
+                    Object::String(bytes, _) => Some(bytes.clone()),
+                    Object::Stream(stream) => stream
+                        .decompressed_content()
+                        .ok()
+                        .or_else(|| Some(stream.content.clone())),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let palette = match base {
+                ColorSpace::Gray => lookup.iter().flat_map(|v| [*v, *v, *v]).collect(),
+                ColorSpace::Cmyk => lookup
+                    .chunks_exact(4)
+                    .flat_map(|cmyk| {
+                        let [c, m, y, k] = [0, 1, 2, 3].map(|i| cmyk[i] as f32 / 255.0);
+                        [
+                            ((1.0 - c) * (1.0 - k) * 255.0) as u8,
+                            ((1.0 - m) * (1.0 - k) * 255.0) as u8,
+                            ((1.0 - y) * (1.0 - k) * 255.0) as u8,
+                        ]
+                    })
+                    .collect(),
+                _ => lookup,
+            };
+            ColorSpace::Indexed(palette)
+        }
+        _ => ColorSpace::Gray,
+    }
+}
+
+/// Looks `key` up in `dict`, following a reference.
+fn dict_entry<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let value = dict.get(key).ok()?;
+    doc.dereference(value).ok().map(|(_, value)| value)
+}
+
+/// A page attribute that may be set on the page or any parent node.
+fn inherited_dict<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Dictionary> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    for _ in 0..32 {
+        if let Some(value) = dict_entry(doc, node, key).and_then(|value| value.as_dict().ok()) {
+            return Some(value);
+        }
+        node = dict_entry(doc, node, b"Parent")?.as_dict().ok()?;
+    }
+    None
+}
+
+/// The page's media box, inherited like other page attributes.
+fn page_box(doc: &Document, page_id: ObjectId) -> MediaBox {
+    let mut node = doc.get_dictionary(page_id).ok();
+    for _ in 0..32 {
+        let Some(dict) = node else {
+            break;
+        };
+        let values = dict_entry(doc, dict, b"MediaBox")
+            .and_then(|value| value.as_array().ok())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_float().ok().map(f64::from))
+                    .collect::<Vec<_>>()
+            });
+        if let Some([llx, lly, urx, ury]) = values.as_deref() {
+            return MediaBox {
+                llx: llx.min(*urx),
+                lly: lly.min(*ury),
+                urx: urx.max(*llx),
+                ury: ury.max(*lly),
+            };
+        }
+        node = dict_entry(doc, dict, b"Parent").and_then(|parent| parent.as_dict().ok());
+    }
+    // US Letter, the default in most PDF producers.
+    MediaBox {
+        llx: 0.0,
+        lly: 0.0,
+        urx: 612.0,
+        ury: 792.0,
+    }
+}
+
+fn info_string(doc: &Document, key: &[u8]) -> Option<String> {
+    let info = doc.trailer.get(b"Info").ok()?;
+    let (_, info) = doc.dereference(info).ok()?;
+    text_string(doc, info.as_dict().ok()?.get(key).ok()?)
+}
+
+fn text_string(doc: &Document, object: &Object) -> Option<String> {
+    let (_, object) = doc.dereference(object).ok()?;
+    let text = pdf_extract::decode_text_string(object).ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
@@ -0,0 +1,106 @@
+//! Plain text backend: blank lines separate paragraphs, form feeds force a page break.
+
+use std::path::{Path, PathBuf};
+
+use super::{BookInput, InputFormat, SpineItem};
+use crate::blocks::{HtmlBlock, TextRun, TextStyle};
+use crate::{BookError, TrbkMetadata};
+
+pub const FORMAT: InputFormat = InputFormat {
+    name: "txt",
+    extensions: &["txt", "text"],
+    sniff: |_| false,
+    open: |path| Ok(Box::new(TextInput::open(path)?)),
+};
+
+#[derive(Clone, Debug)]
+pub struct TextInput {
+    pub path: PathBuf,
+    text: String,
+    spine: Vec<SpineItem>,
+}
+
+impl TextInput {
+    pub fn open(path: &Path) -> Result<Self, BookError> {
+        let bytes = std::fs::read(path)?;
+        let text = String::from_utf8_lossy(&bytes)
+            .trim_start_matches('\u{feff}')
+            .replace("\r\n", "\n");
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(Self {
+            path: path.to_path_buf(),
+            text,
+            spine: vec![SpineItem { name }],
+        })
+    }
+
+    fn title(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "<unknown>".to_string())
+    }
+}
+
+impl BookInput for TextInput {
+    fn metadata(&self) -> TrbkMetadata {
+        TrbkMetadata {
+            title: self.title(),
+            author: "<unknown>".to_string(),
+            language: "<unknown>".to_string(),
+            identifier: self.spine[0].name.clone(),
+            part: None,
+            series: None,
+        }
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        if index != 0 {
+            return Err(BookError::SpineIndex(index));
+        }
+        let mut blocks = Vec::new();
+        for (page, chunk) in self.text.split('\u{c}').enumerate() {
+            if page > 0 {
+                blocks.push(HtmlBlock::PageBreak);
+            }
+            let mut paragraph = String::new();
+            for line in chunk.lines().map(str::trim) {
+                if line.is_empty() {
+                    push_paragraph(&mut blocks, &mut paragraph);
+                    continue;
+                }
+                if !paragraph.is_empty() {
+                    paragraph.push(' ');
+                }
+                paragraph.push_str(line);
+            }
+            push_paragraph(&mut blocks, &mut paragraph);
+        }
+        Ok(blocks)
+    }
+
+    fn spine_title(&self, _index: usize) -> Option<String> {
+        Some(self.title())
+    }
+}
+
+fn push_paragraph(blocks: &mut Vec<HtmlBlock>, paragraph: &mut String) {
+    if paragraph.is_empty() {
+        return;
+    }
+    blocks.push(HtmlBlock::Paragraph {
+        runs: vec![TextRun {
+            text: std::mem::take(paragraph),
+            style: TextStyle::default(),
+            math: None,
+        }],
+        heading_level: None,
+    });
+}
@@ -0,0 +1,292 @@
+//! Stage 3: wrap blocks into lines and place images.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::blocks::SpineBlocks;
+use crate::fonts::{style_id_from_style, Glyph, StyleId};
+use crate::images::ImageRef;
+use crate::typography::NO_BREAK_SPACES;
+use crate::{math, RenderOptions};
+
+/// Codepoints whose advances are kept in a flat table: Latin, Greek,
+/// Cyrillic and general punctuation.
+const FLAT_CODEPOINTS: usize = 0x2100;
+const STYLE_COUNT: usize = 4;
+/// Flat table entry for a codepoint without a glyph.
+const NO_GLYPH: i16 = i16::MIN;
+
+/// Horizontal advance per (style, codepoint), and the width of every token
+/// measured so far, as layout measures the same words over and over.
+pub struct AdvanceMap {
+    flat: Vec<i16>,
+    other: HashMap<(StyleId, u32), i16>,
+    tokens: [RefCell<HashMap<String, i32>>; STYLE_COUNT],
+}
+
+impl AdvanceMap {
+    pub fn get(&self, style: StyleId, codepoint: u32) -> Option<i16> {
+        let index = codepoint as usize;
+        if index < FLAT_CODEPOINTS {
+            let advance = self.flat[style as usize * FLAT_CODEPOINTS + index];
+            (advance != NO_GLYPH).then_some(advance)
+        } else {
+            self.other.get(&(style, codepoint)).copied()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LayoutItem {
+    /// One line, or one column in vertical layout.
+    TextLine {
+        spine_index: i32,
+        runs: Vec<tern_epub::TextRun>,
+        /// Rendered equations, by index into `runs`.
+        inline_images: Vec<(usize, ImageRef)>,
+    },
+    BlankLine {
+        spine_index: i32,
+    },
+    Image {
+        spine_index: i32,
+        image_index: u16,
+        width: u16,
+        height: u16,
+    },
+    PageBreak {
+        spine_index: i32,
+    },
+    /// Takes no space; marks where an element id starts.
+    Anchor {
+        spine_index: i32,
+        id: String,
+    },
+}
+
+pub fn build_advance_map(glyphs: &[Glyph]) -> AdvanceMap {
+    let mut map = AdvanceMap {
+        flat: vec![NO_GLYPH; STYLE_COUNT * FLAT_CODEPOINTS],
+        other: HashMap::new(),
+        tokens: Default::default(),
+    };
+    for glyph in glyphs {
+        let index = glyph.codepoint as usize;
+        if index < FLAT_CODEPOINTS {
+            map.flat[glyph.style as usize * FLAT_CODEPOINTS + index] = glyph.x_advance;
+        } else {
+            map.other.insert((glyph.style, glyph.codepoint), glyph.x_advance);
+        }
+    }
+    map
+}
+
+/// Wraps paragraphs to the text width. Images missing from `image_map` are
+/// dropped; equations missing from it are laid out as their fallback text.
+pub fn layout_blocks(
+    blocks: &[SpineBlocks],
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+    image_map: &HashMap<String, ImageRef>,
+) -> Vec<LayoutItem> {
+    let max_width = (options.screen_width as i32 - options.margin_x as i32 * 2).max(1);
+    layout_blocks_with(blocks, image_map, |runs| {
+        wrap_paragraph_runs(runs, max_width, options, advance_map, image_map)
+    })
+}
+
+/// Shared block walk; `wrap` splits a paragraph into lines (or columns).
+pub(crate) fn layout_blocks_with(
+    blocks: &[SpineBlocks],
+    image_map: &HashMap<String, ImageRef>,
+    mut wrap: impl FnMut(&[tern_epub::TextRun]) -> Vec<Vec<tern_epub::TextRun>>,
+) -> Vec<LayoutItem> {
+    let mut items = Vec::new();
+    for spine in blocks {
+        let spine_index = spine.spine_index;
+        for block in &spine.blocks {
+            match block {
+                tern_epub::HtmlBlock::Paragraph { runs, .. } => {
+                    let lines = wrap(runs);
+                    for line in lines {
+                        let inline_images = line
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(idx, run)| {
+                                let key = math::math_key(run.math.as_ref()?);
+                                Some((idx, *image_map.get(&key)?))
+                            })
+                            .collect();
+                        items.push(LayoutItem::TextLine {
+                            spine_index,
+                            runs: line,
+                            inline_images,
+                        });
+                    }
+                    items.push(LayoutItem::BlankLine { spine_index });
+                }
+                tern_epub::HtmlBlock::PageBreak => {
+                    items.push(LayoutItem::PageBreak { spine_index });
+                }
+                tern_epub::HtmlBlock::Image { src, .. } => {
+                    if let Some(image) = image_map.get(src) {
+                        items.push(LayoutItem::Image {
+                            spine_index,
+                            image_index: image.index,
+                            width: image.width,
+                            height: image.height,
+                        });
+                        items.push(LayoutItem::BlankLine { spine_index });
+                    }
+                }
+                tern_epub::HtmlBlock::Anchor { id } => {
+                    items.push(LayoutItem::Anchor {
+                        spine_index,
+                        id: id.clone(),
+                    });
+                }
+            }
+        }
+    }
+    items
+}
+
+fn wrap_paragraph_runs(
+    runs: &[tern_epub::TextRun],
+    max_width: i32,
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+    image_map: &HashMap<String, ImageRef>,
+) -> Vec<Vec<tern_epub::TextRun>> {
+    let mut lines = Vec::new();
+    let mut current: Vec<tern_epub::TextRun> = Vec::new();
+    let mut current_width = 0i32;
+    let mut after_math = false;
+
+    for run in runs {
+        let image = run
+            .math
+            .as_ref()
+            .and_then(|math| image_map.get(&math::math_key(math)));
+        if let (Some(math), Some(image)) = (&run.math, image) {
+            let width = image.width as i32;
+            let space_width =
+                measure_token_width(" ", run.style, options, advance_map) + options.word_spacing as i32;
+            // Display equations get a line of their own.
+            if !current.is_empty()
+                && (math.display || current_width + space_width + width > max_width)
+            {
+                lines.push(core::mem::take(&mut current));
+                current_width = 0;
+            }
+            if !current.is_empty() {
+                current.push(tern_epub::TextRun {
+                    text: " ".to_string(),
+                    style: run.style,
+                    math: None,
+                });
+                current_width += space_width;
+            }
+            current.push(run.clone());
+            current_width += width;
+            if math.display {
+                lines.push(core::mem::take(&mut current));
+                current_width = 0;
+            }
+            after_math = true;
+            continue;
+        }
+        // Punctuation straight after an equation stays attached to it.
+        let attached = after_math && !run.text.starts_with(char::is_whitespace);
+        after_math = false;
+        for (idx, token) in split_words(&run.text).enumerate() {
+            let token_width = measure_token_width(token, run.style, options, advance_map);
+            if attached && idx == 0 && current_width > 0 && current_width + token_width <= max_width {
+                current.push(tern_epub::TextRun {
+                    text: token.to_string(),
+                    style: run.style,
+                    math: None,
+                });
+                current_width += token_width;
+                continue;
+            }
+            if current_width == 0 {
+                current.push(tern_epub::TextRun {
+                    text: token.to_string(),
+                    style: run.style,
+                    math: None,
+                });
+                current_width = token_width;
+                continue;
+            }
+            let space_width =
+                measure_token_width(" ", run.style, options, advance_map) + options.word_spacing as i32;
+            if current_width + space_width + token_width <= max_width {
+                current.push(tern_epub::TextRun {
+                    text: " ".to_string(),
+                    style: run.style,
+                    math: None,
+                });
+                current.push(tern_epub::TextRun {
+                    text: token.to_string(),
+                    style: run.style,
+                    math: None,
+                });
+                current_width += space_width + token_width;
+                continue;
+            }
+            lines.push(current);
+            current = Vec::new();
+            current.push(tern_epub::TextRun {
+                text: token.to_string(),
+                style: run.style,
+                math: None,
+            });
+            current_width = token_width;
+        }
+        if run.text.contains('\n') {
+            if !current.is_empty() {
+                lines.push(current);
+                current = Vec::new();
+                current_width = 0;
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+/// Words of `text`, split at whitespace other than no-break spaces.
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|ch: char| ch.is_whitespace() && !NO_BREAK_SPACES.contains(&ch))
+        .filter(|word| !word.is_empty())
+}
+
+/// Width of `text` in `style`. Widths are kept per token, so `options` must
+/// be the same for every call on one `advance_map`.
+pub fn measure_token_width(
+    text: &str,
+    style: tern_epub::TextStyle,
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+) -> i32 {
+    let style_id = style_id_from_style(style);
+    let tokens = &advance_map.tokens[style_id as usize];
+    if let Some(width) = tokens.borrow().get(text) {
+        return *width;
+    }
+    let width = text
+        .chars()
+        .map(|ch| {
+            advance_map
+                .get(style_id, ch as u32)
+                .map_or(options.char_width as i32, i32::from)
+        })
+        .sum();
+    tokens.borrow_mut().insert(text.to_string(), width);
+    width
+}
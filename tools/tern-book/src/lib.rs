@@ -1,11 +1,87 @@
-use std::collections::{BTreeSet, HashMap};
-use std::fs::File;
-use std::io::Write;
+//! Book to TRBK conversion.
+//!
+//! [`convert_book_to_trbk`] and the `convert_epub_to_trbk*` helpers run the
+//! whole pipeline. Each stage is also public so other tools can run part of it
+//! or swap content in between:
+//!
+//! 1. [`input`]: open the source book through a [`input::BookInput`] backend
+//!    (EPUB, MOBI/AZW3, plain text, PDF, DjVu, web articles).
+//! 2. [`blocks`]: parse spine items into paragraphs, images and breaks.
+//! 3. [`layout`]: wrap paragraphs into lines for a font size.
+//! 4. [`paginate`]: place lines and images on pages.
+//! 5. [`serialize`]: encode pages, glyphs, TOC and images as TRBK, optionally
+//!    leaving common glyphs to a shared [`fontpack`] and adding the logical
+//!    text the reader can lay out again ([`reflow`]).
+//!
+//! [`build_book`] runs stages 3 and 4 together with glyph, image, equation
+//! ([`math`]) and TOC generation for one font size. Books in
+//! [`WritingMode::VerticalRl`] use the [`vertical`] layout for stage 3 instead,
+//! where equations stay as text. Pre-paginated spine items skip stages 3 and 4
+//! and become one full-screen image page each ([`fixed`]); scanned pages are
+//! trimmed and split by [`scan`] first. [`library`] finds the books of a
+//! whole folder for batch conversion, and [`analyze`] flags pages likely to be
+//! slow to show on the device.
+//!
+//! ```no_run
+//! use tern_book::blocks::{self, HtmlBlock, SpineBlocks, TextRun, TextStyle};
+//! use tern_book::{build_book, fonts, input, serialize, FontPaths};
+//!
+//! # fn main() -> Result<(), tern_book::BookError> {
+//! let book_input = input::open_input("book.epub".as_ref())?;
+//! let mut spine = blocks::extract_blocks(book_input.as_ref(), 200)?;
+//! // Add a closing page after the last spine item.
+//! spine.push(SpineBlocks {
+//!     spine_index: book_input.spine().len() as i32,
+//!     blocks: vec![HtmlBlock::Paragraph {
+//!         runs: vec![TextRun {
+//!             text: "Converted for TernReader".into(),
+//!             style: TextStyle::default(),
+//!             math: None,
+//!         }],
+//!         heading_level: None,
+//!     }],
+//! });
+//! let fonts = fonts::load_fonts(&FontPaths::default())?;
+//! let book = build_book(book_input.as_ref(), &spine, &fonts, 24)?;
+//! let bytes = serialize::serialize_trbk(&book)?;
+//! # let _ = bytes;
+//! # Ok(())
+//! # }
+//! ```
+
 use std::path::{Path, PathBuf};
 
-use image::GenericImageView;
 use thiserror::Error;
 
+pub mod analyze;
+pub mod blocks;
+pub mod fixed;
+pub mod fontpack;
+pub mod fonts;
+pub mod images;
+pub mod input;
+pub mod layout;
+pub mod library;
+pub mod lock;
+pub mod math;
+pub mod paginate;
+pub mod reflow;
+pub mod scan;
+pub mod serialize;
+pub mod timings;
+pub mod toc;
+pub mod typography;
+pub mod vertical;
+
+pub use fonts::{FontPaths, FontSet, Glyph, StyleId};
+pub use images::{ImageLimits, MissingImage};
+pub use serialize::RenderedBook;
+
+use blocks::{collect_used_codepoints_from_blocks, SpineBlocks};
+use input::BookInput;
+use timings::{Stage, Timings};
+use toc::TrbkTocEntry;
+
 #[derive(Debug, Error)]
 pub enum BookError {
     #[error("io error: {0}")]
@@ -14,6 +90,24 @@ pub enum BookError {
     Epub(#[from] tern_epub::EpubError),
     #[error("invalid output")]
     InvalidOutput,
+    #[error("unsupported input format: {0}")]
+    UnsupportedInput(String),
+    #[error("no spine item {0}")]
+    SpineIndex(usize),
+    #[error("the book is protected by {}; only DRM-free books can be converted", .0.name())]
+    Drm(tern_epub::DrmScheme),
+    #[error("pdf error: {0}")]
+    Pdf(String),
+    #[error("djvu error: {0}")]
+    Djvu(String),
+    #[error("mobi error: {0}")]
+    Mobi(String),
+    #[error("article error: {0}")]
+    Article(String),
+    #[error("unreadable cover image: {0}")]
+    Cover(String),
+    #[error("unreadable layout lock: {0}")]
+    LayoutLock(String),
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +121,16 @@ pub struct RenderOptions {
     pub ascent: i16,
     pub word_spacing: i16,
     pub max_spine_items: usize,
+    pub writing_mode: WritingMode,
+    pub image_limits: ImageLimits,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritingMode {
+    #[default]
+    Horizontal,
+    /// Top-to-bottom columns filled right to left, as in Japanese tategaki.
+    VerticalRl,
 }
 
 impl Default for RenderOptions {
@@ -41,6 +145,8 @@ impl Default for RenderOptions {
             ascent: 14,
             word_spacing: 2,
             max_spine_items: 50,
+            writing_mode: WritingMode::Horizontal,
+            image_limits: ImageLimits::default(),
         }
     }
 }
@@ -51,106 +157,15 @@ pub struct TrbkMetadata {
     pub author: String,
     pub language: String,
     pub identifier: String,
+    pub part: Option<TrbkPart>,
+    pub series: Option<String>,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct FontPaths {
-    pub regular: Option<String>,
-    pub bold: Option<String>,
-    pub italic: Option<String>,
-    pub bold_italic: Option<String>,
-}
-
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
-pub enum StyleId {
-    Regular = 0,
-    Bold = 1,
-    Italic = 2,
-    BoldItalic = 3,
-}
-
-#[derive(Clone, Debug)]
-pub struct Glyph {
-    pub codepoint: u32,
-    pub style: StyleId,
-    pub width: u8,
-    pub height: u8,
-    pub x_advance: i16,
-    pub x_offset: i16,
-    pub y_offset: i16,
-    pub bitmap_bw: Vec<u8>,
-    pub bitmap_lsb: Vec<u8>,
-    pub bitmap_msb: Vec<u8>,
-}
-
-#[derive(Clone, Debug)]
-struct SpineBlocks {
-    spine_index: i32,
-    blocks: Vec<tern_epub::HtmlBlock>,
-}
-
-#[derive(Clone, Debug)]
-enum LayoutItem {
-    TextLine {
-        spine_index: i32,
-        runs: Vec<tern_epub::TextRun>,
-    },
-    BlankLine {
-        spine_index: i32,
-    },
-    Image {
-        spine_index: i32,
-        image_index: u16,
-        width: u16,
-        height: u16,
-    },
-    PageBreak {
-        spine_index: i32,
-    },
-}
-
-#[derive(Clone, Debug)]
-struct PageData {
-    spine_index: i32,
-    ops: Vec<PageOp>,
-}
-
-#[derive(Clone, Debug)]
-enum PageOp {
-    Text {
-        x: u16,
-        y: u16,
-        style: StyleId,
-        text: String,
-    },
-    Image {
-        x: u16,
-        y: u16,
-        width: u16,
-        height: u16,
-        image_index: u16,
-    },
-}
-
-#[derive(Clone, Debug)]
-struct ImageAsset {
-    width: u16,
-    height: u16,
-    data: Vec<u8>,
-}
-
-#[derive(Clone, Copy, Debug)]
-struct ImageRef {
-    index: u16,
-    width: u16,
-    height: u16,
-}
-
-#[derive(Clone, Debug)]
-struct TrbkTocEntry {
-    title: String,
-    page_index: u32,
-    level: u8,
+#[derive(Debug, Clone)]
+pub struct TrbkPart {
+    pub index: u16,
+    pub count: u16,
+    pub next: String,
 }
 
 pub fn convert_epub_to_trbk<P: AsRef<Path>, Q: AsRef<Path>>(
@@ -167,972 +182,321 @@ pub fn convert_epub_to_trbk_multi<P: AsRef<Path>, Q: AsRef<Path>>(
     sizes: &[u16],
     font_paths: &FontPaths,
 ) -> Result<(), BookError> {
-    let epub_path = epub_path.as_ref();
-    let output_path = output_path.as_ref();
-    let cache_dir = tern_epub::default_cache_dir(epub_path);
-    let (cache, _) = tern_epub::load_or_build_cache(epub_path, &cache_dir)?;
+    convert_epub_to_trbk_split(epub_path, output_path, sizes, font_paths, None)
+}
 
-    let metadata = TrbkMetadata {
-        title: cache
-            .metadata
-            .title
-            .as_deref()
-            .unwrap_or("<unknown>")
-            .to_string(),
-        author: cache
-            .metadata
-            .creator
-            .as_deref()
-            .unwrap_or("<unknown>")
-            .to_string(),
-        language: cache
-            .metadata
-            .language
-            .as_deref()
-            .unwrap_or("<unknown>")
-            .to_string(),
-        identifier: cache
-            .metadata
-            .identifier
-            .as_deref()
-            .unwrap_or("<unknown>")
-            .to_string(),
+/// Like `convert_epub_to_trbk_multi`, but books longer than `max_part_pages`
+/// are written as `name.part1.trbk`, `name.part2.trbk`, ... split at chapter
+/// boundaries where possible.
+pub fn convert_epub_to_trbk_split<P: AsRef<Path>, Q: AsRef<Path>>(
+    epub_path: P,
+    output_path: Q,
+    sizes: &[u16],
+    font_paths: &FontPaths,
+    max_part_pages: Option<usize>,
+) -> Result<(), BookError> {
+    let epub = input::EpubInput::open(epub_path.as_ref())?;
+    let options = ConvertOptions {
+        sizes: sizes.to_vec(),
+        font_paths: font_paths.clone(),
+        max_part_pages,
+        ..ConvertOptions::default()
     };
-
-    let spine_blocks = extract_blocks(epub_path, &cache, 200)?;
+    convert_book_to_trbk(&epub, output_path.as_ref(), &options).map(|_| ())
+}
+
+/// How [`convert_book_to_trbk`] writes a book, beyond the layout the input
+/// and font size decide.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Font sizes to write, one file each (`name-<size>.trbk` when there are
+    /// several); empty means size 10.
+    pub sizes: Vec<u16>,
+    pub font_paths: FontPaths,
+    /// Books longer than this are split into parts as
+    /// `convert_epub_to_trbk_split` does.
+    pub max_part_pages: Option<usize>,
+    /// A [`fontpack`] for each size is written here and its glyphs are left
+    /// out of the books.
+    pub font_pack_dir: Option<PathBuf>,
+    /// Single-part horizontal books also carry their text for on-device
+    /// layout (TRBK v3).
+    pub reflow: bool,
+}
+
+/// Converts any [`BookInput`] as `options` say. Returns the pages of each
+/// size likely to be slow to show on the device (see [`analyze`]), the images
+/// left out of the book and the time spent in each stage.
+pub fn convert_book_to_trbk(
+    input: &dyn BookInput,
+    output_path: &Path,
+    options: &ConvertOptions,
+) -> Result<ConversionReport, BookError> {
+    let font_paths = &options.font_paths;
+    let reflow = options.reflow;
+    let mut report = ConversionReport::default();
+    let timings = &mut report.timings;
+    let spine_blocks = timings.time(Stage::Parse, || blocks::extract_blocks(input, 200))?;
     let used = collect_used_codepoints_from_blocks(&spine_blocks);
-    let font_set = load_fonts(font_paths)?;
-    warn_missing_style_fonts(&used, &font_set);
+    let font_set = timings.time(Stage::Glyphs, || fonts::load_fonts(font_paths))?;
+    fonts::warn_missing_style_fonts(&used, &font_set);
 
-    let sizes = if sizes.is_empty() { vec![10] } else { sizes.to_vec() };
+    let sizes = if options.sizes.is_empty() { vec![10] } else { options.sizes.clone() };
     let multi = sizes.len() > 1;
-    for size in &sizes {
-        let mut options = RenderOptions::default();
-        let regular = font_set
-            .get(&StyleId::Regular)
-            .ok_or(BookError::InvalidOutput)?;
-        let (metrics, _) = regular.rasterize('n', *size as f32);
-        options.char_width = metrics.advance_width.round().max(1.0) as u16;
-        let mut codepoints = used
-            .get(&StyleId::Regular)
-            .cloned()
-            .unwrap_or_default();
-        if codepoints.is_empty() {
-            for set in used.values() {
-                codepoints.extend(set.iter().copied());
-            }
-        }
-        let ascent = compute_ascent(regular, *size, &codepoints);
-        options.ascent = ascent;
-        if let Some(lines) = regular.horizontal_line_metrics(*size as f32) {
-            let height = (lines.ascent - lines.descent + lines.line_gap)
-                .ceil()
-                .max(1.0) as u16;
-            let extra = (height / 6).max(2);
-            options.line_height = height.saturating_add(extra);
-        } else {
-            options.line_height = size.saturating_mul(2);
-        }
-        options.word_spacing = (options.char_width as i16 / 3).max(2);
+    for (index, size) in sizes.iter().enumerate() {
         let output = output_path_for_size(output_path, *size, multi);
         if let Some(parent) = output.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        let glyphs = build_glyphs(&font_set, *size, &used)?;
-        let advance_map = build_advance_map(&glyphs);
-        let (image_assets, image_map) = build_image_assets(epub_path, &spine_blocks, &options)?;
-        let items = layout_blocks(&spine_blocks, &options, &advance_map, &image_map);
-        let pages = paginate_items(&items, &options, &advance_map);
-        let spine_to_page = compute_spine_page_map(&pages, cache.spine.len());
-        let toc_entries = build_toc_entries(epub_path, &cache, &spine_to_page);
-        write_trbk(
-            &output,
-            &metadata,
-            &options,
-            &pages,
-            &glyphs,
-            &toc_entries,
-            &image_assets,
-        )?;
-    }
-
-    Ok(())
-}
-
-fn extract_blocks(
-    epub_path: &Path,
-    cache: &tern_epub::BookCache,
-    max_spine_items: usize,
-) -> Result<Vec<SpineBlocks>, BookError> {
-    let mut out = Vec::new();
-    let max_try = cache.spine.len().min(max_spine_items).max(1);
-    let opf_dir = tern_epub::opf_base_dir(&cache.opf_path);
-    for index in 0..max_try {
-        let xhtml = match tern_epub::read_spine_xhtml(epub_path, index) {
-            Ok(xhtml) => xhtml,
-            Err(_) => continue,
-        };
-        let mut blocks = match tern_epub::parse_xhtml_blocks(&xhtml) {
-            Ok(blocks) => blocks,
-            Err(_) => continue,
-        };
-        let spine_href = cache
-            .spine
-            .get(index)
-            .map(|entry| entry.href.clone())
-            .unwrap_or_default();
-        let mut spine_path = strip_fragment(&spine_href);
-        if spine_path.starts_with('/') {
-            spine_path = spine_path.trim_start_matches('/').to_string();
-        }
-        let spine_path = if !opf_dir.is_empty() && spine_path.starts_with(&opf_dir) {
-            spine_path
-        } else {
-            tern_epub::resolve_href(&opf_dir, &spine_path)
-        };
-        let spine_path = collapse_double_prefix(&normalize_path(&spine_path), &opf_dir);
-        let spine_dir = tern_epub::opf_base_dir(&spine_path);
-        for block in &mut blocks {
-            if let tern_epub::HtmlBlock::Image { src, .. } = block {
-                let mut cleaned = strip_fragment(src);
-                if cleaned.starts_with('/') {
-                    cleaned = cleaned.trim_start_matches('/').to_string();
-                }
-                let resolved = if !opf_dir.is_empty() && cleaned.starts_with(&opf_dir) {
-                    cleaned
-                } else {
-                    tern_epub::resolve_href(&spine_dir, &cleaned)
-                };
-                let resolved = collapse_double_prefix(&normalize_path(&resolved), &opf_dir);
-                *src = resolved;
-            }
-        }
-        if !blocks.is_empty() {
-            out.push(SpineBlocks {
-                spine_index: index as i32,
-                blocks,
-            });
-        }
-        if out.len() > 500 {
-            break;
-        }
-    }
-    Ok(out)
-}
-
-fn collect_used_codepoints_from_blocks(
-    blocks: &[SpineBlocks],
-) -> HashMap<StyleId, BTreeSet<u32>> {
-    let mut used: HashMap<StyleId, BTreeSet<u32>> = HashMap::new();
-    for spine in blocks {
-        for block in &spine.blocks {
-            if let tern_epub::HtmlBlock::Paragraph { runs, .. } = block {
-                for run in runs {
-                    let style = style_id_from_style(run.style);
-                    let entry = used.entry(style).or_default();
-                    for ch in run.text.chars() {
-                        entry.insert(ch as u32);
-                    }
-                }
-            }
-        }
-    }
-    used
-}
-
-fn build_image_assets(
-    epub_path: &Path,
-    blocks: &[SpineBlocks],
-    options: &RenderOptions,
-) -> Result<(Vec<ImageAsset>, HashMap<String, ImageRef>), BookError> {
-    let mut assets: Vec<ImageAsset> = Vec::new();
-    let mut map: HashMap<String, ImageRef> = HashMap::new();
-
-    for spine in blocks {
-        for block in &spine.blocks {
-            let tern_epub::HtmlBlock::Image { src, .. } = block else {
-                continue;
-            };
-            if map.contains_key(src) {
-                continue;
-            }
-            let mut candidates = Vec::new();
-            let mut candidate = strip_fragment(src);
-            candidates.push(normalize_path(&candidate));
-            let decoded = percent_decode(src);
-            if decoded != *src {
-                candidate = strip_fragment(&decoded);
-                candidates.push(normalize_path(&candidate));
-            }
-            let mut bytes = None;
-            for candidate in candidates.iter().filter(|c| !c.is_empty()) {
-                match tern_epub::read_epub_resource_bytes(epub_path, candidate) {
-                    Ok(data) => {
-                        bytes = Some(data);
-                        break;
-                    }
-                    Err(_) => {}
-                }
-            }
-            let Some(bytes) = bytes else {
-                eprintln!("[tern-book] warning: image not found in epub: {src}");
-                continue;
-            };
-            let dyn_image = match image::load_from_memory(&bytes) {
-                Ok(img) => img,
-                Err(_) => {
-                    eprintln!("[tern-book] warning: failed to decode image: {src}");
-                    continue;
-                }
-            };
-            let (src_w, src_h) = dyn_image.dimensions();
-            let max_w = options.screen_width.max(1) as u32;
-            let max_h =
-                (options.screen_height as i32 - options.margin_y as i32 * 2).max(1) as u32;
-            let mut scale = if src_w >= max_w {
-                max_w as f64 / src_w.max(1) as f64
+        let mut book = render_book(input, &spine_blocks, &font_set, *size, reflow, &mut report.timings)?;
+        if reflow && book.text.is_none() {
+            eprintln!("[tern-book] warning: reflow needs horizontal flowing text; writing prerendered pages only");
+        }
+        if let Some(dir) = &options.font_pack_dir {
+            if book.options.writing_mode == WritingMode::Horizontal {
+                let timings = &mut report.timings;
+                let pack = timings.time(Stage::Glyphs, || fontpack::build_font_pack(&font_set, font_paths, *size))?;
+                let path = timings.time(Stage::Write, || fontpack::write_font_pack(dir, &pack))?;
+                eprintln!("[tern-book] font pack: {}", path.display());
+                book.font_pack = Some(pack.reference());
             } else {
-                let up = max_w as f64 / src_w.max(1) as f64;
-                up.min(2.0)
-            };
-            let max_scale_h = max_h as f64 / src_h.max(1) as f64;
-            if scale > max_scale_h {
-                scale = max_scale_h;
+                eprintln!(
+                    "[tern-book] warning: font packs only cover horizontal text; embedding all glyphs"
+                );
             }
-            let target_w = (src_w as f64 * scale).round().max(1.0) as u32;
-            let target_h = (src_h as f64 * scale).round().max(1.0) as u32;
-            let mut convert = tern_image::ConvertOptions::default();
-            convert.width = target_w;
-            convert.height = target_h;
-            convert.fit = tern_image::FitMode::Contain;
-            convert.dither = tern_image::DitherMode::Bayer;
-            convert.region_mode = tern_image::RegionMode::None;
-            convert.invert = false;
-            convert.debug = false;
-            convert.yolo_model = None;
-            convert.trimg_version = 2;
-            let trimg = tern_image::convert_image(&dyn_image, convert);
-            let data = trimg_to_bytes(&trimg);
-            let index = assets.len() as u16;
-            let image_ref = ImageRef {
-                index,
-                width: trimg.width as u16,
-                height: trimg.height as u16,
-            };
-            assets.push(ImageAsset {
-                width: image_ref.width,
-                height: image_ref.height,
-                data,
-            });
-            map.insert(src.clone(), image_ref);
         }
-    }
-
-    Ok((assets, map))
-}
-
-fn strip_fragment(path: &str) -> String {
-    let mut end = path.len();
-    for (idx, ch) in path.char_indices() {
-        if ch == '#' || ch == '?' {
-            end = idx;
-            break;
+        eprintln!("[tern-book] glyphs at size {size}:");
+        for line in serialize::glyph_report(&book.glyphs, book.font_pack.as_ref()) {
+            eprintln!("[tern-book]   {line}");
         }
-    }
-    path[..end].to_string()
-}
-
-fn normalize_path(path: &str) -> String {
-    let mut parts: Vec<&str> = Vec::new();
-    for part in path.split('/') {
-        if part.is_empty() || part == "." {
-            continue;
+        report.slow_pages.extend(analyze::slow_pages(&book, *size));
+        report.layout.push(lock::LayoutMetrics::of_book(&book, *size));
+        if index == 0 {
+            report.missing_images = std::mem::take(&mut book.missing_images);
         }
-        if part == ".." {
-            if !parts.is_empty() {
-                parts.pop();
+        let parts = match options.max_part_pages {
+            Some(max_pages) if max_pages > 0 && book.pages.len() > max_pages => {
+                split_into_parts(&book, max_pages, &output)
             }
+            _ => Vec::new(),
+        };
+        if parts.len() <= 1 {
+            report.timings.time(Stage::Write, || serialize::write_trbk(&output, &book))?;
             continue;
         }
-        parts.push(part);
-    }
-    parts.join("/")
-}
-
-fn collapse_double_prefix(path: &str, prefix: &str) -> String {
-    if prefix.is_empty() {
-        return path.to_string();
-    }
-    let prefix = prefix.trim_end_matches('/');
-    let double = format!("{}/{}", prefix, prefix);
-    if path.starts_with(&double) {
-        format!("{}/{}", prefix, path[double.len()..].trim_start_matches('/'))
-    } else {
-        path.to_string()
-    }
-}
-
-fn percent_decode(input: &str) -> String {
-    let mut out = Vec::with_capacity(input.len());
-    let bytes = input.as_bytes();
-    let mut i = 0usize;
-    while i < bytes.len() {
-        if bytes[i] == b'%' && i + 2 < bytes.len() {
-            let hi = bytes[i + 1];
-            let lo = bytes[i + 2];
-            if let (Some(hi), Some(lo)) = (hex_val(hi), hex_val(lo)) {
-                out.push((hi << 4) | lo);
-                i += 3;
-                continue;
-            }
-        }
-        out.push(bytes[i]);
-        i += 1;
-    }
-    String::from_utf8_lossy(&out).to_string()
-}
-
-fn hex_val(byte: u8) -> Option<u8> {
-    match byte {
-        b'0'..=b'9' => Some(byte - b'0'),
-        b'a'..=b'f' => Some(byte - b'a' + 10),
-        b'A'..=b'F' => Some(byte - b'A' + 10),
-        _ => None,
-    }
-}
-
-fn layout_blocks(
-    blocks: &[SpineBlocks],
-    options: &RenderOptions,
-    advance_map: &HashMap<(StyleId, u32), i16>,
-    image_map: &HashMap<String, ImageRef>,
-) -> Vec<LayoutItem> {
-    let max_width = (options.screen_width as i32 - options.margin_x as i32 * 2).max(1);
-    let mut items = Vec::new();
-    for spine in blocks {
-        let spine_index = spine.spine_index;
-        for block in &spine.blocks {
-            match block {
-                tern_epub::HtmlBlock::Paragraph { runs, .. } => {
-                    let lines = wrap_paragraph_runs(runs, max_width, options, advance_map);
-                    for line in lines {
-                        items.push(LayoutItem::TextLine {
-                            spine_index,
-                            runs: line,
-                        });
-                    }
-                    items.push(LayoutItem::BlankLine { spine_index });
-                }
-                tern_epub::HtmlBlock::PageBreak => {
-                    items.push(LayoutItem::PageBreak { spine_index });
-                }
-                tern_epub::HtmlBlock::Image { src, .. } => {
-                    if let Some(image) = image_map.get(src) {
-                        items.push(LayoutItem::Image {
-                            spine_index,
-                            image_index: image.index,
-                            width: image.width,
-                            height: image.height,
-                        });
-                        items.push(LayoutItem::BlankLine { spine_index });
-                    }
-                }
-            }
-        }
-    }
-    items
-}
-
-fn wrap_paragraph_runs(
-    runs: &[tern_epub::TextRun],
-    max_width: i32,
-    options: &RenderOptions,
-    advance_map: &HashMap<(StyleId, u32), i16>,
-) -> Vec<Vec<tern_epub::TextRun>> {
-    let mut lines = Vec::new();
-    let mut current: Vec<tern_epub::TextRun> = Vec::new();
-    let mut current_width = 0i32;
-
-    for run in runs {
-        for token in run.text.split_whitespace() {
-            let token_width = measure_token_width(token, run.style, options, advance_map);
-            if current_width == 0 {
-                current.push(tern_epub::TextRun {
-                    text: token.to_string(),
-                    style: run.style,
-                });
-                current_width = token_width;
-                continue;
-            }
-            let space_width =
-                measure_token_width(" ", run.style, options, advance_map) + options.word_spacing as i32;
-            if current_width + space_width + token_width <= max_width {
-                current.push(tern_epub::TextRun {
-                    text: " ".to_string(),
-                    style: run.style,
-                });
-                current.push(tern_epub::TextRun {
-                    text: token.to_string(),
-                    style: run.style,
-                });
-                current_width += space_width + token_width;
-                continue;
-            }
-            lines.push(current);
-            current = Vec::new();
-            current.push(tern_epub::TextRun {
-                text: token.to_string(),
-                style: run.style,
-            });
-            current_width = token_width;
+        if book.text.is_some() {
+            eprintln!("[tern-book] warning: split parts are written without reflow text");
         }
-        if run.text.contains('\n') {
-            if !current.is_empty() {
-                lines.push(current);
-                current = Vec::new();
-                current_width = 0;
-            }
+        for (path, part) in &parts {
+            report.timings.time(Stage::Write, || serialize::write_trbk(path, part))?;
         }
+        eprintln!(
+            "[tern-book] split {} pages into {} parts",
+            book.pages.len(),
+            parts.len()
+        );
     }
 
-    if !current.is_empty() {
-        lines.push(current);
-    }
-
-    lines
+    Ok(report)
 }
 
-fn paginate_items(
-    items: &[LayoutItem],
-    options: &RenderOptions,
-    advance_map: &HashMap<(StyleId, u32), i16>,
-) -> Vec<PageData> {
-    let mut pages = Vec::new();
-    let mut ops: Vec<PageOp> = Vec::new();
-    let mut spine_index = -1i32;
-    let mut cursor_y = options.margin_y as i32;
-    let max_y = (options.screen_height as i32 - options.margin_y as i32).max(1);
-    let line_height = options.line_height as i32;
-    let image_spacing = (options.line_height as i32 / 2).max(0);
-
-    let flush_page = |pages: &mut Vec<PageData>, ops: &mut Vec<PageOp>, spine_index: &mut i32, cursor_y: &mut i32| {
-        if !ops.is_empty() {
-            pages.push(PageData {
-                spine_index: *spine_index,
-                ops: core::mem::take(ops),
-            });
-            *spine_index = -1;
-            *cursor_y = options.margin_y as i32;
-        }
-    };
-
-    for item in items {
-        let item_spine = match item {
-            LayoutItem::TextLine { spine_index, .. } => *spine_index,
-            LayoutItem::BlankLine { spine_index } => *spine_index,
-            LayoutItem::Image { spine_index, .. } => *spine_index,
-            LayoutItem::PageBreak { spine_index } => *spine_index,
-        };
-
-        if spine_index >= 0
-            && item_spine >= 0
-            && item_spine != spine_index
-            && !ops.is_empty()
-        {
-            flush_page(&mut pages, &mut ops, &mut spine_index, &mut cursor_y);
-        }
-
-        if spine_index < 0 {
-            spine_index = item_spine;
-        }
-
-        match item {
-            LayoutItem::PageBreak { .. } => {
-                flush_page(&mut pages, &mut ops, &mut spine_index, &mut cursor_y);
-            }
-            LayoutItem::BlankLine { .. } => {
-                if cursor_y + line_height > max_y {
-                    flush_page(&mut pages, &mut ops, &mut spine_index, &mut cursor_y);
-                }
-                cursor_y += line_height;
-            }
-            LayoutItem::TextLine { runs, .. } => {
-                if cursor_y + line_height > max_y {
-                    flush_page(&mut pages, &mut ops, &mut spine_index, &mut cursor_y);
-                }
-                let baseline = cursor_y + options.ascent as i32;
-                let mut pen_x = options.margin_x as i32;
-                for run in runs {
-                    let style_id = style_id_from_style(run.style);
-                    ops.push(PageOp::Text {
-                        x: pen_x as u16,
-                        y: baseline as u16,
-                        style: style_id,
-                        text: run.text.clone(),
-                    });
-                    let mut adv = measure_token_width(&run.text, run.style, options, advance_map);
-                    if run.text == " " {
-                        adv += options.word_spacing as i32;
-                    }
-                    pen_x += adv;
-                }
-                cursor_y += line_height;
-            }
-            LayoutItem::Image {
-                image_index,
-                width,
-                height,
-                ..
-            } => {
-                let img_h = *height as i32;
-                if cursor_y + img_h > max_y {
-                    flush_page(&mut pages, &mut ops, &mut spine_index, &mut cursor_y);
-                }
-                ops.push(PageOp::Image {
-                    x: 0,
-                    y: cursor_y as u16,
-                    width: *width,
-                    height: *height,
-                    image_index: *image_index,
-                });
-                cursor_y += img_h + image_spacing;
-            }
-        }
-    }
-
-    if !ops.is_empty() {
-        pages.push(PageData {
-            spine_index,
-            ops,
-        });
-    }
-    if pages.is_empty() {
-        pages.push(PageData {
-            spine_index: -1,
-            ops: vec![PageOp::Text {
-                x: options.margin_x,
-                y: (options.margin_y as i32 + options.ascent as i32) as u16,
-                style: StyleId::Regular,
-                text: "(empty)".to_string(),
-            }],
-        });
-    }
-    pages
+/// What [`convert_book_to_trbk`] found worth telling the user about.
+#[derive(Clone, Debug, Default)]
+pub struct ConversionReport {
+    pub slow_pages: Vec<analyze::SlowPage>,
+    /// Images that are shown as a placeholder, the same at every size.
+    pub missing_images: Vec<MissingImage>,
+    /// Layout of each size, for `--lock-layout`.
+    pub layout: Vec<lock::LayoutMetrics>,
+    pub timings: Timings,
+}
+
+/// Lays out and paginates `blocks` at one font size and collects the glyphs,
+/// images and TOC the pages need.
+pub fn build_book(
+    input: &dyn BookInput,
+    blocks: &[SpineBlocks],
+    fonts: &FontSet,
+    size: u16,
+) -> Result<RenderedBook, BookError> {
+    render_book(input, blocks, fonts, size, false, &mut Timings::default())
 }
 
-fn build_advance_map(glyphs: &[Glyph]) -> HashMap<(StyleId, u32), i16> {
-    let mut map = HashMap::new();
-    for glyph in glyphs {
-        map.insert((glyph.style, glyph.codepoint), glyph.x_advance);
-    }
-    map
+/// Like [`build_book`], but also keeps the book's text as [`reflow`] items so
+/// the reader can lay it out for other margins and text sizes. Books in
+/// [`WritingMode::VerticalRl`] or without flowing text get no text section.
+pub fn build_reflowable_book(
+    input: &dyn BookInput,
+    blocks: &[SpineBlocks],
+    fonts: &FontSet,
+    size: u16,
+) -> Result<RenderedBook, BookError> {
+    render_book(input, blocks, fonts, size, true, &mut Timings::default())
 }
 
-fn compute_ascent(font: &fontdue::Font, size: u16, codepoints: &BTreeSet<u32>) -> i16 {
-    let mut cap_ascent = 0i16;
-    let mut ascent = 0i16;
-    for cp in codepoints {
-        if let Some(ch) = char::from_u32(*cp) {
-            let (metrics, _) = font.rasterize(ch, size as f32);
-            let candidate = (metrics.ymin + metrics.height as i32).max(0) as i16;
-            if ch.is_ascii_uppercase() && candidate > cap_ascent {
-                cap_ascent = candidate;
-            }
-            if candidate > ascent {
-                ascent = candidate;
-            }
-        }
-    }
-    let picked = if cap_ascent > 0 { cap_ascent } else { ascent };
-    if picked == 0 {
-        size as i16
+fn render_book(
+    input: &dyn BookInput,
+    blocks: &[SpineBlocks],
+    fonts: &FontSet,
+    size: u16,
+    with_text: bool,
+    timings: &mut Timings,
+) -> Result<RenderedBook, BookError> {
+    // Pre-paginated spine items get a page each from `fixed`; the rest is laid out.
+    let has_fixed = (0..input.spine().len()).any(|index| input.is_fixed_layout(index));
+    let flowing: Vec<SpineBlocks>;
+    let blocks = if has_fixed {
+        flowing = blocks
+            .iter()
+            .filter(|spine| !input.is_fixed_layout(spine.spine_index as usize))
+            .cloned()
+            .collect();
+        &flowing[..]
     } else {
-        picked
-    }
-}
-
-fn measure_token_width(
-    text: &str,
-    style: tern_epub::TextStyle,
-    options: &RenderOptions,
-    advance_map: &HashMap<(StyleId, u32), i16>,
-) -> i32 {
-    let mut width = 0i32;
-    let style_id = style_id_from_style(style);
-    for ch in text.chars() {
-        let cp = ch as u32;
-        if let Some(adv) = advance_map.get(&(style_id, cp)) {
-            width += *adv as i32;
-        } else {
-            width += options.char_width as i32;
-        }
-    }
-    width
-}
-
-fn warn_missing_style_fonts(
-    used: &HashMap<StyleId, BTreeSet<u32>>,
-    fonts: &HashMap<StyleId, fontdue::Font>,
-) {
-    let warn = |style: StyleId, label: &str| {
-        if used.get(&style).map_or(false, |set| !set.is_empty()) && !fonts.contains_key(&style) {
-            eprintln!(
-                "[tern-book] warning: {label} text found but no {label} font was loaded; using regular"
-            );
-        }
+        blocks
     };
-    warn(StyleId::Bold, "bold");
-    warn(StyleId::Italic, "italic");
-    warn(StyleId::BoldItalic, "bold-italic");
-}
-
-fn compute_spine_page_map(pages: &[PageData], spine_count: usize) -> Vec<i32> {
-    let mut map = vec![-1i32; spine_count];
-    for (page_idx, page) in pages.iter().enumerate() {
-        if page.spine_index >= 0 {
-            let spine = page.spine_index as usize;
-            if spine < map.len() && map[spine] < 0 {
-                map[spine] = page_idx as i32;
+    let used = collect_used_codepoints_from_blocks(blocks);
+    let (options, glyphs) = timings.time(Stage::Glyphs, || {
+        let mut options = fonts::options_for_size(fonts, size, &used)?;
+        options.writing_mode = input.writing_mode();
+        options.image_limits = input.image_limits();
+        let glyphs = match options.writing_mode {
+            WritingMode::Horizontal => fonts::build_glyphs(fonts, size, &used)?,
+            WritingMode::VerticalRl => {
+                fonts::build_glyphs(fonts, size, &vertical::vertical_codepoints(fonts, &used))?
             }
-        }
-    }
-    map
-}
-
-fn build_toc_entries(
-    epub_path: &Path,
-    cache: &tern_epub::BookCache,
-    spine_to_page: &[i32],
-) -> Vec<TrbkTocEntry> {
-    let mut spine_titles: HashMap<usize, String> = HashMap::new();
-    let mut fetch_spine_title = |spine: usize| -> Option<String> {
-        if let Some(title) = spine_titles.get(&spine) {
-            return Some(title.clone());
-        }
-        let title = title_from_spine(epub_path, spine);
-        if let Some(ref value) = title {
-            spine_titles.insert(spine, value.clone());
-        }
-        title
+        };
+        Ok::<_, BookError>((options, glyphs))
+    })?;
+    let advance_map = layout::build_advance_map(&glyphs);
+    let (mut images, mut image_map, missing_images) =
+        timings.time(Stage::Images, || images::build_image_assets(input, blocks, &options))?;
+    let placeheld;
+    let blocks = if missing_images.is_empty() {
+        blocks
+    } else {
+        placeheld = images::with_placeholders(blocks, &missing_images);
+        &placeheld[..]
     };
-
-    let mut entries = Vec::new();
-    for entry in &cache.toc {
-        if entry.spine_index < 0 {
-            continue;
-        }
-        let spine = entry.spine_index as usize;
-        if spine >= spine_to_page.len() {
-            continue;
-        }
-        let page_index = spine_to_page[spine];
-        if page_index < 0 {
-            continue;
-        }
-        let mut title = entry.title.clone();
-        if is_bad_toc_title(&title) {
-            if let Some(spine_title) = fetch_spine_title(spine) {
-                title = spine_title;
-            }
-        }
-        entries.push(TrbkTocEntry {
-            title,
-            page_index: page_index as u32,
-            level: entry.level,
+    if options.writing_mode == WritingMode::Horizontal {
+        timings.time(Stage::Images, || {
+            math::build_math_assets(blocks, fonts, size, &options, &mut images, &mut image_map)
         });
     }
-    if entries.is_empty() {
-        for (idx, spine) in cache.spine.iter().enumerate() {
-            let page_index = spine_to_page.get(idx).copied().unwrap_or(-1);
-            if page_index < 0 {
-                continue;
-            }
-            let title = fetch_spine_title(idx).unwrap_or_else(|| {
-                spine
-                    .href
-                    .split('/')
-                    .last()
-                    .unwrap_or("Chapter")
-                    .to_string()
-            });
-            entries.push(TrbkTocEntry {
-                title,
-                page_index: page_index as u32,
-                level: 0,
-            });
-        }
-    }
-    entries
-}
-
-fn is_bad_toc_title(title: &str) -> bool {
-    let trimmed = title.trim();
-    if trimmed.is_empty() {
-        return true;
-    }
-    let lower = trimmed.to_ascii_lowercase();
-    if lower.contains(".xhtml") || lower.contains(".html") || lower.contains(".htm") {
-        return true;
-    }
-    false
-}
-
-fn title_from_spine(epub_path: &Path, spine_index: usize) -> Option<String> {
-    let xhtml = tern_epub::read_spine_xhtml(epub_path, spine_index).ok()?;
-    if let Ok(blocks) = tern_epub::parse_xhtml_blocks(&xhtml) {
-        if let Some(title) = title_from_blocks(&blocks) {
-            return Some(title);
-        }
-    }
-    title_from_title_tag(&xhtml)
-}
-
-fn title_from_blocks(blocks: &[tern_epub::HtmlBlock]) -> Option<String> {
-    for block in blocks {
-        if let tern_epub::HtmlBlock::Paragraph {
-            runs,
-            heading_level: Some(_),
-        } = block
-        {
-            if let Some(text) = text_from_runs(runs) {
-                return Some(text);
-            }
-        }
-    }
-    for block in blocks {
-        if let tern_epub::HtmlBlock::Paragraph { runs, .. } = block {
-            if let Some(text) = text_from_runs(runs) {
-                return Some(text);
-            }
-        }
-    }
-    for block in blocks {
-        if let tern_epub::HtmlBlock::Image { alt: Some(alt), .. } = block {
-            let cleaned = normalize_title(alt);
-            if !cleaned.is_empty() {
-                return Some(cleaned);
-            }
-        }
-    }
-    None
-}
-
-fn text_from_runs(runs: &[tern_epub::TextRun]) -> Option<String> {
-    let mut out = String::new();
-    for run in runs {
-        out.push_str(&run.text);
-    }
-    let cleaned = normalize_title(&out);
-    if cleaned.is_empty() {
-        None
+    let with_text = with_text && options.writing_mode == WritingMode::Horizontal && !blocks.is_empty();
+    let marked;
+    let layout_input = if with_text {
+        marked = reflow::mark_blocks(blocks);
+        &marked[..]
     } else {
-        Some(cleaned)
-    }
-}
-
-fn title_from_title_tag(xhtml: &str) -> Option<String> {
-    let lower = xhtml.to_ascii_lowercase();
-    let start = lower.find("<title")?;
-    let gt = lower[start..].find('>')?;
-    let title_start = start + gt + 1;
-    let end = lower[title_start..].find("</title>")?;
-    let raw = &xhtml[title_start..title_start + end];
-    let cleaned = normalize_title(raw);
-    if cleaned.is_empty() {
-        None
-    } else {
-        Some(cleaned)
-    }
-}
-
-fn normalize_title(input: &str) -> String {
-    let mut out = String::new();
-    let mut in_tag = false;
-    let mut last_space = false;
-    let mut chars = input.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '<' => in_tag = true,
-            '>' => in_tag = false,
-            '&' if !in_tag => {
-                let mut entity = String::new();
-                while let Some(&c) = chars.peek() {
-                    chars.next();
-                    if c == ';' {
-                        break;
-                    }
-                    entity.push(c);
-                }
-                let decoded = match entity.as_str() {
-                    "amp" => Some('&'),
-                    "lt" => Some('<'),
-                    "gt" => Some('>'),
-                    "quot" => Some('"'),
-                    "#39" => Some('\''),
-                    "nbsp" => Some(' '),
-                    _ => None,
-                };
-                if let Some(decoded) = decoded {
-                    if decoded.is_whitespace() {
-                        if !last_space {
-                            out.push(' ');
-                            last_space = true;
-                        }
-                    } else {
-                        out.push(decoded);
-                        last_space = false;
-                    }
-                }
+        blocks
+    };
+    let mut pages = timings.time(Stage::Layout, || {
+        let items = match options.writing_mode {
+            WritingMode::Horizontal => {
+                layout::layout_blocks(layout_input, &options, &advance_map, &image_map)
             }
-            _ if !in_tag => {
-                if ch.is_whitespace() {
-                    if !last_space {
-                        out.push(' ');
-                        last_space = true;
-                    }
-                } else {
-                    out.push(ch);
-                    last_space = false;
-                }
+            WritingMode::VerticalRl => {
+                vertical::layout_blocks_vertical(blocks, &options, &advance_map, &image_map, fonts)
             }
-            _ => {}
-        }
-    }
-    out.trim().to_string()
-}
-
-fn write_trbk(
-    path: &Path,
-    metadata: &TrbkMetadata,
-    options: &RenderOptions,
-    pages: &[PageData],
-    glyphs: &[Glyph],
-    toc_entries: &[TrbkTocEntry],
-    image_assets: &[ImageAsset],
-) -> Result<(), BookError> {
-    let mut file = File::create(path)?;
-
-    let toc_count: u32 = toc_entries.len() as u32;
-    let page_count = pages.len() as u32;
-    let glyph_count = glyphs.len() as u32;
-    let image_count = image_assets.len() as u32;
-
-    let fixed_header_size: u16 = 0x30;
-
-    let mut metadata_bytes = Vec::new();
-    write_string(&mut metadata_bytes, &metadata.title)?;
-    write_string(&mut metadata_bytes, &metadata.author)?;
-    write_string(&mut metadata_bytes, &metadata.language)?;
-    write_string(&mut metadata_bytes, &metadata.identifier)?;
-    write_string(&mut metadata_bytes, "fontdue")?;
-    metadata_bytes.extend_from_slice(&options.char_width.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.line_height.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.ascent.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.margin_x.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.margin_x.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.margin_y.to_le_bytes());
-    metadata_bytes.extend_from_slice(&options.margin_y.to_le_bytes());
-
-    let header_size: u16 = fixed_header_size + metadata_bytes.len() as u16;
-    let toc_offset: u32 = header_size as u32;
-    let mut toc_bytes = Vec::new();
-    for entry in toc_entries {
-        write_string(&mut toc_bytes, &entry.title)?;
-        toc_bytes.extend_from_slice(&entry.page_index.to_le_bytes());
-        toc_bytes.push(entry.level);
-        toc_bytes.push(0);
-        toc_bytes.extend_from_slice(&0u16.to_le_bytes());
-    }
-    let page_lut_offset: u32 = toc_offset + toc_bytes.len() as u32;
-
-    let mut page_lut = Vec::new();
-    let mut page_data = Vec::new();
-
-    for page in pages {
-        let page_start = page_data.len() as u32;
-        page_lut.extend_from_slice(&page_start.to_le_bytes());
-
-        for op in &page.ops {
-            match op {
-                PageOp::Text { x, y, style, text } => {
-                    let mut payload = Vec::new();
-                    payload.extend_from_slice(&x.to_le_bytes());
-                    payload.extend_from_slice(&y.to_le_bytes());
-                    payload.push(*style as u8);
-                    payload.push(0);
-                    payload.extend_from_slice(text.as_bytes());
-                    let length = payload.len() as u16;
-                    page_data.push(0x01);
-                    page_data.extend_from_slice(&length.to_le_bytes());
-                    page_data.extend_from_slice(&payload);
-                }
-                PageOp::Image {
-                    x,
-                    y,
-                    width,
-                    height,
-                    image_index,
-                } => {
-                    let mut payload = Vec::new();
-                    payload.extend_from_slice(&x.to_le_bytes());
-                    payload.extend_from_slice(&y.to_le_bytes());
-                    payload.extend_from_slice(&width.to_le_bytes());
-                    payload.extend_from_slice(&height.to_le_bytes());
-                    payload.extend_from_slice(&image_index.to_le_bytes());
-                    payload.extend_from_slice(&0u16.to_le_bytes());
-                    let length = payload.len() as u16;
-                    page_data.push(0x02);
-                    page_data.extend_from_slice(&length.to_le_bytes());
-                    page_data.extend_from_slice(&payload);
-                }
-            }
-        }
-    }
-
-    let page_data_offset = page_lut_offset + page_lut.len() as u32;
-    let glyph_table_offset = page_data_offset + page_data.len() as u32;
-    let images_offset = if image_count > 0 {
-        glyph_table_offset + glyphs_serialized_len(glyphs) as u32
-    } else {
-        0
-    };
-
-    file.write_all(b"TRBK")?;
-    file.write_all(&[2u8])?; // version
-    file.write_all(&[0u8])?; // flags
-    file.write_all(&header_size.to_le_bytes())?;
-    file.write_all(&options.screen_width.to_le_bytes())?;
-    file.write_all(&options.screen_height.to_le_bytes())?;
-    file.write_all(&page_count.to_le_bytes())?;
-    file.write_all(&toc_count.to_le_bytes())?;
-    file.write_all(&page_lut_offset.to_le_bytes())?;
-    file.write_all(&toc_offset.to_le_bytes())?;
-    file.write_all(&page_data_offset.to_le_bytes())?;
-    file.write_all(&images_offset.to_le_bytes())?;
-    file.write_all(&0u32.to_le_bytes())?; // source hash
-    file.write_all(&glyph_count.to_le_bytes())?;
-    file.write_all(&glyph_table_offset.to_le_bytes())?;
-
-    file.write_all(&metadata_bytes)?;
-
-    if toc_count != 0 {
-        file.write_all(&toc_bytes)?;
-    }
-    file.write_all(&page_lut)?;
-    file.write_all(&page_data)?;
-    write_glyph_table(&mut file, glyphs)?;
-    if image_count > 0 {
-        write_image_table(&mut file, image_assets)?;
+        };
+        paginate::paginate_items(&items, &options, &advance_map)
+    });
+    if has_fixed {
+        let fixed_pages = timings.time(Stage::Images, || fixed::build_fixed_pages(input, fonts, &options, &mut images));
+        if blocks.is_empty() && !fixed_pages.is_empty() {
+            pages.clear();
+        }
+        pages.extend(fixed_pages);
+        pages.sort_by_key(|page| page.spine_index);
+    }
+    let images_before_cover = images.len();
+    if let Some(bytes) = input.cover().filter(|_| !options.image_limits.strip) {
+        timings.time(Stage::Images, || {
+            let cover = image::load_from_memory(&bytes).map_err(|err| BookError::Cover(err.to_string()))?;
+            fixed::insert_cover_page(&cover, &options, &mut pages, &mut images);
+            Ok::<_, BookError>(())
+        })?;
     }
-    Ok(())
-}
-
-fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<(), BookError> {
-    let bytes = value.as_bytes();
-    let len = bytes.len() as u32;
-    writer.write_all(&len.to_le_bytes())?;
-    writer.write_all(bytes)?;
-    Ok(())
+    let (text, toc) = timings.time(Stage::Layout, || {
+        let text = if with_text {
+            let marker_pages = reflow::take_marker_pages(&mut pages);
+            let image_shift = (images.len() - images_before_cover) as u16;
+            Some(reflow::build_text_items(blocks, &pages, &image_map, &marker_pages, image_shift))
+        } else {
+            None
+        };
+        let spine_to_page = paginate::compute_spine_page_map(&pages, input.spine().len());
+        let anchor_to_page = paginate::compute_anchor_page_map(&pages);
+        (text, toc::build_toc_entries(input, &spine_to_page, &anchor_to_page))
+    });
+    Ok(RenderedBook {
+        metadata: input.metadata(),
+        options,
+        pages,
+        glyphs,
+        toc,
+        images,
+        font_pack: None,
+        text,
+        missing_images,
+    })
+}
+
+/// Splits `book` into linked parts named after `output` (`name.partN.trbk`).
+/// Returns a single entry when the book fits in one part.
+pub fn split_into_parts(
+    book: &RenderedBook,
+    max_pages: usize,
+    output: &Path,
+) -> Vec<(PathBuf, RenderedBook)> {
+    let ranges = paginate::split_pages_into_parts(&book.pages, max_pages);
+    let part_count = ranges.len() as u16;
+    let mut parts = Vec::new();
+    for (idx, range) in ranges.iter().enumerate() {
+        let index = idx as u16 + 1;
+        let next = if index < part_count {
+            part_output_path(output, index + 1)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let mut metadata = book.metadata.clone();
+        metadata.title = format!("{} ({}/{})", book.metadata.title, index, part_count);
+        metadata.part = Some(TrbkPart {
+            index,
+            count: part_count,
+            next,
+        });
+        let (pages, images) = paginate::remap_part_images(&book.pages[range.clone()], &book.images);
+        let toc = book
+            .toc
+            .iter()
+            .filter(|entry| range.contains(&(entry.page_index as usize)))
+            .map(|entry| TrbkTocEntry {
+                title: entry.title.clone(),
+                page_index: entry.page_index - range.start as u32,
+                level: entry.level,
+            })
+            .collect::<Vec<_>>();
+        parts.push((
+            part_output_path(output, index),
+            RenderedBook {
+                metadata,
+                options: book.options.clone(),
+                pages,
+                glyphs: book.glyphs.clone(),
+                toc,
+                images,
+                font_pack: book.font_pack.clone(),
+                text: None,
+                missing_images: Vec::new(),
+            },
+        ));
+    }
+    parts
 }
 
 fn output_path_for_size(base: &Path, size: u16, multi: bool) -> PathBuf {
@@ -1150,283 +514,13 @@ fn output_path_for_size(base: &Path, size: u16, multi: bool) -> PathBuf {
     out
 }
 
-fn style_id_from_style(style: tern_epub::TextStyle) -> StyleId {
-    match (style.bold, style.italic) {
-        (false, false) => StyleId::Regular,
-        (true, false) => StyleId::Bold,
-        (false, true) => StyleId::Italic,
-        (true, true) => StyleId::BoldItalic,
-    }
-}
-
-fn load_fonts(paths: &FontPaths) -> Result<HashMap<StyleId, fontdue::Font>, BookError> {
-    let mut map = HashMap::new();
-    let regular_path = paths
-        .regular
-        .as_deref()
-        .unwrap_or("fonts/DejaVuSans.ttf");
-    let regular_bytes = std::fs::read(regular_path).map_err(|err| {
-        BookError::Io(std::io::Error::new(
-            err.kind(),
-            format!("missing font file: {regular_path}"),
-        ))
-    })?;
-    let regular = fontdue::Font::from_bytes(regular_bytes, fontdue::FontSettings::default())
-        .map_err(|_| BookError::InvalidOutput)?;
-    map.insert(StyleId::Regular, regular.clone());
-
-    let auto_bold = if paths.bold.is_none() {
-        guess_font_variant(regular_path, FontVariant::Bold)
-    } else {
-        None
-    };
-    let auto_italic = if paths.italic.is_none() {
-        guess_font_variant(regular_path, FontVariant::Italic)
-    } else {
-        None
-    };
-    let auto_bold_italic = if paths.bold_italic.is_none() {
-        guess_font_variant(regular_path, FontVariant::BoldItalic)
-    } else {
-        None
-    };
-
-    if let Some(path) = paths.bold.as_deref().or(auto_bold.as_deref()) {
-        let bytes = std::fs::read(path).map_err(|err| {
-            BookError::Io(std::io::Error::new(
-                err.kind(),
-                format!("missing font file: {path}"),
-            ))
-        })?;
-        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
-            .map_err(|_| BookError::InvalidOutput)?;
-        map.insert(StyleId::Bold, font);
-    }
-    if let Some(path) = paths.italic.as_deref().or(auto_italic.as_deref()) {
-        let bytes = std::fs::read(path).map_err(|err| {
-            BookError::Io(std::io::Error::new(
-                err.kind(),
-                format!("missing font file: {path}"),
-            ))
-        })?;
-        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
-            .map_err(|_| BookError::InvalidOutput)?;
-        map.insert(StyleId::Italic, font);
-    }
-    if let Some(path) = paths.bold_italic.as_deref().or(auto_bold_italic.as_deref()) {
-        let bytes = std::fs::read(path).map_err(|err| {
-            BookError::Io(std::io::Error::new(
-                err.kind(),
-                format!("missing font file: {path}"),
-            ))
-        })?;
-        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
-            .map_err(|_| BookError::InvalidOutput)?;
-        map.insert(StyleId::BoldItalic, font);
-    }
-
-    Ok(map)
-}
-
-#[derive(Clone, Copy, Debug)]
-enum FontVariant {
-    Bold,
-    Italic,
-    BoldItalic,
-}
-
-fn guess_font_variant(regular_path: &str, variant: FontVariant) -> Option<String> {
-    let path = Path::new(regular_path);
-    let stem = path.file_stem()?.to_string_lossy();
-    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("ttf");
-    let mut candidates = Vec::new();
-
-    // Common patterns: Foo-Regular -> Foo-Bold / Foo-Italic / Foo-BoldItalic
-    let base = stem
-        .replace("Regular", "")
-        .replace("regular", "")
-        .replace("Roman", "")
-        .replace("roman", "")
-        .trim_end_matches('-')
-        .trim_end_matches('_')
-        .to_string();
-    let suffix = match variant {
-        FontVariant::Bold => "Bold",
-        FontVariant::Italic => "Italic",
-        FontVariant::BoldItalic => "Bold Italic",
-    };
-    if !base.is_empty() {
-        candidates.push(format!("{}-{}.{}", base, suffix, ext));
-        candidates.push(format!("{}_{}.{}", base, suffix, ext));
-        candidates.push(format!("{} {}.{}", base, suffix, ext));
-        candidates.push(format!("{}{}.{}", base, suffix.replace(' ', ""), ext));
-    }
-    // Also try replacing Regular in the original stem.
-    let replaced = match variant {
-        FontVariant::Bold => stem.replace("Regular", "Bold").replace("regular", "Bold"),
-        FontVariant::Italic => stem.replace("Regular", "Italic").replace("regular", "Italic"),
-        FontVariant::BoldItalic => stem
-            .replace("Regular", "Bold Italic")
-            .replace("regular", "Bold Italic"),
-    };
-    if replaced != stem {
-        candidates.push(format!("{}.{}", replaced, ext));
-    }
-
-    for name in candidates {
-        let candidate = path.with_file_name(name);
-        if candidate.is_file() {
-            return Some(candidate.to_string_lossy().to_string());
-        }
-    }
-    None
-}
-
-fn build_glyphs(
-    fonts: &HashMap<StyleId, fontdue::Font>,
-    size: u16,
-    used: &HashMap<StyleId, BTreeSet<u32>>,
-) -> Result<Vec<Glyph>, BookError> {
-    let mut glyphs = Vec::new();
-    for (style, codepoints) in used {
-        let font = fonts
-            .get(style)
-            .or_else(|| fonts.get(&StyleId::Regular))
-            .ok_or(BookError::InvalidOutput)?;
-        for codepoint in codepoints {
-            if let Some(ch) = char::from_u32(*codepoint) {
-                let (metrics, bitmap) = font.rasterize(ch, size as f32);
-                let y_offset = (metrics.ymin + metrics.height as i32) as i16;
-                let (bw, lsb, msb) =
-                    pack_gray2_bitmap(&bitmap, metrics.width as usize, metrics.height as usize);
-                glyphs.push(Glyph {
-                    codepoint: *codepoint,
-                    style: *style,
-                    width: metrics.width as u8,
-                    height: metrics.height as u8,
-                    x_advance: metrics.advance_width.round() as i16,
-                    x_offset: metrics.xmin as i16,
-                    y_offset,
-                    bitmap_bw: bw,
-                    bitmap_lsb: lsb,
-                    bitmap_msb: msb,
-                });
-            }
-        }
-    }
-    Ok(glyphs)
-}
-
-fn pack_gray2_bitmap(bitmap: &[u8], width: usize, height: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
-    let total = width * height;
-    let mut bw = vec![0u8; (total + 7) / 8];
-    let mut lsb = vec![0u8; (total + 7) / 8];
-    let mut msb = vec![0u8; (total + 7) / 8];
-    for i in 0..total {
-        let byte = i / 8;
-        let bit = 7 - (i % 8);
-        let val = 255u8.saturating_sub(bitmap[i]);
-        let (bw_bit, msb_bit, lsb_bit) = if val >= 205 {
-            (1u8, 0u8, 0u8)
-        } else if val >= 154 {
-            (1u8, 0u8, 1u8)
-        } else if val >= 103 {
-            (0u8, 1u8, 0u8)
-        } else if val >= 52 {
-            (0u8, 1u8, 1u8)
-        } else {
-            (0u8, 0u8, 0u8)
-        };
-        if bw_bit != 0 {
-            bw[byte] |= 1 << bit;
-        }
-        if lsb_bit != 0 {
-            lsb[byte] |= 1 << bit;
-        }
-        if msb_bit != 0 {
-            msb[byte] |= 1 << bit;
-        }
-    }
-    (bw, lsb, msb)
-}
-
-fn write_glyph_table<W: Write>(writer: &mut W, glyphs: &[Glyph]) -> Result<(), BookError> {
-    for glyph in glyphs {
-        writer.write_all(&glyph.codepoint.to_le_bytes())?;
-        writer.write_all(&[glyph.style as u8])?;
-        writer.write_all(&[glyph.width])?;
-        writer.write_all(&[glyph.height])?;
-        writer.write_all(&glyph.x_advance.to_le_bytes())?;
-        writer.write_all(&glyph.x_offset.to_le_bytes())?;
-        writer.write_all(&glyph.y_offset.to_le_bytes())?;
-        let len = (glyph.bitmap_bw.len() + glyph.bitmap_lsb.len() + glyph.bitmap_msb.len()) as u32;
-        writer.write_all(&len.to_le_bytes())?;
-        writer.write_all(&glyph.bitmap_bw)?;
-        writer.write_all(&glyph.bitmap_lsb)?;
-        writer.write_all(&glyph.bitmap_msb)?;
-    }
-    Ok(())
-}
-
-fn glyphs_serialized_len(glyphs: &[Glyph]) -> usize {
-    let mut total = 0usize;
-    for glyph in glyphs {
-        total += 4
-            + 1
-            + 1
-            + 1
-            + 2
-            + 2
-            + 2
-            + 4
-            + glyph.bitmap_bw.len()
-            + glyph.bitmap_lsb.len()
-            + glyph.bitmap_msb.len();
-    }
-    total
-}
-
-fn write_image_table<W: Write>(writer: &mut W, images: &[ImageAsset]) -> Result<(), BookError> {
-    let count = images.len() as u32;
-    let table_size = 4 + images.len() * 16;
-    let mut data_offset = table_size as u32;
-
-    writer.write_all(&count.to_le_bytes())?;
-    for image in images {
-        writer.write_all(&data_offset.to_le_bytes())?;
-        writer.write_all(&(image.data.len() as u32).to_le_bytes())?;
-        writer.write_all(&image.width.to_le_bytes())?;
-        writer.write_all(&image.height.to_le_bytes())?;
-        writer.write_all(&0u16.to_le_bytes())?;
-        writer.write_all(&0u16.to_le_bytes())?;
-        data_offset = data_offset.saturating_add(image.data.len() as u32);
-    }
-    for image in images {
-        writer.write_all(&image.data)?;
-    }
-    Ok(())
-}
-
-fn trimg_to_bytes(trimg: &tern_image::Trimg) -> Vec<u8> {
-    let mut out = Vec::new();
-    out.extend_from_slice(b"TRIM");
-    match &trimg.data {
-        tern_image::TrimgData::Mono1 { bits } => {
-            out.push(1);
-            out.push(1);
-            out.extend_from_slice(&(trimg.width as u16).to_le_bytes());
-            out.extend_from_slice(&(trimg.height as u16).to_le_bytes());
-            out.extend_from_slice(&[0u8; 6]);
-            out.extend_from_slice(bits);
-        }
-        tern_image::TrimgData::Gray2 { data } => {
-            out.push(2);
-            out.push(2);
-            out.extend_from_slice(&(trimg.width as u16).to_le_bytes());
-            out.extend_from_slice(&(trimg.height as u16).to_le_bytes());
-            out.extend_from_slice(&[0u8; 6]);
-            out.extend_from_slice(data);
-        }
-    }
+fn part_output_path(base: &Path, index: u16) -> PathBuf {
+    let stem = base
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "book".to_string());
+    let ext = base.extension().and_then(|s| s.to_str()).unwrap_or("trbk");
+    let mut out = base.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    out.push(format!("{}.part{}.{}", stem, index, ext));
     out
 }
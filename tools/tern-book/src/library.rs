@@ -0,0 +1,104 @@
+//! Whole-folder conversion (`tern-book convert-dir`).
+//!
+//! Every book under the input folder is converted to the same relative path
+//! under the output folder, with a `.trbk` extension. Libraries often keep a
+//! book in several formats side by side (Calibre does), so only the first
+//! backend in [`input::FORMATS`] order is converted for each output.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::input;
+use crate::{output_path_for_size, part_output_path, BookError};
+
+#[derive(Clone, Debug)]
+pub struct LibraryBook {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Another input converts to the same output, so this one is left out.
+    pub duplicate_of: Option<PathBuf>,
+}
+
+/// Books under `in_dir` with a supported extension, sorted by path, and where
+/// each goes under `out_dir`. Hidden files and folders are skipped, as is
+/// `out_dir` when it sits inside `in_dir`.
+pub fn find_books(in_dir: &Path, out_dir: &Path) -> Result<Vec<LibraryBook>, BookError> {
+    let mut inputs = Vec::new();
+    let skip = out_dir.canonicalize().ok();
+    collect_inputs(in_dir, skip.as_deref(), &mut inputs)?;
+    inputs.sort();
+
+    let mut books: Vec<LibraryBook> = inputs
+        .into_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(in_dir).unwrap_or(&path);
+            LibraryBook {
+                output: out_dir.join(relative).with_extension("trbk"),
+                input: path,
+                duplicate_of: None,
+            }
+        })
+        .collect();
+    let mut chosen: HashMap<PathBuf, usize> = HashMap::new();
+    for index in 0..books.len() {
+        let Some(&kept) = chosen.get(&books[index].output) else {
+            chosen.insert(books[index].output.clone(), index);
+            continue;
+        };
+        let (keep, drop) = if format_rank(&books[index].input) < format_rank(&books[kept].input) {
+            chosen.insert(books[index].output.clone(), index);
+            (index, kept)
+        } else {
+            (kept, index)
+        };
+        books[drop].duplicate_of = Some(books[keep].input.clone());
+    }
+    Ok(books)
+}
+
+/// Whether a conversion of `input` to `output` is newer than the input. With
+/// several sizes every size's file is checked, so a run interrupted between
+/// sizes converts the book again; split books are checked by their first part.
+pub fn is_up_to_date(input: &Path, output: &Path, sizes: &[u16]) -> bool {
+    let Ok(modified) = std::fs::metadata(input).and_then(|meta| meta.modified()) else {
+        return false;
+    };
+    let sizes = if sizes.is_empty() { &[10][..] } else { sizes };
+    sizes.iter().all(|size| {
+        let first = output_path_for_size(output, *size, sizes.len() > 1);
+        [part_output_path(&first, 1), first].iter().any(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|written| written >= modified)
+        })
+    })
+}
+
+fn collect_inputs(dir: &Path, skip: Option<&Path>, inputs: &mut Vec<PathBuf>) -> Result<(), BookError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            if skip.is_some() && path.canonicalize().ok().as_deref() == skip {
+                continue;
+            }
+            collect_inputs(&path, skip, inputs)?;
+        } else if format_rank(&path).is_some() {
+            inputs.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Position in [`input::FORMATS`] of the backend for the path's extension.
+fn format_rank(path: &Path) -> Option<usize> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    input::FORMATS
+        .iter()
+        .position(|format| format.extensions.contains(&extension.as_str()))
+}
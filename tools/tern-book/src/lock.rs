@@ -0,0 +1,282 @@
+//! Layout metrics kept between conversions with `--lock-layout`.
+//!
+//! People note page numbers outside the book, so a reconversion that moves
+//! pages around should not go unnoticed. For each size the metrics file
+//! holds a hash of the glyph advances and one hash per page of what is drawn
+//! on it; a later conversion compares against them and says where pagination
+//! first differs. The file is only ever extended with new sizes, so it keeps
+//! describing the first conversion.
+
+use std::path::Path;
+
+use crate::fontpack::fnv1a;
+use crate::paginate::{PageData, PageOp};
+use crate::serialize::RenderedBook;
+use crate::BookError;
+
+/// Layout of one font size of a book.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayoutMetrics {
+    pub size: u16,
+    /// Hash of every glyph's advance, which decides where lines break.
+    pub advances: u32,
+    /// Hash of each page's text and image placements, in page order.
+    pub pages: Vec<u32>,
+}
+
+impl LayoutMetrics {
+    pub fn of_book(book: &RenderedBook, size: u16) -> Self {
+        let mut advances: Vec<(u8, u32, i16)> = book
+            .glyphs
+            .iter()
+            .map(|glyph| (glyph.style as u8, glyph.codepoint, glyph.x_advance))
+            .collect();
+        advances.sort_unstable();
+        let mut bytes = Vec::with_capacity(advances.len() * 7);
+        for (style, codepoint, advance) in advances {
+            bytes.push(style);
+            bytes.extend_from_slice(&codepoint.to_le_bytes());
+            bytes.extend_from_slice(&advance.to_le_bytes());
+        }
+        Self {
+            size,
+            advances: fnv1a(&bytes),
+            pages: book.pages.iter().map(page_hash).collect(),
+        }
+    }
+
+    /// Hash of all page breaks, for a one-line comparison.
+    pub fn breaks(&self) -> u32 {
+        let bytes: Vec<u8> = self.pages.iter().flat_map(|hash| hash.to_le_bytes()).collect();
+        fnv1a(&bytes)
+    }
+
+    /// What differs from the `recorded` layout of the same size, one line
+    /// each; empty when pagination is unchanged.
+    pub fn changes_from(&self, recorded: &LayoutMetrics) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.advances != recorded.advances {
+            changes.push("glyph advances differ (another font, size or converter version)".to_string());
+        }
+        if self.pages.len() != recorded.pages.len() {
+            changes.push(format!("{} pages, was {}", self.pages.len(), recorded.pages.len()));
+        }
+        if let Some(page) = self.pages.iter().zip(&recorded.pages).position(|(a, b)| a != b) {
+            changes.push(format!("page {} and later may show different text", page + 1));
+        }
+        changes
+    }
+}
+
+fn page_hash(page: &PageData) -> u32 {
+    let mut bytes = Vec::new();
+    for op in &page.ops {
+        match op {
+            PageOp::Text { x, y, style, text } => {
+                bytes.push(0x01);
+                bytes.extend_from_slice(&x.to_le_bytes());
+                bytes.extend_from_slice(&y.to_le_bytes());
+                bytes.push(*style as u8);
+                bytes.extend_from_slice(text.as_bytes());
+            }
+            PageOp::Image {
+                x,
+                y,
+                width,
+                height,
+                image_index,
+            } => {
+                bytes.push(0x02);
+                for value in [x, y, width, height, image_index] {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+    }
+    fnv1a(&bytes)
+}
+
+/// Metrics recorded in `path`, or none if it does not exist yet.
+pub fn read_metrics(path: &Path) -> Result<Vec<LayoutMetrics>, BookError> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse_metrics(&text).map_err(BookError::LayoutLock),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn write_metrics(path: &Path, metrics: &[LayoutMetrics]) -> Result<(), BookError> {
+    let sizes: Vec<String> = metrics
+        .iter()
+        .map(|size| {
+            let pages: Vec<String> = size.pages.iter().map(u32::to_string).collect();
+            format!(
+                "    {{\n      \"size\": {},\n      \"advances\": {},\n      \"breaks\": {},\n      \"pages\": [{}]\n    }}",
+                size.size,
+                size.advances,
+                size.breaks(),
+                pages.join(", ")
+            )
+        })
+        .collect();
+    let text = format!("{{\n  \"version\": 1,\n  \"sizes\": [\n{}\n  ]\n}}\n", sizes.join(",\n"));
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// Reads what [`write_metrics`] writes: an object with a `sizes` array of
+/// objects holding numbers and number arrays. Unknown keys are skipped.
+fn parse_metrics(text: &str) -> Result<Vec<LayoutMetrics>, String> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+    let mut metrics = Vec::new();
+    parser.object(|parser, key| {
+        if key != "sizes" {
+            return parser.skip_value();
+        }
+        parser.array(|parser| {
+            let mut size = None;
+            let mut advances = None;
+            let mut pages = None;
+            parser.object(|parser, key| {
+                match key {
+                    "size" => size = Some(parser.number()?),
+                    "advances" => advances = Some(parser.number()?),
+                    "pages" => {
+                        let mut hashes = Vec::new();
+                        parser.array(|parser| {
+                            hashes.push(parser.number()? as u32);
+                            Ok(())
+                        })?;
+                        pages = Some(hashes);
+                    }
+                    _ => parser.skip_value()?,
+                }
+                Ok(())
+            })?;
+            match (size, advances, pages) {
+                (Some(size), Some(advances), Some(pages)) => {
+                    metrics.push(LayoutMetrics {
+                        size: size as u16,
+                        advances: advances as u32,
+                        pages,
+                    });
+                    Ok(())
+                }
+                _ => Err("a size entry lacks size, advances or pages".to_string()),
+            }
+        })
+    })?;
+    Ok(metrics)
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.text.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at byte {}", byte as char, self.pos))
+        }
+    }
+
+    /// Consumes `byte` if it is next.
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn object(
+        &mut self,
+        mut field: impl FnMut(&mut Self, &str) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.expect(b'{')?;
+        if self.eat(b'}') {
+            return Ok(());
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            field(self, &key)?;
+            if !self.eat(b',') {
+                return self.expect(b'}');
+            }
+        }
+    }
+
+    fn array(&mut self, mut element: impl FnMut(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        self.expect(b'[')?;
+        if self.eat(b']') {
+            return Ok(());
+        }
+        loop {
+            element(self)?;
+            if !self.eat(b',') {
+                return self.expect(b']');
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while let Some(&byte) = self.text.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'"' => return Ok(String::from_utf8_lossy(&self.text[start..self.pos - 1]).into_owned()),
+                b'\\' => self.pos += 1,
+                _ => {}
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        self.peek();
+        let start = self.pos;
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| format!("expected a number at byte {start}"))
+    }
+
+    fn skip_value(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some(b'{') => self.object(|parser, _| parser.skip_value()),
+            Some(b'[') => self.array(Self::skip_value),
+            Some(b'"') => self.string().map(|_| ()),
+            _ => {
+                let start = self.pos;
+                while self
+                    .text
+                    .get(self.pos)
+                    .is_some_and(|byte| !matches!(byte, b',' | b'}' | b']') && !byte.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    Err(format!("expected a value at byte {start}"))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
@@ -1,29 +1,111 @@
+use std::collections::HashMap;
 use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-const BUILD_VERSION: &str = env!("TRUSTY_VERSION");
-const BUILD_TIME: &str = env!("TRUSTY_BUILD_TIME");
+/// Exit code for DRM-protected input, so scripts can tell it from other failures.
+const EXIT_DRM: i32 = 3;
+/// Summary `convert-dir` writes into the output folder.
+const REPORT_NAME: &str = "tern-book-report.txt";
+/// Report lines of the books `convert-dir` has finished so far, one
+/// `name<TAB>line` per line, so `--resume` can carry on after an interruption.
+const JOURNAL_NAME: &str = "tern-book-resume.txt";
+
+/// Options shared by single books and `convert-dir`.
+struct Options {
+    font_paths: tern_book::FontPaths,
+    sizes: Vec<u16>,
+    max_pages: Option<usize>,
+    format: Option<String>,
+    writing_mode: Option<tern_book::WritingMode>,
+    pdf_mode: Option<tern_book::input::PdfMode>,
+    scan: tern_book::scan::ScanOptions,
+    title: Option<String>,
+    author: Option<String>,
+    language: Option<String>,
+    series: Option<String>,
+    cover: Option<Vec<u8>>,
+    font_pack_dir: Option<PathBuf>,
+    reflow: bool,
+    analyze: bool,
+    image_limits: tern_book::ImageLimits,
+    typography: tern_book::typography::Typography,
+    lock_layout: Option<PathBuf>,
+    resume: bool,
+    timings: bool,
+}
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let mut args = env::args().skip(1).collect::<Vec<_>>();
     if args.len() == 1 && (args[0] == "--version" || args[0] == "-V" || args[0] == "version") {
-        println!("tern-book {BUILD_VERSION} ({BUILD_TIME})");
+        println!("{}", tern_build_info::VersionLine("tern-book"));
         return;
     }
-    if args.len() < 2 {
-        eprintln!("Usage: tern-book <input.epub> <output.trbk> [--font path.ttf] [--sizes 8,10,12] [--font-bold path.ttf] [--font-italic path.ttf] [--font-bold-italic path.ttf]");
+    if args.len() < 2 || (args[0] == "convert-dir" && args.len() < 3) {
+        eprintln!("Usage: tern-book <input.epub|input.mobi|input.azw3|input.pdf|input.djvu|input.html|input.txt|https://...> <output.trbk> [--format epub|mobi|pdf|djvu|html|txt] [--font path.ttf] [--sizes 8,10,12] [--font-bold path.ttf] [--font-italic path.ttf] [--font-bold-italic path.ttf] [--max-pages N] [--writing-mode auto|horizontal|vertical] [--pdf-mode reflow|image|scan] [--crop-margins] [--split-spreads] [--title T] [--author A] [--language L] [--series S] [--cover path.png] [--font-pack DIR] [--reflow] [--analyze] [--max-image-dim N] [--image-grayscale 1|2] [--images max-kb N] [--no-images] [--typography off|basic|lang] [--lock-layout metrics.json] [--timings]");
+        eprintln!("       tern-book convert-dir <in_dir> <out_dir> [--resume] [the options above, except --format, --title, --author, --series, --cover and --lock-layout]");
         std::process::exit(1);
     }
 
+    if args[0] == "convert-dir" {
+        let options = parse_options(&args[3..]);
+        convert_dir(Path::new(&args[1]), Path::new(&args[2]), options);
+        return;
+    }
+
     let input = args.remove(0);
     let output = args.remove(0);
+    let options = parse_options(&args);
+    if options.resume {
+        eprintln!("[tern-book] warning: --resume applies to convert-dir; ignoring it");
+    }
+    match convert(Path::new(&input), Path::new(&output), &options) {
+        Ok(missing_images) => {
+            if !missing_images.is_empty() {
+                eprintln!("[tern-book] {} images are missing from the book:", missing_images.len());
+            }
+            for image in &missing_images {
+                eprintln!("[tern-book]   {}", describe_missing_image(image));
+            }
+        }
+        Err(err) => {
+            eprintln!("Conversion failed: {err}");
+            match err {
+                tern_book::BookError::Drm(_) => std::process::exit(EXIT_DRM),
+                _ => std::process::exit(1),
+            }
+        }
+    }
+
+    println!("Wrote TRBK output(s) starting at {output}");
+}
 
+fn parse_options(args: &[String]) -> Options {
     let mut font = None;
     let mut font_bold = None;
     let mut font_italic = None;
     let mut font_bold_italic = None;
     let mut sizes = None;
+    let mut max_pages = None;
+    let mut format = None;
+    let mut writing_mode = None;
+    let mut pdf_mode = None;
+    let mut typography = None;
+    let mut scan = tern_book::scan::ScanOptions::default();
+    let mut title = None;
+    let mut author = None;
+    let mut language = None;
+    let mut series = None;
+    let mut cover = None;
+    let mut font_pack_dir = None;
+    let mut lock_layout = None;
+    let mut reflow = false;
+    let mut resume = false;
+    let mut timings = false;
+    let mut analyze = false;
+    let mut image_limits = tern_book::ImageLimits::default();
 
     let mut i = 0;
     while i < args.len() {
@@ -48,6 +130,92 @@ fn main() {
                 i += 1;
                 sizes = args.get(i).cloned();
             }
+            "--format" => {
+                i += 1;
+                format = args.get(i).cloned();
+            }
+            "--writing-mode" => {
+                i += 1;
+                writing_mode = args.get(i).cloned();
+            }
+            "--pdf-mode" => {
+                i += 1;
+                pdf_mode = args.get(i).cloned();
+            }
+            "--typography" => {
+                i += 1;
+                typography = args.get(i).cloned();
+            }
+            "--title" => {
+                i += 1;
+                title = args.get(i).cloned();
+            }
+            "--author" => {
+                i += 1;
+                author = args.get(i).cloned();
+            }
+            "--language" => {
+                i += 1;
+                language = args.get(i).cloned();
+            }
+            "--series" => {
+                i += 1;
+                series = args.get(i).cloned();
+            }
+            "--cover" => {
+                i += 1;
+                cover = args.get(i).cloned();
+            }
+            "--font-pack" => {
+                i += 1;
+                font_pack_dir = args.get(i).cloned();
+            }
+            "--lock-layout" => {
+                i += 1;
+                lock_layout = args.get(i).cloned();
+            }
+            "--reflow" => reflow = true,
+            "--resume" => resume = true,
+            "--timings" => timings = true,
+            "--analyze" => analyze = true,
+            "--crop-margins" => scan.crop_margins = true,
+            "--split-spreads" => scan.split_spreads = true,
+            "--max-pages" => {
+                i += 1;
+                max_pages = args.get(i).and_then(|s| s.trim().parse::<usize>().ok());
+            }
+            "--max-image-dim" => {
+                i += 1;
+                image_limits.max_dim = args.get(i).and_then(|s| s.trim().parse::<u16>().ok());
+            }
+            "--image-grayscale" => {
+                i += 1;
+                image_limits.gray_bits = match args.get(i).map(|s| s.trim()) {
+                    Some("1") => 1,
+                    Some("2") => 2,
+                    other => {
+                        eprintln!(
+                            "Unknown image grayscale '{}', expected 1 or 2",
+                            other.unwrap_or_default()
+                        );
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--no-images" => image_limits.strip = true,
+            "--images" => {
+                i += 1;
+                match args.get(i).map(|s| s.trim()) {
+                    Some("max-kb") => {
+                        i += 1;
+                        image_limits.max_kb = args.get(i).and_then(|s| s.trim().parse::<u32>().ok());
+                    }
+                    other => {
+                        eprintln!("Unknown --images limit '{}', expected max-kb N", other.unwrap_or_default());
+                        std::process::exit(1);
+                    }
+                }
+            }
             _ => {}
         }
         i += 1;
@@ -66,10 +234,308 @@ fn main() {
         bold_italic: font_bold_italic,
     };
 
-    if let Err(err) = tern_book::convert_epub_to_trbk_multi(&input, &output, &sizes, &font_paths) {
-        eprintln!("Conversion failed: {err}");
+    let writing_mode = match writing_mode.as_deref() {
+        None | Some("auto") => None,
+        Some("horizontal") => Some(tern_book::WritingMode::Horizontal),
+        Some("vertical") | Some("vertical-rl") => Some(tern_book::WritingMode::VerticalRl),
+        Some(other) => {
+            eprintln!("Unknown writing mode '{other}', expected auto, horizontal or vertical");
+            std::process::exit(1);
+        }
+    };
+    let pdf_mode = match pdf_mode.as_deref() {
+        None => None,
+        Some("reflow") => Some(tern_book::input::PdfMode::Reflow),
+        Some("image") => Some(tern_book::input::PdfMode::Image),
+        Some("scan") => Some(tern_book::input::PdfMode::Scan),
+        Some(other) => {
+            eprintln!("Unknown PDF mode '{other}', expected reflow, image or scan");
+            std::process::exit(1);
+        }
+    };
+    let typography = match typography.as_deref() {
+        None => tern_book::typography::Typography::Off,
+        Some(name) => tern_book::typography::Typography::from_name(name).unwrap_or_else(|| {
+            eprintln!("Unknown typography '{name}', expected off, basic or lang");
+            std::process::exit(1);
+        }),
+    };
+    let cover = cover.map(|path| match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to read cover image {path}: {err}");
+            std::process::exit(1);
+        }
+    });
+
+    Options {
+        font_paths,
+        sizes,
+        max_pages,
+        format,
+        writing_mode,
+        pdf_mode,
+        scan,
+        title,
+        author,
+        language,
+        series,
+        cover,
+        font_pack_dir: font_pack_dir.map(PathBuf::from),
+        reflow,
+        analyze,
+        image_limits,
+        typography,
+        lock_layout: lock_layout.map(PathBuf::from),
+        resume,
+        timings,
+    }
+}
+
+/// Converts one book, returning the images shown as a placeholder.
+fn convert(
+    input: &Path,
+    output: &Path,
+    options: &Options,
+) -> Result<Vec<tern_book::MissingImage>, tern_book::BookError> {
+    let format = match options.format.as_deref() {
+        Some(name) => tern_book::input::format_by_name(name).ok_or_else(|| {
+            tern_book::BookError::UnsupportedInput(name.to_string())
+        }),
+        None => tern_book::input::detect_format(input),
+    };
+    format
+        .and_then(|format| match (format.name, options.pdf_mode) {
+            ("pdf", Some(mode)) => {
+                let mut book = tern_book::input::PdfInput::open(input, mode)?;
+                book.scan = options.scan;
+                Ok(Box::new(book) as Box<dyn tern_book::input::BookInput>)
+            }
+            ("djvu", _) => {
+                let mut book = tern_book::input::DjvuInput::open(input)?;
+                book.scan = options.scan;
+                Ok(Box::new(book) as Box<dyn tern_book::input::BookInput>)
+            }
+            _ => (format.open)(input),
+        })
+        .and_then(|book| {
+            let mut book = tern_book::input::InputOverrides::new(book.as_ref());
+            book.writing_mode = options.writing_mode;
+            book.metadata = tern_book::input::MetadataOverrides {
+                title: options.title.clone(),
+                author: options.author.clone(),
+                language: options.language.clone(),
+                series: options.series.clone(),
+            };
+            book.cover = options.cover.clone();
+            book.image_limits = Some(options.image_limits);
+            book.typography = options.typography;
+            let convert = tern_book::ConvertOptions {
+                sizes: options.sizes.clone(),
+                font_paths: options.font_paths.clone(),
+                max_part_pages: options.max_pages,
+                font_pack_dir: options.font_pack_dir.clone(),
+                reflow: options.reflow,
+            };
+            tern_book::convert_book_to_trbk(&book, output, &convert)
+        })
+        .and_then(|report| {
+            report_slow_pages(&report.slow_pages, options.analyze);
+            if options.timings {
+                eprintln!("[tern-book] timings for {}:", input.display());
+                for line in report.timings.summary() {
+                    eprintln!("[tern-book]   {line}");
+                }
+            }
+            if let Some(path) = &options.lock_layout {
+                lock_layout(path, &report.layout)?;
+            }
+            Ok(report.missing_images)
+        })
+}
+
+/// Compares each size with the layout recorded in `path`, warning loudly
+/// where pagination moved, and records the sizes it has no layout for yet.
+fn lock_layout(path: &Path, layout: &[tern_book::lock::LayoutMetrics]) -> Result<(), tern_book::BookError> {
+    let mut recorded = tern_book::lock::read_metrics(path)?;
+    let mut added = false;
+    for metrics in layout {
+        match recorded.iter().find(|previous| previous.size == metrics.size) {
+            Some(previous) => {
+                let changes = metrics.changes_from(previous);
+                if changes.is_empty() {
+                    eprintln!("[tern-book] pagination at size {} matches {}", metrics.size, path.display());
+                    continue;
+                }
+                eprintln!(
+                    "[tern-book] WARNING: pagination at size {} differs from {}; page numbers noted before no longer match:",
+                    metrics.size,
+                    path.display()
+                );
+                for change in changes {
+                    eprintln!("[tern-book] WARNING:   {change}");
+                }
+            }
+            None => {
+                recorded.push(metrics.clone());
+                added = true;
+            }
+        }
+    }
+    if added {
+        tern_book::lock::write_metrics(path, &recorded)?;
+        eprintln!("[tern-book] recorded layout in {}", path.display());
+    }
+    Ok(())
+}
+
+/// Report line for an image shown as a placeholder.
+fn describe_missing_image(image: &tern_book::MissingImage) -> String {
+    format!(
+        "{} ({}), shown as {}",
+        image.src,
+        image.reason,
+        tern_book::images::placeholder_text(image.alt.as_deref())
+    )
+}
+
+/// Lists pages likely to be slow on the device with `--analyze`, else just
+/// says how many there are.
+fn report_slow_pages(slow_pages: &[tern_book::analyze::SlowPage], analyze: bool) {
+    if !analyze {
+        if !slow_pages.is_empty() {
+            eprintln!(
+                "[tern-book] warning: {} pages are likely to be slow on the device; --analyze lists them",
+                slow_pages.len()
+            );
+        }
+        return;
+    }
+    for slow in slow_pages {
+        eprintln!("[tern-book] slow page {} at size {}: {}", slow.page + 1, slow.size, slow.describe());
+    }
+    if slow_pages.is_empty() {
+        eprintln!("[tern-book] no pages likely to be slow on the device");
+    }
+}
+
+/// Converts every book under `in_dir` into the same folders under `out_dir`,
+/// skipping books whose output is newer, and writes a report of the run.
+fn convert_dir(in_dir: &Path, out_dir: &Path, mut options: Options) {
+    let per_book = [
+        ("--format", options.format.take().is_some()),
+        ("--title", options.title.take().is_some()),
+        ("--author", options.author.take().is_some()),
+        ("--series", options.series.take().is_some()),
+        ("--cover", options.cover.take().is_some()),
+        ("--lock-layout", options.lock_layout.take().is_some()),
+    ];
+    for (flag, given) in per_book {
+        if given {
+            eprintln!("[tern-book] warning: {flag} applies to single books; convert-dir ignores it");
+        }
+    }
+
+    let books = match tern_book::library::find_books(in_dir, out_dir) {
+        Ok(books) => books,
+        Err(err) => {
+            eprintln!("Failed to read {}: {err}", in_dir.display());
+            std::process::exit(1);
+        }
+    };
+    let name = |path: &Path| path.strip_prefix(in_dir).unwrap_or(path).display().to_string();
+    let journal_path = out_dir.join(JOURNAL_NAME);
+    let mut finished = if options.resume { read_journal(&journal_path) } else { HashMap::new() };
+    if !options.resume || finished.is_empty() {
+        let _ = std::fs::remove_file(&journal_path);
+    } else {
+        eprintln!("[tern-book] resuming: {} books were finished before", finished.len());
+    }
+    let mut report = Vec::new();
+    let (mut converted, mut current, mut skipped, mut failed) = (0, 0, 0, 0);
+    for (index, book) in books.iter().enumerate() {
+        let book_name = name(&book.input);
+        let lines = match finished.remove(&book_name) {
+            Some(lines) => lines,
+            None => {
+                let lines = convert_library_book(book, &options, index, books.len(), &name);
+                append_journal(&journal_path, &book_name, &lines);
+                lines
+            }
+        };
+        match lines.first().and_then(|line| line.split(' ').next()) {
+            Some("converted") => converted += 1,
+            Some("up") => current += 1,
+            Some("skipped") => skipped += 1,
+            _ => failed += 1,
+        }
+        report.extend(lines);
+    }
+
+    let summary = format!("{converted} converted, {current} up to date, {skipped} skipped, {failed} failed");
+    let report_path = out_dir.join(REPORT_NAME);
+    let text = format!("tern-book convert-dir {}\n{summary}\n\n{}\n", in_dir.display(), report.join("\n"));
+    if let Err(err) = std::fs::create_dir_all(out_dir).and_then(|()| std::fs::write(&report_path, text)) {
+        eprintln!("[tern-book] warning: failed to write {}: {err}", report_path.display());
+    }
+    let _ = std::fs::remove_file(&journal_path);
+    println!("{summary}; report in {}", report_path.display());
+    if failed > 0 {
         std::process::exit(1);
     }
+}
 
-    println!("Wrote TRBK output(s) starting at {output}");
+/// Converts one book of a `convert-dir` run unless it is a duplicate or up to
+/// date, returning its report lines.
+fn convert_library_book(
+    book: &tern_book::library::LibraryBook,
+    options: &Options,
+    index: usize,
+    count: usize,
+    name: &dyn Fn(&Path) -> String,
+) -> Vec<String> {
+    if let Some(kept) = &book.duplicate_of {
+        return vec![format!("skipped    {}: same book as {}", name(&book.input), name(kept))];
+    }
+    if tern_book::library::is_up_to_date(&book.input, &book.output, &options.sizes) {
+        return vec![format!("up to date {}", name(&book.input))];
+    }
+    eprintln!("[tern-book] converting {} ({}/{count})", name(&book.input), index + 1);
+    match convert(&book.input, &book.output, options) {
+        Ok(missing_images) => {
+            let mut lines = vec![format!("converted  {}", name(&book.input))];
+            for image in &missing_images {
+                lines.push(format!("           missing image {}", describe_missing_image(image)));
+            }
+            lines
+        }
+        Err(err) => {
+            eprintln!("[tern-book] warning: {} failed: {err}", name(&book.input));
+            vec![format!("failed     {}: {err}", name(&book.input))]
+        }
+    }
+}
+
+/// Report lines of the books an interrupted run finished, by book name.
+fn read_journal(path: &Path) -> HashMap<String, Vec<String>> {
+    let mut finished: HashMap<String, Vec<String>> = HashMap::new();
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    for line in text.lines() {
+        if let Some((book, report_line)) = line.split_once('\t') {
+            finished.entry(book.to_string()).or_default().push(report_line.to_string());
+        }
+    }
+    finished
+}
+
+fn append_journal(path: &Path, book: &str, lines: &[String]) {
+    let entry: String = lines.iter().map(|line| format!("{book}\t{line}\n")).collect();
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::OpenOptions::new().create(true).append(true).open(path))
+        .and_then(|mut file| file.write_all(entry.as_bytes()));
+    if let Err(err) = result {
+        eprintln!("[tern-book] warning: failed to write {}: {err}", path.display());
+    }
 }
@@ -0,0 +1,1392 @@
+//! Equations from MathML or TeX runs, typeset with the book font into small
+//! Gray2 images that pages draw inline.
+//!
+//! Covers the common subset: scripts, fractions, roots, fences, limits,
+//! accents and simple tables. Anything else fails the equation, which then
+//! stays as its fallback text.
+
+use std::collections::{HashMap, HashSet};
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tern_epub::{MathNotation, MathSource};
+
+use crate::blocks::{HtmlBlock, SpineBlocks};
+use crate::fonts::{FontSet, StyleId};
+use crate::images::{trimg_to_bytes, ImageAsset, ImageRef};
+use crate::RenderOptions;
+
+/// Key of an equation in the image map filled by [`build_math_assets`].
+pub fn math_key(math: &MathSource) -> String {
+    let notation = match math.notation {
+        MathNotation::MathMl => "mathml",
+        MathNotation::Tex => "tex",
+    };
+    let mode = if math.display { "block" } else { "inline" };
+    format!("math:{notation}:{mode}:{}", math.source)
+}
+
+/// Renders every equation in `blocks` at `size` into `assets` and `image_map`.
+/// Equations that cannot be rendered are left out and counted in a warning.
+pub fn build_math_assets(
+    blocks: &[SpineBlocks],
+    fonts: &FontSet,
+    size: u16,
+    options: &RenderOptions,
+    assets: &mut Vec<ImageAsset>,
+    image_map: &mut HashMap<String, ImageRef>,
+) {
+    let mut seen = HashSet::new();
+    let mut failed = 0usize;
+    for spine in blocks {
+        for block in &spine.blocks {
+            let HtmlBlock::Paragraph { runs, .. } = block else {
+                continue;
+            };
+            for math in runs.iter().filter_map(|run| run.math.as_ref()) {
+                let key = math_key(math);
+                if !seen.insert(key.clone()) {
+                    continue;
+                }
+                let Some(rendered) = render_math(math, fonts, size, options) else {
+                    failed += 1;
+                    continue;
+                };
+                let image_ref = ImageRef {
+                    index: assets.len() as u16,
+                    width: rendered.width,
+                    height: rendered.height,
+                    baseline: rendered.baseline,
+                };
+                assets.push(ImageAsset {
+                    width: rendered.width,
+                    height: rendered.height,
+                    data: rendered.data,
+                });
+                image_map.insert(key, image_ref);
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!(
+            "[tern-book] warning: {failed} of {} equations could not be rendered and were kept as text",
+            seen.len()
+        );
+    }
+}
+
+struct RenderedMath {
+    width: u16,
+    height: u16,
+    baseline: u16,
+    /// A complete TRI file.
+    data: Vec<u8>,
+}
+
+fn render_math(
+    math: &MathSource,
+    fonts: &FontSet,
+    size: u16,
+    options: &RenderOptions,
+) -> Option<RenderedMath> {
+    let node = match math.notation {
+        MathNotation::MathMl => parse_mathml(&math.source)?,
+        MathNotation::Tex => parse_tex(&math.source, math.display)?,
+    };
+    let setter = Typesetter {
+        regular: fonts.get(&StyleId::Regular)?,
+        italic: fonts.get(&StyleId::Italic),
+    };
+    let max_width = (options.screen_width as f32 - options.margin_x as f32 * 2.0).max(1.0);
+    let max_height = (options.screen_height as f32 - options.margin_y as f32 * 2.0).max(1.0);
+    let mut size = size as f32;
+    let mut boxed = setter.layout(&node, size)?;
+    if boxed.width > max_width {
+        // Shrink wide equations a little rather than give up on them.
+        let scale = max_width / boxed.width;
+        if scale < 0.6 {
+            return None;
+        }
+        size = (size * scale).floor();
+        boxed = setter.layout(&node, size)?;
+    }
+    if boxed.width <= 0.0 || boxed.width > max_width || boxed.ascent + boxed.descent > max_height {
+        return None;
+    }
+    Some(rasterize(&boxed))
+}
+
+fn rasterize(boxed: &MathBox<'_>) -> RenderedMath {
+    let pad = 1i32;
+    let ascent = boxed.ascent.max(0.0).ceil() as i32;
+    let descent = boxed.descent.max(0.0).ceil() as i32;
+    let width = boxed.width.ceil() as i32 + pad * 2;
+    let height = ascent + descent + pad * 2;
+    let baseline = pad + ascent;
+    let mut coverage = vec![0u8; (width * height) as usize];
+    let mut blend = |x: i32, y: i32, value: u8| {
+        if x >= 0 && y >= 0 && x < width && y < height {
+            let idx = (y * width + x) as usize;
+            coverage[idx] = coverage[idx].max(value);
+        }
+    };
+    for item in &boxed.items {
+        match item {
+            Item::Glyph { font, ch, size, x, y } => {
+                let (metrics, bitmap) = font.rasterize(*ch, *size);
+                let left = (pad as f32 + x).round() as i32 + metrics.xmin;
+                let top = baseline + y.round() as i32 - (metrics.ymin + metrics.height as i32);
+                for row in 0..metrics.height {
+                    for col in 0..metrics.width {
+                        blend(left + col as i32, top + row as i32, bitmap[row * metrics.width + col]);
+                    }
+                }
+            }
+            Item::Rule { x, y, width, height } => {
+                let x0 = (pad as f32 + x).round() as i32;
+                let y0 = (baseline as f32 + y).round() as i32;
+                let x1 = ((pad as f32 + x + width).round() as i32).max(x0 + 1);
+                let y1 = ((baseline as f32 + y + height).round() as i32).max(y0 + 1);
+                for py in y0..y1 {
+                    for px in x0..x1 {
+                        blend(px, py, 255);
+                    }
+                }
+            }
+        }
+    }
+
+    let luma = coverage.iter().map(|value| 255 - value).collect::<Vec<_>>();
+    let gray = image::GrayImage::from_raw(width as u32, height as u32, luma)
+        .expect("buffer matches image size");
+    let convert = tern_image::ConvertOptions {
+        width: width as u32,
+        height: height as u32,
+        fit: tern_image::FitMode::Contain,
+        dither: tern_image::DitherMode::None,
+        region_mode: tern_image::RegionMode::None,
+        invert: false,
+        debug: false,
+        yolo_model: None,
+        trimg_version: 2,
+        ..Default::default()
+    };
+    let trimg = tern_image::convert_image(&image::DynamicImage::ImageLuma8(gray), convert);
+    RenderedMath {
+        width: width as u16,
+        height: height as u16,
+        baseline: baseline as u16,
+        data: trimg_to_bytes(&trimg),
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Row(Vec<Node>),
+    /// Identifiers, numbers and text; single-letter identifiers are italic.
+    Text { text: String, italic: bool },
+    /// An operator, spaced by its class.
+    Op(String),
+    /// Horizontal space in em.
+    Space(f32),
+    Frac { num: Box<Node>, den: Box<Node>, rule: bool },
+    Root { body: Box<Node>, index: Option<Box<Node>> },
+    Scripts { base: Box<Node>, sub: Option<Box<Node>>, sup: Option<Box<Node>> },
+    /// Limits or accents stacked under and over the base.
+    Limits { base: Box<Node>, under: Option<Box<Node>>, over: Option<Box<Node>> },
+    Overline(Box<Node>),
+    Fenced { open: String, close: String, body: Box<Node> },
+    Table(Vec<Vec<Node>>),
+}
+
+fn text(text: &str, italic: bool) -> Node {
+    Node::Text {
+        text: text.to_string(),
+        italic,
+    }
+}
+
+/// Turns a row bracketed by matching fence operators into [`Node::Fenced`],
+/// so the fences can grow with tall contents.
+fn fence_row(mut nodes: Vec<Node>) -> Node {
+    let is_fence = |node: Option<&Node>, fences: &str| {
+        matches!(node, Some(Node::Op(op)) if op.chars().count() == 1 && fences.contains(op.as_str()))
+    };
+    if nodes.len() > 2 && is_fence(nodes.first(), "([{|‖⟨⌊⌈") && is_fence(nodes.last(), ")]}|‖⟩⌋⌉") {
+        let Some(Node::Op(close)) = nodes.pop() else {
+            unreachable!()
+        };
+        let Node::Op(open) = nodes.remove(0) else {
+            unreachable!()
+        };
+        return Node::Fenced {
+            open,
+            close,
+            body: Box::new(Node::Row(nodes)),
+        };
+    }
+    if nodes.len() == 1 {
+        return nodes.remove(0);
+    }
+    Node::Row(nodes)
+}
+
+enum Xml {
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<Xml>,
+    },
+    Text(String),
+}
+
+impl Xml {
+    fn attr(&self, key: &str) -> Option<&str> {
+        match self {
+            Xml::Element { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str()),
+            Xml::Text(_) => None,
+        }
+    }
+
+    fn elements(&self) -> Vec<&Xml> {
+        match self {
+            Xml::Element { children, .. } => children
+                .iter()
+                .filter(|child| matches!(child, Xml::Element { .. }))
+                .collect(),
+            Xml::Text(_) => Vec::new(),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Xml::Element { children, .. } => {
+                children.iter().map(Xml::text).collect::<String>().trim().to_string()
+            }
+            Xml::Text(text) => text.clone(),
+        }
+    }
+}
+
+fn parse_mathml(source: &str) -> Option<Node> {
+    let mut reader = Reader::from_str(source);
+    // The <math> element itself, rebuilt around its children.
+    let mut stack = vec![Xml::Element {
+        name: "math".to_string(),
+        attrs: Vec::new(),
+        children: Vec::new(),
+    }];
+    loop {
+        let event = reader.read_event().ok()?;
+        let is_empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = xml_local_name(e.name().as_ref());
+                let attrs = e
+                    .attributes()
+                    .filter_map(Result::ok)
+                    .map(|attr| {
+                        (
+                            xml_local_name(attr.key.as_ref()),
+                            String::from_utf8_lossy(&attr.value).to_string(),
+                        )
+                    })
+                    .collect();
+                let element = Xml::Element {
+                    name,
+                    attrs,
+                    children: Vec::new(),
+                };
+                if is_empty {
+                    push_child(&mut stack, element);
+                } else {
+                    stack.push(element);
+                }
+            }
+            Event::End(_) => {
+                if stack.len() < 2 {
+                    return None;
+                }
+                let element = stack.pop()?;
+                push_child(&mut stack, element);
+            }
+            Event::Text(e) => {
+                let decoded = e.decode().ok()?;
+                push_child(&mut stack, Xml::Text(decoded.to_string()));
+            }
+            Event::GeneralRef(e) => {
+                let resolved = match e.resolve_char_ref().ok()? {
+                    Some(ch) => ch.to_string(),
+                    None => {
+                        let name = e.decode().ok()?;
+                        named_entity(&name)?.to_string()
+                    }
+                };
+                push_child(&mut stack, Xml::Text(resolved));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if stack.len() != 1 {
+        return None;
+    }
+    mathml_node(&stack.pop()?)
+}
+
+fn push_child(stack: &mut [Xml], child: Xml) {
+    if let Some(Xml::Element { children, .. }) = stack.last_mut() {
+        children.push(child);
+    }
+}
+
+fn xml_local_name(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    match name.rsplit_once(':') {
+        Some((_, local)) => local.to_string(),
+        None => name.to_string(),
+    }
+}
+
+fn mathml_node(element: &Xml) -> Option<Node> {
+    let Xml::Element { name, .. } = element else {
+        return None;
+    };
+    let children = element.elements();
+    let child = |idx: usize| -> Option<Box<Node>> { Some(Box::new(mathml_node(children.get(idx)?)?)) };
+    let row = || -> Option<Node> {
+        Some(fence_row(children.iter().map(|child| mathml_node(child)).collect::<Option<Vec<_>>>()?))
+    };
+    let node = match name.as_str() {
+        "mi" => {
+            let content = element.text();
+            let italic = content.chars().count() == 1
+                && element.attr("mathvariant").is_none_or(|variant| variant.contains("italic"));
+            Node::Text {
+                text: content,
+                italic,
+            }
+        }
+        "mn" | "mtext" => text(&element.text(), false),
+        "ms" => text(&format!("\"{}\"", element.text()), false),
+        "mo" => Node::Op(element.text()),
+        "mspace" => Node::Space(element.attr("width").and_then(parse_em).unwrap_or(0.0)),
+        "msup" => Node::Scripts {
+            base: child(0)?,
+            sub: None,
+            sup: Some(child(1)?),
+        },
+        "msub" => Node::Scripts {
+            base: child(0)?,
+            sub: Some(child(1)?),
+            sup: None,
+        },
+        "msubsup" => Node::Scripts {
+            base: child(0)?,
+            sub: Some(child(1)?),
+            sup: Some(child(2)?),
+        },
+        // Prescripts are rare enough to leave out.
+        "mmultiscripts" => Node::Scripts {
+            base: child(0)?,
+            sub: child(1),
+            sup: child(2),
+        },
+        "mfrac" => Node::Frac {
+            num: child(0)?,
+            den: child(1)?,
+            rule: element
+                .attr("linethickness")
+                .is_none_or(|value| parse_em(value) != Some(0.0)),
+        },
+        "msqrt" => Node::Root {
+            body: Box::new(row()?),
+            index: None,
+        },
+        "mroot" => Node::Root {
+            body: child(0)?,
+            index: Some(child(1)?),
+        },
+        "mover" => Node::Limits {
+            base: child(0)?,
+            under: None,
+            over: Some(child(1)?),
+        },
+        "munder" => Node::Limits {
+            base: child(0)?,
+            under: Some(child(1)?),
+            over: None,
+        },
+        "munderover" => Node::Limits {
+            base: child(0)?,
+            under: Some(child(1)?),
+            over: Some(child(2)?),
+        },
+        "mfenced" => {
+            let separators = element
+                .attr("separators")
+                .unwrap_or(",")
+                .chars()
+                .filter(|ch| !ch.is_whitespace())
+                .collect::<Vec<_>>();
+            let mut nodes = Vec::new();
+            for (idx, item) in children.iter().enumerate() {
+                let separator = idx
+                    .checked_sub(1)
+                    .and_then(|prev| separators.get(prev).or(separators.last()));
+                if let Some(separator) = separator {
+                    nodes.push(Node::Op(separator.to_string()));
+                }
+                nodes.push(mathml_node(item)?);
+            }
+            Node::Fenced {
+                open: element.attr("open").unwrap_or("(").to_string(),
+                close: element.attr("close").unwrap_or(")").to_string(),
+                body: Box::new(Node::Row(nodes)),
+            }
+        }
+        "mtable" => {
+            let mut rows = Vec::new();
+            for row_element in &children {
+                // The first cell of a labelled row is its equation number.
+                let skip = matches!(row_element, Xml::Element { name, .. } if name == "mlabeledtr") as usize;
+                let cells = row_element
+                    .elements()
+                    .into_iter()
+                    .skip(skip)
+                    .map(mathml_node)
+                    .collect::<Option<Vec<_>>>()?;
+                rows.push(cells);
+            }
+            Node::Table(rows)
+        }
+        "semantics" | "maction" => mathml_node(children.first()?)?,
+        "annotation" | "annotation-xml" | "none" | "mprescripts" | "mphantom" => Node::Row(Vec::new()),
+        // math, mrow, mstyle, mpadded, menclose, merror, mtd and anything unknown.
+        _ => row()?,
+    };
+    Some(node)
+}
+
+fn parse_em(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let named = match value {
+        "veryverythinmathspace" => Some(1.0 / 18.0),
+        "verythinmathspace" => Some(2.0 / 18.0),
+        "thinmathspace" => Some(3.0 / 18.0),
+        "mediummathspace" => Some(4.0 / 18.0),
+        "thickmathspace" => Some(5.0 / 18.0),
+        "verythickmathspace" => Some(6.0 / 18.0),
+        _ => None,
+    };
+    if named.is_some() {
+        return named;
+    }
+    let (number, scale) = if let Some(number) = value.strip_suffix("em") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix("ex") {
+        (number, 0.5)
+    } else if let Some(number) = value.strip_suffix("px") {
+        (number, 1.0 / 16.0)
+    } else {
+        (value, 1.0)
+    };
+    number.trim().parse::<f32>().ok().map(|number| number * scale)
+}
+
+fn named_entity(name: &str) -> Option<&'static str> {
+    if let Some(resolved) = quick_xml::escape::resolve_predefined_entity(name) {
+        return Some(resolved);
+    }
+    let resolved = match name {
+        "InvisibleTimes" | "it" | "ApplyFunction" | "af" | "InvisibleComma" | "ic" => "",
+        "nbsp" | "ThinSpace" | "thinsp" => " ",
+        "minus" => "−",
+        "plusmn" | "PlusMinus" => "±",
+        "infin" => "∞",
+        "le" => "≤",
+        "ge" => "≥",
+        "ne" => "≠",
+        "rarr" | "RightArrow" => "→",
+        "larr" | "LeftArrow" => "←",
+        "middot" | "centerdot" => "·",
+        "sdot" => "⋅",
+        "int" | "Integral" => "∫",
+        "Sum" => "∑",
+        "part" | "PartialD" => "∂",
+        "radic" | "Sqrt" => "√",
+        _ => return tex_symbol(name).map(|(symbol, _)| symbol),
+    };
+    Some(resolved)
+}
+
+/// Parses TeX; `display` sets limits under and over sums rather than beside them.
+fn parse_tex(source: &str, display: bool) -> Option<Node> {
+    let mut parser = TexParser {
+        chars: source.chars().collect(),
+        pos: 0,
+        display,
+    };
+    let mut nodes = Vec::new();
+    loop {
+        nodes.extend(parser.row()?);
+        if parser.pos >= parser.chars.len() {
+            break;
+        }
+        // Line and column breaks outside an environment are run together.
+        if parser.eat("\\\\") || parser.eat("&") {
+            nodes.push(Node::Space(1.0));
+            continue;
+        }
+        return None;
+    }
+    Some(fence_row(nodes))
+}
+
+struct TexParser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+}
+
+impl TexParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        let pattern = text.chars().collect::<Vec<_>>();
+        self.chars[self.pos.min(self.chars.len())..].starts_with(&pattern)
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        if self.starts_with(text) {
+            self.pos += text.chars().count();
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Nodes up to the end of the current group, cell or row.
+    fn row(&mut self) -> Option<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let at_command = |parser: &Self, name: &str| {
+                parser.starts_with(name)
+                    && !parser
+                        .chars
+                        .get(parser.pos + name.chars().count())
+                        .is_some_and(char::is_ascii_alphabetic)
+            };
+            if self.peek().is_none_or(|ch| ch == '}' || ch == '&')
+                || self.starts_with("\\\\")
+                || at_command(self, "\\right")
+                || at_command(self, "\\end")
+            {
+                return Some(nodes);
+            }
+            let atom = self.atom()?;
+            let mut sub = None;
+            let mut sup = None;
+            loop {
+                self.skip_whitespace();
+                if self.eat("^") {
+                    sup = Some(Box::new(self.argument()?));
+                } else if self.eat("_") {
+                    sub = Some(Box::new(self.argument()?));
+                } else if self.eat("'") {
+                    sup = Some(Box::new(text("′", false)));
+                } else {
+                    break;
+                }
+            }
+            let limits = match &atom {
+                Node::Op(op) => op.chars().count() == 1 && "∑∏∐⋃⋂".contains(op.as_str()),
+                Node::Row(parts) => matches!(
+                    parts.first(),
+                    Some(Node::Text { text, .. }) if LIMIT_FUNCTIONS.contains(&text.as_str())
+                ),
+                _ => false,
+            };
+            if self.display && limits && (sub.is_some() || sup.is_some()) {
+                nodes.push(Node::Limits {
+                    base: Box::new(atom),
+                    under: sub,
+                    over: sup,
+                });
+            } else if sub.is_some() || sup.is_some() {
+                nodes.push(Node::Scripts {
+                    base: Box::new(atom),
+                    sub,
+                    sup,
+                });
+            } else {
+                nodes.push(atom);
+            }
+        }
+    }
+
+    fn group(&mut self) -> Option<Node> {
+        let nodes = self.row()?;
+        if !self.eat("}") {
+            return None;
+        }
+        Some(fence_row(nodes))
+    }
+
+    /// A braced group or a single token.
+    fn argument(&mut self) -> Option<Node> {
+        self.skip_whitespace();
+        if self.eat("{") {
+            return self.group();
+        }
+        self.atom()
+    }
+
+    /// The raw text of a braced group, for `\text{…}` and environment names.
+    fn raw_group(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if !self.eat("{") {
+            return None;
+        }
+        let mut depth = 1;
+        let mut out = String::new();
+        while let Some(ch) = self.peek() {
+            self.pos += 1;
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(out);
+                    }
+                }
+                _ => {}
+            }
+            out.push(ch);
+        }
+        None
+    }
+
+    fn atom(&mut self) -> Option<Node> {
+        let ch = self.peek()?;
+        self.pos += 1;
+        let node = match ch {
+            '{' => self.group()?,
+            '\\' => self.command()?,
+            '0'..='9' | '.' => {
+                let mut number = ch.to_string();
+                while let Some(next) = self.peek().filter(|next| next.is_ascii_digit() || *next == '.') {
+                    number.push(next);
+                    self.pos += 1;
+                }
+                text(&number, false)
+            }
+            '-' => Node::Op("−".to_string()),
+            '*' => Node::Op("∗".to_string()),
+            '+' | '=' | '<' | '>' | ',' | ';' | ':' | '!' | '?' | '(' | ')' | '[' | ']' | '/' | '|' => {
+                Node::Op(ch.to_string())
+            }
+            '~' => Node::Space(0.33),
+            _ if ch.is_alphabetic() => text(&ch.to_string(), true),
+            _ => text(&ch.to_string(), false),
+        };
+        Some(node)
+    }
+
+    fn command(&mut self) -> Option<Node> {
+        let mut name = String::new();
+        while let Some(ch) = self.peek().filter(char::is_ascii_alphabetic) {
+            name.push(ch);
+            self.pos += 1;
+        }
+        if name.is_empty() {
+            name.push(self.peek()?);
+            self.pos += 1;
+        }
+        let node = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => Node::Frac {
+                num: Box::new(self.argument()?),
+                den: Box::new(self.argument()?),
+                rule: true,
+            },
+            "binom" | "dbinom" | "tbinom" => Node::Fenced {
+                open: "(".to_string(),
+                close: ")".to_string(),
+                body: Box::new(Node::Frac {
+                    num: Box::new(self.argument()?),
+                    den: Box::new(self.argument()?),
+                    rule: false,
+                }),
+            },
+            "sqrt" => {
+                self.skip_whitespace();
+                let index = if self.eat("[") {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|ch| ch != ']') {
+                        self.pos += 1;
+                    }
+                    let source = self.chars[start..self.pos].iter().collect::<String>();
+                    if !self.eat("]") {
+                        return None;
+                    }
+                    Some(Box::new(parse_tex(&source, false)?))
+                } else {
+                    None
+                };
+                Node::Root {
+                    body: Box::new(self.argument()?),
+                    index,
+                }
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let body = self.row()?;
+                if !self.eat("\\right") {
+                    return None;
+                }
+                let close = self.delimiter()?;
+                Node::Fenced {
+                    open,
+                    close,
+                    body: Box::new(Node::Row(body)),
+                }
+            }
+            "text" | "textrm" | "textup" | "mbox" | "mathrm" | "operatorname" | "textbf" | "mathbf" => {
+                text(&self.raw_group()?, false)
+            }
+            "textit" | "mathit" => text(&self.raw_group()?, true),
+            "mathbb" => {
+                let content = self.raw_group()?;
+                let mapped = content
+                    .chars()
+                    .map(|ch| match ch {
+                        'R' => 'ℝ',
+                        'N' => 'ℕ',
+                        'Z' => 'ℤ',
+                        'Q' => 'ℚ',
+                        'C' => 'ℂ',
+                        other => other,
+                    })
+                    .collect::<String>();
+                text(&mapped, false)
+            }
+            "mathsf" | "mathcal" | "mathscr" | "mathfrak" | "boldsymbol" | "bm" | "mathnormal" => {
+                self.argument()?
+            }
+            "displaystyle" | "textstyle" | "scriptstyle" | "limits" | "nolimits" | "big" | "Big"
+            | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" => Node::Row(Vec::new()),
+            "overline" | "bar" => Node::Overline(Box::new(self.argument()?)),
+            "hat" | "widehat" | "tilde" | "widetilde" | "vec" | "dot" | "ddot" | "check" | "breve"
+            | "acute" | "grave" | "overrightarrow" => {
+                let accent = match name.as_str() {
+                    "hat" | "widehat" => "ˆ",
+                    "tilde" | "widetilde" => "˜",
+                    "vec" | "overrightarrow" => "→",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    "check" => "ˇ",
+                    "breve" => "˘",
+                    "acute" => "´",
+                    _ => "`",
+                };
+                Node::Limits {
+                    base: Box::new(self.argument()?),
+                    under: None,
+                    over: Some(Box::new(text(accent, false))),
+                }
+            }
+            "begin" => self.environment()?,
+            "," => Node::Space(0.17),
+            ":" | ">" => Node::Space(0.22),
+            ";" => Node::Space(0.28),
+            "!" => Node::Space(-0.17),
+            " " => Node::Space(0.33),
+            "quad" => Node::Space(1.0),
+            "qquad" => Node::Space(2.0),
+            "{" | "}" | "|" => Node::Op(if name == "|" { "‖".to_string() } else { name }),
+            "#" | "%" | "&" | "$" | "_" => text(&name, false),
+            _ if TEX_FUNCTIONS.contains(&name.as_str()) => {
+                Node::Row(vec![text(&name, false), Node::Space(0.17)])
+            }
+            _ => match tex_symbol(&name)? {
+                (symbol, true) => Node::Op(symbol.to_string()),
+                (symbol, false) => text(symbol, symbol.chars().all(|ch| ch.is_lowercase())),
+            },
+        };
+        Some(node)
+    }
+
+    fn delimiter(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let ch = self.peek()?;
+        self.pos += 1;
+        if ch == '.' {
+            return Some(String::new());
+        }
+        if ch != '\\' {
+            return Some(ch.to_string());
+        }
+        match self.command()? {
+            Node::Op(symbol) => Some(symbol),
+            Node::Text { text, .. } if text.chars().count() == 1 => Some(text),
+            _ => None,
+        }
+    }
+
+    fn environment(&mut self) -> Option<Node> {
+        let name = self.raw_group()?;
+        if name == "array" {
+            self.raw_group()?;
+        }
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(fence_row(self.row()?));
+            if self.eat("&") {
+                continue;
+            }
+            if self.eat("\\\\") {
+                rows.push(core::mem::take(&mut cells));
+                continue;
+            }
+            if !self.eat("\\end") || self.raw_group()? != name {
+                return None;
+            }
+            break;
+        }
+        // A trailing `\\` leaves one empty cell behind.
+        if !(cells.len() == 1 && matches!(&cells[0], Node::Row(nodes) if nodes.is_empty())) {
+            rows.push(cells);
+        }
+        let table = Node::Table(rows);
+        let (open, close) = match name.trim_end_matches('*') {
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            "matrix" | "smallmatrix" | "array" | "aligned" | "align" | "gathered" | "gather"
+            | "split" | "eqnarray" => return Some(table),
+            _ => return None,
+        };
+        Some(Node::Fenced {
+            open: open.to_string(),
+            close: close.to_string(),
+            body: Box::new(table),
+        })
+    }
+}
+
+const LIMIT_FUNCTIONS: &[&str] = &["lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd", "Pr"];
+
+const TEX_FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd",
+    "arg", "deg", "dim", "ker", "hom", "Pr",
+];
+
+/// A named TeX symbol (also used for MathML entity names) and whether it is an operator.
+fn tex_symbol(name: &str) -> Option<(&'static str, bool)> {
+    let symbol = match name {
+        "alpha" => ("α", false),
+        "beta" => ("β", false),
+        "gamma" => ("γ", false),
+        "delta" => ("δ", false),
+        "epsilon" => ("ϵ", false),
+        "varepsilon" => ("ε", false),
+        "zeta" => ("ζ", false),
+        "eta" => ("η", false),
+        "theta" => ("θ", false),
+        "vartheta" => ("ϑ", false),
+        "iota" => ("ι", false),
+        "kappa" => ("κ", false),
+        "lambda" => ("λ", false),
+        "mu" => ("μ", false),
+        "nu" => ("ν", false),
+        "xi" => ("ξ", false),
+        "pi" => ("π", false),
+        "varpi" => ("ϖ", false),
+        "rho" => ("ρ", false),
+        "varrho" => ("ϱ", false),
+        "sigma" => ("σ", false),
+        "varsigma" => ("ς", false),
+        "tau" => ("τ", false),
+        "upsilon" => ("υ", false),
+        "phi" => ("ϕ", false),
+        "varphi" => ("φ", false),
+        "chi" => ("χ", false),
+        "psi" => ("ψ", false),
+        "omega" => ("ω", false),
+        "Gamma" => ("Γ", false),
+        "Delta" => ("Δ", false),
+        "Theta" => ("Θ", false),
+        "Lambda" => ("Λ", false),
+        "Xi" => ("Ξ", false),
+        "Pi" => ("Π", false),
+        "Sigma" => ("Σ", false),
+        "Upsilon" => ("Υ", false),
+        "Phi" => ("Φ", false),
+        "Psi" => ("Ψ", false),
+        "Omega" => ("Ω", false),
+        "infty" => ("∞", false),
+        "partial" => ("∂", false),
+        "nabla" => ("∇", false),
+        "hbar" => ("ℏ", false),
+        "ell" => ("ℓ", false),
+        "emptyset" | "varnothing" => ("∅", false),
+        "forall" => ("∀", false),
+        "exists" => ("∃", false),
+        "neg" | "lnot" => ("¬", false),
+        "prime" => ("′", false),
+        "angle" => ("∠", false),
+        "triangle" => ("△", false),
+        "ldots" | "dots" => ("…", false),
+        "cdots" => ("⋯", false),
+        "vdots" => ("⋮", false),
+        "ddots" => ("⋱", false),
+        "aleph" => ("ℵ", false),
+        "Re" => ("ℜ", false),
+        "Im" => ("ℑ", false),
+        "top" => ("⊤", false),
+        "bot" => ("⊥", false),
+        "degree" => ("°", false),
+        "pm" => ("±", true),
+        "mp" => ("∓", true),
+        "times" => ("×", true),
+        "div" => ("÷", true),
+        "cdot" => ("·", true),
+        "ast" => ("∗", true),
+        "star" => ("⋆", true),
+        "circ" => ("∘", true),
+        "bullet" => ("•", true),
+        "cup" => ("∪", true),
+        "cap" => ("∩", true),
+        "wedge" | "land" => ("∧", true),
+        "vee" | "lor" => ("∨", true),
+        "oplus" => ("⊕", true),
+        "otimes" => ("⊗", true),
+        "setminus" => ("∖", true),
+        "leq" | "le" => ("≤", true),
+        "geq" | "ge" => ("≥", true),
+        "neq" | "ne" => ("≠", true),
+        "approx" => ("≈", true),
+        "equiv" => ("≡", true),
+        "sim" => ("∼", true),
+        "simeq" => ("≃", true),
+        "cong" => ("≅", true),
+        "propto" => ("∝", true),
+        "in" => ("∈", true),
+        "notin" => ("∉", true),
+        "ni" => ("∋", true),
+        "subset" => ("⊂", true),
+        "subseteq" => ("⊆", true),
+        "supset" => ("⊃", true),
+        "supseteq" => ("⊇", true),
+        "to" | "rightarrow" => ("→", true),
+        "leftarrow" | "gets" => ("←", true),
+        "Rightarrow" => ("⇒", true),
+        "Leftarrow" => ("⇐", true),
+        "leftrightarrow" => ("↔", true),
+        "Leftrightarrow" => ("⇔", true),
+        "implies" => ("⟹", true),
+        "iff" => ("⟺", true),
+        "mapsto" => ("↦", true),
+        "perp" => ("⊥", true),
+        "parallel" => ("∥", true),
+        "mid" => ("∣", true),
+        "ll" => ("≪", true),
+        "gg" => ("≫", true),
+        "sum" => ("∑", true),
+        "prod" => ("∏", true),
+        "coprod" => ("∐", true),
+        "int" => ("∫", true),
+        "iint" => ("∬", true),
+        "iiint" => ("∭", true),
+        "oint" => ("∮", true),
+        "bigcup" => ("⋃", true),
+        "bigcap" => ("⋂", true),
+        "langle" => ("⟨", true),
+        "rangle" => ("⟩", true),
+        "lfloor" => ("⌊", true),
+        "rfloor" => ("⌋", true),
+        "lceil" => ("⌈", true),
+        "rceil" => ("⌉", true),
+        "lbrace" => ("{", true),
+        "rbrace" => ("}", true),
+        "vert" | "lvert" | "rvert" => ("|", true),
+        "Vert" | "lVert" | "rVert" => ("‖", true),
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// Positions are relative to the box origin on its baseline, y pointing down.
+#[derive(Default)]
+struct MathBox<'a> {
+    width: f32,
+    ascent: f32,
+    descent: f32,
+    items: Vec<Item<'a>>,
+}
+
+enum Item<'a> {
+    Glyph {
+        font: &'a fontdue::Font,
+        ch: char,
+        size: f32,
+        x: f32,
+        y: f32,
+    },
+    /// A filled rectangle; `y` is its top edge.
+    Rule { x: f32, y: f32, width: f32, height: f32 },
+}
+
+impl<'a> MathBox<'a> {
+    fn place(&mut self, other: MathBox<'a>, dx: f32, dy: f32) {
+        self.width = self.width.max(dx + other.width);
+        self.ascent = self.ascent.max(other.ascent - dy);
+        self.descent = self.descent.max(other.descent + dy);
+        self.items.extend(other.items.into_iter().map(|item| match item {
+            Item::Glyph { font, ch, size, x, y } => Item::Glyph {
+                font,
+                ch,
+                size,
+                x: x + dx,
+                y: y + dy,
+            },
+            Item::Rule { x, y, width, height } => Item::Rule {
+                x: x + dx,
+                y: y + dy,
+                width,
+                height,
+            },
+        }));
+    }
+
+    fn rule(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.width = self.width.max(x + width);
+        self.ascent = self.ascent.max(-y);
+        self.descent = self.descent.max(y + height);
+        self.items.push(Item::Rule { x, y, width, height });
+    }
+}
+
+struct Typesetter<'a> {
+    regular: &'a fontdue::Font,
+    italic: Option<&'a fontdue::Font>,
+}
+
+const RELATIONS: &str = "=<>≤≥≠≈≡∼≃≅∝∈∉∋⊂⊆⊃⊇→←⇒⇐↔⇔⟹⟺↦∥∣≪≫:";
+const BINARY: &str = "+−±∓×÷·⋅∗⋆∘•∪∩∧∨⊕⊗∖";
+const LARGE: &str = "∑∏∐∫∬∭∮⋃⋂";
+
+fn script_size(size: f32) -> f32 {
+    (size * 0.7).max(7.0)
+}
+
+impl<'a> Typesetter<'a> {
+    fn layout(&self, node: &Node, size: f32) -> Option<MathBox<'a>> {
+        let mut out = MathBox::default();
+        match node {
+            Node::Row(children) => {
+                let mut x = 0.0;
+                for (idx, child) in children.iter().enumerate() {
+                    let boxed = match child {
+                        // A leading minus or plus is a sign, not a binary operator.
+                        Node::Op(op) => self.op(op, size, idx > 0)?,
+                        _ => self.layout(child, size)?,
+                    };
+                    let width = boxed.width;
+                    out.place(boxed, x, 0.0);
+                    x += width;
+                }
+                out.width = x;
+            }
+            Node::Text { text, italic } => return self.text(text, *italic, size),
+            Node::Op(op) => return self.op(op, size, true),
+            Node::Space(em) => out.width = em * size,
+            Node::Frac { num, den, rule } => {
+                let inner = (size * 0.8).max(8.0);
+                let num = self.layout(num, inner)?;
+                let den = self.layout(den, inner)?;
+                let thickness = (size / 16.0).round().max(1.0);
+                let axis = size * 0.28;
+                let gap = (size * 0.12).max(1.0);
+                let pad = size * 0.1;
+                let width = num.width.max(den.width) + pad * 2.0;
+                let num_dy = -(axis + thickness / 2.0 + gap + num.descent);
+                let den_dy = -axis + thickness / 2.0 + gap + den.ascent;
+                let num_x = (width - num.width) / 2.0;
+                let den_x = (width - den.width) / 2.0;
+                out.place(num, num_x, num_dy);
+                out.place(den, den_x, den_dy);
+                if *rule {
+                    out.rule(0.0, -axis - thickness / 2.0, width, thickness);
+                }
+                out.width = width;
+            }
+            Node::Root { body, index } => {
+                let body = self.layout(body, size)?;
+                let thickness = (size / 16.0).round().max(1.0);
+                let gap = (size * 0.1).max(1.0);
+                let top = body.ascent + gap + thickness;
+                let total = top + body.descent;
+                let radical = self.stretched('√', total, size)?;
+                let mut x = 0.0;
+                if let Some(index) = index {
+                    let index = self.layout(index, (size * 0.55).max(6.0))?;
+                    let index_width = index.width;
+                    let index_dy = -(body.ascent * 0.5) - index.descent;
+                    out.place(index, 0.0, index_dy);
+                    x = (index_width - radical.width * 0.5).max(0.0);
+                }
+                let radical_width = radical.width;
+                // Glyph top meets the top of the bar.
+                let radical_dy = -top + radical.ascent;
+                out.place(radical, x, radical_dy);
+                x += radical_width;
+                out.rule(x - thickness, -top, body.width + gap + thickness, thickness);
+                let body_width = body.width;
+                out.place(body, x, 0.0);
+                out.width = x + body_width + gap;
+            }
+            Node::Scripts { base, sub, sup } => {
+                let italic = matches!(base.as_ref(), Node::Text { italic: true, .. });
+                let base = self.layout(base, size)?;
+                let small = script_size(size);
+                let sub = match sub {
+                    Some(sub) => Some(self.layout(sub, small)?),
+                    None => None,
+                };
+                let sup = match sup {
+                    Some(sup) => Some(self.layout(sup, small)?),
+                    None => None,
+                };
+                let mut up = sup
+                    .as_ref()
+                    .map(|sup| (base.ascent - small * 0.4).max(size * 0.38).max(sup.descent + size * 0.12))
+                    .unwrap_or(0.0);
+                let mut down = sub
+                    .as_ref()
+                    .map(|sub| (base.descent + small * 0.1).max(size * 0.15).max(sub.ascent - size * 0.36))
+                    .unwrap_or(0.0);
+                if let (Some(sup), Some(sub)) = (&sup, &sub) {
+                    let clearance = (up - sup.descent) - (sub.ascent - down);
+                    let wanted = size * 0.12;
+                    if clearance < wanted {
+                        down += (wanted - clearance) / 2.0;
+                        up += (wanted - clearance) / 2.0;
+                    }
+                }
+                let x = base.width;
+                out.place(base, 0.0, 0.0);
+                let mut width = x;
+                if let Some(sup) = sup {
+                    let sup_x = x + if italic { size * 0.05 } else { 0.0 };
+                    width = width.max(sup_x + sup.width);
+                    out.place(sup, sup_x, -up);
+                }
+                if let Some(sub) = sub {
+                    width = width.max(x + sub.width);
+                    out.place(sub, x, down);
+                }
+                out.width = width + size * 0.05;
+            }
+            Node::Limits { base, under, over } => {
+                let base = self.layout(base, size)?;
+                let small = script_size(size);
+                let gap = size * 0.08;
+                let under = match under {
+                    Some(under) => Some(self.layout(under, small)?),
+                    None => None,
+                };
+                let over = match over {
+                    Some(over) => Some(self.layout(over, small)?),
+                    None => None,
+                };
+                let width = [Some(&base), under.as_ref(), over.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .map(|boxed| boxed.width)
+                    .fold(0.0, f32::max);
+                let (base_ascent, base_descent) = (base.ascent, base.descent);
+                let base_x = (width - base.width) / 2.0;
+                out.place(base, base_x, 0.0);
+                if let Some(over) = over {
+                    let over_x = (width - over.width) / 2.0;
+                    let over_dy = -(base_ascent + gap + over.descent);
+                    out.place(over, over_x, over_dy);
+                }
+                if let Some(under) = under {
+                    let under_x = (width - under.width) / 2.0;
+                    let under_dy = base_descent + gap + under.ascent;
+                    out.place(under, under_x, under_dy);
+                }
+                out.width = width;
+            }
+            Node::Overline(body) => {
+                let body = self.layout(body, size)?;
+                let thickness = (size / 16.0).round().max(1.0);
+                let top = body.ascent + size * 0.1 + thickness;
+                let width = body.width;
+                out.place(body, 0.0, 0.0);
+                out.rule(0.0, -top, width, thickness);
+            }
+            Node::Fenced { open, close, body } => {
+                let body = self.layout(body, size)?;
+                let height = body.ascent + body.descent;
+                let stretch = height > size * 1.2;
+                let center = (body.descent - body.ascent) / 2.0;
+                let mut x = 0.0;
+                let place_fence = |out: &mut MathBox<'a>, fence: &str, x: &mut f32| -> Option<()> {
+                    let Some(ch) = fence.chars().next() else {
+                        return Some(());
+                    };
+                    let boxed = if stretch {
+                        let boxed = self.stretched(ch, height + size * 0.1, size)?;
+                        let dy = center + (boxed.ascent - boxed.descent) / 2.0;
+                        (boxed, dy)
+                    } else {
+                        (self.text(fence, false, size)?, 0.0)
+                    };
+                    let width = boxed.0.width;
+                    out.place(boxed.0, *x, boxed.1);
+                    *x += width;
+                    Some(())
+                };
+                place_fence(&mut out, open, &mut x)?;
+                let body_width = body.width;
+                out.place(body, x, 0.0);
+                x += body_width;
+                place_fence(&mut out, close, &mut x)?;
+                out.width = x;
+            }
+            Node::Table(rows) => {
+                let cells = rows
+                    .iter()
+                    .map(|row| row.iter().map(|cell| self.layout(cell, size)).collect::<Option<Vec<_>>>())
+                    .collect::<Option<Vec<_>>>()?;
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                let mut widths = vec![0.0f32; columns];
+                for row in &cells {
+                    for (idx, cell) in row.iter().enumerate() {
+                        widths[idx] = widths[idx].max(cell.width);
+                    }
+                }
+                let column_gap = size * 0.8;
+                let row_gap = size * 0.25;
+                let metrics = cells
+                    .iter()
+                    .map(|row| {
+                        let ascent = row.iter().map(|cell| cell.ascent).fold(size * 0.7, f32::max);
+                        let descent = row.iter().map(|cell| cell.descent).fold(size * 0.2, f32::max);
+                        (ascent, descent)
+                    })
+                    .collect::<Vec<_>>();
+                let total = metrics.iter().map(|(ascent, descent)| ascent + descent).sum::<f32>()
+                    + row_gap * metrics.len().saturating_sub(1) as f32;
+                // Centre the table on the maths axis.
+                let mut y = -(total / 2.0 + size * 0.28);
+                for (row, (ascent, descent)) in cells.into_iter().zip(metrics) {
+                    let baseline = y + ascent;
+                    let mut x = 0.0;
+                    for (idx, cell) in row.into_iter().enumerate() {
+                        let cell_x = x + (widths[idx] - cell.width) / 2.0;
+                        out.place(cell, cell_x, baseline);
+                        x += widths[idx] + column_gap;
+                    }
+                    y = baseline + descent + row_gap;
+                }
+                out.width = widths.iter().sum::<f32>() + column_gap * columns.saturating_sub(1) as f32;
+            }
+        }
+        Some(out)
+    }
+
+    /// An operator with space around it by class when `spaced`. Large operators
+    /// are drawn bigger and centred on the maths axis.
+    fn op(&self, op: &str, size: f32, spaced: bool) -> Option<MathBox<'a>> {
+        let op = if op == "-" { "−" } else { op };
+        let single = op.chars().count() == 1;
+        if single && LARGE.contains(op) {
+            let boxed = self.text(op, false, size * 1.3)?;
+            let dy = -size * 0.28 + (boxed.ascent - boxed.descent) / 2.0;
+            let width = boxed.width;
+            let mut out = MathBox::default();
+            out.place(boxed, 0.0, dy);
+            out.width = width + size * 0.1;
+            return Some(out);
+        }
+        let space = if !spaced || !single {
+            0.0
+        } else if RELATIONS.contains(op) {
+            0.28
+        } else if BINARY.contains(op) {
+            0.22
+        } else {
+            0.0
+        };
+        let trailing = if op == "," || op == ";" { 0.17 } else { space };
+        let boxed = self.text(op, false, size)?;
+        let width = boxed.width;
+        let mut out = MathBox::default();
+        out.place(boxed, space * size, 0.0);
+        out.width = (space + trailing) * size + width;
+        Some(out)
+    }
+
+    /// Lays out `text`, or fails if the fonts have no glyph for one of its characters.
+    fn text(&self, text: &str, italic: bool, size: f32) -> Option<MathBox<'a>> {
+        let mut out = MathBox::default();
+        let mut x = 0.0;
+        for ch in text.chars() {
+            // Invisible times and function application.
+            if ('\u{2061}'..='\u{2064}').contains(&ch) {
+                continue;
+            }
+            let font = self.font_for(ch, italic)?;
+            let metrics = font.metrics(ch, size);
+            if !ch.is_whitespace() {
+                out.items.push(Item::Glyph {
+                    font,
+                    ch,
+                    size,
+                    x,
+                    y: 0.0,
+                });
+                out.ascent = out.ascent.max((metrics.ymin + metrics.height as i32) as f32);
+                out.descent = out.descent.max(-metrics.ymin as f32);
+            }
+            x += metrics.advance_width;
+        }
+        out.width = x;
+        Some(out)
+    }
+
+    /// `ch` scaled so its ink is about `height` tall, with its natural placement
+    /// relative to the baseline.
+    fn stretched(&self, ch: char, height: f32, size: f32) -> Option<MathBox<'a>> {
+        let font = self.font_for(ch, false)?;
+        let natural = font.metrics(ch, size).height.max(1) as f32;
+        let scaled = (size * height / natural).max(size);
+        self.text(&ch.to_string(), false, scaled)
+    }
+
+    fn font_for(&self, ch: char, italic: bool) -> Option<&'a fontdue::Font> {
+        let has = |font: &fontdue::Font| ch.is_whitespace() || font.lookup_glyph_index(ch) != 0;
+        let italic = self.italic.filter(|font| italic && has(font));
+        italic.or(Some(self.regular).filter(|font| has(font)))
+    }
+}
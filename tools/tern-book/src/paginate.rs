@@ -0,0 +1,329 @@
+//! Stage 4: break laid-out lines into pages of draw operations.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::fonts::{style_id_from_style, StyleId};
+use crate::images::ImageAsset;
+use crate::layout::{measure_token_width, AdvanceMap, LayoutItem};
+use crate::vertical;
+use crate::{RenderOptions, WritingMode};
+
+#[derive(Clone, Debug)]
+pub struct PageData {
+    pub spine_index: i32,
+    pub ops: Vec<PageOp>,
+    /// Element ids of `spine_index` whose content starts on this page.
+    pub anchors: Vec<String>,
+}
+
+/// Page of each `(spine index, element id)` that was laid out.
+pub type AnchorPageMap = HashMap<(usize, String), u32>;
+
+#[derive(Clone, Debug)]
+pub enum PageOp {
+    Text {
+        x: u16,
+        y: u16,
+        style: StyleId,
+        text: String,
+    },
+    Image {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        image_index: u16,
+    },
+}
+
+/// Starts a new page at every spine document and page break. Never returns
+/// an empty book. In vertical mode each `TextLine` is a column placed right to left.
+pub fn paginate_items(
+    items: &[LayoutItem],
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+) -> Vec<PageData> {
+    let mut pages = Vec::new();
+    let mut ops: Vec<PageOp> = Vec::new();
+    let mut anchors: Vec<String> = Vec::new();
+    // Anchors wait for the next drawn item so they land on the page it ends up on.
+    let mut pending_anchors: Vec<(i32, String)> = Vec::new();
+    let mut spine_index = -1i32;
+    let mut last_spine = -1i32;
+    // Position along the page flow: down for horizontal text, leftwards from
+    // the right edge for vertical columns.
+    let (flow_start, flow_end) = match options.writing_mode {
+        WritingMode::Horizontal => (options.margin_y, options.screen_height - options.margin_y),
+        WritingMode::VerticalRl => (options.margin_x, options.screen_width - options.margin_x),
+    };
+    let mut cursor = flow_start as i32;
+    let max_cursor = (flow_end as i32).max(1);
+    let line_height = options.line_height as i32;
+    let image_spacing = (options.line_height as i32 / 2).max(0);
+
+    let flush_page = |pages: &mut Vec<PageData>,
+                      ops: &mut Vec<PageOp>,
+                      anchors: &mut Vec<String>,
+                      spine_index: &mut i32,
+                      cursor: &mut i32| {
+        if !ops.is_empty() {
+            pages.push(PageData {
+                spine_index: *spine_index,
+                ops: core::mem::take(ops),
+                anchors: core::mem::take(anchors),
+            });
+            *spine_index = -1;
+            *cursor = flow_start as i32;
+        }
+    };
+
+    for item in items {
+        if let LayoutItem::Anchor { spine_index, id } = item {
+            pending_anchors.push((*spine_index, id.clone()));
+            continue;
+        }
+        let item_spine = match item {
+            LayoutItem::TextLine { spine_index, .. } => *spine_index,
+            LayoutItem::BlankLine { spine_index } => *spine_index,
+            LayoutItem::Image { spine_index, .. } => *spine_index,
+            LayoutItem::PageBreak { spine_index } => *spine_index,
+            LayoutItem::Anchor { spine_index, .. } => *spine_index,
+        };
+        last_spine = item_spine;
+
+        if spine_index >= 0
+            && item_spine >= 0
+            && item_spine != spine_index
+            && !ops.is_empty()
+        {
+            // Anchors after the last drawn item of a document stay on its last page.
+            take_anchors(&mut pending_anchors, spine_index, &mut anchors);
+            flush_page(&mut pages, &mut ops, &mut anchors, &mut spine_index, &mut cursor);
+        }
+
+        if spine_index < 0 {
+            spine_index = item_spine;
+        }
+
+        match item {
+            LayoutItem::PageBreak { .. } => {
+                flush_page(&mut pages, &mut ops, &mut anchors, &mut spine_index, &mut cursor);
+            }
+            LayoutItem::BlankLine { .. } => {
+                if cursor + line_height > max_cursor {
+                    flush_page(&mut pages, &mut ops, &mut anchors, &mut spine_index, &mut cursor);
+                }
+                cursor += line_height;
+            }
+            LayoutItem::TextLine {
+                runs,
+                inline_images,
+                ..
+            } => {
+                // Equations taller than the text push the line's neighbours apart.
+                let ascent = options.ascent as i32;
+                let above = inline_images
+                    .iter()
+                    .map(|(_, image)| image.baseline as i32 - ascent)
+                    .fold(0, i32::max);
+                let below = inline_images
+                    .iter()
+                    .map(|(_, image)| (image.height - image.baseline) as i32 - (line_height - ascent))
+                    .fold(0, i32::max);
+                if cursor + above + line_height + below > max_cursor {
+                    flush_page(&mut pages, &mut ops, &mut anchors, &mut spine_index, &mut cursor);
+                }
+                take_anchors(&mut pending_anchors, item_spine, &mut anchors);
+                if options.writing_mode == WritingMode::VerticalRl {
+                    let center_x = options.screen_width as i32 - cursor - line_height / 2;
+                    vertical::push_column_ops(&mut ops, runs, center_x, options, advance_map);
+                    cursor += line_height;
+                    continue;
+                }
+                cursor += above;
+                let baseline = cursor + ascent;
+                let mut pen_x = options.margin_x as i32;
+                for (idx, run) in runs.iter().enumerate() {
+                    if let Some((_, image)) = inline_images.iter().find(|(image_idx, _)| *image_idx == idx) {
+                        if runs.len() == 1 && run.math.as_ref().is_some_and(|math| math.display) {
+                            pen_x = (options.screen_width as i32 - image.width as i32) / 2;
+                        }
+                        ops.push(PageOp::Image {
+                            x: pen_x.max(0) as u16,
+                            y: (baseline - image.baseline as i32).max(0) as u16,
+                            width: image.width,
+                            height: image.height,
+                            image_index: image.index,
+                        });
+                        pen_x += image.width as i32;
+                        continue;
+                    }
+                    let style_id = style_id_from_style(run.style);
+                    ops.push(PageOp::Text {
+                        x: pen_x as u16,
+                        y: baseline as u16,
+                        style: style_id,
+                        text: run.text.clone(),
+                    });
+                    let mut adv = measure_token_width(&run.text, run.style, options, advance_map);
+                    if run.text == " " {
+                        adv += options.word_spacing as i32;
+                    }
+                    pen_x += adv;
+                }
+                cursor += line_height + below;
+            }
+            LayoutItem::Anchor { .. } => {}
+            LayoutItem::Image {
+                image_index,
+                width,
+                height,
+                ..
+            } => {
+                let extent = match options.writing_mode {
+                    WritingMode::Horizontal => *height as i32,
+                    WritingMode::VerticalRl => *width as i32,
+                };
+                if cursor + extent > max_cursor {
+                    flush_page(&mut pages, &mut ops, &mut anchors, &mut spine_index, &mut cursor);
+                }
+                take_anchors(&mut pending_anchors, item_spine, &mut anchors);
+                let (x, y) = match options.writing_mode {
+                    WritingMode::Horizontal => (0, cursor),
+                    WritingMode::VerticalRl => {
+                        ((options.screen_width as i32 - cursor - extent).max(0), options.margin_y as i32)
+                    }
+                };
+                ops.push(PageOp::Image {
+                    x: x as u16,
+                    y: y as u16,
+                    width: *width,
+                    height: *height,
+                    image_index: *image_index,
+                });
+                cursor += extent + image_spacing;
+            }
+        }
+    }
+
+    if !ops.is_empty() {
+        // A page started by an overflowing last item has not picked up its spine yet.
+        if spine_index < 0 {
+            spine_index = last_spine;
+        }
+        take_anchors(&mut pending_anchors, spine_index, &mut anchors);
+        pages.push(PageData {
+            spine_index,
+            ops,
+            anchors,
+        });
+    }
+    if pages.is_empty() {
+        pages.push(PageData {
+            spine_index: -1,
+            ops: vec![PageOp::Text {
+                x: options.margin_x,
+                y: (options.margin_y as i32 + options.ascent as i32) as u16,
+                style: StyleId::Regular,
+                text: "(empty)".to_string(),
+            }],
+            anchors: Vec::new(),
+        });
+    }
+    pages
+}
+
+/// Moves pending anchors of `spine_index` onto the current page. Anchors of
+/// later documents stay pending; those of earlier ones had no page left and
+/// are dropped.
+fn take_anchors(pending: &mut Vec<(i32, String)>, spine_index: i32, anchors: &mut Vec<String>) {
+    pending.retain(|(spine, id)| {
+        if *spine == spine_index {
+            anchors.push(id.clone());
+        }
+        *spine > spine_index
+    });
+}
+
+/// Page ranges of at most `max_pages`, cut at chapter starts where possible.
+pub fn split_pages_into_parts(pages: &[PageData], max_pages: usize) -> Vec<Range<usize>> {
+    let mut chapter_starts = Vec::new();
+    for (idx, page) in pages.iter().enumerate() {
+        if idx == 0 || page.spine_index != pages[idx - 1].spine_index {
+            chapter_starts.push(idx);
+        }
+    }
+    let mut parts = Vec::new();
+    let mut start = 0usize;
+    for (idx, &chapter_start) in chapter_starts.iter().enumerate() {
+        let chapter_end = chapter_starts.get(idx + 1).copied().unwrap_or(pages.len());
+        if chapter_end - start > max_pages && chapter_start > start {
+            parts.push(start..chapter_start);
+            start = chapter_start;
+        }
+        // A single chapter longer than a part has to be cut mid-chapter.
+        while chapter_end - start > max_pages {
+            parts.push(start..start + max_pages);
+            start += max_pages;
+        }
+    }
+    if start < pages.len() {
+        parts.push(start..pages.len());
+    }
+    parts
+}
+
+/// Keeps only the images `pages` use and renumbers them from zero.
+pub fn remap_part_images(pages: &[PageData], assets: &[ImageAsset]) -> (Vec<PageData>, Vec<ImageAsset>) {
+    let mut remap: HashMap<u16, u16> = HashMap::new();
+    let mut part_assets = Vec::new();
+    let mut part_pages = pages.to_vec();
+    for page in &mut part_pages {
+        for op in &mut page.ops {
+            if let PageOp::Image { image_index, .. } = op {
+                if let Some(new_index) = remap.get(image_index) {
+                    *image_index = *new_index;
+                    continue;
+                }
+                let Some(asset) = assets.get(*image_index as usize) else {
+                    continue;
+                };
+                let new_index = part_assets.len() as u16;
+                part_assets.push(asset.clone());
+                remap.insert(*image_index, new_index);
+                *image_index = new_index;
+            }
+        }
+    }
+    (part_pages, part_assets)
+}
+
+/// First page of each spine document, or -1 if it produced no pages.
+pub fn compute_spine_page_map(pages: &[PageData], spine_count: usize) -> Vec<i32> {
+    let mut map = vec![-1i32; spine_count];
+    for (page_idx, page) in pages.iter().enumerate() {
+        if page.spine_index >= 0 {
+            let spine = page.spine_index as usize;
+            if spine < map.len() && map[spine] < 0 {
+                map[spine] = page_idx as i32;
+            }
+        }
+    }
+    map
+}
+
+/// Page each anchor starts on.
+pub fn compute_anchor_page_map(pages: &[PageData]) -> AnchorPageMap {
+    let mut map = HashMap::new();
+    for (page_idx, page) in pages.iter().enumerate() {
+        if page.spine_index < 0 {
+            continue;
+        }
+        for id in &page.anchors {
+            map.entry((page.spine_index as usize, id.clone()))
+                .or_insert(page_idx as u32);
+        }
+    }
+    map
+}
@@ -0,0 +1,274 @@
+//! Logical text for on-device reflow (TRBK v3).
+//!
+//! Next to the prerendered pages, a reflowable book stores its paragraphs as
+//! styled runs so the reader can wrap and paginate them again for other
+//! margins or text sizes. Each item remembers the prerendered page it starts
+//! on, which is how positions and TOC entries carry over. Covers and
+//! fixed-layout pages are kept as references to their prerendered page.
+//!
+//! The pages an item starts on are found by putting a marker anchor in front
+//! of every block before pagination; see [`mark_blocks`].
+
+use std::collections::HashMap;
+
+use crate::blocks::{HtmlBlock, SpineBlocks};
+use crate::fonts::{style_id_from_style, StyleId};
+use crate::images::ImageRef;
+use crate::math;
+use crate::paginate::PageData;
+use crate::BookError;
+
+const ITEM_PARAGRAPH: u8 = 1;
+const ITEM_IMAGE: u8 = 2;
+const ITEM_PAGE_BREAK: u8 = 3;
+const ITEM_PAGE: u8 = 4;
+const RUN_TEXT: u8 = 0;
+const RUN_IMAGE: u8 = 1;
+/// Marker anchor ids start with a byte no element id contains.
+const MARKER_PREFIX: &str = "\u{0}reflow:";
+
+#[derive(Clone, Debug)]
+pub struct TextItem {
+    /// Prerendered page the item starts on.
+    pub page: u32,
+    pub kind: TextItemKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum TextItemKind {
+    Paragraph {
+        runs: Vec<TextPiece>,
+        heading_level: Option<u8>,
+    },
+    Image(ImageRef),
+    PageBreak,
+    /// A prerendered page shown as is, such as the cover or a fixed-layout page.
+    Page,
+}
+
+#[derive(Clone, Debug)]
+pub enum TextPiece {
+    Text {
+        style: StyleId,
+        text: String,
+    },
+    /// A rendered equation. Display equations get a line of their own.
+    Image {
+        style: StyleId,
+        image: ImageRef,
+        display: bool,
+    },
+}
+
+/// Copy of `blocks` with a marker anchor in front of every paragraph and
+/// image, numbered in order. Markers take no space, so pagination is
+/// unchanged; [`take_marker_pages`] collects them again.
+pub fn mark_blocks(blocks: &[SpineBlocks]) -> Vec<SpineBlocks> {
+    let mut next = 0usize;
+    blocks
+        .iter()
+        .map(|spine| {
+            let mut marked = Vec::with_capacity(spine.blocks.len() * 2);
+            for block in &spine.blocks {
+                if matches!(block, HtmlBlock::Paragraph { .. } | HtmlBlock::Image { .. }) {
+                    marked.push(HtmlBlock::Anchor {
+                        id: format!("{MARKER_PREFIX}{next}"),
+                    });
+                    next += 1;
+                }
+                marked.push(block.clone());
+            }
+            SpineBlocks {
+                spine_index: spine.spine_index,
+                blocks: marked,
+            }
+        })
+        .collect()
+}
+
+/// Removes the markers from the pages' anchors and returns the page each
+/// marker landed on.
+pub fn take_marker_pages(pages: &mut [PageData]) -> HashMap<usize, u32> {
+    let mut marker_pages = HashMap::new();
+    for (page_index, page) in pages.iter_mut().enumerate() {
+        page.anchors.retain(|id| match id.strip_prefix(MARKER_PREFIX) {
+            Some(marker) => {
+                if let Ok(marker) = marker.parse() {
+                    marker_pages.insert(marker, page_index as u32);
+                }
+                false
+            }
+            None => true,
+        });
+    }
+    marker_pages
+}
+
+/// Items for the book's final `pages`, in reading order. Pages outside the
+/// flowing `blocks` (covers, fixed-layout items) become [`TextItemKind::Page`];
+/// every flowing document starts after a page break. `image_shift` is added
+/// to image indices, for assets inserted in front after layout.
+pub fn build_text_items(
+    blocks: &[SpineBlocks],
+    pages: &[PageData],
+    image_map: &HashMap<String, ImageRef>,
+    marker_pages: &HashMap<usize, u32>,
+    image_shift: u16,
+) -> Vec<TextItem> {
+    let mut items = Vec::new();
+    // First marker of each flowing document.
+    let mut first_marker = HashMap::new();
+    let mut next = 0usize;
+    for spine in blocks {
+        first_marker.insert(spine.spine_index, next);
+        next += spine
+            .blocks
+            .iter()
+            .filter(|block| matches!(block, HtmlBlock::Paragraph { .. } | HtmlBlock::Image { .. }))
+            .count();
+    }
+    let shifted = |image: &ImageRef| ImageRef {
+        index: image.index + image_shift,
+        ..*image
+    };
+    let mut last_spine = None;
+    for (page_index, page) in pages.iter().enumerate() {
+        let page_index = page_index as u32;
+        let Some(spine) = blocks.iter().find(|spine| spine.spine_index == page.spine_index) else {
+            items.push(TextItem {
+                page: page_index,
+                kind: TextItemKind::Page,
+            });
+            last_spine = None;
+            continue;
+        };
+        if last_spine == Some(page.spine_index) {
+            continue;
+        }
+        last_spine = Some(page.spine_index);
+        items.push(TextItem {
+            page: page_index,
+            kind: TextItemKind::PageBreak,
+        });
+        let mut marker = first_marker[&spine.spine_index];
+        let mut item_page = page_index;
+        for block in &spine.blocks {
+            let kind = match block {
+                HtmlBlock::Paragraph {
+                    runs,
+                    heading_level,
+                } => TextItemKind::Paragraph {
+                    runs: runs
+                        .iter()
+                        .map(|run| {
+                            let style = style_id_from_style(run.style);
+                            let image = run
+                                .math
+                                .as_ref()
+                                .and_then(|source| Some((source, image_map.get(&math::math_key(source))?)));
+                            match image {
+                                Some((source, image)) => TextPiece::Image {
+                                    style,
+                                    image: shifted(image),
+                                    display: source.display,
+                                },
+                                None => TextPiece::Text {
+                                    style,
+                                    text: run.text.clone(),
+                                },
+                            }
+                        })
+                        .collect(),
+                    heading_level: *heading_level,
+                },
+                HtmlBlock::Image { src, .. } => match image_map.get(src) {
+                    Some(image) => TextItemKind::Image(shifted(image)),
+                    None => {
+                        marker += 1;
+                        continue;
+                    }
+                },
+                HtmlBlock::PageBreak => TextItemKind::PageBreak,
+                HtmlBlock::Anchor { .. } => continue,
+            };
+            if !matches!(kind, TextItemKind::PageBreak) {
+                // Blocks that drew nothing keep the page of the one before.
+                if let Some(page) = marker_pages.get(&marker) {
+                    item_page = *page;
+                }
+                marker += 1;
+            }
+            items.push(TextItem {
+                page: item_page,
+                kind,
+            });
+        }
+    }
+    items
+}
+
+/// Encodes the section: a u32 offset per item, relative to the section
+/// start, then the items. Each item is its kind, heading level, two reserved
+/// bytes, its prerendered page (u32) and payload length (u32), then the
+/// payload.
+pub fn serialize_text_section(items: &[TextItem]) -> Result<Vec<u8>, BookError> {
+    let mut lut = Vec::with_capacity(items.len() * 4);
+    let mut body = Vec::new();
+    let lut_len = items.len() * 4;
+    for item in items {
+        lut.extend_from_slice(&((lut_len + body.len()) as u32).to_le_bytes());
+        let mut payload = Vec::new();
+        let (kind, heading_level) = match &item.kind {
+            TextItemKind::Paragraph {
+                runs,
+                heading_level,
+            } => {
+                for run in runs {
+                    match run {
+                        TextPiece::Text { style, text } => {
+                            let len = u16::try_from(text.len()).map_err(|_| BookError::InvalidOutput)?;
+                            payload.push(RUN_TEXT);
+                            payload.push(*style as u8);
+                            payload.extend_from_slice(&len.to_le_bytes());
+                            payload.extend_from_slice(text.as_bytes());
+                        }
+                        TextPiece::Image {
+                            style,
+                            image,
+                            display,
+                        } => {
+                            payload.push(RUN_IMAGE);
+                            payload.push(*style as u8);
+                            payload.extend_from_slice(&10u16.to_le_bytes());
+                            write_image(&mut payload, image);
+                            payload.push(*display as u8);
+                            payload.push(0);
+                        }
+                    }
+                }
+                (ITEM_PARAGRAPH, heading_level.unwrap_or(0))
+            }
+            TextItemKind::Image(image) => {
+                write_image(&mut payload, image);
+                (ITEM_IMAGE, 0)
+            }
+            TextItemKind::PageBreak => (ITEM_PAGE_BREAK, 0),
+            TextItemKind::Page => (ITEM_PAGE, 0),
+        };
+        body.push(kind);
+        body.push(heading_level);
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&item.page.to_le_bytes());
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(&payload);
+    }
+    lut.extend_from_slice(&body);
+    Ok(lut)
+}
+
+fn write_image(out: &mut Vec<u8>, image: &ImageRef) {
+    out.extend_from_slice(&image.index.to_le_bytes());
+    out.extend_from_slice(&image.width.to_le_bytes());
+    out.extend_from_slice(&image.height.to_le_bytes());
+    out.extend_from_slice(&image.baseline.to_le_bytes());
+}
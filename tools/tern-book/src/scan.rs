@@ -0,0 +1,147 @@
+//! Scanned pages, as found in DjVu files and image-only PDFs.
+//!
+//! A backend hands [`crate::fixed`] each page as one raster image through
+//! [`crate::input::BookInput::page_images`], after [`prepare_page`] has
+//! optionally trimmed the paper margins and cut two-page spreads in half. The
+//! pages are then thresholded with [`tern_image::RegionMode::Crisp`] so text
+//! stays sharp while photos keep their dither.
+
+use image::GrayImage;
+
+/// Rows or columns with less ink than this share are treated as blank paper.
+const MIN_INK: f32 = 0.004;
+/// Rows or columns with more ink than this share are the dark edge around the
+/// paper, not content.
+const MAX_INK: f32 = 0.85;
+/// A trimmed page keeps this share of its size as margin on each side.
+const CROP_PADDING: f32 = 0.02;
+/// Pages wider than this times their height are taken to be spreads.
+const SPREAD_RATIO: f32 = 1.1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Trim the blank paper around the text block.
+    pub crop_margins: bool,
+    /// Cut landscape scans of two facing pages into a left and a right page.
+    pub split_spreads: bool,
+}
+
+/// Turns one scanned page into the pages to show, left to right, with the
+/// paper brought up to white.
+pub fn prepare_page(mut page: GrayImage, options: ScanOptions) -> Vec<GrayImage> {
+    whiten_paper(&mut page);
+    let halves = if options.split_spreads {
+        match split_spread(&page) {
+            Some((left, right)) => vec![left, right],
+            None => vec![page],
+        }
+    } else {
+        vec![page]
+    };
+    if options.crop_margins {
+        halves.iter().map(crop_margins).collect()
+    } else {
+        halves
+    }
+}
+
+/// The page cut to the bounding box of its content. Pages that look blank, or
+/// would lose most of their area, are returned unchanged.
+pub fn crop_margins(page: &GrayImage) -> GrayImage {
+    let (width, height) = page.dimensions();
+    if width == 0 || height == 0 {
+        return page.clone();
+    }
+    let threshold = tern_image::otsu_threshold(page);
+    let (rows, columns) = ink_profile(page, threshold);
+    let (Some((top, bottom)), Some((left, right))) = (
+        content_span(&rows, width),
+        content_span(&columns, height),
+    ) else {
+        return page.clone();
+    };
+    let pad_x = (width as f32 * CROP_PADDING) as u32;
+    let pad_y = (height as f32 * CROP_PADDING) as u32;
+    let x0 = left.saturating_sub(pad_x);
+    let y0 = top.saturating_sub(pad_y);
+    let x1 = (right + 1 + pad_x).min(width);
+    let y1 = (bottom + 1 + pad_y).min(height);
+    // A box this small is more likely a smudge than the text block.
+    if (x1 - x0) * 5 < width || (y1 - y0) * 5 < height {
+        return page.clone();
+    }
+    image::imageops::crop_imm(page, x0, y0, x1 - x0, y1 - y0).to_image()
+}
+
+/// Splits a landscape scan at the lightest column near its middle, which is
+/// where the gutter between the two pages falls.
+pub fn split_spread(page: &GrayImage) -> Option<(GrayImage, GrayImage)> {
+    let (width, height) = page.dimensions();
+    if (width as f32) < height as f32 * SPREAD_RATIO {
+        return None;
+    }
+    let threshold = tern_image::otsu_threshold(page);
+    let (_, columns) = ink_profile(page, threshold);
+    let from = (width as usize * 2) / 5;
+    let to = (width as usize * 3) / 5;
+    // Averaged over a few columns so one clean column inside a line of text
+    // does not win over the gutter.
+    let window = (width as usize / 100).max(1);
+    let ink_near = |x: usize| -> u32 {
+        columns[x.saturating_sub(window)..(x + window + 1).min(columns.len())]
+            .iter()
+            .sum()
+    };
+    let middle = width as usize / 2;
+    let gutter = (from..to).min_by_key(|&x| (ink_near(x), x.abs_diff(middle)))? as u32;
+    let left = image::imageops::crop_imm(page, 0, 0, gutter, height).to_image();
+    let right = image::imageops::crop_imm(page, gutter, 0, width - gutter, height).to_image();
+    Some((left, right))
+}
+
+/// Stretches the levels so the typical paper tone becomes white; otherwise
+/// tinted or yellowed paper is dithered into a dot pattern on the panel.
+fn whiten_paper(page: &mut GrayImage) {
+    let threshold = tern_image::otsu_threshold(page);
+    let mut paper = page
+        .pixels()
+        .map(|pixel| pixel.0[0])
+        .filter(|lum| *lum >= threshold)
+        .collect::<Vec<_>>();
+    if paper.is_empty() {
+        return;
+    }
+    let mid = paper.len() / 2;
+    let paper = *paper.select_nth_unstable(mid).1 as u32;
+    if paper == 0 || paper >= 255 {
+        return;
+    }
+    for pixel in page.pixels_mut() {
+        pixel.0[0] = (pixel.0[0] as u32 * 255 / paper).min(255) as u8;
+    }
+}
+
+/// Dark pixel counts of each row and each column.
+fn ink_profile(page: &GrayImage, threshold: u8) -> (Vec<u32>, Vec<u32>) {
+    let (width, height) = page.dimensions();
+    let mut rows = vec![0u32; height as usize];
+    let mut columns = vec![0u32; width as usize];
+    for (x, y, pixel) in page.enumerate_pixels() {
+        if pixel.0[0] < threshold {
+            rows[y as usize] += 1;
+            columns[x as usize] += 1;
+        }
+    }
+    (rows, columns)
+}
+
+/// First and last line of `counts` holding content, out of `length` pixels
+/// each.
+fn content_span(counts: &[u32], length: u32) -> Option<(u32, u32)> {
+    let min = ((length as f32 * MIN_INK) as u32).max(2);
+    let max = (length as f32 * MAX_INK) as u32;
+    let is_content = |count: &u32| *count >= min && *count <= max;
+    let first = counts.iter().position(is_content)?;
+    let last = counts.iter().rposition(is_content)?;
+    Some((first as u32, last as u32))
+}
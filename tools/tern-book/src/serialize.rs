@@ -0,0 +1,445 @@
+//! Stage 5: encode a rendered book as TRBK.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::fontpack::FontPackRef;
+use crate::fonts::{Glyph, StyleId};
+use crate::images::{ImageAsset, MissingImage};
+use crate::paginate::{PageData, PageOp};
+use crate::reflow::{serialize_text_section, TextItem};
+use crate::toc::TrbkTocEntry;
+use crate::{BookError, RenderOptions, TrbkMetadata};
+
+/// Header flag: glyph records may point at an earlier glyph's bitmap.
+const FLAG_SHARED_GLYPHS: u8 = 0x04;
+/// Set in a glyph's bitmap length to make the rest of it a glyph index.
+const SHARED_GLYPH_REF: u32 = 0x8000_0000;
+/// Header flag: the book names a font pack some glyph bitmaps come from.
+const FLAG_FONT_PACK: u8 = 0x08;
+/// Bitmap length of a glyph left to the font pack.
+const FONT_PACK_GLYPH: u32 = 0xFFFF_FFFF;
+/// Capability bits at header offset 0x24; the low half are features a reader
+/// must have to open the book, the high half extras it may go without.
+pub const CAP_SHARED_GLYPHS: u32 = 0x0000_0001;
+pub const CAP_FONT_PACK: u32 = 0x0000_0002;
+pub const CAP_COMPRESSION: u32 = 0x0000_0004;
+pub const CAP_REFLOW_TEXT: u32 = 0x0001_0000;
+pub const CAP_KERNING: u32 = 0x0002_0000;
+pub const CAPS_REQUIRED: u32 = 0x0000_FFFF;
+/// Bytes in a glyph record before the bitmap.
+const GLYPH_HEADER_LEN: usize = 4 + 1 + 1 + 1 + 2 + 2 + 2 + 4;
+
+/// Everything that goes into one TRBK file.
+#[derive(Clone, Debug)]
+pub struct RenderedBook {
+    pub metadata: TrbkMetadata,
+    pub options: RenderOptions,
+    pub pages: Vec<PageData>,
+    pub glyphs: Vec<Glyph>,
+    pub toc: Vec<TrbkTocEntry>,
+    pub images: Vec<ImageAsset>,
+    /// Font pack whose glyphs are left out of the glyph table.
+    pub font_pack: Option<FontPackRef>,
+    /// Logical text for on-device reflow; books with it are written as v3.
+    pub text: Option<Vec<TextItem>>,
+    /// Images shown as a placeholder; not written to the file.
+    pub missing_images: Vec<MissingImage>,
+}
+
+/// Where a glyph record takes its bitmap from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlyphBitmap {
+    Inline,
+    /// The bitmap of the earlier glyph at this index.
+    Shared(u32),
+    FontPack,
+}
+
+/// Writes the book straight to `path`, section by section, so image-heavy
+/// books are never held in memory as one buffer.
+pub fn write_trbk(path: &Path, book: &RenderedBook) -> Result<(), BookError> {
+    write_atomically(path, |writer| write_trbk_to(writer, book))
+}
+
+/// Writes to `path` with `.tmp` appended and renames the file into place once
+/// `write` succeeds, so an interrupted conversion never leaves a truncated
+/// file that looks finished.
+pub(crate) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), BookError>,
+) -> Result<(), BookError> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = File::create(&temp).map_err(BookError::from).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(std::fs::rename(&temp, path)?),
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+pub fn serialize_trbk(book: &RenderedBook) -> Result<Vec<u8>, BookError> {
+    let mut file = Vec::new();
+    write_trbk_to(&mut file, book)?;
+    Ok(file)
+}
+
+/// Every section size is worked out first so the header can be written with
+/// final offsets, then the sections follow in file order.
+pub fn write_trbk_to<W: Write>(file: &mut W, book: &RenderedBook) -> Result<(), BookError> {
+    let RenderedBook {
+        metadata,
+        options,
+        pages,
+        glyphs,
+        toc: toc_entries,
+        images: image_assets,
+        font_pack,
+        text,
+        missing_images: _,
+    } = book;
+
+    let toc_count: u32 = toc_entries.len() as u32;
+    let page_count = pages.len() as u32;
+    let glyph_count = glyphs.len() as u32;
+    let image_count = image_assets.len() as u32;
+    let bitmaps = glyph_bitmaps(glyphs, font_pack.as_ref());
+
+    // Version 3 adds the text section offset and item count.
+    let (version, fixed_header_size): (u8, u16) = if text.is_some() { (3, 0x38) } else { (2, 0x30) };
+
+    let mut metadata_bytes = Vec::new();
+    write_string(&mut metadata_bytes, &metadata.title)?;
+    write_string(&mut metadata_bytes, &metadata.author)?;
+    write_string(&mut metadata_bytes, &metadata.language)?;
+    write_string(&mut metadata_bytes, &metadata.identifier)?;
+    write_string(&mut metadata_bytes, "fontdue")?;
+    metadata_bytes.extend_from_slice(&options.char_width.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.line_height.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.ascent.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.margin_x.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.margin_x.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.margin_y.to_le_bytes());
+    metadata_bytes.extend_from_slice(&options.margin_y.to_le_bytes());
+    if let Some(part) = &metadata.part {
+        metadata_bytes.extend_from_slice(&part.index.to_le_bytes());
+        metadata_bytes.extend_from_slice(&part.count.to_le_bytes());
+        write_string(&mut metadata_bytes, &part.next)?;
+    }
+    if let Some(series) = &metadata.series {
+        write_string(&mut metadata_bytes, series)?;
+    }
+    if let Some(pack) = font_pack {
+        write_string(&mut metadata_bytes, &pack.name)?;
+        metadata_bytes.extend_from_slice(&pack.hash.to_le_bytes());
+    }
+
+    let header_size: u16 = fixed_header_size + metadata_bytes.len() as u16;
+    let toc_offset: u32 = header_size as u32;
+    let mut toc_bytes = Vec::new();
+    for entry in toc_entries {
+        write_string(&mut toc_bytes, &entry.title)?;
+        toc_bytes.extend_from_slice(&entry.page_index.to_le_bytes());
+        toc_bytes.push(entry.level);
+        toc_bytes.push(0);
+        toc_bytes.extend_from_slice(&0u16.to_le_bytes());
+    }
+    let page_lut_offset: u32 = toc_offset + toc_bytes.len() as u32;
+
+    let page_data_len: usize = pages.iter().flat_map(|page| &page.ops).map(op_len).sum();
+    let page_data_offset = page_lut_offset + page_count * 4;
+    let glyph_table_offset = page_data_offset + page_data_len as u32;
+    let glyph_table_end = glyph_table_offset + glyphs_serialized_len(glyphs, &bitmaps) as u32;
+    let (images_offset, text_offset) = if image_count > 0 {
+        (glyph_table_end, glyph_table_end + image_table_len(image_assets) as u32)
+    } else {
+        (0, glyph_table_end)
+    };
+
+    file.write_all(b"TRBK")?;
+    file.write_all(&[version])?;
+    let mut flags: u8 = 0;
+    let mut capabilities: u32 = 0;
+    if metadata.part.is_some() {
+        flags |= 0x01;
+    }
+    if metadata.series.is_some() {
+        flags |= 0x02;
+    }
+    if bitmaps.iter().any(|bitmap| matches!(bitmap, GlyphBitmap::Shared(_))) {
+        flags |= FLAG_SHARED_GLYPHS;
+        capabilities |= CAP_SHARED_GLYPHS;
+    }
+    if font_pack.is_some() {
+        flags |= FLAG_FONT_PACK;
+        capabilities |= CAP_FONT_PACK;
+    }
+    if text.is_some() {
+        capabilities |= CAP_REFLOW_TEXT;
+    }
+    file.write_all(&[flags])?; // flags
+    file.write_all(&header_size.to_le_bytes())?;
+    file.write_all(&options.screen_width.to_le_bytes())?;
+    file.write_all(&options.screen_height.to_le_bytes())?;
+    file.write_all(&page_count.to_le_bytes())?;
+    file.write_all(&toc_count.to_le_bytes())?;
+    file.write_all(&page_lut_offset.to_le_bytes())?;
+    file.write_all(&toc_offset.to_le_bytes())?;
+    file.write_all(&page_data_offset.to_le_bytes())?;
+    file.write_all(&images_offset.to_le_bytes())?;
+    file.write_all(&capabilities.to_le_bytes())?;
+    file.write_all(&glyph_count.to_le_bytes())?;
+    file.write_all(&glyph_table_offset.to_le_bytes())?;
+    if let Some(items) = text {
+        // The text section goes last.
+        file.write_all(&text_offset.to_le_bytes())?;
+        file.write_all(&(items.len() as u32).to_le_bytes())?;
+    }
+
+    file.write_all(&metadata_bytes)?;
+
+    if toc_count != 0 {
+        file.write_all(&toc_bytes)?;
+    }
+    let mut page_start = 0u32;
+    for page in pages {
+        file.write_all(&page_start.to_le_bytes())?;
+        page_start += page.ops.iter().map(op_len).sum::<usize>() as u32;
+    }
+    for op in pages.iter().flat_map(|page| &page.ops) {
+        write_op(file, op)?;
+    }
+    write_glyph_table(file, glyphs, &bitmaps)?;
+    if image_count > 0 {
+        write_image_table(file, image_assets)?;
+    }
+    if let Some(items) = text {
+        file.write_all(&serialize_text_section(items)?)?;
+    }
+    Ok(())
+}
+
+/// Bytes `write_op` writes for `op`: kind, payload length, payload.
+fn op_len(op: &PageOp) -> usize {
+    let payload_len = match op {
+        PageOp::Text { text, .. } => 2 + 2 + 1 + 1 + text.len(),
+        PageOp::Image { .. } => 2 + 2 + 2 + 2 + 2 + 2,
+    };
+    1 + 2 + payload_len
+}
+
+fn write_op<W: Write>(writer: &mut W, op: &PageOp) -> Result<(), BookError> {
+    let length = (op_len(op) - 3) as u16;
+    match op {
+        PageOp::Text { x, y, style, text } => {
+            writer.write_all(&[0x01])?;
+            writer.write_all(&length.to_le_bytes())?;
+            writer.write_all(&x.to_le_bytes())?;
+            writer.write_all(&y.to_le_bytes())?;
+            writer.write_all(&[*style as u8, 0])?;
+            writer.write_all(text.as_bytes())?;
+        }
+        PageOp::Image {
+            x,
+            y,
+            width,
+            height,
+            image_index,
+        } => {
+            writer.write_all(&[0x02])?;
+            writer.write_all(&length.to_le_bytes())?;
+            writer.write_all(&x.to_le_bytes())?;
+            writer.write_all(&y.to_le_bytes())?;
+            writer.write_all(&width.to_le_bytes())?;
+            writer.write_all(&height.to_le_bytes())?;
+            writer.write_all(&image_index.to_le_bytes())?;
+            writer.write_all(&0u16.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<(), BookError> {
+    let bytes = value.as_bytes();
+    let len = bytes.len() as u32;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+pub(crate) fn write_glyph_table<W: Write>(
+    writer: &mut W,
+    glyphs: &[Glyph],
+    bitmaps: &[GlyphBitmap],
+) -> Result<(), BookError> {
+    for (glyph, bitmap) in glyphs.iter().zip(bitmaps) {
+        writer.write_all(&glyph.codepoint.to_le_bytes())?;
+        writer.write_all(&[glyph.style as u8])?;
+        writer.write_all(&[glyph.width])?;
+        writer.write_all(&[glyph.height])?;
+        writer.write_all(&glyph.x_advance.to_le_bytes())?;
+        writer.write_all(&glyph.x_offset.to_le_bytes())?;
+        writer.write_all(&glyph.y_offset.to_le_bytes())?;
+        match bitmap {
+            GlyphBitmap::Shared(index) => {
+                writer.write_all(&(SHARED_GLYPH_REF | index).to_le_bytes())?;
+                continue;
+            }
+            GlyphBitmap::FontPack => {
+                writer.write_all(&FONT_PACK_GLYPH.to_le_bytes())?;
+                continue;
+            }
+            GlyphBitmap::Inline => {}
+        }
+        writer.write_all(&(bitmap_len(glyph) as u32).to_le_bytes())?;
+        writer.write_all(&glyph.bitmap_bw)?;
+        writer.write_all(&glyph.bitmap_lsb)?;
+        writer.write_all(&glyph.bitmap_msb)?;
+    }
+    Ok(())
+}
+
+fn bitmap_len(glyph: &Glyph) -> usize {
+    glyph.bitmap_bw.len() + glyph.bitmap_lsb.len() + glyph.bitmap_msb.len()
+}
+
+/// For each glyph, whether its bitmap is left to `font_pack` or reuses the
+/// first earlier glyph with the same size and bitmap. Bold and italic sets
+/// built from the same font share most punctuation and digits this way.
+pub fn glyph_bitmaps(glyphs: &[Glyph], font_pack: Option<&FontPackRef>) -> Vec<GlyphBitmap> {
+    let mut first = HashMap::new();
+    glyphs
+        .iter()
+        .enumerate()
+        .map(|(index, glyph)| {
+            if bitmap_len(glyph) == 0 {
+                return GlyphBitmap::Inline;
+            }
+            if font_pack.is_some_and(|pack| pack.contains(glyph)) {
+                return GlyphBitmap::FontPack;
+            }
+            let key = (
+                glyph.width,
+                glyph.height,
+                &glyph.bitmap_bw,
+                &glyph.bitmap_lsb,
+                &glyph.bitmap_msb,
+            );
+            match first.get(&key) {
+                Some(&source) => GlyphBitmap::Shared(source),
+                None => {
+                    first.insert(key, index as u32);
+                    GlyphBitmap::Inline
+                }
+            }
+        })
+        .collect()
+}
+
+fn glyphs_serialized_len(glyphs: &[Glyph], bitmaps: &[GlyphBitmap]) -> usize {
+    glyphs
+        .iter()
+        .zip(bitmaps)
+        .map(|(glyph, bitmap)| match bitmap {
+            GlyphBitmap::Inline => GLYPH_HEADER_LEN + bitmap_len(glyph),
+            GlyphBitmap::Shared(_) | GlyphBitmap::FontPack => GLYPH_HEADER_LEN,
+        })
+        .sum()
+}
+
+/// One line per style with its glyph count and glyph table bytes, and what
+/// shared bitmaps and the font pack saved, so it is clear where a book's size
+/// goes.
+pub fn glyph_report(glyphs: &[Glyph], font_pack: Option<&FontPackRef>) -> Vec<String> {
+    let bitmaps = glyph_bitmaps(glyphs, font_pack);
+    let mut lines = Vec::new();
+    for style in [StyleId::Regular, StyleId::Bold, StyleId::Italic, StyleId::BoldItalic] {
+        let mut count = 0;
+        let mut bytes = 0;
+        let mut reused = 0;
+        let mut packed = 0;
+        for (glyph, bitmap) in glyphs.iter().zip(&bitmaps) {
+            if glyph.style != style {
+                continue;
+            }
+            count += 1;
+            bytes += GLYPH_HEADER_LEN;
+            match bitmap {
+                GlyphBitmap::Inline => bytes += bitmap_len(glyph),
+                GlyphBitmap::Shared(_) => reused += 1,
+                GlyphBitmap::FontPack => packed += 1,
+            }
+        }
+        if count == 0 {
+            continue;
+        }
+        let mut line = format!(
+            "{style:?}: {count} glyphs, {:.1} KiB ({reused} shared",
+            bytes as f64 / 1024.0
+        );
+        if font_pack.is_some() {
+            line.push_str(&format!(", {packed} in font pack"));
+        }
+        line.push(')');
+        lines.push(line);
+    }
+    let bitmap_bytes = |wanted: fn(&GlyphBitmap) -> bool| {
+        glyphs
+            .iter()
+            .zip(&bitmaps)
+            .filter(|(_, bitmap)| wanted(bitmap))
+            .map(|(glyph, _)| bitmap_len(glyph))
+            .sum::<usize>()
+    };
+    let mut total = format!(
+        "total: {} glyphs, {:.1} KiB, {:.1} KiB saved by sharing bitmaps",
+        glyphs.len(),
+        glyphs_serialized_len(glyphs, &bitmaps) as f64 / 1024.0,
+        bitmap_bytes(|bitmap| matches!(bitmap, GlyphBitmap::Shared(_))) as f64 / 1024.0
+    );
+    if let Some(pack) = font_pack {
+        total.push_str(&format!(
+            ", {:.1} KiB left to {}",
+            bitmap_bytes(|bitmap| *bitmap == GlyphBitmap::FontPack) as f64 / 1024.0,
+            pack.name
+        ));
+    }
+    lines.push(total);
+    lines
+}
+
+fn image_table_len(images: &[ImageAsset]) -> usize {
+    4 + images.len() * 16 + images.iter().map(|image| image.data.len()).sum::<usize>()
+}
+
+fn write_image_table<W: Write>(writer: &mut W, images: &[ImageAsset]) -> Result<(), BookError> {
+    let count = images.len() as u32;
+    let table_size = 4 + images.len() * 16;
+    let mut data_offset = table_size as u32;
+
+    writer.write_all(&count.to_le_bytes())?;
+    for image in images {
+        writer.write_all(&data_offset.to_le_bytes())?;
+        writer.write_all(&(image.data.len() as u32).to_le_bytes())?;
+        writer.write_all(&image.width.to_le_bytes())?;
+        writer.write_all(&image.height.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        data_offset = data_offset.saturating_add(image.data.len() as u32);
+    }
+    for image in images {
+        writer.write_all(&image.data)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Time and memory spent in each conversion stage, printed with `--timings`.
+//! Nothing is sent anywhere; the summary only goes to stderr.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the source and splitting it into blocks.
+    Parse,
+    /// Line wrapping, pagination, TOC and reflow text.
+    Layout,
+    /// Loading fonts and rasterizing glyphs, font packs included.
+    Glyphs,
+    /// Decoding and converting images, covers, math and fixed-layout pages.
+    Images,
+    /// Encoding and writing the output files.
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Parse, Stage::Layout, Stage::Glyphs, Stage::Images, Stage::Write];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Layout => "layout",
+            Stage::Glyphs => "glyphs",
+            Stage::Images => "images",
+            Stage::Write => "write",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    elapsed: [Duration; 5],
+    /// Peak resident memory of the process after each stage last ran.
+    peak_kib: [Option<u64>; 5],
+}
+
+impl Timings {
+    /// Runs `f`, adding its run time to `stage`.
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.elapsed[stage as usize] += start.elapsed();
+        self.peak_kib[stage as usize] = peak_memory_kib();
+        value
+    }
+
+    pub fn elapsed(&self, stage: Stage) -> Duration {
+        self.elapsed[stage as usize]
+    }
+
+    /// One line per stage with its time, share of the total and the peak
+    /// memory reached by then, then the total.
+    pub fn summary(&self) -> Vec<String> {
+        let total: Duration = self.elapsed.iter().sum();
+        let mut lines: Vec<String> = Stage::ALL
+            .iter()
+            .map(|&stage| {
+                let elapsed = self.elapsed(stage);
+                let share = if total.is_zero() {
+                    0.0
+                } else {
+                    elapsed.as_secs_f64() * 100.0 / total.as_secs_f64()
+                };
+                let memory = match self.peak_kib[stage as usize] {
+                    Some(kib) => format!(", peak memory {} MiB", kib / 1024),
+                    None => String::new(),
+                };
+                format!("{:<7} {:>8.2} s ({share:>4.1}%){memory}", stage.name(), elapsed.as_secs_f64())
+            })
+            .collect();
+        lines.push(format!("{:<7} {:>8.2} s", "total", total.as_secs_f64()));
+        lines
+    }
+}
+
+/// Peak resident set size of this process, where the OS reports it.
+fn peak_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
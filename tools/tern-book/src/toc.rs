@@ -0,0 +1,196 @@
+//! Table of contents, mapped from spine documents to pages.
+
+use std::collections::HashMap;
+
+use crate::input::BookInput;
+use crate::paginate::AnchorPageMap;
+
+#[derive(Clone, Debug)]
+pub struct TrbkTocEntry {
+    pub title: String,
+    pub page_index: u32,
+    pub level: u8,
+}
+
+/// Uses the input's navigation when it has entries, otherwise one entry per spine item.
+/// Entries with an anchor point at the page the anchor was laid out on.
+pub fn build_toc_entries(
+    input: &dyn BookInput,
+    spine_to_page: &[i32],
+    anchor_to_page: &AnchorPageMap,
+) -> Vec<TrbkTocEntry> {
+    let mut spine_titles: HashMap<usize, String> = HashMap::new();
+    let mut fetch_spine_title = |spine: usize| -> Option<String> {
+        if let Some(title) = spine_titles.get(&spine) {
+            return Some(title.clone());
+        }
+        let title = input.spine_title(spine);
+        if let Some(ref value) = title {
+            spine_titles.insert(spine, value.clone());
+        }
+        title
+    };
+
+    let mut entries = Vec::new();
+    for entry in input.toc() {
+        let spine = entry.spine_index;
+        if spine >= spine_to_page.len() {
+            continue;
+        }
+        let anchor_page = entry
+            .anchor
+            .as_ref()
+            .and_then(|anchor| anchor_to_page.get(&(spine, anchor.clone())));
+        let page_index = match anchor_page {
+            Some(&page) => page as i32,
+            None => spine_to_page[spine],
+        };
+        if page_index < 0 {
+            continue;
+        }
+        let mut title = entry.title;
+        if is_bad_toc_title(&title) {
+            if let Some(spine_title) = fetch_spine_title(spine) {
+                title = spine_title;
+            }
+        }
+        entries.push(TrbkTocEntry {
+            title,
+            page_index: page_index as u32,
+            level: entry.level,
+        });
+    }
+    if entries.is_empty() {
+        for (idx, spine) in input.spine().iter().enumerate() {
+            let page_index = spine_to_page.get(idx).copied().unwrap_or(-1);
+            if page_index < 0 {
+                continue;
+            }
+            let title = fetch_spine_title(idx).unwrap_or_else(|| {
+                spine
+                    .name
+                    .split('/')
+                    .last()
+                    .unwrap_or("Chapter")
+                    .to_string()
+            });
+            entries.push(TrbkTocEntry {
+                title,
+                page_index: page_index as u32,
+                level: 0,
+            });
+        }
+    }
+    entries
+}
+
+fn is_bad_toc_title(title: &str) -> bool {
+    let trimmed = title.trim();
+    if trimmed.is_empty() {
+        return true;
+    }
+    let lower = trimmed.to_ascii_lowercase();
+    if lower.contains(".xhtml") || lower.contains(".html") || lower.contains(".htm") {
+        return true;
+    }
+    false
+}
+
+pub(crate) fn title_from_blocks(blocks: &[tern_epub::HtmlBlock]) -> Option<String> {
+    for block in blocks {
+        if let tern_epub::HtmlBlock::Paragraph {
+            runs,
+            heading_level: Some(_),
+        } = block
+        {
+            if let Some(text) = text_from_runs(runs) {
+                return Some(text);
+            }
+        }
+    }
+    for block in blocks {
+        if let tern_epub::HtmlBlock::Paragraph { runs, .. } = block {
+            if let Some(text) = text_from_runs(runs) {
+                return Some(text);
+            }
+        }
+    }
+    for block in blocks {
+        if let tern_epub::HtmlBlock::Image { alt: Some(alt), .. } = block {
+            let cleaned = normalize_title(alt);
+            if !cleaned.is_empty() {
+                return Some(cleaned);
+            }
+        }
+    }
+    None
+}
+
+fn text_from_runs(runs: &[tern_epub::TextRun]) -> Option<String> {
+    let mut out = String::new();
+    for run in runs {
+        out.push_str(&run.text);
+    }
+    let cleaned = normalize_title(&out);
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+pub(crate) fn normalize_title(input: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    let mut last_space = false;
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            '&' if !in_tag => {
+                let mut entity = String::new();
+                while let Some(&c) = chars.peek() {
+                    chars.next();
+                    if c == ';' {
+                        break;
+                    }
+                    entity.push(c);
+                }
+                let decoded = match entity.as_str() {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "#39" => Some('\''),
+                    "nbsp" => Some(' '),
+                    _ => None,
+                };
+                if let Some(decoded) = decoded {
+                    if decoded.is_whitespace() {
+                        if !last_space {
+                            out.push(' ');
+                            last_space = true;
+                        }
+                    } else {
+                        out.push(decoded);
+                        last_space = false;
+                    }
+                }
+            }
+            _ if !in_tag => {
+                if ch.is_whitespace() {
+                    if !last_space {
+                        out.push(' ');
+                        last_space = true;
+                    }
+                } else {
+                    out.push(ch);
+                    last_space = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    out.trim().to_string()
+}
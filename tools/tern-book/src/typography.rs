@@ -0,0 +1,127 @@
+//! Typographic cleanup of paragraph text before layout: curly quotes, em
+//! dashes and ellipses, and with [`Typography::Lang`] the spacing rules of the
+//! book's language. See [`crate::input::InputOverrides`].
+
+use crate::blocks::{HtmlBlock, TextRun};
+
+/// How much of the text to rewrite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Typography {
+    /// Text as the book has it.
+    #[default]
+    Off,
+    /// Straight quotes to curly ones, `--` to an em dash and `...` to an
+    /// ellipsis.
+    Basic,
+    /// [`Typography::Basic`] plus the book language's spacing, so far no-break
+    /// spaces around French punctuation.
+    Lang,
+}
+
+impl Typography {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "basic" => Some(Self::Basic),
+            "lang" => Some(Self::Lang),
+            _ => None,
+        }
+    }
+}
+
+/// Spaces the layout keeps inside a word instead of breaking the line at.
+pub const NO_BREAK_SPACES: [char; 3] = ['\u{a0}', '\u{2007}', '\u{202f}'];
+
+/// Punctuation French sets off from the word before it.
+const FRENCH_SPACED: [char; 5] = [';', ':', '!', '?', '»'];
+
+/// Rewrites the text of every paragraph in `blocks`. `language` is the
+/// book's language tag, e.g. `fr-FR`.
+pub fn apply(blocks: &mut [HtmlBlock], typography: Typography, language: &str) {
+    if typography == Typography::Off {
+        return;
+    }
+    let french = typography == Typography::Lang
+        && language.get(..2).is_some_and(|code| code.eq_ignore_ascii_case("fr"));
+    for block in blocks {
+        if let HtmlBlock::Paragraph { runs, .. } = block {
+            apply_to_runs(runs, french);
+        }
+    }
+}
+
+/// Runs of one paragraph. Layout puts a space between runs, so each starts a
+/// word; quotes at the end of a run look ahead into the next one.
+fn apply_to_runs(runs: &mut [TextRun], french: bool) {
+    for index in 0..runs.len() {
+        if runs[index].math.is_some() {
+            continue;
+        }
+        let after = runs[index + 1..]
+            .iter()
+            .find_map(|run| run.text.chars().next());
+        runs[index].text = rewrite(&runs[index].text, after, french);
+    }
+}
+
+fn rewrite(text: &str, after: Option<char>, french: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut prev = ' ';
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let next = chars.peek().copied().or(after);
+        let ch = match ch {
+            '"' if opens_quote(prev, next) => '\u{201c}',
+            '"' => '\u{201d}',
+            // Elided years such as '90s take an apostrophe.
+            '\'' if opens_quote(prev, next) && !next.is_some_and(|next| next.is_ascii_digit()) => '\u{2018}',
+            '\'' => '\u{2019}',
+            '-' if chars.peek() == Some(&'-') => {
+                while chars.peek() == Some(&'-') {
+                    chars.next();
+                }
+                '\u{2014}'
+            }
+            '.' if chars.clone().take(2).eq(['.', '.']) => {
+                chars.nth(1);
+                '\u{2026}'
+            }
+            _ => ch,
+        };
+        if french {
+            if ch == ' ' && prev == '«' {
+                out.push('\u{a0}');
+                prev = '\u{a0}';
+                continue;
+            }
+            // Not at the start of the run, where layout adds its own space.
+            if FRENCH_SPACED.contains(&ch) && out.ends_with(' ') && !out.trim().is_empty() {
+                out.pop();
+                out.push('\u{a0}');
+            } else if needs_french_space(prev, ch) {
+                out.push('\u{a0}');
+            }
+        }
+        out.push(ch);
+        prev = ch;
+    }
+    out
+}
+
+/// A quote opens after a space or opening punctuation and before a word.
+fn opens_quote(prev: char, next: Option<char>) -> bool {
+    let before_word = next.is_some_and(|next| {
+        next.is_alphanumeric() || matches!(next, '(' | '[' | '"' | '\'' | '\u{201c}' | '\u{2018}')
+    });
+    let after_space =
+        prev.is_whitespace() || matches!(prev, '(' | '[' | '{' | '\u{201c}' | '\u{2018}' | '\u{2014}' | '\u{2013}');
+    before_word && after_space
+}
+
+/// Whether French wants a no-break space between `prev` and `ch` where the
+/// book has none. Colons are left alone, as times and links have them too.
+fn needs_french_space(prev: char, ch: char) -> bool {
+    let spaced = matches!(ch, ';' | '!' | '?' | '»') && prev.is_alphanumeric();
+    let after_guillemet = prev == '«' && !ch.is_whitespace();
+    spaced || after_guillemet
+}
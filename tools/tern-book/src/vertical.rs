@@ -0,0 +1,279 @@
+//! Stage 3 for [`WritingMode::VerticalRl`](crate::WritingMode): characters run
+//! top to bottom in columns that fill the page from right to left.
+//!
+//! Every character becomes its own draw op, so readers need no changes. CJK
+//! text stays upright and punctuation switches to its vertical presentation
+//! form when the font has one. Latin and other horizontal scripts are drawn
+//! turned 90° clockwise, using glyphs rasterised rotated and stored at
+//! [`ROTATED_BASE`] + codepoint.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::blocks::SpineBlocks;
+use crate::fonts::{style_id_from_style, FontSet, StyleId};
+use crate::images::ImageRef;
+use crate::layout::{layout_blocks_with, measure_token_width, AdvanceMap, LayoutItem};
+use crate::paginate::PageOp;
+use crate::RenderOptions;
+
+/// Start of Supplementary Private Use Area-A; rotated copies of BMP
+/// characters live at this offset.
+pub const ROTATED_BASE: u32 = 0xF0000;
+
+/// Characters that may not start a column. They hang below the last one instead.
+const NO_COLUMN_START: &str = "、。，．・：；？！ヽヾゝゞ々ーぁぃぅぇぉっゃゅょゎァィゥェォッャュョヮヵヶ）」』】〕〉》…‥,.;:!?)]}";
+
+/// The rotated stand-in for `ch`, if it is in the BMP.
+pub fn rotated(ch: char) -> Option<char> {
+    let codepoint = ch as u32;
+    if codepoint > 0xFFFD {
+        return None;
+    }
+    char::from_u32(ROTATED_BASE + codepoint)
+}
+
+/// Original codepoint of a rotated stand-in.
+pub fn rotated_source(codepoint: u32) -> Option<u32> {
+    (ROTATED_BASE..=ROTATED_BASE + 0xFFFD)
+        .contains(&codepoint)
+        .then(|| codepoint - ROTATED_BASE)
+}
+
+/// Codepoint to draw for `ch` in a vertical column.
+pub fn display_char(font: &fontdue::Font, ch: char) -> char {
+    if let Some(form) = vertical_form(ch) {
+        if font.lookup_glyph_index(form) != 0 {
+            return form;
+        }
+    }
+    if is_sideways(ch) {
+        if let Some(turned) = rotated(ch) {
+            return turned;
+        }
+    }
+    ch
+}
+
+/// `used` with every codepoint replaced by what [`display_char`] draws.
+pub fn vertical_codepoints(
+    fonts: &FontSet,
+    used: &HashMap<StyleId, BTreeSet<u32>>,
+) -> HashMap<StyleId, BTreeSet<u32>> {
+    let mut out = HashMap::new();
+    for (style, codepoints) in used {
+        let font = fonts.get(style).or_else(|| fonts.get(&StyleId::Regular));
+        let shown = codepoints
+            .iter()
+            .filter_map(|codepoint| char::from_u32(*codepoint))
+            .map(|ch| font.map_or(ch, |font| display_char(font, ch)) as u32)
+            .collect();
+        out.insert(*style, shown);
+    }
+    out
+}
+
+/// Offset from the baseline to the middle of the line box; rotated glyphs are
+/// centred on the column with it.
+pub fn column_center(font: &fontdue::Font, size: u16) -> i16 {
+    match font.horizontal_line_metrics(size as f32) {
+        Some(lines) => ((lines.ascent + lines.descent) / 2.0).round() as i16,
+        None => (size as f32 * 0.35).round() as i16,
+    }
+}
+
+/// Turns an 8-bit coverage bitmap a quarter turn clockwise; the result is
+/// `height` pixels wide and `width` pixels tall.
+pub fn rotate_clockwise(bitmap: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0u8; width * height];
+    for row in 0..width {
+        for col in 0..height {
+            out[row * height + col] = bitmap[(height - 1 - col) * width + row];
+        }
+    }
+    out
+}
+
+/// Like [`layout_blocks`](crate::layout::layout_blocks), but each `TextLine`
+/// is a column holding one run per character.
+pub fn layout_blocks_vertical(
+    blocks: &[SpineBlocks],
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+    image_map: &HashMap<String, ImageRef>,
+    fonts: &FontSet,
+) -> Vec<LayoutItem> {
+    let column_length = (options.screen_height as i32 - options.margin_y as i32 * 2).max(1);
+    layout_blocks_with(blocks, image_map, |runs| {
+        wrap_columns(runs, column_length, options, advance_map, fonts)
+    })
+}
+
+/// Draw ops for one column centred on `center_x`.
+pub fn push_column_ops(
+    ops: &mut Vec<PageOp>,
+    runs: &[tern_epub::TextRun],
+    center_x: i32,
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+) {
+    let mut pen_y = options.margin_y as i32;
+    for run in runs {
+        let advance = measure_token_width(&run.text, run.style, options, advance_map);
+        let sideways = run
+            .text
+            .chars()
+            .next()
+            .and_then(|ch| rotated_source(ch as u32))
+            .is_some();
+        let (x, y) = if sideways {
+            (center_x, pen_y)
+        } else {
+            (center_x - advance / 2, pen_y + options.ascent as i32)
+        };
+        ops.push(PageOp::Text {
+            x: x.max(0) as u16,
+            y: y.max(0) as u16,
+            style: style_id_from_style(run.style),
+            text: run.text.clone(),
+        });
+        pen_y += advance;
+    }
+}
+
+struct Cell {
+    source: char,
+    shown: char,
+    style: tern_epub::TextStyle,
+    advance: i32,
+}
+
+fn wrap_columns(
+    runs: &[tern_epub::TextRun],
+    column_length: i32,
+    options: &RenderOptions,
+    advance_map: &AdvanceMap,
+    fonts: &FontSet,
+) -> Vec<Vec<tern_epub::TextRun>> {
+    let mut cells = Vec::new();
+    for run in runs {
+        let style_id = style_id_from_style(run.style);
+        let font = fonts.get(&style_id).or_else(|| fonts.get(&StyleId::Regular));
+        for ch in run.text.chars() {
+            let shown = font.map_or(ch, |font| display_char(font, ch));
+            let mut buf = [0u8; 4];
+            let advance = measure_token_width(shown.encode_utf8(&mut buf), run.style, options, advance_map);
+            cells.push(Cell {
+                source: ch,
+                shown,
+                style: run.style,
+                advance,
+            });
+        }
+    }
+
+    let mut columns = Vec::new();
+    let mut column: Vec<tern_epub::TextRun> = Vec::new();
+    let mut used = 0i32;
+    let mut start = 0usize;
+    while start < cells.len() {
+        if cells[start].source == '\n' {
+            if !column.is_empty() {
+                columns.push(core::mem::take(&mut column));
+                used = 0;
+            }
+            start += 1;
+            continue;
+        }
+        let end = word_end(&cells, start);
+        let length: i32 = cells[start..end].iter().map(|cell| cell.advance).sum();
+        let hangs = end == start + 1 && NO_COLUMN_START.contains(cells[start].source);
+        // Sideways words move to the next column whole unless they are longer than one.
+        if !column.is_empty() && !hangs && length <= column_length && used + length > column_length {
+            columns.push(core::mem::take(&mut column));
+            used = 0;
+        }
+        for cell in &cells[start..end] {
+            if column.is_empty() && cell.source == ' ' {
+                continue;
+            }
+            if !column.is_empty() && !hangs && used + cell.advance > column_length {
+                columns.push(core::mem::take(&mut column));
+                used = 0;
+            }
+            column.push(tern_epub::TextRun {
+                text: cell.shown.to_string(),
+                style: cell.style,
+                math: None,
+            });
+            used += cell.advance;
+        }
+        start = end;
+    }
+    if !column.is_empty() {
+        columns.push(column);
+    }
+    columns
+}
+
+/// End of the sideways word starting at `start`, or `start + 1`.
+fn word_end(cells: &[Cell], start: usize) -> usize {
+    let in_word = |cell: &Cell| cell.source.is_alphanumeric() && is_sideways(cell.source);
+    let mut end = start + 1;
+    if in_word(&cells[start]) {
+        while end < cells.len() && in_word(&cells[end]) {
+            end += 1;
+        }
+    }
+    end
+}
+
+/// Horizontal scripts and the few full-width marks that have no upright form.
+fn is_sideways(ch: char) -> bool {
+    let codepoint = ch as u32;
+    match ch {
+        'ー' | '～' | '〜' | '－' | '＝' | '（' | '）' | '［' | '］' | '｛' | '｝' | '＜' | '＞' | '「' | '」'
+        | '『' | '』' | '【' | '】' | '〔' | '〕' | '〈' | '〉' | '《' | '》' => true,
+        // Enclosed numbers, shapes and dingbats read upright.
+        _ => {
+            codepoint < 0x2E80
+                && !(0x2460..=0x24FF).contains(&codepoint)
+                && !(0x25A0..=0x27BF).contains(&codepoint)
+        }
+    }
+}
+
+fn vertical_form(ch: char) -> Option<char> {
+    let form = match ch {
+        '，' => '\u{FE10}',
+        '、' => '\u{FE11}',
+        '。' => '\u{FE12}',
+        '：' => '\u{FE13}',
+        '；' => '\u{FE14}',
+        '！' => '\u{FE15}',
+        '？' => '\u{FE16}',
+        '…' => '\u{FE19}',
+        '‥' => '\u{FE30}',
+        '—' | '―' => '\u{FE31}',
+        '–' => '\u{FE32}',
+        '（' => '\u{FE35}',
+        '）' => '\u{FE36}',
+        '｛' => '\u{FE37}',
+        '｝' => '\u{FE38}',
+        '〔' => '\u{FE39}',
+        '〕' => '\u{FE3A}',
+        '【' => '\u{FE3B}',
+        '】' => '\u{FE3C}',
+        '《' => '\u{FE3D}',
+        '》' => '\u{FE3E}',
+        '〈' => '\u{FE3F}',
+        '〉' => '\u{FE40}',
+        '「' => '\u{FE41}',
+        '」' => '\u{FE42}',
+        '『' => '\u{FE43}',
+        '』' => '\u{FE44}',
+        '［' => '\u{FE47}',
+        '］' => '\u{FE48}',
+        _ => return None,
+    };
+    Some(form)
+}
@@ -1,9 +1,9 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use quick_xml::events::{BytesStart, Event};
+use quick_xml::events::{BytesRef, BytesStart, Event};
 use quick_xml::Reader;
 use thiserror::Error;
 
@@ -50,6 +50,7 @@ pub struct OpfManifestItem {
 pub struct OpfSpineItem {
     pub idref: String,
     pub linear: bool,
+    pub properties: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +61,8 @@ pub struct OpfPackage {
     pub nav_href: Option<String>,
     pub toc_href: Option<String>,
     pub cover_href: Option<String>,
+    /// `rendition:layout` is `pre-paginated` for the whole book.
+    pub pre_paginated: bool,
     pub opf_path: String,
     pub opf_dir: String,
 }
@@ -76,6 +79,8 @@ pub struct EpubBook {
     pub container: EpubContainer,
     pub package: OpfPackage,
     pub toc: Vec<TocEntry>,
+    /// EPUB3 `landmarks` nav entries such as the cover or start of the text.
+    pub landmarks: Vec<TocEntry>,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -88,6 +93,23 @@ pub struct TextStyle {
 pub struct TextRun {
     pub text: String,
     pub style: TextStyle,
+    /// Set for an equation; `text` is then a plain-text fallback.
+    pub math: Option<MathSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MathSource {
+    pub notation: MathNotation,
+    /// The children of the `<math>` element, or TeX without its delimiters.
+    pub source: String,
+    /// `display="block"` MathML or `$$…$$`/`\[…\]` TeX.
+    pub display: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MathNotation {
+    MathMl,
+    Tex,
 }
 
 #[derive(Debug, Clone)]
@@ -98,6 +120,8 @@ pub enum HtmlBlock {
     },
     PageBreak,
     Image { alt: Option<String>, src: String },
+    /// An element `id`, placed before the content it belongs to.
+    Anchor { id: String },
 }
 
 #[derive(Debug, Clone)]
@@ -123,6 +147,7 @@ pub struct BookCache {
     pub cover_href: Option<String>,
     pub spine: Vec<CacheSpineEntry>,
     pub toc: Vec<CacheTocEntry>,
+    pub landmarks: Vec<CacheTocEntry>,
     pub cache_path: PathBuf,
     pub source_size: u64,
     pub source_mtime: u64,
@@ -134,7 +159,7 @@ pub struct CacheStatus {
     pub cache_path: PathBuf,
 }
 
-const CACHE_VERSION: u8 = 1;
+const CACHE_VERSION: u8 = 2;
 
 pub fn open_epub<P: AsRef<Path>>(path: P) -> Result<EpubBook, EpubError> {
     let file = std::fs::File::open(path.as_ref())?;
@@ -146,20 +171,22 @@ pub fn open_epub<P: AsRef<Path>>(path: P) -> Result<EpubBook, EpubError> {
     let opf_xml = read_zip_file_to_string(&mut archive, &container.rootfile_path)?;
     let mut package = parse_opf(&opf_xml, &container.rootfile_path)?;
 
-    let toc = if let Some(nav_href) = package.nav_href.clone() {
+    let mut toc = Vec::new();
+    let mut landmarks = Vec::new();
+    if let Some(nav_href) = package.nav_href.clone() {
         let nav_path = resolve_href(&package.opf_dir, &nav_href);
         let nav_xml = read_zip_file_to_string(&mut archive, &nav_path)?;
-        match parse_nav_toc(&nav_xml, &nav_path) {
-            Ok(toc) => toc,
-            Err(_) => Vec::new(),
+        toc = parse_nav_toc(&nav_xml, &nav_path).unwrap_or_default();
+        landmarks = parse_nav_landmarks(&nav_xml, &nav_path).unwrap_or_default();
+    }
+    // EPUB3 books often ship an NCX too; use it when the nav has no TOC.
+    if toc.is_empty() {
+        if let Some(toc_href) = package.toc_href.clone() {
+            let toc_path = resolve_href(&package.opf_dir, &toc_href);
+            let toc_xml = read_zip_file_to_string(&mut archive, &toc_path)?;
+            toc = parse_ncx_toc(&toc_xml, &toc_path)?;
         }
-    } else if let Some(toc_href) = package.toc_href.clone() {
-        let toc_path = resolve_href(&package.opf_dir, &toc_href);
-        let toc_xml = read_zip_file_to_string(&mut archive, &toc_path)?;
-        parse_ncx_toc(&toc_xml, &toc_path)?
-    } else {
-        Vec::new()
-    };
+    }
 
     if package.cover_href.is_none() {
         package.cover_href = find_cover_href(&package);
@@ -169,6 +196,7 @@ pub fn open_epub<P: AsRef<Path>>(path: P) -> Result<EpubBook, EpubError> {
         container,
         package,
         toc,
+        landmarks,
     })
 }
 
@@ -182,6 +210,7 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
     let mut current_text = String::new();
     let mut current_style = TextStyle::default();
     let mut heading_level: Option<u8> = None;
+    let mut anchors: Vec<String> = Vec::new();
     let mut in_body = true;
     let mut skip_depth: usize = 0;
     let mut last_was_space = false;
@@ -204,6 +233,24 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                     continue;
                 }
 
+                if is_xml_name(name, b"math") {
+                    let display = attr_value(&e, b"display")?.as_deref() == Some("block");
+                    anchors.extend(element_anchor(&e, name)?);
+                    let source = reader.read_text(quick_xml::name::QName(name))?.into_owned();
+                    flush_text_run(&mut runs, &mut current_text, current_style, &mut last_was_space);
+                    runs.push(TextRun {
+                        text: mathml_fallback_text(&source),
+                        style: current_style,
+                        math: Some(MathSource {
+                            notation: MathNotation::MathMl,
+                            source,
+                            display,
+                        }),
+                    });
+                    buf.clear();
+                    continue;
+                }
+
                 if is_block_tag(name) {
                     flush_paragraph(
                         &mut blocks,
@@ -211,6 +258,7 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     heading_level = heading_level_from(name);
                     last_was_space = false;
@@ -221,19 +269,22 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     heading_level = None;
                     last_was_space = false;
-                } else if is_xml_name(name, b"img") {
+                } else if is_xml_name(name, b"img") || is_xml_name(name, b"image") {
                     flush_paragraph(
                         &mut blocks,
                         &mut runs,
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     let alt = attr_value(&e, b"alt")?;
-                    if let Some(src) = attr_value(&e, b"src")? {
+                    if let Some(src) = image_src(&e)? {
+                        push_anchors(&mut blocks, &mut anchors);
                         blocks.push(HtmlBlock::Image { alt, src });
                     }
                     heading_level = None;
@@ -251,11 +302,13 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     blocks.push(HtmlBlock::PageBreak);
                     heading_level = None;
                     last_was_space = false;
                 }
+                anchors.extend(element_anchor(&e, name)?);
             }
             Event::Empty(e) => {
                 let name_buf = e.name().as_ref().to_vec();
@@ -267,19 +320,22 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     heading_level = None;
                     last_was_space = false;
-                } else if is_xml_name(name, b"img") {
+                } else if is_xml_name(name, b"img") || is_xml_name(name, b"image") {
                     flush_paragraph(
                         &mut blocks,
                         &mut runs,
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     let alt = attr_value(&e, b"alt")?;
-                    if let Some(src) = attr_value(&e, b"src")? {
+                    if let Some(src) = image_src(&e)? {
+                        push_anchors(&mut blocks, &mut anchors);
                         blocks.push(HtmlBlock::Image { alt, src });
                     }
                     heading_level = None;
@@ -291,11 +347,15 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     blocks.push(HtmlBlock::PageBreak);
                     heading_level = None;
                     last_was_space = false;
                 }
+                if in_body && skip_depth == 0 {
+                    anchors.extend(element_anchor(&e, name)?);
+                }
             }
             Event::End(e) => {
                 let name_buf = e.name().as_ref().to_vec();
@@ -317,6 +377,7 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                         &mut current_text,
                         current_style,
                         heading_level,
+                        &mut anchors,
                     );
                     heading_level = None;
                     last_was_space = false;
@@ -342,6 +403,14 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
                     &mut last_was_space,
                 );
             }
+            Event::GeneralRef(e) => {
+                if !in_body || skip_depth > 0 {
+                    buf.clear();
+                    continue;
+                }
+                // `&amp;` and friends matter inside TeX, not just in prose.
+                push_normalized_text(&resolve_entity(&e)?, &mut current_text, &mut last_was_space);
+            }
             Event::Eof => break,
             _ => {}
         }
@@ -354,7 +423,9 @@ pub fn parse_xhtml_blocks(xml: &str) -> Result<Vec<HtmlBlock>, EpubError> {
         &mut current_text,
         current_style,
         heading_level,
+        &mut anchors,
     );
+    push_anchors(&mut blocks, &mut anchors);
     Ok(blocks)
 }
 
@@ -370,6 +441,131 @@ pub fn read_spine_xhtml<P: AsRef<Path>>(epub_path: P, spine_index: usize) -> Res
     read_zip_file_to_string(&mut archive, href)
 }
 
+/// True when a stylesheet or the first spine document sets
+/// `writing-mode: vertical-rl` (or its `-epub-`/`-webkit-` prefixed form).
+pub fn declares_vertical_writing<P: AsRef<Path>>(epub_path: P) -> Result<bool, EpubError> {
+    let book = open_epub(epub_path.as_ref())?;
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(epub_path.as_ref())?)?;
+    let mut sources = book
+        .package
+        .manifest
+        .iter()
+        .filter(|item| item.media_type == "text/css")
+        .map(|item| resolve_href(&book.package.opf_dir, &item.href))
+        .collect::<Vec<_>>();
+    sources.extend(build_spine_hrefs(&book.package).into_iter().take(1));
+    for path in sources {
+        let Ok(text) = read_zip_file_to_string(&mut archive, &path) else {
+            continue;
+        };
+        if has_vertical_rl(&text) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn has_vertical_rl(css: &str) -> bool {
+    let compact = css
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    compact.contains("writing-mode:vertical-rl")
+}
+
+/// Content protection a book was found to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrmScheme {
+    AdobeAdept,
+    Lcp,
+    FairPlay,
+    /// Amazon's Mobipocket encryption, found in MOBI and AZW files.
+    Kindle,
+    Unknown,
+}
+
+impl DrmScheme {
+    pub fn name(self) -> &'static str {
+        match self {
+            DrmScheme::AdobeAdept => "Adobe DRM",
+            DrmScheme::Lcp => "Readium LCP",
+            DrmScheme::FairPlay => "Apple FairPlay",
+            DrmScheme::Kindle => "Kindle DRM",
+            DrmScheme::Unknown => "an unknown DRM scheme",
+        }
+    }
+}
+
+/// Font obfuscation algorithms; these leave the text readable.
+const FONT_OBFUSCATION: &[&str] = &[
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+/// Finds encrypted content. `META-INF/encryption.xml` entries that only
+/// obfuscate fonts do not count.
+pub fn detect_drm<P: AsRef<Path>>(epub_path: P) -> Result<Option<DrmScheme>, EpubError> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(epub_path.as_ref())?)?;
+    if archive.by_name("META-INF/license.lcpl").is_ok() {
+        return Ok(Some(DrmScheme::Lcp));
+    }
+    if archive.by_name("META-INF/sinf.xml").is_ok() {
+        return Ok(Some(DrmScheme::FairPlay));
+    }
+    let Ok(xml) = read_zip_file_to_string(&mut archive, "META-INF/encryption.xml") else {
+        return Ok(None);
+    };
+    let mut reader = Reader::from_str(&xml);
+    let mut encrypted = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if is_xml_name(e.name().as_ref(), b"EncryptionMethod") => {
+                let algorithm = attr_value(&e, b"Algorithm")?.unwrap_or_default();
+                if !FONT_OBFUSCATION.contains(&algorithm.as_str()) {
+                    encrypted = true;
+                    break;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !encrypted {
+        return Ok(None);
+    }
+    if archive.by_name("META-INF/rights.xml").is_ok() || xml.contains("http://ns.adobe.com/adept") {
+        return Ok(Some(DrmScheme::AdobeAdept));
+    }
+    Ok(Some(DrmScheme::Unknown))
+}
+
+/// Whether each spine document is pre-paginated, in spine order. Spine
+/// `rendition:layout-*` properties override the book-wide `rendition:layout`.
+pub fn fixed_layout_spine<P: AsRef<Path>>(epub_path: P) -> Result<Vec<bool>, EpubError> {
+    let book = open_epub(epub_path.as_ref())?;
+    let package = &book.package;
+    let manifest_ids = package
+        .manifest
+        .iter()
+        .map(|item| item.id.as_str())
+        .collect::<HashSet<_>>();
+    Ok(package
+        .spine
+        .iter()
+        .filter(|item| manifest_ids.contains(item.idref.as_str()))
+        .map(|item| {
+            if item.properties.iter().any(|p| p == "rendition:layout-pre-paginated") {
+                true
+            } else if item.properties.iter().any(|p| p == "rendition:layout-reflowable") {
+                false
+            } else {
+                package.pre_paginated
+            }
+        })
+        .collect())
+}
+
 pub fn read_epub_resource_bytes<P: AsRef<Path>>(epub_path: P, href: &str) -> Result<Vec<u8>, EpubError> {
     let file = std::fs::File::open(epub_path.as_ref())?;
     let mut archive = zip::ZipArchive::new(file)?;
@@ -399,6 +595,7 @@ pub fn blocks_to_plain_text(blocks: &[HtmlBlock]) -> String {
                 let label = alt.as_deref().unwrap_or("image");
                 out.push_str(&format!("[Image: {label}]\n\n"));
             }
+            HtmlBlock::Anchor { .. } => {}
         }
     }
     out
@@ -414,6 +611,7 @@ pub fn blocks_to_runs(blocks: &[HtmlBlock]) -> Vec<TextRun> {
                     runs.push(TextRun {
                         text: "\n\n".to_string(),
                         style: TextStyle::default(),
+                        math: None,
                     });
                 }
                 first = false;
@@ -427,10 +625,11 @@ pub fn blocks_to_runs(blocks: &[HtmlBlock]) -> Vec<TextRun> {
                 runs.push(TextRun {
                     text: "\n\n".to_string(),
                     style: TextStyle::default(),
+                    math: None,
                 });
             }
-            HtmlBlock::Image { .. } => {
-                // Skip images for text runs.
+            HtmlBlock::Image { .. } | HtmlBlock::Anchor { .. } => {
+                // Skip images and anchors for text runs.
             }
         }
     }
@@ -519,18 +718,12 @@ pub fn load_cache(epub_path: &Path, cache_path: &Path) -> Result<Option<BookCach
 
     let mut toc = Vec::with_capacity(toc_count);
     for _ in 0..toc_count {
-        let title = read_string(&mut file)?;
-        let href = read_string(&mut file)?;
-        let anchor = read_string(&mut file)?;
-        let level = read_u8(&mut file)?;
-        let spine_index = read_i32(&mut file)?;
-        toc.push(CacheTocEntry {
-            title,
-            href,
-            anchor,
-            level,
-            spine_index,
-        });
+        toc.push(read_toc_entry(&mut file)?);
+    }
+    let landmark_count = read_u32(&mut file)? as usize;
+    let mut landmarks = Vec::with_capacity(landmark_count);
+    for _ in 0..landmark_count {
+        landmarks.push(read_toc_entry(&mut file)?);
     }
 
     Ok(Some(BookCache {
@@ -548,6 +741,7 @@ pub fn load_cache(epub_path: &Path, cache_path: &Path) -> Result<Option<BookCach
         },
         spine,
         toc,
+        landmarks,
         cache_path: cache_path.to_path_buf(),
         source_size,
         source_mtime,
@@ -585,6 +779,8 @@ pub fn build_cache(epub_path: &Path, cache_dir: &Path) -> Result<BookCache, Epub
 
     let mut toc_entries = Vec::new();
     flatten_toc(&book.toc, 0, &mut toc_entries, &href_to_index);
+    let mut landmarks = Vec::new();
+    flatten_toc(&book.landmarks, 0, &mut landmarks, &href_to_index);
 
     for (idx, entry) in toc_entries.iter().enumerate() {
         if entry.spine_index >= 0 && (entry.spine_index as usize) < spine_entries.len() {
@@ -624,11 +820,11 @@ pub fn build_cache(epub_path: &Path, cache_dir: &Path) -> Result<BookCache, Epub
     }
 
     for entry in &toc_entries {
-        write_string(&mut file, &entry.title)?;
-        write_string(&mut file, &entry.href)?;
-        write_string(&mut file, &entry.anchor)?;
-        write_u8(&mut file, entry.level)?;
-        write_i32(&mut file, entry.spine_index)?;
+        write_toc_entry(&mut file, entry)?;
+    }
+    write_u32(&mut file, landmarks.len() as u32)?;
+    for entry in &landmarks {
+        write_toc_entry(&mut file, entry)?;
     }
 
     Ok(BookCache {
@@ -637,6 +833,7 @@ pub fn build_cache(epub_path: &Path, cache_dir: &Path) -> Result<BookCache, Epub
         cover_href: book.package.cover_href,
         spine: spine_entries,
         toc: toc_entries,
+        landmarks,
         cache_path,
         source_size,
         source_mtime,
@@ -709,6 +906,7 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
     let mut toc_href = None;
     let mut cover_id = None;
     let mut spine_toc_id: Option<String> = None;
+    let mut pre_paginated = false;
 
     loop {
         match reader.read_event_into(&mut buf)? {
@@ -749,12 +947,24 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
                             Some(v) => v != "no",
                             None => true,
                         };
-                        spine.push(OpfSpineItem { idref, linear });
+                        let properties = attr_value(&e, b"properties")?
+                            .unwrap_or_default()
+                            .split_whitespace()
+                            .map(|s| s.to_string())
+                            .collect();
+                        spine.push(OpfSpineItem {
+                            idref,
+                            linear,
+                            properties,
+                        });
                     }
                     name if is_xml_name(name, b"meta") && in_metadata => {
                         let name = attr_value(&e, b"name")?;
                         let property = attr_value(&e, b"property")?;
                         let content = attr_value(&e, b"content")?;
+                        if name.as_deref() == Some("fixed-layout") && content.as_deref() == Some("true") {
+                            pre_paginated = true;
+                        }
                         if let Some(name) = name {
                             if name == "cover" {
                                 cover_id = content.clone();
@@ -763,6 +973,8 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
                         if let Some(property) = property {
                             if property == "cover-image" {
                                 cover_id = content;
+                            } else if property == "rendition:layout" {
+                                current_meta = Some("layout");
                             }
                         }
                     }
@@ -809,12 +1021,24 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
                         Some(v) => v != "no",
                         None => true,
                     };
-                    spine.push(OpfSpineItem { idref, linear });
+                    let properties = attr_value(&e, b"properties")?
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(|s| s.to_string())
+                        .collect();
+                    spine.push(OpfSpineItem {
+                        idref,
+                        linear,
+                        properties,
+                    });
                 }
                 name if is_xml_name(name, b"meta") && in_metadata => {
                     let name = attr_value(&e, b"name")?;
                     let property = attr_value(&e, b"property")?;
                     let content = attr_value(&e, b"content")?;
+                    if name.as_deref() == Some("fixed-layout") && content.as_deref() == Some("true") {
+                        pre_paginated = true;
+                    }
                     if let Some(name) = name {
                         if name == "cover" {
                             cover_id = content.clone();
@@ -836,7 +1060,8 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
                     if is_xml_name(name, b"title")
                         || is_xml_name(name, b"creator")
                         || is_xml_name(name, b"language")
-                        || is_xml_name(name, b"identifier") =>
+                        || is_xml_name(name, b"identifier")
+                        || is_xml_name(name, b"meta") =>
                 {
                     current_meta = None;
                 }
@@ -851,6 +1076,7 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
                             "creator" => metadata.creator = Some(text),
                             "language" => metadata.language = Some(text),
                             "identifier" => metadata.identifier = Some(text),
+                            "layout" => pre_paginated = text.trim() == "pre-paginated",
                             _ => {}
                         }
                     }
@@ -882,12 +1108,23 @@ fn parse_opf(xml: &str, opf_path: &str) -> Result<OpfPackage, EpubError> {
         nav_href,
         toc_href,
         cover_href,
+        pre_paginated,
         opf_path: opf_path.to_string(),
         opf_dir,
     })
 }
 
 fn parse_nav_toc(xml: &str, nav_path: &str) -> Result<Vec<TocEntry>, EpubError> {
+    parse_nav_list(xml, nav_path, "toc")
+}
+
+fn parse_nav_landmarks(xml: &str, nav_path: &str) -> Result<Vec<TocEntry>, EpubError> {
+    parse_nav_list(xml, nav_path, "landmarks")
+}
+
+/// Nested `<ol>` lists of the `<nav>` with the given `epub:type`. An entry's
+/// label is its `<a>` or, for unlinked headings, its `<span>`.
+fn parse_nav_list(xml: &str, nav_path: &str, nav_type: &str) -> Result<Vec<TocEntry>, EpubError> {
     let mut reader = Reader::from_str(xml);
     reader.config_mut().trim_text(true);
 
@@ -896,57 +1133,68 @@ fn parse_nav_toc(xml: &str, nav_path: &str) -> Result<Vec<TocEntry>, EpubError>
     let mut buf = Vec::new();
     let mut toc: Vec<TocEntry> = Vec::new();
     let mut stack: Vec<TocEntry> = Vec::new();
-    let mut in_toc_nav = false;
+    let mut in_nav = false;
     let mut nav_depth = 0usize;
-    let mut in_link = false;
+    let mut label_depth = 0usize;
     let mut current_href: Option<String> = None;
     let mut current_text = String::new();
 
     loop {
         match reader.read_event_into(&mut buf)? {
             Event::Start(e) => {
-                match e.name().as_ref() {
-                    b"nav" => {
-                        if is_toc_nav(&e)? {
-                            in_toc_nav = true;
-                            nav_depth = 1;
-                        } else if in_toc_nav {
-                            nav_depth += 1;
-                        }
-                    }
-                    b"li" if in_toc_nav => {
-                        stack.push(TocEntry {
-                            label: String::new(),
-                            href: String::new(),
-                            children: Vec::new(),
-                        });
+                let name_buf = e.name().as_ref().to_vec();
+                let name = name_buf.as_slice();
+                if is_xml_name(name, b"nav") {
+                    if in_nav {
+                        nav_depth += 1;
+                    } else if nav_has_type(&e, nav_type)? {
+                        in_nav = true;
+                        nav_depth = 1;
                     }
-                    b"a" if in_toc_nav => {
-                        in_link = true;
+                } else if in_nav && is_xml_name(name, b"li") {
+                    stack.push(TocEntry {
+                        label: String::new(),
+                        href: String::new(),
+                        children: Vec::new(),
+                    });
+                } else if in_nav && (is_xml_name(name, b"a") || is_xml_name(name, b"span")) {
+                    if label_depth == 0 {
                         current_text.clear();
+                        current_href = None;
+                    }
+                    label_depth += 1;
+                    if is_xml_name(name, b"a") && current_href.is_none() {
                         current_href = attr_value(&e, b"href")?;
                     }
-                    _ => {}
                 }
             }
-            Event::End(e) => match e.name().as_ref() {
-                b"nav" if in_toc_nav => {
-                    if nav_depth == 1 {
-                        in_toc_nav = false;
-                    } else {
-                        nav_depth -= 1;
-                    }
+            Event::End(e) => {
+                let name_buf = e.name().as_ref().to_vec();
+                let name = name_buf.as_slice();
+                if !in_nav {
+                    buf.clear();
+                    continue;
                 }
-                b"a" if in_toc_nav => {
-                    in_link = false;
-                    if let Some(entry) = stack.last_mut() {
-                        entry.label = current_text.trim().to_string();
-                        if let Some(href) = current_href.take() {
-                            entry.href = resolve_href(&base_dir, &href);
+                if is_xml_name(name, b"nav") {
+                    nav_depth -= 1;
+                    if nav_depth == 0 {
+                        in_nav = false;
+                    }
+                } else if is_xml_name(name, b"a") || is_xml_name(name, b"span") {
+                    label_depth = label_depth.saturating_sub(1);
+                    if label_depth == 0 {
+                        if let Some(entry) = stack.last_mut() {
+                            if entry.label.is_empty() {
+                                entry.label = current_text.trim().to_string();
+                            }
+                            if let Some(href) = current_href.take() {
+                                if entry.href.is_empty() {
+                                    entry.href = resolve_href(&base_dir, &href);
+                                }
+                            }
                         }
                     }
-                }
-                b"li" if in_toc_nav => {
+                } else if is_xml_name(name, b"li") {
                     if let Some(entry) = stack.pop() {
                         if let Some(parent) = stack.last_mut() {
                             parent.children.push(entry);
@@ -955,12 +1203,12 @@ fn parse_nav_toc(xml: &str, nav_path: &str) -> Result<Vec<TocEntry>, EpubError>
                         }
                     }
                 }
-                _ => {}
-            },
-            Event::Text(e) => {
-                if in_toc_nav && in_link {
-                    current_text.push_str(&e.decode().map_err(quick_xml::Error::from)?.into_owned());
-                }
+            }
+            Event::Text(e) if in_nav && label_depth > 0 => {
+                push_label_text(&mut current_text, &e.decode().map_err(quick_xml::Error::from)?);
+            }
+            Event::GeneralRef(e) if in_nav && label_depth > 0 => {
+                push_label_text(&mut current_text, &resolve_entity(&e)?);
             }
             Event::Eof => break,
             _ => {}
@@ -985,7 +1233,7 @@ fn parse_ncx_toc(xml: &str, ncx_path: &str) -> Result<Vec<TocEntry>, EpubError>
 
     loop {
         match reader.read_event_into(&mut buf)? {
-            Event::Start(e) => match e.name().as_ref() {
+            Event::Start(e) => match local_name(e.name().as_ref()) {
                 b"navPoint" => {
                     stack.push_back(TocEntry {
                         label: String::new(),
@@ -995,16 +1243,14 @@ fn parse_ncx_toc(xml: &str, ncx_path: &str) -> Result<Vec<TocEntry>, EpubError>
                 }
                 b"navLabel" => in_nav_label = true,
                 b"text" if in_nav_label => in_label_text = true,
-                b"content" => {
-                    if let Some(href) = attr_value(&e, b"src")? {
-                        if let Some(entry) = stack.back_mut() {
-                            entry.href = resolve_href(&base_dir, &href);
-                        }
-                    }
-                }
+                b"content" => set_ncx_href(&e, &base_dir, stack.back_mut())?,
                 _ => {}
             },
-            Event::End(e) => match e.name().as_ref() {
+            // `<content src="..."/>` is normally self-closing.
+            Event::Empty(e) if local_name(e.name().as_ref()) == b"content" => {
+                set_ncx_href(&e, &base_dir, stack.back_mut())?;
+            }
+            Event::End(e) => match local_name(e.name().as_ref()) {
                 b"navLabel" => in_nav_label = false,
                 b"text" => in_label_text = false,
                 b"navPoint" => {
@@ -1018,11 +1264,14 @@ fn parse_ncx_toc(xml: &str, ncx_path: &str) -> Result<Vec<TocEntry>, EpubError>
                 }
                 _ => {}
             },
-            Event::Text(e) => {
-                if in_nav_label && in_label_text {
-                    if let Some(entry) = stack.back_mut() {
-                        entry.label = e.decode().map_err(quick_xml::Error::from)?.into_owned();
-                    }
+            Event::Text(e) if in_nav_label && in_label_text => {
+                if let Some(entry) = stack.back_mut() {
+                    push_label_text(&mut entry.label, &e.decode().map_err(quick_xml::Error::from)?);
+                }
+            }
+            Event::GeneralRef(e) if in_nav_label && in_label_text => {
+                if let Some(entry) = stack.back_mut() {
+                    push_label_text(&mut entry.label, &resolve_entity(&e)?);
                 }
             }
             Event::Eof => break,
@@ -1034,6 +1283,13 @@ fn parse_ncx_toc(xml: &str, ncx_path: &str) -> Result<Vec<TocEntry>, EpubError>
     Ok(toc)
 }
 
+fn set_ncx_href(e: &BytesStart<'_>, base_dir: &str, entry: Option<&mut TocEntry>) -> Result<(), EpubError> {
+    if let (Some(href), Some(entry)) = (attr_value(e, b"src")?, entry) {
+        entry.href = resolve_href(base_dir, &href);
+    }
+    Ok(())
+}
+
 fn find_cover_href(package: &OpfPackage) -> Option<String> {
     package
         .manifest
@@ -1042,19 +1298,36 @@ fn find_cover_href(package: &OpfPackage) -> Option<String> {
         .map(|item| item.href.clone())
 }
 
-fn is_toc_nav(e: &BytesStart<'_>) -> Result<bool, EpubError> {
-    let mut is_toc = false;
-    if let Some(value) = attr_value(e, b"epub:type")? {
-        if value == "toc" {
-            is_toc = true;
+fn nav_has_type(e: &BytesStart<'_>, nav_type: &str) -> Result<bool, EpubError> {
+    for attr in [b"epub:type".as_slice(), b"type".as_slice()] {
+        let value = attr_value(e, attr)?.unwrap_or_default();
+        if value.split_whitespace().any(|token| token == nav_type) {
+            return Ok(true);
         }
     }
-    if let Some(value) = attr_value(e, b"type")? {
-        if value == "toc" {
-            is_toc = true;
-        }
+    Ok(false)
+}
+
+fn resolve_entity(e: &BytesRef<'_>) -> Result<String, EpubError> {
+    if let Some(ch) = e.resolve_char_ref()? {
+        return Ok(ch.to_string());
     }
-    Ok(is_toc)
+    let name = e.decode().map_err(quick_xml::Error::from)?;
+    Ok(quick_xml::escape::resolve_predefined_entity(&name)
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Joins text split by inline markup or entities with single spaces.
+fn push_label_text(label: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if !label.is_empty() {
+        label.push(' ');
+    }
+    label.push_str(text);
 }
 
 fn attr_value(e: &BytesStart<'_>, name: &[u8]) -> Result<Option<String>, EpubError> {
@@ -1067,6 +1340,16 @@ fn attr_value(e: &BytesStart<'_>, name: &[u8]) -> Result<Option<String>, EpubErr
     Ok(None)
 }
 
+/// `src` of an `<img>`, or the `href` of an SVG `<image>` as used by most cover pages.
+fn image_src(e: &BytesStart<'_>) -> Result<Option<String>, EpubError> {
+    for name in [&b"src"[..], b"href", b"xlink:href"] {
+        if let Some(value) = attr_value(e, name)? {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
 pub fn opf_base_dir(path: &str) -> String {
     match path.rfind('/') {
         Some(idx) => path[..idx + 1].to_string(),
@@ -1083,7 +1366,29 @@ pub fn resolve_href(base_dir: &str, href: &str) -> String {
     }
     let mut buf = PathBuf::from(base_dir);
     buf.push(href);
-    buf.to_string_lossy().replace('\\', "/")
+    normalize_zip_path(&buf.to_string_lossy().replace('\\', "/"))
+}
+
+/// Collapses `.` and `..` segments so hrefs from different documents compare equal.
+fn normalize_zip_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn local_name(name: &[u8]) -> &[u8] {
+    match name.iter().position(|b| *b == b':') {
+        Some(idx) => &name[idx + 1..],
+        None => name,
+    }
 }
 
 fn is_xml_name(name: &[u8], expected: &[u8]) -> bool {
@@ -1158,10 +1463,7 @@ fn flush_text_run(
         *last_was_space = false;
     }
     if !current_text.is_empty() {
-        runs.push(TextRun {
-            text: current_text.clone(),
-            style,
-        });
+        push_text_run(runs, current_text, style);
         current_text.clear();
     }
 }
@@ -1172,21 +1474,20 @@ fn flush_paragraph(
     current_text: &mut String,
     style: TextStyle,
     heading_level: Option<u8>,
+    anchors: &mut Vec<String>,
 ) {
     if !current_text.is_empty() {
-        runs.push(TextRun {
-            text: current_text.clone(),
-            style,
-        });
+        push_text_run(runs, current_text, style);
         current_text.clear();
     }
     if runs.is_empty() {
         return;
     }
+    push_anchors(blocks, anchors);
     let mut merged: Vec<TextRun> = Vec::new();
     for run in runs.drain(..) {
         if let Some(last) = merged.last_mut() {
-            if last.style == run.style {
+            if last.style == run.style && last.math.is_none() && run.math.is_none() {
                 last.text.push_str(&run.text);
                 continue;
             }
@@ -1199,6 +1500,131 @@ fn flush_paragraph(
     });
 }
 
+fn push_anchors(blocks: &mut Vec<HtmlBlock>, anchors: &mut Vec<String>) {
+    blocks.extend(anchors.drain(..).map(|id| HtmlBlock::Anchor { id }));
+}
+
+/// Pushes `text`, splitting out `$…$`, `$$…$$`, `\(…\)` and `\[…\]` TeX as math runs.
+fn push_text_run(runs: &mut Vec<TextRun>, text: &str, style: TextStyle) {
+    let mut rest = text;
+    while let Some((start, end, inner, display)) = find_tex(rest) {
+        if start > 0 {
+            runs.push(TextRun {
+                text: rest[..start].to_string(),
+                style,
+                math: None,
+            });
+        }
+        runs.push(TextRun {
+            text: inner.to_string(),
+            style,
+            math: Some(MathSource {
+                notation: MathNotation::Tex,
+                source: inner.to_string(),
+                display,
+            }),
+        });
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        runs.push(TextRun {
+            text: rest.to_string(),
+            style,
+            math: None,
+        });
+    }
+}
+
+/// Byte range of the first TeX span in `text`, its contents and whether it is display math.
+fn find_tex(text: &str) -> Option<(usize, usize, &str, bool)> {
+    let mut search = 0;
+    while let Some(offset) = text[search..].find(['$', '\\']) {
+        let start = search + offset;
+        let tail = &text[start..];
+        let (open, close, display) = if tail.starts_with("$$") {
+            ("$$", "$$", true)
+        } else if tail.starts_with('$') {
+            ("$", "$", false)
+        } else if tail.starts_with("\\(") {
+            ("\\(", "\\)", false)
+        } else if tail.starts_with("\\[") {
+            ("\\[", "\\]", true)
+        } else {
+            search = start + 1;
+            continue;
+        };
+        let body = &tail[open.len()..];
+        if let Some(len) = body.find(close) {
+            let inner = &body[..len];
+            if looks_like_tex(inner, open == "$") {
+                return Some((start, start + open.len() + len + close.len(), inner, display));
+            }
+        }
+        search = start + open.len();
+    }
+    None
+}
+
+fn looks_like_tex(inner: &str, single_dollar: bool) -> bool {
+    if inner.trim().is_empty() {
+        return false;
+    }
+    if !single_dollar {
+        return true;
+    }
+    // Prices such as "$5 and $10" pair up too; TeX keeps `$` tight to its contents.
+    if inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace) {
+        return false;
+    }
+    inner.contains(['\\', '^', '_', '{', '='])
+        || (inner.chars().count() == 1 && inner.chars().all(char::is_alphabetic))
+}
+
+/// Text content of a MathML fragment, without `<annotation>` sources.
+fn mathml_fallback_text(source: &str) -> String {
+    let mut reader = Reader::from_str(source);
+    let mut out = String::new();
+    let mut skip_depth = 0usize;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = e.name();
+                if skip_depth > 0
+                    || is_xml_name(name.as_ref(), b"annotation")
+                    || is_xml_name(name.as_ref(), b"annotation-xml")
+                {
+                    skip_depth += 1;
+                }
+            }
+            Ok(Event::End(_)) => skip_depth = skip_depth.saturating_sub(1),
+            Ok(Event::Text(e)) if skip_depth == 0 => {
+                if let Ok(text) = e.decode() {
+                    out.push_str(text.trim());
+                }
+            }
+            Ok(Event::GeneralRef(e)) if skip_depth == 0 => {
+                if let Ok(text) = resolve_entity(&e) {
+                    out.push_str(&text);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `id` of any element, or the legacy `name` of an `<a>`.
+fn element_anchor(e: &BytesStart<'_>, name: &[u8]) -> Result<Option<String>, EpubError> {
+    if let Some(id) = attr_value(e, b"id")? {
+        return Ok(Some(id));
+    }
+    if is_xml_name(name, b"a") {
+        return attr_value(e, b"name");
+    }
+    Ok(None)
+}
+
 fn push_normalized_text(input: &str, buf: &mut String, last_was_space: &mut bool) {
     for ch in input.chars() {
         if ch.is_whitespace() {
@@ -1235,6 +1661,19 @@ fn split_href_anchor(href: &str) -> (String, String) {
     }
 }
 
+/// Unlinked headings in a nav list start where their first linked child does.
+fn first_href(entry: &TocEntry) -> &str {
+    if !entry.href.is_empty() {
+        return &entry.href;
+    }
+    entry
+        .children
+        .iter()
+        .map(first_href)
+        .find(|href| !href.is_empty())
+        .unwrap_or("")
+}
+
 fn flatten_toc(
     entries: &[TocEntry],
     level: u8,
@@ -1242,7 +1681,7 @@ fn flatten_toc(
     spine_map: &HashMap<&str, i32>,
 ) {
     for entry in entries {
-        let (path, anchor) = split_href_anchor(&entry.href);
+        let (path, anchor) = split_href_anchor(first_href(entry));
         let spine_index = spine_map.get(path.as_str()).copied().unwrap_or(-1);
         out.push(CacheTocEntry {
             title: entry.label.clone(),
@@ -1257,6 +1696,24 @@ fn flatten_toc(
     }
 }
 
+fn read_toc_entry<R: Read>(reader: &mut R) -> Result<CacheTocEntry, EpubError> {
+    Ok(CacheTocEntry {
+        title: read_string(reader)?,
+        href: read_string(reader)?,
+        anchor: read_string(reader)?,
+        level: read_u8(reader)?,
+        spine_index: read_i32(reader)?,
+    })
+}
+
+fn write_toc_entry<W: Write>(writer: &mut W, entry: &CacheTocEntry) -> Result<(), EpubError> {
+    write_string(writer, &entry.title)?;
+    write_string(writer, &entry.href)?;
+    write_string(writer, &entry.anchor)?;
+    write_u8(writer, entry.level)?;
+    write_i32(writer, entry.spine_index)
+}
+
 fn zip_entry_size<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, name: &str) -> Option<u64> {
     if let Ok(file) = archive.by_name(name) {
         return Some(file.size());
@@ -1,7 +1,10 @@
-use std::io::{self, Write};
+use std::io::{self, Cursor, Write};
 use std::path::{Path, PathBuf};
 
-use image::{DynamicImage, GrayImage};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, GrayImage, ImageDecoder, ImageFormat, ImageReader};
 use rxing::{
     BarcodeFormat, BinaryBitmap, DecodeHintValue, DecodeHints, Luma8LuminanceSource,
     MultiFormatReader, MultiFormatWriter, Point,
@@ -17,6 +20,9 @@ const VERSION_V1: u8 = 1;
 const VERSION_V2: u8 = 2;
 const FORMAT_MONO1: u8 = 1;
 const FORMAT_GRAY2: u8 = 2;
+const META_SOURCE_NAME: u8 = 1;
+const META_CONVERTED: u8 = 2;
+const META_DESCRIPTION: u8 = 3;
 
 #[derive(Clone, Copy, Debug)]
 pub enum FitMode {
@@ -41,6 +47,22 @@ pub enum RegionMode {
     Barcode,
 }
 
+/// Which frame of an animated GIF, APNG or WebP is converted.
+#[derive(Clone, Copy, Debug)]
+pub enum FrameMode {
+    First,
+    Middle,
+}
+
+impl FrameMode {
+    pub fn index(self, frame_count: usize) -> usize {
+        match self {
+            FrameMode::First => 0,
+            FrameMode::Middle => frame_count / 2,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ConvertOptions {
     pub width: u32,
@@ -55,6 +77,7 @@ pub struct ConvertOptions {
     pub yolo_confidence: f32,
     pub yolo_nms: f32,
     pub trimg_version: u8,
+    pub frame: FrameMode,
 }
 
 impl Default for ConvertOptions {
@@ -72,6 +95,7 @@ impl Default for ConvertOptions {
             yolo_confidence: 0.25,
             yolo_nms: 0.45,
             trimg_version: VERSION_V1,
+            frame: FrameMode::First,
         }
     }
 }
@@ -86,6 +110,61 @@ pub struct Trimg {
     pub width: u32,
     pub height: u32,
     pub data: TrimgData,
+    /// When the photo was taken, packed by [`pack_capture_date`]; stored in
+    /// header bytes 12..16, where 0 means unknown.
+    pub captured: Option<u32>,
+    pub info: TrimgInfo,
+}
+
+/// Optional details written after the pixel data as tag, u16 length and
+/// value entries. Header bytes 10..12 hold the section's length, so readers
+/// that do not know it can skip it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrimgInfo {
+    /// Name of the file the image was converted from.
+    pub source_name: Option<String>,
+    /// When it was converted, packed by [`pack_capture_date`].
+    pub converted: Option<u32>,
+    pub description: Option<String>,
+}
+
+impl TrimgInfo {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            let value = &value[..value.len().min(u16::MAX as usize)];
+            out.push(tag);
+            out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+        if let Some(name) = &self.source_name {
+            push(META_SOURCE_NAME, name.as_bytes());
+        }
+        if let Some(converted) = self.converted {
+            push(META_CONVERTED, &converted.to_le_bytes());
+        }
+        if let Some(description) = &self.description {
+            push(META_DESCRIPTION, description.as_bytes());
+        }
+        out
+    }
+
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        let mut info = Self::default();
+        while !data.is_empty() {
+            let tag = data[0];
+            let len = u16::from_le_bytes([*data.get(1)?, *data.get(2)?]) as usize;
+            let value = data.get(3..3 + len)?;
+            match tag {
+                META_SOURCE_NAME => info.source_name = Some(String::from_utf8_lossy(value).into_owned()),
+                META_CONVERTED => info.converted = Some(u32::from_le_bytes(value.try_into().ok()?)),
+                META_DESCRIPTION => info.description = Some(String::from_utf8_lossy(value).into_owned()),
+                _ => {}
+            }
+            data = &data[3 + len..];
+        }
+        Some(info)
+    }
 }
 
 pub enum TrimgData {
@@ -94,8 +173,161 @@ pub enum TrimgData {
 }
 
 pub fn convert_bytes(bytes: &[u8], options: ConvertOptions) -> Result<Trimg, ConvertError> {
+    let mut frames = decode_frames(bytes)?;
+    let index = options.frame.index(frames.len());
+    if frames.len() > 1 {
+        eprintln!(
+            "[tern-image] warning: animated image has {} frames, converting frame {}",
+            frames.len(),
+            index + 1
+        );
+    }
+    let image = frames.swap_remove(index);
+    let mut trimg = convert_image(&image, options);
+    trimg.captured = capture_date(bytes);
+    Ok(trimg)
+}
+
+/// Capture date from the image's EXIF data: DateTimeOriginal, or the file's
+/// DateTime when the camera left that out.
+pub fn capture_date(bytes: &[u8]) -> Option<u32> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let exif = decoder.exif_metadata().ok()??;
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(&exif);
+    let text = exif_date_text(tiff)?;
+    parse_exif_date(&text)
+}
+
+/// Packs a date like a FAT timestamp (years since 1980, month, day, hour,
+/// minute, seconds / 2), so later dates compare greater.
+pub fn pack_capture_date(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> u32 {
+    ((year.saturating_sub(1980) & 0x7F) << 25)
+        | ((month & 0x0F) << 21)
+        | ((day & 0x1F) << 16)
+        | ((hour & 0x1F) << 11)
+        | ((minute & 0x3F) << 5)
+        | ((second / 2) & 0x1F)
+}
+
+/// Packs a Unix time in UTC the same way as [`pack_capture_date`].
+pub fn pack_unix_time(secs: u64) -> u32 {
+    let days = (secs / 86_400) as i64;
+    let of_day = (secs % 86_400) as u32;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (year_of_era + era * 400 + i64::from(month <= 2)) as u32;
+    pack_capture_date(year, month, day, of_day / 3600, of_day / 60 % 60, of_day % 60)
+}
+
+const EXIF_TAG_DATE_TIME: u16 = 0x0132;
+const EXIF_TAG_EXIF_IFD: u16 = 0x8769;
+const EXIF_TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+fn exif_date_text(tiff: &[u8]) -> Option<String> {
+    let little = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if little { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    // Offset of the value of `tag` in the IFD at `ifd`.
+    let find = |ifd: usize, tag: u16| -> Option<usize> {
+        let count = u16_at(ifd)? as usize;
+        (0..count)
+            .map(|index| ifd + 2 + index * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+            .map(|entry| entry + 8)
+    };
+    let ascii_at = |value: usize| -> Option<String> {
+        // "YYYY:MM:DD HH:MM:SS" plus NUL never fits inline.
+        let start = u32_at(value)? as usize;
+        let text = tiff.get(start..start + 19)?;
+        Some(String::from_utf8_lossy(text).into_owned())
+    };
+    let ifd0 = u32_at(4)? as usize;
+    let original = find(ifd0, EXIF_TAG_EXIF_IFD)
+        .and_then(u32_at)
+        .and_then(|exif_ifd| find(exif_ifd as usize, EXIF_TAG_DATE_TIME_ORIGINAL))
+        .and_then(ascii_at);
+    original.or_else(|| find(ifd0, EXIF_TAG_DATE_TIME).and_then(ascii_at))
+}
+
+fn parse_exif_date(text: &str) -> Option<u32> {
+    let (date, time) = text.split_once(' ')?;
+    let mut date = date.split(':').map(|part| part.parse::<u32>().ok());
+    let mut time = time.split(':').map(|part| part.parse::<u32>().ok());
+    let year = date.next()??;
+    let month = date.next()??;
+    let day = date.next()??;
+    if year < 1980 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let hour = time.next()??;
+    let minute = time.next()??;
+    let second = time.next()??;
+    Some(pack_capture_date(year, month, day, hour, minute, second))
+}
+
+/// Decodes every frame of an animated GIF, APNG or WebP, composited onto the
+/// full canvas. Still images come back as a single frame.
+pub fn decode_frames(bytes: &[u8]) -> Result<Vec<DynamicImage>, ConvertError> {
+    let frames = match image::guess_format(bytes) {
+        Ok(ImageFormat::Gif) => {
+            let decoder = GifDecoder::new(Cursor::new(bytes)).map_err(|_| ConvertError::Decode)?;
+            collect_frames(decoder)?
+        }
+        Ok(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(bytes)).map_err(|_| ConvertError::Decode)?;
+            if decoder.is_apng().map_err(|_| ConvertError::Decode)? {
+                collect_frames(decoder.apng().map_err(|_| ConvertError::Decode)?)?
+            } else {
+                Vec::new()
+            }
+        }
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(|_| ConvertError::Decode)?;
+            if decoder.has_animation() {
+                collect_frames(decoder)?
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    };
+    if !frames.is_empty() {
+        return Ok(frames);
+    }
     let image = image::load_from_memory(bytes).map_err(|_| ConvertError::Decode)?;
-    Ok(convert_image(&image, options))
+    Ok(vec![image])
+}
+
+fn collect_frames<'a>(decoder: impl AnimationDecoder<'a>) -> Result<Vec<DynamicImage>, ConvertError> {
+    decoder
+        .into_frames()
+        .map(|frame| {
+            frame
+                .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+                .map_err(|_| ConvertError::Decode)
+        })
+        .collect()
 }
 
 pub fn convert_image(image: &DynamicImage, options: ConvertOptions) -> Trimg {
@@ -201,6 +433,8 @@ pub fn convert_image(image: &DynamicImage, options: ConvertOptions) -> Trimg {
             width: options.width,
             height: options.height,
             data: TrimgData::Gray2 { data },
+            captured: None,
+            info: TrimgInfo::default(),
         }
     } else {
         let mut bits = vec![0u8; ((options.width as usize * options.height as usize) + 7) / 8];
@@ -255,6 +489,8 @@ pub fn convert_image(image: &DynamicImage, options: ConvertOptions) -> Trimg {
             width: options.width,
             height: options.height,
             data: TrimgData::Mono1 { bits },
+            captured: None,
+            info: TrimgInfo::default(),
         }
     }
 }
@@ -271,6 +507,11 @@ pub fn write_trimg(path: &Path, trimg: &Trimg) -> io::Result<()> {
     header[5] = format;
     header[6..8].copy_from_slice(&(trimg.width as u16).to_le_bytes());
     header[8..10].copy_from_slice(&(trimg.height as u16).to_le_bytes());
+    let metadata = trimg.info.encode();
+    let metadata_len = u16::try_from(metadata.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "image metadata too long"))?;
+    header[10..12].copy_from_slice(&metadata_len.to_le_bytes());
+    header[12..16].copy_from_slice(&trimg.captured.unwrap_or(0).to_le_bytes());
     file.write_all(&header)?;
     match &trimg.data {
         TrimgData::Mono1 { bits } => file.write_all(bits)?,
@@ -278,6 +519,7 @@ pub fn write_trimg(path: &Path, trimg: &Trimg) -> io::Result<()> {
             file.write_all(data)?;
         }
     }
+    file.write_all(&metadata)?;
     Ok(())
 }
 
@@ -287,6 +529,11 @@ pub fn parse_trimg(data: &[u8]) -> Option<Trimg> {
     }
     let width = u16::from_le_bytes([data[6], data[7]]) as u32;
     let height = u16::from_le_bytes([data[8], data[9]]) as u32;
+    let metadata_len = u16::from_le_bytes([data[10], data[11]]) as usize;
+    let captured = Some(u32::from_le_bytes([data[12], data[13], data[14], data[15]]))
+        .filter(|&date| date != 0);
+    let (data, metadata) = data.split_at(data.len().checked_sub(metadata_len)?);
+    let info = TrimgInfo::decode(metadata)?;
     let plane = ((width as usize * height as usize) + 7) / 8;
     match (data[4], data[5]) {
         (VERSION_V1, FORMAT_MONO1) => {
@@ -299,6 +546,8 @@ pub fn parse_trimg(data: &[u8]) -> Option<Trimg> {
                 data: TrimgData::Mono1 {
                     bits: data[16..].to_vec(),
                 },
+                captured,
+                info,
             })
         }
         (VERSION_V2, FORMAT_GRAY2) => {
@@ -310,6 +559,8 @@ pub fn parse_trimg(data: &[u8]) -> Option<Trimg> {
                 width,
                 height,
                 data: TrimgData::Gray2 { data },
+                captured,
+                info,
             })
         }
         _ => None,
@@ -1217,7 +1468,8 @@ fn apply_dither(lum: u8, x: u32, y: u32, mode: DitherMode) -> bool {
     }
 }
 
-fn otsu_threshold(img: &GrayImage) -> u8 {
+/// Gray level that best separates ink from paper, by Otsu's method.
+pub fn otsu_threshold(img: &GrayImage) -> u8 {
     let mut hist = [0u32; 256];
     for pixel in img.pixels() {
         hist[pixel.0[0] as usize] += 1;
@@ -1,14 +1,12 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use tern_image::{ConvertOptions, DitherMode, FitMode, RegionMode};
-
-const BUILD_VERSION: &str = env!("TRUSTY_VERSION");
-const BUILD_TIME: &str = env!("TRUSTY_BUILD_TIME");
+use tern_image::{ConvertOptions, DitherMode, FitMode, FrameMode, RegionMode, TrimgInfo};
 
 fn usage() -> ! {
     eprintln!(
-        "Usage:\n  tern-image convert <input> <output> [--size WxH] [--fit contain|cover|stretch|integer|width] [--dither bayer|none] [--region auto|none|crisp|barcode] [--trimg-version 1|2] [--yolo-model path] [--yolo-classes N] [--yolo-confidence F] [--yolo-nms F] [--invert] [--debug]\n\nDefaults: --size 480x800 --fit width --dither bayer --region auto --trimg-version 1"
+        "Usage:\n  tern-image convert <input> <output> [--size WxH] [--fit contain|cover|stretch|integer|width] [--dither bayer|none] [--region auto|none|crisp|barcode] [--trimg-version 1|2] [--yolo-model path] [--yolo-classes N] [--yolo-confidence F] [--yolo-nms F] [--frame first|middle|all] [--description text] [--no-metadata] [--invert] [--debug]\n\nDefaults: --size 480x800 --fit width --dither bayer --region auto --trimg-version 1 --frame first\n\n--frame all writes every frame of an animated image as name.001.tri, name.002.tri, ...\n--no-metadata leaves out the source name, conversion date and description, for firmware older than the metadata section"
     );
     std::process::exit(2);
 }
@@ -24,7 +22,7 @@ fn main() {
     let mut args = env::args().skip(1);
     let cmd = args.next().unwrap_or_default();
     if cmd == "--version" || cmd == "-V" || cmd == "version" {
-        println!("tern-image {BUILD_VERSION} ({BUILD_TIME})");
+        println!("{}", tern_build_info::VersionLine("tern-image"));
         return;
     }
     if cmd != "convert" {
@@ -38,6 +36,9 @@ fn main() {
     }
 
     let mut options = ConvertOptions::default();
+    let mut all_frames = false;
+    let mut description = None;
+    let mut with_metadata = true;
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -121,6 +122,23 @@ fn main() {
                     usage();
                 }
             }
+            "--frame" => {
+                let value = args.next().unwrap_or_default();
+                match value.as_str() {
+                    "first" => options.frame = FrameMode::First,
+                    "middle" => options.frame = FrameMode::Middle,
+                    "all" => all_frames = true,
+                    _ => usage(),
+                }
+            }
+            "--description" => {
+                let value = args.next().unwrap_or_default();
+                if value.is_empty() {
+                    usage();
+                }
+                description = Some(value);
+            }
+            "--no-metadata" => with_metadata = false,
             "--invert" => options.invert = true,
             "--debug" => options.debug = true,
             _ => usage(),
@@ -137,16 +155,71 @@ fn main() {
         }
     };
 
-    let trimg = match tern_image::convert_bytes(&data, options) {
+    let info = if with_metadata {
+        let converted = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| tern_image::pack_unix_time(elapsed.as_secs()))
+            .ok();
+        TrimgInfo {
+            source_name: input_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            converted,
+            description,
+        }
+    } else {
+        TrimgInfo::default()
+    };
+
+    if all_frames {
+        write_all_frames(&data, output_path, &options, &info);
+        return;
+    }
+
+    let mut trimg = match tern_image::convert_bytes(&data, options) {
         Ok(trimg) => trimg,
         Err(err) => {
             eprintln!("Conversion failed: {err:?}");
             std::process::exit(1);
         }
     };
+    trimg.info = info;
 
     if let Err(err) = tern_image::write_trimg(output_path, &trimg) {
         eprintln!("Failed to write output: {err}");
         std::process::exit(1);
     }
 }
+
+/// Writes each frame as its own numbered image so the sequence pages like a slideshow.
+fn write_all_frames(data: &[u8], output_path: &Path, options: &ConvertOptions, info: &TrimgInfo) {
+    let frames = match tern_image::decode_frames(data) {
+        Ok(frames) => frames,
+        Err(err) => {
+            eprintln!("Conversion failed: {err:?}");
+            std::process::exit(1);
+        }
+    };
+    let captured = tern_image::capture_date(data);
+    for (index, frame) in frames.iter().enumerate() {
+        let mut trimg = tern_image::convert_image(frame, options.clone());
+        trimg.captured = captured;
+        trimg.info = info.clone();
+        let path = frame_output_path(output_path, index + 1);
+        if let Err(err) = tern_image::write_trimg(&path, &trimg) {
+            eprintln!("Failed to write output: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn frame_output_path(base: &Path, index: usize) -> PathBuf {
+    let stem = base
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "image".to_string());
+    let ext = base.extension().and_then(|s| s.to_str()).unwrap_or("tri");
+    let mut out = base.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    out.push(format!("{}.{:03}.{}", stem, index, ext));
+    out
+}
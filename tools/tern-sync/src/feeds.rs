@@ -0,0 +1,474 @@
+//! Daily news issues from RSS and Atom feeds (`tern-sync --feeds feeds.toml`).
+//!
+//! New articles from the configured feeds go into one book per day,
+//! `News-YYYY-MM-DD.trbk`, with a chapter per article grouped by feed. The
+//! articles taken so far are listed in a `.seen` file next to the feeds file,
+//! so a later run on the same day rebuilds that day's issue with the earlier
+//! articles plus any new ones.
+
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use tern_book::blocks::HtmlBlock;
+use tern_book::input::{ArticleInput, BookInput, NavEntry, SpineItem};
+use tern_book::{BookError, FontPaths, TrbkMetadata};
+use time::{Date, Duration, Month, OffsetDateTime};
+
+use crate::SyncError;
+
+pub const DEFAULT_FOLDER: &str = "/News";
+pub const DEFAULT_KEEP_DAYS: u32 = 7;
+pub const DEFAULT_MAX_ARTICLES: usize = 10;
+const ISSUE_PREFIX: &str = "News-";
+/// Days an article stays in the `.seen` file, long after it left its feed.
+const SEEN_DAYS: i64 = 90;
+
+/// Settings from the feeds file, which is a small subset of TOML: `key =
+/// value` lines with strings, integers or arrays of integers, and a
+/// `[[feed]]` table per feed with `name`, `url` and optionally `max_articles`.
+#[derive(Clone, Debug)]
+pub struct FeedsConfig {
+    /// Device folder the issues go into.
+    pub folder: String,
+    /// Issues this many days old or older are deleted from the device.
+    pub keep_days: u32,
+    /// New articles taken from each feed per run, unless the feed sets its own.
+    pub max_articles: usize,
+    pub sizes: Vec<u16>,
+    pub font_paths: FontPaths,
+    pub feeds: Vec<Feed>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Feed {
+    pub name: String,
+    pub url: String,
+    pub max_articles: Option<usize>,
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Array(Vec<Value>),
+}
+
+impl FeedsConfig {
+    pub fn load(path: &Path) -> Result<Self, SyncError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SyncError> {
+        let mut config = Self {
+            folder: DEFAULT_FOLDER.to_string(),
+            keep_days: DEFAULT_KEEP_DAYS,
+            max_articles: DEFAULT_MAX_ARTICLES,
+            sizes: vec![10],
+            font_paths: FontPaths::default(),
+            feeds: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| SyncError::Feeds(format!("line {}: {}", number + 1, message));
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[feed]]" {
+                config.feeds.push(Feed {
+                    name: String::new(),
+                    url: String::new(),
+                    max_articles: None,
+                });
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected `key = value` or `[[feed]]`"));
+            };
+            let Some(value) = parse_value(value.trim()) else {
+                return Err(error("expected a string, an integer or an array of integers"));
+            };
+            let count = |value: &Value| match value {
+                Value::Integer(n) => usize::try_from(*n).ok(),
+                _ => None,
+            };
+            let key = key.trim();
+            match (config.feeds.last_mut(), key, value) {
+                (None, "folder", Value::String(folder)) => {
+                    config.folder = format!("/{}", folder.trim_matches('/'));
+                }
+                (None, "keep_days", value) => {
+                    config.keep_days = count(&value)
+                        .and_then(|days| u32::try_from(days).ok())
+                        .filter(|&days| days > 0)
+                        .ok_or_else(|| error("keep_days must be a positive integer"))?;
+                }
+                (None, "max_articles", value) => {
+                    config.max_articles = count(&value).ok_or_else(|| error("max_articles must be an integer"))?;
+                }
+                (None, "sizes", Value::Array(sizes)) => {
+                    config.sizes = sizes
+                        .iter()
+                        .map(|size| count(size).and_then(|size| u16::try_from(size).ok()))
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|sizes| !sizes.is_empty())
+                        .ok_or_else(|| error("sizes must be a list of font sizes"))?;
+                }
+                (None, "font", Value::String(path)) => config.font_paths.regular = Some(path),
+                (None, "font_bold", Value::String(path)) => config.font_paths.bold = Some(path),
+                (None, "font_italic", Value::String(path)) => config.font_paths.italic = Some(path),
+                (None, "font_bold_italic", Value::String(path)) => config.font_paths.bold_italic = Some(path),
+                (Some(feed), "name", Value::String(name)) => feed.name = name,
+                (Some(feed), "url", Value::String(url)) => feed.url = url,
+                (Some(feed), "max_articles", value) => {
+                    feed.max_articles = Some(count(&value).ok_or_else(|| error("max_articles must be an integer"))?);
+                }
+                _ => return Err(error(&format!("unexpected `{key}`"))),
+            }
+        }
+        for feed in &mut config.feeds {
+            if feed.url.is_empty() {
+                return Err(SyncError::Feeds(format!("feed `{}` has no url", feed.name)));
+            }
+            if feed.name.is_empty() {
+                feed.name = feed.url.clone();
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Drops a `#` comment, unless the `#` sits inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (at, ch) in line.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(open), _) if ch == open => quote = None,
+            (None, '#') => return &line[..at],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(items) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_value)
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array);
+    }
+    // Literal strings, handy for Windows paths.
+    if let Some(literal) = text.strip_prefix('\'').and_then(|text| text.strip_suffix('\'')) {
+        return Some(Value::String(literal.to_string()));
+    }
+    if let Some(basic) = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+        let mut out = String::new();
+        let mut chars = basic.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                escaped @ ('"' | '\\') => out.push(escaped),
+                _ => return None,
+            }
+        }
+        return Some(Value::String(out));
+    }
+    text.replace('_', "").parse().ok().map(Value::Integer)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FeedItem {
+    /// `guid` or `id`, else the link.
+    pub id: String,
+    pub title: String,
+    pub link: String,
+}
+
+/// Items of an RSS or Atom feed, in feed order. Items without a link are left out.
+pub fn parse_feed(xml: &[u8]) -> Result<Vec<FeedItem>, SyncError> {
+    let error = |err: quick_xml::Error| SyncError::Feeds(format!("bad feed: {err}"));
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut items = Vec::new();
+    let mut item: Option<FeedItem> = None;
+    let mut field = None;
+    let mut text = String::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(error)?;
+        match event {
+            Event::Start(e) => match (local_name(&e).as_str(), item.as_mut()) {
+                ("item" | "entry", _) => item = Some(FeedItem::default()),
+                ("link", Some(item)) if item.link.is_empty() && atom_link(&e).is_some() => {
+                    item.link = atom_link(&e).unwrap_or_default();
+                }
+                (name @ ("title" | "link" | "guid" | "id"), Some(_)) => {
+                    field = Some(name.to_string());
+                    text.clear();
+                }
+                _ => {}
+            },
+            Event::Empty(e) => {
+                let link = atom_link(&e).filter(|_| local_name(&e) == "link");
+                if let Some((item, link)) = item.as_mut().zip(link).filter(|(item, _)| item.link.is_empty()) {
+                    item.link = link;
+                }
+            }
+            Event::Text(e) if field.is_some() => text.push_str(&e.decode().map_err(quick_xml::Error::from).map_err(error)?),
+            Event::CData(e) if field.is_some() => text.push_str(&String::from_utf8_lossy(&e)),
+            Event::GeneralRef(e) if field.is_some() => {
+                let resolved = match e.resolve_char_ref().map_err(error)? {
+                    Some(ch) => ch.to_string(),
+                    None => {
+                        let name = e.decode().map_err(quick_xml::Error::from).map_err(error)?;
+                        quick_xml::escape::resolve_predefined_entity(&name).unwrap_or_default().to_string()
+                    }
+                };
+                text.push_str(&resolved);
+            }
+            Event::End(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if field.as_deref() == Some(name.as_str()) {
+                    field = None;
+                    let value = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if let Some(item) = item.as_mut() {
+                        match name.as_str() {
+                            "title" => item.title = value,
+                            "link" if item.link.is_empty() => item.link = value,
+                            "guid" | "id" => item.id = value,
+                            _ => {}
+                        }
+                    }
+                } else if name == "item" || name == "entry" {
+                    items.extend(item.take().filter(|item| !item.link.is_empty()).map(|mut item| {
+                        if item.id.is_empty() {
+                            item.id = item.link.clone();
+                        }
+                        item
+                    }));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(items)
+}
+
+fn local_name(e: &BytesStart<'_>) -> String {
+    String::from_utf8_lossy(e.local_name().as_ref()).into_owned()
+}
+
+/// `href` of an Atom `<link>` to the article itself, i.e. with no `rel` or
+/// `rel="alternate"`.
+fn atom_link(e: &BytesStart<'_>) -> Option<String> {
+    let attribute = |key: &[u8]| {
+        e.attributes()
+            .filter_map(Result::ok)
+            .find(|attr| attr.key.local_name().as_ref() == key)
+            .and_then(|attr| attr.unescape_value().ok().map(|value| value.into_owned()))
+    };
+    let href = attribute(b"href")?;
+    attribute(b"rel")
+        .is_none_or(|rel| rel == "alternate")
+        .then_some(href)
+}
+
+#[derive(Clone, Debug)]
+pub struct SeenArticle {
+    /// Issue the article went into.
+    pub date: Date,
+    pub feed: String,
+    pub id: String,
+    pub link: String,
+}
+
+/// Articles already put in an issue, one `date<TAB>feed<TAB>id<TAB>link` line each.
+#[derive(Clone, Debug, Default)]
+pub struct SeenArticles {
+    pub articles: Vec<SeenArticle>,
+}
+
+impl SeenArticles {
+    /// A missing file means nothing was taken yet.
+    pub fn load(path: &Path) -> Result<Self, SyncError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let articles = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                Some(SeenArticle {
+                    date: parse_date(fields.next()?)?,
+                    feed: fields.next()?.to_string(),
+                    id: fields.next()?.to_string(),
+                    link: fields.next()?.to_string(),
+                })
+            })
+            .collect();
+        Ok(Self { articles })
+    }
+
+    /// Writes the list back, leaving out articles taken long before `today`.
+    pub fn save(&self, path: &Path, today: Date) -> Result<(), SyncError> {
+        let oldest = today - Duration::days(SEEN_DAYS);
+        let text = self
+            .articles
+            .iter()
+            .filter(|article| article.date >= oldest)
+            .map(|article| format!("{}\t{}\t{}\t{}\n", article.date, article.feed, article.id, article.link))
+            .collect::<String>();
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.articles.iter().any(|article| article.id == id)
+    }
+
+    /// Articles from `feed` that went into the issue for `date`.
+    pub fn taken_on<'a>(&'a self, date: Date, feed: &'a str) -> impl Iterator<Item = &'a SeenArticle> + 'a {
+        self.articles
+            .iter()
+            .filter(move |article| article.date == date && article.feed == feed)
+    }
+
+    pub fn add(&mut self, date: Date, feed: &str, item: &FeedItem) {
+        self.articles.push(SeenArticle {
+            date,
+            feed: feed.to_string(),
+            id: item.id.clone(),
+            link: item.link.clone(),
+        });
+    }
+}
+
+/// Local date, or the UTC one when the local offset cannot be found.
+pub fn today() -> Date {
+    OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .date()
+}
+
+fn parse_date(text: &str) -> Option<Date> {
+    let mut parts = text.get(..10)?.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+/// File name of the issue for `date`.
+pub fn issue_name(date: Date) -> String {
+    format!("{ISSUE_PREFIX}{date}.trbk")
+}
+
+/// Date of an issue file, including the per-size and per-part files.
+pub fn issue_date(name: &str) -> Option<Date> {
+    if !name.to_ascii_lowercase().ends_with(".trbk") {
+        return None;
+    }
+    parse_date(name.strip_prefix(ISSUE_PREFIX)?)
+}
+
+/// One day's issue, an article per chapter.
+pub struct NewsIssue {
+    pub date: Date,
+    /// Feed name and article, grouped by feed.
+    articles: Vec<(String, ArticleInput)>,
+    spine: Vec<SpineItem>,
+}
+
+impl NewsIssue {
+    pub fn new(date: Date) -> Self {
+        Self {
+            date,
+            articles: Vec::new(),
+            spine: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, feed: &str, article: ArticleInput) {
+        self.spine.push(SpineItem {
+            name: article.source.clone(),
+        });
+        self.articles.push((feed.to_string(), article));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.articles.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.articles.len()
+    }
+}
+
+impl BookInput for NewsIssue {
+    fn metadata(&self) -> TrbkMetadata {
+        let language = self
+            .articles
+            .first()
+            .map(|(_, article)| article.metadata().language)
+            .unwrap_or_else(|| "<unknown>".to_string());
+        TrbkMetadata {
+            title: format!("News {}", self.date),
+            author: "tern-sync".to_string(),
+            language,
+            identifier: format!("tern-sync:news:{}", self.date),
+            part: None,
+            series: Some("News".to_string()),
+        }
+    }
+
+    fn spine(&self) -> &[SpineItem] {
+        &self.spine
+    }
+
+    fn blocks(&self, index: usize) -> Result<Vec<HtmlBlock>, BookError> {
+        let (_, article) = self.articles.get(index).ok_or(BookError::SpineIndex(index))?;
+        article.blocks(0)
+    }
+
+    /// A section per feed with its articles under it.
+    fn toc(&self) -> Vec<NavEntry> {
+        let mut toc = Vec::new();
+        for (index, (feed, article)) in self.articles.iter().enumerate() {
+            if index == 0 || self.articles[index - 1].0 != *feed {
+                toc.push(NavEntry {
+                    title: feed.clone(),
+                    spine_index: index,
+                    anchor: None,
+                    level: 0,
+                });
+            }
+            toc.push(NavEntry {
+                title: article.metadata().title,
+                spine_index: index,
+                anchor: None,
+                level: 1,
+            });
+        }
+        toc
+    }
+
+    fn resource(&self, name: &str) -> Option<Vec<u8>> {
+        self.articles.iter().find_map(|(_, article)| article.resource(name))
+    }
+
+    fn spine_title(&self, index: usize) -> Option<String> {
+        self.articles.get(index).map(|(_, article)| article.metadata().title)
+    }
+}
@@ -0,0 +1,706 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use tern_book::serialize::CAPS_REQUIRED;
+use thiserror::Error;
+
+pub mod feeds;
+pub mod mock;
+
+pub const MAGIC: u16 = 0x5452; // "TR"
+pub const VERSION: u8 = 0x01;
+pub const PROTOCOL_ID: u32 = 0x5854_3430; // "XT40"
+
+/// Device error code for requests it cannot serve yet, e.g. while it asks
+/// whether to allow USB access.
+pub const ERR_BUSY: u16 = 8;
+/// Device error code for a path that does not exist.
+pub const ERR_NOT_FOUND: u16 = 4;
+/// Device error code for writes while USB access was allowed read-only.
+pub const ERR_READ_ONLY: u16 = 9;
+
+pub const FLAG_RESP: u8 = 1 << 0;
+pub const FLAG_ERR: u8 = 1 << 1;
+pub const FLAG_EOF: u8 = 1 << 2;
+pub const FLAG_CONT: u8 = 1 << 3;
+/// The data of a streamed `WRITE` chunk is an LZ4 block.
+pub const FLAG_LZ4: u8 = 1 << 4;
+
+/// `INFO` capability bit for LZ4 compressed `WRITE` chunks.
+pub const CAP_LZ4: u32 = 1 << 10;
+/// Uncompressed data per compressed chunk, as a multiple of the frame payload
+/// limit; the device refuses chunks that inflate to more than 4 times it.
+const LZ4_CHUNK_RATIO: usize = 4;
+
+const HEADER_LEN: usize = 2 + 1 + 1 + 1 + 2 + 4;
+const MAX_FRAME_PAYLOAD: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Ping = 0x01,
+    Info = 0x02,
+    List = 0x10,
+    Read = 0x11,
+    Write = 0x12,
+    Delete = 0x13,
+    Mkdir = 0x14,
+    Rmdir = 0x15,
+    Rename = 0x16,
+    Cancel = 0x17,
+    DupCheck = 0x18,
+    Cleanup = 0x19,
+    Hash = 0x1A,
+    Bench = 0x1B,
+    Eject = 0x20,
+}
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serial error: {0}")]
+    Serial(#[from] serialport::Error),
+    #[error("timed out waiting for the device")]
+    Timeout,
+    #[error("device error {code}: {message}")]
+    Device { code: u16, message: String },
+    #[error("unexpected response: {0}")]
+    Protocol(String),
+    #[error("device allows read-only access: {0}")]
+    ReadOnly(String),
+    #[error("device cannot open this file: {0}")]
+    Incompatible(String),
+    #[error("feeds error: {0}")]
+    Feeds(String),
+    #[error("conversion failed: {0}")]
+    Book(#[from] tern_book::BookError),
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub version: u8,
+    pub flags: u8,
+    pub cmd: u8,
+    pub req_id: u16,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub max_payload: u32,
+    pub capabilities: u32,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+/// What the device reports about itself after the protocol id in `PING`.
+/// Firmware that predates the profile sends none.
+#[derive(Clone, Debug)]
+pub struct DeviceProfile {
+    pub model: String,
+    /// Screen size books are laid out for, in portrait.
+    pub screen_width: u16,
+    pub screen_height: u16,
+    pub gray_bits: u8,
+    pub firmware: String,
+    /// Oldest and newest TRBK and TRI versions the firmware opens.
+    pub trbk_versions: (u8, u8),
+    pub trimg_versions: (u8, u8),
+    /// TRBK capability bits the firmware reads; `None` from firmware that
+    /// predates them.
+    pub trbk_capabilities: Option<u32>,
+    /// Commit and build time of the firmware; `None` from firmware that
+    /// predates them.
+    pub firmware_build: Option<(String, String)>,
+}
+
+impl DeviceProfile {
+    fn parse(cursor: &mut Cursor) -> Result<Self, SyncError> {
+        let model_len = cursor.u16()? as usize;
+        let model = cursor.string(model_len)?;
+        let screen_width = cursor.u16()?;
+        let screen_height = cursor.u16()?;
+        let gray_bits = cursor.u8()?;
+        let firmware_len = cursor.u16()? as usize;
+        let firmware = cursor.string(firmware_len)?;
+        let trbk_versions = (cursor.u8()?, cursor.u8()?);
+        let trimg_versions = (cursor.u8()?, cursor.u8()?);
+        let trbk_capabilities = if cursor.remaining() >= 4 {
+            Some(cursor.u32()?)
+        } else {
+            None
+        };
+        let firmware_build = if cursor.remaining() >= 2 {
+            let commit_len = cursor.u16()? as usize;
+            let commit = cursor.string(commit_len)?;
+            let built_len = cursor.u16()? as usize;
+            Some((commit, cursor.string(built_len)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            model,
+            screen_width,
+            screen_height,
+            gray_bits,
+            firmware,
+            trbk_versions,
+            trimg_versions,
+            trbk_capabilities,
+            firmware_build,
+        })
+    }
+
+    /// Refuses books and images in a version the device cannot open, and
+    /// books that need a capability it lacks. Other files are not checked.
+    pub fn check_file(&self, data: &[u8]) -> Result<(), SyncError> {
+        let (kind, (oldest, newest)) = match data.get(0..4) {
+            Some(b"TRBK") => ("TRBK", self.trbk_versions),
+            Some(b"TRIM") => ("TRI", self.trimg_versions),
+            _ => return Ok(()),
+        };
+        let Some(&version) = data.get(4) else {
+            return Ok(());
+        };
+        if version < oldest || version > newest {
+            return Err(SyncError::Incompatible(format!(
+                "{} v{}, {} {} opens v{}-v{}",
+                kind, version, self.model, self.firmware, oldest, newest
+            )));
+        }
+        let (Some(supported), Some(header)) = (self.trbk_capabilities, data.get(0x24..0x28)) else {
+            return Ok(());
+        };
+        let capabilities = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let missing = capabilities & !supported & CAPS_REQUIRED;
+        if kind == "TRBK" && missing != 0 {
+            return Err(SyncError::Incompatible(format!(
+                "TRBK needs capabilities {:#06x}, {} {} reads {:#06x}",
+                missing, self.model, self.firmware, supported
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ListEntry {
+    pub name: String,
+    pub short_name: String,
+    pub attributes: u8,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// SD card timings from the device's `BENCH`.
+#[derive(Clone, Debug)]
+pub struct CardBench {
+    pub size: u32,
+    pub write: Duration,
+    pub read: Duration,
+}
+
+/// A file as the device's `HASH` reports it.
+#[derive(Clone, Debug)]
+pub struct FileHash {
+    pub name: String,
+    pub size: u64,
+    pub crc: u32,
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn encode_frame(flags: u8, cmd: u8, req_id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len() + 4);
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    out.push(VERSION);
+    out.push(flags);
+    out.push(cmd);
+    out.extend_from_slice(&req_id.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    let crc = crc32(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Takes the next valid frame off the front of `rx`, dropping log noise and
+/// frames with a bad CRC before it. Returns `None` until a whole frame is in.
+pub fn take_frame(rx: &mut Vec<u8>) -> Option<Frame> {
+    let magic = MAGIC.to_le_bytes();
+    loop {
+        let start = rx.windows(2).position(|w| w == magic)?;
+        rx.drain(..start);
+        if rx.len() < HEADER_LEN {
+            return None;
+        }
+        let len = u32::from_le_bytes([rx[7], rx[8], rx[9], rx[10]]) as usize;
+        if len > MAX_FRAME_PAYLOAD {
+            rx.drain(..1);
+            continue;
+        }
+        let total = HEADER_LEN + len + 4;
+        if rx.len() < total {
+            return None;
+        }
+        let crc_start = HEADER_LEN + len;
+        let expected = u32::from_le_bytes([rx[crc_start], rx[crc_start + 1], rx[crc_start + 2], rx[crc_start + 3]]);
+        if crc32(&rx[..crc_start]) != expected {
+            log::debug!("dropping frame with bad crc");
+            rx.drain(..1);
+            continue;
+        }
+        let frame = Frame {
+            version: rx[2],
+            flags: rx[3],
+            cmd: rx[4],
+            req_id: u16::from_le_bytes([rx[5], rx[6]]),
+            payload: rx[HEADER_LEN..crc_start].to_vec(),
+        };
+        rx.drain(..total);
+        return Some(frame);
+    }
+}
+
+fn push_path(buf: &mut Vec<u8>, path: &str) {
+    buf.extend_from_slice(&(path.len() as u16).to_le_bytes());
+    buf.extend_from_slice(path.as_bytes());
+}
+
+/// Little-endian reader over a response payload.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SyncError> {
+        if self.pos + len > self.data.len() {
+            return Err(SyncError::Protocol("short payload".into()));
+        }
+        let out = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, SyncError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SyncError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SyncError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SyncError> {
+        let b = self.take(8)?;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(b);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, SyncError> {
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+/// Host side of the USB serial file protocol described in `docs/serial.md`.
+pub struct Client<P> {
+    port: P,
+    rx: Vec<u8>,
+    next_req: u16,
+    timeout: Duration,
+    max_payload: usize,
+    profile: Option<DeviceProfile>,
+    host_id: Option<String>,
+    /// Set by [`Client::info`] when the device accepts compressed chunks.
+    compress: bool,
+}
+
+impl<P: Read + Write> Client<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            rx: Vec::new(),
+            next_req: 1,
+            timeout: Duration::from_secs(5),
+            max_payload: 4096,
+            profile: None,
+            host_id: None,
+            compress: false,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Identifier sent with `PING`, which the device can remember as always
+    /// allowed.
+    pub fn set_host_id(&mut self, host_id: impl Into<String>) {
+        self.host_id = Some(host_id.into());
+    }
+
+    fn alloc_req(&mut self) -> u16 {
+        let req = self.next_req;
+        self.next_req = self.next_req.wrapping_add(1).max(1);
+        req
+    }
+
+    fn send(&mut self, flags: u8, cmd: Command, req_id: u16, payload: &[u8]) -> Result<(), SyncError> {
+        let frame = encode_frame(flags, cmd as u8, req_id, payload);
+        self.port.write_all(&frame)?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// Pulls the next valid frame off the wire, skipping log noise and corrupt frames.
+    fn read_frame(&mut self) -> Result<Frame, SyncError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(frame) = take_frame(&mut self.rx) {
+                return Ok(frame);
+            }
+            if Instant::now() >= deadline {
+                return Err(SyncError::Timeout);
+            }
+            let mut buf = [0u8; 4096];
+            match self.port.read(&mut buf) {
+                Ok(0) => std::thread::sleep(Duration::from_millis(5)),
+                Ok(len) => self.rx.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(5))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn read_response(&mut self, cmd: Command, req_id: u16) -> Result<Frame, SyncError> {
+        loop {
+            let frame = self.read_frame()?;
+            if frame.flags & FLAG_RESP == 0 || frame.req_id != req_id {
+                log::debug!("ignoring frame cmd=0x{:02X} req={}", frame.cmd, frame.req_id);
+                continue;
+            }
+            if frame.flags & FLAG_ERR != 0 {
+                let mut cursor = Cursor::new(&frame.payload);
+                let code = cursor.u16().unwrap_or(0);
+                let len = cursor.u16().unwrap_or(0) as usize;
+                let message = cursor.string(len.min(cursor.remaining())).unwrap_or_default();
+                if code == ERR_READ_ONLY {
+                    return Err(SyncError::ReadOnly(message));
+                }
+                return Err(SyncError::Device { code, message });
+            }
+            if frame.cmd != cmd as u8 {
+                return Err(SyncError::Protocol(format!(
+                    "expected cmd 0x{:02X}, got 0x{:02X}",
+                    cmd as u8, frame.cmd
+                )));
+            }
+            return Ok(frame);
+        }
+    }
+
+    /// Sends a request and gathers a possibly chunked (`CONT`..`EOF`) response.
+    fn request(&mut self, cmd: Command, payload: &[u8]) -> Result<(Frame, Vec<u8>), SyncError> {
+        let req_id = self.alloc_req();
+        self.send(0, cmd, req_id, payload)?;
+        let mut frame = self.read_response(cmd, req_id)?;
+        let mut data = std::mem::take(&mut frame.payload);
+        while frame.flags & FLAG_CONT != 0 {
+            frame = self.read_response(cmd, req_id)?;
+            data.extend_from_slice(&frame.payload);
+        }
+        Ok((frame, data))
+    }
+
+    /// Returns the protocol id and the frame version the device answered with,
+    /// and keeps the device profile when there is one.
+    pub fn ping(&mut self) -> Result<(u32, u8), SyncError> {
+        let mut payload = Vec::new();
+        if let Some(host_id) = &self.host_id {
+            push_path(&mut payload, host_id);
+        }
+        let (frame, data) = self.request(Command::Ping, &payload)?;
+        let mut cursor = Cursor::new(&data);
+        let id = cursor.u32()?;
+        if cursor.remaining() > 0 {
+            self.profile = Some(DeviceProfile::parse(&mut cursor)?);
+        }
+        Ok((id, frame.version))
+    }
+
+    /// Pings until the device accepts, for up to `wait` while it is busy
+    /// showing the USB prompt. `waiting` is called once when it is.
+    pub fn wait_for_access(&mut self, wait: Duration, mut waiting: impl FnMut()) -> Result<(u32, u8), SyncError> {
+        let deadline = Instant::now() + wait;
+        let mut notified = false;
+        loop {
+            match self.ping() {
+                Err(SyncError::Device { code: ERR_BUSY, .. }) if Instant::now() < deadline => {
+                    if !notified {
+                        waiting();
+                        notified = true;
+                    }
+                    std::thread::sleep(Duration::from_millis(500));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Profile from the last [`Client::ping`].
+    pub fn profile(&self) -> Option<&DeviceProfile> {
+        self.profile.as_ref()
+    }
+
+    pub fn info(&mut self) -> Result<DeviceInfo, SyncError> {
+        let (_, data) = self.request(Command::Info, &[])?;
+        let mut cursor = Cursor::new(&data);
+        let max_payload = cursor.u32()?;
+        let capabilities = cursor.u32()?;
+        let (free_bytes, total_bytes) = if cursor.remaining() >= 16 {
+            (Some(cursor.u64()?), Some(cursor.u64()?))
+        } else {
+            (None, None)
+        };
+        if max_payload > 0 {
+            self.max_payload = max_payload as usize;
+        }
+        self.compress = capabilities & CAP_LZ4 != 0;
+        Ok(DeviceInfo {
+            max_payload,
+            capabilities,
+            free_bytes,
+            total_bytes,
+        })
+    }
+
+    /// Lists a directory page by page; returns the device's reported total and the entries.
+    pub fn list(&mut self, path: &str) -> Result<(u32, Vec<ListEntry>), SyncError> {
+        let mut entries = Vec::new();
+        let mut total;
+        loop {
+            let mut payload = Vec::new();
+            push_path(&mut payload, path);
+            payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&0u16.to_le_bytes());
+            let (_, data) = self.request(Command::List, &payload)?;
+            let mut cursor = Cursor::new(&data);
+            total = cursor.u32()?;
+            let _start = cursor.u32()?;
+            let count = cursor.u16()?;
+            for _ in 0..count {
+                let is_dir = cursor.u8()? != 0;
+                let attributes = cursor.u8()?;
+                let name_len = cursor.u16()? as usize;
+                let name = cursor.string(name_len)?;
+                let short_len = cursor.u8()? as usize;
+                let short_name = cursor.string(short_len)?;
+                let size = cursor.u64()?;
+                entries.push(ListEntry {
+                    name,
+                    short_name,
+                    attributes,
+                    is_dir,
+                    size,
+                });
+            }
+            if count == 0 || entries.len() as u32 >= total {
+                break;
+            }
+        }
+        Ok((total, entries))
+    }
+
+    pub fn read_file(&mut self, path: &str, len: u64) -> Result<Vec<u8>, SyncError> {
+        let mut out = Vec::with_capacity(len as usize);
+        while (out.len() as u64) < len {
+            let chunk = (len - out.len() as u64).min(self.max_payload as u64) as u32;
+            let mut payload = Vec::new();
+            push_path(&mut payload, path);
+            payload.extend_from_slice(&(out.len() as u64).to_le_bytes());
+            payload.extend_from_slice(&chunk.to_le_bytes());
+            let (_, data) = self.request(Command::Read, &payload)?;
+            if data.is_empty() {
+                break;
+            }
+            out.extend_from_slice(&data);
+        }
+        Ok(out)
+    }
+
+    /// Streams `data` to `path` with `CONT`/`EOF` chunks under one request id.
+    /// Files the device's profile says it cannot open are refused first.
+    /// Chunks go LZ4 compressed when the device supports it and they shrink.
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), SyncError> {
+        if let Some(profile) = &self.profile {
+            profile.check_file(data)?;
+        }
+        let req_id = self.alloc_req();
+        let header_len = 2 + path.len() + 4 + 8;
+        let chunk_len = self.max_payload.saturating_sub(header_len).max(1);
+        let mut offset = 0usize;
+        loop {
+            let mut end = (offset + chunk_len).min(data.len());
+            let mut compressed = None;
+            if self.compress {
+                let wide_end = (offset + chunk_len * LZ4_CHUNK_RATIO).min(data.len());
+                let packed = lz4_flex::block::compress_prepend_size(&data[offset..wide_end]);
+                if packed.len() <= chunk_len && packed.len() < wide_end - offset {
+                    end = wide_end;
+                    compressed = Some(packed);
+                }
+            }
+            let last = end >= data.len();
+            let mut payload = Vec::with_capacity(header_len + end - offset);
+            push_path(&mut payload, path);
+            payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&(offset as u64).to_le_bytes());
+            let mut flags = if last { FLAG_EOF } else { FLAG_CONT };
+            match &compressed {
+                Some(packed) => {
+                    payload.extend_from_slice(packed);
+                    flags |= FLAG_LZ4;
+                }
+                None => payload.extend_from_slice(&data[offset..end]),
+            }
+            self.send(flags, Command::Write, req_id, &payload)?;
+            let frame = self.read_response(Command::Write, req_id)?;
+            let written = Cursor::new(&frame.payload).u32()? as usize;
+            if last {
+                if written != data.len() {
+                    return Err(SyncError::Protocol(format!(
+                        "device reported {} of {} bytes written",
+                        written,
+                        data.len()
+                    )));
+                }
+                return Ok(());
+            }
+            if written <= offset {
+                return Err(SyncError::Protocol(format!(
+                    "device made no progress at offset {}",
+                    offset
+                )));
+            }
+            offset = written;
+        }
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), SyncError> {
+        let mut payload = Vec::new();
+        push_path(&mut payload, path);
+        self.request(Command::Delete, &payload)?;
+        Ok(())
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> Result<(), SyncError> {
+        let mut payload = Vec::new();
+        push_path(&mut payload, path);
+        self.request(Command::Mkdir, &payload)?;
+        Ok(())
+    }
+
+    /// Sizes and CRC-32s of the files in `path`. The device hashes new files a
+    /// bit per request, so this asks again until every CRC is known.
+    pub fn hash_dir(&mut self, path: &str) -> Result<Vec<FileHash>, SyncError> {
+        let mut payload = Vec::new();
+        push_path(&mut payload, path);
+        loop {
+            let (_, data) = self.request(Command::Hash, &payload)?;
+            let mut cursor = Cursor::new(&data);
+            let count = cursor.u32()?;
+            let mut files = Vec::with_capacity(count as usize);
+            let mut pending = false;
+            for _ in 0..count {
+                let name_len = cursor.u16()? as usize;
+                let name = cursor.string(name_len)?;
+                let size = cursor.u64()?;
+                let known = cursor.u8()? != 0;
+                let crc = cursor.u32()?;
+                pending |= !known;
+                files.push(FileHash { name, size, crc });
+            }
+            if !pending {
+                return Ok(files);
+            }
+        }
+    }
+
+    /// Has the device write and read back a scratch file of `size` bytes on
+    /// its own, timing the card without the USB link.
+    pub fn bench(&mut self, size: u32) -> Result<CardBench, SyncError> {
+        let (_, data) = self.request(Command::Bench, &size.to_le_bytes())?;
+        let mut cursor = Cursor::new(&data);
+        Ok(CardBench {
+            size: cursor.u32()?,
+            write: Duration::from_micros(cursor.u32()? as u64),
+            read: Duration::from_micros(cursor.u32()? as u64),
+        })
+    }
+
+    /// Has the device delete `._*` files and other macOS and Windows clutter;
+    /// returns how many entries it removed.
+    pub fn cleanup(&mut self) -> Result<u32, SyncError> {
+        let (_, data) = self.request(Command::Cleanup, &[])?;
+        Cursor::new(&data).u32()
+    }
+}
+
+/// Host identifier to send with `PING`: the machine's host name when it can
+/// be found.
+pub fn default_host_id() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "tern-sync".to_string())
+}
+
+pub fn open_port(path: &str, baud: u32) -> Result<Box<dyn serialport::SerialPort>, SyncError> {
+    let port = serialport::new(path, baud)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+    Ok(port)
+}
+
+/// Best guess at the device's serial port: the ESP32-C3 USB Serial/JTAG shows up as an ACM port.
+pub fn find_port() -> Option<String> {
+    let ports = serialport::available_ports().ok()?;
+    let mut names = ports.into_iter().map(|p| p.port_name).collect::<Vec<_>>();
+    names.sort();
+    names
+        .iter()
+        .find(|name| name.contains("ttyACM") || name.contains("usbmodem"))
+        .or_else(|| names.first())
+        .cloned()
+}
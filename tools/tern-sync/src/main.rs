@@ -0,0 +1,596 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tern_book::input::article;
+use tern_sync::feeds::{FeedsConfig, NewsIssue, SeenArticles};
+use tern_sync::{Client, SyncError, ERR_NOT_FOUND, PROTOCOL_ID, VERSION};
+
+const DOCTOR_FILE: &str = "/TERNDOC.TMP";
+const DOCTOR_BYTES: usize = 16 * 1024;
+const BENCH_FILE: &str = "/TERNBNCH.TMP";
+/// Default `bench` size in KiB; the device takes up to 4 MiB.
+const BENCH_KIB: u32 = 1024;
+/// How long to wait for the user to answer the device's USB prompt.
+const ACCESS_WAIT: Duration = Duration::from_secs(60);
+/// How long `sync` keeps trying to reopen the port after the link dropped.
+const RECONNECT_WAIT: Duration = Duration::from_secs(30);
+/// Times `sync` sends one file before giving up on a link that keeps dropping.
+const SYNC_ATTEMPTS: u32 = 3;
+
+struct Check {
+    name: &'static str,
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+    listing: Vec<String>,
+}
+
+impl Report {
+    fn pass(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            ok: true,
+            detail: detail.into(),
+        });
+    }
+
+    fn fail(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            ok: false,
+            detail: detail.into(),
+        });
+    }
+
+    fn record<T>(&mut self, name: &'static str, result: Result<T, SyncError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.fail(name, err.to_string());
+                None
+            }
+        }
+    }
+
+    fn print(&self) -> bool {
+        println!("tern-sync doctor report");
+        println!("=======================");
+        for check in &self.checks {
+            println!(
+                "[{}] {:<12} {}",
+                if check.ok { " ok " } else { "FAIL" },
+                check.name,
+                check.detail
+            );
+        }
+        for line in &self.listing {
+            println!("    {}", line);
+        }
+        let passed = self.checks.iter().filter(|check| check.ok).count();
+        println!("-----------------------");
+        println!("{}/{} checks passed", passed, self.checks.len());
+        passed == self.checks.len()
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Deterministic test pattern so a bad read-back is reproducible.
+fn test_pattern(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn doctor(port_name: Option<String>, baud: u32) -> bool {
+    let mut report = Report::default();
+    println!("tern-sync {}", env!("CARGO_PKG_VERSION"));
+
+    let Some(port_name) = port_name.or_else(tern_sync::find_port) else {
+        report.fail("port", "no serial ports found; is the device plugged in?");
+        return report.print();
+    };
+    let Some(port) = report.record("port", tern_sync::open_port(&port_name, baud)) else {
+        return report.print();
+    };
+    report.pass("port", format!("{} @ {} baud", port_name, baud));
+    let mut client = Client::new(port);
+    client.set_host_id(tern_sync::default_host_id());
+
+    // Ping doubles as the wake-up: the device only enters USB mode on traffic,
+    // and asks first unless this host is remembered.
+    let access = client.wait_for_access(ACCESS_WAIT, || {
+        println!("Waiting for USB access to be allowed on the device...");
+    });
+    let Some((id, version)) = report.record("ping", access) else {
+        return report.print();
+    };
+    if id != PROTOCOL_ID || version != VERSION {
+        report.fail(
+            "ping",
+            format!(
+                "protocol 0x{:08X} v{} (expected 0x{:08X} v{})",
+                id, version, PROTOCOL_ID, VERSION
+            ),
+        );
+        return report.print();
+    }
+    report.pass("ping", format!("protocol XT40 v{}", version));
+    match client.profile() {
+        Some(profile) => report.pass(
+            "device",
+            format!(
+                "{} firmware {}{}, {}x{} {}-bit gray, TRBK v{}-v{}{}, TRI v{}-v{}",
+                profile.model,
+                profile.firmware,
+                profile
+                    .firmware_build
+                    .as_ref()
+                    .map(|(commit, built)| format!(" (commit {commit}, built {built})"))
+                    .unwrap_or_default(),
+                profile.screen_width,
+                profile.screen_height,
+                profile.gray_bits,
+                profile.trbk_versions.0,
+                profile.trbk_versions.1,
+                profile
+                    .trbk_capabilities
+                    .map(|caps| format!(" (capabilities {:#x})", caps))
+                    .unwrap_or_default(),
+                profile.trimg_versions.0,
+                profile.trimg_versions.1
+            ),
+        ),
+        None => report.fail("device", "firmware does not report a device profile"),
+    }
+
+    let info = report.record("info", client.info());
+    if let Some(info) = &info {
+        report.pass(
+            "info",
+            format!(
+                "max payload {} bytes, capabilities 0x{:08X}",
+                info.max_payload, info.capabilities
+            ),
+        );
+        match (info.free_bytes, info.total_bytes) {
+            (Some(free), Some(total)) => {
+                let detail = format!("{} free of {}", format_bytes(free), format_bytes(total));
+                if free < DOCTOR_BYTES as u64 {
+                    report.fail("free space", detail);
+                } else {
+                    report.pass("free space", detail);
+                }
+            }
+            _ => report.fail("free space", "firmware does not report free space"),
+        }
+    }
+
+    let data = test_pattern(DOCTOR_BYTES);
+    let expected_crc = tern_sync::crc32(&data);
+    let started = Instant::now();
+    let written = match client.write_file(DOCTOR_FILE, &data) {
+        Err(SyncError::ReadOnly(_)) => {
+            report.pass("write", "skipped, USB access was allowed read-only");
+            false
+        }
+        result => report.record("write", result).is_some(),
+    };
+    if written {
+        let secs = started.elapsed().as_secs_f64();
+        report.pass(
+            "write",
+            format!(
+                "{} bytes to {} in {:.2}s ({}/s)",
+                data.len(),
+                DOCTOR_FILE,
+                secs,
+                format_bytes((data.len() as f64 / secs.max(0.001)) as u64)
+            ),
+        );
+        if let Some(read) = report.record("read back", client.read_file(DOCTOR_FILE, data.len() as u64)) {
+            let actual_crc = tern_sync::crc32(&read);
+            if read.len() == data.len() && actual_crc == expected_crc {
+                report.pass("read back", format!("crc32 0x{:08X} matches", actual_crc));
+            } else {
+                report.fail(
+                    "read back",
+                    format!(
+                        "{} bytes, crc32 0x{:08X} (expected {} bytes, 0x{:08X})",
+                        read.len(),
+                        actual_crc,
+                        data.len(),
+                        expected_crc
+                    ),
+                );
+            }
+        }
+        if report.record("cleanup", client.delete(DOCTOR_FILE)).is_some() {
+            report.pass("cleanup", format!("deleted {}", DOCTOR_FILE));
+        }
+    }
+
+    if let Some((total, entries)) = report.record("list /", client.list("/")) {
+        let dirs = entries.iter().filter(|entry| entry.is_dir).count();
+        let detail = format!(
+            "{} entries ({} files, {} dirs)",
+            entries.len(),
+            entries.len() - dirs,
+            dirs
+        );
+        if entries.len() as u32 == total {
+            report.pass("list /", detail);
+        } else {
+            report.fail("list /", format!("{}, device reported {}", detail, total));
+        }
+        for entry in &entries {
+            report.listing.push(format!(
+                "{:<4} {:>10}  {:<12} {}",
+                if entry.is_dir { "dir" } else { "file" },
+                entry.size,
+                entry.short_name,
+                entry.name
+            ));
+        }
+    }
+
+    report.print()
+}
+
+/// Builds today's news issue from the feeds in `config_path`, uploads it and
+/// deletes issues older than the configured number of days.
+fn cleanup(port_name: Option<String>, baud: u32) -> Result<(), SyncError> {
+    let port_name = port_name
+        .or_else(tern_sync::find_port)
+        .ok_or_else(|| SyncError::Protocol("no serial ports found; is the device plugged in?".to_string()))?;
+    let mut client = Client::new(tern_sync::open_port(&port_name, baud)?);
+    client.set_host_id(tern_sync::default_host_id());
+    client.wait_for_access(ACCESS_WAIT, || {
+        println!("Waiting for USB access to be allowed on the device...");
+    })?;
+    let removed = client.cleanup()?;
+    println!("Removed {} system files and folders", removed);
+    Ok(())
+}
+
+fn rate(bytes: usize, elapsed: Duration) -> String {
+    format!("{}/s", format_bytes((bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64))
+}
+
+/// Times the card on its own with `BENCH`, then the same amount of data over
+/// USB, so a slow card can be told apart from a slow link.
+fn bench(port_name: Option<String>, baud: u32, kib: u32) -> Result<(), SyncError> {
+    let mut client = connect(port_name.as_deref(), baud)?;
+    let size = kib.saturating_mul(1024) as usize;
+    let card = client.bench(size as u32)?;
+    println!(
+        "SD write     {:>12}  ({} in {:.2}s)",
+        rate(size, card.write),
+        format_bytes(size as u64),
+        card.write.as_secs_f64()
+    );
+    println!(
+        "SD read      {:>12}  ({} in {:.2}s)",
+        rate(size, card.read),
+        format_bytes(size as u64),
+        card.read.as_secs_f64()
+    );
+
+    // The pattern does not compress, so this is the link's raw rate.
+    let data = test_pattern(size);
+    let started = Instant::now();
+    client.write_file(BENCH_FILE, &data)?;
+    let upload = started.elapsed();
+    let started = Instant::now();
+    let read = client.read_file(BENCH_FILE, data.len() as u64);
+    let download = started.elapsed();
+    client.delete(BENCH_FILE)?;
+    if read? != data {
+        return Err(SyncError::Protocol("benchmark file read back differently".to_string()));
+    }
+    println!(
+        "USB upload   {:>12}  ({:.2}s, includes the card write)",
+        rate(size, upload),
+        upload.as_secs_f64()
+    );
+    println!(
+        "USB download {:>12}  ({:.2}s, includes the card read)",
+        rate(size, download),
+        download.as_secs_f64()
+    );
+    let card_share = card.write.as_secs_f64() / upload.as_secs_f64().max(0.001);
+    let verdict = if card_share > 0.5 {
+        "a faster card would help most"
+    } else {
+        "the USB link is the limit"
+    };
+    println!("The card takes {:.0}% of the upload time; {}.", card_share * 100.0, verdict);
+    Ok(())
+}
+
+fn connect(port_name: Option<&str>, baud: u32) -> Result<Client<Box<dyn serialport::SerialPort>>, SyncError> {
+    let port_name = port_name
+        .map(str::to_string)
+        .or_else(tern_sync::find_port)
+        .ok_or_else(|| SyncError::Protocol("no serial ports found; is the device plugged in?".to_string()))?;
+    let mut client = Client::new(tern_sync::open_port(&port_name, baud)?);
+    client.set_host_id(tern_sync::default_host_id());
+    client.wait_for_access(ACCESS_WAIT, || {
+        println!("Waiting for USB access to be allowed on the device...");
+    })?;
+    client.info()?;
+    Ok(client)
+}
+
+/// Reopens the port until the device answers again or `RECONNECT_WAIT` passes.
+fn reconnect(port_name: Option<&str>, baud: u32) -> Result<Client<Box<dyn serialport::SerialPort>>, SyncError> {
+    let deadline = Instant::now() + RECONNECT_WAIT;
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        match connect(port_name, baud) {
+            Err(SyncError::Io(_) | SyncError::Serial(_) | SyncError::Timeout) if Instant::now() < deadline => {}
+            result => return result,
+        }
+    }
+}
+
+fn join_device_path(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+/// Uploads the files under `local` that are missing from `device_dir` or
+/// differ in size or CRC. A dropped link is reopened and the file sent again,
+/// which the device resumes where it stopped.
+fn sync(port_name: Option<String>, baud: u32, local: &Path, device_dir: &str) -> Result<(), SyncError> {
+    let mut client = connect(port_name.as_deref(), baud)?;
+    let mut uploaded = 0;
+    let mut unchanged = 0;
+    let mut dirs: Vec<(PathBuf, String)> = vec![(local.to_path_buf(), device_dir.to_string())];
+    while let Some((local_dir, remote_dir)) = dirs.pop() {
+        let remote = match client.hash_dir(&remote_dir) {
+            Ok(files) => files,
+            Err(SyncError::Device { .. }) => {
+                client.mkdir(&remote_dir)?;
+                Vec::new()
+            }
+            Err(err) => return Err(err),
+        };
+        let mut entries = std::fs::read_dir(&local_dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let remote_path = join_device_path(&remote_dir, &name);
+            if entry.file_type()?.is_dir() {
+                dirs.push((entry.path(), remote_path));
+                continue;
+            }
+            let data = std::fs::read(entry.path())?;
+            let crc = tern_sync::crc32(&data);
+            let same = remote.iter().any(|file| {
+                file.name.eq_ignore_ascii_case(&name) && file.size == data.len() as u64 && file.crc == crc
+            });
+            if same {
+                unchanged += 1;
+                continue;
+            }
+            let mut attempt = 1;
+            loop {
+                match client.write_file(&remote_path, &data) {
+                    Ok(()) => break,
+                    Err(err @ (SyncError::Io(_) | SyncError::Serial(_) | SyncError::Timeout)) if attempt < SYNC_ATTEMPTS => {
+                        eprintln!("[tern-sync] warning: {} interrupted: {err}; reconnecting", remote_path);
+                        attempt += 1;
+                        client = reconnect(port_name.as_deref(), baud)?;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            println!("Uploaded {} ({})", remote_path, format_bytes(data.len() as u64));
+            uploaded += 1;
+        }
+    }
+    println!("{} uploaded, {} unchanged", uploaded, unchanged);
+    Ok(())
+}
+
+fn feeds(port_name: Option<String>, baud: u32, config_path: &Path) -> Result<(), SyncError> {
+    let config = FeedsConfig::load(config_path)?;
+    let seen_path = config_path.with_extension("seen");
+    let mut seen = SeenArticles::load(&seen_path)?;
+    let today = tern_sync::feeds::today();
+    let mut issue = NewsIssue::new(today);
+    for feed in &config.feeds {
+        // Articles an earlier run today already put in the issue.
+        for article in seen.taken_on(today, &feed.name) {
+            match tern_book::input::ArticleInput::fetch(&article.link) {
+                Ok(input) => issue.push(&feed.name, input),
+                Err(err) => eprintln!("[tern-sync] warning: {} failed: {err}", article.link),
+            }
+        }
+        let items = match article::fetch(&feed.url).map_err(SyncError::from).and_then(|xml| tern_sync::feeds::parse_feed(&xml)) {
+            Ok(items) => items,
+            Err(err) => {
+                eprintln!("[tern-sync] warning: feed {} failed: {err}", feed.name);
+                continue;
+            }
+        };
+        let items = items.into_iter().filter(|item| !seen.contains(&item.id)).collect::<Vec<_>>();
+        let max_articles = feed.max_articles.unwrap_or(config.max_articles);
+        let mut taken = 0;
+        for item in &items {
+            if taken == max_articles {
+                break;
+            }
+            println!("{}: {}", feed.name, if item.title.is_empty() { &item.link } else { &item.title });
+            match tern_book::input::ArticleInput::fetch(&item.link) {
+                Ok(input) => {
+                    issue.push(&feed.name, input);
+                    seen.add(today, &feed.name, item);
+                    taken += 1;
+                }
+                Err(err) => eprintln!("[tern-sync] warning: {} failed: {err}", item.link),
+            }
+        }
+    }
+
+    let out_dir = env::temp_dir().join(format!("tern-sync-news-{}", std::process::id()));
+    let mut files = Vec::new();
+    if issue.is_empty() {
+        println!("No new articles");
+    } else {
+        std::fs::create_dir_all(&out_dir)?;
+        let output = out_dir.join(tern_sync::feeds::issue_name(today));
+        let options = tern_book::ConvertOptions {
+            sizes: config.sizes.clone(),
+            font_paths: config.font_paths.clone(),
+            ..tern_book::ConvertOptions::default()
+        };
+        tern_book::convert_book_to_trbk(&issue, &output, &options)?;
+        files = std::fs::read_dir(&out_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+    }
+
+    let port_name = port_name
+        .or_else(tern_sync::find_port)
+        .ok_or_else(|| SyncError::Protocol("no serial ports found; is the device plugged in?".to_string()))?;
+    let mut client = Client::new(tern_sync::open_port(&port_name, baud)?);
+    client.set_host_id(tern_sync::default_host_id());
+    client.wait_for_access(ACCESS_WAIT, || {
+        println!("Waiting for USB access to be allowed on the device...");
+    })?;
+    let entries = match client.list(&config.folder) {
+        Ok((_, entries)) => entries,
+        Err(SyncError::Device { code: ERR_NOT_FOUND, .. }) => {
+            client.mkdir(&config.folder)?;
+            Vec::new()
+        }
+        Err(err) => return Err(err),
+    };
+    for file in &files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let data = std::fs::read(file)?;
+        client.write_file(&format!("{}/{}", config.folder, name), &data)?;
+        println!("Uploaded {}/{} ({})", config.folder, name, format_bytes(data.len() as u64));
+    }
+    let _ = std::fs::remove_dir_all(&out_dir);
+    seen.save(&seen_path, today)?;
+
+    let oldest_kept = today - time::Duration::days(i64::from(config.keep_days) - 1);
+    for entry in entries.iter().filter(|entry| !entry.is_dir) {
+        let expired = tern_sync::feeds::issue_date(&entry.name).is_some_and(|date| date < oldest_kept);
+        if expired {
+            client.delete(&format!("{}/{}", config.folder, entry.name))?;
+            println!("Deleted {}/{}", config.folder, entry.name);
+        }
+    }
+    println!("{} articles in {}", issue.len(), tern_sync::feeds::issue_name(today));
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() == 1 && (args[0] == "--version" || args[0] == "-V" || args[0] == "version") {
+        println!("{}", tern_build_info::VersionLine("tern-sync"));
+        return;
+    }
+    let mut port = None;
+    let mut baud = 115_200u32;
+    let mut command = None;
+    let mut feeds_path = None;
+    let mut operands = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--port" => {
+                i += 1;
+                port = args.get(i).cloned();
+            }
+            "--baud" => {
+                i += 1;
+                baud = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(baud);
+            }
+            "--feeds" => {
+                i += 1;
+                feeds_path = args.get(i).cloned();
+                command = Some("feeds".to_string());
+            }
+            other if command.is_none() => command = Some(other.to_string()),
+            other => operands.push(other.to_string()),
+        }
+        i += 1;
+    }
+
+    match (command.as_deref(), feeds_path) {
+        (Some("doctor"), _) => {
+            if !doctor(port, baud) {
+                std::process::exit(1);
+            }
+        }
+        (Some("cleanup"), _) => {
+            if let Err(err) = cleanup(port, baud) {
+                eprintln!("Cleanup failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        (Some("bench"), _) => {
+            let kib = operands.first().and_then(|s| s.parse().ok()).unwrap_or(BENCH_KIB);
+            if let Err(err) = bench(port, baud, kib) {
+                eprintln!("Bench failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        (Some("sync"), _) if !operands.is_empty() => {
+            let device_dir = operands.get(1).map(String::as_str).unwrap_or("/");
+            if let Err(err) = sync(port, baud, Path::new(&operands[0]), device_dir) {
+                eprintln!("Sync failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        (Some("feeds"), Some(feeds_path)) => {
+            if let Err(err) = feeds(port, baud, Path::new(&feeds_path)) {
+                eprintln!("Feeds failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("Usage: tern-sync [--port PATH] [--baud N] doctor");
+            eprintln!("       tern-sync [--port PATH] [--baud N] cleanup");
+            eprintln!("       tern-sync [--port PATH] [--baud N] bench [SIZE_KIB]");
+            eprintln!("       tern-sync [--port PATH] [--baud N] sync LOCAL_DIR [DEVICE_DIR]");
+            eprintln!("       tern-sync [--port PATH] [--baud N] --feeds feeds.toml");
+            eprintln!("       tern-sync --version");
+            std::process::exit(1);
+        }
+    }
+}
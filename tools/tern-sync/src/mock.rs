@@ -0,0 +1,824 @@
+//! The device end of the USB protocol, run in-process over a host directory.
+//!
+//! A [`MockDevice`] stands in for the serial port given to [`Client::new`],
+//! so host tooling can be tested in CI without an X4 plugged in. It answers
+//! the way `x4/src/usb_mode.rs` does: paged `LIST`, streamed and LZ4
+//! compressed `WRITE` with resume, `HASH`, the USB prompt (as `ERR_BUSY`)
+//! and read-only access. Clones share one device, so a test can keep a handle
+//! to pull the cable or look at the card while a client owns another.
+//!
+//! [`Client::new`]: crate::Client::new
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use tern_book::serialize::{CAP_COMPRESSION, CAP_FONT_PACK, CAP_KERNING, CAP_REFLOW_TEXT, CAP_SHARED_GLYPHS};
+use tern_core::fs::is_system_clutter;
+
+use crate::{
+    crc32, encode_frame, take_frame, Command, Cursor, Frame, CAP_LZ4, ERR_BUSY, ERR_NOT_FOUND, ERR_READ_ONLY,
+    FLAG_CONT, FLAG_EOF, FLAG_ERR, FLAG_LZ4, FLAG_RESP, PROTOCOL_ID,
+};
+
+const ERR_INVALID_COMMAND: u16 = 1;
+const ERR_BAD_PATH: u16 = 2;
+const ERR_IO: u16 = 3;
+const ERR_INVALID_ARGS: u16 = 7;
+
+/// `INFO` capabilities, as the firmware reports them.
+const CAPS_READ_ONLY: u32 = 0x0000_0283;
+const CAPS_FULL: u32 = 0x0000_0FFF;
+/// The firmware refuses compressed chunks inflating past this many payloads.
+const LZ4_MAX_RATIO: usize = 4;
+const BENCH_MAX_BYTES: u32 = 4 * 1024 * 1024;
+const DUP_HASH_LEN: usize = 4096;
+
+/// Printed between frames with [`MockDevice::log_noise`], like the firmware's
+/// log output on the same port.
+const LOG_LINE: &[u8] = b"INFO - usb: frame handled\r\n";
+
+/// Characters 0x80 to 0xFF of code page 437, the firmware's FatFs code page.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+/// Upper-case byte FatFs stores for each of them (its `ExCvt` table).
+const CP437_UPPER: [u8; 128] = [
+    0x80, 0x9A, 0x45, 0x41, 0x8E, 0x41, 0x8F, 0x80, 0x45, 0x45, 0x45, 0x49, 0x49, 0x49, 0x8E, 0x8F,
+    0x90, 0x92, 0x92, 0x4F, 0x99, 0x4F, 0x55, 0x55, 0x59, 0x99, 0x9A, 0x9B, 0x9C, 0x9D, 0x9E, 0x9F,
+    0x41, 0x49, 0x4F, 0x55, 0xA5, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xAB, 0xAC, 0xAD, 0xAE, 0xAF,
+    0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF,
+    0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD, 0xCE, 0xCF,
+    0xD0, 0xD1, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xDB, 0xDC, 0xDD, 0xDE, 0xDF,
+    0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xEB, 0xEC, 0xED, 0xEE, 0xEF,
+    0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
+];
+
+#[derive(Clone)]
+pub struct MockDevice {
+    state: Arc<Mutex<Device>>,
+}
+
+struct Device {
+    root: PathBuf,
+    max_payload: usize,
+    read_only: bool,
+    lz4: bool,
+    trbk_versions: (u8, u8),
+    /// Pings still answered `ERR_BUSY`, as while the prompt is showing.
+    busy_pings: u32,
+    log_noise: bool,
+    /// Frames still delivered before the link drops.
+    frames_left: Option<usize>,
+    connected: bool,
+    ejected: bool,
+    rx: Vec<u8>,
+    tx: Vec<u8>,
+    upload: Option<Upload>,
+    /// Upload kept when the link dropped or the host restarted it.
+    paused: Option<Upload>,
+    resumed: u32,
+}
+
+struct Upload {
+    req_id: u16,
+    path: String,
+    total: u64,
+    data: Vec<u8>,
+}
+
+impl MockDevice {
+    /// A device with full access whose card is the directory `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(Device {
+                root: root.into(),
+                max_payload: 4096,
+                read_only: false,
+                lz4: true,
+                trbk_versions: (1, 3),
+                busy_pings: 0,
+                log_noise: false,
+                frames_left: None,
+                connected: true,
+                ejected: false,
+                rx: Vec::new(),
+                tx: Vec::new(),
+                upload: None,
+                paused: None,
+                resumed: 0,
+            })),
+        }
+    }
+
+    /// Access allowed read-only on the prompt.
+    pub fn read_only(self) -> Self {
+        self.device().read_only = true;
+        self
+    }
+
+    /// Firmware that predates LZ4 compressed chunks.
+    pub fn without_lz4(self) -> Self {
+        self.device().lz4 = false;
+        self
+    }
+
+    pub fn max_payload(self, max_payload: usize) -> Self {
+        self.device().max_payload = max_payload;
+        self
+    }
+
+    /// Oldest and newest TRBK versions the profile says the firmware opens.
+    pub fn trbk_versions(self, oldest: u8, newest: u8) -> Self {
+        self.device().trbk_versions = (oldest, newest);
+        self
+    }
+
+    /// Keeps the USB prompt up for `pings` pings.
+    pub fn busy_for(self, pings: u32) -> Self {
+        self.device().busy_pings = pings;
+        self
+    }
+
+    /// Writes a log line before every response.
+    pub fn log_noise(self) -> Self {
+        self.device().log_noise = true;
+        self
+    }
+
+    /// Pulls the cable once `frames` more frames from the host have been
+    /// answered. Whatever upload is in flight is kept for resuming.
+    pub fn drop_link_after(&self, frames: usize) {
+        self.device().frames_left = Some(frames);
+    }
+
+    /// Plugs the cable back in.
+    pub fn reconnect(&self) {
+        let mut device = self.device();
+        device.connected = true;
+        device.rx.clear();
+        device.tx.clear();
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.device().root.clone()
+    }
+
+    /// Whether the host sent `EJECT`.
+    pub fn ejected(&self) -> bool {
+        self.device().ejected
+    }
+
+    /// Uploads picked up where an earlier one stopped.
+    pub fn resumed_uploads(&self) -> u32 {
+        self.device().resumed
+    }
+
+    fn device(&self) -> MutexGuard<'_, Device> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Read for MockDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut device = self.device();
+        let len = buf.len().min(device.tx.len());
+        buf[..len].copy_from_slice(&device.tx[..len]);
+        device.tx.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for MockDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut device = self.device();
+        if !device.connected {
+            return Ok(buf.len());
+        }
+        device.rx.extend_from_slice(buf);
+        while let Some(frame) = take_frame(&mut device.rx) {
+            if device.frames_left == Some(0) {
+                device.disconnect();
+                break;
+            }
+            if let Some(left) = device.frames_left.as_mut() {
+                *left -= 1;
+            }
+            device.handle(frame);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Device {
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.frames_left = None;
+        self.rx.clear();
+        self.tx.clear();
+        if let Some(upload) = self.upload.take() {
+            self.paused = Some(upload);
+        }
+    }
+
+    fn respond(&mut self, flags: u8, frame: &Frame, payload: &[u8]) {
+        if self.log_noise {
+            self.tx.extend_from_slice(LOG_LINE);
+        }
+        let response = encode_frame(FLAG_RESP | flags, frame.cmd, frame.req_id, payload);
+        self.tx.extend_from_slice(&response);
+    }
+
+    fn ok(&mut self, frame: &Frame, payload: &[u8]) {
+        self.respond(0, frame, payload);
+    }
+
+    fn error(&mut self, frame: &Frame, code: u16, message: &str) {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&code.to_le_bytes());
+        payload.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        payload.extend_from_slice(message.as_bytes());
+        self.respond(FLAG_ERR, frame, &payload);
+    }
+
+    fn io_error(&mut self, frame: &Frame, err: std::io::Error) {
+        let code = if err.kind() == std::io::ErrorKind::NotFound {
+            ERR_NOT_FOUND
+        } else {
+            ERR_IO
+        };
+        self.error(frame, code, &err.to_string());
+    }
+
+    /// Sends `payload` in `CONT` frames of at most the payload limit.
+    fn send_chunked(&mut self, frame: &Frame, payload: &[u8]) {
+        if payload.len() <= self.max_payload {
+            self.ok(frame, payload);
+            return;
+        }
+        let chunks: Vec<&[u8]> = payload.chunks(self.max_payload).collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let flags = if index + 1 == chunks.len() { FLAG_EOF } else { FLAG_CONT };
+            self.respond(flags, frame, chunk);
+        }
+    }
+
+    /// Host path of a device path. Paths leaving the card are refused.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for part in path.split('/').filter(|part| !part.is_empty() && *part != ".") {
+            if part == ".." || part.contains('\\') {
+                return None;
+            }
+            resolved.push(part);
+        }
+        Some(resolved)
+    }
+
+    fn handle(&mut self, frame: Frame) {
+        let cmd = frame.cmd;
+        if self.busy_pings > 0 || self.ejected {
+            if cmd == Command::Ping as u8 {
+                self.busy_pings = self.busy_pings.saturating_sub(1);
+            }
+            return self.error(&frame, ERR_BUSY, "usb not active");
+        }
+        if self.read_only && is_write_command(cmd) {
+            return self.error(&frame, ERR_READ_ONLY, "read-only access");
+        }
+        match cmd {
+            x if x == Command::Ping as u8 => self.ping(&frame),
+            x if x == Command::Info as u8 => self.info(&frame),
+            x if x == Command::List as u8 => self.list(&frame),
+            x if x == Command::Read as u8 => self.read(&frame),
+            x if x == Command::Write as u8 => self.write(&frame),
+            x if x == Command::Delete as u8 => self.path_op(&frame, |path| fs::remove_file(path)),
+            x if x == Command::Mkdir as u8 => self.path_op(&frame, |path| fs::create_dir(path)),
+            x if x == Command::Rmdir as u8 => self.path_op(&frame, |path| fs::remove_dir(path)),
+            x if x == Command::Rename as u8 => self.rename(&frame),
+            x if x == Command::Cancel as u8 => self.cancel(&frame),
+            x if x == Command::DupCheck as u8 => self.dup_check(&frame),
+            x if x == Command::Cleanup as u8 => self.cleanup(&frame),
+            x if x == Command::Hash as u8 => self.hash(&frame),
+            x if x == Command::Bench as u8 => self.bench(&frame),
+            x if x == Command::Eject as u8 => {
+                self.ejected = true;
+                self.ok(&frame, &[]);
+            }
+            _ => self.error(&frame, ERR_INVALID_COMMAND, "unknown command"),
+        }
+    }
+
+    fn ping(&mut self, frame: &Frame) {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+        push_string(&mut payload, "X4");
+        payload.extend_from_slice(&480u16.to_le_bytes());
+        payload.extend_from_slice(&800u16.to_le_bytes());
+        payload.push(2);
+        push_string(&mut payload, tern_build_info::VERSION);
+        let (oldest, newest) = self.trbk_versions;
+        payload.extend_from_slice(&[oldest, newest, 1, 2]);
+        let capabilities = CAP_SHARED_GLYPHS | CAP_FONT_PACK | CAP_COMPRESSION | CAP_REFLOW_TEXT | CAP_KERNING;
+        payload.extend_from_slice(&capabilities.to_le_bytes());
+        push_string(&mut payload, tern_build_info::GIT_HASH);
+        push_string(&mut payload, tern_build_info::BUILD_TIME);
+        self.ok(frame, &payload);
+    }
+
+    fn info(&mut self, frame: &Frame) {
+        let capabilities = if self.read_only {
+            CAPS_READ_ONLY
+        } else if self.lz4 {
+            CAPS_FULL
+        } else {
+            CAPS_FULL & !CAP_LZ4
+        };
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.max_payload as u32).to_le_bytes());
+        payload.extend_from_slice(&capabilities.to_le_bytes());
+        payload.extend_from_slice(&(1u64 << 30).to_le_bytes());
+        payload.extend_from_slice(&(4u64 << 30).to_le_bytes());
+        self.ok(frame, &payload);
+    }
+
+    fn list(&mut self, frame: &Frame) {
+        let mut cursor = Cursor::new(&frame.payload);
+        let Some(dir) = read_path(&mut cursor).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        let start = cursor.u32().unwrap_or(0) as usize;
+        let max_entries = match cursor.u16().unwrap_or(0) {
+            0 => usize::MAX,
+            max => max as usize,
+        };
+        let entries = match dir_entries(&dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.io_error(frame, err),
+        };
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&(start as u32).to_le_bytes());
+        let count_pos = payload.len();
+        payload.extend_from_slice(&0u16.to_le_bytes());
+        let short_names = short_names(&entries);
+        let mut count = 0u16;
+        let page = entries.iter().zip(&short_names).skip(start).take(max_entries);
+        for ((name, is_dir, size), short_name) in page {
+            let entry_len = 1 + 1 + 2 + name.len() + 1 + short_name.len() + 8;
+            if count > 0 && payload.len() + entry_len > self.max_payload {
+                break;
+            }
+            payload.push(u8::from(*is_dir));
+            payload.push(if *is_dir { 0x10 } else { 0x20 });
+            push_string(&mut payload, name);
+            payload.push(short_name.len() as u8);
+            payload.extend_from_slice(short_name.as_bytes());
+            payload.extend_from_slice(&size.to_le_bytes());
+            count += 1;
+        }
+        payload[count_pos..count_pos + 2].copy_from_slice(&count.to_le_bytes());
+        self.respond(FLAG_EOF, frame, &payload);
+    }
+
+    fn read(&mut self, frame: &Frame) {
+        let mut cursor = Cursor::new(&frame.payload);
+        let Some(path) = read_path(&mut cursor).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        let (Ok(offset), Ok(len)) = (cursor.u64(), cursor.u32()) else {
+            return self.error(frame, ERR_INVALID_ARGS, "bad offset");
+        };
+        match read_at(&path, offset, len) {
+            Ok(data) => self.send_chunked(frame, &data),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    fn write(&mut self, frame: &Frame) {
+        if frame.flags & (FLAG_CONT | FLAG_EOF) == 0 {
+            return self.write_at(frame);
+        }
+        let mut cursor = Cursor::new(&frame.payload);
+        let (Some(path), Ok(total), Ok(offset)) = (read_path(&mut cursor), cursor.u32(), cursor.u64()) else {
+            return self.error(frame, ERR_INVALID_ARGS, "missing header");
+        };
+        let total = total as u64;
+        let Some(data) = self.chunk_data(frame, &frame.payload[cursor.pos..]) else {
+            return self.error(frame, ERR_INVALID_ARGS, "bad data");
+        };
+        let last = frame.flags & FLAG_EOF != 0;
+        // A host that reconnected starts the same file over under a new
+        // request id; keep what arrived so it can resume.
+        if self.upload.as_ref().is_some_and(|upload| upload.req_id != frame.req_id) {
+            self.paused = self.upload.take();
+        }
+        if self.upload.is_none() {
+            if self.resolve(&path).is_none() {
+                return self.error(frame, ERR_BAD_PATH, "bad path");
+            }
+            let paused = self.paused.take().filter(|paused| {
+                paused.path.eq_ignore_ascii_case(&path)
+                    && paused.total == total
+                    && paused.data.len() >= data.len()
+                    && paused.data.starts_with(&data)
+            });
+            if let Some(mut upload) = paused.filter(|_| offset == 0 && !last) {
+                upload.req_id = frame.req_id;
+                let written = upload.data.len() as u32;
+                self.upload = Some(upload);
+                self.resumed += 1;
+                return self.respond(FLAG_CONT, frame, &written.to_le_bytes());
+            }
+            self.upload = Some(Upload {
+                req_id: frame.req_id,
+                path: path.clone(),
+                total,
+                data: Vec::new(),
+            });
+        }
+        let Some(upload) = self.upload.as_mut() else {
+            return;
+        };
+        if !upload.path.eq_ignore_ascii_case(&path) || upload.total != total {
+            return self.error(frame, ERR_INVALID_ARGS, "path mismatch");
+        }
+        let written = upload.data.len() as u64;
+        if offset > written {
+            return self.error(frame, ERR_INVALID_ARGS, "offset ahead");
+        }
+        if offset == written {
+            upload.data.extend_from_slice(&data);
+        }
+        let written = upload.data.len() as u32;
+        if !last {
+            return self.respond(FLAG_CONT, frame, &written.to_le_bytes());
+        }
+        let Some(upload) = self.upload.take() else {
+            return;
+        };
+        if upload.data.len() as u64 != upload.total {
+            return self.error(frame, ERR_IO, "write length mismatch");
+        }
+        let target = self.resolve(&upload.path).unwrap_or_default();
+        match fs::write(target, &upload.data) {
+            Ok(()) => self.respond(FLAG_EOF, frame, &written.to_le_bytes()),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    /// Data of a streamed `WRITE` chunk, inflated when it came compressed.
+    fn chunk_data(&self, frame: &Frame, data: &[u8]) -> Option<Vec<u8>> {
+        if frame.flags & FLAG_LZ4 == 0 {
+            return Some(data.to_vec());
+        }
+        let inflated_len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        if !self.lz4 || inflated_len > self.max_payload * LZ4_MAX_RATIO {
+            return None;
+        }
+        lz4_flex::block::decompress_size_prepended(data).ok()
+    }
+
+    /// Unstreamed `WRITE`: path, offset, length and the data, written in place.
+    fn write_at(&mut self, frame: &Frame) {
+        let mut cursor = Cursor::new(&frame.payload);
+        let Some(path) = read_path(&mut cursor).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        let (Ok(offset), Ok(len)) = (cursor.u64(), cursor.u32()) else {
+            return self.error(frame, ERR_INVALID_ARGS, "bad offset");
+        };
+        let Ok(data) = cursor.take(len as usize) else {
+            return self.error(frame, ERR_INVALID_ARGS, "bad data");
+        };
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(data)
+            });
+        match result {
+            Ok(()) => self.ok(frame, &len.to_le_bytes()),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    fn path_op(&mut self, frame: &Frame, op: impl FnOnce(&Path) -> std::io::Result<()>) {
+        let Some(path) = read_path(&mut Cursor::new(&frame.payload)).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        match op(&path) {
+            Ok(()) => self.ok(frame, &[]),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    fn rename(&mut self, frame: &Frame) {
+        let mut cursor = Cursor::new(&frame.payload);
+        let from = read_path(&mut cursor).and_then(|path| self.resolve(&path));
+        let to = read_path(&mut cursor).and_then(|path| self.resolve(&path));
+        let (Some(from), Some(to)) = (from, to) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        match fs::rename(from, to) {
+            Ok(()) => self.ok(frame, &[]),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    fn cancel(&mut self, frame: &Frame) {
+        let mut payload = Vec::new();
+        if let Some(upload) = self.upload.take() {
+            payload.extend_from_slice(&(upload.data.len() as u32).to_le_bytes());
+        } else {
+            self.paused = None;
+        }
+        self.ok(frame, &payload);
+    }
+
+    fn dup_check(&mut self, frame: &Frame) {
+        let mut cursor = Cursor::new(&frame.payload);
+        let Some(dir) = read_path(&mut cursor).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        let (Ok(size), Ok(head_crc)) = (cursor.u64(), cursor.u32()) else {
+            return self.error(frame, ERR_INVALID_ARGS, "bad size");
+        };
+        let entries = match dir_entries(&dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.io_error(frame, err),
+        };
+        let names: Vec<String> = entries
+            .into_iter()
+            .filter(|(name, is_dir, file_size)| {
+                !is_dir
+                    && *file_size == size
+                    && read_at(&dir.join(name), 0, size.min(DUP_HASH_LEN as u64) as u32)
+                        .is_ok_and(|head| crc32(&head) == head_crc)
+            })
+            .map(|(name, _, _)| name)
+            .collect();
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        for name in &names {
+            push_string(&mut payload, name);
+        }
+        self.send_chunked(frame, &payload);
+    }
+
+    fn cleanup(&mut self, frame: &Frame) {
+        let root = self.root.clone();
+        match remove_clutter(&root) {
+            Ok(removed) => self.ok(frame, &removed.to_le_bytes()),
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+
+    fn hash(&mut self, frame: &Frame) {
+        let Some(dir) = read_path(&mut Cursor::new(&frame.payload)).and_then(|path| self.resolve(&path)) else {
+            return self.error(frame, ERR_BAD_PATH, "bad path");
+        };
+        let entries = match dir_entries(&dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.io_error(frame, err),
+        };
+        let files: Vec<(String, u64)> = entries
+            .into_iter()
+            .filter(|(_, is_dir, _)| !is_dir)
+            .map(|(name, _, size)| (name, size))
+            .collect();
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        for (name, size) in &files {
+            let crc = match fs::read(dir.join(name)) {
+                Ok(data) => crc32(&data),
+                Err(err) => return self.io_error(frame, err),
+            };
+            push_string(&mut payload, name);
+            payload.extend_from_slice(&size.to_le_bytes());
+            payload.push(1);
+            payload.extend_from_slice(&crc.to_le_bytes());
+        }
+        self.send_chunked(frame, &payload);
+    }
+
+    fn bench(&mut self, frame: &Frame) {
+        let size = match Cursor::new(&frame.payload).u32() {
+            Ok(size) if size > 0 && size <= BENCH_MAX_BYTES => size,
+            _ => return self.error(frame, ERR_INVALID_ARGS, "bad size"),
+        };
+        let scratch = self.root.join("bench.tmp");
+        let start = Instant::now();
+        let result = fs::write(&scratch, vec![0xA5; size as usize]).and_then(|()| {
+            let write = start.elapsed();
+            let start = Instant::now();
+            fs::read(&scratch)?;
+            fs::remove_file(&scratch)?;
+            Ok((write, start.elapsed()))
+        });
+        match result {
+            Ok((write, read)) => {
+                let mut payload = Vec::new();
+                payload.extend_from_slice(&size.to_le_bytes());
+                payload.extend_from_slice(&(write.as_micros() as u32).to_le_bytes());
+                payload.extend_from_slice(&(read.as_micros() as u32).to_le_bytes());
+                self.ok(frame, &payload);
+            }
+            Err(err) => self.io_error(frame, err),
+        }
+    }
+}
+
+fn is_write_command(cmd: u8) -> bool {
+    [
+        Command::Write,
+        Command::Delete,
+        Command::Mkdir,
+        Command::Rmdir,
+        Command::Rename,
+        Command::Cleanup,
+        Command::Bench,
+    ]
+    .iter()
+    .any(|command| *command as u8 == cmd)
+}
+
+fn push_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn read_path(cursor: &mut Cursor) -> Option<String> {
+    let len = cursor.u16().ok()? as usize;
+    let bytes = cursor.take(len).ok()?;
+    std::str::from_utf8(bytes).ok().map(str::to_string)
+}
+
+/// Name, whether it is a directory and size of each entry, by name, leaving
+/// out clutter as the firmware does.
+fn dir_entries(dir: &Path) -> std::io::Result<Vec<(String, bool, u64)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_system_clutter(&name) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        entries.push((name, metadata.is_dir(), if metadata.is_dir() { 0 } else { metadata.len() }));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// The 8.3 names FatFs gives `entries`, taken as created in name order.
+/// Names that lose characters get the first free `~N` tail, as the firmware
+/// would number them.
+fn short_names(entries: &[(String, bool, u64)]) -> Vec<String> {
+    let parts: Vec<_> = entries.iter().map(|(name, _, _)| short_name_parts(name)).collect();
+    let mut taken: Vec<String> = parts
+        .iter()
+        .filter(|(_, _, lossy)| !lossy)
+        .map(|(body, extension, _)| join_short_name(body, extension))
+        .collect();
+    let mut names = Vec::with_capacity(entries.len());
+    for ((name, _, _), (body, extension, lossy)) in entries.iter().zip(parts) {
+        if !lossy {
+            names.push(join_short_name(&body, &extension));
+            continue;
+        }
+        let short = (1..100)
+            .map(|seq| join_short_name(&numbered_body(&body, name, seq), &extension))
+            .find(|short| !taken.contains(short))
+            .unwrap_or_default();
+        taken.push(short.clone());
+        names.push(short);
+    }
+    names
+}
+
+/// Body and extension of the 8.3 name of `name` before any `~N` tail, and
+/// whether characters were lost making it, following FatFs `create_name`.
+fn short_name_parts(name: &str) -> (String, String, bool) {
+    let mut long: Vec<char> = name.chars().collect();
+    while matches!(long.last(), Some(' ' | '.')) {
+        long.pop();
+    }
+    let mut index = long.iter().take_while(|&&ch| ch == ' ').count();
+    let mut lossy = index > 0 || long.get(index) == Some(&'.');
+    // Just past the last dot, or 0 without one.
+    let extension_start = long.iter().rposition(|&ch| ch == '.').map_or(0, |dot| dot + 1);
+    let mut fields = [String::new(), String::new()];
+    let mut field = 0;
+    while let Some(&ch) = long.get(index) {
+        index += 1;
+        if ch == ' ' || (ch == '.' && index != extension_start) {
+            lossy = true;
+            continue;
+        }
+        if fields[field].chars().count() >= [8, 3][field] || index == extension_start {
+            if field == 1 {
+                lossy = true;
+                break;
+            }
+            if index != extension_start {
+                lossy = true;
+            }
+            if index > extension_start {
+                break;
+            }
+            index = extension_start;
+            field = 1;
+            continue;
+        }
+        let ch = match oem_upper(ch) {
+            Some(ch) if !"+,;=[]".contains(ch) => ch,
+            _ => {
+                lossy = true;
+                '_'
+            }
+        };
+        fields[field].push(ch);
+    }
+    let [body, extension] = fields;
+    (body, extension, lossy)
+}
+
+/// `ch` in upper case as the card's code page 437 stores it, or `None` when
+/// the code page does not have it.
+fn oem_upper(ch: char) -> Option<char> {
+    if ch.is_ascii() {
+        return (ch != '\0').then(|| ch.to_ascii_uppercase());
+    }
+    let index = CP437_HIGH.chars().position(|oem| oem == ch)?;
+    match CP437_UPPER[index] {
+        upper @ 0..0x80 => Some(upper as char),
+        upper => CP437_HIGH.chars().nth(upper as usize - 0x80),
+    }
+}
+
+/// `body` with tail `~seq`, or a hash of the long name after the fifth
+/// collision, as FatFs `gen_numname` makes it.
+fn numbered_body(body: &str, long_name: &str, seq: u16) -> String {
+    let mut seq = seq as u32;
+    if seq > 5 {
+        let long = long_name.trim_end_matches([' ', '.']);
+        for unit in long.encode_utf16() {
+            let mut unit = unit;
+            for _ in 0..16 {
+                seq = (seq << 1) + (unit & 1) as u32;
+                unit >>= 1;
+                if seq & 0x10000 != 0 {
+                    seq ^= 0x11021;
+                }
+            }
+        }
+        seq &= 0xFFFF;
+    }
+    let tail = format!("~{seq:X}");
+    let kept: String = body.chars().take(8 - tail.len()).collect();
+    kept + &tail
+}
+
+fn join_short_name(body: &str, extension: &str) -> String {
+    if extension.is_empty() {
+        body.to_string()
+    } else {
+        format!("{body}.{extension}")
+    }
+}
+
+fn read_at(path: &Path, offset: u64, len: u32) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len as usize);
+    file.take(len as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn remove_clutter(dir: &Path) -> std::io::Result<u32> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_dir = entry.file_type()?.is_dir();
+        if is_system_clutter(&name) {
+            if is_dir {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+            removed += 1;
+        } else if is_dir && !name.starts_with('.') {
+            removed += remove_clutter(&entry.path())?;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,149 @@
+//! Drives the host client against the in-process mock device.
+
+use std::fs;
+use std::time::Duration;
+
+use tern_sync::{mock::MockDevice, Client, SyncError, ERR_BUSY, PROTOCOL_ID};
+
+/// Bytes that LZ4 shrinks, but not into a single chunk.
+fn book_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / 7 % 251) as u8).collect()
+}
+
+fn connect(device: &MockDevice) -> Client<MockDevice> {
+    let mut client = Client::new(device.clone());
+    client.set_timeout(Duration::from_millis(200));
+    let (id, _) = client.ping().unwrap();
+    assert_eq!(id, PROTOCOL_ID);
+    client.info().unwrap();
+    client
+}
+
+#[test]
+fn ping_reports_the_profile_and_listing_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    for i in 0..40 {
+        fs::write(card.join(format!("book-{i:02}.trbk")), [0u8; 16]).unwrap();
+    }
+    fs::create_dir(card.join(".Spotlight-V100")).unwrap();
+    let device = MockDevice::new(card).max_payload(512).log_noise();
+    let mut client = connect(&device);
+
+    let profile = client.profile().unwrap();
+    assert_eq!(profile.model, "X4");
+    assert_eq!((profile.screen_width, profile.screen_height), (480, 800));
+
+    let (total, entries) = client.list("/").unwrap();
+    assert_eq!(total, 40);
+    assert_eq!(entries.len(), 40);
+    assert_eq!(entries[0].name, "book-00.trbk");
+    assert_eq!(entries[0].short_name, "BOOK-0~1.TRB");
+    assert_eq!(entries[4].short_name, "BOOK-0~5.TRB");
+    // Past five collisions FatFs numbers by a hash of the long name.
+    assert_eq!(entries[5].short_name, "BOO~266B.TRB");
+}
+
+#[test]
+fn listing_reports_the_short_names_fatfs_makes() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    for name in ["README.TXT", "Notes.md", "a+b c.txt", "café.txt", "long name here.epub", "x.tar.gz"] {
+        fs::write(card.join(name), b"x").unwrap();
+    }
+    let device = MockDevice::new(card);
+    let mut client = connect(&device);
+
+    let (_, entries) = client.list("/").unwrap();
+    let short: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.short_name.as_str()))
+        .collect();
+    assert_eq!(
+        short,
+        [
+            ("Notes.md", "NOTES.MD"),
+            ("README.TXT", "README.TXT"),
+            ("a+b c.txt", "A_BC~1.TXT"),
+            ("café.txt", "CAFE.TXT"),
+            ("long name here.epub", "LONGNA~1.EPU"),
+            ("x.tar.gz", "XTAR~1.GZ"),
+        ]
+    );
+}
+
+#[test]
+fn compressed_upload_round_trips_and_hashes() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    fs::create_dir(card.join("books")).unwrap();
+    let device = MockDevice::new(card).max_payload(1024);
+    let mut client = connect(&device);
+    let data = book_bytes(20_000);
+
+    client.write_file("/books/a.trbk", &data).unwrap();
+    assert_eq!(fs::read(card.join("books/a.trbk")).unwrap(), data);
+    assert_eq!(client.read_file("/books/a.trbk", data.len() as u64).unwrap(), data);
+
+    let hashes = client.hash_dir("/books").unwrap();
+    assert_eq!(hashes.len(), 1);
+    assert_eq!(hashes[0].crc, tern_sync::crc32(&data));
+
+    client.delete("/books/a.trbk").unwrap();
+    assert!(!card.join("books/a.trbk").exists());
+}
+
+#[test]
+fn upload_resumes_after_the_cable_is_pulled() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    let device = MockDevice::new(card).max_payload(512).without_lz4();
+    let mut client = connect(&device);
+    let data = book_bytes(8_000);
+
+    device.drop_link_after(5);
+    assert!(matches!(client.write_file("/a.trbk", &data), Err(SyncError::Timeout)));
+    assert!(!card.join("a.trbk").exists());
+
+    device.reconnect();
+    let mut client = connect(&device);
+    client.write_file("/a.trbk", &data).unwrap();
+    assert_eq!(device.resumed_uploads(), 1);
+    assert_eq!(fs::read(card.join("a.trbk")).unwrap(), data);
+}
+
+#[test]
+fn prompt_and_read_only_access() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    fs::write(card.join("a.trbk"), b"TRBK").unwrap();
+    fs::write(card.join("._a.trbk"), b"").unwrap();
+
+    let device = MockDevice::new(card).busy_for(1).read_only();
+    let mut client = Client::new(device.clone());
+    client.set_timeout(Duration::from_millis(200));
+    assert!(matches!(client.ping(), Err(SyncError::Device { code: ERR_BUSY, .. })));
+    let mut waited = false;
+    client.wait_for_access(Duration::from_secs(2), || waited = true).unwrap();
+    assert!(!waited, "access was allowed after the first ping");
+
+    assert!(matches!(client.mkdir("/books"), Err(SyncError::ReadOnly(_))));
+    assert!(matches!(client.cleanup(), Err(SyncError::ReadOnly(_))));
+    assert_eq!(client.list("/").unwrap().0, 1);
+}
+
+#[test]
+fn cleanup_removes_clutter_the_listing_hides() {
+    let dir = tempfile::tempdir().unwrap();
+    let card = dir.path();
+    fs::write(card.join("a.trbk"), b"TRBK").unwrap();
+    fs::write(card.join("._a.trbk"), b"").unwrap();
+    fs::create_dir_all(card.join("books/.fseventsd")).unwrap();
+    let device = MockDevice::new(card);
+    let mut client = connect(&device);
+
+    assert_eq!(client.cleanup().unwrap(), 2);
+    assert!(card.join("a.trbk").exists());
+    assert!(!card.join("._a.trbk").exists());
+    assert!(!card.join("books/.fseventsd").exists());
+}
@@ -0,0 +1,96 @@
+use tern_core::{
+    display::{Display, GrayscaleMode, HEIGHT, RefreshMode, WIDTH},
+    framebuffer::{BUFFER_SIZE, DisplayBuffers},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Gray {
+    None,
+    // Shades drawn over the black/white frame.
+    Differential,
+    // Shades replace the black/white frame.
+    Absolute,
+}
+
+/// Display that keeps what the panel would show so it can be read back.
+pub struct FrameCapture {
+    frame: Box<[u8; BUFFER_SIZE]>,
+    lsb: Box<[u8; BUFFER_SIZE]>,
+    msb: Box<[u8; BUFFER_SIZE]>,
+    gray: Gray,
+}
+
+impl Default for FrameCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        Self {
+            frame: Box::new([0xFF; BUFFER_SIZE]),
+            lsb: Box::new([0; BUFFER_SIZE]),
+            msb: Box::new([0; BUFFER_SIZE]),
+            gray: Gray::None,
+        }
+    }
+
+    /// The last frame as portrait RGBA, `HEIGHT` pixels wide and `WIDTH` tall.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut out = vec![0u8; WIDTH * HEIGHT * 4];
+        for index in 0..WIDTH * HEIGHT {
+            let byte = index / 8;
+            let shift = 7 - (index % 8);
+            let white = (self.frame[byte] >> shift) & 1;
+            let lsb = (self.lsb[byte] >> shift) & 1;
+            let msb = (self.msb[byte] >> shift) & 1;
+            let level = match self.gray {
+                Gray::Absolute => gray2_level(msb, lsb),
+                Gray::Differential if lsb | msb != 0 => gray2_level(msb, lsb),
+                _ if white == 1 => 0xFF,
+                _ => 0x00,
+            };
+            // The panel is landscape; the reader draws rotated by 90 degrees.
+            let x = HEIGHT - 1 - index / WIDTH;
+            let y = index % WIDTH;
+            let offset = (y * HEIGHT + x) * 4;
+            out[offset..offset + 4].copy_from_slice(&[level, level, level, 0xFF]);
+        }
+        out
+    }
+}
+
+impl Display for FrameCapture {
+    fn display(&mut self, buffers: &mut DisplayBuffers, _mode: RefreshMode) {
+        self.frame.copy_from_slice(buffers.get_active_buffer());
+        self.gray = Gray::None;
+        buffers.swap_buffers();
+    }
+    fn copy_to_lsb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
+        self.lsb.copy_from_slice(buffers);
+    }
+    fn copy_to_msb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
+        self.msb.copy_from_slice(buffers);
+    }
+    fn copy_grayscale_buffers(&mut self, lsb: &[u8; BUFFER_SIZE], msb: &[u8; BUFFER_SIZE]) {
+        self.lsb.copy_from_slice(lsb);
+        self.msb.copy_from_slice(msb);
+    }
+    fn display_differential_grayscale(&mut self, _turn_off_screen: bool) {
+        self.gray = Gray::Differential;
+    }
+    fn display_absolute_grayscale(&mut self, _: GrayscaleMode) {
+        self.gray = Gray::Absolute;
+    }
+}
+
+/// Luminance of a 2-bit pixel, matching the TRI Gray2 encoding.
+fn gray2_level(msb: u8, lsb: u8) -> u8 {
+    match (msb, lsb) {
+        (0, 0) => 0xFF,
+        (0, _) => 0xAA,
+        (_, 0) => 0x55,
+        _ => 0x00,
+    }
+}
@@ -0,0 +1,138 @@
+//! WebAssembly build of the book renderer.
+//!
+//! Pages are rasterised by the same reader code the device runs, so a TRBK
+//! can be previewed in the browser before it is copied to the card and
+//! rendering bugs can be reproduced without hardware.
+
+mod capture;
+mod source;
+
+use std::collections::BTreeMap;
+
+use tern_core::{
+    app::book_reader::{BookReaderContext, BookReaderState},
+    display::{HEIGHT, WIDTH},
+    framebuffer::{BUFFER_SIZE, DisplayBuffers, Rotation},
+    image_viewer::ImageError,
+    reflow::ReadingLayout,
+};
+use wasm_bindgen::prelude::*;
+
+pub use capture::FrameCapture;
+pub use source::{BOOK_ENTRY, MemoryBookSource};
+
+/// Renders pages of one in-memory book to portrait RGBA frames.
+pub struct PageRenderer {
+    reader: BookReaderState,
+    source: MemoryBookSource,
+    display: FrameCapture,
+    buffers: Box<DisplayBuffers>,
+    gray2_lsb: Vec<u8>,
+    gray2_msb: Vec<u8>,
+}
+
+impl PageRenderer {
+    pub fn new(data: Vec<u8>) -> Result<Self, ImageError> {
+        let mut buffers = Box::new(DisplayBuffers::default());
+        buffers.set_rotation(Rotation::Rotate90);
+        Ok(Self {
+            reader: BookReaderState::new(),
+            source: MemoryBookSource::new(data)?,
+            display: FrameCapture::new(),
+            buffers,
+            gray2_lsb: vec![0u8; BUFFER_SIZE],
+            gray2_msb: vec![0u8; BUFFER_SIZE],
+        })
+    }
+
+    pub fn source(&self) -> &MemoryBookSource {
+        &self.source
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.source.info().page_count
+    }
+
+    pub fn render_page(&mut self, index: usize) -> Result<Vec<u8>, ImageError> {
+        if index >= self.page_count() {
+            return Err(ImageError::Message(format!(
+                "page {} out of range (book has {})",
+                index + 1,
+                self.page_count()
+            )));
+        }
+        let entry = self.source.entry();
+        let positions = BTreeMap::from([(entry.name.clone(), index)]);
+        // The preview shows the pages as converted.
+        self.reader.open(
+            &mut self.source,
+            &[],
+            &entry,
+            &entry.name,
+            &positions,
+            ReadingLayout::default(),
+        )?;
+        let mut full_refresh = true;
+        let mut ctx = BookReaderContext {
+            display_buffers: &mut self.buffers,
+            gray2_lsb: self.gray2_lsb.as_mut_slice(),
+            gray2_msb: self.gray2_msb.as_mut_slice(),
+            source: &mut self.source,
+            full_refresh: &mut full_refresh,
+            refresh_tuning: Default::default(),
+        };
+        self.reader.draw_book(&mut ctx, &mut self.display)?;
+        Ok(self.display.to_rgba())
+    }
+}
+
+/// Browser entry point; frames are RGBA, `width()` x `height()`.
+#[wasm_bindgen]
+pub struct BookPreview {
+    renderer: PageRenderer,
+}
+
+#[wasm_bindgen]
+impl BookPreview {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>) -> Result<BookPreview, JsError> {
+        let renderer = PageRenderer::new(data).map_err(to_js_error)?;
+        Ok(Self { renderer })
+    }
+
+    pub fn width() -> u32 {
+        HEIGHT as u32
+    }
+
+    pub fn height() -> u32 {
+        WIDTH as u32
+    }
+
+    #[wasm_bindgen(getter, js_name = pageCount)]
+    pub fn page_count(&self) -> usize {
+        self.renderer.page_count()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn title(&self) -> String {
+        self.renderer.source().info().metadata.title.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn author(&self) -> String {
+        self.renderer.source().info().metadata.author.clone()
+    }
+
+    /// Renders a zero-based page, including the page indicator the device draws.
+    #[wasm_bindgen(js_name = renderPage)]
+    pub fn render_page(&mut self, index: usize) -> Result<Vec<u8>, JsError> {
+        self.renderer.render_page(index).map_err(to_js_error)
+    }
+}
+
+fn to_js_error(err: ImageError) -> JsError {
+    match err {
+        ImageError::Message(message) => JsError::new(&message),
+        other => JsError::new(&format!("{:?}", other)),
+    }
+}
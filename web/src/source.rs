@@ -0,0 +1,101 @@
+use std::rc::Rc;
+
+use tern_core::image_viewer::{
+    BookSource, DiagnosticsSource, EntryKind, Gray2StreamSource, ImageData, ImageEntry,
+    ImageError, ImageSource, PersistenceSource, PowerSource, PrefetchSource,
+};
+use tern_core::reflow::{self, TextItem};
+use tern_core::trbk::{self, TrbkBook, TrbkBookInfo, TrbkPage};
+
+/// Name the in-memory book is listed under.
+pub const BOOK_ENTRY: &str = "preview.trbk";
+
+/// Source backed by a single TRBK held in memory.
+pub struct MemoryBookSource {
+    data: Vec<u8>,
+    pages: Vec<TrbkPage>,
+    info: Rc<TrbkBookInfo>,
+}
+
+impl MemoryBookSource {
+    pub fn new(data: Vec<u8>) -> Result<Self, ImageError> {
+        let book = trbk::parse_trbk(&data)?;
+        let info = Rc::new(book.info());
+        Ok(Self {
+            data,
+            pages: book.pages,
+            info,
+        })
+    }
+
+    pub fn info(&self) -> &TrbkBookInfo {
+        &self.info
+    }
+
+    pub fn entry(&self) -> ImageEntry {
+        ImageEntry {
+            name: BOOK_ENTRY.to_string(),
+            kind: EntryKind::File,
+        }
+    }
+}
+
+impl ImageSource for MemoryBookSource {
+    fn refresh(&mut self, _path: &[String]) -> Result<Vec<ImageEntry>, ImageError> {
+        Ok(vec![self.entry()])
+    }
+
+    fn load(&mut self, _path: &[String], _entry: &ImageEntry) -> Result<ImageData, ImageError> {
+        Err(ImageError::Unsupported)
+    }
+}
+
+impl BookSource for MemoryBookSource {
+    fn load_trbk(&mut self, _path: &[String], _entry: &ImageEntry) -> Result<TrbkBook, ImageError> {
+        trbk::parse_trbk(&self.data)
+    }
+
+    fn open_trbk(
+        &mut self,
+        _path: &[String],
+        _entry: &ImageEntry,
+    ) -> Result<Rc<TrbkBookInfo>, ImageError> {
+        Ok(self.info.clone())
+    }
+
+    fn trbk_text(
+        &mut self,
+        first: usize,
+        visit: &mut dyn FnMut(usize, TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        let text = self.info.text.ok_or(ImageError::Unsupported)?;
+        reflow::read_text_items(&self.data, text, first, visit)
+    }
+
+    fn trbk_page(&mut self, page_index: usize) -> Result<TrbkPage, ImageError> {
+        self.pages
+            .get(page_index)
+            .cloned()
+            .ok_or(ImageError::Decode)
+    }
+
+    fn trbk_image(&mut self, image_index: usize) -> Result<ImageData, ImageError> {
+        let image = self.info.images.get(image_index).ok_or(ImageError::Decode)?;
+        let start = image.data_offset as usize;
+        let end = start + image.data_len as usize;
+        if end > self.data.len() {
+            return Err(ImageError::Decode);
+        }
+        trbk::parse_trimg(&self.data[start..end])
+    }
+}
+
+impl Gray2StreamSource for MemoryBookSource {}
+
+impl PersistenceSource for MemoryBookSource {}
+
+impl PowerSource for MemoryBookSource {}
+
+impl DiagnosticsSource for MemoryBookSource {}
+
+impl PrefetchSource for MemoryBookSource {}
@@ -17,6 +17,19 @@ int ff_mount() {
     return f_mount(&fs, "", 1);
 }
 
+int ff_space(QWORD* free_bytes, QWORD* total_bytes) {
+    FATFS* fs;
+    DWORD free_clusters;
+    FRESULT res = f_getfree("", &free_clusters, &fs);
+    if (res != FR_OK) {
+        return res;
+    }
+    QWORD cluster_bytes = (QWORD)fs->csize * FF_MIN_SS;
+    *free_bytes = (QWORD)free_clusters * cluster_bytes;
+    *total_bytes = (QWORD)(fs->n_fatent - 2) * cluster_bytes;
+    return FR_OK;
+}
+
 #if defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L
 _Static_assert(sizeof(char) == 1, "char size mismatch");
 _Static_assert(sizeof(BYTE) == 1, "BYTE size mismatch");
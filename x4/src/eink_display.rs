@@ -11,7 +11,7 @@ use esp_hal::{
 };
 use log::{error, info, warn};
 use tern_core::{
-    display::{Display, GrayscaleMode, RefreshMode},
+    display::{BorderMode, Display, GrayscaleMode, RefreshMode, RefreshTuning},
     framebuffer::{BUFFER_SIZE, DisplayBuffers},
 };
 
@@ -62,6 +62,10 @@ const DATA_ENTRY_X_INC_Y_DEC: u8 = 0x01;
 // Temperature sensor control
 const TEMP_SENSOR_INTERNAL: u8 = 0x80;
 
+// Auto write RAM patterns filling the whole area with one value
+const RAM_PATTERN_BLACK: u8 = 0x77;
+const RAM_PATTERN_WHITE: u8 = 0xF7;
+
 #[rustfmt::skip]
 mod lut {
     pub static GRAYSCALE: &[u8] = &[
@@ -155,6 +159,10 @@ pub struct EInkDisplay<'gpio, SPI> {
     is_screen_on: bool,
     custom_lut_active: bool,
     in_grayscale_mode: bool,
+    tuning: RefreshTuning,
+    /// Set when BUSY never deasserted; later waits are skipped until the
+    /// panel has been reset.
+    stalled: bool,
 }
 
 impl<'gpio, SPI> EInkDisplay<'gpio, SPI>
@@ -184,6 +192,8 @@ where
             is_screen_on: false,
             custom_lut_active: false,
             in_grayscale_mode: false,
+            tuning: RefreshTuning::default(),
+            stalled: false,
         }
     }
 
@@ -201,6 +211,22 @@ where
         Ok(())
     }
 
+    /// Whether the panel stopped responding since the last call.
+    pub fn take_stalled(&mut self) -> bool {
+        core::mem::take(&mut self.stalled)
+    }
+
+    /// Resets and reinitializes a panel that stopped responding. The next
+    /// refresh is a half refresh, as after power on.
+    pub fn recover(&mut self) -> Result<(), SPI::Error> {
+        warn!("Recovering stalled display");
+        self.stalled = false;
+        self.is_screen_on = false;
+        self.custom_lut_active = false;
+        self.in_grayscale_mode = false;
+        self.begin()
+    }
+
     pub fn display_gray_buffer(&mut self, turn_off_screen: bool) -> Result<(), SPI::Error> {
         warn!("Displaying grayscale buffer");
         self.in_grayscale_mode = true;
@@ -274,12 +300,16 @@ where
     }
 
     fn wait_while_busy(&mut self, comment: &str) {
+        if self.stalled {
+            return;
+        }
         let mut iterations = 0u32;
         while self.busy.is_high() {
             self.delay.delay_millis(1);
             iterations += 1;
             if iterations > 10000 {
                 error!("Timeout waiting for busy: {}", comment);
+                self.stalled = true;
                 break;
             }
         }
@@ -312,7 +342,7 @@ where
 
         // Border waveform control
         self.send_command(commands::BORDER_WAVEFORM)?;
-        self.send_data(&[0x01])?;
+        self.send_data(&[border_waveform(self.tuning.border)])?;
 
         // Set up full screen RAM area
         self.set_ram_area(0, 0, Self::WIDTH as u16, Self::HEIGHT as u16)?;
@@ -502,6 +532,14 @@ where
                     .unwrap();
                 self.write_ram_buffer(commands::WRITE_RAM_RED, previous)
                     .unwrap();
+                if self.tuning.double_fast {
+                    // Drive the same transition twice to clear more ghosting.
+                    self.refresh_display(mode, false).unwrap();
+                    self.write_ram_buffer(commands::WRITE_RAM_BW, current)
+                        .unwrap();
+                    self.write_ram_buffer(commands::WRITE_RAM_RED, previous)
+                        .unwrap();
+                }
             }
         }
 
@@ -512,6 +550,26 @@ where
         self.refresh_display(mode, false).unwrap();
     }
 
+    fn set_refresh_tuning(&mut self, tuning: RefreshTuning) {
+        self.tuning = tuning;
+        self.send_command(commands::BORDER_WAVEFORM).unwrap();
+        self.send_data(&[border_waveform(tuning.border)]).unwrap();
+    }
+
+    fn deep_clean(&mut self) {
+        if self.in_grayscale_mode {
+            self.grayscale_revert_internal().unwrap();
+        }
+        for pattern in [RAM_PATTERN_BLACK, RAM_PATTERN_WHITE] {
+            self.set_ram_area(0, 0, Self::WIDTH as u16, Self::HEIGHT as u16)
+                .unwrap();
+            self.send_command(commands::AUTO_WRITE_BW_RAM).unwrap();
+            self.send_data(&[pattern]).unwrap();
+            self.wait_while_busy("AUTO_WRITE_BW_RAM");
+            self.refresh_display(RefreshMode::Full, false).unwrap();
+        }
+    }
+
     fn copy_to_lsb(&mut self, buffers: &[u8; BUFFER_SIZE]) {
         self.set_ram_area(0, 0, Self::WIDTH as u16, Self::HEIGHT as u16)
             .unwrap();
@@ -548,3 +606,12 @@ where
         self.custom_lut_active = false;
     }
 }
+
+/// BORDER_WAVEFORM value for each border mode.
+fn border_waveform(mode: BorderMode) -> u8 {
+    match mode {
+        BorderMode::Waveform => 0x01,
+        BorderMode::Vcom => 0x80,
+        BorderMode::Floating => 0xC0,
+    }
+}
@@ -7,30 +7,72 @@ use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 
+use embassy_futures::yield_now;
+use embassy_time::Instant;
 use embedded_io::{Read, Seek, SeekFrom, Write};
-use tern_core::fs::{DirEntry, Directory, File, Filesystem, Mode};
+use tern_core::fs::{is_system_clutter, DirEntry, Directory, File, Filesystem, Mode};
 use crate::sdspi_fs::UsbFsOps;
+use crate::spi_bus::BusArbiter;
+use tern_core::app::diagnostics::FLIGHT_LOG_FILE;
+use tern_core::app::profiles::{read_profiles, Profile};
+use tern_core::persistence::{crc32_update, parse_book_positions, StateStorage, StateStore};
 use tern_core::image_viewer::{
-    BookSource, EntryKind, Gray2StreamSource, ImageData, ImageEntry, ImageError, ImageSource,
-    PersistenceSource, PowerSource,
+    BookSource, DiagnosticsSource, EntryKind, Gray2StreamSource, HeapUsage, ImageData,
+    ImageEntry, ImageError, ImageInfo, ImageSource, PersistenceSource, PowerSource,
+    PrefetchSource,
 };
 
+/// Read size for image and book streams. Several sectors at a time let
+/// FatFs read straight into the buffer with one multi-block command.
+const STREAM_CHUNK: usize = 4096;
+/// Time one `usb_hash_dir` call may spend hashing before it answers with the
+/// files done so far.
+const HASH_BUDGET_MS: u64 = 1000;
+
+/// Largest TRIM image `load` accepts.
+const MAX_IMAGE_BYTES: usize = 200_000;
+
+/// Called between the chunks of reads that cannot be awaited, such as gray
+/// images streamed to the screen, so the main loop can keep sampling input
+/// and servicing the USB link. Files that are opened whole are read ahead by
+/// [`SdImageSource::read_ahead`] instead.
+pub type ReadHook = Box<dyn FnMut()>;
+
 pub struct SdImageSource<F>
 where
     F: Filesystem + 'static,
 {
     fs: F,
     trbk: Option<TrbkStream>,
-    short_names: Vec<(String, String)>,
+    /// EPUB read from the card, whose text stands in for a TRBK's.
+    epub: Option<tern_core::epub::EpubBook>,
     usb_stream: Option<Box<UsbWriteStreamState<F::File<'static>>>>,
+    /// Upload whose temp file was kept when the connection went away.
+    usb_paused: Option<PausedUpload>,
+    /// Sizes and CRCs for `usb_hash_dir`, loaded on first use.
+    hash_index: Option<Vec<HashEntry>>,
+    state: StateStore,
+    /// SD card time of the last `trbk_page`, for page render timing.
+    page_read_us: u32,
+    read_hook: Option<ReadHook>,
+    prefetch: Option<Prefetch>,
 }
 
 pub struct UsbDirEntry {
     pub name: String,
+    pub short_name: String,
+    pub attributes: u8,
     pub is_dir: bool,
     pub size: u64,
 }
 
+pub struct UsbFileHash {
+    pub name: String,
+    pub size: u64,
+    /// `None` while the file is still being hashed.
+    pub crc: Option<u32>,
+}
+
 pub trait UsbStorage {
     fn usb_list(&mut self, path: &str) -> Result<Vec<UsbDirEntry>, ImageError>;
     fn usb_read(&mut self, path: &str, offset: u64, length: u32) -> Result<Vec<u8>, ImageError>;
@@ -45,22 +87,89 @@ pub trait UsbStorage {
         let _ = final_chunk;
         self.usb_write(path, offset, data)
     }
+    fn usb_abort_stream(&mut self) -> Result<(), ImageError>;
+    /// Closes the streamed upload but keeps its temp file for
+    /// [`UsbStorage::usb_resume_stream`].
+    fn usb_suspend_stream(&mut self, total: u64);
+    /// Reopens a suspended upload of `path` if its temp file starts with
+    /// `head`; returns the offset to continue from.
+    fn usb_resume_stream(&mut self, path: &str, total: u64, head: &[u8]) -> Option<u64>;
+    /// Size and CRC-32 of the files in `path`. Hashing stops after a time
+    /// budget; files left without a CRC continue on the next call.
+    fn usb_hash_dir(&mut self, path: &str) -> Result<Vec<UsbFileHash>, ImageError>;
     fn usb_delete(&mut self, path: &str) -> Result<(), ImageError>;
     fn usb_rmdir(&mut self, path: &str) -> Result<(), ImageError>;
     fn usb_rename(&mut self, from: &str, to: &str) -> Result<(), ImageError>;
     fn usb_mkdir(&mut self, path: &str) -> Result<(), ImageError>;
+    fn usb_free_space(&mut self) -> Result<(u64, u64), ImageError>;
+    /// Deletes macOS and Windows clutter anywhere on the card; returns how
+    /// many entries went.
+    fn usb_cleanup(&mut self) -> Result<u32, ImageError>;
+    /// Writes, reads back and deletes a scratch file of `size` bytes; returns
+    /// the write and read times in microseconds.
+    fn usb_bench(&mut self, size: u32) -> Result<(u32, u32), ImageError>;
 }
 
 struct UsbWriteStreamState<FileT> {
     path: String,
+    temp_path: String,
     file: FileT,
     next_offset: u64,
+    /// Running CRC-32 while the data arrives in order.
+    crc: Option<u32>,
+}
+
+/// A file read ahead of the load that opens it.
+struct Prefetch {
+    path: String,
+    data: Vec<u8>,
+    filled: usize,
+}
+
+struct PausedUpload {
+    path: String,
+    total: u64,
+    written: u64,
+}
+
+#[derive(Clone)]
+struct HashEntry {
+    /// Card path without the leading `/`.
+    path: String,
+    size: u64,
+    /// Running CRC-32 over the first `hashed` bytes.
+    crc: u32,
+    hashed: u64,
+}
+
+impl HashEntry {
+    fn new(path: String, size: u64) -> Self {
+        Self {
+            path,
+            size,
+            crc: 0xFFFF_FFFF,
+            hashed: 0,
+        }
+    }
+
+    /// Parses an index line: `<size> <crc hex> <path>`.
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let size = parts.next()?.parse().ok()?;
+        let crc = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let path = parts.next()?.to_string();
+        Some(Self {
+            path,
+            size,
+            crc: !crc,
+            hashed: size,
+        })
+    }
 }
 
 struct TrbkStream {
     path: Vec<String>,
     name: String,
-    short_name: Option<String>,
     page_offsets: Vec<u32>,
     page_data_offset: u32,
     glyph_table_offset: u32,
@@ -115,18 +224,66 @@ where
         Self {
             fs,
             trbk: None,
-            short_names: Vec::new(),
+            epub: None,
             usb_stream: None,
+            usb_paused: None,
+            hash_index: None,
+            state: StateStore::new(),
+            page_read_us: 0,
+            read_hook: None,
+            prefetch: None,
+        }
+    }
+
+    pub fn set_read_hook(&mut self, hook: ReadHook) {
+        self.read_hook = Some(hook);
+    }
+
+    /// Reads more of the file passed to `prefetch`, giving the executor a
+    /// turn after each chunk, until it is complete or storage used up this
+    /// main loop pass. A failed read is dropped and the load reports it.
+    pub async fn read_ahead(&mut self, bus: &BusArbiter) {
+        let Some(prefetch) = self.prefetch.as_mut() else {
+            return;
+        };
+        if prefetch.filled == prefetch.data.len() {
+            return;
+        }
+        if let Err(err) = Self::read_prefetch(&self.fs, prefetch, bus).await {
+            log::warn!("Reading {} ahead failed: {:?}", prefetch.path, err);
+            self.prefetch = None;
         }
     }
 
-    fn lookup_short_name(&self, name: &str) -> Option<String> {
-        for (long, short) in &self.short_names {
-            if long.eq_ignore_ascii_case(name) {
-                return Some(short.clone());
+    async fn read_prefetch(
+        fs: &F,
+        prefetch: &mut Prefetch,
+        bus: &BusArbiter,
+    ) -> Result<(), ImageError> {
+        let mut file = fs
+            .open_file(&prefetch.path, Mode::Read)
+            .map_err(|_| ImageError::Io)?;
+        file.seek(SeekFrom::Start(prefetch.filled as u64))
+            .map_err(|_| ImageError::Io)?;
+        while prefetch.filled < prefetch.data.len() && !bus.storage_slice_spent() {
+            let end = (prefetch.filled + STREAM_CHUNK).min(prefetch.data.len());
+            let read = file
+                .read(&mut prefetch.data[prefetch.filled..end])
+                .map_err(|_| ImageError::Io)?;
+            if read == 0 {
+                return Err(ImageError::Decode);
             }
+            prefetch.filled += read;
+            yield_now().await;
         }
-        None
+        Ok(())
+    }
+
+    /// The bytes of `file_path` if they were read ahead.
+    fn take_prefetched(&mut self, file_path: &str) -> Option<Vec<u8>> {
+        let prefetch = self.prefetch.take()?;
+        (prefetch.path == file_path && prefetch.filled == prefetch.data.len())
+            .then_some(prefetch.data)
     }
 
     fn is_supported(name: &str) -> bool {
@@ -170,6 +327,18 @@ where
         ".trusty_cache"
     }
 
+    fn usb_upload_temp_path() -> String {
+        format!("{}/UPLOAD.TMP", Self::thumbnails_dirname())
+    }
+
+    fn bench_temp_path() -> String {
+        format!("{}/BENCH.TMP", Self::thumbnails_dirname())
+    }
+
+    fn hash_index_path() -> String {
+        format!("{}/TRHASH.IDX", Self::thumbnails_dirname())
+    }
+
     fn thumbnail_name(key: &str) -> String {
         let hash = thumb_hash_hex(key);
         let short = &hash[..6.min(hash.len())];
@@ -207,11 +376,38 @@ where
         }
     }
 
-    fn read_book_positions(&self) -> Vec<(String, usize)> {
+    fn read_book_positions(&self) -> (Vec<(String, usize)>, bool) {
         let mut file = match self
             .fs
             .open_file(Self::book_positions_filename(), Mode::Read)
             .or_else(|_| self.fs.open_file(Self::book_positions_filename_legacy(), Mode::Read))
+        {
+            Ok(file) => file,
+            Err(_) => return (Vec::new(), false),
+        };
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(read) => read,
+                Err(_) => return (Vec::new(), false),
+            };
+            if read == 0 {
+                break;
+            }
+            if data.try_reserve(read).is_err() {
+                return (Vec::new(), false);
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+        parse_book_positions(&String::from_utf8_lossy(&data))
+    }
+
+    fn read_recent_entries(&self) -> Vec<String> {
+        let mut file = match self
+            .fs
+            .open_file(Self::recent_entries_filename(), Mode::Read)
+            .or_else(|_| self.fs.open_file(Self::recent_entries_filename_legacy(), Mode::Read))
         {
             Ok(file) => file,
             Err(_) => return Vec::new(),
@@ -237,22 +433,46 @@ where
         };
         let mut entries = Vec::new();
         for line in text.lines() {
-            let Some((name, page_str)) = line.split_once('\t') else {
-                continue;
-            };
-            let name = name.trim();
-            let page_str = page_str.trim();
-            if name.is_empty() {
-                continue;
+            let value = line.trim();
+            if !value.is_empty() {
+                entries.push(value.to_string());
             }
-            let Ok(page) = page_str.parse::<usize>() else {
-                continue;
-            };
-            entries.push((name.to_string(), page));
         }
         entries
     }
 
+    fn ensure_state(&mut self) {
+        if !self.state.is_loaded() {
+            self.reload_state();
+        }
+    }
+
+    fn reload_state(&mut self) {
+        let first_load = !self.state.is_loaded();
+        let loaded = self.state.load(&mut FsStateStorage { fs: &self.fs });
+        // Only the first profile predates the state blob.
+        if loaded || !first_load || self.state.profile() != 0 {
+            return;
+        }
+        // No usable state blob yet: carry over the old text files.
+        log::info!("No state blob found, reading legacy state files");
+        let resume = self.read_resume();
+        let (book_positions, cleaned) = self.read_book_positions();
+        let recent_entries = self.read_recent_entries();
+        let state = self.state.state_mut();
+        state.resume = resume;
+        state.book_positions = book_positions;
+        state.recent_entries = recent_entries;
+        if cleaned {
+            log::info!("Saving repaired book positions");
+            self.save_state();
+        }
+    }
+
+    fn save_state(&mut self) {
+        self.state.save(&mut FsStateStorage { fs: &self.fs });
+    }
+
 }
 
 impl<F> UsbStorage for SdImageSource<F>
@@ -267,8 +487,15 @@ where
         };
         let mut out = Vec::new();
         for entry in listed {
+            // FatFs may hand back dot entries for subdirectories; hosts never want them,
+            // nor the clutter CLEANUP removes.
+            if entry.name() == "." || entry.name() == ".." || is_system_clutter(entry.name()) {
+                continue;
+            }
             out.push(UsbDirEntry {
                 name: entry.name().to_string(),
+                short_name: entry.short_name().to_string(),
+                attributes: entry.attributes(),
                 is_dir: entry.is_directory(),
                 size: entry.size() as u64,
             });
@@ -286,6 +513,7 @@ where
     }
 
     fn usb_write(&mut self, path: &str, offset: u64, data: &[u8]) -> Result<u32, ImageError> {
+        check_fat_name(path)?;
         let mut file = if offset == 0 {
             match self.fs.open_file(path, Mode::Write) {
                 Ok(file) => file,
@@ -307,6 +535,8 @@ where
         let _ = file
             .flush()
             .map_err(|err| ImageError::Message(alloc::format!("flush failed: {:?}", err)))?;
+        drop(file);
+        self.forget_hash(path);
         Ok(written as u32)
     }
 
@@ -318,12 +548,19 @@ where
         final_chunk: bool,
     ) -> Result<u32, ImageError> {
         if offset == 0 {
-            if let Some(mut stream) = self.usb_stream.take() {
-                let _ = stream.file.flush();
+            check_fat_name(path)?;
+            if self.usb_stream.take().is_some() {
+                log::warn!("usb stream restarted, discarding previous upload");
             }
+            // Uploads land in a temp file and only replace `path` once complete,
+            // so an interrupted transfer never leaves a truncated book behind.
+            let temp_path = Self::usb_upload_temp_path();
+            let _ = self.fs.create_dir_all(Self::thumbnails_dirname());
+            let _ = self.fs.delete_file(&temp_path);
+            self.usb_paused = None;
             let file = self
                 .fs
-                .open_file(path, Mode::Write)
+                .open_file(&temp_path, Mode::Write)
                 .map_err(|err| ImageError::Message(alloc::format!("open write failed: {:?}", err)))?;
             // SAFETY: UsbStorage is only used on device with owned file handles (FatFs).
             // We widen the lifetime to store the handle across calls.
@@ -332,8 +569,10 @@ where
             };
             self.usb_stream = Some(Box::new(UsbWriteStreamState {
                 path: path.to_string(),
+                temp_path,
                 file,
                 next_offset: 0,
+                crc: Some(0xFFFF_FFFF),
             }));
         }
 
@@ -349,47 +588,304 @@ where
                 .seek(SeekFrom::Start(offset))
                 .map_err(|err| ImageError::Message(alloc::format!("seek failed: {:?}", err)))?;
             stream.next_offset = offset;
+            stream.crc = None;
         }
         let written = stream
             .file
             .write(data)
             .map_err(|err| ImageError::Message(alloc::format!("write failed: {:?}", err)))?;
         stream.next_offset = stream.next_offset.saturating_add(written as u64);
+        stream.crc = stream.crc.map(|crc| crc32_update(crc, &data[..written]));
         if final_chunk {
             let _ = stream
                 .file
                 .flush()
                 .map_err(|err| ImageError::Message(alloc::format!("flush failed: {:?}", err)))?;
-            self.usb_stream = None;
+            // The temp file is synced above, so it can be moved into place.
+            let Some(stream) = self.usb_stream.take() else {
+                return Err(ImageError::Message("usb stream not initialized".into()));
+            };
+            let UsbWriteStreamState {
+                temp_path,
+                next_offset,
+                crc,
+                ..
+            } = *stream;
+            let _ = self.fs.delete_file(path);
+            self.fs.rename_file(&temp_path, path).map_err(|err| {
+                let _ = self.fs.delete_file(&temp_path);
+                ImageError::Message(alloc::format!("rename failed: {:?}", err))
+            })?;
+            match crc {
+                Some(crc) => self.record_hash(path, next_offset, !crc),
+                None => self.forget_hash(path),
+            }
         }
         Ok(written as u32)
     }
 
+    fn usb_abort_stream(&mut self) -> Result<(), ImageError> {
+        self.usb_paused = None;
+        let temp_path = match self.usb_stream.take() {
+            Some(stream) => stream.temp_path,
+            None => Self::usb_upload_temp_path(),
+        };
+        match self.fs.exists(&temp_path) {
+            Ok(false) => Ok(()),
+            _ => self
+                .fs
+                .delete_file(&temp_path)
+                .map_err(|err| ImageError::Message(alloc::format!("delete partial failed: {:?}", err))),
+        }
+    }
+
+    fn usb_suspend_stream(&mut self, total: u64) {
+        let Some(mut stream) = self.usb_stream.take() else {
+            return;
+        };
+        if let Err(err) = stream.file.flush() {
+            log::warn!("usb suspend flush failed: {:?}", err);
+            drop(stream);
+            let _ = self.usb_abort_stream();
+            return;
+        }
+        self.usb_paused = Some(PausedUpload {
+            path: stream.path.clone(),
+            total,
+            written: stream.next_offset,
+        });
+    }
+
+    fn usb_resume_stream(&mut self, path: &str, total: u64, head: &[u8]) -> Option<u64> {
+        let paused = self.usb_paused.take()?;
+        if !paused.path.eq_ignore_ascii_case(path) || paused.total != total || paused.written == 0 {
+            return None;
+        }
+        let temp_path = Self::usb_upload_temp_path();
+        let mut file = self.fs.open_file(&temp_path, Mode::ReadWrite).ok()?;
+        // The host resends the start of the file; a different start means a
+        // different file under the same name.
+        let check_len = head.len().min(paused.written as usize);
+        let mut start = vec![0u8; check_len];
+        read_exact(&mut file, &mut start).ok()?;
+        if start[..] != head[..check_len] {
+            return None;
+        }
+        file.seek(SeekFrom::Start(paused.written)).ok()?;
+        // SAFETY: as in `usb_write_stream`.
+        let file = unsafe { core::mem::transmute::<F::File<'_>, F::File<'static>>(file) };
+        self.usb_stream = Some(Box::new(UsbWriteStreamState {
+            path: paused.path,
+            temp_path,
+            file,
+            next_offset: paused.written,
+            crc: None,
+        }));
+        Some(paused.written)
+    }
+
+    fn usb_hash_dir(&mut self, path: &str) -> Result<Vec<UsbFileHash>, ImageError> {
+        let listed = {
+            let dir = self.fs.open_directory(path).map_err(|_| ImageError::Io)?;
+            dir.list().map_err(|_| ImageError::Io)?
+        };
+        let files: Vec<(String, u64)> = listed
+            .into_iter()
+            .filter(|entry| !entry.is_directory() && !is_system_clutter(entry.name()))
+            .map(|entry| (entry.name().to_string(), entry.size() as u64))
+            .collect();
+        let started = Instant::now();
+        let mut changed = false;
+        let mut out = Vec::with_capacity(files.len());
+        for (name, size) in files {
+            let full_path = Self::join_usb_path(path, &name);
+            let key = full_path.trim_start_matches('/').to_string();
+            let index = self.hash_index();
+            let pos = match index.iter().position(|entry| entry.path.eq_ignore_ascii_case(&key)) {
+                Some(pos) if index[pos].size == size => pos,
+                Some(pos) => {
+                    index[pos] = HashEntry::new(key, size);
+                    pos
+                }
+                None => {
+                    index.push(HashEntry::new(key, size));
+                    index.len() - 1
+                }
+            };
+            let mut entry = self.hash_index()[pos].clone();
+            if entry.hashed < size && started.elapsed().as_millis() < HASH_BUDGET_MS {
+                self.hash_file(&full_path, &mut entry, started)?;
+                changed |= entry.hashed == size;
+            }
+            out.push(UsbFileHash {
+                name,
+                size,
+                crc: (entry.hashed == size).then_some(!entry.crc),
+            });
+            self.hash_index()[pos] = entry;
+        }
+        if changed {
+            self.save_hash_index();
+        }
+        Ok(out)
+    }
+
     fn usb_delete(&mut self, path: &str) -> Result<(), ImageError> {
         self.fs.delete_file(path).map_err(|_| ImageError::Io)?;
         self.cleanup_deleted_path_with_usb(path);
+        self.forget_hash(path);
         Ok(())
     }
 
     fn usb_rmdir(&mut self, path: &str) -> Result<(), ImageError> {
         self.usb_delete_dir_recursive(path)?;
         self.fs.delete_file(path).map_err(|_| ImageError::Io)?;
+        self.forget_hash(path);
         Ok(())
     }
 
     fn usb_rename(&mut self, from: &str, to: &str) -> Result<(), ImageError> {
-        self.fs.rename_file(from, to).map_err(|_| ImageError::Io)
+        check_fat_name(to)?;
+        self.fs.rename_file(from, to).map_err(|_| ImageError::Io)?;
+        self.forget_hash(from);
+        self.forget_hash(to);
+        Ok(())
     }
 
     fn usb_mkdir(&mut self, path: &str) -> Result<(), ImageError> {
+        check_fat_name(path)?;
         self.fs.create_dir_all(path).map_err(|_| ImageError::Io)
     }
+
+    fn usb_free_space(&mut self) -> Result<(u64, u64), ImageError> {
+        self.fs.free_space().map_err(|_| ImageError::Io)
+    }
+
+    fn usb_cleanup(&mut self) -> Result<u32, ImageError> {
+        self.remove_system_files_in("/")
+    }
+
+    fn usb_bench(&mut self, size: u32) -> Result<(u32, u32), ImageError> {
+        let path = Self::bench_temp_path();
+        let _ = self.fs.create_dir_all(Self::thumbnails_dirname());
+        let result = self.bench_file(&path, size as usize);
+        let _ = self.fs.delete_file(&path);
+        result
+    }
 }
 
 impl<F> SdImageSource<F>
 where
     F: Filesystem,
 {
+    /// Removes the upload temp file left behind by a transfer that never finished.
+    pub fn clear_stale_uploads(&mut self)
+    where
+        F: UsbFsOps,
+    {
+        let path = Self::usb_upload_temp_path();
+        if !self.fs.exists(&path).unwrap_or(false) {
+            return;
+        }
+        match self.fs.delete_file(&path) {
+            Ok(()) => log::info!("Removed stale upload {}", path),
+            Err(err) => log::warn!("Failed to remove stale upload {}: {:?}", path, err),
+        }
+    }
+
+    fn hash_index(&mut self) -> &mut Vec<HashEntry> {
+        if self.hash_index.is_none() {
+            let data = self.read_hash_index().unwrap_or_default();
+            let text = String::from_utf8_lossy(&data);
+            let entries = text.lines().filter_map(HashEntry::parse).collect();
+            self.hash_index = Some(entries);
+        }
+        self.hash_index.get_or_insert_with(Vec::new)
+    }
+
+    fn read_hash_index(&self) -> Option<Vec<u8>> {
+        let mut file = self.fs.open_file(&Self::hash_index_path(), Mode::Read).ok()?;
+        let mut data = Vec::new();
+        let mut buffer = vec![0u8; STREAM_CHUNK];
+        loop {
+            let read = file.read(&mut buffer).ok()?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+        Some(data)
+    }
+
+    /// Writes the finished entries; partial hashes start over after a restart.
+    fn save_hash_index(&mut self) {
+        let mut text = String::new();
+        for entry in self.hash_index().iter().filter(|entry| entry.hashed == entry.size) {
+            text.push_str(&format!("{} {:08x} {}\n", entry.size, !entry.crc, entry.path));
+        }
+        let _ = self.fs.create_dir_all(Self::thumbnails_dirname());
+        let result = self
+            .fs
+            .open_file(&Self::hash_index_path(), Mode::Write)
+            .map_err(|_| ImageError::Io)
+            .and_then(|mut file| {
+                write_all(&mut file, text.as_bytes())?;
+                file.flush().map_err(|_| ImageError::Io)
+            });
+        if let Err(err) = result {
+            log::warn!("Failed to save hash index: {:?}", err);
+        }
+    }
+
+    fn record_hash(&mut self, path: &str, size: u64, crc: u32) {
+        let key = path.trim_start_matches('/').to_string();
+        let index = self.hash_index();
+        index.retain(|entry| !entry.path.eq_ignore_ascii_case(&key));
+        index.push(HashEntry {
+            path: key,
+            size,
+            crc: !crc,
+            hashed: size,
+        });
+        self.save_hash_index();
+    }
+
+    /// Drops the entries for `path` and anything below it.
+    fn forget_hash(&mut self, path: &str) {
+        let key = path.trim_start_matches('/');
+        let index = self.hash_index();
+        let before = index.len();
+        index.retain(|entry| {
+            let below = entry.path.len() > key.len()
+                && entry.path.as_bytes()[key.len()] == b'/'
+                && entry.path[..key.len()].eq_ignore_ascii_case(key);
+            !(entry.path.eq_ignore_ascii_case(key) || below)
+        });
+        if index.len() != before {
+            self.save_hash_index();
+        }
+    }
+
+    /// Continues hashing `entry` until the file is done or the budget that
+    /// began at `started` runs out.
+    fn hash_file(&mut self, path: &str, entry: &mut HashEntry, started: Instant) -> Result<(), ImageError> {
+        let mut file = self.fs.open_file(path, Mode::Read).map_err(|_| ImageError::Io)?;
+        file.seek(SeekFrom::Start(entry.hashed)).map_err(|_| ImageError::Io)?;
+        let mut buffer = vec![0u8; STREAM_CHUNK];
+        while entry.hashed < entry.size {
+            let read = file.read(&mut buffer).map_err(|_| ImageError::Io)?;
+            if read == 0 {
+                return Err(ImageError::Decode);
+            }
+            entry.crc = crc32_update(entry.crc, &buffer[..read]);
+            entry.hashed += read as u64;
+            if started.elapsed().as_millis() >= HASH_BUDGET_MS {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn normalize_deleted_path(path: &str) -> String {
         path.trim_start_matches('/').to_string()
     }
@@ -407,7 +903,7 @@ where
         if target.is_empty() {
             return;
         }
-        if let Some(resume) = self.read_resume() {
+        if let Some(resume) = self.load_resume() {
             if Self::path_matches(&resume, &target) {
                 self.save_resume(None);
             }
@@ -420,7 +916,7 @@ where
             self.save_recent_entries(&recents);
         }
 
-        let mut positions = self.read_book_positions();
+        let mut positions = self.load_book_positions();
         let old_len = positions.len();
         positions.retain(|(entry, _)| !Self::path_matches(entry, &target));
         if positions.len() != old_len {
@@ -445,6 +941,74 @@ where
             .delete_file(&format!("{}/{}", cache_legacy, title));
     }
 
+    fn bench_file(&mut self, path: &str, size: usize) -> Result<(u32, u32), ImageError> {
+        let mut buffer: Vec<u8> = (0..STREAM_CHUNK).map(|i| i as u8).collect();
+        let started = Instant::now();
+        {
+            let mut file = self.fs.open_file(path, Mode::Write).map_err(|_| ImageError::Io)?;
+            let mut left = size;
+            while left > 0 {
+                let len = left.min(STREAM_CHUNK);
+                write_all(&mut file, &buffer[..len])?;
+                left -= len;
+            }
+            file.flush().map_err(|_| ImageError::Io)?;
+        }
+        let write_us = started.elapsed().as_micros() as u32;
+        let started = Instant::now();
+        {
+            let mut file = self.fs.open_file(path, Mode::Read).map_err(|_| ImageError::Io)?;
+            let mut total = 0;
+            loop {
+                let read = file.read(&mut buffer).map_err(|_| ImageError::Io)?;
+                if read == 0 {
+                    break;
+                }
+                total += read;
+            }
+            if total != size {
+                return Err(ImageError::Message(format!("read back {} of {} bytes", total, size)));
+            }
+        }
+        let read_us = started.elapsed().as_micros() as u32;
+        Ok((write_us, read_us))
+    }
+
+    /// Deletes clutter under `path`, descending into other folders except the
+    /// reader's own hidden ones.
+    pub fn remove_system_files_in(&mut self, path: &str) -> Result<u32, ImageError>
+    where
+        F: UsbFsOps,
+    {
+        let listed = {
+            let dir = self.fs.open_directory(path).map_err(|_| ImageError::Io)?;
+            dir.list().map_err(|_| ImageError::Io)?
+        };
+        let entries: Vec<(String, bool)> = listed
+            .into_iter()
+            .map(|entry| (entry.name().to_string(), entry.is_directory()))
+            .collect();
+        let mut removed = 0;
+        for (name, is_dir) in entries {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let full_path = Self::join_usb_path(path, &name);
+            if is_system_clutter(&name) {
+                if is_dir {
+                    self.usb_delete_dir_recursive(&full_path)?;
+                }
+                match self.fs.delete_file(&full_path) {
+                    Ok(()) => removed += 1,
+                    Err(err) => log::warn!("Failed to remove {}: {:?}", full_path, err),
+                }
+            } else if is_dir && !name.starts_with('.') {
+                removed += self.remove_system_files_in(&full_path)?;
+            }
+        }
+        Ok(removed)
+    }
+
     fn usb_delete_dir_recursive(&mut self, path: &str) -> Result<(), ImageError>
     where
         F: UsbFsOps,
@@ -474,6 +1038,12 @@ where
     }
 }
 
+fn cooperate(hook: &mut Option<ReadHook>) {
+    if let Some(hook) = hook.as_mut() {
+        hook();
+    }
+}
+
 fn read_exact<R: Read + ?Sized>(reader: &mut R, mut buf: &mut [u8]) -> Result<(), ImageError> {
     while !buf.is_empty() {
         let read = reader.read(buf).map_err(|_| ImageError::Io)?;
@@ -486,6 +1056,38 @@ fn read_exact<R: Read + ?Sized>(reader: &mut R, mut buf: &mut [u8]) -> Result<()
     Ok(())
 }
 
+/// Reads one glyph record of a font pack, laid out as in the TRBK glyph table.
+fn read_pack_glyph<R: Read + ?Sized>(reader: &mut R) -> Result<tern_core::trbk::TrbkGlyph, ImageError> {
+    let mut record = [0u8; 4 + 1 + 1 + 1 + 2 + 2 + 2 + 4];
+    read_exact(reader, &mut record)?;
+    let width = record[5];
+    let height = record[6];
+    let bitmap_len = u32::from_le_bytes([record[13], record[14], record[15], record[16]]) as usize;
+    let mut bitmap = vec![0u8; bitmap_len];
+    read_exact(reader, &mut bitmap)?;
+    let plane_len = ((width as usize * height as usize) + 7) / 8;
+    let (bitmap_bw, bitmap_lsb, bitmap_msb) = if bitmap_len == plane_len * 3 {
+        let bw = bitmap[0..plane_len].to_vec();
+        let lsb = bitmap[plane_len..plane_len * 2].to_vec();
+        let msb = bitmap[plane_len * 2..plane_len * 3].to_vec();
+        (bw, Some(lsb), Some(msb))
+    } else {
+        (bitmap, None, None)
+    };
+    Ok(tern_core::trbk::TrbkGlyph {
+        codepoint: u32::from_le_bytes([record[0], record[1], record[2], record[3]]),
+        style: record[4],
+        width,
+        height,
+        x_advance: i16::from_le_bytes([record[7], record[8]]),
+        x_offset: i16::from_le_bytes([record[9], record[10]]),
+        y_offset: i16::from_le_bytes([record[11], record[12]]),
+        bitmap_bw,
+        bitmap_lsb,
+        bitmap_msb,
+    })
+}
+
 fn write_all<W: Write>(writer: &mut W, mut data: &[u8]) -> Result<(), ImageError> {
     while !data.is_empty() {
         let written = writer.write(data).map_err(|_| ImageError::Io)?;
@@ -497,6 +1099,34 @@ fn write_all<W: Write>(writer: &mut W, mut data: &[u8]) -> Result<(), ImageError
     Ok(())
 }
 
+struct FsStateStorage<'a, F: Filesystem> {
+    fs: &'a F,
+}
+
+impl<F: Filesystem> StateStorage for FsStateStorage<'_, F> {
+    fn read_state_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        let mut file = self.fs.open_file(name, Mode::Read).ok()?;
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            let read = file.read(&mut buffer).ok()?;
+            if read == 0 {
+                break;
+            }
+            data.try_reserve(read).ok()?;
+            data.extend_from_slice(&buffer[..read]);
+        }
+        Some(data)
+    }
+
+    fn write_state_file(&mut self, name: &str, data: &[u8]) -> bool {
+        let Ok(mut file) = self.fs.open_file(name, Mode::Write) else {
+            return false;
+        };
+        write_all(&mut file, data).is_ok() && file.flush().is_ok()
+    }
+}
+
 fn thumb_hash_hex(key: &str) -> String {
     let mut hash: u32 = 0x811c9dc5;
     for b in key.as_bytes() {
@@ -570,6 +1200,31 @@ fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, ImageError> {
     ]))
 }
 
+/// Rejects a path FatFs would refuse or quietly change, so an upload keeps
+/// the exact long name the host sent instead of failing with a bare I/O error.
+fn check_fat_name(path: &str) -> Result<(), ImageError> {
+    for part in path.split('/').filter(|part| !part.is_empty()) {
+        let problem = if part == "." || part == ".." {
+            Some("is reserved")
+        } else if part.encode_utf16().count() > 255 {
+            Some("is longer than 255 characters")
+        } else if part.ends_with('.') || part.ends_with(' ') {
+            Some("ends with a dot or space")
+        } else if part
+            .chars()
+            .any(|c| c < ' ' || matches!(c, '"' | '*' | ':' | '<' | '>' | '?' | '\\' | '|'))
+        {
+            Some("has a character FAT does not allow")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(ImageError::Message(format!("name '{}' {}", part, problem)));
+        }
+    }
+    Ok(())
+}
+
 fn read_trimg_from_file<R: Read>(reader: &mut R, len: usize) -> Result<ImageData, ImageError> {
     if len < 16 {
         return Err(ImageError::Decode);
@@ -579,6 +1234,7 @@ fn read_trimg_from_file<R: Read>(reader: &mut R, len: usize) -> Result<ImageData
     if &header[0..4] != b"TRIM" {
         return Err(ImageError::Unsupported);
     }
+    let len = len.saturating_sub(tern_core::trbk::trimg_metadata_len(&header));
     let width = u16::from_le_bytes([header[6], header[7]]) as u32;
     let height = u16::from_le_bytes([header[8], header[9]]) as u32;
     let plane = ((width as usize * height as usize) + 7) / 8;
@@ -594,7 +1250,7 @@ fn read_trimg_from_file<R: Read>(reader: &mut R, len: usize) -> Result<ImageData
                     "Not enough memory for image buffer.".into(),
                 ));
             }
-            let mut buffer = [0u8; 512];
+            let mut buffer = vec![0u8; STREAM_CHUNK];
             while bits.len() < plane {
                 let read = reader.read(&mut buffer).map_err(|_| ImageError::Io)?;
                 if read == 0 {
@@ -680,19 +1336,16 @@ where
             log::warn!("Failed to list directory '{}': {:?}", path_str, err);
             ImageError::Io
         })?;
-        self.short_names.clear();
         for entry in listed {
             let name = entry.name().to_string();
             let short = entry.short_name().to_string();
             let upper = name.to_ascii_uppercase();
             let short_upper = short.to_ascii_uppercase();
             let short_is_hidden = short.starts_with('.');
-            if !name.is_empty() {
-                self.short_names.push((name.clone(), short));
-            }
             if name.is_empty()
                 || name.starts_with('.')
                 || short_is_hidden
+                || is_system_clutter(&name)
                 || upper == Self::resume_filename()
                 || upper == Self::resume_filename_legacy().to_ascii_uppercase()
                 || upper == Self::book_positions_filename()
@@ -735,20 +1388,35 @@ where
             return Err(ImageError::Message("Select a file, not a folder.".into()));
         }
         let lower = entry.name.to_ascii_lowercase();
-        if lower.ends_with(".epub") || lower.ends_with(".epb") {
-            return Err(ImageError::Message("EPUB files must be converted to .trbk.".into()));
-        }
-        if lower.ends_with(".trbk") || lower.ends_with(".tbk") {
+        if lower.ends_with(".trbk")
+            || lower.ends_with(".tbk")
+            || lower.ends_with(".epub")
+            || lower.ends_with(".epb")
+        {
             return Err(ImageError::Unsupported);
         }
 
         let file_path = Self::build_path(path, &entry.name);
+        if let Some(mut bits) = self.take_prefetched(&file_path) {
+            // Only 1-bit images are read ahead, and `prefetch` checked the
+            // header; the plane follows it.
+            let width = u16::from_le_bytes([bits[6], bits[7]]) as u32;
+            let height = u16::from_le_bytes([bits[8], bits[9]]) as u32;
+            let plane = ((width as usize * height as usize) + 7) / 8;
+            let metadata_len = tern_core::trbk::trimg_metadata_len(&bits[..16]);
+            let pixels_len = bits.len().saturating_sub(metadata_len);
+            if 16 + plane != pixels_len {
+                return Err(ImageError::Decode);
+            }
+            bits.truncate(16 + plane);
+            bits.drain(..16);
+            return Ok(ImageData::Mono1 { width, height, bits });
+        }
         let mut file = self
             .fs
             .open_file(&file_path, Mode::Read)
             .map_err(|_| ImageError::Io)?;
 
-        const MAX_IMAGE_BYTES: usize = 200_000;
         let file_len = file.size();
         if file_len < 16 || file_len > MAX_IMAGE_BYTES {
             return Err(ImageError::Message(
@@ -761,12 +1429,13 @@ where
         if read != header.len() || &header[0..4] != b"TRIM" {
             return Err(ImageError::Unsupported);
         }
+        let pixels_len = file_len.saturating_sub(tern_core::trbk::trimg_metadata_len(&header));
         let width = u16::from_le_bytes([header[6], header[7]]) as u32;
         let height = u16::from_le_bytes([header[8], header[9]]) as u32;
         let plane = ((width as usize * height as usize) + 7) / 8;
         match (header[4], header[5]) {
             (1, 1) => {
-                if 16 + plane != file_len {
+                if 16 + plane != pixels_len {
                     return Err(ImageError::Decode);
                 }
                 let mut bits = Vec::new();
@@ -775,8 +1444,9 @@ where
                         "Not enough memory for image buffer.".into(),
                     ));
                 }
-                let mut buffer = [0u8; 512];
+                let mut buffer = vec![0u8; STREAM_CHUNK];
                 while bits.len() < plane {
+                    cooperate(&mut self.read_hook);
                     let read = file.read(&mut buffer).map_err(|_| ImageError::Io)?;
                     if read == 0 {
                         break;
@@ -796,7 +1466,7 @@ where
                 Ok(ImageData::Mono1 { width, height, bits })
             }
             (2, 2) => {
-                if 16 + plane * 3 != file_len {
+                if 16 + plane * 3 != pixels_len {
                     return Err(ImageError::Decode);
                 }
                 let key = self.entry_path_string(path, entry);
@@ -806,132 +1476,257 @@ where
         }
     }
 
-}
+    fn capture_date(&mut self, path: &[String], entry: &ImageEntry) -> Option<u32> {
+        let file_path = Self::build_path(path, &entry.name);
+        let mut file = self.fs.open_file(&file_path, Mode::Read).ok()?;
+        let mut header = [0u8; 16];
+        let read = file.read(&mut header).ok()?;
+        if read != header.len() {
+            return None;
+        }
+        tern_core::trbk::trimg_capture_date(&header)
+    }
+
+    fn image_info(&mut self, path: &[String], entry: &ImageEntry) -> Option<ImageInfo> {
+        // Longer sections are someone else's data; show the header alone.
+        const MAX_METADATA_BYTES: usize = 2048;
+        let file_path = Self::build_path(path, &entry.name);
+        let mut file = self.fs.open_file(&file_path, Mode::Read).ok()?;
+        let mut header = [0u8; 16];
+        read_exact(&mut file, &mut header).ok()?;
+        if &header[0..4] != b"TRIM" {
+            return None;
+        }
+        let len = tern_core::trbk::trimg_metadata_len(&header);
+        let mut metadata = Vec::new();
+        if len <= MAX_METADATA_BYTES && len + 16 <= file.size() {
+            metadata.resize(len, 0);
+            file.seek(SeekFrom::Start((file.size() - len) as u64)).ok()?;
+            read_exact(&mut file, &mut metadata).ok()?;
+        }
+        Some(tern_core::trbk::parse_trimg_info(&header, &metadata))
+    }
+}
+
+impl<F> PersistenceSource for SdImageSource<F>
+where
+    F: Filesystem,
+{
+    fn save_resume(&mut self, name: Option<&str>) {
+        if let Some(name) = name {
+            log::info!("Saving resume state: {}", name);
+        }
+        self.ensure_state();
+        self.state.state_mut().resume = name.map(|name| name.to_string());
+        self.save_state();
+    }
+
+    fn load_resume(&mut self) -> Option<String> {
+        // Always re-read so the post-save readback checks what is on the card.
+        self.reload_state();
+        self.state.state().resume.clone()
+    }
+
+    fn save_book_positions(&mut self, entries: &[(String, usize)]) {
+        self.ensure_state();
+        self.state.state_mut().book_positions = entries.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_positions(&mut self) -> Vec<(String, usize)> {
+        self.ensure_state();
+        self.state.state().book_positions.clone()
+    }
+
+    fn save_recent_entries(&mut self, entries: &[String]) {
+        log::info!("Saving recent entries: {}", entries.len());
+        self.ensure_state();
+        self.state.state_mut().recent_entries = entries.to_vec();
+        self.save_state();
+    }
+
+    fn load_recent_entries(&mut self) -> Vec<String> {
+        self.ensure_state();
+        self.state.state().recent_entries.clone()
+    }
+
+    fn save_reading_font(&mut self, name: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().reading_font = name.map(|name| name.to_string());
+        self.save_state();
+    }
+
+    fn load_reading_font(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().reading_font.clone()
+    }
+
+    fn save_reading_layout(&mut self, layout: tern_core::reflow::ReadingLayout) {
+        self.ensure_state();
+        self.state.state_mut().reading_layout = layout;
+        self.save_state();
+    }
+
+    fn load_reading_layout(&mut self) -> tern_core::reflow::ReadingLayout {
+        self.ensure_state();
+        self.state.state().reading_layout
+    }
+
+    fn save_usb_hosts(&mut self, hosts: &[String]) {
+        self.ensure_state();
+        self.state.state_mut().usb_hosts = hosts.to_vec();
+        self.save_state();
+    }
+
+    fn load_usb_hosts(&mut self) -> Vec<String> {
+        self.ensure_state();
+        self.state.state().usb_hosts.clone()
+    }
+
+    fn save_book_crops(&mut self, crops: &[(String, u8)]) {
+        self.ensure_state();
+        self.state.state_mut().book_crops = crops.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_crops(&mut self) -> Vec<(String, u8)> {
+        self.ensure_state();
+        self.state.state().book_crops.clone()
+    }
+
+    fn save_show_front_matter(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().show_front_matter = show;
+        self.save_state();
+    }
+
+    fn load_show_front_matter(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().show_front_matter
+    }
+
+    fn save_refresh_tuning(&mut self, tuning: tern_core::display::RefreshTuning) {
+        self.ensure_state();
+        self.state.state_mut().refresh_tuning = tuning;
+        self.save_state();
+    }
+
+    fn load_refresh_tuning(&mut self) -> tern_core::display::RefreshTuning {
+        self.ensure_state();
+        self.state.state().refresh_tuning
+    }
+
+    fn save_image_order(&mut self, order: tern_core::app::image_viewer::ImageOrder) {
+        self.ensure_state();
+        self.state.state_mut().image_order = order;
+        self.save_state();
+    }
+
+    fn load_image_order(&mut self) -> tern_core::app::image_viewer::ImageOrder {
+        self.ensure_state();
+        self.state.state().image_order
+    }
+
+    fn save_clean_page(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_page = clean;
+        self.save_state();
+    }
+
+    fn load_clean_page(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_page
+    }
+
+    fn save_book_paces(&mut self, paces: &[(String, u32)]) {
+        self.ensure_state();
+        self.state.state_mut().book_paces = paces.to_vec();
+        self.save_state();
+    }
+
+    fn load_book_paces(&mut self) -> Vec<(String, u32)> {
+        self.ensure_state();
+        self.state.state().book_paces.clone()
+    }
 
-impl<F> PersistenceSource for SdImageSource<F>
-where
-    F: Filesystem,
-{
-    fn save_resume(&mut self, name: Option<&str>) {
-        let resume_name = Self::resume_filename();
-        if let Some(name) = name {
-            log::info!("Saving resume state: {}", name);
-            let mut file = match self.fs.open_file(resume_name, Mode::Write) {
-                Ok(file) => file,
-                Err(_) => return,
-            };
-            let mut written = 0usize;
-            let bytes = name.as_bytes();
-            while written < bytes.len() {
-                match file.write(&bytes[written..]) {
-                    Ok(0) | Err(_) => break,
-                    Ok(n) => written += n,
-                }
-            }
-            let _ = file.flush();
-        } else {
-            let _ = self.fs.open_file(resume_name, Mode::Write);
-        }
+    fn save_chapter_progress(&mut self, show: bool) {
+        self.ensure_state();
+        self.state.state_mut().chapter_progress = show;
+        self.save_state();
     }
 
-    fn load_resume(&mut self) -> Option<String> {
-        self.read_resume()
+    fn load_chapter_progress(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().chapter_progress
     }
 
-    fn save_book_positions(&mut self, entries: &[(String, usize)]) {
-        let positions_name = Self::book_positions_filename();
-        if entries.is_empty() {
-            return;
-        }
-        let mut file = match self.fs.open_file(positions_name, Mode::Write) {
-            Ok(file) => file,
-            Err(_) => return,
-        };
-        for (name, page) in entries {
-            let mut line = String::new();
-            line.push_str(name);
-            line.push('\t');
-            line.push_str(&page.to_string());
-            line.push('\n');
-            if write_all(&mut file, line.as_bytes()).is_err() {
-                return;
-            }
-        }
-        let _ = file.flush();
+    fn save_simple_mode(&mut self, folder: Option<&str>) {
+        self.ensure_state();
+        self.state.state_mut().simple_mode = folder.map(|folder| folder.to_string());
+        self.save_state();
     }
 
-    fn load_book_positions(&mut self) -> Vec<(String, usize)> {
-        self.read_book_positions()
+    fn load_simple_mode(&mut self) -> Option<String> {
+        self.ensure_state();
+        self.state.state().simple_mode.clone()
     }
 
-    fn save_recent_entries(&mut self, entries: &[String]) {
-        let name = Self::recent_entries_filename();
-        if entries.is_empty() {
-            return;
-        }
-        log::info!("Saving recent entries: {} -> {}", entries.len(), name);
-        let mut file = match self.fs.open_file(name, Mode::Write) {
-            Ok(file) => file,
-            Err(err) => {
-                log::warn!("Failed to open recent entries file {}: {:?}", name, err);
-                return;
-            }
-        };
-        for entry in entries {
-            if write_all(&mut file, entry.as_bytes()).is_err() {
-                log::warn!("Failed to write recent entry to {}", name);
-                return;
-            }
-            if write_all(&mut file, b"\n").is_err() {
-                log::warn!("Failed to write recent entry newline to {}", name);
-                return;
-            }
-        }
-        let _ = file.flush();
+    fn save_lock_code(&mut self, code: &[u8]) {
+        self.ensure_state();
+        self.state.state_mut().lock_code = code.to_vec();
+        self.save_state();
     }
 
-    fn load_recent_entries(&mut self) -> Vec<String> {
-        let mut file = match self
-            .fs
-            .open_file(Self::recent_entries_filename(), Mode::Read)
-            .or_else(|_| self.fs.open_file(Self::recent_entries_filename_legacy(), Mode::Read))
-        {
-            Ok(file) => file,
-            Err(err) => {
-                log::info!("No recent entries file: {:?}", err);
-                if let Ok(mut file) = self.fs.open_file(Self::recent_entries_filename(), Mode::Write) {
-                    let _ = file.flush();
-                }
-                return Vec::new();
-            }
-        };
-        let mut data = Vec::new();
-        let mut buffer = [0u8; 256];
-        loop {
-            let read = match file.read(&mut buffer) {
-                Ok(read) => read,
-                Err(_) => return Vec::new(),
-            };
-            if read == 0 {
-                break;
-            }
-            if data.try_reserve(read).is_err() {
-                return Vec::new();
-            }
-            data.extend_from_slice(&buffer[..read]);
-        }
-        let text = match core::str::from_utf8(&data) {
-            Ok(text) => text,
-            Err(_) => return Vec::new(),
-        };
-        let mut entries = Vec::new();
-        for line in text.lines() {
-            let value = line.trim();
-            if !value.is_empty() {
-                entries.push(value.to_string());
-            }
-        }
-        entries
+    fn load_lock_code(&mut self) -> Vec<u8> {
+        self.ensure_state();
+        self.state.state().lock_code.clone()
+    }
+
+    fn save_clean_system_files(&mut self, clean: bool) {
+        self.ensure_state();
+        self.state.state_mut().clean_system_files = clean;
+        self.save_state();
+    }
+
+    fn load_clean_system_files(&mut self) -> bool {
+        self.ensure_state();
+        self.state.state().clean_system_files
+    }
+
+    fn save_frontlight(&mut self, level: tern_core::display::FrontlightLevel) {
+        self.ensure_state();
+        self.state.state_mut().frontlight = level;
+        self.save_state();
+    }
+
+    fn load_frontlight(&mut self) -> tern_core::display::FrontlightLevel {
+        self.ensure_state();
+        self.state.state().frontlight
+    }
+
+    fn save_power_button_mode(&mut self, mode: tern_core::app::power_menu::PowerButtonMode) {
+        self.ensure_state();
+        self.state.state_mut().power_button = mode;
+        self.save_state();
+    }
+
+    fn load_power_button_mode(&mut self) -> tern_core::app::power_menu::PowerButtonMode {
+        self.ensure_state();
+        self.state.state().power_button
+    }
+
+    fn load_profiles(&mut self) -> Vec<Profile> {
+        read_profiles(&mut FsStateStorage { fs: &self.fs })
+    }
+
+    fn active_profile(&mut self) -> usize {
+        self.ensure_state();
+        self.state.profile() as usize
+    }
+
+    fn switch_profile(&mut self, index: usize) {
+        self.state.set_profile(&mut FsStateStorage { fs: &self.fs }, index as u8);
+        self.ensure_state();
     }
 
     fn load_thumbnail(&mut self, key: &str) -> Option<ImageData> {
@@ -962,8 +1757,9 @@ where
         if bits.try_reserve(expected).is_err() {
             return None;
         }
-        let mut buffer = [0u8; 256];
+        let mut buffer = vec![0u8; STREAM_CHUNK];
         while bits.len() < expected {
+            cooperate(&mut self.read_hook);
             let read = file.read(&mut buffer).ok()?;
             if read == 0 {
                 break;
@@ -1134,12 +1930,13 @@ where
 
             let total_pixels = (width as usize) * (height as usize);
             let plane_len = (total_pixels + 7) / 8;
-            let mut tmp = [0u8; 256];
+            let mut tmp = vec![0u8; STREAM_CHUNK];
             let mut pixel_index: usize = 0;
             let mut read_plane = |target: &mut [u8], is_base: bool| -> Result<(), ImageError> {
                 pixel_index = 0;
                 let mut remaining = plane_len;
                 while remaining > 0 {
+                    cooperate(&mut self.read_hook);
                     let want = remaining.min(tmp.len());
                     read_exact(reader, &mut tmp[..want])?;
                     for byte in &tmp[..want] {
@@ -1186,15 +1983,7 @@ where
             let Some(state) = &self.trbk else {
                 return Err(ImageError::Decode);
             };
-            let file_path = if state.path.is_empty() {
-                state
-                    .short_name
-                    .as_deref()
-                    .unwrap_or(state.name.as_str())
-                    .to_string()
-            } else {
-                format!("{}/{}", state.path.join("/"), state.name)
-            };
+            let file_path = Self::build_path(&state.path, &state.name);
             let mut file = self
                 .fs
                 .open_file(&file_path, Mode::Read)
@@ -1282,12 +2071,13 @@ where
             }
 
             let plane_len = (total_pixels + 7) / 8;
-            let mut tmp = [0u8; 256];
+            let mut tmp = vec![0u8; STREAM_CHUNK];
             let mut pixel_index = 0usize;
             let mut read_plane = |sum: &mut [u16], track_count: bool| -> Result<(), ImageError> {
                 pixel_index = 0;
                 let mut remaining = plane_len;
                 while remaining > 0 {
+                    cooperate(&mut self.read_hook);
                     let want = remaining.min(tmp.len());
                     read_exact(reader, &mut tmp[..want])?;
                     for byte in &tmp[..want] {
@@ -1324,15 +2114,7 @@ where
         let result = if let Some(offset_str) = key.strip_prefix("trbk:") {
             let offset: u32 = offset_str.parse().ok()?;
             let state = self.trbk.as_ref()?;
-            let file_path = if state.path.is_empty() {
-                state
-                    .short_name
-                    .as_deref()
-                    .unwrap_or(state.name.as_str())
-                    .to_string()
-            } else {
-                format!("{}/{}", state.path.join("/"), state.name)
-            };
+            let file_path = Self::build_path(&state.path, &state.name);
             let mut file = self.fs.open_file(&file_path, Mode::Read).ok()?;
             file.seek(SeekFrom::Start(offset as u64)).ok()?;
             load_from_reader(&mut file)
@@ -1375,6 +2157,204 @@ where
     }
 }
 
+impl<F> SdImageSource<F>
+where
+    F: Filesystem,
+{
+    /// Streams the book's font pack from `/fonts` into the glyphs that left
+    /// their bitmaps to it. A missing or mismatched pack only leaves those
+    /// glyphs blank.
+    fn fill_font_pack(
+        &self,
+        glyphs: &mut [tern_core::trbk::TrbkGlyph],
+        reference: &tern_core::trbk::TrbkFontPackRef,
+    ) {
+        let pack_path = Self::build_path(
+            &[tern_core::trbk::FONT_PACK_DIR.to_string()],
+            &reference.name,
+        );
+        if let Err(err) = self.read_font_pack(&pack_path, glyphs, reference) {
+            log::warn!("Font pack '{}' not loaded: {:?}", pack_path, err);
+        }
+    }
+
+    fn read_font_pack(
+        &self,
+        pack_path: &str,
+        glyphs: &mut [tern_core::trbk::TrbkGlyph],
+        reference: &tern_core::trbk::TrbkFontPackRef,
+    ) -> Result<(), ImageError> {
+        let mut file = self
+            .fs
+            .open_file(pack_path, Mode::Read)
+            .map_err(|_| ImageError::Io)?;
+        let mut header = [0u8; tern_core::trbk::FONT_PACK_HEADER_LEN];
+        read_exact(&mut file, &mut header)?;
+        let header = tern_core::trbk::parse_font_pack_header(&header)?;
+        if header.hash != reference.hash {
+            return Err(ImageError::Message("font pack does not match the book".into()));
+        }
+        for _ in 0..header.glyph_count {
+            tern_core::trbk::fill_pack_glyph(glyphs, read_pack_glyph(&mut file)?);
+        }
+        Ok(())
+    }
+
+    /// Installed font packs as file name and header.
+    fn font_packs(&self) -> Vec<(String, tern_core::trbk::TrbkFontPackHeader)> {
+        let Ok(dir) = self.fs.open_directory(tern_core::trbk::FONT_PACK_DIR) else {
+            return Vec::new();
+        };
+        let Ok(listed) = dir.list() else {
+            return Vec::new();
+        };
+        let mut packs = Vec::new();
+        for entry in listed {
+            if entry.is_directory()
+                || is_system_clutter(entry.name())
+                || tern_core::trbk::font_pack_typeface(entry.name()).is_none()
+            {
+                continue;
+            }
+            let pack_path = Self::build_path(
+                &[tern_core::trbk::FONT_PACK_DIR.to_string()],
+                entry.name(),
+            );
+            let Ok(mut file) = self.fs.open_file(&pack_path, Mode::Read) else {
+                continue;
+            };
+            let mut header = [0u8; tern_core::trbk::FONT_PACK_HEADER_LEN];
+            if read_exact(&mut file, &mut header).is_err() {
+                continue;
+            }
+            if let Ok(header) = tern_core::trbk::parse_font_pack_header(&header) {
+                packs.push((entry.name().to_string(), header));
+            }
+        }
+        packs
+    }
+
+    /// Draws the book in the chosen typeface, streaming its pack closest in
+    /// size over the book's glyphs.
+    fn apply_reading_font(
+        &self,
+        glyphs: &mut [tern_core::trbk::TrbkGlyph],
+        char_width: u16,
+        typeface: &str,
+    ) {
+        let packs = self
+            .font_packs()
+            .into_iter()
+            .filter(|(name, _)| tern_core::trbk::font_pack_typeface(name) == Some(typeface))
+            .collect::<Vec<_>>();
+        let Some(name) = tern_core::trbk::pick_reading_font_pack(&packs, char_width) else {
+            log::warn!("No font pack installed for reading font '{}'", typeface);
+            return;
+        };
+        let pack_path = Self::build_path(&[tern_core::trbk::FONT_PACK_DIR.to_string()], name);
+        let result = self
+            .fs
+            .open_file(&pack_path, Mode::Read)
+            .map_err(|_| ImageError::Io)
+            .and_then(|mut file| {
+                let mut header = [0u8; tern_core::trbk::FONT_PACK_HEADER_LEN];
+                read_exact(&mut file, &mut header)?;
+                let header = tern_core::trbk::parse_font_pack_header(&header)?;
+                for _ in 0..header.glyph_count {
+                    tern_core::trbk::replace_glyph(glyphs, read_pack_glyph(&mut file)?);
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            log::warn!("Reading font '{}' not loaded: {:?}", pack_path, err);
+        }
+    }
+
+    /// Reads a small EPUB from the card and takes its glyphs from the
+    /// installed font pack the reader would pick, so books that were never
+    /// converted can still be read through reflow.
+    fn open_epub(
+        &mut self,
+        path: &[String],
+        entry: &ImageEntry,
+    ) -> Result<Rc<tern_core::trbk::TrbkBookInfo>, ImageError> {
+        let file_path = Self::build_path(path, &entry.name);
+        let data = match self.take_prefetched(&file_path) {
+            Some(data) => data,
+            None => self.read_epub(&file_path)?,
+        };
+        let book = tern_core::epub::parse_epub(&data)?;
+        drop(data);
+
+        self.ensure_state();
+        let state = self.state.state();
+        let reading_font = state.reading_font.clone();
+        let reading = state.reading_layout;
+        let packs = self.font_packs();
+        let Some(name) =
+            tern_core::epub::pick_epub_font_pack(&packs, reading_font.as_deref(), reading)
+        else {
+            return Err(ImageError::Message(tern_core::epub::NO_FONT_PACK.into()));
+        };
+        let typeface = tern_core::trbk::font_pack_typeface(name).unwrap_or_default();
+        let glyphs = self.read_pack_glyphs(name)?;
+        let info = Rc::new(book.book_info(typeface, glyphs));
+        self.trbk = None;
+        self.epub = Some(book);
+        Ok(info)
+    }
+
+    fn read_epub(&mut self, file_path: &str) -> Result<Vec<u8>, ImageError> {
+        let mut file = self
+            .fs
+            .open_file(file_path, Mode::Read)
+            .map_err(|_| ImageError::Io)?;
+        let file_len = file.size();
+        tern_core::epub::check_epub_size(file_len)?;
+
+        let mut data = Vec::new();
+        if data.try_reserve_exact(file_len).is_err() {
+            return Err(ImageError::Message(
+                "Not enough memory for book file.".into(),
+            ));
+        }
+        data.resize(file_len, 0);
+        let mut filled = 0;
+        while filled < file_len {
+            cooperate(&mut self.read_hook);
+            let end = (filled + STREAM_CHUNK).min(file_len);
+            let read = file.read(&mut data[filled..end]).map_err(|_| ImageError::Io)?;
+            if read == 0 {
+                return Err(ImageError::Decode);
+            }
+            filled += read;
+        }
+        Ok(data)
+    }
+
+    /// Every glyph of font pack `name` in `/fonts`.
+    fn read_pack_glyphs(&self, name: &str) -> Result<Vec<tern_core::trbk::TrbkGlyph>, ImageError> {
+        let pack_path = Self::build_path(&[tern_core::trbk::FONT_PACK_DIR.to_string()], name);
+        let mut file = self
+            .fs
+            .open_file(&pack_path, Mode::Read)
+            .map_err(|_| ImageError::Io)?;
+        let mut header = [0u8; tern_core::trbk::FONT_PACK_HEADER_LEN];
+        read_exact(&mut file, &mut header)?;
+        let header = tern_core::trbk::parse_font_pack_header(&header)?;
+        let mut glyphs = Vec::new();
+        if glyphs.try_reserve_exact(header.glyph_count).is_err() {
+            return Err(ImageError::Message(
+                "Not enough memory for font pack.".into(),
+            ));
+        }
+        for _ in 0..header.glyph_count {
+            glyphs.push(read_pack_glyph(&mut file)?);
+        }
+        Ok(glyphs)
+    }
+}
+
 impl<F> BookSource for SdImageSource<F>
 where
     F: Filesystem,
@@ -1407,8 +2387,9 @@ where
                 "Not enough memory for book file.".into(),
             ));
         }
-        let mut buffer = [0u8; 512];
+        let mut buffer = vec![0u8; STREAM_CHUNK];
         while data.len() < file_len {
+            cooperate(&mut self.read_hook);
             let read = file.read(&mut buffer).map_err(|_| ImageError::Io)?;
             if read == 0 {
                 break;
@@ -1426,7 +2407,23 @@ where
             return Err(ImageError::Decode);
         }
 
-        tern_core::trbk::parse_trbk(&data)
+        let mut book = tern_core::trbk::parse_trbk(&data)?;
+        drop(data);
+        if let Some(reference) = book.metadata.font_pack.clone() {
+            self.fill_font_pack(Rc::make_mut(&mut book.glyphs).as_mut_slice(), &reference);
+        }
+        let state = self.state.state();
+        let reading_font = tern_core::reflow::reading_font_for(
+            &book.metadata,
+            book.text.is_some(),
+            state.reading_font.as_deref(),
+            state.reading_layout,
+        )
+        .map(|(typeface, char_width)| (typeface.to_string(), char_width));
+        if let Some((typeface, char_width)) = reading_font {
+            self.apply_reading_font(Rc::make_mut(&mut book.glyphs).as_mut_slice(), char_width, &typeface);
+        }
+        Ok(book)
     }
 
     fn open_trbk(
@@ -1437,21 +2434,24 @@ where
         if entry.kind != EntryKind::File {
             return Err(ImageError::Unsupported);
         }
+        self.epub = None;
+        let lower = entry.name.to_ascii_lowercase();
+        if lower.ends_with(".epub") || lower.ends_with(".epb") {
+            return self.open_epub(path, entry);
+        }
         let file_path = Self::build_path(path, &entry.name);
         let mut file = self
             .fs
             .open_file(&file_path, Mode::Read)
             .map_err(|_| ImageError::Io)?;
 
+        let file_len = file.size();
         let mut header = [0u8; 0x30];
-        read_exact(&mut file, &mut header)?;
-        if &header[0..4] != b"TRBK" {
-            return Err(ImageError::Decode);
-        }
+        let header_len = file_len.min(header.len());
+        read_exact(&mut file, &mut header[..header_len])?;
+        tern_core::trbk::check_trbk_header(&header[..header_len], file_len)?;
         let version = header[4];
-        if version != 1 && version != 2 {
-            return Err(ImageError::Unsupported);
-        }
+        let header_flags = header[5];
         let header_size = read_u16_le(&header, 0x06)? as usize;
         let screen_width = read_u16_le(&header, 0x08)?;
         let screen_height = read_u16_le(&header, 0x0A)?;
@@ -1483,7 +2483,8 @@ where
         file.seek(SeekFrom::Start(0)).map_err(|_| ImageError::Io)?;
         read_exact(&mut file, &mut header_buf)?;
 
-        let mut cursor = if version >= 2 { 0x30 } else { 0x2C };
+        let text = tern_core::trbk::parse_text_info(&header_buf, version)?;
+        let mut cursor = tern_core::trbk::metadata_offset(version);
         let title = read_string(&header_buf, &mut cursor)?;
         let author = read_string(&header_buf, &mut cursor)?;
         let language = read_string(&header_buf, &mut cursor)?;
@@ -1495,7 +2496,10 @@ where
         let margin_left = read_u16_le(&header_buf, cursor)?; cursor += 2;
         let margin_right = read_u16_le(&header_buf, cursor)?; cursor += 2;
         let margin_top = read_u16_le(&header_buf, cursor)?; cursor += 2;
-        let margin_bottom = read_u16_le(&header_buf, cursor)?;
+        let margin_bottom = read_u16_le(&header_buf, cursor)?; cursor += 2;
+        let part = tern_core::trbk::parse_part_info(&header_buf, cursor, header_flags);
+        let series = tern_core::trbk::parse_series(&header_buf, cursor, header_flags);
+        let font_pack = tern_core::trbk::parse_font_pack_ref(&header_buf, cursor, header_flags);
 
         let metadata = tern_core::trbk::TrbkMetadata {
             title,
@@ -1510,6 +2514,9 @@ where
             margin_right,
             margin_top,
             margin_bottom,
+            part,
+            series,
+            font_pack,
         };
 
         let mut toc_entries = Vec::new();
@@ -1569,7 +2576,37 @@ where
                 let x_advance = i16::from_le_bytes([header[7], header[8]]);
                 let x_offset = i16::from_le_bytes([header[9], header[10]]);
                 let y_offset = i16::from_le_bytes([header[11], header[12]]);
-                let bitmap_len = u32::from_le_bytes([header[13], header[14], header[15], header[16]]) as usize;
+                let bitmap_len = u32::from_le_bytes([header[13], header[14], header[15], header[16]]);
+                if header_flags & tern_core::trbk::TRBK_FLAG_FONT_PACK != 0
+                    && bitmap_len == tern_core::trbk::TRBK_FONT_PACK_GLYPH
+                {
+                    glyphs.push(tern_core::trbk::TrbkGlyph {
+                        codepoint,
+                        style,
+                        width,
+                        height,
+                        x_advance,
+                        x_offset,
+                        y_offset,
+                        bitmap_bw: Vec::new(),
+                        bitmap_lsb: None,
+                        bitmap_msb: None,
+                    });
+                    continue;
+                }
+                if header_flags & tern_core::trbk::TRBK_FLAG_SHARED_GLYPHS != 0
+                    && bitmap_len & tern_core::trbk::TRBK_SHARED_GLYPH_REF != 0
+                {
+                    let source = glyphs
+                        .get((bitmap_len & !tern_core::trbk::TRBK_SHARED_GLYPH_REF) as usize)
+                        .ok_or(ImageError::Decode)?;
+                    let glyph = tern_core::trbk::shared_glyph(
+                        source, codepoint, style, x_advance, x_offset, y_offset,
+                    );
+                    glyphs.push(glyph);
+                    continue;
+                }
+                let bitmap_len = bitmap_len as usize;
                 let mut bitmap = vec![0u8; bitmap_len];
                 read_exact(&mut file, &mut bitmap)?;
                 let plane_len = ((width as usize * height as usize) + 7) / 8;
@@ -1671,6 +2708,20 @@ where
             }
         }
 
+        if let Some(reference) = &metadata.font_pack {
+            self.fill_font_pack(&mut glyphs, reference);
+        }
+        let state = self.state.state();
+        let reading_font = tern_core::reflow::reading_font_for(
+            &metadata,
+            text.is_some(),
+            state.reading_font.as_deref(),
+            state.reading_layout,
+        )
+        .map(|(typeface, char_width)| (typeface.to_string(), char_width));
+        if let Some((typeface, char_width)) = reading_font {
+            self.apply_reading_font(&mut glyphs, char_width, &typeface);
+        }
         let glyphs = Rc::new(glyphs);
         let info = Rc::new(tern_core::trbk::TrbkBookInfo {
             screen_width,
@@ -1680,12 +2731,12 @@ where
             glyphs: glyphs.clone(),
             toc: toc_entries,
             images,
+            text,
         });
 
         self.trbk = Some(TrbkStream {
             path: path.to_vec(),
             name: entry.name.clone(),
-            short_name: self.lookup_short_name(&entry.name),
             page_offsets: offsets,
             page_data_offset,
             glyph_table_offset,
@@ -1702,15 +2753,8 @@ where
         if page_index >= state.page_offsets.len() {
             return Err(ImageError::Decode);
         }
-        let file_path = if state.path.is_empty() {
-            state
-                .short_name
-                .as_deref()
-                .unwrap_or(state.name.as_str())
-                .to_string()
-        } else {
-            Self::build_path(&state.path, &state.name)
-        };
+        let file_path = Self::build_path(&state.path, &state.name);
+        let read_started = Instant::now();
         let mut file = self
             .fs
             .open_file(&file_path, Mode::Read)
@@ -1730,6 +2774,8 @@ where
         file.seek(SeekFrom::Start(start as u64))
             .map_err(|_| ImageError::Io)?;
         read_exact(&mut file, &mut buf)?;
+        drop(file);
+        self.page_read_us = read_started.elapsed().as_micros().min(u32::MAX as u64) as u32;
         let ops = tern_core::trbk::parse_trbk_page_ops(&buf)?;
         Ok(tern_core::trbk::TrbkPage { ops })
     }
@@ -1743,15 +2789,7 @@ where
             .images
             .get(image_index)
             .ok_or(ImageError::Decode)?;
-        let file_path = if state.path.is_empty() {
-            state
-                .short_name
-                .as_deref()
-                .unwrap_or(state.name.as_str())
-                .to_string()
-        } else {
-            Self::build_path(&state.path, &state.name)
-        };
+        let file_path = Self::build_path(&state.path, &state.name);
         let mut file = self
             .fs
             .open_file(&file_path, Mode::Read)
@@ -1777,8 +2815,64 @@ where
         read_trimg_from_file(&mut file, image.data_len as usize)
     }
 
+    fn trbk_text(
+        &mut self,
+        first: usize,
+        visit: &mut dyn FnMut(usize, tern_core::reflow::TextItem) -> bool,
+    ) -> Result<(), ImageError> {
+        if let Some(book) = &self.epub {
+            return book.text(first, visit);
+        }
+        let Some(state) = &self.trbk else {
+            return Err(ImageError::Decode);
+        };
+        let Some(text) = state.info.text else {
+            return Err(ImageError::Unsupported);
+        };
+        let file_path = Self::build_path(&state.path, &state.name);
+        if first >= text.item_count as usize {
+            return Ok(());
+        }
+        let mut file = self
+            .fs
+            .open_file(&file_path, Mode::Read)
+            .map_err(|_| ImageError::Io)?;
+        let mut lut_entry = [0u8; 4];
+        file.seek(SeekFrom::Start(text.offset as u64 + first as u64 * 4))
+            .map_err(|_| ImageError::Io)?;
+        read_exact(&mut file, &mut lut_entry)?;
+        let item_offset = text.offset + u32::from_le_bytes(lut_entry);
+        file.seek(SeekFrom::Start(item_offset as u64))
+            .map_err(|_| ImageError::Io)?;
+        // Items are stored back to back, so read on from the first.
+        for index in first..text.item_count as usize {
+            let mut item = vec![0u8; tern_core::reflow::TEXT_ITEM_HEADER_LEN];
+            read_exact(&mut file, &mut item)?;
+            let len = tern_core::reflow::text_item_len(&item)?;
+            item.resize(tern_core::reflow::TEXT_ITEM_HEADER_LEN + len, 0);
+            read_exact(&mut file, &mut item[tern_core::reflow::TEXT_ITEM_HEADER_LEN..])?;
+            if !visit(index, tern_core::reflow::parse_text_item(&item)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn close_trbk(&mut self) {
         self.trbk = None;
+        self.epub = None;
+    }
+
+    fn reading_fonts(&mut self) -> Vec<String> {
+        let mut typefaces = self
+            .font_packs()
+            .iter()
+            .filter_map(|(name, _)| tern_core::trbk::font_pack_typeface(name))
+            .map(|typeface| typeface.to_string())
+            .collect::<Vec<_>>();
+        typefaces.sort();
+        typefaces.dedup();
+        typefaces
     }
 }
 
@@ -1788,6 +2882,89 @@ where
 {
 }
 
+/// EPUBs and 1-bit images are read whole before they are parsed, so those
+/// are read ahead; TRBK books and gray images stream from the card.
+impl<F> PrefetchSource for SdImageSource<F>
+where
+    F: Filesystem,
+{
+    fn prefetch(&mut self, path: &[String], entry: &ImageEntry) -> bool {
+        self.prefetch = None;
+        if entry.kind != EntryKind::File {
+            return false;
+        }
+        let file_path = Self::build_path(path, &entry.name);
+        let Ok(mut file) = self.fs.open_file(&file_path, Mode::Read) else {
+            return false;
+        };
+        let file_len = file.size();
+        let lower = entry.name.to_ascii_lowercase();
+        let whole = if lower.ends_with(".epub") || lower.ends_with(".epb") {
+            tern_core::epub::check_epub_size(file_len).is_ok()
+        } else if lower.ends_with(".trbk") || lower.ends_with(".tbk") {
+            false
+        } else {
+            let mut header = [0u8; 16];
+            (16..=MAX_IMAGE_BYTES).contains(&file_len)
+                && file.read(&mut header).ok() == Some(header.len())
+                && &header[0..4] == b"TRIM"
+                && header[4] == 1
+                && header[5] == 1
+        };
+        drop(file);
+        // Anything else, or a file that does not fit, is read by the load.
+        let mut data = Vec::new();
+        if !whole || data.try_reserve_exact(file_len).is_err() {
+            return false;
+        }
+        data.resize(file_len, 0);
+        self.prefetch = Some(Prefetch {
+            path: file_path,
+            data,
+            filled: 0,
+        });
+        true
+    }
+
+    fn prefetch_pending(&self) -> bool {
+        self.prefetch
+            .as_ref()
+            .is_some_and(|prefetch| prefetch.filled < prefetch.data.len())
+    }
+
+    fn cancel_prefetch(&mut self) {
+        self.prefetch = None;
+    }
+}
+
+impl<F> DiagnosticsSource for SdImageSource<F>
+where
+    F: Filesystem,
+{
+    fn heap_usage(&self) -> Option<HeapUsage> {
+        let stats = esp_alloc::HEAP.stats();
+        Some(HeapUsage {
+            used: stats.current_usage,
+            free: stats.size.saturating_sub(stats.current_usage),
+            peak: stats.max_usage,
+        })
+    }
+
+    fn now_us(&self) -> Option<u64> {
+        Some(Instant::now().as_micros())
+    }
+
+    fn last_page_read_us(&self) -> Option<u32> {
+        Some(self.page_read_us)
+    }
+
+    fn save_flight_log(&mut self, text: &str) {
+        if !FsStateStorage { fs: &self.fs }.write_state_file(FLIGHT_LOG_FILE, text.as_bytes()) {
+            log::warn!("Failed to write {}", FLIGHT_LOG_FILE);
+        }
+    }
+}
+
 
 fn adjust_thumbnail_luma(lum: u8) -> u8 {
     let mut value = ((lum as i32 - 128) * 13) / 10 + 128;
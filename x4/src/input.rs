@@ -20,6 +20,8 @@ where
     PinBatt: AdcChannel + AnalogPin,
 {
     inner: ButtonState,
+    /// Buttons seen by `sample` since the last `update`.
+    latched: u8,
     pin1: AdcPin<Pin1, ADC1<'a>, AdcCal<'a>>,
     pin2: AdcPin<Pin2, ADC1<'a>, AdcCal<'a>>,
     pin_batt: AdcPin<PinBatt, ADC1<'a>, AdcCal<'a>>,
@@ -49,6 +51,7 @@ where
         let adc = Adc::new(adc, adc_config);
         GpioButtonState {
             inner: ButtonState::default(),
+            latched: 0,
             pin1,
             pin2,
             pin_batt,
@@ -69,7 +72,7 @@ where
         None
     }
 
-    pub fn update(&mut self) {
+    fn read_current(&mut self) -> u8 {
         let mut current: u8 = 0;
         let raw_button1 = nb::block!(self.adc.read_oneshot(&mut self.pin1)).unwrap();
         if let Some(button) = Self::get_button_from_adc(raw_button1 as _, &ADC_THRESHOLDS_1) {
@@ -86,6 +89,18 @@ where
             "Button ADC Readings - Pin1: {}, Pin2: {}, Current State: {:07b}",
             raw_button1, raw_button2, current
         );
+        current
+    }
+
+    /// Samples the buttons without advancing the state, so a press made
+    /// during a long SD read is still reported by the next `update`.
+    pub fn sample(&mut self) {
+        self.latched |= self.read_current();
+    }
+
+    pub fn update(&mut self) {
+        let current = self.read_current() | self.latched;
+        self.latched = 0;
         self.inner.update(current);
     }
 
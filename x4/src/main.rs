@@ -12,6 +12,7 @@ pub mod image_source;
 pub mod input;
 pub mod sdspi_fatfs;
 pub mod sdspi_fs;
+pub mod spi_bus;
 pub mod usb_mode;
 
 use core::cell::RefCell;
@@ -20,28 +21,32 @@ use crate::eink_display::EInkDisplay;
 use crate::image_source::SdImageSource;
 use crate::input::*;
 use alloc::boxed::Box;
+use alloc::format;
 use alloc::string::String;
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer};
-use embedded_hal_bus::spi::RefCellDevice;
+use embassy_time::{Duration, Instant, Timer};
 use crate::sdspi_fatfs::FatFs;
+use crate::spi_bus::{BusArbiter, BusDevice, Priority};
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
 use esp_hal::delay::Delay;
 use esp_hal::gpio::{AnyPin, Input, InputConfig, Level, Output, OutputConfig, RtcPinWithResistors};
 use esp_hal::interrupt::software::SoftwareInterruptControl;
-use esp_hal::rtc_cntl::{Rtc, sleep::{RtcioWakeupSource, WakeupLevel}};
+use esp_hal::rtc_cntl::{Rtc, SocResetReason, reset_reason, sleep::{RtcioWakeupSource, WakeupLevel}};
 use esp_hal::spi::Mode;
 use esp_hal::spi::master::{Config, Spi};
 use esp_hal::time::Rate;
-use esp_hal::timer::timg::TimerGroup;
+use esp_hal::system::Cpu;
+use esp_hal::timer::timg::{MwdtStage, TimerGroup};
 use esp_hal::usb_serial_jtag::UsbSerialJtag;
-use log::info;
+use log::{info, warn};
 use tern_core::application::Application;
-use tern_core::display::{Display, RefreshMode};
+use tern_core::display::{Display, NoFrontlight, RefreshMode};
 use tern_core::framebuffer::DisplayBuffers;
-use tern_core::input::Buttons;
-use usb_mode::{poll as usb_poll, UsbMode};
+use tern_core::input::{Buttons, MotionSensor, NoMotion};
+use tern_core::app::power_menu::PowerRequest;
+use tern_core::app::system::BootMode;
+use usb_mode::{poll as usb_poll, UsbLink, UsbMode};
 
 extern crate alloc;
 
@@ -49,6 +54,16 @@ extern crate alloc;
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// Shortest gap between partial redraws of the USB transfer screen.
+const USB_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// A main loop iteration taking longer than this restarts the reader. Long
+/// enough for a full refresh after a slow book load.
+const WATCHDOG_TIMEOUT_SECS: u64 = 30;
+/// Longest a single bus transaction should take; a full frame write at the
+/// slow SPI clock stays well inside the display's.
+const DISPLAY_SPI_TIMEOUT: Duration = Duration::from_millis(500);
+const SDCARD_SPI_TIMEOUT: Duration = Duration::from_millis(50);
+
 fn log_heap() {
     let stats = esp_alloc::HEAP.stats();
     info!("{stats}");
@@ -72,13 +87,21 @@ async fn main(_spawner: Spawner) {
     esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 0x10000);
     esp_alloc::heap_allocator!(size: 260000);
 
+    let restarted_by_watchdog = matches!(
+        reset_reason(Cpu::ProCpu),
+        Some(SocResetReason::CoreMwdt1 | SocResetReason::Cpu0Mwdt1)
+    );
+
     let sw_int = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_rtos::start(timg0.timer0, sw_int.software_interrupt0);
 
-    let (mut rx, mut tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+    let (rx, tx) = UsbSerialJtag::new(peripherals.USB_DEVICE)
         .into_async()
         .split();
+    // Empty while `usb_poll` has the link, so the read hook leaves it alone.
+    let usb_link: &'static RefCell<_> =
+        Box::leak(Box::new(RefCell::new(Some(UsbLink::new(rx, tx)))));
     let mut usb_mode = UsbMode::new(4096);
 
     info!("Heap initialized");
@@ -97,6 +120,7 @@ async fn main(_spawner: Spawner) {
         .with_mosi(peripherals.GPIO10)
         .with_miso(peripherals.GPIO7);
     let shared_spi: &'static RefCell<_> = Box::leak(Box::new(RefCell::new(spi)));
+    let bus_arbiter: &'static BusArbiter = Box::leak(Box::new(BusArbiter::new()));
 
     info!("Setting up GPIO pins");
     let dc = Output::new(peripherals.GPIO4, Level::High, OutputConfig::default());
@@ -105,8 +129,15 @@ async fn main(_spawner: Spawner) {
 
     info!("Initializing SPI for E-Ink Display");
     let eink_cs = Output::new(peripherals.GPIO21, Level::High, OutputConfig::default());
-    let eink_spi_device = RefCellDevice::new(&shared_spi, eink_cs, delay.clone())
-        .expect("Failed to create SPI device");
+    let eink_spi_device = BusDevice::new(
+        shared_spi,
+        bus_arbiter,
+        eink_cs,
+        delay.clone(),
+        Priority::Display,
+        DISPLAY_SPI_TIMEOUT,
+    )
+    .expect("Failed to create SPI device");
 
     info!("SPI initialized");
 
@@ -123,27 +154,70 @@ async fn main(_spawner: Spawner) {
     display.display(&mut display_buffers, RefreshMode::Full);
 
     let eink_cs = Output::new(peripherals.GPIO12, Level::High, OutputConfig::default());
-    let sdcard_spi = RefCellDevice::new(&shared_spi, eink_cs, delay.clone())
-        .expect("Failed to create SPI device for SD card");
+    let sdcard_spi = BusDevice::new(
+        shared_spi,
+        bus_arbiter,
+        eink_cs,
+        delay.clone(),
+        Priority::Storage,
+        SDCARD_SPI_TIMEOUT,
+    )
+    .expect("Failed to create SPI device for SD card");
 
     let sdcard = FatFs::new(sdcard_spi, delay.clone());
     info!("SD Card initialized");
 
     let mut image_source = SdImageSource::new(sdcard);
-    let mut application = Application::new(&mut display_buffers, &mut image_source);
-    let mut button_state = GpioButtonState::new(
-        peripherals.GPIO1,
-        peripherals.GPIO2,
-        peripherals.GPIO0,
-        peripherals.GPIO3,
-        peripherals.ADC1,
-    );
+    image_source.clear_stale_uploads();
+    let button_state: &'static RefCell<_> = Box::leak(Box::new(RefCell::new(
+        GpioButtonState::new(
+            peripherals.GPIO1,
+            peripherals.GPIO2,
+            peripherals.GPIO0,
+            peripherals.GPIO3,
+            peripherals.ADC1,
+        ),
+    )));
+    // Keep catching presses and draining the USB link during reads that
+    // cannot be awaited, such as gray images streamed to the screen.
+    image_source.set_read_hook(Box::new(move || {
+        if let Ok(mut buttons) = button_state.try_borrow_mut() {
+            buttons.sample();
+        }
+        if let Ok(mut link) = usb_link.try_borrow_mut()
+            && let Some(link) = link.as_mut()
+        {
+            link.service();
+        }
+    }));
+    // Holding Back while powering on boots without the saved state.
+    button_state.borrow_mut().update();
+    let boot_buttons = button_state.borrow().get_buttons();
+    let boot_mode = if boot_buttons.is_pressed(Buttons::Back)
+        || boot_buttons.is_held(Buttons::Back)
+    {
+        info!("Back held at power-on, booting in safe mode");
+        BootMode::Safe
+    } else if restarted_by_watchdog {
+        // Whatever hung may be reached again by resuming, so skip it.
+        warn!("Restarted by the watchdog, booting in safe mode");
+        BootMode::Safe
+    } else {
+        BootMode::Normal
+    };
+    let mut application =
+        Application::with_boot_mode(&mut display_buffers, &mut image_source, boot_mode);
+    if restarted_by_watchdog {
+        application.report_hang("Restarted after it stopped responding");
+    }
     let mut battery_timer_ms: u32 = 0;
     let mut last_usb_state = usb_mode::UsbModeState::Idle;
     let mut last_usb_status = usb_mode.status();
     let mut usb_ui_dirty = true;
     let mut usb_ui_cooldown_ms: u32 = 0;
-    let initial_battery = button_state.read_battery_percent();
+    let mut usb_transfer_revision = usb_mode.transfer_revision();
+    let mut usb_progress_drawn = Instant::now();
+    let initial_battery = button_state.borrow_mut().read_battery_percent();
     application.set_battery_percent(initial_battery);
 
     // After initializing the SD card, increase the SPI frequency
@@ -157,54 +231,157 @@ async fn main(_spawner: Spawner) {
         .expect("Failed to apply the second SPI configuration");
     info!("Display complete! Starting image viewer...");
 
+    let timg1 = TimerGroup::new(peripherals.TIMG1);
+    let mut watchdog = timg1.wdt;
+    watchdog.set_timeout(
+        MwdtStage::Stage0,
+        esp_hal::time::Duration::from_secs(WATCHDOG_TIMEOUT_SECS),
+    );
+    watchdog.enable();
+    // The X4 has no accelerometer or frontlight; boards with them put their
+    // drivers here.
+    let mut motion = NoMotion;
+    let mut frontlight = NoFrontlight;
+
     loop {
         Timer::after(Duration::from_millis(2)).await;
+        watchdog.feed();
+        bus_arbiter.begin_slice();
         usb_ui_cooldown_ms = usb_ui_cooldown_ms.saturating_sub(10);
 
-        button_state.update();
-        let buttons = button_state.get_buttons();
-        usb_poll(&mut usb_mode, &mut rx, &mut tx, application.source_mut()).await;
+        button_state.borrow_mut().update();
+        let buttons = button_state.borrow().get_buttons();
+        let link = usb_link.borrow_mut().take();
+        if let Some(mut link) = link {
+            usb_poll(&mut usb_mode, &mut link, application.source_mut(), bus_arbiter).await;
+            *usb_link.borrow_mut() = Some(link);
+        }
+        // A file being opened is read here, a slice of card time per pass,
+        // so input and USB keep being handled while it loads.
+        application.source_mut().read_ahead(bus_arbiter).await;
         let usb_state = usb_mode.state();
         let usb_status = usb_mode.status();
         if usb_state != last_usb_state {
             usb_ui_dirty = true;
+            if last_usb_state == usb_mode::UsbModeState::Idle {
+                application.enter_usb_mode();
+            } else if usb_state == usb_mode::UsbModeState::Idle {
+                // Hosts write their clutter while they have the card.
+                if last_usb_state == usb_mode::UsbModeState::Active
+                    && !usb_mode.read_only()
+                    && application.clean_system_files()
+                {
+                    match application.source_mut().remove_system_files_in("/") {
+                        Ok(removed) => info!("Removed {} system files after USB", removed),
+                        Err(err) => warn!("System file cleanup failed: {:?}", err),
+                    }
+                }
+                application.leave_usb_mode();
+            }
             last_usb_state = usb_state;
         }
         if usb_status != last_usb_status {
+            if usb_status.cancelled != last_usb_status.cancelled
+                || usb_status.paused != last_usb_status.paused
+            {
+                usb_ui_dirty = true;
+            }
             last_usb_status = usb_status;
         }
         match usb_state {
             usb_mode::UsbModeState::Prompt => {
-                usb_mode.accept();
-                usb_ui_dirty = true;
+                if !application.usb_allowed() {
+                    info!("USB file access is off in simple mode");
+                    usb_mode.reject();
+                    usb_ui_dirty = true;
+                    continue;
+                }
+                if usb_ui_dirty {
+                    let host_line = usb_mode.host().map(|host| format!("Host: {}", host));
+                    let footer = if usb_mode.host().is_some() {
+                        "Confirm: allow  Left: read-only\nRight: always  Back: cancel"
+                    } else {
+                        "Confirm: allow  Left: read-only\nBack: cancel"
+                    };
+                    application.draw_usb_modal(
+                        &mut display,
+                        "USB Connected",
+                        "Enable USB file access?",
+                        host_line.as_deref(),
+                        footer,
+                    );
+                    usb_ui_dirty = false;
+                }
+                if buttons.is_pressed(Buttons::Confirm) {
+                    usb_mode.accept();
+                    usb_ui_dirty = true;
+                } else if buttons.is_pressed(Buttons::Left) {
+                    usb_mode.accept_read_only();
+                    usb_ui_dirty = true;
+                } else if buttons.is_pressed(Buttons::Right) && usb_mode.host().is_some() {
+                    usb_mode.accept_always(application.source_mut());
+                    usb_ui_dirty = true;
+                } else if buttons.is_pressed(Buttons::Back) {
+                    usb_mode.reject();
+                    usb_ui_dirty = true;
+                }
                 continue;
             }
             usb_mode::UsbModeState::Active => {
-                if usb_ui_dirty {
+                // Progress is redrawn with a partial refresh, at most once per
+                // second, to spare the panel.
+                let revision = usb_mode.transfer_revision();
+                let progress_due = revision != usb_transfer_revision
+                    && usb_progress_drawn.elapsed() >= USB_PROGRESS_INTERVAL;
+                if usb_ui_dirty || progress_due {
                     let status = usb_mode.status();
-                    let mut status_line = String::new();
-                    if let Some(cmd) = status.last_cmd {
-                        let _ = write!(&mut status_line, "Last cmd 0x{:02X}", cmd);
-                        if let Some(req) = status.last_req {
-                            let _ = write!(&mut status_line, " req {}", req);
-                        }
-                        if let Some(count) = status.last_list_count {
-                            let _ = write!(&mut status_line, " list {}", count);
-                        }
-                        if let Some(err) = status.last_err {
-                            let _ = write!(&mut status_line, " err {:?}", err);
+                    let message = if status.cancelled {
+                        "Transfer cancelled"
+                    } else if status.paused {
+                        "Transfer paused, resend to resume"
+                    } else if usb_mode.read_only() {
+                        "USB mode active (read-only)"
+                    } else {
+                        "USB mode active"
+                    };
+                    let mut lines = alloc::vec![String::from(message)];
+                    let mut progress = None;
+                    if let Some(transfer) = usb_mode.transfer() {
+                        lines.push(format!("{} {}", transfer.operation, transfer.name));
+                        if transfer.done > 0 || transfer.total.is_some() {
+                            let mut bytes = usb_mode::format_bytes(transfer.done);
+                            if let Some(total) = transfer.total {
+                                let _ = write!(&mut bytes, " of {}", usb_mode::format_bytes(total));
+                                progress = Some((transfer.done, total));
+                            }
+                            if let Some(rate) = transfer.rate() {
+                                let _ = write!(&mut bytes, ", {}/s", usb_mode::format_bytes(rate));
+                            }
+                            lines.push(bytes);
                         }
                     } else {
-                        status_line.push_str("No USB activity");
+                        lines.push(String::from("No USB activity"));
                     }
-                    application.draw_usb_modal(
+                    if let Some(err) = status.last_err {
+                        lines.push(format!("Error: {:?}", err));
+                    }
+                    let lines = lines.iter().map(String::as_str).collect::<alloc::vec::Vec<_>>();
+                    let refresh = if usb_ui_dirty {
+                        RefreshMode::Full
+                    } else {
+                        RefreshMode::Fast
+                    };
+                    application.draw_usb_transfer(
                         &mut display,
                         "USB File Access",
-                        "USB mode active",
-                        Some(status_line.as_str()),
+                        &lines,
+                        progress,
                         "Eject in host or Back to exit",
+                        refresh,
                     );
                     usb_ui_dirty = false;
+                    usb_transfer_revision = revision;
+                    usb_progress_drawn = Instant::now();
                 }
                 if buttons.is_pressed(Buttons::Back) {
                     usb_mode.set_state(usb_mode::UsbModeState::Idle);
@@ -232,15 +409,37 @@ async fn main(_spawner: Spawner) {
             usb_mode::UsbModeState::Idle => {}
         }
 
+        while let Some(event) = motion.poll_motion() {
+            application.handle_motion(event);
+        }
         application.update(&buttons, 10);
         battery_timer_ms = battery_timer_ms.saturating_add(10);
         if battery_timer_ms >= 30_000 {
             battery_timer_ms = 0;
-            let percent = button_state.read_battery_percent();
+            let percent = button_state.borrow_mut().read_battery_percent();
             application.set_battery_percent(percent);
         }
         application.draw(&mut display);
+        application.apply_frontlight(&mut frontlight);
+        if display.take_stalled() {
+            if let Err(err) = display.recover() {
+                warn!("Display reset failed: {:?}", err);
+            }
+            application.report_hang("Display stopped responding, reset it");
+        }
         let _ = application.take_wake_transition();
+        match application.take_power_request() {
+            Some(PowerRequest::Reboot) => {
+                info!("Rebooting from power menu");
+                esp_hal::system::software_reset();
+            }
+            Some(PowerRequest::UsbMode) => {
+                usb_mode.accept();
+                usb_ui_dirty = true;
+                continue;
+            }
+            None => {}
+        }
         if application.take_sleep_transition() {
             display.deep_sleep().ok();
             let mut wake_pin = unsafe { AnyPin::steal(3) };
@@ -4,8 +4,6 @@ use core::{
 };
 
 use alloc::{slice, vec::Vec};
-use alloc::string::ToString;
-use embedded_hal_bus::spi::RefCellDevice;
 use embedded_io::{ErrorType, Read, Seek, SeekFrom};
 use embedded_sdmmc::{Block, BlockDevice, BlockIdx, SdCard, sdcard};
 use esp_hal::Blocking;
@@ -15,6 +13,7 @@ use esp_hal::spi::master::Spi;
 use log::trace;
 use tern_core::fs::{Filesystem, Mode};
 use crate::sdspi_fs::UsbFsOps;
+use crate::spi_bus::BusDevice;
 
 pub type BYTE = u8;
 pub type WORD = u16;
@@ -28,6 +27,8 @@ pub const STA_NODISK: DSTATUS = 0x02; /* No medium in the drive */
 pub const STA_PROTECT: DSTATUS = 0x04; /* Write protected */
 
 pub const SECTOR_SIZE: usize = 512;
+/// Sectors fetched per multi-block read.
+const READ_BATCH_BLOCKS: usize = 8;
 
 pub type DRESULT = u32;
 pub const DRESULT_RES_OK: DRESULT = 0;
@@ -50,6 +51,7 @@ pub struct FRESULT(pub i32);
 
 impl FRESULT {
     pub const OK: FRESULT = FRESULT(0);
+    pub const INVALID_NAME: FRESULT = FRESULT(6);
 }
 
 // Implement embedded_io::Error for FRESULT
@@ -73,7 +75,7 @@ impl embedded_io::Error for FRESULT {
     }
 }
 
-type SPI = RefCellDevice<'static, Spi<'static, Blocking>, Output<'static>, Delay>;
+type SPI = BusDevice<'static, Spi<'static, Blocking>, Output<'static>, Delay>;
 type Sd = SdCard<SPI, Delay>;
 
 static mut DRIVER: Option<Sd> = None;
@@ -121,18 +123,34 @@ pub unsafe extern "C" fn disk_read(
     trace!("disk_read called: sector {}, count {}", sector, count);
     unsafe {
         if let Some(driver) = &*core::ptr::addr_of!(DRIVER) {
-            for i in 0..count {
+            if count == 1 {
                 let mut block = [Block::new()];
-                let block_idx = BlockIdx((sector + i) as _);
-                if let Err(_) = driver.read(&mut block, block_idx) {
+                if let Err(_) = driver.read(&mut block, BlockIdx(sector as _)) {
+                    return DRESULT_RES_ERROR;
+                }
+                let dest = core::slice::from_raw_parts_mut(buff, SECTOR_SIZE);
+                dest.copy_from_slice(block[0].as_slice());
+                return DRESULT_RES_OK;
+            }
+            // Runs of sectors are read with one multi-block command (CMD18)
+            // per batch instead of a command per sector.
+            let count = count as usize;
+            let mut blocks = alloc::vec![Block::new(); count.min(READ_BATCH_BLOCKS)];
+            let mut done = 0usize;
+            while done < count {
+                let batch = (count - done).min(blocks.len());
+                let block_idx = BlockIdx((sector as usize + done) as _);
+                if let Err(_) = driver.read(&mut blocks[..batch], block_idx) {
                     return DRESULT_RES_ERROR;
                 }
-                let block_bytes = block[0].as_slice();
-                let dest = core::slice::from_raw_parts_mut(
-                    buff.add((i as usize) * SECTOR_SIZE),
-                    SECTOR_SIZE,
-                );
-                dest.copy_from_slice(block_bytes);
+                for (i, block) in blocks[..batch].iter().enumerate() {
+                    let dest = core::slice::from_raw_parts_mut(
+                        buff.add((done + i) * SECTOR_SIZE),
+                        SECTOR_SIZE,
+                    );
+                    dest.copy_from_slice(block.as_slice());
+                }
+                done += batch;
             }
             DRESULT_RES_OK
         } else {
@@ -327,6 +345,7 @@ unsafe extern "C" {
     // Custom helper functions
     fn ff_mount() -> FRESULT;
     fn ff_exists(path: *const u8) -> bool;
+    fn ff_space(free_bytes: *mut QWORD, total_bytes: *mut QWORD) -> FRESULT;
     fn getnum() -> i32;
 }
 
@@ -343,14 +362,16 @@ impl FatFs {
     }
 }
 
-fn null_terminate(path: &str) -> [u8; 512] {
-    assert!(
-        path.len() < 512,
-        "Path too long for static null-terminated buffer"
-    );
+/// Long names make long paths; one that does not fit is an invalid name
+/// rather than a panic, since hosts can send any path over USB.
+fn null_terminate(path: &str) -> Result<[u8; 512], FRESULT> {
+    if path.len() >= 512 {
+        log::warn!("Path too long for FatFs: {} bytes", path.len());
+        return Err(FRESULT::INVALID_NAME);
+    }
     let mut null_terminated_path = [0u8; 512];
     null_terminated_path[..path.len()].copy_from_slice(path.as_bytes());
-    null_terminated_path
+    Ok(null_terminated_path)
 }
 
 impl ErrorType for FatFs {
@@ -369,7 +390,7 @@ impl Filesystem for FatFs {
         path: &str,
         mode: Mode,
     ) -> Result<Self::File<'_>, Self::Error> {
-        let path = null_terminate(path);
+        let path = null_terminate(path)?;
         let mode = match mode {
             Mode::Read => 0x01,                    // FA_READ
             Mode::Write => 0x02 | 0x08,            // FA_WRITE | FA_CREATE_ALWAYS
@@ -386,16 +407,16 @@ impl Filesystem for FatFs {
         }
     }
     fn create_dir_all(&self, path: &str) -> Result<(), Self::Error> {
-        let path = null_terminate(path);
+        let path = null_terminate(path)?;
         let res = unsafe { f_mkdir(path.as_ptr()) };
         if res.0 != 0 { Err(res) } else { Ok(()) }
     }
     fn exists(&self, path: &str) -> Result<bool, Self::Error> {
-        let path = null_terminate(path);
+        let path = null_terminate(path)?;
         Ok(unsafe { ff_exists(path.as_ptr()) })
     }
     fn open_directory(&self, path: &str) -> Result<Self::Directory<'_>, Self::Error> {
-        let path = null_terminate(path);
+        let path = null_terminate(path)?;
         unsafe {
             let mut d: DIR = core::mem::zeroed();
             let res = f_opendir(&mut d as *mut DIR, path.as_ptr());
@@ -420,7 +441,8 @@ impl Filesystem for FatFs {
 
 impl UsbFsOps for FatFs {
     fn delete_file(&self, path: &str) -> Result<(), embedded_sdmmc::Error<sdcard::Error>> {
-        let path = null_terminate(path);
+        let path = null_terminate(path)
+            .map_err(|_| embedded_sdmmc::Error::DeviceError(sdcard::Error::WriteError))?;
         let res = unsafe { f_unlink(path.as_ptr()) };
         if res.0 != 0 {
             Err(embedded_sdmmc::Error::DeviceError(sdcard::Error::WriteError))
@@ -429,9 +451,21 @@ impl UsbFsOps for FatFs {
         }
     }
 
+    fn free_space(&self) -> Result<(u64, u64), embedded_sdmmc::Error<sdcard::Error>> {
+        let mut free: QWORD = 0;
+        let mut total: QWORD = 0;
+        let res = unsafe { ff_space(&mut free as *mut QWORD, &mut total as *mut QWORD) };
+        if res.0 != 0 {
+            Err(embedded_sdmmc::Error::DeviceError(sdcard::Error::ReadError))
+        } else {
+            Ok((free, total))
+        }
+    }
+
     fn rename_file(&self, from: &str, to: &str) -> Result<(), embedded_sdmmc::Error<sdcard::Error>> {
-        let from = null_terminate(from);
-        let to = null_terminate(to);
+        let (Ok(from), Ok(to)) = (null_terminate(from), null_terminate(to)) else {
+            return Err(embedded_sdmmc::Error::DeviceError(sdcard::Error::WriteError));
+        };
         let res = unsafe { f_rename(from.as_ptr(), to.as_ptr()) };
         if res.0 != 0 {
             Err(embedded_sdmmc::Error::DeviceError(sdcard::Error::WriteError))
@@ -443,8 +477,10 @@ impl UsbFsOps for FatFs {
 
 pub struct DirEntry {
     name: alloc::string::String,
+    short_name: alloc::string::String,
     size: usize,
     is_dir: bool,
+    attributes: u8,
 }
 
 impl DirEntry {
@@ -456,27 +492,33 @@ impl DirEntry {
             .take_while(|&&b| b != 0)
             .copied()
             .collect::<Vec<u8>>();
-        let raw = alloc::string::String::from_utf8_lossy(&name_bytes).into_owned();
-        let name = if raw.contains('.') {
-            raw.trim().to_string()
-        } else if raw.len() > 8 {
-            let base = raw.chars().take(8).collect::<alloc::string::String>();
-            let ext = raw.chars().skip(8).take(3).collect::<alloc::string::String>();
-            let base = base.trim().to_string();
-            let ext = ext.trim().to_string();
-            if ext.is_empty() {
-                base
-            } else {
-                alloc::format!("{}.{}", base, ext)
-            }
+        // With LFN enabled fname is the long name (UTF-8), or the 8.3 name
+        // with its dot when the entry has no long name.
+        let name = alloc::string::String::from_utf8_lossy(&name_bytes).into_owned();
+
+        // altname is empty when the long name already fits 8.3.
+        let alt_bytes = fno
+            .altname
+            .iter()
+            .take_while(|&&b| b != 0)
+            .copied()
+            .collect::<Vec<u8>>();
+        let short_name = if alt_bytes.is_empty() {
+            name.clone()
         } else {
-            raw.trim().to_string()
+            alloc::string::String::from_utf8_lossy(&alt_bytes).into_owned()
         };
 
         let is_dir = (fno.fattrib & 0x10) != 0; // AM_DIR = 0x10
         let size = fno.fsize as usize;
 
-        Self { name, size, is_dir }
+        Self {
+            name,
+            short_name,
+            size,
+            is_dir,
+            attributes: fno.fattrib,
+        }
     }
 }
 
@@ -487,9 +529,15 @@ impl tern_core::fs::DirEntry for DirEntry {
     fn name(&self) -> &str {
         &self.name
     }
+    fn short_name(&self) -> &str {
+        &self.short_name
+    }
     fn size(&self) -> usize {
         self.size
     }
+    fn attributes(&self) -> u8 {
+        self.attributes
+    }
 }
 
 pub struct DirectoryEntry {
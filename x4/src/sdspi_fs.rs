@@ -35,6 +35,8 @@ where
 pub trait UsbFsOps {
     fn delete_file(&self, path: &str) -> Result<()>;
     fn rename_file(&self, from: &str, to: &str) -> Result<()>;
+    /// Free and total bytes on the volume.
+    fn free_space(&self) -> Result<(u64, u64)>;
 }
 
 type Error = embedded_sdmmc::Error<sdcard::Error>;
@@ -153,6 +155,10 @@ where
     fn rename_file(&self, from: &str, to: &str) -> Result<()> {
         self.rename_file_impl(from, to)
     }
+
+    fn free_space(&self) -> Result<(u64, u64)> {
+        Err(embedded_sdmmc::Error::Unsupported)
+    }
 }
 
 impl<SPI> tern_core::fs::Filesystem for SdSpiFilesystem<SPI>
@@ -408,4 +414,25 @@ impl tern_core::fs::DirEntry for SdSpiDirEntry {
     fn size(&self) -> usize {
         self.entry.size as usize
     }
+
+    fn attributes(&self) -> u8 {
+        let attrs = &self.entry.attributes;
+        let mut bits = 0;
+        if attrs.is_read_only() {
+            bits |= tern_core::fs::ATTR_READ_ONLY;
+        }
+        if attrs.is_hidden() {
+            bits |= tern_core::fs::ATTR_HIDDEN;
+        }
+        if attrs.is_system() {
+            bits |= tern_core::fs::ATTR_SYSTEM;
+        }
+        if attrs.is_directory() {
+            bits |= tern_core::fs::ATTR_DIRECTORY;
+        }
+        if attrs.is_archive() {
+            bits |= tern_core::fs::ATTR_ARCHIVE;
+        }
+        bits
+    }
 }
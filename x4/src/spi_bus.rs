@@ -0,0 +1,199 @@
+//! Arbitration for the SPI bus shared by the display and the SD card.
+//!
+//! Each client gets a [`BusDevice`] with a priority and a per-transaction
+//! deadline. Transactions are never cut short: an SD transaction stopped
+//! halfway would fail the FatFs call it belongs to and could leave the card
+//! half written. Instead the deadline is checked between the operations of a
+//! transaction, and a storage transaction that passes it uses up the rest of
+//! the main loop pass, so storage callers hand the bus to the display before
+//! their next FatFs call. Storage time is also counted per pass so USB
+//! transfers can hand the bus back between frames.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Debug;
+
+use embassy_time::{Duration, Instant};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{self, ErrorKind, ErrorType, Operation, SpiBus, SpiDevice};
+use log::warn;
+
+/// Storage time per main loop pass before USB work yields to the UI.
+const STORAGE_SLICE: Duration = Duration::from_millis(40);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Never waits for storage, so a refresh is not held up by the card.
+    Display,
+    /// Counted against the loop's slice, and gives up the rest of the pass
+    /// when a transaction overruns.
+    Storage,
+}
+
+#[derive(Debug)]
+pub enum BusError<SPI, CS> {
+    Spi(SPI),
+    Cs(CS),
+    /// The bus was already taken, e.g. from a callback inside a transaction.
+    Busy,
+}
+
+impl<SPI, CS> spi::Error for BusError<SPI, CS>
+where
+    SPI: spi::Error,
+    CS: Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            BusError::Spi(err) => err.kind(),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// State shared by all devices on the bus.
+pub struct BusArbiter {
+    storage_busy: Cell<Duration>,
+    storage_overran: Cell<bool>,
+}
+
+impl BusArbiter {
+    pub fn new() -> Self {
+        Self {
+            storage_busy: Cell::new(Duration::from_ticks(0)),
+            storage_overran: Cell::new(false),
+        }
+    }
+
+    /// Starts a main loop pass.
+    pub fn begin_slice(&self) {
+        self.storage_busy.set(Duration::from_ticks(0));
+        self.storage_overran.set(false);
+    }
+
+    /// Whether storage used up its share of this pass and should let the UI
+    /// draw before its next FatFs call.
+    pub fn storage_slice_spent(&self) -> bool {
+        self.storage_overran.get() || self.storage_busy.get() >= STORAGE_SLICE
+    }
+
+    fn overran(&self, priority: Priority, timeout: Duration) {
+        match priority {
+            Priority::Storage if !self.storage_overran.get() => {
+                warn!(
+                    "SD transaction ran past its {} ms deadline, yielding the bus after this call",
+                    timeout.as_millis()
+                );
+                self.storage_overran.set(true);
+            }
+            Priority::Storage => {}
+            Priority::Display => {
+                warn!(
+                    "Display transaction ran past its {} ms deadline",
+                    timeout.as_millis()
+                );
+            }
+        }
+    }
+
+    fn finished(&self, priority: Priority, elapsed: Duration) {
+        if priority == Priority::Storage {
+            self.storage_busy.set(self.storage_busy.get() + elapsed);
+        }
+    }
+}
+
+impl Default for BusArbiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One chip select on the shared bus; a drop-in for `RefCellDevice`.
+pub struct BusDevice<'a, BUS, CS, D> {
+    bus: &'a RefCell<BUS>,
+    arbiter: &'a BusArbiter,
+    cs: CS,
+    delay: D,
+    priority: Priority,
+    timeout: Duration,
+}
+
+impl<'a, BUS, CS, D> BusDevice<'a, BUS, CS, D>
+where
+    CS: OutputPin,
+{
+    pub fn new(
+        bus: &'a RefCell<BUS>,
+        arbiter: &'a BusArbiter,
+        mut cs: CS,
+        delay: D,
+        priority: Priority,
+        timeout: Duration,
+    ) -> Result<Self, CS::Error> {
+        cs.set_high()?;
+        Ok(Self {
+            bus,
+            arbiter,
+            cs,
+            delay,
+            priority,
+            timeout,
+        })
+    }
+}
+
+impl<BUS, CS, D> ErrorType for BusDevice<'_, BUS, CS, D>
+where
+    BUS: ErrorType,
+    CS: OutputPin,
+{
+    type Error = BusError<BUS::Error, CS::Error>;
+}
+
+impl<BUS, CS, D> SpiDevice for BusDevice<'_, BUS, CS, D>
+where
+    BUS: SpiBus,
+    CS: OutputPin,
+    D: DelayNs,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut bus = self.bus.try_borrow_mut().map_err(|_| BusError::Busy)?;
+        let (arbiter, priority, timeout) = (self.arbiter, self.priority, self.timeout);
+        let started = Instant::now();
+        let deadline = started + timeout;
+        let mut overran = false;
+
+        self.cs.set_low().map_err(BusError::Cs)?;
+        let delay = &mut self.delay;
+        let op_result = operations.iter_mut().try_for_each(|op| {
+            let result = match op {
+                Operation::Read(buf) => bus.read(buf),
+                Operation::Write(buf) => bus.write(buf),
+                Operation::Transfer(read, write) => bus.transfer(read, write),
+                Operation::TransferInPlace(buf) => bus.transfer_in_place(buf),
+                Operation::DelayNs(ns) => {
+                    bus.flush()?;
+                    delay.delay_ns(*ns);
+                    Ok(())
+                }
+            };
+            // The rest of the transaction still runs; the deadline only
+            // decides who gets the bus next.
+            if !overran && Instant::now() > deadline {
+                overran = true;
+                arbiter.overran(priority, timeout);
+            }
+            result
+        });
+        let flush_result = bus.flush();
+        let cs_result = self.cs.set_high();
+        drop(bus);
+
+        arbiter.finished(priority, started.elapsed());
+        op_result.map_err(BusError::Spi)?;
+        flush_result.map_err(BusError::Spi)?;
+        cs_result.map_err(BusError::Cs)?;
+        Ok(())
+    }
+}
@@ -1,13 +1,22 @@
+//! USB file access over the serial protocol in `docs/serial.md`.
+//!
+//! The ESP32-C3's only USB peripheral is the fixed-function Serial/JTAG
+//! controller, which enumerates as a CDC-ACM port plus JTAG and cannot take
+//! another device class. There is no mass storage mode for that reason: the
+//! card is reached through these commands, with `tern-sync` or the web app
+//! on the host side.
+
 #![allow(dead_code)]
 
 extern crate alloc;
 
-use alloc::{string::{String, ToString}, vec::Vec};
+use alloc::{borrow::Cow, string::{String, ToString}, vec::Vec};
 use embedded_io_async::{Read, Write};
 use esp_hal::{Async, usb_serial_jtag::{UsbSerialJtagRx, UsbSerialJtagTx}};
-use embassy_time::{Duration, with_timeout};
-use crate::image_source::{UsbStorage, UsbDirEntry};
-use tern_core::image_viewer::ImageError;
+use embassy_time::{Duration, Instant, with_timeout};
+use crate::image_source::{UsbDirEntry, UsbFileHash, UsbStorage};
+use crate::spi_bus::BusArbiter;
+use tern_core::image_viewer::{ImageError, PersistenceSource};
 
 const MAGIC: u16 = 0x5452; // "TR"
 const VERSION: u8 = 0x01;
@@ -16,6 +25,25 @@ const FLAG_RESP: u8 = 1 << 0;
 const FLAG_ERR: u8 = 1 << 1;
 const FLAG_EOF: u8 = 1 << 2;
 const FLAG_CONT: u8 = 1 << 3;
+/// The data of a streamed `WRITE` chunk is an LZ4 block.
+const FLAG_LZ4: u8 = 1 << 4;
+/// Largest inflated chunk, as a multiple of the frame payload limit.
+const LZ4_MAX_RATIO: usize = 4;
+
+const DEVICE_MODEL: &str = "X4";
+/// Bits per pixel of the deepest gray mode the display draws.
+const GRAY_BITS: u8 = 2;
+
+/// Remembered hosts kept; the oldest is forgotten past this.
+const MAX_REMEMBERED_HOSTS: usize = 8;
+
+const WRITE_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest scratch file `BENCH` writes, so it finishes well inside the watchdog.
+const BENCH_MAX_BYTES: u32 = 4 * 1024 * 1024;
+/// Bytes hashed from the start of a file for duplicate detection.
+const DUP_HASH_LEN: u32 = 4096;
+/// Transfers shorter than this show no rate yet.
+const RATE_MIN_ELAPSED: Duration = Duration::from_secs(1);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UsbModeState {
@@ -36,6 +64,11 @@ pub enum Command {
     Mkdir = 0x14,
     Rmdir = 0x15,
     Rename = 0x16,
+    Cancel = 0x17,
+    DupCheck = 0x18,
+    Cleanup = 0x19,
+    Hash = 0x1A,
+    Bench = 0x1B,
     Eject = 0x20,
 }
 
@@ -49,6 +82,7 @@ pub enum ErrorCode {
     CrcMismatch = 6,
     InvalidArgs = 7,
     Busy = 8,
+    ReadOnly = 9,
 }
 
 #[derive(Clone, Debug)]
@@ -80,6 +114,29 @@ impl UsbProtocol {
         self.rx_buf.extend_from_slice(bytes);
     }
 
+    /// Bytes received and not yet parsed, leaving none behind.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.rx_buf)
+    }
+
+    /// Command of the first frame, once all of it has arrived. The frame is
+    /// not checked; `next_frame` does that when it is taken.
+    fn peek_command(&self) -> Option<u8> {
+        if self.rx_buf.len() < 2 + 1 + 1 + 1 + 2 + 4 + 4 {
+            return None;
+        }
+        let len = u32::from_le_bytes([
+            self.rx_buf[7],
+            self.rx_buf[8],
+            self.rx_buf[9],
+            self.rx_buf[10],
+        ]) as usize;
+        if self.rx_buf.len() < 11 + len + 4 {
+            return None;
+        }
+        Some(self.rx_buf[4])
+    }
+
     pub fn next_frame(&mut self) -> Option<Result<Frame, ErrorCode>> {
         if self.rx_buf.len() < 2 + 1 + 1 + 1 + 2 + 4 + 4 {
             return None;
@@ -140,6 +197,14 @@ pub struct UsbMode {
     last_err: Option<ErrorCode>,
     last_list_count: Option<u16>,
     write_session: Option<WriteSession>,
+    cancelled: bool,
+    /// The last upload stopped part way and can resume.
+    paused: bool,
+    /// Identifier the host sent with its `PING`, if any.
+    host: Option<String>,
+    /// Accepted for listing and reading only.
+    read_only: bool,
+    transfer: TransferTracker,
 }
 
 impl UsbMode {
@@ -152,6 +217,11 @@ impl UsbMode {
             last_err: None,
             last_list_count: None,
             write_session: None,
+            cancelled: false,
+            paused: false,
+            host: None,
+            read_only: false,
+            transfer: TransferTracker::default(),
         }
     }
 
@@ -161,6 +231,30 @@ impl UsbMode {
 
     pub fn set_state(&mut self, state: UsbModeState) {
         self.state = state;
+        if state == UsbModeState::Idle {
+            self.host = None;
+            self.read_only = false;
+            self.transfer.current = None;
+        }
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Current or most recent operation of this session.
+    pub fn transfer(&self) -> Option<&UsbTransfer> {
+        self.transfer.current.as_ref()
+    }
+
+    /// Changes whenever the transfer advances, so the screen knows when to
+    /// redraw.
+    pub fn transfer_revision(&self) -> u32 {
+        self.transfer.revision
     }
 
     pub fn protocol(&mut self) -> &mut UsbProtocol {
@@ -173,6 +267,8 @@ impl UsbMode {
             last_req: self.last_req,
             last_err: self.last_err,
             last_list_count: self.last_list_count,
+            cancelled: self.cancelled,
+            paused: self.paused,
         }
     }
 
@@ -186,11 +282,100 @@ impl UsbMode {
 
     pub fn accept(&mut self) {
         self.state = UsbModeState::Active;
+        self.read_only = false;
+    }
+
+    /// Accepts LIST, READ and DUPCHECK only; commands that change the card
+    /// get `ReadOnly`.
+    pub fn accept_read_only(&mut self) {
+        self.state = UsbModeState::Active;
+        self.read_only = true;
     }
 
     pub fn reject(&mut self) {
         self.state = UsbModeState::Rejected;
     }
+
+    /// Accepts and remembers the host, so its next connections skip the
+    /// prompt. Hosts that sent no identifier are only accepted.
+    pub fn accept_always<S: PersistenceSource>(&mut self, storage: &mut S) {
+        if let Some(host) = self.host.clone() {
+            let mut hosts = storage.load_usb_hosts();
+            hosts.retain(|known| *known != host);
+            hosts.push(host);
+            if hosts.len() > MAX_REMEMBERED_HOSTS {
+                hosts.remove(0);
+            }
+            storage.save_usb_hosts(&hosts);
+        }
+        self.accept();
+    }
+}
+
+/// The serial link, shared between `poll` and the SD read hook. Reads that
+/// stream straight to the screen hold the main loop, so between their chunks
+/// `service` moves what the host sent out of the 64-byte FIFO and answers a
+/// waiting `PING`. Everything else needs the card, which the read is using,
+/// and is answered by the next `poll`.
+pub struct UsbLink {
+    rx: UsbSerialJtagRx<'static, Async>,
+    tx: UsbSerialJtagTx<'static, Async>,
+    /// Bytes `service` drained, handed to `UsbMode` by the next `poll`.
+    inbox: UsbProtocol,
+    /// Response `service` could not fit in the TX FIFO; `poll` sends the
+    /// rest before anything else.
+    outbox: Vec<u8>,
+    /// Copied from `UsbMode` by `poll`; a `PING` in the prompt needs the
+    /// remembered hosts, so it waits too.
+    active: bool,
+}
+
+impl UsbLink {
+    pub fn new(rx: UsbSerialJtagRx<'static, Async>, tx: UsbSerialJtagTx<'static, Async>) -> Self {
+        Self {
+            rx,
+            tx,
+            inbox: UsbProtocol::new(0),
+            outbox: Vec::new(),
+            active: false,
+        }
+    }
+
+    /// Keeps the link moving from blocking code; never waits on the host.
+    pub fn service(&mut self) {
+        let mut buf = [0u8; 64];
+        loop {
+            let len = self.rx.drain_rx_fifo(&mut buf);
+            if len == 0 {
+                break;
+            }
+            self.inbox.push_bytes(&buf[..len]);
+        }
+        // One answer at a time, so responses keep their order.
+        if self.outbox.is_empty()
+            && self.active
+            && self.inbox.peek_command() == Some(Command::Ping as u8)
+            && let Some(Ok(frame)) = self.inbox.next_frame()
+        {
+            self.outbox = ping_response(frame.req_id);
+        }
+        self.send_outbox();
+    }
+
+    /// Moves as much of the outbox as the TX FIFO has room for.
+    fn send_outbox(&mut self) {
+        let mut sent = 0;
+        while let Some(&byte) = self.outbox.get(sent) {
+            if self.tx.write_byte_nb(byte).is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        if sent > 0 {
+            let _ = self.tx.flush_tx_nb();
+            self.outbox.drain(..sent);
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -199,6 +384,80 @@ pub struct UsbStatus {
     pub last_req: Option<u16>,
     pub last_err: Option<ErrorCode>,
     pub last_list_count: Option<u16>,
+    pub cancelled: bool,
+    pub paused: bool,
+}
+
+/// What the host is doing, for the USB screen.
+#[derive(Clone, Debug)]
+pub struct UsbTransfer {
+    pub operation: &'static str,
+    /// Last segment of the path.
+    pub name: String,
+    pub done: u64,
+    /// Known for streamed uploads only.
+    pub total: Option<u64>,
+    started: Instant,
+}
+
+impl UsbTransfer {
+    /// Average bytes per second, once the transfer has run long enough to
+    /// give a steady figure.
+    pub fn rate(&self) -> Option<u64> {
+        let elapsed = self.started.elapsed();
+        if elapsed < RATE_MIN_ELAPSED || self.done == 0 {
+            return None;
+        }
+        Some(self.done.saturating_mul(1000) / elapsed.as_millis().max(1))
+    }
+}
+
+#[derive(Default)]
+struct TransferTracker {
+    current: Option<UsbTransfer>,
+    revision: u32,
+}
+
+impl TransferTracker {
+    /// Records progress. A different operation or file, or a count that
+    /// went backwards, starts a new transfer.
+    fn track(&mut self, operation: &'static str, path: &str, done: u64, total: Option<u64>) {
+        let name = path.rsplit('/').find(|segment| !segment.is_empty()).unwrap_or(path);
+        let same = self.current.as_ref().is_some_and(|transfer| {
+            transfer.operation == operation && transfer.name == name && transfer.done <= done
+        });
+        match self.current.as_mut() {
+            Some(transfer) if same => {
+                transfer.done = done;
+                transfer.total = total.or(transfer.total);
+            }
+            _ => {
+                self.current = Some(UsbTransfer {
+                    operation,
+                    name: name.to_string(),
+                    done,
+                    total,
+                    started: Instant::now(),
+                });
+            }
+        }
+        self.revision = self.revision.wrapping_add(1);
+    }
+}
+
+/// Byte count for the USB screen, e.g. `512 B`, `1.4 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return alloc::format!("{} B", bytes);
+    }
+    let mut scaled = bytes * 10 / 1024;
+    let mut unit = 0;
+    while scaled >= 10 * 1024 && unit + 1 < UNITS.len() {
+        scaled /= 1024;
+        unit += 1;
+    }
+    alloc::format!("{}.{} {}", scaled / 10, scaled % 10, UNITS[unit])
 }
 
 #[derive(Clone, Debug)]
@@ -208,6 +467,41 @@ struct WriteSession {
     offset: u64,
     total_len: u64,
     written: u64,
+    last_activity: Instant,
+}
+
+/// Drops an in-flight streamed write and removes the partial file.
+fn cancel_write<S: UsbStorage>(usb: &mut UsbMode, storage: &mut S, reason: &str) -> Option<WriteSession> {
+    let session = usb.write_session.take()?;
+    log::warn!(
+        "usb write cancelled ({}): {} at {}/{}",
+        reason,
+        session.path,
+        session.written,
+        session.total_len
+    );
+    if let Err(err) = storage.usb_abort_stream() {
+        log::warn!("usb abort cleanup failed: {:?}", err);
+    }
+    usb.cancelled = true;
+    Some(session)
+}
+
+/// Stops an in-flight streamed write but keeps what arrived, so the host can
+/// resume it by sending the same file again.
+fn pause_write<S: UsbStorage>(usb: &mut UsbMode, storage: &mut S, reason: &str) {
+    let Some(session) = usb.write_session.take() else {
+        return;
+    };
+    log::warn!(
+        "usb write paused ({}): {} at {}/{}",
+        reason,
+        session.path,
+        session.written,
+        session.total_len
+    );
+    storage.usb_suspend_stream(session.total_len);
+    usb.paused = true;
 }
 
 fn write_u16(buf: &mut Vec<u8>, value: u16) {
@@ -218,6 +512,43 @@ fn write_u32(buf: &mut Vec<u8>, value: u32) {
     buf.extend_from_slice(&value.to_le_bytes());
 }
 
+/// Commands refused in read-only mode.
+fn is_write_command(cmd: u8) -> bool {
+    [
+        Command::Write,
+        Command::Delete,
+        Command::Mkdir,
+        Command::Rmdir,
+        Command::Rename,
+        Command::Cleanup,
+        Command::Bench,
+    ]
+    .iter()
+    .any(|command| *command as u8 == cmd)
+}
+
+/// Device profile sent after the protocol id in the `PING` response, so hosts
+/// can convert for this screen and refuse files the firmware cannot open.
+fn write_device_profile(buf: &mut Vec<u8>) {
+    write_string(buf, DEVICE_MODEL);
+    // Books are laid out in portrait.
+    write_u16(buf, tern_core::display::HEIGHT as u16);
+    write_u16(buf, tern_core::display::WIDTH as u16);
+    buf.push(GRAY_BITS);
+    write_string(buf, tern_core::build_info::VERSION);
+    let (trbk_oldest, trbk_newest) = tern_core::trbk::TRBK_VERSIONS;
+    let (trimg_oldest, trimg_newest) = tern_core::trbk::TRIMG_VERSIONS;
+    buf.extend_from_slice(&[trbk_oldest, trbk_newest, trimg_oldest, trimg_newest]);
+    buf.extend_from_slice(&tern_core::trbk::TRBK_CAPS_SUPPORTED.to_le_bytes());
+    write_string(buf, tern_core::build_info::GIT_HASH);
+    write_string(buf, tern_core::build_info::BUILD_TIME);
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_u16(buf, value.len() as u16);
+    buf.extend_from_slice(value.as_bytes());
+}
+
 fn encode_frame(flags: u8, cmd: u8, req_id: u16, payload: &[u8]) -> Vec<u8> {
     let mut out = Vec::with_capacity(2 + 1 + 1 + 1 + 2 + 4 + payload.len() + 4);
     write_u16(&mut out, MAGIC);
@@ -247,6 +578,13 @@ fn encode_error_for(req_id: u16, cmd: u8, code: ErrorCode, err: ImageError, fall
     }
 }
 
+fn ping_response(req_id: u16) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_u32(&mut payload, 0x5854_3430); // "XT40"
+    write_device_profile(&mut payload);
+    encode_ok(req_id, Command::Ping as u8, &payload)
+}
+
 fn encode_ok(req_id: u16, cmd: u8, payload: &[u8]) -> Vec<u8> {
     encode_frame(FLAG_RESP, cmd, req_id, payload)
 }
@@ -316,6 +654,33 @@ fn read_path(data: &[u8], cursor: &mut usize) -> Option<String> {
 
 
 
+/// Paged LIST response: as many entries from `start` as fit in one frame.
+fn serialize_list_page(entries: &[UsbDirEntry], start: usize, max_entries: usize, max_payload: usize) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_u32(&mut payload, entries.len() as u32);
+    write_u32(&mut payload, start as u32);
+    let count_pos = payload.len();
+    write_u16(&mut payload, 0);
+    let mut count = 0u16;
+    for entry in entries.iter().skip(start).take(max_entries) {
+        let short_len = entry.short_name.len().min(u8::MAX as usize);
+        let entry_len = 1 + 1 + 2 + entry.name.len() + 1 + short_len + 8;
+        if count > 0 && payload.len() + entry_len > max_payload {
+            break;
+        }
+        payload.push(if entry.is_dir { 1 } else { 0 });
+        payload.push(entry.attributes);
+        write_u16(&mut payload, entry.name.len() as u16);
+        payload.extend_from_slice(entry.name.as_bytes());
+        payload.push(short_len as u8);
+        payload.extend_from_slice(&entry.short_name.as_bytes()[..short_len]);
+        payload.extend_from_slice(&entry.size.to_le_bytes());
+        count += 1;
+    }
+    payload[count_pos..count_pos + 2].copy_from_slice(&count.to_le_bytes());
+    payload
+}
+
 fn serialize_list(entries: &[UsbDirEntry]) -> Vec<u8> {
     let mut payload = Vec::new();
     write_u16(&mut payload, entries.len() as u16);
@@ -328,6 +693,72 @@ fn serialize_list(entries: &[UsbDirEntry]) -> Vec<u8> {
     payload
 }
 
+/// Names of files in `dir` with the same size and leading-bytes CRC as an upload.
+fn find_duplicates<S: UsbStorage>(
+    storage: &mut S,
+    dir: &str,
+    size: u64,
+    head_crc: u32,
+) -> Result<Vec<String>, ImageError> {
+    let entries = storage.usb_list(dir)?;
+    let mut matches = Vec::new();
+    for entry in entries {
+        if entry.is_dir || entry.size != size {
+            continue;
+        }
+        let path = if dir.is_empty() || dir == "/" {
+            alloc::format!("/{}", entry.name)
+        } else {
+            alloc::format!("{}/{}", dir.trim_end_matches('/'), entry.name)
+        };
+        let head_len = size.min(DUP_HASH_LEN as u64) as u32;
+        let Ok(head) = storage.usb_read(&path, 0, head_len) else {
+            continue;
+        };
+        if head.len() == head_len as usize && crc32(&head) == head_crc {
+            matches.push(entry.name);
+        }
+    }
+    Ok(matches)
+}
+
+fn serialize_names(names: &[String]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_u16(&mut payload, names.len() as u16);
+    for name in names {
+        write_u16(&mut payload, name.len() as u16);
+        payload.extend_from_slice(name.as_bytes());
+    }
+    payload
+}
+
+/// Data of a streamed `WRITE` chunk from `start`, inflated when the host sent
+/// it LZ4 compressed.
+fn chunk_data(frame: &Frame, start: usize, max_payload: usize) -> Option<Cow<'_, [u8]>> {
+    let data = frame.payload.get(start..)?;
+    if (frame.flags & FLAG_LZ4) == 0 {
+        return Some(Cow::Borrowed(data));
+    }
+    let inflated_len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    if inflated_len > max_payload * LZ4_MAX_RATIO {
+        return None;
+    }
+    lz4_flex::block::decompress_size_prepended(data).ok().map(Cow::Owned)
+}
+
+fn serialize_hashes(hashes: &[UsbFileHash]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_u32(&mut payload, hashes.len() as u32);
+    for hash in hashes {
+        write_u16(&mut payload, hash.name.len() as u16);
+        payload.extend_from_slice(hash.name.as_bytes());
+        payload.extend_from_slice(&hash.size.to_le_bytes());
+        payload.push(if hash.crc.is_some() { 1 } else { 0 });
+        write_u32(&mut payload, hash.crc.unwrap_or(0));
+    }
+    payload
+}
+
 fn send_chunked<'a>(
     tx: &'a mut UsbSerialJtagTx<'static, Async>,
     cmd: u8,
@@ -355,24 +786,45 @@ fn send_chunked<'a>(
     }
 }
 
-pub async fn poll<S: UsbStorage>(
+pub async fn poll<S: UsbStorage + PersistenceSource>(
     usb: &mut UsbMode,
-    rx: &mut UsbSerialJtagRx<'static, Async>,
-    tx: &mut UsbSerialJtagTx<'static, Async>,
+    link: &mut UsbLink,
     storage: &mut S,
+    bus: &BusArbiter,
 ) {
+    let drained = link.inbox.take_bytes();
+    let UsbLink { rx, tx, outbox, .. } = link;
+    if !outbox.is_empty() {
+        let _ = Write::write_all(tx, outbox.as_slice()).await;
+        outbox.clear();
+    }
     let mut buf = [0u8; 2048];
     let read = with_timeout(Duration::from_millis(20), Read::read(rx, &mut buf)).await;
-    if let Ok(Ok(len)) = read {
-        if len > 0 {
-            usb.protocol.push_bytes(&buf[..len]);
-            if usb.should_prompt() {
-                usb.accept();
-            }
+    let len = match read {
+        Ok(Ok(len)) => len,
+        _ => 0,
+    };
+    if !drained.is_empty() || len > 0 {
+        usb.protocol.push_bytes(&drained);
+        usb.protocol.push_bytes(&buf[..len]);
+        if usb.should_prompt() {
+            usb.enter_prompt();
+        }
+    }
+
+    if let Some(session) = usb.write_session.as_ref() {
+        if usb.state() != UsbModeState::Active {
+            pause_write(usb, storage, "usb inactive");
+        } else if session.last_activity.elapsed() >= WRITE_IDLE_TIMEOUT {
+            pause_write(usb, storage, "timeout");
         }
     }
 
     loop {
+        // Frames left over are handled on the next pass, after the UI drew.
+        if bus.storage_slice_spent() {
+            break;
+        }
         let frame = match usb.protocol.next_frame() {
             Some(Ok(frame)) => frame,
             Some(Err(code)) => {
@@ -385,6 +837,17 @@ pub async fn poll<S: UsbStorage>(
         };
         usb.last_cmd = Some(frame.cmd);
         usb.last_req = Some(frame.req_id);
+        if frame.cmd == Command::Ping as u8 && usb.state() == UsbModeState::Prompt {
+            // The host identifier is optional; remembered hosts skip the prompt.
+            usb.host = read_path(&frame.payload, &mut 0).filter(|host| !host.is_empty());
+            let remembered = usb
+                .host
+                .as_ref()
+                .is_some_and(|host| storage.load_usb_hosts().contains(host));
+            if remembered {
+                usb.accept();
+            }
+        }
         if usb.state() != UsbModeState::Active {
             usb.last_err = Some(ErrorCode::Busy);
             let response = encode_error(frame.req_id, frame.cmd, ErrorCode::Busy, "usb not active");
@@ -392,18 +855,31 @@ pub async fn poll<S: UsbStorage>(
             continue;
         }
         let cmd = frame.cmd;
+        if usb.read_only && is_write_command(cmd) {
+            usb.last_err = Some(ErrorCode::ReadOnly);
+            let response = encode_error(frame.req_id, cmd, ErrorCode::ReadOnly, "read-only access");
+            let _ = Write::write_all(tx, &response).await;
+            continue;
+        }
         match cmd {
             x if x == Command::Ping as u8 => {
-                let mut payload = Vec::new();
-                write_u32(&mut payload, 0x5854_3430); // "XT40"
                 usb.last_err = None;
-                let response = encode_ok(frame.req_id, cmd, &payload);
+                let response = ping_response(frame.req_id);
                 let _ = Write::write_all(tx, &response).await;
             }
             x if x == Command::Info as u8 => {
                 let mut payload = Vec::new();
                 write_u32(&mut payload, usb.protocol.max_payload() as u32);
-                write_u32(&mut payload, 0x0000_003F); // list/read/write/delete/mkdir/rmdir
+                let capabilities = if usb.read_only {
+                    0x0000_0283 // list/read/dupcheck/hash
+                } else {
+                    0x0000_0FFF // list/read/write/delete/mkdir/rmdir/cancel/dupcheck/cleanup/hash/lz4/bench
+                };
+                write_u32(&mut payload, capabilities);
+                if let Ok((free, total)) = storage.usb_free_space() {
+                    payload.extend_from_slice(&free.to_le_bytes());
+                    payload.extend_from_slice(&total.to_le_bytes());
+                }
                 usb.last_err = None;
                 let response = encode_ok(frame.req_id, cmd, &payload);
                 let _ = Write::write_all(tx, &response).await;
@@ -416,12 +892,32 @@ pub async fn poll<S: UsbStorage>(
                     let _ = Write::write_all(tx, &response).await;
                     continue;
                 };
+                // Paging fields are optional; without them the legacy single-shot format is sent.
+                let page = read_u32(&frame.payload, &mut cursor)
+                    .map(|start| (start, read_u16(&frame.payload, &mut cursor).unwrap_or(0)));
+                usb.transfer.track("Listing", &path, 0, None);
                 match storage.usb_list(&path) {
                     Ok(entries) => {
                         usb.last_err = None;
-                        usb.last_list_count = Some(entries.len() as u16);
-                        let payload = serialize_list(&entries);
-                        send_chunked(tx, cmd, frame.req_id, &payload, usb.protocol.max_payload()).await;
+                        usb.last_list_count = Some(entries.len().min(u16::MAX as usize) as u16);
+                        if let Some((start, max_entries)) = page {
+                            let max_entries = if max_entries == 0 {
+                                usize::MAX
+                            } else {
+                                max_entries as usize
+                            };
+                            let payload = serialize_list_page(
+                                &entries,
+                                start as usize,
+                                max_entries,
+                                usb.protocol.max_payload(),
+                            );
+                            let response = encode_frame(FLAG_RESP | FLAG_EOF, cmd, frame.req_id, &payload);
+                            let _ = Write::write_all(tx, &response).await;
+                        } else {
+                            let payload = serialize_list(&entries);
+                            send_chunked(tx, cmd, frame.req_id, &payload, usb.protocol.max_payload()).await;
+                        }
                     }
                     Err(err) => {
                         usb.last_err = Some(ErrorCode::Io);
@@ -453,6 +949,7 @@ pub async fn poll<S: UsbStorage>(
                 match storage.usb_read(&path, offset, length) {
                     Ok(data) => {
                         usb.last_err = None;
+                        usb.transfer.track("Downloading", &path, offset + data.len() as u64, None);
                         send_chunked(tx, cmd, frame.req_id, &data, usb.protocol.max_payload()).await;
                     }
                     Err(err) => {
@@ -488,6 +985,16 @@ pub async fn poll<S: UsbStorage>(
                     } else {
                         false
                     };
+                    // A host that reconnected starts the same file over under a new
+                    // request id; keep what arrived so it can resume.
+                    let restarted = has_header
+                        && usb
+                            .write_session
+                            .as_ref()
+                            .is_some_and(|session| session.req_id != frame.req_id);
+                    if restarted {
+                        pause_write(usb, storage, "host restarted");
+                    }
                     if usb.write_session.is_none() {
                         if !has_header {
                             usb.last_err = Some(ErrorCode::InvalidArgs);
@@ -507,13 +1014,36 @@ pub async fn poll<S: UsbStorage>(
                             let _ = Write::write_all(tx, &response).await;
                             continue;
                         };
+                        // A paused upload of the same file picks up where it
+                        // stopped; the host skips ahead on the written offset.
+                        let mut data_start = cursor;
+                        let resumed = match read_u64(&frame.payload, &mut data_start) {
+                            Some(0) if (frame.flags & FLAG_EOF) == 0 => {
+                                chunk_data(&frame, data_start, usb.protocol.max_payload()).and_then(|head| {
+                                    storage.usb_resume_stream(&path, total_len as u64, &head)
+                                })
+                            }
+                            _ => None,
+                        };
+                        usb.cancelled = false;
+                        usb.paused = false;
+                        let written = resumed.unwrap_or(0);
+                        usb.transfer.track("Uploading", &path, written, Some(total_len as u64));
                         usb.write_session = Some(WriteSession {
                             req_id: frame.req_id,
                             path,
                             offset: 0,
                             total_len: total_len as u64,
-                            written: 0,
+                            written,
+                            last_activity: Instant::now(),
                         });
+                        if resumed.is_some() {
+                            let mut payload = Vec::new();
+                            write_u32(&mut payload, written as u32);
+                            let response = encode_frame(FLAG_RESP | FLAG_CONT, cmd, frame.req_id, &payload);
+                            let _ = Write::write_all(tx, &response).await;
+                            continue;
+                        }
                     } else if has_header {
                         let Some(path) = read_path(&frame.payload, &mut cursor) else {
                             usb.last_err = Some(ErrorCode::InvalidArgs);
@@ -551,6 +1081,7 @@ pub async fn poll<S: UsbStorage>(
                         let _ = Write::write_all(tx, &response).await;
                         continue;
                     }
+                    session.last_activity = Instant::now();
                     let Some(offset) = read_u64(&frame.payload, &mut cursor) else {
                         usb.last_err = Some(ErrorCode::InvalidArgs);
                         let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad offset");
@@ -570,12 +1101,26 @@ pub async fn poll<S: UsbStorage>(
                         let _ = Write::write_all(tx, &response).await;
                         continue;
                     }
-                    let data = &frame.payload[cursor..];
+                    let Some(data) = chunk_data(&frame, cursor, usb.protocol.max_payload()) else {
+                        usb.last_err = Some(ErrorCode::InvalidArgs);
+                        let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad data");
+                        let _ = Write::write_all(tx, &response).await;
+                        continue;
+                    };
                     let write_offset = session.offset + session.written;
                     let final_chunk = (frame.flags & FLAG_EOF) != 0;
-                    match storage.usb_write_stream(&session.path, write_offset, data, final_chunk) {
+                    // Only let storage commit the temp file if this chunk completes the upload.
+                    let commit = final_chunk
+                        && session.written.saturating_add(data.len() as u64) == session.total_len;
+                    match storage.usb_write_stream(&session.path, write_offset, &data, commit) {
                         Ok(written) => {
                             session.written = session.written.saturating_add(written as u64);
+                            usb.transfer.track(
+                                "Uploading",
+                                &session.path,
+                                session.written,
+                                Some(session.total_len),
+                            );
                             let mut payload = Vec::new();
                             write_u32(&mut payload, session.written as u32);
                             let mut resp_flags = FLAG_RESP;
@@ -589,6 +1134,7 @@ pub async fn poll<S: UsbStorage>(
                                         "write length mismatch",
                                     );
                                     let _ = Write::write_all(tx, &response).await;
+                                    let _ = storage.usb_abort_stream();
                                     usb.write_session = None;
                                     continue;
                                 }
@@ -607,6 +1153,7 @@ pub async fn poll<S: UsbStorage>(
                             usb.last_err = Some(ErrorCode::Io);
                             let response = encode_error_for(frame.req_id, cmd, ErrorCode::Io, err, "write failed");
                             let _ = Write::write_all(tx, &response).await;
+                            let _ = storage.usb_abort_stream();
                             usb.write_session = None;
                         }
                     }
@@ -639,6 +1186,7 @@ pub async fn poll<S: UsbStorage>(
                     match storage.usb_write(&path, offset, data) {
                         Ok(written) => {
                             usb.last_err = None;
+                            usb.transfer.track("Uploading", &path, offset + written as u64, None);
                             let mut payload = Vec::new();
                             write_u32(&mut payload, written);
                             let response = encode_ok(frame.req_id, cmd, &payload);
@@ -660,6 +1208,7 @@ pub async fn poll<S: UsbStorage>(
                     let _ = Write::write_all(tx, &response).await;
                     continue;
                 };
+                usb.transfer.track("Deleting", &path, 0, None);
                 match storage.usb_delete(&path) {
                     Ok(()) => {
                         usb.last_err = None;
@@ -681,6 +1230,7 @@ pub async fn poll<S: UsbStorage>(
                     let _ = Write::write_all(tx, &response).await;
                     continue;
                 };
+                usb.transfer.track("Creating", &path, 0, None);
                 match storage.usb_mkdir(&path) {
                     Ok(()) => {
                         usb.last_err = None;
@@ -702,6 +1252,7 @@ pub async fn poll<S: UsbStorage>(
                     let _ = Write::write_all(tx, &response).await;
                     continue;
                 };
+                usb.transfer.track("Removing", &path, 0, None);
                 match storage.usb_rmdir(&path) {
                     Ok(()) => {
                         usb.last_err = None;
@@ -729,6 +1280,7 @@ pub async fn poll<S: UsbStorage>(
                     let _ = Write::write_all(tx, &response).await;
                     continue;
                 };
+                usb.transfer.track("Renaming", &from, 0, None);
                 match storage.usb_rename(&from, &to) {
                     Ok(()) => {
                         usb.last_err = None;
@@ -742,6 +1294,120 @@ pub async fn poll<S: UsbStorage>(
                     }
                 }
             }
+            x if x == Command::Cancel as u8 => {
+                let mut payload = Vec::new();
+                if let Some(session) = cancel_write(usb, storage, "host cancel") {
+                    write_u32(&mut payload, session.written as u32);
+                } else if usb.paused {
+                    // Nothing in flight; drop the paused upload instead.
+                    let _ = storage.usb_abort_stream();
+                    usb.paused = false;
+                }
+                usb.last_err = None;
+                let response = encode_ok(frame.req_id, cmd, &payload);
+                let _ = Write::write_all(tx, &response).await;
+            }
+            x if x == Command::DupCheck as u8 => {
+                let mut cursor = 0usize;
+                let Some(dir) = read_path(&frame.payload, &mut cursor) else {
+                    usb.last_err = Some(ErrorCode::InvalidArgs);
+                    let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad path");
+                    let _ = Write::write_all(tx, &response).await;
+                    continue;
+                };
+                let Some(size) = read_u64(&frame.payload, &mut cursor) else {
+                    usb.last_err = Some(ErrorCode::InvalidArgs);
+                    let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad size");
+                    let _ = Write::write_all(tx, &response).await;
+                    continue;
+                };
+                let Some(head_crc) = read_u32(&frame.payload, &mut cursor) else {
+                    usb.last_err = Some(ErrorCode::InvalidArgs);
+                    let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad hash");
+                    let _ = Write::write_all(tx, &response).await;
+                    continue;
+                };
+                match find_duplicates(storage, &dir, size, head_crc) {
+                    Ok(names) => {
+                        usb.last_err = None;
+                        let payload = serialize_names(&names);
+                        send_chunked(tx, cmd, frame.req_id, &payload, usb.protocol.max_payload()).await;
+                    }
+                    Err(err) => {
+                        usb.last_err = Some(ErrorCode::Io);
+                        let response = encode_error_for(frame.req_id, cmd, ErrorCode::Io, err, "dupcheck failed");
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                }
+            }
+            x if x == Command::Cleanup as u8 => {
+                usb.transfer.track("Cleaning", "/", 0, None);
+                match storage.usb_cleanup() {
+                    Ok(removed) => {
+                        usb.last_err = None;
+                        let mut payload = Vec::new();
+                        write_u32(&mut payload, removed);
+                        let response = encode_ok(frame.req_id, cmd, &payload);
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                    Err(err) => {
+                        usb.last_err = Some(ErrorCode::Io);
+                        let response = encode_error_for(frame.req_id, cmd, ErrorCode::Io, err, "cleanup failed");
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                }
+            }
+            x if x == Command::Hash as u8 => {
+                let mut cursor = 0usize;
+                let Some(dir) = read_path(&frame.payload, &mut cursor) else {
+                    usb.last_err = Some(ErrorCode::InvalidArgs);
+                    let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad path");
+                    let _ = Write::write_all(tx, &response).await;
+                    continue;
+                };
+                usb.transfer.track("Checking", &dir, 0, None);
+                match storage.usb_hash_dir(&dir) {
+                    Ok(hashes) => {
+                        usb.last_err = None;
+                        let payload = serialize_hashes(&hashes);
+                        send_chunked(tx, cmd, frame.req_id, &payload, usb.protocol.max_payload()).await;
+                    }
+                    Err(err) => {
+                        usb.last_err = Some(ErrorCode::Io);
+                        let response = encode_error_for(frame.req_id, cmd, ErrorCode::Io, err, "hash failed");
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                }
+            }
+            x if x == Command::Bench as u8 => {
+                let mut cursor = 0usize;
+                let size = match read_u32(&frame.payload, &mut cursor) {
+                    Some(size) if size > 0 && size <= BENCH_MAX_BYTES => size,
+                    _ => {
+                        usb.last_err = Some(ErrorCode::InvalidArgs);
+                        let response = encode_error(frame.req_id, cmd, ErrorCode::InvalidArgs, "bad size");
+                        let _ = Write::write_all(tx, &response).await;
+                        continue;
+                    }
+                };
+                usb.transfer.track("Benchmarking", "card", 0, None);
+                match storage.usb_bench(size) {
+                    Ok((write_us, read_us)) => {
+                        usb.last_err = None;
+                        let mut payload = Vec::new();
+                        write_u32(&mut payload, size);
+                        write_u32(&mut payload, write_us);
+                        write_u32(&mut payload, read_us);
+                        let response = encode_ok(frame.req_id, cmd, &payload);
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                    Err(err) => {
+                        usb.last_err = Some(ErrorCode::Io);
+                        let response = encode_error_for(frame.req_id, cmd, ErrorCode::Io, err, "bench failed");
+                        let _ = Write::write_all(tx, &response).await;
+                    }
+                }
+            }
             x if x == Command::Eject as u8 => {
                 usb.last_err = None;
                 usb.set_state(UsbModeState::Idle);
@@ -760,4 +1426,5 @@ pub async fn poll<S: UsbStorage>(
             }
         }
     }
+    link.active = usb.state() == UsbModeState::Active;
 }